@@ -0,0 +1,325 @@
+//! Benchmarks of the paths every job result goes through: parsing rows as
+//! they arrive from ClickHouse, evaluating filters on them and serializing
+//! them for submission
+//!
+//! Run with `cargo bench --bench hot_paths`, optionally followed by a
+//! substring of the benchmarks to run, e.g. `cargo bench --bench hot_paths
+//! -- filter`. Each benchmark reports the median time per iteration and the
+//! rows processed per second. As with Criterion, `--save-baseline <name>`
+//! saves the medians under `target/hot_paths`, and `--baseline <name>`
+//! compares against them, failing the run when a benchmark got slower by
+//! more than `--threshold` percent.
+
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tsight_agent::client::manifest::Manifest;
+use tsight_agent::config::{FilterAction, GlobalFilters, SqlFilterRules};
+use tsight_agent::executors::base::QueryExecutor;
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+use tsight_agent::executors::json_rows::JsonRowsParser;
+use tsight_agent::models::JobType;
+
+/// Rows of the representative payloads
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// Time each benchmark is measured for at most
+const MEASUREMENT_TIME: Duration = Duration::from_secs(3);
+
+/// Iterations each benchmark is measured for at least and at most
+const MIN_ITERATIONS: usize = 5;
+const MAX_ITERATIONS: usize = 100;
+
+/// Percentage a median may exceed its baseline by, unless `--threshold`
+/// sets another. Medians of runs on the same machine vary by a few percent
+const DEFAULT_THRESHOLD: f64 = 10.0;
+
+/// Row of a typical job result: ids, a timestamp, free text and an email
+fn row(id: usize) -> JobType {
+    JobType::from([
+        ("id".to_string(), json!(id)),
+        ("user_id".to_string(), json!(id % 977)),
+        ("created_at".to_string(), json!("2024-03-01 12:34:56")),
+        (
+            "email".to_string(),
+            json!(format!("user{}@example.com", id)),
+        ),
+        (
+            "note".to_string(),
+            json!(format!("order {} shipped to warehouse {}", id, id % 13)),
+        ),
+        ("amount".to_string(), json!(id as f64 * 1.25)),
+    ])
+}
+
+fn rows(count: usize) -> Vec<JobType> {
+    (0..count).map(row).collect()
+}
+
+/// Body of a `JSONCompactEachRowWithNamesAndTypes` result of `count` rows
+fn body(count: usize) -> Vec<u8> {
+    let mut body = String::from(concat!(
+        "[\"id\",\"user_id\",\"created_at\",\"email\",\"note\",\"amount\"]\n",
+        "[\"UInt64\",\"UInt32\",\"DateTime\",\"String\",\"String\",\"Float64\"]\n",
+    ));
+    for id in 0..count {
+        body.push_str(&format!(
+            "[{},{},\"2024-03-01 12:34:56\",\"user{}@example.com\",\"order {} shipped to warehouse {}\",{}]\n",
+            id,
+            id % 977,
+            id,
+            id,
+            id % 13,
+            id as f64 * 1.25
+        ));
+    }
+    body.into_bytes()
+}
+
+/// Executor with the kind of rules the built-in PII preset has: value
+/// regexes dropping rows and column rules rewriting values
+fn executor() -> ClickhouseExecutor {
+    let value_rule = |regex: &str| SqlFilterRules {
+        column_value_regexes: Some(vec![regex.to_string()]),
+        action: Some(FilterAction::DropRow),
+        ..Default::default()
+    };
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![
+            value_rule(r"\b\d{4}[ -]?\d{4}[ -]?\d{4}[ -]?\d{4}\b"),
+            value_rule(r"\b\d{3}-\d{2}-\d{4}\b"),
+            value_rule(r"(?i)password|secret|token"),
+            value_rule(r"@internal\.example\.com$"),
+            SqlFilterRules {
+                column_name_regexes: Some(vec!["^email$".to_string()]),
+                action: Some(FilterAction::Mask),
+                ..Default::default()
+            },
+            SqlFilterRules {
+                column_name_regexes: Some(vec!["^note$".to_string()]),
+                action: Some(FilterAction::Hash),
+                ..Default::default()
+            },
+        ]),
+        hash_key: Some("benchmark-key".to_string()),
+        ..Default::default()
+    };
+    ClickhouseExecutor::with_global_filters("http://localhost:8123", "default", "", Some(filters))
+        .unwrap()
+}
+
+/// Options given after `--`, named as Criterion's
+#[derive(Default)]
+struct Options {
+    /// Substring of the names of the benchmarks to run
+    filter: Option<String>,
+    /// Baseline the medians are saved as
+    save_baseline: Option<String>,
+    /// Baseline the medians are compared against
+    baseline: Option<String>,
+    /// Percentage a median may exceed its baseline by before failing the run
+    threshold: f64,
+}
+
+impl Options {
+    fn parse() -> Self {
+        let mut options = Self {
+            threshold: DEFAULT_THRESHOLD,
+            ..Default::default()
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .unwrap_or_else(|| panic!("{} needs a value", arg))
+            };
+            match arg.as_str() {
+                "--save-baseline" => options.save_baseline = Some(value()),
+                "--baseline" => options.baseline = Some(value()),
+                "--threshold" => {
+                    options.threshold = value().parse().expect("--threshold is a percentage")
+                }
+                // `cargo bench` passes `--bench` before any user arguments
+                flag if flag.starts_with("--") => (),
+                filter => options.filter = Some(filter.to_string()),
+            }
+        }
+        options
+    }
+}
+
+/// File of a baseline: median nanoseconds per iteration by benchmark name
+fn baseline_file(name: &str) -> PathBuf {
+    let target = std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("target"));
+    target.join("hot_paths").join(format!("{}.json", name))
+}
+
+fn load_baseline(name: &str) -> BTreeMap<String, f64> {
+    let file = baseline_file(name);
+    match std::fs::read(&file) {
+        Ok(content) => serde_json::from_slice(&content)
+            .unwrap_or_else(|e| panic!("Invalid baseline {}: {}", file.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => panic!("Failed to read baseline {}: {}", file.display(), e),
+    }
+}
+
+/// Runs benchmarks, comparing their medians against a baseline
+struct Bencher {
+    options: Options,
+    baseline: Option<BTreeMap<String, f64>>,
+    medians: BTreeMap<String, f64>,
+    regressions: Vec<String>,
+}
+
+impl Bencher {
+    fn new(options: Options) -> Self {
+        let baseline = options.baseline.as_deref().map(|name| {
+            let baseline = load_baseline(name);
+            if baseline.is_empty() {
+                panic!(
+                    "Baseline '{}' wasn't saved, run with --save-baseline {0} first",
+                    name
+                );
+            }
+            baseline
+        });
+        Self {
+            options,
+            baseline,
+            medians: BTreeMap::new(),
+            regressions: Vec::new(),
+        }
+    }
+
+    /// Run `routine` on fresh input from `setup` until enough iterations were
+    /// measured, printing the median time and throughput. Only `routine` is
+    /// timed
+    fn bench<I, O>(
+        &mut self,
+        name: &str,
+        rows: usize,
+        mut setup: impl FnMut() -> I,
+        mut routine: impl FnMut(I) -> O,
+    ) {
+        if let Some(filter) = &self.options.filter {
+            if !name.contains(filter.as_str()) {
+                return;
+            }
+        }
+
+        // Warm up caches and lazily built state
+        black_box(routine(setup()));
+
+        let started = Instant::now();
+        let mut times = Vec::new();
+        while times.len() < MIN_ITERATIONS
+            || (times.len() < MAX_ITERATIONS && started.elapsed() < MEASUREMENT_TIME)
+        {
+            let input = setup();
+            let iteration = Instant::now();
+            let output = routine(input);
+            times.push(iteration.elapsed());
+            drop(black_box(output));
+        }
+        times.sort();
+        let median = times[times.len() / 2];
+        let comparison = match self
+            .baseline
+            .as_ref()
+            .and_then(|baseline| baseline.get(name))
+        {
+            Some(&baseline) => {
+                let change = (median.as_nanos() as f64 / baseline - 1.0) * 100.0;
+                if change > self.options.threshold {
+                    self.regressions.push(format!("{} ({:+.1}%)", name, change));
+                    format!(" {:+.1}% REGRESSED", change)
+                } else {
+                    format!(" {:+.1}%", change)
+                }
+            }
+            None if self.baseline.is_some() => " (not in baseline)".to_string(),
+            None => String::new(),
+        };
+        println!(
+            "{:<40} {:>12.3} ms/iter {:>14.0} rows/s ({} iterations){}",
+            name,
+            median.as_secs_f64() * 1000.0,
+            rows as f64 / median.as_secs_f64(),
+            times.len(),
+            comparison
+        );
+        self.medians
+            .insert(name.to_string(), median.as_nanos() as f64);
+    }
+
+    /// Save the medians, failing the run if any regressed past the threshold
+    fn finish(self) {
+        if let Some(name) = &self.options.save_baseline {
+            // Benchmarks left out by the filter keep their saved medians
+            let mut baseline = load_baseline(name);
+            baseline.extend(self.medians);
+            let file = baseline_file(name);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(&file, serde_json::to_vec_pretty(&baseline).unwrap()).unwrap();
+            println!("Saved baseline '{}' to {}", name, file.display());
+        }
+        if !self.regressions.is_empty() {
+            eprintln!(
+                "Slower than baseline '{}' by more than {}%: {}",
+                self.options.baseline.as_deref().unwrap_or_default(),
+                self.options.threshold,
+                self.regressions.join(", ")
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let mut bencher = Bencher::new(Options::parse());
+    let executor = executor();
+
+    for size in SIZES {
+        let body = body(size);
+        bencher.bench(
+            &format!("parse_json_rows/{}", size),
+            size,
+            || (),
+            |_| {
+                let mut parser = JsonRowsParser::default();
+                let mut rows = Vec::with_capacity(size);
+                for chunk in body.chunks(64 * 1024) {
+                    parser.feed(chunk, &mut |row| rows.push(row)).unwrap();
+                }
+                parser.finish(&mut |row| rows.push(row)).unwrap();
+                rows
+            },
+        );
+
+        bencher.bench(
+            &format!("filter_rows/{}", size),
+            size,
+            || rows(size),
+            |rows| executor.filter_job_results_with_stats(rows),
+        );
+
+        let serialized = rows(size);
+        bencher.bench(
+            &format!("serialize_results/{}", size),
+            size,
+            || (),
+            |_| {
+                let manifest = Manifest::of(&serialized).unwrap();
+                let payload = serde_json::to_vec(&serialized).unwrap();
+                (manifest, payload)
+            },
+        );
+    }
+
+    bencher.finish();
+}
@@ -0,0 +1,78 @@
+//! Record build metadata reported by `--version`, the startup log and
+//! datasource registration
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let git_commit = env::var("TSIGHT_GIT_COMMIT").ok().or_else(git_commit);
+    println!(
+        "cargo:rustc-env=TSIGHT_GIT_COMMIT={}",
+        git_commit.as_deref().unwrap_or("unknown")
+    );
+    println!("cargo:rerun-if-env-changed=TSIGHT_GIT_COMMIT");
+    watch_git_head();
+
+    // Honour SOURCE_DATE_EPOCH so reproducible builds get a fixed date
+    let build_timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=TSIGHT_BUILD_TIMESTAMP={}", build_timestamp);
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!(
+        "cargo:rustc-env=TSIGHT_BUILD_FEATURES={}",
+        features.join(",")
+    );
+
+    println!(
+        "cargo:rustc-env=TSIGHT_BUILD_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+}
+
+/// Short hash of the checked out commit, if built from a git checkout
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!commit.is_empty()).then_some(commit)
+}
+
+/// Rebuild when a commit is checked out or made, without rebuilding on every
+/// build outside of a git checkout
+fn watch_git_head() {
+    let head = Path::new(".git/HEAD");
+    if !head.exists() {
+        return;
+    }
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Ok(contents) = std::fs::read_to_string(head) {
+        if let Some(reference) = contents.trim().strip_prefix("ref: ") {
+            let reference = Path::new(".git").join(reference);
+            if reference.exists() {
+                println!("cargo:rerun-if-changed={}", reference.display());
+            }
+        }
+    }
+}
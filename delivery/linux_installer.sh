@@ -60,8 +60,11 @@ After=network.target
 StartLimitIntervalSec=60
 
 [Service]
+Type=notify
 Environment="RUST_LOG=info"
 ExecStart=$BIN_DIR/tsight_agent --config $CONFIG_FILE
+WatchdogSec=600
+TimeoutStopSec=60
 Restart=on-failure
 RestartSec=5
 StartLimitBurst=3
@@ -1,24 +1,120 @@
 use anyhow::{anyhow, Result};
-use log::debug;
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use rand::Rng;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::Instrument;
 
-use crate::client::{AcquireResultBody, ServerClient};
+use crate::audit::audit_log;
+use crate::client::{AcquireResultBody, JobResultSet, ServerApi, TaskResultSet};
 use crate::config::GlobalFilters;
+use crate::errors::{CategorizedError, ErrorCategory};
+use crate::filters::{FilterStats, SqlFilters};
+use crate::leadership::Leadership;
+use crate::logging::phase_span;
+use crate::metrics::metrics;
 use crate::models::{DataSource, JobType, Record};
+use crate::policy::{apply_row_filters, check_query};
+use crate::redact::{redact_literals, redact_values};
+use crate::signing::verifier;
+use crate::slow_query::{slow_query_log, SlowQuery};
+use crate::spill::JobRows;
+use crate::status::activity;
+use crate::time_macros::expand_time_macros;
+use crate::timeseries::{NullStats, TsMapping};
+use crate::transform::{transform_rows, transform_vec};
 
+use crate::executors::base::{ExecutionStats, QueryError, QueryExecutor};
+use crate::executors::clickhouse_source::ResultColumn;
 use crate::executors::create_executor;
 
+/// Error returned when a task or job runs longer than its timeout
+#[derive(Error, Debug)]
+#[error("Query timed out after {}s", .0.as_secs())]
+pub struct QueryTimedOut(pub Duration);
+
+/// Query run for `sql` of a request: its time macros expanded with the
+/// request's time range and the datasource's row filters applied
+fn prepare_query(
+    datasource: &DataSource,
+    query_request: &AcquireResultBody,
+    sql: &str,
+) -> Result<String> {
+    let sql = expand_time_macros(
+        sql,
+        query_request.time_range.as_ref(),
+        &datasource.source_type,
+    )?;
+    Ok(apply_row_filters(
+        datasource.row_filters.as_ref(),
+        datasource.effective_database(),
+        &sql,
+    )?)
+}
+
+/// Records of a task query, downsampled if its mapping asks for it
+fn downsampled(mapping: &TsMapping, records: Vec<Record>) -> Result<Vec<Record>> {
+    match &mapping.downsample {
+        Some(downsample) => Ok(downsample.apply(records)?),
+        None => Ok(records),
+    }
+}
+
+/// Prefix an executor error with a message, keeping its category
+fn query_failed(e: QueryError, message: impl Display) -> anyhow::Error {
+    anyhow!(CategorizedError::new(
+        ErrorCategory::of_query(&e),
+        format!("{}: {}", message, e)
+    ))
+}
+
+/// Output of a query whose rows are counted in the slow query log
+trait ReturnedRows {
+    fn returned_rows(&self) -> Option<usize>;
+}
+
+impl ReturnedRows for () {
+    fn returned_rows(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl ReturnedRows for (Vec<Record>, NullStats) {
+    fn returned_rows(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+impl ReturnedRows for (Vec<JobType>, FilterStats, Vec<ResultColumn>) {
+    fn returned_rows(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+impl ReturnedRows for (JobRows, FilterStats, Vec<ResultColumn>) {
+    fn returned_rows(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
 /// Base agent implementation with common functionality
 #[derive(Clone)]
 pub struct BaseAgent {
-    pub server_client: ServerClient,
+    pub server_client: Arc<dyn ServerApi>,
     pub datasources: Vec<DataSource>,
     pub global_filters: Option<GlobalFilters>,
+    /// Lease the agent must hold to acquire tasks, when paired with a standby
+    pub leadership: Option<Leadership>,
 }
 
 impl BaseAgent {
     /// Create a new base agent with global filters
     pub fn with_filters(
-        server_client: ServerClient,
+        server_client: Arc<dyn ServerApi>,
         datasources: Vec<DataSource>,
         global_filters: Option<GlobalFilters>,
     ) -> Self {
@@ -26,6 +122,7 @@ impl BaseAgent {
             server_client,
             datasources,
             global_filters,
+            leadership: None,
         }
     }
 
@@ -36,43 +133,393 @@ impl BaseAgent {
             .find(|ds: &&DataSource| ds.name == query_request.datasource_name)
     }
 
-    /// Process a query and return the results
-    pub async fn process_query(&self, query_request: &AcquireResultBody) -> Result<Vec<Record>> {
+    /// Find an available datasource for the request, failing fast for
+    /// disabled datasources, those in a maintenance window, unsigned queries
+    /// when signing is required and queries the datasource's policy rejects
+    fn available_datasource(&self, query_request: &AcquireResultBody) -> Result<&DataSource> {
         let datasource = self.find_datasource(query_request).ok_or_else(|| {
-            anyhow!(
-                "No matching datasource found for query {}",
-                query_request.datasource_name
-            )
+            anyhow!(CategorizedError::new(
+                ErrorCategory::UnknownDatasource,
+                format!(
+                    "No matching datasource found for query {}",
+                    query_request.datasource_name
+                ),
+            ))
         })?;
 
+        if let Some(reason) = datasource.unavailable_reason(Utc::now()) {
+            return Err(anyhow!(CategorizedError::new(
+                ErrorCategory::DatasourceUnavailable,
+                reason
+            )));
+        }
+
+        let filters = datasource
+            .effective_filters(self.global_filters.as_ref())
+            .map(|filters| SqlFilters::new(Some(&filters)))
+            .transpose()?;
+        for task_query in query_request.query_list() {
+            if let Some(verifier) = verifier() {
+                verifier.verify(
+                    &datasource.name,
+                    &task_query.query,
+                    task_query.signature.as_deref(),
+                )?;
+            }
+            let query = expand_time_macros(
+                &task_query.query,
+                query_request.time_range.as_ref(),
+                &datasource.source_type,
+            )?;
+            check_query(
+                &datasource.query_policy,
+                filters.as_ref(),
+                datasource.effective_database(),
+                &query,
+            )?;
+        }
+
+        Ok(datasource)
+    }
+
+    /// Create an executor running all queries of a multi-query task on one
+    /// session
+    async fn session_executor(
+        &self,
+        datasource: &DataSource,
+        query_request: &AcquireResultBody,
+    ) -> Result<Box<dyn QueryExecutor>> {
+        let mut executor = create_executor(datasource, self.global_filters.clone()).await?;
+        let session_id = format!(
+            "tsight-{}-{:016x}",
+            query_request.id,
+            rand::thread_rng().gen::<u64>()
+        );
+        executor.set_session(Some(session_id));
+        Ok(executor)
+    }
+
+    /// Process the queries of a multi-query task on one session, returning
+    /// the records of each named query and the execution statistics
+    pub async fn process_query_set(
+        &self,
+        query_request: &AcquireResultBody,
+    ) -> Result<(Vec<TaskResultSet>, ExecutionStats)> {
+        let datasource = self.available_datasource(query_request)?;
+        let executor = self.session_executor(datasource, query_request).await?;
+        let mapping = query_request.ts_mapping.clone().unwrap_or_default();
+
+        let run = async {
+            let mut result_sets = Vec::new();
+            for task_query in query_request.query_list() {
+                let query = prepare_query(datasource, query_request, &task_query.query)?;
+                match task_query.name {
+                    Some(name) => {
+                        let (records, null_stats) = self
+                            .audited(
+                                datasource,
+                                query_request,
+                                &query,
+                                executor.execute_ts_with(&query, &mapping),
+                            )
+                            .await
+                            .map_err(|e| {
+                                query_failed(e, format!("Query execution error for query {}", name))
+                            })?;
+                        let records = downsampled(&mapping, records)?;
+                        result_sets.push(TaskResultSet {
+                            name,
+                            records,
+                            null_stats,
+                            manifest: None,
+                        });
+                    }
+                    None => self
+                        .audited(
+                            datasource,
+                            query_request,
+                            &query,
+                            executor.execute_statement(&query),
+                        )
+                        .await
+                        .map_err(|e| query_failed(e, "Statement execution error"))?,
+                }
+            }
+            Ok::<_, anyhow::Error>(result_sets)
+        };
+
+        let started_at = Utc::now();
+        let result_sets = Self::with_timeout(datasource, query_request, run).await??;
+        let stats = Self::execution_stats(datasource, executor.as_ref(), query_request, started_at);
+        Ok((result_sets, stats))
+    }
+
+    /// Process the queries of a multi-query job on one session, returning
+    /// the rows of each named query, the statistics of all filters applied
+    /// and the execution statistics
+    pub async fn process_job_set(
+        &self,
+        query_request: &AcquireResultBody,
+    ) -> Result<(Vec<JobResultSet>, FilterStats, ExecutionStats)> {
+        let datasource = self.available_datasource(query_request)?;
+        let executor = self.session_executor(datasource, query_request).await?;
+
+        let run = async {
+            let mut result_sets = Vec::new();
+            let mut stats = FilterStats::default();
+            for task_query in query_request.query_list() {
+                let query = prepare_query(datasource, query_request, &task_query.query)?;
+                match task_query.name {
+                    Some(name) => {
+                        let (records, query_stats, columns) = self
+                            .audited(
+                                datasource,
+                                query_request,
+                                &query,
+                                executor.execute_job_with_columns(&query),
+                            )
+                            .await
+                            .map_err(|e| {
+                                query_failed(e, format!("Query execution error for query {}", name))
+                            })?;
+                        stats.merge(&query_stats);
+                        result_sets.push(JobResultSet {
+                            name,
+                            columns,
+                            records,
+                            manifest: None,
+                        });
+                    }
+                    None => self
+                        .audited(
+                            datasource,
+                            query_request,
+                            &query,
+                            executor.execute_statement(&query),
+                        )
+                        .await
+                        .map_err(|e| query_failed(e, "Statement execution error"))?,
+                }
+            }
+            Ok::<_, anyhow::Error>((result_sets, stats))
+        };
+
+        let started_at = Utc::now();
+        let (mut result_sets, stats) = Self::with_timeout(datasource, query_request, run).await??;
+        metrics().record_filters(&datasource.name, &stats);
+        activity().record_filters(&datasource.name, &stats);
+        let execution_stats =
+            Self::execution_stats(datasource, executor.as_ref(), query_request, started_at);
+        if let Some(transform) = &datasource.transform {
+            for result_set in &mut result_sets {
+                let records = std::mem::take(&mut result_set.records);
+                let columns = std::mem::take(&mut result_set.columns);
+                (result_set.records, result_set.columns) =
+                    transform_vec(transform, records, columns).await?;
+            }
+        }
+        Ok((result_sets, stats, execution_stats))
+    }
+
+    /// Redact an error message about a request before it's logged or sent
+    /// to the server, using the value filters of the request's datasource
+    pub fn redact(&self, query_request: &AcquireResultBody, message: &str) -> String {
+        let filters = match self.find_datasource(query_request) {
+            Some(datasource) => datasource.effective_filters(self.global_filters.as_ref()),
+            None => self.global_filters.clone(),
+        };
+        // Invalid filters already failed the request, its error is sent as is
+        let filters = filters.and_then(|filters| SqlFilters::new(Some(&filters)).ok());
+        redact_values(message, filters.as_ref())
+    }
+
+    /// Check if no configured datasource can currently be queried, in which
+    /// case there's no point acquiring tasks
+    pub fn all_datasources_unavailable(&self) -> bool {
+        let now = Utc::now();
+        !self.datasources.is_empty()
+            && self
+                .datasources
+                .iter()
+                .all(|ds| ds.unavailable_reason(now).is_some())
+    }
+
+    /// Time a task or job may run, its own timeout taking precedence over
+    /// the datasource's
+    fn timeout(datasource: &DataSource, query_request: &AcquireResultBody) -> Duration {
+        Duration::from_secs(query_request.timeout.unwrap_or(datasource.timeout))
+    }
+
+    /// Statistics of the queries of a request started at `started_at`,
+    /// including how long the request waited in the server's queue. Also
+    /// counts the request for the host that served it
+    fn execution_stats(
+        datasource: &DataSource,
+        executor: &dyn QueryExecutor,
+        query_request: &AcquireResultBody,
+        started_at: DateTime<Utc>,
+    ) -> ExecutionStats {
+        if let Some(host) = executor.host() {
+            metrics().record_host_query(&datasource.name, host);
+        }
+        let millis =
+            |from: DateTime<Utc>, to: DateTime<Utc>| (to - from).num_milliseconds().max(0) as u64;
+        ExecutionStats {
+            queue_wait_ms: query_request
+                .enqueued_at
+                .map(|enqueued_at| millis(enqueued_at, started_at)),
+            execution_ms: millis(started_at, Utc::now()),
+            ..executor.execution_stats()
+        }
+    }
+
+    /// Run a query of a request, writing it to the audit log and the slow
+    /// query log if they're configured. A query dropped before finishing,
+    /// e.g. on timeout, is recorded as cancelled in the audit log only
+    async fn audited<T: ReturnedRows, E: Display>(
+        &self,
+        datasource: &DataSource,
+        query_request: &AcquireResultBody,
+        query: &str,
+        run: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let entry = audit_log().map(|audit_log| {
+            audit_log.start(&query_request.id, &datasource.name, redact_literals(query))
+        });
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let result = run.await;
+        let duration = started.elapsed();
+        if let Some(entry) = entry {
+            entry.finish(
+                result
+                    .as_ref()
+                    .err()
+                    .map(|e| self.redact(query_request, &e.to_string())),
+            );
+        }
+
+        if let Some(config) = slow_query_log().filter(|config| config.is_slow(duration)) {
+            let slow_query = SlowQuery {
+                task_id: query_request.id.clone(),
+                datasource: datasource.name.clone(),
+                query: redact_literals(query),
+                duration_ms: duration.as_millis() as u64,
+                rows: result.as_ref().ok().and_then(ReturnedRows::returned_rows),
+                started_at,
+            };
+            slow_query.log();
+            if config.report {
+                if let Err(e) = self.server_client.report_slow_query(&slow_query).await {
+                    warn!("Failed to report slow query: {:#}", e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Run a query of a request, cancelling it once the request's timeout
+    /// elapses. Dropping the query closes its connection, which makes the
+    /// datasource abort it
+    async fn with_timeout<T>(
+        datasource: &DataSource,
+        query_request: &AcquireResultBody,
+        query: impl Future<Output = T>,
+    ) -> Result<T> {
+        let timeout = Self::timeout(datasource, query_request);
+        let started = Instant::now();
+        let result = tokio::time::timeout(timeout, query)
+            .instrument(phase_span!("execute"))
+            .await;
+        let duration = started.elapsed();
+        metrics().record_query(&datasource.name, duration);
+        tracing::debug!(duration_ms = duration.as_millis() as u64, "Query finished");
+        result.map_err(|_| {
+            warn!(
+                "Query {} cancelled after {}s",
+                query_request.id,
+                timeout.as_secs()
+            );
+            anyhow!(QueryTimedOut(timeout))
+        })
+    }
+
+    /// Process a query and return the results with counts of rows with `NULL`s
+    /// and the execution statistics
+    pub async fn process_query(
+        &self,
+        query_request: &AcquireResultBody,
+    ) -> Result<(Vec<Record>, NullStats, ExecutionStats)> {
+        let datasource = self.available_datasource(query_request)?;
+        let query = prepare_query(datasource, query_request, &query_request.query)?;
+
         let executor = create_executor(datasource, self.global_filters.clone()).await?;
 
-        let data = executor
-            .execute_ts(&query_request.query)
-            .await
-            .map_err(|e| anyhow!("Query execution error for query: {}", e))?;
+        let mapping = query_request.ts_mapping.clone().unwrap_or_default();
+        let started_at = Utc::now();
+        let (data, null_stats) = Self::with_timeout(
+            datasource,
+            query_request,
+            self.audited(
+                datasource,
+                query_request,
+                &query,
+                executor.execute_ts_with(&query, &mapping),
+            ),
+        )
+        .await?
+        .map_err(|e| query_failed(e, "Query execution error for query"))?;
+        let execution_stats =
+            Self::execution_stats(datasource, executor.as_ref(), query_request, started_at);
+        let data = downsampled(&mapping, data)?;
 
-        Ok(data)
+        Ok((data, null_stats, execution_stats))
     }
 
-    /// Process a job and return the results
-    pub async fn process_job(&self, query_request: &AcquireResultBody) -> Result<Vec<JobType>> {
-        let datasource = self.find_datasource(query_request).ok_or_else(|| {
-            anyhow!(
-                "No matching datasource found for query {}",
-                query_request.datasource_name
-            )
-        })?;
+    /// Process a job and return the results with statistics of the applied
+    /// filters, the columns of the results and the execution statistics.
+    /// Rows beyond the datasource's memory budget are spilled to disk
+    pub async fn process_job(
+        &self,
+        query_request: &AcquireResultBody,
+    ) -> Result<(JobRows, FilterStats, Vec<ResultColumn>, ExecutionStats)> {
+        let datasource = self.available_datasource(query_request)?;
+        let query = prepare_query(datasource, query_request, &query_request.query)?;
 
         let executor = create_executor(datasource, self.global_filters.clone()).await?;
 
-        let data = executor
-            .execute_job(&query_request.query)
-            .await
-            .map_err(|e| anyhow!("Query execution error for query: {}", e))?;
+        let started_at = Utc::now();
+        let (data, stats, columns) = Self::with_timeout(
+            datasource,
+            query_request,
+            self.audited(
+                datasource,
+                query_request,
+                &query,
+                executor.execute_job_rows(&query),
+            ),
+        )
+        .await?
+        .map_err(|e| query_failed(e, "Query execution error for query"))?;
+        metrics().record_filters(&datasource.name, &stats);
+        activity().record_filters(&datasource.name, &stats);
+        let execution_stats =
+            Self::execution_stats(datasource, executor.as_ref(), query_request, started_at);
+        let (data, columns) = match &datasource.transform {
+            Some(transform) => {
+                let budget = datasource.job_memory_budget_mb.map(|mb| mb * 1024 * 1024);
+                transform_rows(transform, data, columns, budget).await?
+            }
+            None => (data, columns),
+        };
 
-        debug!("Job results: {:?}", &data);
+        // Only counts, as rows may hold values the filters let through
+        debug!(
+            "Job results: {} rows of {} columns",
+            data.len(),
+            columns.len()
+        );
 
-        Ok(data)
+        Ok((data, stats, columns, execution_stats))
     }
 }
@@ -1,30 +1,77 @@
-use crate::client::ServerClient;
+use crate::anonymize::SchemaAnonymizer;
+use crate::client::{DiscoverySummary, FullSyncRequested, ServerApi};
 use crate::config::GlobalFilters;
+use crate::growth::GrowthTracker;
 use crate::models::DataSource;
-use anyhow::Result;
+use crate::schema_diff::{load_cached_schemas, save_cached_schemas, SchemaDiff};
+use anyhow::{Context, Result};
+use chrono::Utc;
 use log::{error, info};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 
+use crate::executors::base::{DiscoveryOptions, QueryExecutor};
+use crate::executors::clickhouse_source::TableSchema;
 use crate::executors::create_executor;
 
 /// Discover schemas for a single datasource and submit them to the server
+#[tracing::instrument(name = "discovery", skip_all, fields(datasource = %datasource.name))]
 pub async fn discover_datasource(
     datasource: &DataSource,
-    server_client: &ServerClient,
+    server_client: &dyn ServerApi,
     global_filters: Option<GlobalFilters>,
 ) -> Result<()> {
     info!("Discovering schemas for datasource: {}", datasource.name);
     server_client
-        .add_datasource(&datasource.name, &datasource.source_type.to_string())
+        .add_datasource(
+            &datasource.name,
+            &datasource.source_type.to_string(),
+            &datasource.labels,
+        )
         .await?;
 
-    let mut executor = create_executor(datasource, global_filters).await?;
+    let mut executor = create_executor(datasource, global_filters.clone()).await?;
     executor.connect().await?;
 
-    let schemas = executor.discover_schemas().await?;
-    info!("Discovering schemas for datasource: {}", datasource.name);
-    server_client
-        .submit_schemas(&datasource.name, schemas)
+    let batch_size = datasource.discovery.batch_size.filter(|size| *size > 0);
+    if let (Some(batch_size), false) = (batch_size, datasource.discovery.incremental) {
+        let summary = submit_in_batches(
+            datasource,
+            executor.as_ref(),
+            server_client,
+            global_filters.as_ref(),
+            batch_size,
+        )
         .await?;
+        info!(
+            "Successfully submitted {} tables in {} batches for datasource: {}",
+            summary.tables, summary.batches, datasource.name
+        );
+        return Ok(());
+    }
+
+    let mut schemas = discover(datasource, executor.as_ref(), DiscoveryOptions::default()).await?;
+    if datasource.discovery.track_growth {
+        let mut growth = GrowthTracker::load(&datasource.row_counts_file(), Utc::now());
+        growth.track(&mut schemas);
+        growth.save()?;
+    }
+    if datasource.discovery.anonymize {
+        schemas = anonymize_schemas(datasource, global_filters.as_ref(), schemas)?;
+    }
+    info!("Discovering schemas for datasource: {}", datasource.name);
+    if datasource.discovery.incremental {
+        submit_schema_changes(datasource, server_client, schemas).await?;
+    } else {
+        server_client
+            .submit_schemas(&datasource.name, schemas.clone(), &datasource.labels)
+            .await?;
+        if datasource.discovery.submit_cached {
+            save_cached_schemas(&datasource.schema_cache_file(), &schemas)?;
+        }
+    }
 
     info!(
         "Successfully submitted schemas for datasource: {}",
@@ -33,13 +80,305 @@ pub async fn discover_datasource(
     Ok(())
 }
 
+/// Discover schemas for a single datasource the way they would be submitted,
+/// without submitting them
+pub async fn preview_datasource(
+    datasource: &DataSource,
+    global_filters: Option<GlobalFilters>,
+) -> Result<Vec<TableSchema>> {
+    let mut executor = create_executor(datasource, global_filters.clone()).await?;
+    executor.connect().await?;
+
+    let schemas = discover(datasource, executor.as_ref(), DiscoveryOptions::default()).await?;
+    if datasource.discovery.anonymize {
+        return anonymize_schemas(datasource, global_filters.as_ref(), schemas);
+    }
+    Ok(schemas)
+}
+
+/// Discover schemas of a datasource, applying its table timeout if any
+async fn discover(
+    datasource: &DataSource,
+    executor: &dyn QueryExecutor,
+    options: DiscoveryOptions,
+) -> Result<Vec<TableSchema>> {
+    let options = DiscoveryOptions {
+        sample_values: datasource.discovery.sample_values,
+        ..options
+    };
+    match datasource.discovery.table_timeout {
+        Some(table_timeout) => {
+            let options = DiscoveryOptions {
+                table_timeout: Some(table_timeout),
+                ..options
+            };
+            discover_skipping_timeouts(datasource, executor, options).await
+        }
+        None => Ok(executor.discover_schemas_with(&options).await?),
+    }
+}
+
+/// Discover schemas with a per-table timeout, skipping tables that timed out
+/// `skip_after_timeouts` times in a row and keeping count in the skip-list file
+pub async fn discover_with_skip_list(
+    datasource: &DataSource,
+    executor: &dyn QueryExecutor,
+    table_timeout: Duration,
+) -> Result<Vec<TableSchema>> {
+    let options = DiscoveryOptions {
+        table_timeout: Some(table_timeout),
+        sample_values: datasource.discovery.sample_values,
+        ..Default::default()
+    };
+    discover_skipping_timeouts(datasource, executor, options).await
+}
+
+async fn discover_skipping_timeouts(
+    datasource: &DataSource,
+    executor: &dyn QueryExecutor,
+    mut options: DiscoveryOptions,
+) -> Result<Vec<TableSchema>> {
+    let path = datasource.discovery_skip_list_file();
+    let mut timeouts: BTreeMap<String, u32> = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+
+    options.skip_tables = timeouts
+        .iter()
+        .filter(|(_, count)| **count >= datasource.discovery.skip_after_timeouts)
+        .map(|(table, _)| table.clone())
+        .collect();
+    let schemas = executor.discover_schemas_with(&options).await?;
+
+    for schema in &schemas {
+        let table = format!("{}.{}", schema.database, schema.table);
+        if options.skip_tables.contains(&table) {
+            continue;
+        }
+        if schema.partial {
+            *timeouts.entry(table).or_default() += 1;
+        } else {
+            timeouts.remove(&table);
+        }
+    }
+    let content = serde_json::to_string_pretty(&timeouts)?;
+    std::fs::write(&path, content)
+        .with_context(|| format!("Failed to write discovery skip list {}", path.display()))?;
+
+    Ok(schemas)
+}
+
+/// Discover schemas, submitting them in batches of `batch_size` tables as
+/// they are discovered and completing the submission with a summary
+pub async fn submit_in_batches(
+    datasource: &DataSource,
+    executor: &dyn QueryExecutor,
+    server_client: &dyn ServerApi,
+    global_filters: Option<&GlobalFilters>,
+    batch_size: usize,
+) -> Result<DiscoverySummary> {
+    let (progress, mut discovered) = tokio::sync::mpsc::unbounded_channel();
+    let options = DiscoveryOptions {
+        progress: Some(progress),
+        ..Default::default()
+    };
+
+    let mut growth = datasource
+        .discovery
+        .track_growth
+        .then(|| GrowthTracker::load(&datasource.row_counts_file(), Utc::now()));
+    let mut summary = DiscoverySummary::default();
+    let mut submit = |mut batch: Vec<TableSchema>| {
+        if let Some(growth) = &mut growth {
+            growth.track(&mut batch);
+        }
+        summary.batches += 1;
+        summary.tables += batch.len();
+        summary.partial_tables += batch.iter().filter(|schema| schema.partial).count();
+        async move {
+            let batch = if datasource.discovery.anonymize {
+                anonymize_schemas(datasource, global_filters, batch)?
+            } else {
+                batch
+            };
+            info!(
+                "Submitting batch of {} tables for datasource: {}",
+                batch.len(),
+                datasource.name
+            );
+            server_client
+                .submit_schema_batch(&datasource.name, batch.clone(), &datasource.labels)
+                .await
+                .map(|()| batch)
+        }
+    };
+
+    // The channel closes once discovery is done and drops its options
+    let discovery = discover(datasource, executor, options);
+    let mut submitted = Vec::new();
+    let submission = async {
+        let mut batch = Vec::new();
+        let mut reported = HashSet::new();
+        while let Some(schema) = discovered.recv().await {
+            reported.insert((schema.database.clone(), schema.table.clone()));
+            batch.push(schema);
+            if batch.len() >= batch_size {
+                submitted.extend(submit(std::mem::take(&mut batch)).await?);
+            }
+        }
+        Ok::<_, anyhow::Error>((batch, reported))
+    };
+    let (schemas, submission) = tokio::join!(discovery, submission);
+    let schemas = schemas?;
+    let (mut remaining, reported) = submission?;
+
+    // Executors not reporting progress return every table at the end
+    remaining.extend(
+        schemas
+            .into_iter()
+            .filter(|schema| !reported.contains(&(schema.database.clone(), schema.table.clone()))),
+    );
+    for batch in remaining.chunks(batch_size) {
+        submitted.extend(submit(batch.to_vec()).await?);
+    }
+
+    server_client
+        .complete_discovery(&datasource.name, summary.clone())
+        .await?;
+    if let Some(growth) = &growth {
+        growth.save()?;
+    }
+    if datasource.discovery.submit_cached {
+        save_cached_schemas(&datasource.schema_cache_file(), &submitted)?;
+    }
+    Ok(summary)
+}
+
+/// Submit the schemas cached by the datasource's last discovery, returning
+/// whether there were any
+pub async fn submit_schema_cache(
+    datasource: &DataSource,
+    server_client: &dyn ServerApi,
+) -> Result<bool> {
+    let path = datasource.schema_cache_file();
+    let Some(schemas) = load_cached_schemas(&path) else {
+        return Ok(false);
+    };
+
+    info!(
+        "Submitting {} cached table schemas for datasource: {}",
+        schemas.len(),
+        datasource.name
+    );
+    server_client
+        .add_datasource(
+            &datasource.name,
+            &datasource.source_type.to_string(),
+            &datasource.labels,
+        )
+        .await?;
+    server_client
+        .submit_cached_schemas(&datasource.name, schemas, &datasource.labels)
+        .await?;
+    Ok(true)
+}
+
+/// Submit the changes since the schemas last submitted for the datasource,
+/// or the full schemas when there are none or the server requests them
+pub async fn submit_schema_changes(
+    datasource: &DataSource,
+    server_client: &dyn ServerApi,
+    schemas: Vec<TableSchema>,
+) -> Result<()> {
+    let path = datasource.schema_cache_file();
+
+    let full_sync = match load_cached_schemas(&path) {
+        Some(previous) => {
+            let diff = SchemaDiff::between(&previous, &schemas);
+            if diff.is_empty() {
+                info!("Schemas of datasource {} are unchanged", datasource.name);
+                false
+            } else {
+                info!("Schema changes of datasource {}: {}", datasource.name, diff);
+                match server_client
+                    .submit_schema_diff(&datasource.name, diff)
+                    .await
+                {
+                    Ok(()) => false,
+                    Err(e) if e.is::<FullSyncRequested>() => {
+                        info!("{} for datasource {}", e, datasource.name);
+                        true
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        None => true,
+    };
+
+    if full_sync {
+        server_client
+            .submit_schemas(&datasource.name, schemas.clone(), &datasource.labels)
+            .await?;
+    }
+    save_cached_schemas(&path, &schemas)
+}
+
+/// Anonymize names of discovered schemas, saving their originals to the
+/// datasource's mapping file
+fn anonymize_schemas(
+    datasource: &DataSource,
+    global_filters: Option<&GlobalFilters>,
+    schemas: Vec<TableSchema>,
+) -> Result<Vec<TableSchema>> {
+    let hash_key = global_filters.and_then(|filters| filters.hash_key.as_deref());
+    let anonymizer = SchemaAnonymizer::new(hash_key)?;
+    let (schemas, mapping) = anonymizer.anonymize(schemas);
+
+    let path = datasource.schema_mapping_file();
+    mapping.save(&path)?;
+    info!(
+        "Anonymized schemas of datasource {}, mapping saved to {}",
+        datasource.name,
+        path.display()
+    );
+    Ok(schemas)
+}
+
 /// Discover and submit schemas for all datasources
 pub async fn discover_and_submit_schemas(
     datasources: &[DataSource],
-    server_client: &ServerClient,
+    server_client: &dyn ServerApi,
     global_filters: Option<GlobalFilters>,
 ) -> Result<()> {
+    // Cached schemas of every datasource go first, as discovering all of
+    // them afresh can take long
+    for datasource in datasources {
+        if !datasource.discovery.enabled || !datasource.discovery.submit_cached {
+            continue;
+        }
+        if let Err(e) = submit_schema_cache(datasource, server_client).await {
+            error!(
+                "Failed to submit cached schemas for datasource {}: {:#}",
+                datasource.name, e
+            );
+        }
+    }
+
     for datasource in datasources {
+        if let Some(reason) = datasource.unavailable_reason(Utc::now()) {
+            info!("Skipping schema discovery: {}", reason);
+            continue;
+        }
+        if !datasource.discovery.enabled {
+            info!(
+                "Schema discovery is disabled for datasource: {}",
+                datasource.name
+            );
+            continue;
+        }
+
         let res = discover_datasource(datasource, server_client, global_filters.clone()).await;
         if res.is_err() {
             error!(
@@ -50,3 +389,43 @@ pub async fn discover_and_submit_schemas(
     }
     Ok(())
 }
+
+/// Rediscover schemas of a datasource every `discovery.interval`
+///
+/// Returns `None` when discovery is disabled or has no interval configured
+pub fn spawn_scheduled_discovery(
+    datasource: DataSource,
+    server_client: Arc<dyn ServerApi>,
+    global_filters: Option<GlobalFilters>,
+) -> Option<JoinHandle<()>> {
+    if !datasource.discovery.enabled {
+        return None;
+    }
+    let interval = datasource
+        .discovery
+        .interval
+        .filter(|interval| !interval.is_zero())?;
+
+    info!(
+        "Scheduling schema discovery for datasource {} every {:?}",
+        datasource.name, interval
+    );
+    Some(tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Some(reason) = datasource.unavailable_reason(Utc::now()) {
+                info!("Skipping scheduled schema discovery: {}", reason);
+                continue;
+            }
+            if let Err(e) =
+                discover_datasource(&datasource, server_client.as_ref(), global_filters.clone())
+                    .await
+            {
+                error!(
+                    "Failed to discover schemas for datasource {}: {:#}",
+                    datasource.name, e
+                );
+            }
+        }
+    }))
+}
@@ -3,14 +3,29 @@ mod datasource;
 
 use anyhow::{anyhow, Result};
 use log::{error, info, warn};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{Instrument, Span};
 
-use crate::client::ServerClient;
+use crate::client::{AcquireResultBody, BackoffRequested, JobKind, ServerApi, ServerClient};
 use crate::config::Config;
 use crate::config::GlobalFilters;
+use crate::diagnostics;
+use crate::errors::{error_tracker, CategorizedError, ErrorCategory, NoDatasourcesAvailable};
+use crate::leadership::Leadership;
+use crate::logging::phase_span;
+use crate::metrics::{metrics, HIGH_PRIORITY_QUEUE, JOB_QUEUE, OBSERVATION_QUEUE};
 use crate::models::DataSource;
-use base::BaseAgent;
-pub use datasource::discover_and_submit_schemas;
+use crate::otlp_metrics::metric_exporter;
+use crate::result_export::result_exporter;
+use crate::shutdown;
+use crate::status::{activity, TaskOutcome};
+use crate::systemd::liveness;
+pub use base::{BaseAgent, QueryTimedOut};
+pub use datasource::{
+    discover_and_submit_schemas, discover_datasource, discover_with_skip_list, preview_datasource,
+    spawn_scheduled_discovery, submit_in_batches, submit_schema_cache, submit_schema_changes,
+};
 
 /// Enum that holds different types of agents
 #[derive(Clone)]
@@ -20,39 +35,117 @@ pub enum Agent {
 }
 
 /// Initialize all agents based on the provided configuration
-pub fn initialize_agents(config: &Config) -> (Agent, Agent, Agent) {
+pub fn initialize_agents(config: &Config) -> Result<(Agent, Agent, Agent)> {
+    let server_client = crate::client::from_config(&config.server)?;
+    Ok(initialize_agents_with_client(config, server_client))
+}
+
+/// Initialize all agents sharing the provided server client
+pub fn initialize_agents_with_client(
+    config: &Config,
+    server_client: Arc<dyn ServerApi>,
+) -> (Agent, Agent, Agent) {
+    initialize_tenant_agents(
+        &config.datasources,
+        config.global_filters.clone(),
+        server_client,
+    )
+}
+
+/// Initialize the agents of one tenant, processing tasks of its datasources
+/// with its server client
+pub fn initialize_tenant_agents(
+    datasources: &[DataSource],
+    global_filters: Option<GlobalFilters>,
+    server_client: Arc<dyn ServerApi>,
+) -> (Agent, Agent, Agent) {
     // Create high priority queue agent
-    let hp_agent = factory::create_observation_agent(
-        config.server.api_key.clone(),
-        config.server.server_url.clone(),
-        config.datasources.clone(),
+    let hp_agent = factory::create_observation_agent_with_client(
+        server_client.clone(),
+        datasources.to_vec(),
         true,
-        config.global_filters.clone(),
+        global_filters.clone(),
     );
     info!("Initialized high priority agent");
 
     // Create job processing agent
-    let job_agent = factory::create_job_agent(
-        config.server.api_key.clone(),
-        config.server.server_url.clone(),
-        config.datasources.clone(),
-        config.global_filters.clone(),
+    let job_agent = factory::create_job_agent_with_client(
+        server_client.clone(),
+        datasources.to_vec(),
+        global_filters.clone(),
     );
     info!("Initialized job agent");
 
     // Create main agent for observations
-    let main_agent = factory::create_observation_agent(
-        config.server.api_key.clone(),
-        config.server.server_url.clone(),
-        config.datasources.clone(),
+    let main_agent = factory::create_observation_agent_with_client(
+        server_client,
+        datasources.to_vec(),
         false,
-        config.global_filters.clone(),
+        global_filters,
     );
     info!("Initialized observations agent");
 
     (hp_agent, job_agent, main_agent)
 }
 
+/// Prefix an acquire error with a message, keeping its category and
+/// `BackoffRequested` intact so `Agent::run` can honor it
+fn preserve_backoff(e: anyhow::Error, message: &str) -> anyhow::Error {
+    if e.is::<BackoffRequested>() {
+        e
+    } else {
+        anyhow!(CategorizedError::new(
+            ErrorCategory::of(&e),
+            format!("{} {}", message, e)
+        ))
+    }
+}
+
+/// Span of a task or job from acquiring it to submitting its results, whose
+/// fields are attached to everything logged while it's processed. The task's
+/// id and datasource are recorded once it's acquired
+fn task_span(queue: &'static str) -> Span {
+    tracing::info_span!(
+        "task",
+        task_id = tracing::field::Empty,
+        queue,
+        datasource = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    )
+}
+
+/// Record the id and datasource of an acquired task on its span
+fn record_task(span: &Span, query_request: &AcquireResultBody) {
+    span.record("task_id", query_request.id.as_str());
+    span.record("datasource", query_request.datasource_name.as_str());
+}
+
+/// Log how long a task or job took and whether it succeeded, recording it
+/// for the status page
+fn finish_task(
+    span: &Span,
+    queue: &str,
+    query_request: &AcquireResultBody,
+    started: Instant,
+    result: &Result<()>,
+) {
+    let duration_ms = started.elapsed().as_millis() as u64;
+    if result.is_err() {
+        span.record("otel.status_code", "ERROR");
+    }
+    activity().finish_task(TaskOutcome {
+        id: query_request.id.clone(),
+        queue: queue.to_string(),
+        datasource: query_request.datasource_name.clone(),
+        succeeded: result.is_ok(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        duration_ms,
+        finished_at: chrono::Utc::now(),
+    });
+    let _entered = span.enter();
+    tracing::debug!(duration_ms, succeeded = result.is_ok(), "Task finished");
+}
+
 /// Observation agent for processing time series queries
 #[derive(Clone)]
 pub struct ObservationAgent {
@@ -63,27 +156,104 @@ pub struct ObservationAgent {
 impl ObservationAgent {
     /// Process the next task from the server
     pub async fn process_next(&self) -> Result<()> {
-        let no_task_error_message;
-        if self.is_high_priority_queue {
-            no_task_error_message = "Failed to acquire next high priority query from server:";
+        let no_task_error_message = if self.is_high_priority_queue {
+            "Failed to acquire next high priority query from server:"
         } else {
-            no_task_error_message = "Failed to acquire next query from server:";
+            "Failed to acquire next query from server:"
+        };
+
+        if self.base.all_datasources_unavailable() {
+            return Err(anyhow!(NoDatasourcesAvailable));
         }
 
-        let query_request = self
+        let span = task_span(self.queue());
+        let started = Instant::now();
+        let acquired = self
             .base
             .server_client
             .acquire_next_query(self.is_high_priority_queue)
-            .await
-            .map_err(|e| anyhow!("{} {}", no_task_error_message, e))?;
+            .instrument(phase_span!(parent: &span, "acquire"))
+            .await;
+        metrics().record_acquire(self.queue(), started.elapsed());
+        liveness().record_acquire(&acquired);
+        let query_request = acquired.map_err(|e| preserve_backoff(e, no_task_error_message))?;
+
+        record_task(&span, &query_request);
+        activity().start_task(self.queue());
+        let started = Instant::now();
+        let result = self.process(&query_request).instrument(span.clone()).await;
+        finish_task(&span, self.queue(), &query_request, started, &result);
+        metrics().record_task(self.queue(), result.is_ok(), started.elapsed());
+        result
+    }
+
+    /// Queue the agent acquires tasks from, as reported in metrics
+    fn queue(&self) -> &'static str {
+        if self.is_high_priority_queue {
+            HIGH_PRIORITY_QUEUE
+        } else {
+            OBSERVATION_QUEUE
+        }
+    }
 
-        let result = self.base.process_query(&query_request).await;
+    /// Process an acquired task and submit its results
+    async fn process(&self, query_request: &AcquireResultBody) -> Result<()> {
+        if query_request.queries.is_some() {
+            return match self.base.process_query_set(query_request).await {
+                Ok((result_sets, execution_stats)) => {
+                    if let Some(exporter) = metric_exporter() {
+                        for result_set in &result_sets {
+                            exporter.export(
+                                &query_request.datasource_name,
+                                Some(&result_set.name),
+                                &result_set.records,
+                            );
+                        }
+                    }
+                    self.base
+                        .server_client
+                        .submit_result_sets(
+                            &query_request.id,
+                            result_sets,
+                            execution_stats,
+                            self.is_high_priority_queue,
+                        )
+                        .instrument(phase_span!("submit"))
+                        .await?;
+                    info!(
+                        "Successfully submitted result sets for query {}",
+                        query_request.id
+                    );
+                    Ok(())
+                }
+                Err(e) => Err(self.fail(query_request, e).await),
+            };
+        }
+
+        let result = self.base.process_query(query_request).await;
 
         match result {
-            Ok(data) => {
+            Ok((data, null_stats, execution_stats)) => {
+                if !null_stats.is_empty() {
+                    info!(
+                        "NULLs in results of query {}: {}",
+                        query_request.id, null_stats
+                    );
+                }
+                if let Some(exporter) = metric_exporter() {
+                    exporter.export(&query_request.datasource_name, None, &data);
+                }
+
                 self.base
                     .server_client
-                    .submit_results(&query_request.id, data, self.is_high_priority_queue)
+                    .submit_results(
+                        &query_request.id,
+                        data,
+                        null_stats,
+                        execution_stats,
+                        self.is_high_priority_queue,
+                    )
+                    .instrument(phase_span!("submit"))
                     .await?;
 
                 info!(
@@ -91,26 +261,30 @@ impl ObservationAgent {
                     query_request.id
                 );
             }
-            Err(e) => {
-                let error_msg = e.to_string();
-                match self
-                    .base
-                    .server_client
-                    .submit_error(&query_request.id, &error_msg, self.is_high_priority_queue)
-                    .await
-                {
-                    Ok(_) => (),
-                    Err(submit_err) => {
-                        // Log the submission error but return the original error
-                        warn!("Failed to submit error: {}", submit_err);
-                    }
-                }
-                return Err(e);
-            }
+            Err(e) => return Err(self.fail(query_request, e).await),
         }
 
         Ok(())
     }
+
+    /// Submit the error of a task, returning it redacted
+    async fn fail(&self, query_request: &AcquireResultBody, e: anyhow::Error) -> anyhow::Error {
+        let error_msg = self.base.redact(query_request, &e.to_string());
+        match self
+            .base
+            .server_client
+            .submit_error(&query_request.id, &error_msg, self.is_high_priority_queue)
+            .instrument(phase_span!("submit"))
+            .await
+        {
+            Ok(_) => (),
+            Err(submit_err) => {
+                // Log the submission error but return the original error
+                warn!("Failed to submit error: {}", submit_err);
+            }
+        }
+        anyhow!(CategorizedError::new(ErrorCategory::of(&e), error_msg))
+    }
 }
 
 /// Job agent for processing job queries
@@ -122,7 +296,7 @@ pub struct JobAgent {
 impl JobAgent {
     /// Create a new job agent
     pub fn with_filters(
-        server_client: ServerClient,
+        server_client: Arc<dyn ServerApi>,
         datasources: Vec<DataSource>,
         global_filters: Option<GlobalFilters>,
     ) -> Self {
@@ -133,55 +307,177 @@ impl JobAgent {
 
     /// Process the next job from the server
     pub async fn process_next(&self) -> Result<()> {
-        let query_request = self
+        if self.base.all_datasources_unavailable() {
+            return Err(anyhow!(NoDatasourcesAvailable));
+        }
+
+        let span = task_span(JOB_QUEUE);
+        let started = Instant::now();
+        let acquired = self
             .base
             .server_client
             .acquire_next_job()
-            .await
-            .map_err(|e| anyhow!("Failed to acquire next job from server: {}", e))?;
+            .instrument(phase_span!(parent: &span, "acquire"))
+            .await;
+        metrics().record_acquire(JOB_QUEUE, started.elapsed());
+        liveness().record_acquire(&acquired);
+        let query_request =
+            acquired.map_err(|e| preserve_backoff(e, "Failed to acquire next job from server:"))?;
+
+        record_task(&span, &query_request);
+        activity().start_task(JOB_QUEUE);
+        let started = Instant::now();
+        let result = self.process(&query_request).instrument(span.clone()).await;
+        finish_task(&span, JOB_QUEUE, &query_request, started, &result);
+        metrics().record_task(JOB_QUEUE, result.is_ok(), started.elapsed());
+        result
+    }
+
+    /// Process an acquired job and submit its results
+    async fn process(&self, query_request: &AcquireResultBody) -> Result<()> {
+        if query_request.kind == JobKind::Diagnostics {
+            let report = diagnostics::run(
+                self.base.server_client.as_ref(),
+                &self.base.datasources,
+                self.base.global_filters.as_ref(),
+            )
+            .await;
+            self.base
+                .server_client
+                .submit_diagnostics(&query_request.id, report)
+                .instrument(phase_span!("submit"))
+                .await?;
+            info!(
+                "Successfully submitted diagnostics for job {}",
+                query_request.id
+            );
+            return Ok(());
+        }
 
-        let result = self.base.process_job(&query_request).await;
+        if query_request.queries.is_some() {
+            return match self.base.process_job_set(query_request).await {
+                Ok((result_sets, filter_stats, execution_stats)) => {
+                    if !filter_stats.is_empty() {
+                        info!(
+                            "Filters applied to job {}: {}",
+                            query_request.id, filter_stats
+                        );
+                    }
+                    self.base
+                        .server_client
+                        .submit_job_result_sets(
+                            &query_request.id,
+                            result_sets,
+                            filter_stats,
+                            execution_stats,
+                        )
+                        .instrument(phase_span!("submit"))
+                        .await?;
+                    info!(
+                        "Successfully submitted result sets for job {}",
+                        query_request.id
+                    );
+                    Ok(())
+                }
+                Err(e) => Err(self.fail(query_request, e).await),
+            };
+        }
+
+        let result = self.base.process_job(query_request).await;
 
         match result {
-            Ok(data) => {
-                self.base
-                    .server_client
-                    .submit_job_results(&query_request.id, data)
-                    .await?;
+            Ok((data, filter_stats, columns, execution_stats)) => {
+                if !filter_stats.is_empty() {
+                    info!(
+                        "Filters applied to job {}: {}",
+                        query_request.id, filter_stats
+                    );
+                }
+
+                let client = &self.base.server_client;
+                if let Some(exporter) = result_exporter().filter(|e| e.exports(data.len())) {
+                    let export = match exporter
+                        .export(&query_request.id, data)
+                        .instrument(phase_span!("export"))
+                        .await
+                    {
+                        Ok(export) => export,
+                        Err(e) => {
+                            let e = anyhow!("Failed to export results: {:#}", e);
+                            return Err(self.fail(query_request, e).await);
+                        }
+                    };
+                    client
+                        .submit_job_export(
+                            &query_request.id,
+                            export,
+                            columns,
+                            filter_stats,
+                            execution_stats,
+                        )
+                        .instrument(phase_span!("submit"))
+                        .await?;
+                } else if data.is_spilled() {
+                    client
+                        .submit_job_chunks(
+                            &query_request.id,
+                            data,
+                            columns,
+                            filter_stats,
+                            execution_stats,
+                        )
+                        .instrument(phase_span!("submit"))
+                        .await?;
+                } else {
+                    client
+                        .submit_job_results(
+                            &query_request.id,
+                            data.into_vec()?,
+                            columns,
+                            filter_stats,
+                            execution_stats,
+                        )
+                        .instrument(phase_span!("submit"))
+                        .await?;
+                }
 
                 info!(
                     "Successfully submitted results for job {}",
                     query_request.id
                 );
             }
-            Err(e) => {
-                let error_msg = e.to_string();
-                match self
-                    .base
-                    .server_client
-                    .submit_job_error(&query_request.id, &error_msg)
-                    .await
-                {
-                    Ok(_) => (),
-                    Err(submit_err) => {
-                        // Log the submission error but return the original error
-                        warn!("Failed to submit error: {}", submit_err);
-                    }
-                }
-                return Err(e);
-            }
+            Err(e) => return Err(self.fail(query_request, e).await),
         }
 
         Ok(())
     }
+
+    /// Submit the error of a job, returning it redacted
+    async fn fail(&self, query_request: &AcquireResultBody, e: anyhow::Error) -> anyhow::Error {
+        let error_msg = self.base.redact(query_request, &e.to_string());
+        match self
+            .base
+            .server_client
+            .submit_job_error(&query_request.id, &error_msg)
+            .instrument(phase_span!("submit"))
+            .await
+        {
+            Ok(_) => (),
+            Err(submit_err) => {
+                // Log the submission error but return the original error
+                warn!("Failed to submit error: {}", submit_err);
+            }
+        }
+        anyhow!(CategorizedError::new(ErrorCategory::of(&e), error_msg))
+    }
 }
 
 impl Agent {
     /// Get a reference to the agent's server client
-    pub fn server_client(&self) -> &ServerClient {
+    pub fn server_client(&self) -> &dyn ServerApi {
         match self {
-            Agent::Observation(agent) => &agent.base.server_client,
-            Agent::Job(agent) => &agent.base.server_client,
+            Agent::Observation(agent) => agent.base.server_client.as_ref(),
+            Agent::Job(agent) => agent.base.server_client.as_ref(),
         }
     }
 
@@ -201,22 +497,76 @@ impl Agent {
         }
     }
 
-    /// Run the agent in a continuous loop
+    /// Only acquire tasks while `leadership` is held
+    pub fn with_leadership(mut self, leadership: Leadership) -> Self {
+        match &mut self {
+            Agent::Observation(agent) => agent.base.leadership = Some(leadership),
+            Agent::Job(agent) => agent.base.leadership = Some(leadership),
+        }
+        self
+    }
+
+    /// Whether the agent may acquire tasks, not being the standby of a pair
+    pub fn is_leader(&self) -> bool {
+        let leadership = match self {
+            Agent::Observation(agent) => &agent.base.leadership,
+            Agent::Job(agent) => &agent.base.leadership,
+        };
+        leadership
+            .as_ref()
+            .is_none_or(|leadership| leadership.is_leader())
+    }
+
+    /// Queue the agent acquires tasks from
+    pub fn queue(&self) -> &'static str {
+        match self {
+            Agent::Observation(agent) => agent.queue(),
+            Agent::Job(_) => JOB_QUEUE,
+        }
+    }
+
+    /// Run the agent in a continuous loop until shutdown is requested
     pub async fn run(&self) {
         loop {
+            if shutdown::is_requested() {
+                info!("Stopped acquiring from the {} queue", self.queue());
+                return;
+            }
+            liveness().beat(self.queue());
+            let mut delay = Duration::from_secs(1);
+
+            // A paused or standby loop keeps beating so it isn't mistaken for
+            // a stalled one
+            if shutdown::is_paused() || !self.is_leader() {
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => (),
+                    _ = shutdown::requested() => (),
+                }
+                continue;
+            }
+
             match self.process_next().await {
                 Ok(_) => (),
                 Err(e) => {
-                    if e.to_string().contains("No tasks available")
-                        || e.to_string().contains("No jobs available")
-                    {
+                    let category = ErrorCategory::of(&e);
+                    metrics().record_error(category);
+                    if category != ErrorCategory::NoWork {
+                        error_tracker().record(category, &format!("{:#}", e));
+                    }
+                    if let Some(backoff) = e.downcast_ref::<BackoffRequested>() {
+                        warn!("{}, pausing requests", backoff);
+                        delay = backoff.retry_after;
+                    } else if category.is_idle() {
                         warn!("{}", e);
                     } else {
-                        error!("Failed to process task: {:#}", e);
+                        error!("Failed to process task ({}): {:#}", category, e);
                     }
                 }
             }
-            tokio::time::sleep(Duration::from_secs(1)).await;
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => (),
+                _ = shutdown::requested() => (),
+            }
         }
     }
 }
@@ -233,7 +583,21 @@ pub mod factory {
         is_high_priority_queue: bool,
         global_filters: Option<GlobalFilters>,
     ) -> Agent {
-        let server_client = ServerClient::new(api_key, server_url);
+        create_observation_agent_with_client(
+            Arc::new(ServerClient::new(api_key, server_url)),
+            datasources,
+            is_high_priority_queue,
+            global_filters,
+        )
+    }
+
+    /// Create a new observation agent using an existing server client
+    pub fn create_observation_agent_with_client(
+        server_client: Arc<dyn ServerApi>,
+        datasources: Vec<DataSource>,
+        is_high_priority_queue: bool,
+        global_filters: Option<GlobalFilters>,
+    ) -> Agent {
         Agent::Observation(ObservationAgent {
             base: BaseAgent::with_filters(server_client, datasources, global_filters),
             is_high_priority_queue,
@@ -247,7 +611,19 @@ pub mod factory {
         datasources: Vec<DataSource>,
         global_filters: Option<GlobalFilters>,
     ) -> Agent {
-        let server_client = ServerClient::new(api_key, server_url);
+        create_job_agent_with_client(
+            Arc::new(ServerClient::new(api_key, server_url)),
+            datasources,
+            global_filters,
+        )
+    }
+
+    /// Create a new job agent using an existing server client
+    pub fn create_job_agent_with_client(
+        server_client: Arc<dyn ServerApi>,
+        datasources: Vec<DataSource>,
+        global_filters: Option<GlobalFilters>,
+    ) -> Agent {
         Agent::Job(JobAgent {
             base: BaseAgent::with_filters(server_client, datasources, global_filters),
         })
@@ -0,0 +1,127 @@
+//! Anonymization of schema names before they're submitted to the server
+
+use crate::executors::clickhouse_source::TableSchema;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::path::Path;
+use thiserror::Error;
+
+/// Number of hex characters of the HMAC kept in anonymized names
+const HASH_LENGTH: usize = 16;
+
+/// Errors of schema anonymization
+#[derive(Error, Debug)]
+pub enum AnonymizeError {
+    #[error("schema anonymization requires global_filters.hash_key to be set")]
+    MissingHashKey,
+    #[error("failed to read schema mapping {0}: {1}")]
+    Read(String, String),
+    #[error("failed to write schema mapping {0}: {1}")]
+    Write(String, String),
+}
+
+/// Original names of anonymized databases, tables and columns, keyed by
+/// their anonymized names
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchemaMapping {
+    #[serde(default)]
+    pub databases: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tables: BTreeMap<String, String>,
+    #[serde(default)]
+    pub columns: BTreeMap<String, String>,
+}
+
+impl SchemaMapping {
+    /// Load a mapping file, an empty mapping if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self, AnonymizeError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let read_error = |e: String| AnonymizeError::Read(path.display().to_string(), e);
+        let content = std::fs::read_to_string(path).map_err(|e| read_error(e.to_string()))?;
+        serde_json::from_str(&content).map_err(|e| read_error(e.to_string()))
+    }
+
+    /// Add names of another mapping, keeping those already known
+    pub fn merge(&mut self, other: SchemaMapping) {
+        self.databases.extend(other.databases);
+        self.tables.extend(other.tables);
+        self.columns.extend(other.columns);
+    }
+
+    /// Merge the mapping into the file at `path`, so names of earlier
+    /// discoveries stay resolvable
+    pub fn save(&self, path: &Path) -> Result<(), AnonymizeError> {
+        let mut mapping = Self::load(path)?;
+        mapping.merge(self.clone());
+
+        let write_error = |e: String| AnonymizeError::Write(path.display().to_string(), e);
+        let content =
+            serde_json::to_string_pretty(&mapping).map_err(|e| write_error(e.to_string()))?;
+        std::fs::write(path, content).map_err(|e| write_error(e.to_string()))
+    }
+}
+
+/// Replaces schema names with keyed hashes, so shapes and cardinalities can
+/// be analysed without revealing names
+pub struct SchemaAnonymizer {
+    key: Vec<u8>,
+}
+
+impl SchemaAnonymizer {
+    /// Create an anonymizer keyed by `global_filters.hash_key`
+    pub fn new(hash_key: Option<&str>) -> Result<Self, AnonymizeError> {
+        let key = hash_key.ok_or(AnonymizeError::MissingHashKey)?;
+        Ok(Self {
+            key: key.as_bytes().to_vec(),
+        })
+    }
+
+    /// Anonymized name, e.g. `tbl_3f2a...`: equal names always get the same
+    /// one, so tables and columns can still be matched across discoveries
+    pub fn anonymize_name(&self, prefix: &str, name: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(prefix.as_bytes());
+        mac.update(b":");
+        mac.update(name.as_bytes());
+        let hash = hex::encode(mac.finalize().into_bytes());
+        format!("{}_{}", prefix, &hash[..HASH_LENGTH])
+    }
+
+    /// Anonymize names of schemas, returning the mapping back to originals
+    pub fn anonymize(&self, schemas: Vec<TableSchema>) -> (Vec<TableSchema>, SchemaMapping) {
+        let mut mapping = SchemaMapping::default();
+        let rename = |names: &mut BTreeMap<String, String>, prefix: &str, name: String| {
+            let anonymized = self.anonymize_name(prefix, &name);
+            names.insert(anonymized.clone(), name);
+            anonymized
+        };
+
+        let schemas = schemas
+            .into_iter()
+            .map(|schema| TableSchema {
+                database: rename(&mut mapping.databases, "db", schema.database),
+                table: rename(&mut mapping.tables, "tbl", schema.table),
+                row_count: schema.row_count,
+                partial: schema.partial,
+                growth: schema.growth,
+                columns: schema
+                    .columns
+                    .into_iter()
+                    .map(|(column, info)| (rename(&mut mapping.columns, "col", column), info))
+                    .collect(),
+                freshness: schema
+                    .freshness
+                    .into_iter()
+                    .map(|(column, latest)| (rename(&mut mapping.columns, "col", column), latest))
+                    .collect(),
+            })
+            .collect();
+
+        (schemas, mapping)
+    }
+}
@@ -0,0 +1,147 @@
+//! Audit log of the queries the agent runs
+//!
+//! Each query of a task or job, statements included, is appended to a local
+//! JSON lines file once it succeeds, fails or is cancelled, with literals
+//! redacted from its text. Lines are written unbuffered, so the log is
+//! complete up to the last finished query even if the agent crashes.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use crate::config::AuditConfig;
+use crate::rotation::{RotatingFile, MEGABYTE};
+
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// Write the queries of all agents to the configured audit log
+pub fn init(config: &AuditConfig) -> Result<()> {
+    let log = AuditLog::open(config)?;
+    AUDIT_LOG
+        .set(log)
+        .map_err(|_| anyhow!("Audit log is already initialized"))
+}
+
+/// The audit log, unless none is configured
+pub fn audit_log() -> Option<&'static AuditLog> {
+    AUDIT_LOG.get()
+}
+
+/// How a query ended
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Succeeded,
+    Failed,
+    /// Stopped before finishing, e.g. when its task timed out
+    Cancelled,
+}
+
+/// Line of the audit log
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AuditRecord {
+    /// When the query started
+    pub timestamp: DateTime<Utc>,
+    pub task_id: String,
+    pub datasource: String,
+    /// Query text as run, with literals redacted
+    pub query: String,
+    pub duration_ms: u64,
+    pub outcome: AuditOutcome,
+    /// Redacted error of a failed query
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Append-only JSON lines file of audit records
+pub struct AuditLog {
+    file: Mutex<RotatingFile>,
+}
+
+impl AuditLog {
+    /// Open the configured file for appending
+    pub fn open(config: &AuditConfig) -> Result<Self> {
+        let file = RotatingFile::open(
+            &config.path,
+            config.max_size_mb * MEGABYTE,
+            config.max_files,
+        )
+        .with_context(|| format!("Failed to open audit log {}", config.path.display()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append a record
+    pub fn write(&self, record: &AuditRecord) -> Result<()> {
+        let line = serde_json::to_vec(record)?;
+        self.file
+            .lock()
+            .unwrap()
+            .write_line(&line)
+            .context("Failed to write audit log")
+    }
+
+    /// Start auditing a query whose text is already redacted. The query is
+    /// recorded when the returned entry is finished, or as cancelled when
+    /// it's dropped unfinished
+    pub fn start(&self, task_id: &str, datasource: &str, query: String) -> AuditEntry<'_> {
+        AuditEntry {
+            log: self,
+            timestamp: Utc::now(),
+            started: Instant::now(),
+            task_id: task_id.to_string(),
+            datasource: datasource.to_string(),
+            query,
+            finished: false,
+        }
+    }
+}
+
+/// Query being audited
+pub struct AuditEntry<'a> {
+    log: &'a AuditLog,
+    timestamp: DateTime<Utc>,
+    started: Instant,
+    task_id: String,
+    datasource: String,
+    query: String,
+    finished: bool,
+}
+
+impl AuditEntry<'_> {
+    /// Record the query as succeeded, or as failed with `error`
+    pub fn finish(mut self, error: Option<String>) {
+        let outcome = match error {
+            Some(_) => AuditOutcome::Failed,
+            None => AuditOutcome::Succeeded,
+        };
+        self.record(outcome, error);
+    }
+
+    fn record(&mut self, outcome: AuditOutcome, error: Option<String>) {
+        self.finished = true;
+        let record = AuditRecord {
+            timestamp: self.timestamp,
+            task_id: std::mem::take(&mut self.task_id),
+            datasource: std::mem::take(&mut self.datasource),
+            query: std::mem::take(&mut self.query),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            outcome,
+            error,
+        };
+        if let Err(e) = self.log.write(&record) {
+            log::error!("{:#}", e);
+        }
+    }
+}
+
+impl Drop for AuditEntry<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.record(AuditOutcome::Cancelled, None);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Minimal AWS support: credential resolution and Signature Version 4 signing
+//!
+//! Only what the agent needs to call a handful of AWS APIs over plain
+//! `reqwest`, without pulling in the full AWS SDK.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Instance metadata service endpoint used for instance role credentials
+const IMDS_URL: &str = "http://169.254.169.254";
+
+/// AWS access credentials
+#[derive(Debug, Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+/// Credentials document returned by the instance metadata service
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InstanceCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: Option<String>,
+}
+
+impl AwsCredentials {
+    /// Read credentials from the standard `AWS_*` environment variables
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            access_key_id: std::env::var("AWS_ACCESS_KEY_ID").ok()?,
+            secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY").ok()?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+
+    /// Resolve credentials from explicit values, the environment or the
+    /// EC2 instance role, in that order
+    pub async fn resolve(explicit: Option<AwsCredentials>, client: &Client) -> Result<Self> {
+        if let Some(credentials) = explicit.or_else(Self::from_env) {
+            return Ok(credentials);
+        }
+
+        Self::from_instance_metadata(client)
+            .await
+            .context("No AWS credentials configured and instance metadata is unavailable")
+    }
+
+    /// Fetch instance role credentials using IMDSv2
+    async fn from_instance_metadata(client: &Client) -> Result<Self> {
+        let timeout = Duration::from_secs(2);
+        let token = client
+            .put(format!("{}/latest/api/token", IMDS_URL))
+            .header("X-aws-ec2-metadata-token-ttl-seconds", "300")
+            .timeout(timeout)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let credentials_url = format!("{}/latest/meta-data/iam/security-credentials/", IMDS_URL);
+        let role = client
+            .get(&credentials_url)
+            .header("X-aws-ec2-metadata-token", &token)
+            .timeout(timeout)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let role = role
+            .lines()
+            .next()
+            .ok_or_else(|| anyhow!("No instance role attached"))?;
+
+        let credentials: InstanceCredentials = client
+            .get(format!("{}{}", credentials_url, role))
+            .header("X-aws-ec2-metadata-token", &token)
+            .timeout(timeout)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(Self {
+            access_key_id: credentials.access_key_id,
+            secret_access_key: credentials.secret_access_key,
+            session_token: credentials.token,
+        })
+    }
+}
+
+/// Service and region a request is signed for
+pub struct SigningScope<'a> {
+    pub region: &'a str,
+    pub service: &'a str,
+}
+
+/// Hex-encoded SHA-256 digest
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode a string as required by SigV4 (RFC 3986 unreserved set)
+pub fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Canonical, sorted query string of a URL
+fn canonical_query(url: &reqwest::Url) -> String {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(k, v)| (uri_encode(&k, true), uri_encode(&v, true)))
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Compute the SigV4 signature for a canonical request
+fn signature(
+    credentials: &AwsCredentials,
+    scope: &SigningScope,
+    time: DateTime<Utc>,
+    canonical_request: &str,
+) -> (String, String) {
+    let date = time.format("%Y%m%d").to_string();
+    let amz_date = time.format("%Y%m%dT%H%M%SZ").to_string();
+    let credential_scope = format!("{}/{}/{}/aws4_request", date, scope.region, scope.service);
+
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        credential_scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+
+    let key = hmac_sha256(
+        format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+        &date,
+    );
+    let key = hmac_sha256(&key, scope.region);
+    let key = hmac_sha256(&key, scope.service);
+    let key = hmac_sha256(&key, "aws4_request");
+
+    (
+        credential_scope,
+        hex::encode(hmac_sha256(&key, &string_to_sign)),
+    )
+}
+
+/// Sign a request, returning all headers that must be sent with it
+///
+/// `headers` must contain every header to be signed except `host` and
+/// `x-amz-date`, which are added automatically.
+pub fn sign_request(
+    method: &str,
+    url: &reqwest::Url,
+    headers: &BTreeMap<String, String>,
+    payload: &[u8],
+    credentials: &AwsCredentials,
+    scope: &SigningScope,
+    time: DateTime<Utc>,
+) -> BTreeMap<String, String> {
+    let mut headers: BTreeMap<String, String> = headers
+        .iter()
+        .map(|(k, v)| (k.to_lowercase(), v.trim().to_string()))
+        .collect();
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    headers.insert("host".to_string(), host);
+    headers.insert(
+        "x-amz-date".to_string(),
+        time.format("%Y%m%dT%H%M%SZ").to_string(),
+    );
+    if let Some(token) = &credentials.session_token {
+        headers.insert("x-amz-security-token".to_string(), token.clone());
+    }
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v))
+        .collect();
+    let signed_headers = headers.keys().cloned().collect::<Vec<_>>().join(";");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        url.path(),
+        canonical_query(url),
+        canonical_headers,
+        signed_headers,
+        sha256_hex(payload)
+    );
+
+    let (credential_scope, signature) = signature(credentials, scope, time, &canonical_request);
+    headers.insert(
+        "authorization".to_string(),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id, credential_scope, signed_headers, signature
+        ),
+    );
+    headers.remove("host");
+    headers
+}
+
+/// Hash standing for the payload of presigned S3 URLs, which isn't signed
+pub const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Presign a URL, adding the SigV4 query parameters so it's valid without
+/// headers for `expires`
+///
+/// Only the `host` header is signed, and `payload_hash` is the hex SHA-256
+/// of the request's body, or `UNSIGNED_PAYLOAD` for S3
+pub fn presign_url(
+    method: &str,
+    url: &reqwest::Url,
+    credentials: &AwsCredentials,
+    scope: &SigningScope,
+    time: DateTime<Utc>,
+    expires: Duration,
+    payload_hash: &str,
+) -> reqwest::Url {
+    let date = time.format("%Y%m%d").to_string();
+    let credential = format!(
+        "{}/{}/{}/{}/aws4_request",
+        credentials.access_key_id, date, scope.region, scope.service
+    );
+    let mut url = url.clone();
+    {
+        let mut query = url.query_pairs_mut();
+        query
+            .append_pair("X-Amz-Algorithm", "AWS4-HMAC-SHA256")
+            .append_pair("X-Amz-Credential", &credential)
+            .append_pair("X-Amz-Date", &time.format("%Y%m%dT%H%M%SZ").to_string())
+            .append_pair("X-Amz-Expires", &expires.as_secs().to_string());
+        if let Some(token) = &credentials.session_token {
+            query.append_pair("X-Amz-Security-Token", token);
+        }
+        query.append_pair("X-Amz-SignedHeaders", "host");
+    }
+
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let canonical_request = format!(
+        "{}\n{}\n{}\nhost:{}\n\nhost\n{}",
+        method,
+        url.path(),
+        canonical_query(&url),
+        host,
+        payload_hash
+    );
+    let (_, signature) = signature(credentials, scope, time, &canonical_request);
+
+    // Query values are re-encoded as SigV4 expects, which form encoding
+    // differs from for spaces and a few symbols
+    let query = format!("{}&X-Amz-Signature={}", canonical_query(&url), signature);
+    url.set_query(Some(&query));
+    url
+}
+
+/// Credentials of a role assumed with STS, valid until `expiration`
+#[derive(Debug, Clone)]
+pub struct AssumedRole {
+    pub credentials: AwsCredentials,
+    pub expiration: DateTime<Utc>,
+}
+
+/// Assume `role_arn` with STS `AssumeRole`, signed with `credentials`
+pub async fn assume_role(
+    client: &Client,
+    endpoint: &str,
+    region: &str,
+    credentials: &AwsCredentials,
+    role_arn: &str,
+    session_name: &str,
+) -> Result<AssumedRole> {
+    let url = reqwest::Url::parse(endpoint).context("Invalid STS endpoint")?;
+    let body = format!(
+        "Action=AssumeRole&Version=2011-06-15&RoleArn={}&RoleSessionName={}",
+        uri_encode(role_arn, true),
+        uri_encode(session_name, true)
+    );
+    let mut headers = BTreeMap::new();
+    headers.insert(
+        "content-type".to_string(),
+        "application/x-www-form-urlencoded".to_string(),
+    );
+    let signed = sign_request(
+        "POST",
+        &url,
+        &headers,
+        body.as_bytes(),
+        credentials,
+        &SigningScope {
+            region,
+            service: "sts",
+        },
+        Utc::now(),
+    );
+
+    let mut request = client.post(url).body(body);
+    for (name, value) in &signed {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let response = request.send().await.context("STS request failed")?;
+    let status = response.status();
+    let text = response.text().await?;
+    if !status.is_success() {
+        let message = xml_element(&text, "Message").unwrap_or(&text);
+        return Err(anyhow!("Failed to assume role {}: {}", role_arn, message));
+    }
+
+    let element = |name: &str| {
+        xml_element(&text, name)
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("STS response lacks {}", name))
+    };
+    Ok(AssumedRole {
+        credentials: AwsCredentials {
+            access_key_id: element("AccessKeyId")?,
+            secret_access_key: element("SecretAccessKey")?,
+            session_token: Some(element("SessionToken")?),
+        },
+        expiration: element("Expiration")?
+            .parse()
+            .context("Invalid expiration in STS response")?,
+    })
+}
+
+/// Text of the first `<name>` element of an XML document, enough for the
+/// flat responses of STS
+fn xml_element<'a>(document: &'a str, name: &str) -> Option<&'a str> {
+    let start = document.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = document[start..].find(&format!("</{}>", name))? + start;
+    Some(document[start..end].trim())
+}
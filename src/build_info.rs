@@ -0,0 +1,80 @@
+//! Build metadata of the agent, recorded by the build script
+//!
+//! Printed by `--version` and at startup, and sent when registering
+//! datasources so the server knows which build each agent runs.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::LazyLock;
+
+/// Version, commit and build date of the running agent
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BuildInfo {
+    pub version: String,
+    /// Short hash of the commit built, `unknown` outside of a git checkout
+    pub git_commit: String,
+    /// Build date in UTC, e.g. `2025-03-01`
+    pub build_date: String,
+    /// Cargo features enabled in the build
+    pub features: Vec<String>,
+    /// Target triple, e.g. `x86_64-unknown-linux-gnu`
+    pub target: String,
+}
+
+static BUILD_INFO: LazyLock<BuildInfo> = LazyLock::new(|| {
+    let build_date = env!("TSIGHT_BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .ok()
+        .and_then(|timestamp| DateTime::<Utc>::from_timestamp(timestamp, 0))
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_commit: env!("TSIGHT_GIT_COMMIT").to_string(),
+        build_date,
+        features: env!("TSIGHT_BUILD_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .map(str::to_string)
+            .collect(),
+        target: env!("TSIGHT_BUILD_TARGET").to_string(),
+    }
+});
+
+static LONG_VERSION: LazyLock<String> = LazyLock::new(|| {
+    let info = build_info();
+    let features = if info.features.is_empty() {
+        "none".to_string()
+    } else {
+        info.features.join(", ")
+    };
+    format!(
+        "{}\ncommit: {}\nbuild date: {}\nfeatures: {}\ntarget: {}",
+        info.version, info.git_commit, info.build_date, features, info.target
+    )
+});
+
+/// Get the build metadata of the running agent
+pub fn build_info() -> &'static BuildInfo {
+    &BUILD_INFO
+}
+
+/// Version with build metadata, one item per line, as printed by `--version`
+pub fn long_version() -> &'static str {
+    &LONG_VERSION
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (commit {}, built {}",
+            self.version, self.git_commit, self.build_date
+        )?;
+        if !self.features.is_empty() {
+            write!(f, ", features: {}", self.features.join(", "))?;
+        }
+        write!(f, ")")
+    }
+}
@@ -0,0 +1,319 @@
+//! Command line interface of the agent
+//!
+//! Without a command the agent runs. The other commands are one-off actions
+//! built on the same modules, so operators can check a configuration, a
+//! connection or a query without starting the full agent.
+
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::agent::{discover_datasource, preview_datasource};
+use crate::build_info;
+use crate::client::ServerApi;
+use crate::config::Config;
+use crate::diagnostics::{self, DiagnosticsReport};
+use crate::executors::clickhouse_source::TableSchema;
+use crate::executors::create_executor;
+use crate::filters::SqlFilters;
+use crate::health::CHECK_OK;
+use crate::models::{DataSource, DataSourceType, JobType};
+use crate::policy::{apply_row_filters, check_query};
+use crate::secrets::keyring::DEFAULT_KEYRING_SERVICE;
+use crate::service::DEFAULT_SERVICE_NAME;
+
+/// TSight agent running queries from the TSight server on your datasources
+#[derive(Debug, Parser)]
+#[command(name = "tsight_agent", version, long_version = build_info::long_version())]
+pub struct Cli {
+    /// Configuration file, taking precedence over `TSIGHT_CONFIG` and the
+    /// default locations
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Detach from the terminal and run in the background, appending output
+    /// to the configured log file. Only applies to running the agent
+    #[arg(long, global = true)]
+    pub daemon: bool,
+
+    /// Write the agent's PID to this file and lock it, refusing to run while
+    /// another agent holds the lock. Only applies to running the agent
+    #[arg(long, global = true, value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand, PartialEq)]
+pub enum Command {
+    /// Run the agent, the default without a command
+    Run,
+    /// Check the configuration and exit
+    Validate,
+    /// Discover schemas once and print them as JSON, or submit them to the
+    /// server with `--submit`
+    Discover(DiscoverArgs),
+    /// Check that the server and datasources can be reached
+    TestConnection(DatasourceArgs),
+    /// Run a query with the datasource's policy and filters applied and
+    /// print its rows as JSON lines
+    Query {
+        /// Datasource to query
+        #[arg(long)]
+        datasource: String,
+        /// SQL of the query
+        sql: String,
+    },
+    /// Print the version
+    Version,
+    /// Store secrets in the OS keyring
+    #[command(subcommand)]
+    Secret(SecretCommand),
+    /// Explain which filter rules apply
+    #[command(subcommand)]
+    Filters(FiltersCommand),
+    /// Help with writing the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Run the agent as a Windows service
+    #[command(subcommand)]
+    Service(ServiceCommand),
+}
+
+/// Datasources a command applies to
+#[derive(Debug, Args, PartialEq)]
+pub struct DatasourceArgs {
+    /// Only this datasource instead of all configured ones
+    #[arg(long)]
+    pub datasource: Option<String>,
+}
+
+#[derive(Debug, Args, PartialEq)]
+pub struct DiscoverArgs {
+    #[command(flatten)]
+    pub datasources: DatasourceArgs,
+    /// Submit the schemas to the server instead of printing them
+    #[arg(long)]
+    pub submit: bool,
+    /// Only print the schemas, the default without `--submit`
+    #[arg(long, conflicts_with = "submit")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Subcommand, PartialEq)]
+pub enum SecretCommand {
+    /// Read a secret from the terminal or stdin and store it
+    Set {
+        /// Entry to store the secret as, referenced as `keyring:<entry>`
+        entry: String,
+        /// Keyring service the entry belongs to
+        #[arg(long, default_value = DEFAULT_KEYRING_SERVICE)]
+        service: String,
+    },
+    /// Read a secret from the terminal or stdin and print it as an `enc:`
+    /// value for the configuration, creating the key if there is none
+    Encrypt {
+        /// Key file, defaults to `secret.key` next to the configuration file
+        #[arg(long, value_name = "PATH", conflicts_with = "keyring_entry")]
+        key_file: Option<PathBuf>,
+        /// Keep the key in this OS keyring entry instead of a file
+        #[arg(long)]
+        keyring_entry: Option<String>,
+        /// Keyring service the key entry belongs to
+        #[arg(long, default_value = DEFAULT_KEYRING_SERVICE)]
+        service: String,
+    },
+}
+
+#[derive(Debug, Subcommand, PartialEq)]
+pub enum FiltersCommand {
+    /// Print which filter rule matches the given database, table, column
+    /// and value
+    Explain {
+        #[arg(long)]
+        value: Option<String>,
+        #[arg(long)]
+        column: Option<String>,
+        /// Table, optionally qualified as `database.table`
+        #[arg(long)]
+        table: Option<String>,
+        #[arg(long)]
+        database: Option<String>,
+        /// Use the filters of this datasource instead of the global ones
+        #[arg(long)]
+        datasource: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand, PartialEq)]
+pub enum ConfigCommand {
+    /// Print an annotated example configuration
+    Example {
+        /// Type of the example datasource: clickhouse, postgresql, mysql,
+        /// prometheus or mock
+        #[arg(long, default_value = "clickhouse")]
+        datasource: DataSourceType,
+    },
+}
+
+#[derive(Debug, Subcommand, PartialEq)]
+pub enum ServiceCommand {
+    /// Register the agent as a service starting at boot, with the
+    /// configuration passed by `--config` or found at the default locations
+    Install {
+        #[arg(long, default_value = DEFAULT_SERVICE_NAME)]
+        name: String,
+    },
+    /// Run as the service, as started by the service control manager
+    Run {
+        #[arg(long, default_value = DEFAULT_SERVICE_NAME)]
+        name: String,
+    },
+    /// Stop the service and remove it
+    Uninstall {
+        #[arg(long, default_value = DEFAULT_SERVICE_NAME)]
+        name: String,
+    },
+}
+
+impl Cli {
+    /// Command to run, running the agent by default
+    pub fn command(&self) -> &Command {
+        self.command.as_ref().unwrap_or(&Command::Run)
+    }
+}
+
+/// Select the datasource named `name`, or all of them
+pub fn select_datasources<'a>(
+    config: &'a Config,
+    name: Option<&str>,
+) -> Result<Vec<&'a DataSource>> {
+    match name {
+        Some(name) => Ok(vec![find_datasource(config, name)?]),
+        None => Ok(config.datasources.iter().collect()),
+    }
+}
+
+fn find_datasource<'a>(config: &'a Config, name: &str) -> Result<&'a DataSource> {
+    config
+        .datasources
+        .iter()
+        .find(|datasource| datasource.name == name)
+        .ok_or_else(|| anyhow!("Unknown datasource '{}'", name))
+}
+
+/// Check what loading the configuration doesn't: every datasource has a
+/// host and all filter rules compile
+pub fn validate(config: &Config) -> Result<()> {
+    SqlFilters::new(config.global_filters.as_ref())
+        .map_err(|e| anyhow!("Invalid global filters: {}", e))?;
+    for datasource in &config.datasources {
+        if datasource.hosts.is_empty() && datasource.source_type != DataSourceType::Mock {
+            return Err(anyhow!("Datasource {} has no hosts", datasource.name));
+        }
+        let filters = datasource.effective_filters(config.global_filters.as_ref());
+        SqlFilters::new(filters.as_ref())
+            .map_err(|e| anyhow!("Invalid filters of datasource {}: {}", datasource.name, e))?;
+    }
+    Ok(())
+}
+
+/// Discover the schemas of datasources and submit them, skipping disabled
+/// ones and those in a maintenance window
+pub async fn discover(
+    config: &Config,
+    datasources: &[&DataSource],
+    server_client: &dyn ServerApi,
+) -> Result<()> {
+    let mut failed = Vec::new();
+    for datasource in datasources {
+        if let Some(reason) = datasource.unavailable_reason(chrono::Utc::now()) {
+            log::warn!("Skipping schema discovery: {}", reason);
+            continue;
+        }
+        if let Err(e) =
+            discover_datasource(datasource, server_client, config.global_filters.clone()).await
+        {
+            log::error!(
+                "Failed to discover schemas for datasource {}: {:#}",
+                datasource.name,
+                e
+            );
+            failed.push(datasource.name.as_str());
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Schema discovery failed for {}", failed.join(", ")))
+    }
+}
+
+/// Discover the schemas of datasources without submitting them, keyed by
+/// datasource name, skipping disabled ones and those in a maintenance window
+pub async fn preview(
+    config: &Config,
+    datasources: &[&DataSource],
+) -> Result<BTreeMap<String, Vec<TableSchema>>> {
+    let mut schemas = BTreeMap::new();
+    let mut failed = Vec::new();
+    for datasource in datasources {
+        if let Some(reason) = datasource.unavailable_reason(chrono::Utc::now()) {
+            log::warn!("Skipping schema discovery: {}", reason);
+            continue;
+        }
+        match preview_datasource(datasource, config.global_filters.clone()).await {
+            Ok(tables) => {
+                schemas.insert(datasource.name.clone(), tables);
+            }
+            Err(e) => {
+                log::error!(
+                    "Failed to discover schemas for datasource {}: {:#}",
+                    datasource.name,
+                    e
+                );
+                failed.push(datasource.name.as_str());
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(schemas)
+    } else {
+        Err(anyhow!("Schema discovery failed for {}", failed.join(", ")))
+    }
+}
+
+/// Check the server and datasources, returning the report and whether every
+/// check that ran passed
+pub async fn test_connection(
+    config: &Config,
+    datasources: &[&DataSource],
+    server_client: &dyn ServerApi,
+) -> (DiagnosticsReport, bool) {
+    let datasources: Vec<DataSource> = datasources.iter().map(|&ds| ds.clone()).collect();
+    let report =
+        diagnostics::run(server_client, &datasources, config.global_filters.as_ref()).await;
+    let passed = report.server.status == CHECK_OK
+        && report
+            .datasources
+            .values()
+            .all(|check| check.status == CHECK_OK || check.latency_ms.is_none());
+    (report, passed)
+}
+
+/// Run a query the way jobs are run, returning its rows after filters
+pub async fn query(config: &Config, datasource: &DataSource, sql: &str) -> Result<Vec<JobType>> {
+    let filters = datasource.effective_filters(config.global_filters.as_ref());
+    let sql_filters = SqlFilters::new(filters.as_ref())?;
+    let database = datasource.effective_database();
+    check_query(&datasource.query_policy, Some(&sql_filters), database, sql)?;
+    let sql = apply_row_filters(datasource.row_filters.as_ref(), database, sql)?;
+
+    let executor = create_executor(datasource, config.global_filters.clone()).await?;
+    let (rows, _, _) = executor.execute_job_with_columns(&sql).await?;
+    Ok(rows)
+}
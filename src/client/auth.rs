@@ -0,0 +1,142 @@
+//! Credentials used to authorize requests to the server API
+//!
+//! Supports a static API key as well as OAuth2 client-credentials flow,
+//! where the bearer token is obtained from a token endpoint, cached and
+//! refreshed shortly before it expires.
+
+use crate::config::AuthConfig;
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Lifetime assumed for tokens when the endpoint doesn't report `expires_in`
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(300);
+
+/// Source of the bearer token sent with every request
+#[derive(Clone)]
+pub enum Credentials {
+    /// Long-lived static API key
+    ApiKey(String),
+    /// Token obtained via OAuth2 client-credentials grant
+    OAuth2(Arc<OAuth2TokenProvider>),
+}
+
+impl Credentials {
+    /// Get the current bearer token, fetching a new one if required
+    pub async fn bearer_token(&self) -> Result<String> {
+        match self {
+            Credentials::ApiKey(api_key) => Ok(api_key.clone()),
+            Credentials::OAuth2(provider) => provider.token().await,
+        }
+    }
+
+    /// Drop any cached token so the next request obtains a fresh one
+    pub async fn invalidate(&self) {
+        if let Credentials::OAuth2(provider) = self {
+            provider.invalidate().await;
+        }
+    }
+}
+
+/// Token endpoint response as defined by RFC 6749
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+/// Cached access token with its refresh deadline
+struct CachedToken {
+    access_token: String,
+    refresh_at: Instant,
+}
+
+/// Obtains and caches tokens using the OAuth2 client-credentials grant
+pub struct OAuth2TokenProvider {
+    config: AuthConfig,
+    client: Client,
+    cached: Mutex<Option<CachedToken>>,
+}
+
+impl OAuth2TokenProvider {
+    /// Create a new token provider
+    pub fn new(config: AuthConfig, client: Client) -> Self {
+        Self {
+            config,
+            client,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Get a valid access token, refreshing it before expiry
+    pub async fn token(&self) -> Result<String> {
+        // Holding the lock while fetching keeps concurrent agents from
+        // requesting several tokens at once
+        let mut cached = self.cached.lock().await;
+
+        if let Some(token) = cached.as_ref() {
+            if Instant::now() < token.refresh_at {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let token = self.fetch_token().await?;
+        let access_token = token.access_token.clone();
+        *cached = Some(token);
+        Ok(access_token)
+    }
+
+    /// Drop the cached token
+    pub async fn invalidate(&self) {
+        *self.cached.lock().await = None;
+    }
+
+    /// Request a new token from the token endpoint
+    async fn fetch_token(&self) -> Result<CachedToken> {
+        log::debug!("Requesting access token from {}", self.config.token_url);
+
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", self.config.client_id.as_str()),
+            ("client_secret", self.config.client_secret.as_str()),
+        ];
+        if let Some(scope) = &self.config.scope {
+            form.push(("scope", scope.as_str()));
+        }
+
+        let response = self
+            .client
+            .post(&self.config.token_url)
+            .form(&form)
+            .timeout(Duration::from_secs(30))
+            .send()
+            .await
+            .context("Failed to send token request")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to obtain access token: {}",
+                response.status()
+            ));
+        }
+
+        let body: TokenResponse = response
+            .json()
+            .await
+            .context("Failed to parse token response")?;
+
+        let lifetime = body
+            .expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TOKEN_LIFETIME);
+        let margin = Duration::from_secs(self.config.refresh_margin_secs);
+
+        Ok(CachedToken {
+            access_token: body.access_token,
+            refresh_at: Instant::now() + lifetime.saturating_sub(margin),
+        })
+    }
+}
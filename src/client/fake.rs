@@ -0,0 +1,596 @@
+//! In-memory implementation of the server API
+//!
+//! `FakeServer` hands out queued tasks and jobs and records everything the
+//! agents submit, so agents can be exercised without an HTTP server.
+
+use super::manifest::ExportManifest;
+use super::{
+    AcquireResultBody, DatasourceStatus, DiscoverySummary, FullSyncRequested, JobResultSet,
+    LeaseGrant, LeaseRequest, ServerApi, TaskResultSet,
+};
+use crate::crash::CrashReport;
+use crate::diagnostics::DiagnosticsReport;
+use crate::errors::{ErrorSummary, NoWorkAvailable};
+use crate::executors::base::ExecutionStats;
+use crate::executors::clickhouse_source::{ResultColumn, TableSchema};
+use crate::filters::FilterStats;
+use crate::models::{JobType, Record};
+use crate::schema_diff::SchemaDiff;
+use crate::slow_query::SlowQuery;
+use crate::spill::JobRows;
+use crate::timeseries::NullStats;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Everything queued on and submitted to the fake server
+#[derive(Default)]
+struct FakeState {
+    queries: VecDeque<AcquireResultBody>,
+    high_priority_queries: VecDeque<AcquireResultBody>,
+    jobs: VecDeque<AcquireResultBody>,
+    task_results: Vec<(String, Vec<Record>)>,
+    task_null_stats: Vec<(String, NullStats)>,
+    task_result_sets: Vec<(String, Vec<TaskResultSet>)>,
+    task_errors: Vec<(String, String)>,
+    job_results: Vec<(String, Vec<JobType>)>,
+    job_columns: Vec<(String, Vec<ResultColumn>)>,
+    job_filter_stats: Vec<(String, FilterStats)>,
+    job_result_sets: Vec<(String, Vec<JobResultSet>)>,
+    job_exports: Vec<(String, ExportManifest)>,
+    job_errors: Vec<(String, String)>,
+    diagnostics: Vec<(String, DiagnosticsReport)>,
+    execution_stats: Vec<(String, ExecutionStats)>,
+    schemas: HashMap<String, Vec<TableSchema>>,
+    cached_schemas: Vec<(String, Vec<TableSchema>)>,
+    schema_diffs: Vec<(String, SchemaDiff)>,
+    full_sync_requested: bool,
+    schema_batches: Vec<(String, Vec<TableSchema>)>,
+    pending_schemas: HashMap<String, Vec<TableSchema>>,
+    discovery_summaries: Vec<(String, DiscoverySummary)>,
+    datasources: HashMap<String, String>,
+    datasource_labels: HashMap<String, BTreeMap<String, String>>,
+    schema_labels: HashMap<String, BTreeMap<String, String>>,
+    datasource_statuses: Vec<(String, DatasourceStatus)>,
+    crash_reports: Vec<CrashReport>,
+    slow_queries: Vec<SlowQuery>,
+    error_summaries: Vec<ErrorSummary>,
+    /// Holder of the lease and when it expires
+    lease: Option<(String, Instant)>,
+    unreachable: bool,
+}
+
+/// In-memory server for tests and local development
+#[derive(Default)]
+pub struct FakeServer {
+    state: Mutex<FakeState>,
+}
+
+impl FakeServer {
+    /// Create an empty fake server
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a task to be handed out to observation agents
+    pub fn enqueue_task(&self, task: AcquireResultBody, is_high_priority_queue: bool) {
+        let mut state = self.state.lock().unwrap();
+        if is_high_priority_queue {
+            state.high_priority_queries.push_back(task);
+        } else {
+            state.queries.push_back(task);
+        }
+    }
+
+    /// Queue a job to be handed out to job agents
+    pub fn enqueue_job(&self, job: AcquireResultBody) {
+        self.state.lock().unwrap().jobs.push_back(job);
+    }
+
+    /// Submitted task results as `(task_id, records)`
+    pub fn task_results(&self) -> Vec<(String, Vec<Record>)> {
+        self.state.lock().unwrap().task_results.clone()
+    }
+
+    /// `NULL` statistics submitted with task results as `(task_id, stats)`
+    pub fn task_null_stats(&self) -> Vec<(String, NullStats)> {
+        self.state.lock().unwrap().task_null_stats.clone()
+    }
+
+    /// Result sets submitted for multi-query tasks as `(task_id, result_sets)`
+    pub fn task_result_sets(&self) -> Vec<(String, Vec<TaskResultSet>)> {
+        self.state.lock().unwrap().task_result_sets.clone()
+    }
+
+    /// Submitted task errors as `(task_id, error)`
+    pub fn task_errors(&self) -> Vec<(String, String)> {
+        self.state.lock().unwrap().task_errors.clone()
+    }
+
+    /// Submitted job results as `(job_id, records)`
+    pub fn job_results(&self) -> Vec<(String, Vec<JobType>)> {
+        self.state.lock().unwrap().job_results.clone()
+    }
+
+    /// Columns submitted with job results as `(job_id, columns)`
+    pub fn job_columns(&self) -> Vec<(String, Vec<ResultColumn>)> {
+        self.state.lock().unwrap().job_columns.clone()
+    }
+
+    /// Filter statistics submitted with job results as `(job_id, stats)`
+    pub fn job_filter_stats(&self) -> Vec<(String, FilterStats)> {
+        self.state.lock().unwrap().job_filter_stats.clone()
+    }
+
+    /// Result sets submitted for multi-query jobs as `(job_id, result_sets)`
+    pub fn job_result_sets(&self) -> Vec<(String, Vec<JobResultSet>)> {
+        self.state.lock().unwrap().job_result_sets.clone()
+    }
+
+    /// Manifests of job results uploaded to a bucket as `(job_id, export)`
+    pub fn job_exports(&self) -> Vec<(String, ExportManifest)> {
+        self.state.lock().unwrap().job_exports.clone()
+    }
+
+    /// Submitted job errors as `(job_id, error)`
+    pub fn job_errors(&self) -> Vec<(String, String)> {
+        self.state.lock().unwrap().job_errors.clone()
+    }
+
+    /// Reports submitted for diagnostics jobs as `(job_id, report)`
+    pub fn diagnostics(&self) -> Vec<(String, DiagnosticsReport)> {
+        self.state.lock().unwrap().diagnostics.clone()
+    }
+
+    /// Execution statistics submitted with task and job results as
+    /// `(id, stats)`
+    pub fn execution_stats(&self) -> Vec<(String, ExecutionStats)> {
+        self.state.lock().unwrap().execution_stats.clone()
+    }
+
+    /// Last schemas submitted for a datasource
+    pub fn schemas(&self, datasource_name: &str) -> Option<Vec<TableSchema>> {
+        self.state
+            .lock()
+            .unwrap()
+            .schemas
+            .get(datasource_name)
+            .cloned()
+    }
+
+    /// Schemas submitted from the agent's cache as `(datasource_name, schemas)`
+    pub fn cached_schemas(&self) -> Vec<(String, Vec<TableSchema>)> {
+        self.state.lock().unwrap().cached_schemas.clone()
+    }
+
+    /// Submitted schema diffs as `(datasource_name, diff)`
+    pub fn schema_diffs(&self) -> Vec<(String, SchemaDiff)> {
+        self.state.lock().unwrap().schema_diffs.clone()
+    }
+
+    /// Submitted schema batches as `(datasource_name, schemas)`
+    pub fn schema_batches(&self) -> Vec<(String, Vec<TableSchema>)> {
+        self.state.lock().unwrap().schema_batches.clone()
+    }
+
+    /// Submitted discovery completion markers as `(datasource_name, summary)`
+    pub fn discovery_summaries(&self) -> Vec<(String, DiscoverySummary)> {
+        self.state.lock().unwrap().discovery_summaries.clone()
+    }
+
+    /// Reported datasource connectivity changes as `(datasource_name, status)`
+    pub fn datasource_statuses(&self) -> Vec<(String, DatasourceStatus)> {
+        self.state.lock().unwrap().datasource_statuses.clone()
+    }
+
+    /// Reported crashes
+    pub fn crash_reports(&self) -> Vec<CrashReport> {
+        self.state.lock().unwrap().crash_reports.clone()
+    }
+
+    /// Reported slow queries
+    pub fn slow_queries(&self) -> Vec<SlowQuery> {
+        self.state.lock().unwrap().slow_queries.clone()
+    }
+
+    /// Reported error summaries
+    pub fn error_summaries(&self) -> Vec<ErrorSummary> {
+        self.state.lock().unwrap().error_summaries.clone()
+    }
+
+    /// Answer the next schema diff with a full sync request
+    pub fn request_full_sync(&self) {
+        self.state.lock().unwrap().full_sync_requested = true;
+    }
+
+    /// Fail reachability checks and datasource status reports, as if the
+    /// server were down
+    pub fn set_unreachable(&self, unreachable: bool) {
+        self.state.lock().unwrap().unreachable = unreachable;
+    }
+
+    /// Type of a registered datasource
+    pub fn datasource_type(&self, datasource_name: &str) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .datasources
+            .get(datasource_name)
+            .cloned()
+    }
+
+    /// Agent holding an unexpired lease
+    pub fn lease_holder(&self) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .lease
+            .as_ref()
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(holder, _)| holder.clone())
+    }
+
+    /// Labels a datasource was registered with
+    pub fn datasource_labels(&self, datasource_name: &str) -> Option<BTreeMap<String, String>> {
+        self.state
+            .lock()
+            .unwrap()
+            .datasource_labels
+            .get(datasource_name)
+            .cloned()
+    }
+
+    /// Labels submitted with the last schemas or schema batch of a datasource
+    pub fn schema_labels(&self, datasource_name: &str) -> Option<BTreeMap<String, String>> {
+        self.state
+            .lock()
+            .unwrap()
+            .schema_labels
+            .get(datasource_name)
+            .cloned()
+    }
+}
+
+#[async_trait]
+impl ServerApi for FakeServer {
+    async fn acquire_next_query(&self, is_high_priority_queue: bool) -> Result<AcquireResultBody> {
+        let mut state = self.state.lock().unwrap();
+        let queue = if is_high_priority_queue {
+            &mut state.high_priority_queries
+        } else {
+            &mut state.queries
+        };
+        queue
+            .pop_front()
+            .ok_or_else(|| anyhow!(NoWorkAvailable::TASKS))
+    }
+
+    async fn submit_results(
+        &self,
+        task_id: &str,
+        data: Vec<Record>,
+        null_stats: NullStats,
+        execution_stats: ExecutionStats,
+        _is_high_priority_queue: bool,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.task_results.push((task_id.to_string(), data));
+        state
+            .task_null_stats
+            .push((task_id.to_string(), null_stats));
+        state
+            .execution_stats
+            .push((task_id.to_string(), execution_stats));
+        Ok(())
+    }
+
+    async fn submit_result_sets(
+        &self,
+        task_id: &str,
+        result_sets: Vec<TaskResultSet>,
+        execution_stats: ExecutionStats,
+        _is_high_priority_queue: bool,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .task_result_sets
+            .push((task_id.to_string(), result_sets));
+        state
+            .execution_stats
+            .push((task_id.to_string(), execution_stats));
+        Ok(())
+    }
+
+    async fn submit_error(
+        &self,
+        task_id: &str,
+        error: &str,
+        _is_high_priority_queue: bool,
+    ) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .task_errors
+            .push((task_id.to_string(), error.to_string()));
+        Ok(())
+    }
+
+    async fn acquire_next_job(&self) -> Result<AcquireResultBody> {
+        self.state
+            .lock()
+            .unwrap()
+            .jobs
+            .pop_front()
+            .ok_or_else(|| anyhow!(NoWorkAvailable::JOBS))
+    }
+
+    async fn submit_job_results(
+        &self,
+        job_id: &str,
+        data: Vec<JobType>,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.job_results.push((job_id.to_string(), data));
+        state.job_columns.push((job_id.to_string(), columns));
+        state
+            .job_filter_stats
+            .push((job_id.to_string(), filter_stats));
+        state
+            .execution_stats
+            .push((job_id.to_string(), execution_stats));
+        Ok(())
+    }
+
+    async fn submit_job_chunks(
+        &self,
+        job_id: &str,
+        data: JobRows,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        let data = data.into_vec()?;
+        self.submit_job_results(job_id, data, columns, filter_stats, execution_stats)
+            .await
+    }
+
+    async fn submit_job_export(
+        &self,
+        job_id: &str,
+        export: ExportManifest,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.job_exports.push((job_id.to_string(), export));
+        state.job_columns.push((job_id.to_string(), columns));
+        state
+            .job_filter_stats
+            .push((job_id.to_string(), filter_stats));
+        state
+            .execution_stats
+            .push((job_id.to_string(), execution_stats));
+        Ok(())
+    }
+
+    async fn submit_job_result_sets(
+        &self,
+        job_id: &str,
+        result_sets: Vec<JobResultSet>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .job_result_sets
+            .push((job_id.to_string(), result_sets));
+        state
+            .job_filter_stats
+            .push((job_id.to_string(), filter_stats));
+        state
+            .execution_stats
+            .push((job_id.to_string(), execution_stats));
+        Ok(())
+    }
+
+    async fn submit_job_error(&self, job_id: &str, error: &str) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .job_errors
+            .push((job_id.to_string(), error.to_string()));
+        Ok(())
+    }
+
+    async fn submit_diagnostics(&self, job_id: &str, report: DiagnosticsReport) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .diagnostics
+            .push((job_id.to_string(), report));
+        Ok(())
+    }
+
+    async fn submit_schemas(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .schema_labels
+            .insert(datasource_name.to_string(), labels.clone());
+        state.schemas.insert(datasource_name.to_string(), schemas);
+        Ok(())
+    }
+
+    async fn submit_cached_schemas(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .schema_labels
+            .insert(datasource_name.to_string(), labels.clone());
+        state
+            .cached_schemas
+            .push((datasource_name.to_string(), schemas.clone()));
+        state.schemas.insert(datasource_name.to_string(), schemas);
+        Ok(())
+    }
+
+    async fn submit_schema_diff(&self, datasource_name: &str, diff: SchemaDiff) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let full_sync_requested = std::mem::take(&mut state.full_sync_requested);
+        let Some(schemas) = state.schemas.get(datasource_name).cloned() else {
+            return Err(FullSyncRequested.into());
+        };
+        if full_sync_requested {
+            return Err(FullSyncRequested.into());
+        }
+
+        let schemas = diff.apply(schemas);
+        state.schemas.insert(datasource_name.to_string(), schemas);
+        state.schema_diffs.push((datasource_name.to_string(), diff));
+        Ok(())
+    }
+
+    async fn submit_schema_batch(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .schema_labels
+            .insert(datasource_name.to_string(), labels.clone());
+        state
+            .pending_schemas
+            .entry(datasource_name.to_string())
+            .or_default()
+            .extend(schemas.iter().cloned());
+        state
+            .schema_batches
+            .push((datasource_name.to_string(), schemas));
+        Ok(())
+    }
+
+    async fn complete_discovery(
+        &self,
+        datasource_name: &str,
+        summary: DiscoverySummary,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let schemas = state
+            .pending_schemas
+            .remove(datasource_name)
+            .unwrap_or_default();
+        state.schemas.insert(datasource_name.to_string(), schemas);
+        state
+            .discovery_summaries
+            .push((datasource_name.to_string(), summary));
+        Ok(())
+    }
+
+    async fn add_datasource(
+        &self,
+        datasource_name: &str,
+        datasource_type: &str,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .datasources
+            .insert(datasource_name.to_string(), datasource_type.to_string());
+        state
+            .datasource_labels
+            .insert(datasource_name.to_string(), labels.clone());
+        Ok(())
+    }
+
+    async fn report_datasource_status(
+        &self,
+        datasource_name: &str,
+        status: &DatasourceStatus,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.unreachable {
+            return Err(anyhow!("Server unreachable"));
+        }
+        state
+            .datasource_statuses
+            .push((datasource_name.to_string(), status.clone()));
+        Ok(())
+    }
+
+    async fn report_crash(&self, report: &CrashReport) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .crash_reports
+            .push(report.clone());
+        Ok(())
+    }
+
+    async fn report_slow_query(&self, query: &SlowQuery) -> Result<()> {
+        self.state.lock().unwrap().slow_queries.push(query.clone());
+        Ok(())
+    }
+
+    async fn report_errors(&self, summary: &ErrorSummary) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .error_summaries
+            .push(summary.clone());
+        Ok(())
+    }
+
+    async fn acquire_lease(&self, request: &LeaseRequest) -> Result<LeaseGrant> {
+        let mut state = self.state.lock().unwrap();
+        if state.unreachable {
+            return Err(anyhow!("Server unreachable"));
+        }
+        let now = Instant::now();
+        let granted = match &state.lease {
+            Some((holder, expires)) => *holder == request.holder || *expires <= now,
+            None => true,
+        };
+        if granted {
+            let expires = now + Duration::from_millis(request.ttl_ms);
+            state.lease = Some((request.holder.clone(), expires));
+        }
+        Ok(LeaseGrant {
+            granted,
+            holder: state.lease.as_ref().map(|(holder, _)| holder.clone()),
+        })
+    }
+
+    async fn release_lease(&self, holder: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .lease
+            .as_ref()
+            .is_some_and(|(current, _)| current == holder)
+        {
+            state.lease = None;
+        }
+        Ok(())
+    }
+
+    async fn check_reachable(&self) -> Result<()> {
+        if self.state.lock().unwrap().unreachable {
+            return Err(anyhow!("Server unreachable"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,702 @@
+//! gRPC transport of the task protocol
+//!
+//! `GrpcServerClient` acquires tasks and jobs, submits their results and
+//! reports datasource connectivity over the server's gRPC service, defined
+//! in `proto/agent.proto`. Protobuf carries records and task definitions as
+//! typed messages rather than JSON, and with `push` set the server hands
+//! out tasks over one long-lived stream instead of a request per poll.
+//! Everything else, from registration to leases, still goes to the HTTP
+//! API through an inner `ServerClient`, which also supplies credentials and
+//! the rate limit.
+
+pub mod proto;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use hyper_tls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use reqwest::StatusCode;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::client::Grpc;
+use tonic::codec::{ProstCodec, Streaming};
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::{Code, Status};
+
+use super::manifest::{ExportManifest, Manifest};
+use super::{
+    parse_retry_after, AcquireResultBody, BackoffRequested, DatasourceStatus, DiscoverySummary,
+    JobResultSet, LeaseGrant, LeaseRequest, ServerApi, ServerClient, TaskResultSet,
+    DEFAULT_RETRY_AFTER, MAX_RETRY_AFTER, PROTOCOL_VERSION, PROTOCOL_VERSION_HEADER,
+};
+use crate::config::{GrpcConfig, ServerConfig};
+use crate::crash::CrashReport;
+use crate::diagnostics::DiagnosticsReport;
+use crate::errors::{CategorizedError, ErrorCategory, ErrorSummary, NoWorkAvailable};
+use crate::executors::base::ExecutionStats;
+use crate::executors::clickhouse_source::{ResultColumn, TableSchema};
+use crate::filters::FilterStats;
+use crate::kubernetes::pod_metadata;
+use crate::models::{JobType, Record};
+use crate::notify::conditions;
+use crate::schema_diff::SchemaDiff;
+use crate::slow_query::SlowQuery;
+use crate::spill::JobRows;
+use crate::telemetry::trace_headers;
+use crate::timeseries::NullStats;
+use proto::Queue;
+
+/// HTTP/2 client carrying gRPC calls, over TLS for `https` endpoints
+type Channel = hyper_util::client::legacy::Client<HttpsConnector<HttpConnector>, tonic::body::Body>;
+
+/// Time the server gets to hand out a task, as on the HTTP API
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Interval of HTTP/2 pings keeping the task stream open through proxies
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Client of the server's gRPC service
+pub struct GrpcServerClient {
+    http: ServerClient,
+    grpc: Grpc<Channel>,
+    extra_headers: HashMap<String, String>,
+    /// Task stream, when the server pushes tasks
+    push: Option<Push>,
+}
+
+/// Tasks pushed by the server over a `Session` stream
+#[derive(Default)]
+struct Push {
+    /// Stream opened on first use, and again once it closes
+    session: tokio::sync::Mutex<Option<Arc<Session>>>,
+    /// Tasks pushed after the agent asking for them gave up waiting,
+    /// handed out before asking for more
+    unclaimed: Arc<Mutex<HashMap<i32, VecDeque<proto::Task>>>>,
+}
+
+/// Agents waiting for a pushed task or no work, by queue
+type Waiting = HashMap<i32, VecDeque<oneshot::Sender<Option<proto::Task>>>>;
+
+/// Open task stream, answering each `Ready` message with a task or no work
+struct Session {
+    ready: mpsc::UnboundedSender<proto::AgentMessage>,
+    /// Agents waiting for an answer by queue, in the order they asked;
+    /// `None` once the stream closed
+    waiting: Mutex<Option<Waiting>>,
+    unclaimed: Arc<Mutex<HashMap<i32, VecDeque<proto::Task>>>>,
+}
+
+impl Session {
+    fn is_open(&self) -> bool {
+        self.waiting.lock().unwrap().is_some()
+    }
+
+    /// Ask for the next task of a queue, failing once the stream closed
+    fn request(&self, queue: Queue) -> Result<oneshot::Receiver<Option<proto::Task>>> {
+        let (sender, receiver) = oneshot::channel();
+        let mut waiting = self.waiting.lock().unwrap();
+        let waiting = waiting
+            .as_mut()
+            .context("Task stream of the server closed")?;
+        waiting.entry(queue as i32).or_default().push_back(sender);
+        let message = proto::AgentMessage {
+            message: Some(proto::agent_message::Message::Ready(proto::Ready {
+                queue: queue as i32,
+            })),
+        };
+        self.ready
+            .send(message)
+            .map_err(|_| anyhow!("Task stream of the server closed"))?;
+        Ok(receiver)
+    }
+
+    /// Hand a pushed task to the first agent still waiting for its queue,
+    /// keeping it for the next one to ask when there's none
+    fn deliver(&self, message: proto::ServerMessage) {
+        let mut task = match message.message {
+            Some(proto::server_message::Message::Task(task)) => Some(*task),
+            Some(proto::server_message::Message::NoWork(_)) | None => None,
+        };
+        if let Some(waiting) = self.waiting.lock().unwrap().as_mut() {
+            let waiting = waiting.entry(message.queue).or_default();
+            while let Some(waiter) = waiting.pop_front() {
+                match waiter.send(task) {
+                    Ok(()) => return,
+                    Err(returned) => task = returned,
+                }
+            }
+        }
+        if let Some(task) = task {
+            self.unclaimed
+                .lock()
+                .unwrap()
+                .entry(message.queue)
+                .or_default()
+                .push_back(task);
+        }
+    }
+
+    /// Read pushed tasks until the stream ends, then fail the waiting agents
+    async fn read(self: Arc<Self>, mut stream: Streaming<proto::ServerMessage>) {
+        loop {
+            match stream.message().await {
+                Ok(Some(message)) => self.deliver(message),
+                Ok(None) => {
+                    log::info!("Server closed the task stream");
+                    break;
+                }
+                Err(status) => {
+                    log::warn!("Task stream failed: {}", status);
+                    break;
+                }
+            }
+        }
+        self.waiting.lock().unwrap().take();
+    }
+}
+
+/// Build the HTTP/2 client for gRPC calls, negotiating HTTP/2 over TLS
+fn build_channel() -> Channel {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_nodelay(true);
+    let tls = native_tls::TlsConnector::builder()
+        .request_alpns(&["h2"])
+        .build()
+        .expect("Failed to build TLS connector");
+
+    hyper_util::client::legacy::Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .http2_keep_alive_interval(KEEP_ALIVE_INTERVAL)
+        .timer(TokioTimer::new())
+        .build(HttpsConnector::from((http, tls.into())))
+}
+
+/// Queue whose tasks the server has none of
+fn no_work(queue: Queue) -> NoWorkAvailable {
+    match queue {
+        Queue::Jobs => NoWorkAvailable::JOBS,
+        Queue::Observations | Queue::HighPriority => NoWorkAvailable::TASKS,
+    }
+}
+
+impl GrpcServerClient {
+    /// Create a client of the gRPC endpoint of the server configuration,
+    /// sending other calls to its HTTP API
+    pub fn from_config(config: &ServerConfig, grpc: &GrpcConfig) -> Result<Self> {
+        let origin = grpc
+            .url
+            .parse()
+            .with_context(|| format!("Invalid gRPC url '{}'", grpc.url))?;
+        Ok(Self {
+            http: ServerClient::from_config(config),
+            grpc: Grpc::with_origin(build_channel(), origin),
+            extra_headers: config.extra_headers.clone().unwrap_or_default(),
+            push: grpc.push.then(Push::default),
+        })
+    }
+
+    /// Build a call carrying credentials, the protocol version, the trace
+    /// context of the current span and the extra headers, respecting the
+    /// rate limit
+    async fn request<T>(&self, message: T) -> Result<tonic::Request<T>> {
+        if let Some(rate_limiter) = &self.http.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+
+        let mut request = tonic::Request::new(message);
+        let metadata = request.metadata_mut();
+        metadata.insert(
+            "authorization",
+            MetadataValue::try_from(self.http.auth_header().await?)
+                .context("Invalid server credentials")?,
+        );
+        let headers = trace_headers()
+            .into_iter()
+            .chain(self.extra_headers.clone())
+            .chain([(
+                PROTOCOL_VERSION_HEADER.to_string(),
+                PROTOCOL_VERSION.to_string(),
+            )]);
+        for (name, value) in headers {
+            match (
+                MetadataKey::from_bytes(name.as_bytes()),
+                MetadataValue::try_from(value.as_str()),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    metadata.insert(name, value);
+                }
+                _ => log::warn!("Skipping invalid extra header: {}", name),
+            }
+        }
+        Ok(request)
+    }
+
+    /// Make a unary call of the agent service
+    async fn call<M1, M2>(&self, method: &str, message: M1) -> Result<Result<M2, Status>>
+    where
+        M1: prost::Message + Send + Sync + 'static,
+        M2: prost::Message + Default + Send + Sync + 'static,
+    {
+        let request = self.request(message).await?;
+        let path = PathAndQuery::try_from(format!("/{}/{}", proto::SERVICE, method))?;
+        let mut grpc = self.grpc.clone();
+        grpc.ready()
+            .await
+            .context("Failed to connect to the gRPC server")?;
+        let response = grpc.unary(request, path, ProstCodec::default()).await;
+        // Statuses of the server have no source, unlike transport failures
+        match &response {
+            Err(status) if std::error::Error::source(status).is_some() => conditions()
+                .record_server_unreachable(&self.http.base_url, status.message(), Utc::now()),
+            _ => conditions().record_server_reachable(&self.http.base_url),
+        }
+        Ok(response.map(tonic::Response::into_inner))
+    }
+
+    /// Make a unary call acknowledged with an empty message
+    async fn submit<M>(&self, method: &str, message: M, error_context: &str) -> Result<()>
+    where
+        M: prost::Message + Send + Sync + 'static,
+    {
+        match self.call::<M, proto::Ack>(method, message).await? {
+            Ok(_) => Ok(()),
+            Err(status) => Err(self.status_error(status, error_context).await),
+        }
+    }
+
+    /// Turn a failed call into the errors of the HTTP API: dropping cached
+    /// credentials if the server rejects them and asking for backoff when
+    /// it's out of capacity or unavailable
+    async fn status_error(&self, status: Status, error_context: &str) -> anyhow::Error {
+        let backoff_status = match status.code() {
+            Code::Unauthenticated => {
+                conditions().record_auth_failure(&self.http.base_url, Utc::now());
+                self.http.credentials.invalidate().await;
+                None
+            }
+            Code::ResourceExhausted => Some(StatusCode::TOO_MANY_REQUESTS),
+            Code::Unavailable => Some(StatusCode::SERVICE_UNAVAILABLE),
+            _ => None,
+        };
+        if let Some(backoff_status) = backoff_status {
+            let retry_after = status
+                .metadata()
+                .get("retry-after")
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after)
+                .unwrap_or(DEFAULT_RETRY_AFTER)
+                .min(MAX_RETRY_AFTER);
+            return BackoffRequested {
+                status: backoff_status,
+                retry_after,
+            }
+            .into();
+        }
+
+        anyhow!(CategorizedError::new(
+            ErrorCategory::Server,
+            format!(
+                "{}: {:?}: {}",
+                error_context,
+                status.code(),
+                status.message()
+            ),
+        ))
+    }
+
+    /// Acquire the next task of a queue, pushed over the task stream or
+    /// asked for with a call
+    async fn acquire(&self, queue: Queue) -> Result<AcquireResultBody> {
+        let task = match &self.push {
+            Some(push) => self.acquire_pushed(push, queue).await?,
+            None => {
+                let request = proto::AcquireRequest {
+                    queue: queue as i32,
+                };
+                let call = self.call::<_, proto::Task>("Acquire", request);
+                match tokio::time::timeout(ACQUIRE_TIMEOUT, call).await {
+                    Err(_) => return Err(anyhow!(no_work(queue))),
+                    Ok(result) => match result? {
+                        Ok(task) => task,
+                        Err(status) if status.code() == Code::NotFound => {
+                            return Err(anyhow!(no_work(queue)))
+                        }
+                        Err(status) => {
+                            return Err(self.status_error(status, "Failed to acquire task").await)
+                        }
+                    },
+                }
+            }
+        };
+        task.try_into()
+    }
+
+    async fn acquire_pushed(&self, push: &Push, queue: Queue) -> Result<proto::Task> {
+        let unclaimed = push
+            .unclaimed
+            .lock()
+            .unwrap()
+            .get_mut(&(queue as i32))
+            .and_then(VecDeque::pop_front);
+        if let Some(task) = unclaimed {
+            return Ok(task);
+        }
+
+        if let Some(rate_limiter) = &self.http.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+        let answer = self.session(push).await?.request(queue)?;
+        match tokio::time::timeout(ACQUIRE_TIMEOUT, answer).await {
+            Ok(Ok(Some(task))) => Ok(task),
+            Ok(Ok(None)) | Err(_) => Err(anyhow!(no_work(queue))),
+            Ok(Err(_)) => Err(anyhow!("Task stream of the server closed")),
+        }
+    }
+
+    /// Open task stream, opening it if it isn't
+    async fn session(&self, push: &Push) -> Result<Arc<Session>> {
+        let mut current = push.session.lock().await;
+        if let Some(session) = current.as_ref().filter(|session| session.is_open()) {
+            return Ok(session.clone());
+        }
+
+        let (ready, messages) = mpsc::unbounded_channel();
+        let request = self.request(UnboundedReceiverStream::new(messages)).await?;
+        let path = PathAndQuery::try_from(format!("/{}/Session", proto::SERVICE))?;
+        let mut grpc = self.grpc.clone();
+        grpc.ready()
+            .await
+            .context("Failed to connect to the gRPC server")?;
+        let stream = match grpc.streaming(request, path, ProstCodec::default()).await {
+            Ok(response) => response.into_inner(),
+            Err(status) => {
+                return Err(self
+                    .status_error(status, "Failed to open task stream")
+                    .await)
+            }
+        };
+        log::info!("Opened task stream of the gRPC server");
+
+        let session = Arc::new(Session {
+            ready,
+            waiting: Mutex::new(Some(HashMap::new())),
+            unclaimed: push.unclaimed.clone(),
+        });
+        tokio::spawn(session.clone().read(stream));
+        *current = Some(session.clone());
+        Ok(session)
+    }
+}
+
+/// Results of a job without result sets or chunks
+fn job_results(
+    job_id: &str,
+    records: Vec<JobType>,
+    columns: Vec<ResultColumn>,
+    filter_stats: FilterStats,
+    execution_stats: ExecutionStats,
+) -> Result<proto::JobResults> {
+    Ok(proto::JobResults {
+        job_id: job_id.to_string(),
+        manifest: Some(Manifest::of(&records)?.into()),
+        rows_json: proto::rows_json(&records)?,
+        columns: columns.into_iter().map(Into::into).collect(),
+        result_sets: Vec::new(),
+        chunks: 0,
+        sampled: filter_stats.sampled(),
+        filter_stats_json: serde_json::to_string(&filter_stats)?,
+        execution_stats_json: serde_json::to_string(&execution_stats)?,
+        export: None,
+    })
+}
+
+#[async_trait]
+impl ServerApi for GrpcServerClient {
+    async fn acquire_next_query(&self, is_high_priority_queue: bool) -> Result<AcquireResultBody> {
+        self.acquire(Queue::of_tasks(is_high_priority_queue)).await
+    }
+
+    async fn submit_results(
+        &self,
+        task_id: &str,
+        data: Vec<Record>,
+        null_stats: NullStats,
+        execution_stats: ExecutionStats,
+        is_high_priority_queue: bool,
+    ) -> Result<()> {
+        let results = proto::TaskResults {
+            task_id: task_id.to_string(),
+            queue: Queue::of_tasks(is_high_priority_queue) as i32,
+            manifest: Some(Manifest::of(&data)?.into()),
+            records: data.into_iter().map(Into::into).collect(),
+            result_sets: Vec::new(),
+            null_stats_json: serde_json::to_string(&null_stats)?,
+            execution_stats_json: serde_json::to_string(&execution_stats)?,
+        };
+        self.submit("SubmitTaskResults", results, "Failed to submit results")
+            .await
+    }
+
+    async fn submit_result_sets(
+        &self,
+        task_id: &str,
+        result_sets: Vec<TaskResultSet>,
+        execution_stats: ExecutionStats,
+        is_high_priority_queue: bool,
+    ) -> Result<()> {
+        let mut null_stats = NullStats::default();
+        for result_set in &result_sets {
+            null_stats.merge(&result_set.null_stats);
+        }
+        let result_sets = result_sets
+            .into_iter()
+            .map(|result_set| {
+                Ok(proto::TaskResultSet {
+                    name: result_set.name,
+                    manifest: Some(Manifest::of(&result_set.records)?.into()),
+                    records: result_set.records.into_iter().map(Into::into).collect(),
+                    null_stats_json: serde_json::to_string(&result_set.null_stats)?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let results = proto::TaskResults {
+            task_id: task_id.to_string(),
+            queue: Queue::of_tasks(is_high_priority_queue) as i32,
+            records: Vec::new(),
+            manifest: Some(Manifest::of::<Record>(&[])?.into()),
+            result_sets,
+            null_stats_json: serde_json::to_string(&null_stats)?,
+            execution_stats_json: serde_json::to_string(&execution_stats)?,
+        };
+        self.submit("SubmitTaskResults", results, "Failed to submit results")
+            .await
+    }
+
+    async fn submit_error(
+        &self,
+        task_id: &str,
+        error: &str,
+        is_high_priority_queue: bool,
+    ) -> Result<()> {
+        let error = proto::TaskError {
+            id: task_id.to_string(),
+            queue: Queue::of_tasks(is_high_priority_queue) as i32,
+            error: error.to_string(),
+        };
+        self.submit("SubmitError", error, "Failed to submit error")
+            .await
+    }
+
+    async fn acquire_next_job(&self) -> Result<AcquireResultBody> {
+        self.acquire(Queue::Jobs).await
+    }
+
+    async fn submit_job_results(
+        &self,
+        job_id: &str,
+        data: Vec<JobType>,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        let results = job_results(job_id, data, columns, filter_stats, execution_stats)?;
+        self.submit("SubmitJobResults", results, "Failed to submit job results")
+            .await
+    }
+
+    async fn submit_job_chunks(
+        &self,
+        job_id: &str,
+        data: JobRows,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        let mut chunks = 0;
+        for records in data.into_chunks()? {
+            let records = records?;
+            let chunk = proto::JobChunk {
+                job_id: job_id.to_string(),
+                index: chunks,
+                manifest: Some(Manifest::of(&records)?.into()),
+                rows_json: proto::rows_json(&records)?,
+            };
+            let error_context = format!("Failed to submit job chunk {}", chunks);
+            self.submit("SubmitJobChunk", chunk, &error_context).await?;
+            chunks += 1;
+        }
+        let results = proto::JobResults {
+            chunks,
+            ..job_results(job_id, Vec::new(), columns, filter_stats, execution_stats)?
+        };
+        self.submit("SubmitJobResults", results, "Failed to submit job results")
+            .await
+    }
+
+    async fn submit_job_export(
+        &self,
+        job_id: &str,
+        export: ExportManifest,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        let results = proto::JobResults {
+            export: Some(export.into()),
+            ..job_results(job_id, Vec::new(), columns, filter_stats, execution_stats)?
+        };
+        self.submit("SubmitJobResults", results, "Failed to submit job results")
+            .await
+    }
+
+    async fn submit_job_result_sets(
+        &self,
+        job_id: &str,
+        result_sets: Vec<JobResultSet>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        let result_sets = result_sets
+            .into_iter()
+            .map(|result_set| {
+                Ok(proto::JobResultSet {
+                    manifest: Some(Manifest::of(&result_set.records)?.into()),
+                    rows_json: proto::rows_json(&result_set.records)?,
+                    name: result_set.name,
+                    columns: result_set.columns.into_iter().map(Into::into).collect(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        let results = proto::JobResults {
+            result_sets,
+            ..job_results(
+                job_id,
+                Vec::new(),
+                Vec::new(),
+                filter_stats,
+                execution_stats,
+            )?
+        };
+        self.submit("SubmitJobResults", results, "Failed to submit job results")
+            .await
+    }
+
+    async fn submit_job_error(&self, job_id: &str, error: &str) -> Result<()> {
+        let error = proto::TaskError {
+            id: job_id.to_string(),
+            queue: Queue::Jobs as i32,
+            error: error.to_string(),
+        };
+        self.submit("SubmitError", error, "Failed to submit error")
+            .await
+    }
+
+    async fn submit_diagnostics(&self, job_id: &str, report: DiagnosticsReport) -> Result<()> {
+        self.http.submit_diagnostics(job_id, report).await
+    }
+
+    async fn submit_schemas(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        self.http
+            .submit_schemas(datasource_name, schemas, labels)
+            .await
+    }
+
+    async fn submit_cached_schemas(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        self.http
+            .submit_cached_schemas(datasource_name, schemas, labels)
+            .await
+    }
+
+    async fn submit_schema_diff(&self, datasource_name: &str, diff: SchemaDiff) -> Result<()> {
+        self.http.submit_schema_diff(datasource_name, diff).await
+    }
+
+    async fn submit_schema_batch(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        self.http
+            .submit_schema_batch(datasource_name, schemas, labels)
+            .await
+    }
+
+    async fn complete_discovery(
+        &self,
+        datasource_name: &str,
+        summary: DiscoverySummary,
+    ) -> Result<()> {
+        self.http.complete_discovery(datasource_name, summary).await
+    }
+
+    async fn add_datasource(
+        &self,
+        datasource_name: &str,
+        datasource_type: &str,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        self.http
+            .add_datasource(datasource_name, datasource_type, labels)
+            .await
+    }
+
+    async fn report_datasource_status(
+        &self,
+        datasource_name: &str,
+        status: &DatasourceStatus,
+    ) -> Result<()> {
+        let heartbeat = proto::DatasourceHeartbeat {
+            datasource_name: datasource_name.to_string(),
+            reachable: status.reachable,
+            error: status.error.clone(),
+            latency_ms: status.latency_ms,
+            checked_at_ms: status.checked_at.timestamp_millis(),
+            labels: status.labels.clone(),
+            kubernetes: pod_metadata().map(Into::into),
+        };
+        self.submit("Heartbeat", heartbeat, "Failed to report datasource status")
+            .await
+    }
+
+    async fn report_crash(&self, report: &CrashReport) -> Result<()> {
+        self.http.report_crash(report).await
+    }
+
+    async fn report_slow_query(&self, query: &SlowQuery) -> Result<()> {
+        self.http.report_slow_query(query).await
+    }
+
+    async fn report_errors(&self, summary: &ErrorSummary) -> Result<()> {
+        self.http.report_errors(summary).await
+    }
+
+    async fn acquire_lease(&self, request: &LeaseRequest) -> Result<LeaseGrant> {
+        self.http.acquire_lease(request).await
+    }
+
+    async fn release_lease(&self, holder: &str) -> Result<()> {
+        self.http.release_lease(holder).await
+    }
+
+    async fn check_reachable(&self) -> Result<()> {
+        self.http.check_reachable().await
+    }
+}
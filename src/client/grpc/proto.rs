@@ -0,0 +1,449 @@
+//! Messages of `proto/agent.proto`, written out by hand in place of
+//! generated code, and their conversions to and from the JSON API types.
+//! `tests/grpc_proto_test.rs` checks their fields against the proto file
+
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use std::collections::BTreeMap;
+
+use crate::client::manifest;
+use crate::client::types;
+use crate::executors::clickhouse_source::ResultColumn;
+use crate::kubernetes;
+use crate::models::{self, JobType};
+
+/// Package and service the RPC paths are made of
+pub const SERVICE: &str = "tsight.agent.v1.AgentService";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum Queue {
+    Observations = 0,
+    HighPriority = 1,
+    Jobs = 2,
+}
+
+impl Queue {
+    /// Queue of observation tasks, or of high priority ones
+    pub fn of_tasks(is_high_priority_queue: bool) -> Self {
+        if is_high_priority_queue {
+            Self::HighPriority
+        } else {
+            Self::Observations
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum JobKind {
+    Query = 0,
+    Diagnostics = 1,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AcquireRequest {
+    #[prost(enumeration = "Queue", tag = "1")]
+    pub queue: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Task {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "2")]
+    pub datasource_name: String,
+    #[prost(string, tag = "3")]
+    pub query: String,
+    #[prost(message, repeated, tag = "4")]
+    pub queries: Vec<TaskQuery>,
+    #[prost(string, tag = "5")]
+    pub ts_mapping_json: String,
+    #[prost(uint64, optional, tag = "6")]
+    pub timeout_secs: Option<u64>,
+    #[prost(int64, optional, tag = "7")]
+    pub enqueued_at_ms: Option<i64>,
+    #[prost(enumeration = "JobKind", tag = "8")]
+    pub kind: i32,
+    #[prost(string, optional, tag = "9")]
+    pub signature: Option<String>,
+    #[prost(message, optional, tag = "10")]
+    pub time_range: Option<TimeRange>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TaskQuery {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(string, tag = "2")]
+    pub query: String,
+    #[prost(string, optional, tag = "3")]
+    pub signature: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TimeRange {
+    #[prost(int64, tag = "1")]
+    pub from_ms: i64,
+    #[prost(int64, tag = "2")]
+    pub to_ms: i64,
+    #[prost(uint64, optional, tag = "3")]
+    pub interval_ms: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Record {
+    #[prost(int64, tag = "1")]
+    pub t: i64,
+    #[prost(btree_map = "string, double", tag = "2")]
+    pub values: BTreeMap<String, f64>,
+    #[prost(btree_map = "string, string", tag = "3")]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Manifest {
+    #[prost(uint64, tag = "1")]
+    pub row_count: u64,
+    #[prost(uint64, tag = "2")]
+    pub byte_size: u64,
+    #[prost(string, tag = "3")]
+    pub checksum: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Column {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub r#type: String,
+    #[prost(bool, tag = "3")]
+    pub nullable: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TaskResults {
+    #[prost(string, tag = "1")]
+    pub task_id: String,
+    #[prost(enumeration = "Queue", tag = "2")]
+    pub queue: i32,
+    #[prost(message, repeated, tag = "3")]
+    pub records: Vec<Record>,
+    #[prost(message, optional, tag = "4")]
+    pub manifest: Option<Manifest>,
+    #[prost(message, repeated, tag = "5")]
+    pub result_sets: Vec<TaskResultSet>,
+    #[prost(string, tag = "6")]
+    pub null_stats_json: String,
+    #[prost(string, tag = "7")]
+    pub execution_stats_json: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TaskResultSet {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub records: Vec<Record>,
+    #[prost(message, optional, tag = "3")]
+    pub manifest: Option<Manifest>,
+    #[prost(string, tag = "4")]
+    pub null_stats_json: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct JobResults {
+    #[prost(string, tag = "1")]
+    pub job_id: String,
+    #[prost(string, repeated, tag = "2")]
+    pub rows_json: Vec<String>,
+    #[prost(message, optional, tag = "3")]
+    pub manifest: Option<Manifest>,
+    #[prost(message, repeated, tag = "4")]
+    pub columns: Vec<Column>,
+    #[prost(message, repeated, tag = "5")]
+    pub result_sets: Vec<JobResultSet>,
+    #[prost(uint32, tag = "6")]
+    pub chunks: u32,
+    #[prost(bool, tag = "7")]
+    pub sampled: bool,
+    #[prost(string, tag = "8")]
+    pub filter_stats_json: String,
+    #[prost(string, tag = "9")]
+    pub execution_stats_json: String,
+    #[prost(message, optional, tag = "10")]
+    pub export: Option<ExportManifest>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportManifest {
+    #[prost(string, tag = "1")]
+    pub location: String,
+    #[prost(uint64, tag = "2")]
+    pub row_count: u64,
+    #[prost(uint64, tag = "3")]
+    pub byte_size: u64,
+    #[prost(string, tag = "4")]
+    pub sha256: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct JobResultSet {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub columns: Vec<Column>,
+    #[prost(string, repeated, tag = "3")]
+    pub rows_json: Vec<String>,
+    #[prost(message, optional, tag = "4")]
+    pub manifest: Option<Manifest>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct JobChunk {
+    #[prost(string, tag = "1")]
+    pub job_id: String,
+    #[prost(uint32, tag = "2")]
+    pub index: u32,
+    #[prost(string, repeated, tag = "3")]
+    pub rows_json: Vec<String>,
+    #[prost(message, optional, tag = "4")]
+    pub manifest: Option<Manifest>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TaskError {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(enumeration = "Queue", tag = "2")]
+    pub queue: i32,
+    #[prost(string, tag = "3")]
+    pub error: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DatasourceHeartbeat {
+    #[prost(string, tag = "1")]
+    pub datasource_name: String,
+    #[prost(bool, tag = "2")]
+    pub reachable: bool,
+    #[prost(string, optional, tag = "3")]
+    pub error: Option<String>,
+    #[prost(uint64, tag = "4")]
+    pub latency_ms: u64,
+    #[prost(int64, tag = "5")]
+    pub checked_at_ms: i64,
+    #[prost(btree_map = "string, string", tag = "6")]
+    pub labels: BTreeMap<String, String>,
+    #[prost(message, optional, tag = "7")]
+    pub kubernetes: Option<PodMetadata>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PodMetadata {
+    #[prost(string, tag = "1")]
+    pub pod_name: String,
+    #[prost(string, optional, tag = "2")]
+    pub namespace: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub node_name: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Ack {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AgentMessage {
+    #[prost(oneof = "agent_message::Message", tags = "1")]
+    pub message: Option<agent_message::Message>,
+}
+
+pub mod agent_message {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Message {
+        #[prost(message, tag = "1")]
+        Ready(super::Ready),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Ready {
+    #[prost(enumeration = "Queue", tag = "1")]
+    pub queue: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ServerMessage {
+    #[prost(enumeration = "Queue", tag = "1")]
+    pub queue: i32,
+    #[prost(oneof = "server_message::Message", tags = "2, 3")]
+    pub message: Option<server_message::Message>,
+}
+
+pub mod server_message {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Message {
+        #[prost(message, boxed, tag = "2")]
+        Task(Box<super::Task>),
+        #[prost(message, tag = "3")]
+        NoWork(super::NoWork),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct NoWork {}
+
+impl TryFrom<Task> for types::AcquireResultBody {
+    type Error = anyhow::Error;
+
+    fn try_from(task: Task) -> Result<Self> {
+        let kind = match task.kind() {
+            JobKind::Query => types::JobKind::Query,
+            JobKind::Diagnostics => types::JobKind::Diagnostics,
+        };
+        let ts_mapping = match task.ts_mapping_json.as_str() {
+            "" => None,
+            json => Some(serde_json::from_str(json).context("Invalid ts_mapping of task")?),
+        };
+        let time_range = match task.time_range {
+            Some(range) => Some(types::TimeRange {
+                from: DateTime::from_timestamp_millis(range.from_ms)
+                    .context("Invalid start of task time range")?,
+                to: DateTime::from_timestamp_millis(range.to_ms)
+                    .context("Invalid end of task time range")?,
+                interval_ms: range.interval_ms,
+            }),
+            None => None,
+        };
+        Ok(Self {
+            id: task.id,
+            datasource_name: task.datasource_name,
+            query: task.query,
+            queries: (!task.queries.is_empty()).then(|| {
+                task.queries
+                    .into_iter()
+                    .map(|query| types::TaskQuery {
+                        name: query.name,
+                        query: query.query,
+                        signature: query.signature,
+                    })
+                    .collect()
+            }),
+            ts_mapping,
+            timeout: task.timeout_secs,
+            enqueued_at: task
+                .enqueued_at_ms
+                .and_then(DateTime::from_timestamp_millis),
+            kind,
+            signature: task.signature,
+            time_range,
+        })
+    }
+}
+
+impl TryFrom<types::AcquireResultBody> for Task {
+    type Error = anyhow::Error;
+
+    fn try_from(body: types::AcquireResultBody) -> Result<Self> {
+        let kind = match body.kind {
+            types::JobKind::Query => JobKind::Query,
+            types::JobKind::Diagnostics => JobKind::Diagnostics,
+        };
+        Ok(Self {
+            id: body.id,
+            datasource_name: body.datasource_name,
+            query: body.query,
+            queries: body
+                .queries
+                .into_iter()
+                .flatten()
+                .map(|query| TaskQuery {
+                    name: query.name,
+                    query: query.query,
+                    signature: query.signature,
+                })
+                .collect(),
+            ts_mapping_json: match &body.ts_mapping {
+                Some(mapping) => serde_json::to_string(mapping)?,
+                None => String::new(),
+            },
+            timeout_secs: body.timeout,
+            enqueued_at_ms: body.enqueued_at.map(|at| at.timestamp_millis()),
+            kind: kind as i32,
+            signature: body.signature,
+            time_range: body.time_range.map(|range| TimeRange {
+                from_ms: range.from.timestamp_millis(),
+                to_ms: range.to.timestamp_millis(),
+                interval_ms: range.interval_ms,
+            }),
+        })
+    }
+}
+
+impl From<models::Record> for Record {
+    fn from(record: models::Record) -> Self {
+        Self {
+            t: record.t,
+            values: record.values,
+            labels: record.labels,
+        }
+    }
+}
+
+impl From<Record> for models::Record {
+    fn from(record: Record) -> Self {
+        Self {
+            t: record.t,
+            values: record.values,
+            labels: record.labels,
+        }
+    }
+}
+
+impl From<manifest::Manifest> for Manifest {
+    fn from(manifest: manifest::Manifest) -> Self {
+        Self {
+            row_count: manifest.row_count,
+            byte_size: manifest.byte_size,
+            checksum: manifest.checksum,
+        }
+    }
+}
+
+impl From<manifest::ExportManifest> for ExportManifest {
+    fn from(export: manifest::ExportManifest) -> Self {
+        Self {
+            location: export.location,
+            row_count: export.row_count,
+            byte_size: export.byte_size,
+            sha256: export.sha256,
+        }
+    }
+}
+
+impl From<&kubernetes::PodMetadata> for PodMetadata {
+    fn from(metadata: &kubernetes::PodMetadata) -> Self {
+        Self {
+            pod_name: metadata.pod_name.clone(),
+            namespace: metadata.namespace.clone(),
+            node_name: metadata.node_name.clone(),
+        }
+    }
+}
+
+impl From<ResultColumn> for Column {
+    fn from(column: ResultColumn) -> Self {
+        Self {
+            name: column.name,
+            r#type: column.type_name,
+            nullable: column.nullable,
+        }
+    }
+}
+
+/// Rows of a job as one JSON object each
+pub fn rows_json(rows: &[JobType]) -> Result<Vec<String>> {
+    rows.iter()
+        .map(|row| serde_json::to_string(row).map_err(Into::into))
+        .collect()
+}
@@ -0,0 +1,65 @@
+//! Manifests of submitted results
+//!
+//! Each submission carries the row count, byte size and checksum of its
+//! records, so the server can detect truncated or corrupted uploads. Both are
+//! computed over the canonical form of the records: compact JSON with object
+//! keys sorted, as the order of a row's keys isn't stable.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use twox_hash::XxHash3_64;
+
+/// Row count, size and checksum of submitted records
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub row_count: u64,
+    /// Size in bytes of the canonical JSON of the records
+    pub byte_size: u64,
+    /// XXH3-64 of the canonical JSON of the records, as 16 hex digits
+    pub checksum: String,
+}
+
+impl Manifest {
+    /// Compute the manifest of records
+    pub fn of<T: Serialize>(records: &[T]) -> Result<Self> {
+        let payload = serde_json::to_vec(&canonicalize(serde_json::to_value(records)?))?;
+        Ok(Self {
+            row_count: records.len() as u64,
+            byte_size: payload.len() as u64,
+            checksum: format!("{:016x}", XxHash3_64::oneshot(&payload)),
+        })
+    }
+}
+
+/// Location and manifest of job results uploaded to a bucket as gzip
+/// compressed JSON lines
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExportManifest {
+    /// URL of the object, e.g.
+    /// `https://s3.eu-west-1.amazonaws.com/exports/tsight/<job id>.jsonl.gz`
+    pub location: String,
+    pub row_count: u64,
+    /// Size in bytes of the object
+    pub byte_size: u64,
+    /// SHA-256 of the object, as 64 hex digits
+    pub sha256: String,
+}
+
+/// Rebuild objects with their keys sorted, at any depth
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<_> = object.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonicalize(value)))
+                    .collect::<Map<_, _>>(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(canonicalize).collect()),
+        value => value,
+    }
+}
@@ -0,0 +1,1410 @@
+//! Client module for interacting with the server API
+//!
+//! This module provides a client for communicating with the server API,
+//! handling tasks, jobs, schema discovery, and datasource management.
+
+pub mod auth;
+pub mod fake;
+pub mod grpc;
+pub mod manifest;
+pub mod rate_limit;
+
+use crate::build_info::build_info;
+use crate::config::ServerConfig;
+use crate::crash::CrashReport;
+use crate::diagnostics::DiagnosticsReport;
+use crate::errors::{CategorizedError, ErrorCategory, ErrorSummary, NoWorkAvailable};
+use crate::executors::base::ExecutionStats;
+use crate::executors::clickhouse_source::ResultColumn;
+use crate::filters::FilterStats;
+use crate::kubernetes::pod_metadata;
+use crate::models::JobType;
+use crate::notify::conditions;
+use crate::schema_diff::SchemaDiff;
+use crate::slow_query::SlowQuery;
+use crate::spill::JobRows;
+use crate::telemetry::trace_headers;
+use crate::timeseries::NullStats;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use auth::{Credentials, OAuth2TokenProvider};
+use chrono::{DateTime, Utc};
+use manifest::{ExportManifest, Manifest};
+use rate_limit::RateLimiter;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Version of the agent/server protocol implemented by this client
+pub const PROTOCOL_VERSION: &str = "2";
+
+/// Header carrying `PROTOCOL_VERSION` on every request
+pub const PROTOCOL_VERSION_HEADER: &str = "X-TSight-Protocol-Version";
+
+/// User-Agent sent with every request, e.g. `tsight-agent/0.1.0 (linux/x86_64)`
+pub fn user_agent() -> String {
+    format!(
+        "tsight-agent/{} ({}/{})",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+/// Join the server URL and an optional path prefix into the base URL for API routes
+fn base_url(server_url: &str, path_prefix: Option<&str>) -> String {
+    let mut url = server_url.trim_end_matches('/').to_string();
+    if let Some(prefix) = path_prefix.map(|p| p.trim_matches('/')) {
+        if !prefix.is_empty() {
+            url.push('/');
+            url.push_str(prefix);
+        }
+    }
+    url
+}
+
+/// Client of the server configuration, talking gRPC for the task protocol
+/// when `grpc` is set and HTTP otherwise
+pub fn from_config(config: &ServerConfig) -> Result<Arc<dyn ServerApi>> {
+    Ok(match &config.grpc {
+        Some(grpc) => Arc::new(grpc::GrpcServerClient::from_config(config, grpc)?),
+        None => Arc::new(ServerClient::from_config(config)),
+    })
+}
+
+/// Build the HTTP client used for server requests, shared by all agents
+fn build_http_client(extra_headers: Option<&HashMap<String, String>>) -> Client {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        PROTOCOL_VERSION_HEADER,
+        HeaderValue::from_static(PROTOCOL_VERSION),
+    );
+
+    for (name, value) in extra_headers.into_iter().flatten() {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => log::warn!("Skipping invalid extra header: {}", name),
+        }
+    }
+
+    crate::http::client_builder()
+        .user_agent(user_agent())
+        .default_headers(headers)
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+/// Delay used when the server asks for backoff without a `Retry-After` header
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Upper bound for server-requested backoff
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+
+/// Time the server gets to answer a reachability check
+const REACHABILITY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The server is rate limiting the agent or is under maintenance (429/503)
+#[derive(Debug, thiserror::Error)]
+#[error("Server requested backoff for {}s: {status}", retry_after.as_secs())]
+pub struct BackoffRequested {
+    pub status: StatusCode,
+    pub retry_after: Duration,
+}
+
+/// The server can't apply a schema diff and needs the full schemas
+#[derive(Debug, thiserror::Error)]
+#[error("Server requested a full schema sync")]
+pub struct FullSyncRequested;
+
+/// Counts sent with the marker completing a batched schema submission
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiscoverySummary {
+    /// Tables submitted across all batches
+    pub tables: usize,
+    /// Tables submitted with partial information
+    pub partial_tables: usize,
+    /// Batches submitted
+    pub batches: usize,
+}
+
+/// Connectivity of a datasource reported when it changes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatasourceStatus {
+    pub reachable: bool,
+    /// Why the probe failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time the probe took
+    pub latency_ms: u64,
+    pub checked_at: DateTime<Utc>,
+    /// Labels of the datasource
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Request to acquire or renew the lease of an active/standby pair of agents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeaseRequest {
+    pub holder: String,
+    /// Time the lease lasts unless it's renewed
+    pub ttl_ms: u64,
+}
+
+/// Answer to a lease request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeaseGrant {
+    /// Whether the requesting agent holds the lease
+    pub granted: bool,
+    /// Agent holding the lease, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holder: Option<String>,
+}
+
+/// Parse a `Retry-After` header given either as seconds or as an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.signed_duration_since(chrono::Utc::now());
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+// Request/Response types
+mod types {
+    use super::manifest::{ExportManifest, Manifest};
+    use super::*;
+    use crate::build_info::BuildInfo;
+    use crate::diagnostics::DiagnosticsReport;
+    use crate::executors::base::ExecutionStats;
+    use crate::executors::clickhouse_source::{ResultColumn, TableSchema};
+    use crate::filters::FilterStats;
+    use crate::kubernetes::PodMetadata;
+    use crate::models::{JobType, Record};
+    use crate::timeseries::{NullStats, TsMapping};
+    use chrono::{DateTime, Utc};
+
+    /// Request to acquire a task from the queue
+    #[derive(Debug, Serialize, Deserialize, Clone)]
+    pub struct AcquireRequest {
+        pub is_high_priority_queue: bool,
+    }
+
+    /// Response when acquiring a task or job
+    #[derive(Debug, Serialize, Deserialize, Clone)]
+    pub struct AcquireResultBody {
+        pub id: String,
+        pub datasource_name: String,
+        /// Query of a single-query task, ignored when `queries` is set
+        #[serde(default)]
+        pub query: String,
+        /// Queries run in order on one session, returning a result set each
+        /// when named
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub queries: Option<Vec<TaskQuery>>,
+        /// Columns of a task query's rows making up records, `t` in seconds
+        /// and all other numeric columns when unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ts_mapping: Option<TsMapping>,
+        /// Seconds the query may run, the datasource's `timeout` when unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timeout: Option<u64>,
+        /// When the server queued the task, used to report its queue wait
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub enqueued_at: Option<DateTime<Utc>>,
+        /// What a job asks the agent to do, running its queries by default
+        #[serde(default)]
+        pub kind: JobKind,
+        /// Base64 Ed25519 signature of `query`, checked when query signing
+        /// is configured
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub signature: Option<String>,
+        /// Time range of the dashboard or alert the task is run for,
+        /// expanding the time macros of its queries
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub time_range: Option<TimeRange>,
+    }
+
+    /// Time range a task's queries are run for
+    #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+    pub struct TimeRange {
+        pub from: DateTime<Utc>,
+        pub to: DateTime<Utc>,
+        /// Milliseconds between points, a thousandth of the range when unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub interval_ms: Option<u64>,
+    }
+
+    /// Kind of job
+    #[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum JobKind {
+        /// Run the job's queries and submit their results
+        #[default]
+        Query,
+        /// Check the agent itself and submit a diagnostics report
+        Diagnostics,
+    }
+
+    impl AcquireResultBody {
+        /// Queries of the task in order, `query` unless `queries` is set
+        pub fn query_list(&self) -> Vec<TaskQuery> {
+            match &self.queries {
+                Some(queries) => queries.clone(),
+                None => vec![TaskQuery {
+                    name: None,
+                    query: self.query.clone(),
+                    signature: self.signature.clone(),
+                }],
+            }
+        }
+    }
+
+    /// Query of a multi-query task
+    #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+    pub struct TaskQuery {
+        /// Name of the query's result set; statements without one, such as
+        /// `SET`, are run for their effect on the session and return no rows
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub name: Option<String>,
+        pub query: String,
+        /// Base64 Ed25519 signature of `query`, checked when query signing
+        /// is configured
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub signature: Option<String>,
+    }
+
+    /// Records of a named query of a multi-query task
+    #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+    pub struct TaskResultSet {
+        pub name: String,
+        pub records: Vec<Record>,
+        pub null_stats: NullStats,
+        /// Set by the client when the result set is submitted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub manifest: Option<Manifest>,
+    }
+
+    /// Rows of a named query of a multi-query job
+    #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+    pub struct JobResultSet {
+        pub name: String,
+        /// Names, simplified types and nullability of the columns of `records`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub columns: Vec<ResultColumn>,
+        pub records: Vec<JobType>,
+        /// Set by the client when the result set is submitted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub manifest: Option<Manifest>,
+    }
+
+    /// Request to submit task results
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct SubmitTaskRequest {
+        /// Records with millisecond timestamps and values by column name
+        pub records: Vec<Record>,
+        /// Row count, size and checksum of `records`
+        pub manifest: Manifest,
+        /// Rows skipped or zero-filled for `NULL`s while mapping the results
+        #[serde(default)]
+        pub null_stats: NullStats,
+        /// Result sets of a multi-query task, whose `records` are then empty
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub result_sets: Vec<TaskResultSet>,
+        /// Timing and resource usage of the task's queries
+        #[serde(default)]
+        pub execution_stats: ExecutionStats,
+        pub is_high_priority_queue: bool,
+    }
+
+    /// Request to submit job results
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct SubmitJobRequest {
+        pub records: Vec<JobType>,
+        /// Row count, size and checksum of `records`
+        pub manifest: Manifest,
+        /// Names, simplified types and nullability of the columns of `records`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub columns: Vec<ResultColumn>,
+        /// Result sets of a multi-query job, whose `records` are then empty
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub result_sets: Vec<JobResultSet>,
+        /// Chunks the records were submitted in beforehand, whose `records`
+        /// are then empty
+        #[serde(default, skip_serializing_if = "is_zero")]
+        pub chunks: usize,
+        /// Rows dropped and values rewritten by filters while running the job
+        pub filter_stats: FilterStats,
+        /// Whether records are a subset left by `sample_rate` or `max_rows`
+        pub sampled: bool,
+        /// Timing and resource usage of the job's queries
+        #[serde(default)]
+        pub execution_stats: ExecutionStats,
+        /// Location and manifest of the records when they were uploaded to
+        /// a bucket instead, `records` then being empty
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub export: Option<ExportManifest>,
+    }
+
+    /// Request to submit a chunk of the records of a job
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct SubmitJobChunkRequest {
+        /// Position of the chunk among those of the job, from 0
+        pub index: usize,
+        pub records: Vec<JobType>,
+        /// Row count, size and checksum of `records`
+        pub manifest: Manifest,
+    }
+
+    fn is_zero(value: &usize) -> bool {
+        *value == 0
+    }
+
+    /// Request to submit the report of a diagnostics job
+    #[derive(Debug, Serialize)]
+    pub struct DiagnosticsSubmissionRequest {
+        pub diagnostics: DiagnosticsReport,
+    }
+
+    /// Request to submit an error
+    #[derive(Debug, Serialize)]
+    pub struct ErrorSubmissionRequest {
+        pub error: String,
+        pub is_high_priority_queue: bool,
+    }
+
+    /// Request to submit schema information
+    #[derive(Debug, Serialize)]
+    pub struct SchemaSubmissionRequest {
+        pub schemas: Vec<TableSchema>,
+        /// Set when the schemas come from the agent's cache of the last
+        /// discovery rather than a fresh one
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        pub from_cache: bool,
+        /// Labels of the datasource
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        pub labels: BTreeMap<String, String>,
+    }
+
+    /// Response to a schema diff submission
+    #[derive(Debug, Default, Deserialize)]
+    pub struct SchemaDiffResponse {
+        /// Set when the server lost track of the schemas the diff is based on
+        #[serde(default)]
+        pub full_sync: bool,
+    }
+
+    /// Request to create or update a datasource
+    #[derive(Debug, Serialize)]
+    pub struct DatasourceUpsertRequest {
+        pub datasource_type: String,
+        /// Build of the agent registering the datasource
+        pub agent: &'static BuildInfo,
+        /// Labels of the datasource
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        pub labels: BTreeMap<String, String>,
+        /// Pod of the agent, when running in Kubernetes
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub kubernetes: Option<&'static PodMetadata>,
+    }
+
+    /// Status of a datasource as reported, with the pod of the agent
+    #[derive(Debug, Serialize)]
+    pub struct DatasourceStatusReport<'a> {
+        #[serde(flatten)]
+        pub status: &'a DatasourceStatus,
+        /// Pod of the agent, when running in Kubernetes
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub kubernetes: Option<&'static PodMetadata>,
+    }
+}
+
+use types::*;
+
+/// Operations the agent performs against the server API
+///
+/// Implemented by `ServerClient` for the HTTP API, by
+/// `grpc::GrpcServerClient` for the gRPC service and by `fake::FakeServer`
+/// for tests and local development.
+#[async_trait]
+pub trait ServerApi: Send + Sync {
+    /// Acquire the next task from the queue
+    async fn acquire_next_query(&self, is_high_priority_queue: bool) -> Result<AcquireResultBody>;
+
+    /// Submit task results to the server along with counts of rows with
+    /// `NULL`s and execution statistics
+    async fn submit_results(
+        &self,
+        task_id: &str,
+        data: Vec<crate::models::Record>,
+        null_stats: NullStats,
+        execution_stats: ExecutionStats,
+        is_high_priority_queue: bool,
+    ) -> Result<()>;
+
+    /// Submit the named result sets of a multi-query task along with
+    /// execution statistics
+    async fn submit_result_sets(
+        &self,
+        task_id: &str,
+        result_sets: Vec<TaskResultSet>,
+        execution_stats: ExecutionStats,
+        is_high_priority_queue: bool,
+    ) -> Result<()>;
+
+    /// Submit an error for a task
+    async fn submit_error(
+        &self,
+        task_id: &str,
+        error: &str,
+        is_high_priority_queue: bool,
+    ) -> Result<()>;
+
+    /// Acquire the next job from the queue
+    async fn acquire_next_job(&self) -> Result<AcquireResultBody>;
+
+    /// Submit job results to the server along with their columns, filter
+    /// and execution statistics
+    async fn submit_job_results(
+        &self,
+        job_id: &str,
+        data: Vec<JobType>,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()>;
+
+    /// Submit job results spilled to disk, posting their rows in chunks
+    /// before their columns, filter and execution statistics
+    async fn submit_job_chunks(
+        &self,
+        job_id: &str,
+        data: JobRows,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()>;
+
+    /// Submit the location and manifest of job results uploaded to a bucket
+    /// along with their columns, filter and execution statistics
+    async fn submit_job_export(
+        &self,
+        job_id: &str,
+        export: ExportManifest,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()>;
+
+    /// Submit the named result sets of a multi-query job along with filter
+    /// and execution statistics
+    async fn submit_job_result_sets(
+        &self,
+        job_id: &str,
+        result_sets: Vec<JobResultSet>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()>;
+
+    /// Submit an error for a job
+    async fn submit_job_error(&self, job_id: &str, error: &str) -> Result<()>;
+
+    /// Submit the report of a diagnostics job
+    async fn submit_diagnostics(&self, job_id: &str, report: DiagnosticsReport) -> Result<()>;
+
+    /// Submit schema information for a datasource along with its labels
+    async fn submit_schemas(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<crate::executors::clickhouse_source::TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()>;
+
+    /// Submit schemas cached by the last discovery of a datasource, marked
+    /// `from_cache` until a fresh discovery replaces them
+    async fn submit_cached_schemas(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<crate::executors::clickhouse_source::TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()>;
+
+    /// Submit changes of a datasource's schemas since the last submission,
+    /// failing with `FullSyncRequested` when the server needs full schemas
+    async fn submit_schema_diff(&self, datasource_name: &str, diff: SchemaDiff) -> Result<()>;
+
+    /// Submit schemas of some of a datasource's tables while discovery is
+    /// still running
+    async fn submit_schema_batch(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<crate::executors::clickhouse_source::TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()>;
+
+    /// Mark a batched schema submission as complete, replacing the schemas
+    /// submitted before it
+    async fn complete_discovery(
+        &self,
+        datasource_name: &str,
+        summary: DiscoverySummary,
+    ) -> Result<()>;
+
+    /// Add or update a datasource and its labels
+    async fn add_datasource(
+        &self,
+        datasource_name: &str,
+        datasource_type: &str,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()>;
+
+    /// Report a change of a datasource's connectivity
+    async fn report_datasource_status(
+        &self,
+        datasource_name: &str,
+        status: &DatasourceStatus,
+    ) -> Result<()>;
+
+    /// Report a crash of one of the agent's loops
+    async fn report_crash(&self, report: &CrashReport) -> Result<()>;
+
+    /// Report a query that ran longer than the slow query threshold
+    async fn report_slow_query(&self, query: &SlowQuery) -> Result<()>;
+
+    /// Report the errors of all agents by category since the previous report
+    async fn report_errors(&self, summary: &ErrorSummary) -> Result<()>;
+
+    /// Acquire the lease of an active/standby pair, or renew it when the
+    /// agent already holds it
+    async fn acquire_lease(&self, request: &LeaseRequest) -> Result<LeaseGrant>;
+
+    /// Give up the lease so the standby agent can take over right away
+    async fn release_lease(&self, holder: &str) -> Result<()>;
+
+    /// Check that the server can be reached
+    async fn check_reachable(&self) -> Result<()>;
+}
+
+/// Client for interacting with the server API
+#[derive(Clone)]
+pub struct ServerClient {
+    credentials: Credentials,
+    /// Server URL including the optional path prefix
+    base_url: String,
+    client: Client,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+// Re-export types that are used by other modules
+pub use types::{AcquireResultBody, JobKind, JobResultSet, TaskQuery, TaskResultSet, TimeRange};
+
+impl ServerClient {
+    /// Create a new server client
+    pub fn new(api_key: String, server_url: String) -> Self {
+        Self {
+            credentials: Credentials::ApiKey(api_key),
+            base_url: base_url(&server_url, None),
+            client: build_http_client(None),
+            rate_limiter: None,
+        }
+    }
+
+    /// Create a new server client from the server configuration
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let client = build_http_client(config.extra_headers.as_ref());
+        let credentials = match &config.auth {
+            Some(auth) => Credentials::OAuth2(Arc::new(OAuth2TokenProvider::new(
+                auth.clone(),
+                client.clone(),
+            ))),
+            None => Credentials::ApiKey(config.api_key.clone()),
+        };
+
+        let rate_limiter = config
+            .rate_limit
+            .as_ref()
+            .filter(|rate_limit| rate_limit.requests_per_second > 0.0)
+            .map(|rate_limit| Arc::new(RateLimiter::from_config(rate_limit)));
+
+        Self {
+            credentials,
+            base_url: base_url(&config.server_url, config.path_prefix.as_deref()),
+            client,
+            rate_limiter,
+        }
+    }
+
+    /// Get authorization header for API requests
+    async fn auth_header(&self) -> Result<String> {
+        let token = self
+            .credentials
+            .bearer_token()
+            .await
+            .context("Failed to obtain server credentials")?;
+        Ok(format!("Bearer {}", token))
+    }
+
+    /// Build an authorized POST request to the given API path, carrying
+    /// the trace context of the current span
+    async fn post(&self, path: &str) -> Result<RequestBuilder> {
+        let mut request = self
+            .client
+            .post(format!("{}{}", self.base_url, path))
+            .header("Authorization", self.auth_header().await?);
+        for (name, value) in trace_headers() {
+            request = request.header(name, value);
+        }
+        Ok(request)
+    }
+
+    /// Send a request respecting the rate limit, dropping cached credentials
+    /// if the server rejects them, reporting protocol version mismatches and
+    /// turning 429/503 responses into `BackoffRequested`
+    async fn send(
+        &self,
+        request: RequestBuilder,
+        error_context: &str,
+    ) -> Result<reqwest::Response> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+
+        let response = request
+            .send()
+            .await
+            .inspect_err(|e| {
+                conditions().record_server_unreachable(&self.base_url, &e.to_string(), Utc::now())
+            })
+            .with_context(|| error_context.to_string())?;
+        conditions().record_server_reachable(&self.base_url);
+
+        match response.status() {
+            StatusCode::UNAUTHORIZED => {
+                conditions().record_auth_failure(&self.base_url, Utc::now());
+                self.credentials.invalidate().await
+            }
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                let retry_after = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_retry_after)
+                    .unwrap_or(DEFAULT_RETRY_AFTER)
+                    .min(MAX_RETRY_AFTER);
+
+                return Err(BackoffRequested {
+                    status: response.status(),
+                    retry_after,
+                }
+                .into());
+            }
+            StatusCode::UPGRADE_REQUIRED | StatusCode::CONFLICT => {
+                let message = format!(
+                    "Server rejected protocol version {} used by {}: {}. \
+                     Please upgrade the agent to the latest release",
+                    PROTOCOL_VERSION,
+                    user_agent(),
+                    response.status()
+                );
+                log::error!("{}", message);
+                return Err(anyhow!(message));
+            }
+            _ => (),
+        }
+
+        Ok(response)
+    }
+
+    /// Post the results of a task with the manifests of its records
+    async fn post_task_results(
+        &self,
+        task_id: &str,
+        mut submission: SubmitTaskRequest,
+    ) -> Result<()> {
+        submission.manifest = Manifest::of(&submission.records)?;
+        for result_set in &mut submission.result_sets {
+            result_set.manifest = Some(Manifest::of(&result_set.records)?);
+        }
+        let request = self
+            .post(&format!("/tasks/{}/submit", task_id))
+            .await?
+            .json(&submission);
+        let response = self
+            .send(request, "Failed to send submit results request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to submit results: {}", response.status()));
+        }
+
+        Ok(())
+    }
+
+    /// Post the results of a job with the manifests of its records
+    async fn post_job_results(&self, job_id: &str, mut submission: SubmitJobRequest) -> Result<()> {
+        submission.manifest = Manifest::of(&submission.records)?;
+        for result_set in &mut submission.result_sets {
+            result_set.manifest = Some(Manifest::of(&result_set.records)?);
+        }
+        let request = self
+            .post(&format!("/jobs/{}/submit", job_id))
+            .await?
+            .json(&submission);
+        let response = self
+            .send(request, "Failed to send submit job results request")
+            .await?;
+
+        log::debug!("submit_job_results, response: {:?}", &response);
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to submit job results: {}",
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Post a chunk of the records of a job with its manifest
+    async fn post_job_chunk(
+        &self,
+        job_id: &str,
+        index: usize,
+        records: Vec<JobType>,
+    ) -> Result<()> {
+        let submission = SubmitJobChunkRequest {
+            index,
+            manifest: Manifest::of(&records)?,
+            records,
+        };
+        let request = self
+            .post(&format!("/jobs/{}/submit/chunk", job_id))
+            .await?
+            .json(&submission);
+        let response = self
+            .send(request, "Failed to send submit job chunk request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to submit job chunk {}: {}",
+                index,
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Post full schemas of a datasource
+    async fn post_schemas(
+        &self,
+        datasource_name: &str,
+        submission: SchemaSubmissionRequest,
+    ) -> Result<()> {
+        log::debug!("Submitting schemas: {:?}", &submission);
+        let request = self
+            .post(&format!("/datasource/{}/discovery", datasource_name))
+            .await?
+            .json(&submission);
+        let response = self
+            .send(request, "Failed to send submit schemas request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to submit schemas: {}", response.status()));
+        }
+
+        Ok(())
+    }
+
+    /// Handle common response error cases
+    async fn handle_response_errors<T>(
+        &self,
+        response: reqwest::Response,
+        not_found: NoWorkAvailable,
+        error_context: String,
+    ) -> Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(anyhow!(not_found));
+        } else if !response.status().is_success() {
+            return Err(anyhow!(CategorizedError::new(
+                ErrorCategory::Server,
+                format!("{}: {}", error_context, response.status()),
+            )));
+        }
+
+        response.json::<T>().await.context(error_context)
+    }
+}
+
+#[async_trait]
+impl ServerApi for ServerClient {
+    // Task-related methods
+
+    /// Acquire the next task from the queue
+    async fn acquire_next_query(&self, is_high_priority_queue: bool) -> Result<AcquireResultBody> {
+        let request = self
+            .post("/tasks/acquire")
+            .await?
+            .json(&AcquireRequest {
+                is_high_priority_queue,
+            })
+            .timeout(Duration::from_secs(60));
+        let response = self
+            .send(request, "Failed to send acquire task request")
+            .await?;
+
+        self.handle_response_errors(
+            response,
+            NoWorkAvailable::TASKS,
+            "Failed to acquire task".to_string(),
+        )
+        .await
+    }
+
+    /// Submit task results to the server along with counts of rows with
+    /// `NULL`s and execution statistics
+    async fn submit_results(
+        &self,
+        task_id: &str,
+        data: Vec<crate::models::Record>,
+        null_stats: NullStats,
+        execution_stats: ExecutionStats,
+        is_high_priority_queue: bool,
+    ) -> Result<()> {
+        self.post_task_results(
+            task_id,
+            SubmitTaskRequest {
+                records: data,
+                manifest: Manifest::default(),
+                null_stats,
+                result_sets: Vec::new(),
+                execution_stats,
+                is_high_priority_queue,
+            },
+        )
+        .await
+    }
+
+    /// Submit the named result sets of a multi-query task along with
+    /// execution statistics
+    async fn submit_result_sets(
+        &self,
+        task_id: &str,
+        result_sets: Vec<TaskResultSet>,
+        execution_stats: ExecutionStats,
+        is_high_priority_queue: bool,
+    ) -> Result<()> {
+        let mut null_stats = NullStats::default();
+        for result_set in &result_sets {
+            null_stats.merge(&result_set.null_stats);
+        }
+        self.post_task_results(
+            task_id,
+            SubmitTaskRequest {
+                records: Vec::new(),
+                manifest: Manifest::default(),
+                null_stats,
+                result_sets,
+                execution_stats,
+                is_high_priority_queue,
+            },
+        )
+        .await
+    }
+
+    /// Submit an error for a task
+    async fn submit_error(
+        &self,
+        task_id: &str,
+        error: &str,
+        is_high_priority_queue: bool,
+    ) -> Result<()> {
+        let request = self
+            .post(&format!("/tasks/{}/submit", task_id))
+            .await?
+            .json(&ErrorSubmissionRequest {
+                error: error.to_string(),
+                is_high_priority_queue,
+            });
+        let response = self
+            .send(request, "Failed to send submit error request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to submit error: {}", response.status()));
+        }
+
+        Ok(())
+    }
+
+    // Job-related methods
+
+    /// Acquire the next job from the queue
+    async fn acquire_next_job(&self) -> Result<AcquireResultBody> {
+        let request = self
+            .post("/jobs/acquire")
+            .await?
+            .timeout(Duration::from_secs(60));
+        let response = self
+            .send(request, "Failed to send acquire job request")
+            .await?;
+
+        self.handle_response_errors(
+            response,
+            NoWorkAvailable::JOBS,
+            "Failed to acquire job".to_string(),
+        )
+        .await
+    }
+
+    /// Submit job results to the server along with their columns, filter
+    /// and execution statistics
+    async fn submit_job_results(
+        &self,
+        job_id: &str,
+        data: Vec<JobType>,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        self.post_job_results(
+            job_id,
+            SubmitJobRequest {
+                records: data,
+                manifest: Manifest::default(),
+                columns,
+                result_sets: Vec::new(),
+                chunks: 0,
+                sampled: filter_stats.sampled(),
+                filter_stats,
+                execution_stats,
+                export: None,
+            },
+        )
+        .await
+    }
+
+    /// Submit job results spilled to disk, posting their rows in chunks
+    /// before their columns, filter and execution statistics
+    async fn submit_job_chunks(
+        &self,
+        job_id: &str,
+        data: JobRows,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        let mut chunks = 0;
+        for records in data.into_chunks()? {
+            self.post_job_chunk(job_id, chunks, records?).await?;
+            chunks += 1;
+        }
+        self.post_job_results(
+            job_id,
+            SubmitJobRequest {
+                records: Vec::new(),
+                manifest: Manifest::default(),
+                columns,
+                result_sets: Vec::new(),
+                chunks,
+                sampled: filter_stats.sampled(),
+                filter_stats,
+                execution_stats,
+                export: None,
+            },
+        )
+        .await
+    }
+
+    /// Submit the location and manifest of job results uploaded to a bucket
+    /// along with their columns, filter and execution statistics
+    async fn submit_job_export(
+        &self,
+        job_id: &str,
+        export: ExportManifest,
+        columns: Vec<ResultColumn>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        self.post_job_results(
+            job_id,
+            SubmitJobRequest {
+                records: Vec::new(),
+                manifest: Manifest::default(),
+                columns,
+                result_sets: Vec::new(),
+                chunks: 0,
+                sampled: filter_stats.sampled(),
+                filter_stats,
+                execution_stats,
+                export: Some(export),
+            },
+        )
+        .await
+    }
+
+    /// Submit the named result sets of a multi-query job along with filter
+    /// and execution statistics
+    async fn submit_job_result_sets(
+        &self,
+        job_id: &str,
+        result_sets: Vec<JobResultSet>,
+        filter_stats: FilterStats,
+        execution_stats: ExecutionStats,
+    ) -> Result<()> {
+        self.post_job_results(
+            job_id,
+            SubmitJobRequest {
+                records: Vec::new(),
+                manifest: Manifest::default(),
+                columns: Vec::new(),
+                result_sets,
+                chunks: 0,
+                sampled: filter_stats.sampled(),
+                filter_stats,
+                execution_stats,
+                export: None,
+            },
+        )
+        .await
+    }
+
+    /// Submit an error for a job
+    async fn submit_job_error(&self, job_id: &str, error: &str) -> Result<()> {
+        let request =
+            self.post(&format!("/jobs/{}/submit", job_id))
+                .await?
+                .json(&ErrorSubmissionRequest {
+                    error: error.to_string(),
+                    is_high_priority_queue: false,
+                });
+        let response = self
+            .send(request, "Failed to send submit job error request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to submit error: {}", response.status()));
+        }
+
+        Ok(())
+    }
+
+    /// Submit the report of a diagnostics job
+    async fn submit_diagnostics(&self, job_id: &str, report: DiagnosticsReport) -> Result<()> {
+        let request = self.post(&format!("/jobs/{}/submit", job_id)).await?.json(
+            &DiagnosticsSubmissionRequest {
+                diagnostics: report,
+            },
+        );
+        let response = self
+            .send(request, "Failed to send submit diagnostics request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to submit diagnostics: {}",
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Schema and datasource management methods
+
+    /// Submit schema information for a datasource
+    async fn submit_schemas(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<crate::executors::clickhouse_source::TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let submission = SchemaSubmissionRequest {
+            schemas,
+            from_cache: false,
+            labels: labels.clone(),
+        };
+        self.post_schemas(datasource_name, submission).await
+    }
+
+    /// Submit schemas cached by the last discovery of a datasource
+    async fn submit_cached_schemas(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<crate::executors::clickhouse_source::TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let submission = SchemaSubmissionRequest {
+            schemas,
+            from_cache: true,
+            labels: labels.clone(),
+        };
+        self.post_schemas(datasource_name, submission).await
+    }
+
+    /// Submit changes of a datasource's schemas since the last submission
+    async fn submit_schema_diff(&self, datasource_name: &str, diff: SchemaDiff) -> Result<()> {
+        log::debug!("Submitting schema diff: {:?}", &diff);
+        let request = self
+            .post(&format!("/datasource/{}/discovery/diff", datasource_name))
+            .await?
+            .json(&diff);
+        let response = self
+            .send(request, "Failed to send submit schema diff request")
+            .await?;
+
+        // Servers without diff support don't know the endpoint
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(FullSyncRequested.into());
+        }
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to submit schema diff: {}",
+                response.status()
+            ));
+        }
+
+        let body: SchemaDiffResponse = response.json().await.unwrap_or_default();
+        if body.full_sync {
+            return Err(FullSyncRequested.into());
+        }
+
+        Ok(())
+    }
+
+    /// Submit schemas of some of a datasource's tables
+    async fn submit_schema_batch(
+        &self,
+        datasource_name: &str,
+        schemas: Vec<crate::executors::clickhouse_source::TableSchema>,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        log::debug!("Submitting schema batch of {} tables", schemas.len());
+        let request = self
+            .post(&format!("/datasource/{}/discovery/batch", datasource_name))
+            .await?
+            .json(&SchemaSubmissionRequest {
+                schemas,
+                from_cache: false,
+                labels: labels.clone(),
+            });
+        let response = self
+            .send(request, "Failed to send submit schema batch request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to submit schema batch: {}",
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Mark a batched schema submission as complete
+    async fn complete_discovery(
+        &self,
+        datasource_name: &str,
+        summary: DiscoverySummary,
+    ) -> Result<()> {
+        log::debug!("Completing discovery: {:?}", &summary);
+        let request = self
+            .post(&format!(
+                "/datasource/{}/discovery/complete",
+                datasource_name
+            ))
+            .await?
+            .json(&summary);
+        let response = self
+            .send(request, "Failed to send discovery complete request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to complete discovery: {}",
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Add or update a datasource
+    async fn add_datasource(
+        &self,
+        datasource_name: &str,
+        datasource_type: &str,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        log::info!("Add datasource: {:?}", &datasource_name);
+        let request = self
+            .post(&format!("/datasource/{}/add", datasource_name))
+            .await?
+            .json(&DatasourceUpsertRequest {
+                datasource_type: datasource_type.to_string(),
+                agent: build_info(),
+                labels: labels.clone(),
+                kubernetes: pod_metadata(),
+            });
+        let response = self
+            .send(request, "Failed to send add datasource request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to update existed or create a new datasource: {}",
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Report a change of a datasource's connectivity
+    async fn report_datasource_status(
+        &self,
+        datasource_name: &str,
+        status: &DatasourceStatus,
+    ) -> Result<()> {
+        let request = self
+            .post(&format!("/datasource/{}/status", datasource_name))
+            .await?
+            .json(&DatasourceStatusReport {
+                status,
+                kubernetes: pod_metadata(),
+            });
+        let response = self
+            .send(request, "Failed to send datasource status request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to report datasource status: {}",
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Report a crash of one of the agent's loops
+    async fn report_crash(&self, report: &CrashReport) -> Result<()> {
+        let request = self.post("/agent/crash").await?.json(report);
+        let response = self
+            .send(request, "Failed to send crash report request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to report crash: {}", response.status()));
+        }
+
+        Ok(())
+    }
+
+    /// Report a query that ran longer than the slow query threshold
+    async fn report_slow_query(&self, query: &SlowQuery) -> Result<()> {
+        let request = self.post("/agent/slow_query").await?.json(query);
+        let response = self
+            .send(request, "Failed to send slow query report request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to report slow query: {}",
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Acquire or renew the lease of an active/standby pair
+    async fn acquire_lease(&self, request: &LeaseRequest) -> Result<LeaseGrant> {
+        let http_request = self.post("/agent/lease").await?.json(request);
+        let response = self
+            .send(http_request, "Failed to send lease request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to acquire lease: {}", response.status()));
+        }
+
+        response
+            .json()
+            .await
+            .context("Failed to parse lease response")
+    }
+
+    /// Give up the lease of an active/standby pair
+    async fn release_lease(&self, holder: &str) -> Result<()> {
+        let request = self
+            .post("/agent/lease/release")
+            .await?
+            .json(&serde_json::json!({ "holder": holder }));
+        let response = self
+            .send(request, "Failed to send lease release request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to release lease: {}", response.status()));
+        }
+
+        Ok(())
+    }
+
+    /// Report the errors of all agents by category since the previous report
+    async fn report_errors(&self, summary: &ErrorSummary) -> Result<()> {
+        let request = self.post("/agent/errors").await?.json(summary);
+        let response = self
+            .send(request, "Failed to send error summary request")
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to report error summary: {}",
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check that the server answers at its base URL. Any response short of
+    /// a server error counts, as the base URL itself needn't be a route
+    async fn check_reachable(&self) -> Result<()> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+
+        let response = self
+            .client
+            .get(&self.base_url)
+            .timeout(REACHABILITY_TIMEOUT)
+            .send()
+            .await
+            .context("Failed to reach server")?;
+
+        if response.status().is_server_error() {
+            return Err(anyhow!("Server responded with {}", response.status()));
+        }
+
+        Ok(())
+    }
+}
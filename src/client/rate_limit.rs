@@ -0,0 +1,69 @@
+//! Token-bucket rate limiter for outgoing server requests
+//!
+//! A single limiter is shared by every clone of `ServerClient`, so all agent
+//! loops together never exceed the configured requests-per-second ceiling.
+
+use crate::config::RateLimitConfig;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Current state of the bucket
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token-bucket rate limiter
+pub struct RateLimiter {
+    rate: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing `requests_per_second` with bursts up to `burst`
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        let capacity = f64::from(burst.max(1));
+        Self {
+            rate: requests_per_second,
+            capacity,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Create a limiter from configuration
+    pub fn from_config(config: &RateLimitConfig) -> Self {
+        let burst = config
+            .burst
+            .unwrap_or_else(|| config.requests_per_second.ceil() as u32);
+        Self::new(config.requests_per_second, burst)
+    }
+
+    /// Wait until a request is allowed to be sent
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().await;
+
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+            };
+
+            log::debug!("Rate limit reached, delaying request by {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
@@ -0,0 +1,1786 @@
+pub mod datasource_url;
+pub mod example;
+pub mod strict;
+
+use crate::filters::{builtin_preset, BUILTIN_PRESET_PREFIX};
+use crate::models::{default_timeout, DataSource, DataSourceType, JobType};
+use crate::secrets::encrypted::{is_encrypted, KeySource};
+use crate::timeseries::TimeUnit;
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use strict::UnknownKey;
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct ServerConfig {
+    /// Static API key. Not required when `auth` is configured
+    #[serde(default)]
+    pub api_key: String,
+    pub server_url: String,
+    /// OAuth2 client-credentials authentication used instead of `api_key`
+    pub auth: Option<AuthConfig>,
+    /// Ceiling for requests sent to the server across all agent loops
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Path prefix for API routes when the server sits behind a gateway, e.g. `/tsight/api`
+    pub path_prefix: Option<String>,
+    /// Additional headers sent with every request, e.g. `X-Org-Id`
+    pub extra_headers: Option<HashMap<String, String>>,
+    /// gRPC endpoint used to acquire tasks and submit their results
+    /// instead of the HTTP API
+    pub grpc: Option<GrpcConfig>,
+}
+
+/// gRPC transport of the task protocol
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GrpcConfig {
+    /// Endpoint of the server's gRPC service, e.g. `https://grpc.tsight.app`
+    pub url: String,
+    /// Have the server push tasks over a stream rather than polling for them
+    #[serde(default = "default_grpc_push")]
+    pub push: bool,
+}
+
+fn default_grpc_push() -> bool {
+    true
+}
+
+/// Token-bucket rate limit for outgoing server requests
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RateLimitConfig {
+    pub requests_per_second: f64,
+    /// Maximum burst size, defaults to one second worth of requests
+    pub burst: Option<u32>,
+}
+
+/// OAuth2 client-credentials settings for obtaining bearer tokens
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuthConfig {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scope: Option<String>,
+    /// Refresh the token this many seconds before it expires
+    #[serde(default = "default_refresh_margin")]
+    pub refresh_margin_secs: u64,
+}
+
+fn default_refresh_margin() -> u64 {
+    30
+}
+
+/// What happens to a row when an exclude rule matches one of its columns or values
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// Remove the whole row
+    #[default]
+    DropRow,
+    /// Replace characters of the value with `*`, keeping its length and last 4 characters
+    Mask,
+    /// Replace the value with `[REDACTED]`
+    RedactValue,
+    /// Replace the value with its HMAC-SHA256 keyed by `global_filters.hash_key`
+    Hash,
+}
+
+/// Outcome of an ordered filter rule
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Keep the database, table, column or value as is
+    Allow,
+    /// Skip the database or table, drop the row of the column or value
+    Deny,
+    /// Mask the values, as the `mask` filter action
+    Mask,
+    /// Redact the values, as the `redact_value` filter action
+    RedactValue,
+    /// Hash the values, as the `hash` filter action
+    Hash,
+}
+
+/// Filter rule of the ordered `rules` list, the first matching rule decides
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FilterRule {
+    /// Name reported in filter statistics, defaults to `rules[<index>]`
+    pub name: Option<String>,
+    /// Patterns and value checks of the rule, with the keys of
+    /// `sql_filters_exclude` entries
+    #[serde(rename = "match")]
+    pub matcher: SqlFilterRules,
+    pub action: RuleAction,
+}
+
+/// How the patterns of a filter rule are matched
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// Patterns are regular expressions matching anywhere unless anchored
+    #[default]
+    Regex,
+    /// Patterns match the whole name or value literally
+    Exact,
+    /// Patterns match literally anywhere in the name or value
+    Substring,
+}
+
+/// Bounds of numeric values, either may be left open
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+pub struct NumericRange {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct SqlFilterRules {
+    pub database_regexes: Option<Vec<String>>,
+    pub table_regexes: Option<Vec<String>>,
+    /// Patterns matched against qualified `database.table` names
+    pub qualified_table_regexes: Option<Vec<String>>,
+    pub column_name_regexes: Option<Vec<String>>,
+    pub column_value_regexes: Option<Vec<String>>,
+    /// Action of exclude rules on matching columns and values, defaults to `drop_row`
+    pub action: Option<FilterAction>,
+    /// Name reported in filter statistics, defaults to the rule's position
+    pub name: Option<String>,
+    /// Match all patterns of the rule ignoring case
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// How patterns of the rule are matched, defaults to `regex`
+    #[serde(rename = "match")]
+    pub match_mode: Option<MatchMode>,
+    /// Exclude values longer than this many characters
+    pub max_length: Option<usize>,
+    /// Exclude numeric values outside this range
+    pub numeric_range: Option<NumericRange>,
+    /// Exclude strings with a higher Shannon entropy in bits per character,
+    /// which are likely tokens or keys
+    pub max_entropy: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct GlobalFilters {
+    /// Ordered rules evaluated before the allow and exclude lists
+    pub rules: Option<Vec<FilterRule>>,
+    pub sql_filters_exclude: Option<Vec<SqlFilterRules>>,
+    pub sql_filters_allow: Option<Vec<SqlFilterRules>>,
+    /// Local-only secret keying the `hash` action, never sent to the server
+    pub hash_key: Option<String>,
+    /// Maximum number of rows submitted per job
+    pub max_rows: Option<usize>,
+    /// Fraction of job rows kept, between 0 and 1
+    pub sample_rate: Option<f64>,
+    /// Differential privacy applied to aggregate job results
+    pub privacy: Option<PrivacyConfig>,
+}
+
+/// Noise and minimum group size of aggregate job results
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PrivacyConfig {
+    /// Privacy budget of each noised value, smaller values add more noise
+    pub epsilon: Option<f64>,
+    /// Most a single source row can change a noised value
+    #[serde(default = "default_sensitivity")]
+    pub sensitivity: f64,
+    /// Numeric aggregate columns Laplace noise is added to
+    #[serde(default)]
+    pub noise_columns: Vec<String>,
+    /// Column holding the number of source rows of each group
+    pub count_column: Option<String>,
+    /// Groups of fewer source rows are suppressed
+    pub min_group_size: Option<u64>,
+}
+
+fn default_sensitivity() -> f64 {
+    1.0
+}
+
+/// Schema discovery settings of a single datasource
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiscoveryConfig {
+    /// Whether schemas of the datasource are discovered at all
+    #[serde(default = "default_discovery_enabled")]
+    pub enabled: bool,
+    /// Rediscover schemas this often, e.g. `6h`. Discovery runs only at startup when unset
+    #[serde(default, with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    /// Replace database, table and column names with keyed hashes before
+    /// schemas are submitted
+    #[serde(default)]
+    pub anonymize: bool,
+    /// File keeping the anonymized names' originals, defaults to
+    /// `schema-mapping-<datasource>.json` in the working directory
+    pub mapping_file: Option<PathBuf>,
+    /// Submit only changes since the last submitted schemas
+    #[serde(default)]
+    pub incremental: bool,
+    /// File keeping the last submitted schemas, defaults to
+    /// `schema-cache-<datasource>.json` in the working directory
+    pub cache_file: Option<PathBuf>,
+    /// Submit the schemas cached by the last discovery at startup, before
+    /// fresh discovery finishes
+    #[serde(default)]
+    pub submit_cached: bool,
+    /// Record a table with partial information when discovering it takes
+    /// longer than this, e.g. `30s`
+    #[serde(default, with = "humantime_serde")]
+    pub table_timeout: Option<Duration>,
+    /// Skip discovery of tables that timed out this many times in a row
+    #[serde(default = "default_skip_after_timeouts")]
+    pub skip_after_timeouts: u32,
+    /// File keeping the timeouts of each table, defaults to
+    /// `discovery-skip-<datasource>.json` in the working directory
+    pub skip_list_file: Option<PathBuf>,
+    /// Report how many rows each table gained since the previous discovery
+    #[serde(default)]
+    pub track_growth: bool,
+    /// File keeping the row counts of the last discovery, defaults to
+    /// `row-counts-<datasource>.json` in the working directory
+    pub row_counts_file: Option<PathBuf>,
+    /// Report up to this many of the most frequent values of each string
+    /// column, passed through the value filters first
+    pub sample_values: Option<usize>,
+    /// Submit schemas in batches of this many tables as they are discovered,
+    /// followed by a completion marker. Ignored for incremental discovery
+    pub batch_size: Option<usize>,
+}
+
+fn default_skip_after_timeouts() -> u32 {
+    3
+}
+
+fn default_discovery_enabled() -> bool {
+    true
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_discovery_enabled(),
+            interval: None,
+            anonymize: false,
+            mapping_file: None,
+            incremental: false,
+            cache_file: None,
+            submit_cached: false,
+            table_timeout: None,
+            skip_after_timeouts: default_skip_after_timeouts(),
+            skip_list_file: None,
+            track_growth: false,
+            row_counts_file: None,
+            sample_values: None,
+            batch_size: None,
+        }
+    }
+}
+
+/// Kind of SQL statement a query policy can accept
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatementKind {
+    /// `SELECT` queries, including `WITH` and set operations
+    Select,
+    /// `SHOW TABLES`, `SHOW DATABASES` and similar
+    Show,
+    /// `DESCRIBE <table>`
+    Describe,
+    /// `EXPLAIN` of an accepted statement, never `EXPLAIN ANALYZE`
+    Explain,
+    /// `SET <setting> = <value>`, changing settings of a multi-query task's
+    /// session
+    Set,
+}
+
+/// How the agent authenticates to a datasource
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum DatasourceAuth {
+    /// The datasource's `username` and `password`
+    #[default]
+    Password,
+    /// Kerberos tickets obtained with a keytab, sent over SPNEGO
+    Kerberos(KerberosConfig),
+    /// AWS IAM authentication tokens of RDS and Aurora databases, used as
+    /// the password
+    Iam(IamAuthConfig),
+}
+
+/// Kerberos credentials of a datasource
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct KerberosConfig {
+    /// Keytab holding the agent's key, e.g. `/etc/tsight/agent.keytab`
+    pub keytab: PathBuf,
+    /// Principal to authenticate as, e.g. `tsight@EXAMPLE.COM`, the first
+    /// one of the keytab when unset
+    pub principal: Option<String>,
+    /// Service of the datasource's principal, which is `<service>/<host>`
+    #[serde(default = "default_kerberos_service")]
+    pub service: String,
+}
+
+fn default_kerberos_service() -> String {
+    "HTTP".to_string()
+}
+
+/// AWS IAM authentication of an RDS or Aurora datasource
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct IamAuthConfig {
+    /// Region of the database, defaults to the `AWS_REGION` env variable
+    pub region: Option<String>,
+    /// Role assumed to sign tokens, e.g.
+    /// `arn:aws:iam::123456789012:role/tsight-reader`. Tokens are signed
+    /// with the `AWS_*` env variables or the instance role when unset
+    pub role_arn: Option<String>,
+    /// Override of the STS endpoint, e.g. for VPC endpoints
+    pub sts_endpoint_url: Option<String>,
+}
+
+/// What the agent does at startup when a datasource's user can write
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadOnlyCheck {
+    /// Don't check the user's privileges
+    Off,
+    /// Log a warning listing the write privileges
+    #[default]
+    Warn,
+    /// Refuse to start
+    Enforce,
+}
+
+/// How the agent picks the host of a datasource with several hosts
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HostStrategy {
+    /// The first healthy host in the order of `hosts`
+    #[default]
+    FirstHealthy,
+    /// Healthy hosts in turn
+    RoundRobin,
+    /// The healthy host with the lowest probe latency
+    Nearest,
+}
+
+/// Canned results and schemas of a `mock` datasource
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct MockConfig {
+    /// Results of queries, answered by the first whose `query_regex` matches
+    #[serde(default)]
+    pub results: Vec<MockResult>,
+    /// Tables reported by schema discovery
+    #[serde(default)]
+    pub tables: Vec<MockTable>,
+}
+
+/// Canned result of the queries of a `mock` datasource matching a regex
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct MockResult {
+    /// Regex of the queries answered, any query when unset
+    pub query_regex: Option<String>,
+    /// Columns of the rows in order, inferred from the values when unset
+    #[serde(default)]
+    pub columns: Vec<MockColumn>,
+    #[serde(default)]
+    pub rows: Vec<JobType>,
+    /// Fail matching queries with this error instead of returning rows
+    pub error: Option<String>,
+}
+
+/// Column of a `mock` datasource with its ClickHouse type, e.g. `UInt64`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MockColumn {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+}
+
+/// Table of a `mock` datasource reported by schema discovery
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MockTable {
+    #[serde(default = "default_mock_database")]
+    pub database: String,
+    pub table: String,
+    #[serde(default)]
+    pub row_count: u64,
+    #[serde(default)]
+    pub columns: Vec<MockColumn>,
+}
+
+fn default_mock_database() -> String {
+    "default".to_string()
+}
+
+/// Program post-processing the rows of a datasource's jobs before they're
+/// submitted, reading and writing them as JSON lines
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TransformConfig {
+    /// Program and its arguments, e.g. `["python3", "transform.py"]`
+    pub command: Vec<String>,
+    /// Time the program may take for the rows of a job, e.g. `10s`
+    #[serde(default = "default_transform_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// Megabytes of memory the program may allocate
+    #[serde(default = "default_transform_memory_limit_mb")]
+    pub memory_limit_mb: u64,
+    /// Megabytes of rows the program may write
+    #[serde(default = "default_transform_max_output_mb")]
+    pub max_output_mb: u64,
+}
+
+fn default_transform_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_transform_memory_limit_mb() -> u64 {
+    256
+}
+
+fn default_transform_max_output_mb() -> u64 {
+    1024
+}
+
+/// Statements a datasource executes on behalf of the server
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueryPolicy {
+    /// Whether queries are checked before they reach the datasource
+    #[serde(default = "default_query_policy_enabled")]
+    pub enabled: bool,
+    /// Statement kinds accepted, a query must consist of a single one of them
+    #[serde(default = "default_allowed_statements")]
+    pub allowed_statements: Vec<StatementKind>,
+}
+
+fn default_query_policy_enabled() -> bool {
+    true
+}
+
+fn default_allowed_statements() -> Vec<StatementKind> {
+    vec![StatementKind::Select]
+}
+
+impl Default for QueryPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: default_query_policy_enabled(),
+            allowed_statements: default_allowed_statements(),
+        }
+    }
+}
+
+/// Recurring UTC time window during which a datasource isn't queried
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MaintenanceWindow {
+    /// Days the window starts on, every day when empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Start time, e.g. `02:00`
+    pub start: NaiveTime,
+    /// End time. A window ending before its start runs past midnight
+    pub end: NaiveTime,
+}
+
+impl MaintenanceWindow {
+    /// Whether the window recurs on `day`
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    /// Check if `at` falls within the window
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let time = at.time();
+        let today = at.weekday();
+        if self.start <= self.end {
+            self.starts_on(today) && time >= self.start && time < self.end
+        } else {
+            (self.starts_on(today) && time >= self.start)
+                || (self.starts_on(today.pred()) && time < self.end)
+        }
+    }
+}
+
+/// Vault connection used to resolve `vault:` secret references
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VaultConfig {
+    pub address: String,
+    /// Vault token. Falls back to `token_file`, then the `VAULT_TOKEN` env variable
+    pub token: Option<String>,
+    pub token_file: Option<String>,
+    pub namespace: Option<String>,
+    /// How often to renew the token and leases of fetched secrets
+    #[serde(default = "default_renew_interval")]
+    pub renew_interval_secs: u64,
+}
+
+fn default_renew_interval() -> u64 {
+    300
+}
+
+/// AWS Secrets Manager settings used to resolve `aws-sm:` references
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct AwsSecretsConfig {
+    /// Defaults to the `AWS_REGION` env variable
+    pub region: Option<String>,
+    /// Static credentials. Default to `AWS_*` env variables, then the instance role
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    /// Override of the Secrets Manager endpoint, e.g. for VPC endpoints
+    pub endpoint_url: Option<String>,
+}
+
+/// GCP Secret Manager settings used to resolve `gcp-sm:` references
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct GcpSecretsConfig {
+    /// Defaults to `GOOGLE_OAUTH_ACCESS_TOKEN`, then the metadata server
+    pub access_token: Option<String>,
+    pub endpoint_url: Option<String>,
+}
+
+/// OS keyring used to resolve `keyring:` secret references
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct KeyringConfig {
+    /// Service name entries are stored under, defaults to `tsight-agent`
+    pub service: Option<String>,
+}
+
+/// Secret providers used to resolve credential references in the config
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct SecretsConfig {
+    pub vault: Option<VaultConfig>,
+    pub aws: Option<AwsSecretsConfig>,
+    pub gcp: Option<GcpSecretsConfig>,
+    pub keyring: Option<KeyringConfig>,
+    pub encryption: Option<EncryptionConfig>,
+}
+
+/// Machine-local key decrypting `enc:` credentials while the config loads
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct EncryptionConfig {
+    /// Key file, relative to the config file. Defaults to `secret.key` next
+    /// to it
+    pub key_file: Option<PathBuf>,
+    /// Read the key from this OS keyring entry instead of a file
+    pub keyring_entry: Option<String>,
+}
+
+/// Local HTTP listener serving the agent's metrics
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListenerConfig {
+    /// Address to listen on, e.g. `127.0.0.1:9464`
+    pub address: String,
+}
+
+/// Format of log lines written to stderr
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the task or discovery
+    /// the record belongs to
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}'", s)),
+        }
+    }
+}
+
+/// When a file is rotated regardless of its size
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationPeriod {
+    #[default]
+    Never,
+    /// At the start of every UTC hour
+    Hourly,
+    /// At midnight UTC
+    Daily,
+}
+
+/// Log file written instead of stderr
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogFileConfig {
+    pub path: PathBuf,
+    /// Rotate the file once it would exceed this many megabytes
+    #[serde(default = "default_log_max_size_mb")]
+    pub max_size_mb: u64,
+    /// Also rotate the file every hour or day
+    #[serde(default)]
+    pub rotation: RotationPeriod,
+    /// Rotated files kept as `<path>.1` (newest) to `<path>.<max_files>`
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+}
+
+fn default_log_max_size_mb() -> u64 {
+    100
+}
+
+fn default_log_max_files() -> usize {
+    7
+}
+
+impl LogFileConfig {
+    /// Log file at `path` with the default rotation
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_size_mb: default_log_max_size_mb(),
+            rotation: RotationPeriod::default(),
+            max_files: default_log_max_files(),
+        }
+    }
+}
+
+/// Log output settings
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Write logs to a rotated file instead of stderr
+    pub file: Option<LogFileConfig>,
+}
+
+/// OTLP/HTTP export of task traces
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct OpenTelemetryConfig {
+    /// Collector address, e.g. `http://localhost:4318`. Defaults to the
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT` env variable, then `http://localhost:4318`
+    pub endpoint: Option<String>,
+    /// Service name of exported spans, defaults to `tsight-agent`
+    pub service_name: Option<String>,
+    /// Additional headers sent to the collector, e.g. for authentication
+    pub headers: Option<HashMap<String, String>>,
+}
+
+/// Public keys queries from the server must be signed with
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QuerySigningConfig {
+    /// Base64 encoded raw 32-byte Ed25519 public keys, several while keys
+    /// are rotated
+    pub public_keys: Vec<String>,
+}
+
+/// Local audit log of the queries the agent runs
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditConfig {
+    /// JSON lines file, e.g. `/var/log/tsight/audit.jsonl`
+    pub path: PathBuf,
+    /// Rotate the file once it would exceed this many megabytes
+    #[serde(default = "default_audit_max_size_mb")]
+    pub max_size_mb: u64,
+    /// Rotated files kept as `<path>.1` (newest) to `<path>.<max_files>`
+    #[serde(default = "default_audit_max_files")]
+    pub max_files: usize,
+}
+
+fn default_audit_max_size_mb() -> u64 {
+    100
+}
+
+fn default_audit_max_files() -> usize {
+    10
+}
+
+impl AuditConfig {
+    /// Audit log at `path` with the default rotation
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_size_mb: default_audit_max_size_mb(),
+            max_files: default_audit_max_files(),
+        }
+    }
+}
+
+/// Logging of queries running longer than a threshold
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SlowQueryConfig {
+    /// Queries running at least this long are logged, e.g. `10s`
+    #[serde(with = "humantime_serde")]
+    pub threshold: Duration,
+    /// Also report slow queries to the server
+    #[serde(default)]
+    pub report: bool,
+}
+
+/// Active/standby pairing of agents serving the same datasources, where only
+/// the holder of the server's lease acquires tasks
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LeadershipConfig {
+    /// Name the agent holds the lease under, its host name and process id by
+    /// default
+    pub holder: Option<String>,
+    /// Time a lease lasts unless it's renewed, e.g. `10s`. The standby takes
+    /// over this long after the leader last renewed it
+    #[serde(default = "default_lease_ttl", with = "humantime_serde")]
+    pub lease_ttl: Duration,
+    /// How often the lease is requested, a third of `lease_ttl` by default
+    #[serde(default, with = "humantime_serde")]
+    pub renew_interval: Option<Duration>,
+}
+
+fn default_lease_ttl() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Endpoint for Grafana's JSON datasources, running their queries like tasks
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GrafanaConfig {
+    /// Address to listen on, e.g. `127.0.0.1:3100`
+    pub address: String,
+    /// Key Grafana must send as a bearer token or basic auth password. Only
+    /// optional on a loopback address, where any request is answered when
+    /// it's unset
+    pub api_key: Option<String>,
+    /// Datasources Grafana may query, all of them when unset
+    pub datasources: Option<Vec<String>>,
+}
+
+/// OTLP/HTTP export of the records of time series tasks as gauges, besides
+/// submitting them to the server
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OtlpMetricsConfig {
+    /// Collector address, e.g. `http://localhost:4318`
+    pub endpoint: String,
+    /// Additional headers sent to the collector, e.g. for authentication
+    pub headers: Option<HashMap<String, String>>,
+    /// Prepended to value column names to make metric names, `tsight_` by
+    /// default
+    #[serde(default = "default_otlp_metric_prefix")]
+    pub metric_prefix: String,
+    /// Datasources whose task results are exported, all by default
+    pub datasources: Option<Vec<String>>,
+    /// Service name of the exported resource, defaults to `tsight-agent`
+    pub service_name: Option<String>,
+}
+
+fn default_otlp_metric_prefix() -> String {
+    "tsight_".to_string()
+}
+
+/// Endpoint for Prometheus' remote read, answering with series queried
+/// from the datasources
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RemoteReadConfig {
+    /// Address to listen on, e.g. `127.0.0.1:9201`
+    pub address: String,
+    /// Key Prometheus must send as a bearer token or basic auth password.
+    /// Only optional on a loopback address, where any request is answered
+    /// when it's unset
+    pub api_key: Option<String>,
+    pub metrics: Vec<RemoteReadMetric>,
+}
+
+/// Metric read from a datasource with a query template
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RemoteReadMetric {
+    /// Metric name the series get, matched against `__name__`
+    pub name: String,
+    pub datasource: String,
+    /// Query with time macros, expanded with the time range and step of the
+    /// read request
+    pub query: String,
+    /// Column holding the timestamp, `t` by default
+    #[serde(default = "default_time_column")]
+    pub time_column: String,
+    #[serde(default)]
+    pub time_unit: TimeUnit,
+    /// Column holding the sample value, `value` by default
+    #[serde(default = "default_value_column")]
+    pub value_column: String,
+    /// Columns labelling series, all columns besides the time and value
+    /// ones by default
+    pub label_columns: Option<Vec<String>>,
+}
+
+fn default_time_column() -> String {
+    "t".to_string()
+}
+
+fn default_value_column() -> String {
+    "value".to_string()
+}
+
+/// Periodic insert of the agent's own metrics into a ClickHouse table
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelfMetricsConfig {
+    /// ClickHouse datasource on whose hosts the table is
+    pub datasource: String,
+    /// Table, optionally qualified with its database, created when missing.
+    /// `tsight_agent_metrics` by default
+    #[serde(default = "default_self_metrics_table")]
+    pub table: String,
+    /// User inserting the metrics, the datasource's by default. Preferably
+    /// one allowed to insert into the table only, so the datasource's user
+    /// stays read-only
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Insert the metrics this often, `1m` by default
+    #[serde(default = "default_self_metrics_interval", with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+fn default_self_metrics_table() -> String {
+    "tsight_agent_metrics".to_string()
+}
+
+fn default_self_metrics_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// Upload of job results to an S3 compatible bucket, submitting only their
+/// location and manifest to the server
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResultExportConfig {
+    pub bucket: String,
+    /// Prepended to the keys of the uploaded objects, e.g. `tsight/`
+    #[serde(default)]
+    pub prefix: String,
+    /// Defaults to the `AWS_REGION` env variable, `auto` for GCS
+    pub region: Option<String>,
+    /// Defaults to `https://s3.<region>.amazonaws.com`, e.g.
+    /// `https://storage.googleapis.com` for GCS
+    pub endpoint_url: Option<String>,
+    /// Static credentials, HMAC keys for GCS. Default to `AWS_*` env
+    /// variables, then the instance role
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    /// Jobs with fewer rows are submitted as usual, none when unset
+    pub min_rows: Option<usize>,
+}
+
+/// Webhooks notified of sustained failures on the agent's side, which the
+/// server may not hear about
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NotificationsConfig {
+    pub webhooks: Vec<WebhookConfig>,
+    /// Notify when a probed datasource stays unreachable this long, `5m` by
+    /// default
+    #[serde(default = "default_down_after", with = "humantime_serde")]
+    pub datasource_down_after: Duration,
+    /// Notify when a server can't be connected to this long, `5m` by default
+    #[serde(default = "default_down_after", with = "humantime_serde")]
+    pub server_down_after: Duration,
+    /// Notify when a server rejects the agent's credentials this many times
+    /// within `auth_failure_window`, 3 by default
+    #[serde(default = "default_auth_failures")]
+    pub auth_failures: usize,
+    #[serde(default = "default_auth_failure_window", with = "humantime_serde")]
+    pub auth_failure_window: Duration,
+}
+
+fn default_down_after() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn default_auth_failures() -> usize {
+    3
+}
+
+fn default_auth_failure_window() -> Duration {
+    Duration::from_secs(600)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Additional headers sent with every notification, e.g. for
+    /// authentication
+    pub headers: Option<HashMap<String, String>>,
+}
+
+/// Body of webhook requests
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// The notification as a JSON object
+    #[default]
+    Generic,
+    /// A Slack incoming webhook message
+    Slack,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct Config {
+    /// Server handing out tasks for the datasources of no tenant. Optional
+    /// when every datasource belongs to a tenant
+    #[serde(default)]
+    pub server: ServerConfig,
+    pub datasources: Vec<DataSource>,
+    /// Further servers, each handing out tasks for its own datasources
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
+    pub global_filters: Option<GlobalFilters>,
+    /// Named filter sets datasources reference in their `filter_presets` list
+    pub filter_presets: Option<HashMap<String, GlobalFilters>>,
+    pub secrets: Option<SecretsConfig>,
+    /// Reject unknown keys in config files, on by default
+    pub strict: Option<bool>,
+    /// Local HTTP listener, disabled when unset
+    pub listener: Option<ListenerConfig>,
+    pub logging: Option<LoggingConfig>,
+    /// Export traces of tasks and jobs, disabled when unset
+    pub opentelemetry: Option<OpenTelemetryConfig>,
+    /// Audit log of executed queries, disabled when unset
+    pub audit: Option<AuditConfig>,
+    /// Log of slow queries, disabled when unset
+    pub slow_query: Option<SlowQueryConfig>,
+    /// Only run queries signed with one of these keys, disabled when unset
+    pub query_signing: Option<QuerySigningConfig>,
+    /// Only acquire tasks while holding the lease of each server, so a
+    /// standby agent can take over from the active one
+    pub leadership: Option<LeadershipConfig>,
+    /// Webhooks notified of sustained local failures, disabled when unset
+    pub notifications: Option<NotificationsConfig>,
+    /// Endpoint for Grafana's JSON datasources, disabled when unset
+    pub grafana: Option<GrafanaConfig>,
+    /// Endpoint for Prometheus' remote read, disabled when unset
+    pub remote_read: Option<RemoteReadConfig>,
+    /// Export of time series task results to an OTLP collector, disabled
+    /// when unset
+    pub otlp_metrics: Option<OtlpMetricsConfig>,
+    /// Writer of the agent's own metrics to a ClickHouse table, disabled
+    /// when unset
+    pub self_metrics: Option<SelfMetricsConfig>,
+    /// Upload of job results to a bucket, submitted to the server when unset
+    pub result_export: Option<ResultExportConfig>,
+    /// Report errors by category to the server this often, `5m` when unset
+    /// and never when `0s`
+    #[serde(default, with = "humantime_serde")]
+    pub error_report_interval: Option<Duration>,
+}
+
+/// Server of a tenant, running its own agents for a subset of datasources
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TenantConfig {
+    pub name: String,
+    pub server: ServerConfig,
+    /// Names of the tenant's datasources, each belonging to one tenant only
+    pub datasources: Vec<String>,
+}
+
+/// Server and datasources of a tenant, or of the top-level `server`
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub name: String,
+    pub server: ServerConfig,
+    pub datasources: Vec<DataSource>,
+}
+
+/// Name of the tenant of the top-level `server`
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Partial configuration merged from files in the `config.d/` directory
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ConfigFragment {
+    #[serde(default)]
+    pub datasources: Vec<DataSource>,
+    pub global_filters: Option<GlobalFilters>,
+    pub filter_presets: Option<HashMap<String, GlobalFilters>>,
+}
+
+/// Concatenate optional rule lists
+fn concat_rules<T>(target: &mut Option<Vec<T>>, rules: Option<Vec<T>>) {
+    if let Some(rules) = rules {
+        target.get_or_insert_with(Vec::new).extend(rules);
+    }
+}
+
+impl GlobalFilters {
+    /// Append the rules of another filter set to this one
+    pub fn merge(&mut self, other: GlobalFilters) {
+        concat_rules(&mut self.rules, other.rules);
+        concat_rules(&mut self.sql_filters_exclude, other.sql_filters_exclude);
+        concat_rules(&mut self.sql_filters_allow, other.sql_filters_allow);
+        if self.hash_key.is_none() {
+            self.hash_key = other.hash_key;
+        }
+        if self.max_rows.is_none() {
+            self.max_rows = other.max_rows;
+        }
+        if self.sample_rate.is_none() {
+            self.sample_rate = other.sample_rate;
+        }
+        if self.privacy.is_none() {
+            self.privacy = other.privacy;
+        }
+    }
+}
+
+/// Name of the directory next to the main config file holding config fragments
+pub const CONFIG_FRAGMENTS_DIR: &str = "config.d";
+
+/// Parse a single YAML file into the requested type, also returning keys
+/// of the file the type doesn't define
+fn load_file<T: serde::de::DeserializeOwned>(
+    path: &Path,
+) -> Result<(T, Vec<UnknownKey>), config::ConfigError> {
+    let settings = config::Config::builder()
+        .add_source(config::File::from(path))
+        .build()
+        .map_err(|e| {
+            config::ConfigError::NotFound(format!(
+                "Failed to load config file at '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+    let (value, ignored) = strict::deserialize_tracking(settings).map_err(|e| {
+        config::ConfigError::Message(format!(
+            "Failed to parse config file at '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    Ok((value, strict::locate_unknown_keys(path, ignored)))
+}
+
+/// Fragment files in the `config.d/` directory next to `path`, sorted by name
+fn fragment_paths(path: &Path) -> Result<Vec<PathBuf>, config::ConfigError> {
+    let dir = path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(CONFIG_FRAGMENTS_DIR);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let entries = std::fs::read_dir(&dir).map_err(|e| {
+        config::ConfigError::Message(format!("Failed to read '{}': {}", dir.display(), e))
+    })?;
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && matches!(
+                    path.extension().and_then(|ext| ext.to_str()),
+                    Some("yaml") | Some("yml")
+                )
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+impl Config {
+    /// Load the config file at `path` merged with fragments from `config.d/`
+    pub fn load(path: &Path) -> Result<Self, config::ConfigError> {
+        let (mut config, mut unknown_keys): (Config, _) = load_file(path)?;
+
+        for fragment_path in fragment_paths(path)? {
+            log::info!("Merging configuration fragment {}", fragment_path.display());
+            let (fragment, fragment_unknown_keys): (ConfigFragment, _) = load_file(&fragment_path)?;
+            unknown_keys.extend(fragment_unknown_keys);
+            config.merge_fragment(fragment).map_err(|e| {
+                config::ConfigError::Message(format!(
+                    "Failed to merge config file at '{}': {}",
+                    fragment_path.display(),
+                    e
+                ))
+            })?;
+        }
+
+        config.check_unknown_keys(&unknown_keys)?;
+        config
+            .check_tenants()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .check_grpc_urls()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .check_grafana_datasources()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .check_endpoint_api_keys()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .check_remote_read_metrics()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .check_otlp_metrics_datasources()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .check_self_metrics()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .resolve_datasource_urls()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .check_credential_files()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .check_transforms()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .resolve_filter_presets()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .decrypt_credentials(path.parent().unwrap_or(Path::new("")))
+            .map_err(|e| config::ConfigError::Message(format!("{:#}", e)))?;
+
+        Ok(config)
+    }
+
+    /// Servers and their datasources: the top-level `server`, if set, with
+    /// the datasources of no tenant, followed by each tenant
+    pub fn tenants(&self) -> Vec<Tenant> {
+        let mut tenants = Vec::new();
+        if !self.server.server_url.is_empty() {
+            tenants.push(Tenant {
+                name: DEFAULT_TENANT.to_string(),
+                server: self.server.clone(),
+                datasources: self
+                    .datasources
+                    .iter()
+                    .filter(|ds| {
+                        !self
+                            .tenants
+                            .iter()
+                            .any(|t| t.datasources.contains(&ds.name))
+                    })
+                    .cloned()
+                    .collect(),
+            });
+        }
+        for tenant in &self.tenants {
+            tenants.push(Tenant {
+                name: tenant.name.clone(),
+                server: tenant.server.clone(),
+                datasources: self
+                    .datasources
+                    .iter()
+                    .filter(|ds| tenant.datasources.contains(&ds.name))
+                    .cloned()
+                    .collect(),
+            });
+        }
+        tenants
+    }
+
+    /// Check that tenant names are unique and that every datasource is
+    /// served by exactly one server
+    pub fn check_tenants(&self) -> Result<(), String> {
+        let has_server = !self.server.server_url.is_empty();
+        if !has_server && self.tenants.is_empty() {
+            return Err("server.server_url is required".to_string());
+        }
+        let mut names = HashSet::new();
+        if has_server {
+            names.insert(DEFAULT_TENANT);
+        }
+        let mut owners: HashMap<&str, &str> = HashMap::new();
+        for tenant in &self.tenants {
+            if !names.insert(tenant.name.as_str()) {
+                return Err(format!("duplicate tenant name '{}'", tenant.name));
+            }
+            if tenant.server.server_url.is_empty() {
+                return Err(format!("tenant '{}' has no server_url", tenant.name));
+            }
+            for name in &tenant.datasources {
+                if !self.datasources.iter().any(|ds| &ds.name == name) {
+                    return Err(format!(
+                        "tenant '{}' lists unknown datasource '{}'",
+                        tenant.name, name
+                    ));
+                }
+                if let Some(owner) = owners.insert(name, &tenant.name) {
+                    return Err(format!(
+                        "datasource '{}' belongs to both tenants '{}' and '{}'",
+                        name, owner, tenant.name
+                    ));
+                }
+            }
+        }
+        if !has_server {
+            if let Some(datasource) = self
+                .datasources
+                .iter()
+                .find(|ds| !owners.contains_key(ds.name.as_str()))
+            {
+                return Err(format!(
+                    "datasource '{}' belongs to no tenant and no top-level server is set",
+                    datasource.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that gRPC endpoints are HTTP(S) URLs
+    pub fn check_grpc_urls(&self) -> Result<(), String> {
+        for tenant in self.tenants() {
+            let Some(grpc) = &tenant.server.grpc else {
+                continue;
+            };
+            let valid = grpc
+                .url
+                .parse::<hyper::Uri>()
+                .is_ok_and(|uri| matches!(uri.scheme_str(), Some("http" | "https")));
+            if !valid {
+                return Err(format!(
+                    "gRPC url '{}' of tenant '{}' isn't an http:// or https:// URL",
+                    grpc.url, tenant.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the datasources exposed to Grafana are configured
+    pub fn check_grafana_datasources(&self) -> Result<(), String> {
+        let exposed = self
+            .grafana
+            .iter()
+            .flat_map(|grafana| grafana.datasources.iter().flatten());
+        for name in exposed {
+            if !self.datasources.iter().any(|ds| &ds.name == name) {
+                return Err(format!(
+                    "Datasource '{}' exposed to Grafana isn't configured",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that endpoints running queries for other tools require a key,
+    /// unless only reachable from the host
+    pub fn check_endpoint_api_keys(&self) -> Result<(), String> {
+        if let Some(grafana) = &self.grafana {
+            check_endpoint_api_key("grafana", &grafana.address, grafana.api_key.as_ref())?;
+        }
+        if let Some(remote_read) = &self.remote_read {
+            check_endpoint_api_key(
+                "remote_read",
+                &remote_read.address,
+                remote_read.api_key.as_ref(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Check that the datasources of remote read metrics are configured
+    pub fn check_remote_read_metrics(&self) -> Result<(), String> {
+        let metrics = self
+            .remote_read
+            .iter()
+            .flat_map(|remote_read| remote_read.metrics.iter());
+        for metric in metrics {
+            if !self
+                .datasources
+                .iter()
+                .any(|ds| ds.name == metric.datasource)
+            {
+                return Err(format!(
+                    "Datasource '{}' of remote read metric '{}' isn't configured",
+                    metric.datasource, metric.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the datasources whose results are exported over OTLP are
+    /// configured
+    pub fn check_otlp_metrics_datasources(&self) -> Result<(), String> {
+        let exported = self
+            .otlp_metrics
+            .iter()
+            .flat_map(|otlp_metrics| otlp_metrics.datasources.iter().flatten());
+        for name in exported {
+            if !self.datasources.iter().any(|ds| &ds.name == name) {
+                return Err(format!(
+                    "Datasource '{}' exported over OTLP isn't configured",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that self metrics go to a valid table of a configured ClickHouse
+    /// datasource
+    pub fn check_self_metrics(&self) -> Result<(), String> {
+        let Some(self_metrics) = &self.self_metrics else {
+            return Ok(());
+        };
+        match self
+            .datasources
+            .iter()
+            .find(|ds| ds.name == self_metrics.datasource)
+        {
+            None => {
+                return Err(format!(
+                    "Datasource '{}' of self metrics isn't configured",
+                    self_metrics.datasource
+                ))
+            }
+            Some(ds) if ds.source_type != DataSourceType::Clickhouse => {
+                return Err(format!(
+                    "Self metrics can only be written to ClickHouse, not to datasource '{}'",
+                    self_metrics.datasource
+                ))
+            }
+            Some(_) => {}
+        }
+        let parts: Vec<_> = self_metrics.table.split('.').collect();
+        let is_identifier = |part: &&str| {
+            part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        if parts.len() > 2 || !parts.iter().all(is_identifier) {
+            return Err(format!(
+                "Invalid self metrics table '{}', expected table or database.table",
+                self_metrics.table
+            ));
+        }
+        Ok(())
+    }
+
+    /// Credential fields, which may hold secret references or encrypted values
+    pub fn credentials_mut(&mut self) -> Vec<&mut String> {
+        let mut credentials = Vec::new();
+        let servers = std::iter::once(&mut self.server)
+            .chain(self.tenants.iter_mut().map(|tenant| &mut tenant.server));
+        for server in servers {
+            credentials.push(&mut server.api_key);
+            if let Some(auth) = server.auth.as_mut() {
+                credentials.push(&mut auth.client_secret);
+            }
+        }
+        if let Some(hash_key) = self
+            .global_filters
+            .as_mut()
+            .and_then(|filters| filters.hash_key.as_mut())
+        {
+            credentials.push(hash_key);
+        }
+        for datasource in self.datasources.iter_mut() {
+            credentials.push(&mut datasource.username);
+            credentials.push(&mut datasource.password);
+        }
+        if let Some(api_key) = self
+            .grafana
+            .as_mut()
+            .and_then(|grafana| grafana.api_key.as_mut())
+        {
+            credentials.push(api_key);
+        }
+        if let Some(api_key) = self
+            .remote_read
+            .as_mut()
+            .and_then(|remote_read| remote_read.api_key.as_mut())
+        {
+            credentials.push(api_key);
+        }
+        if let Some(self_metrics) = self.self_metrics.as_mut() {
+            credentials.extend(self_metrics.username.as_mut());
+            credentials.extend(self_metrics.password.as_mut());
+        }
+        if let Some(result_export) = self.result_export.as_mut() {
+            credentials.extend(result_export.secret_access_key.as_mut());
+            credentials.extend(result_export.session_token.as_mut());
+        }
+        // Webhook URLs like Slack's carry their secret in the path
+        for webhook in self
+            .notifications
+            .iter_mut()
+            .flat_map(|notifications| notifications.webhooks.iter_mut())
+        {
+            credentials.push(&mut webhook.url);
+        }
+        credentials
+    }
+
+    /// Decrypt `enc:` credentials with the key of `secrets.encryption`,
+    /// relative to `config_dir`. The key is only read if a credential is
+    /// encrypted
+    pub fn decrypt_credentials(&mut self, config_dir: &Path) -> anyhow::Result<()> {
+        if !self
+            .credentials_mut()
+            .iter()
+            .any(|value| is_encrypted(value))
+        {
+            return Ok(());
+        }
+
+        let source = KeySource::from_config(self.secrets.as_ref(), config_dir);
+        let key = source.load()?;
+        for value in self.credentials_mut() {
+            if is_encrypted(value) {
+                *value = key.decrypt(value).map_err(|e| {
+                    e.context(format!("Failed to decrypt a credential with {}", source))
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject unknown keys in strict mode, only warn about them otherwise
+    fn check_unknown_keys(&self, unknown_keys: &[UnknownKey]) -> Result<(), config::ConfigError> {
+        if unknown_keys.is_empty() {
+            return Ok(());
+        }
+
+        if !self.strict.unwrap_or(true) {
+            for key in unknown_keys {
+                log::warn!("Ignoring {}", key);
+            }
+            return Ok(());
+        }
+
+        let keys: Vec<String> = unknown_keys.iter().map(|key| key.to_string()).collect();
+        Err(config::ConfigError::Message(format!(
+            "Unknown configuration keys (set 'strict: false' to ignore them):\n  {}",
+            keys.join("\n  ")
+        )))
+    }
+
+    /// Append datasources and filters of a fragment to this config
+    pub fn merge_fragment(&mut self, fragment: ConfigFragment) -> Result<(), String> {
+        for datasource in fragment.datasources {
+            if self.datasources.iter().any(|ds| ds.name == datasource.name) {
+                return Err(format!("duplicate datasource name '{}'", datasource.name));
+            }
+            self.datasources.push(datasource);
+        }
+
+        if let Some(filters) = fragment.global_filters {
+            match self.global_filters.as_mut() {
+                Some(global_filters) => global_filters.merge(filters),
+                None => self.global_filters = Some(filters),
+            }
+        }
+
+        for (name, preset) in fragment.filter_presets.unwrap_or_default() {
+            let presets = self.filter_presets.get_or_insert_with(HashMap::new);
+            if presets.contains_key(&name) {
+                return Err(format!("duplicate filter preset name '{}'", name));
+            }
+            presets.insert(name, preset);
+        }
+
+        Ok(())
+    }
+
+    /// Expand each datasource's `url` into its hosts, credentials and
+    /// database, rejecting fields set both ways
+    pub fn resolve_datasource_urls(&mut self) -> Result<(), String> {
+        for datasource in self.datasources.iter_mut() {
+            let Some(url) = datasource.url.take() else {
+                continue;
+            };
+            let error = |e: &str| format!("datasource '{}': {}", datasource.name, e);
+            let parsed = datasource_url::parse(&url).map_err(|e| error(&format!("url: {}", e)))?;
+            if parsed.source_type != datasource.source_type {
+                return Err(error(&format!(
+                    "url is for {} but source_type is {}",
+                    parsed.source_type, datasource.source_type
+                )));
+            }
+            if !datasource.hosts.is_empty() {
+                return Err(error("'url' and 'hosts' can't both be set"));
+            }
+            datasource.hosts = parsed.hosts;
+
+            let fields = [
+                ("username", &mut datasource.username, parsed.username),
+                ("password", &mut datasource.password, parsed.password),
+            ];
+            for (name, field, value) in fields {
+                if let Some(value) = value {
+                    if !field.is_empty() {
+                        return Err(error(&format!("'url' and '{}' can't both be set", name)));
+                    }
+                    *field = value;
+                }
+            }
+            if let Some(database) = parsed.database {
+                if datasource.database.is_some() {
+                    return Err(error("'url' and 'database' can't both be set"));
+                }
+                datasource.database = Some(database);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that credentials set as files aren't also set directly, and
+    /// that the files can be read
+    pub fn check_credential_files(&self) -> Result<(), String> {
+        for datasource in &self.datasources {
+            let error = |e: &str| format!("datasource '{}': {}", datasource.name, e);
+            let fields = [
+                ("username", &datasource.username, &datasource.username_file),
+                ("password", &datasource.password, &datasource.password_file),
+            ];
+            for (name, value, file) in fields {
+                if file.is_some() && !value.is_empty() {
+                    return Err(error(&format!(
+                        "'{}' and '{}_file' can't both be set",
+                        name, name
+                    )));
+                }
+            }
+            datasource
+                .credentials()
+                .map_err(|e| error(&format!("{:#}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// Check that transform programs, which run unsandboxed with the agent's
+    /// permissions, were opted into when building the agent, and that their
+    /// memory can be limited
+    pub fn check_transforms(&self) -> Result<(), String> {
+        let Some(datasource) = self
+            .datasources
+            .iter()
+            .find(|datasource| datasource.transform.is_some())
+        else {
+            return Ok(());
+        };
+        if !cfg!(feature = "transform-programs") {
+            return Err(format!(
+                "datasource '{}': transform programs run with the agent's permissions, \
+                 and are only available in builds with the 'transform-programs' feature",
+                datasource.name
+            ));
+        }
+        if !cfg!(unix) {
+            return Err(format!(
+                "datasource '{}': transform programs are only supported on Unix",
+                datasource.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Look up a preset defined in the config or compiled into the agent
+    fn filter_preset(&self, name: &str) -> Result<GlobalFilters, String> {
+        if name.starts_with(BUILTIN_PRESET_PREFIX) {
+            return builtin_preset(name)
+                .ok_or_else(|| format!("unknown built-in filter preset '{}'", name));
+        }
+        self.filter_presets
+            .as_ref()
+            .and_then(|presets| presets.get(name))
+            .cloned()
+            .ok_or_else(|| format!("unknown filter preset '{}'", name))
+    }
+
+    /// Expand the presets referenced by each datasource's `filter_presets` list
+    pub fn resolve_filter_presets(&mut self) -> Result<(), String> {
+        if let Some(name) = self
+            .filter_presets
+            .iter()
+            .flat_map(|presets| presets.keys())
+            .find(|name| name.starts_with(BUILTIN_PRESET_PREFIX))
+        {
+            return Err(format!(
+                "filter preset name '{}' uses the reserved '{}' prefix",
+                name, BUILTIN_PRESET_PREFIX
+            ));
+        }
+
+        let mut resolved = Vec::with_capacity(self.datasources.len());
+        for datasource in &self.datasources {
+            let mut preset_filters: Option<GlobalFilters> = None;
+            for name in datasource.filter_presets.iter().flatten() {
+                let preset = self
+                    .filter_preset(name)
+                    .map_err(|e| format!("datasource '{}': {}", datasource.name, e))?;
+                preset_filters
+                    .get_or_insert_with(GlobalFilters::default)
+                    .merge(preset);
+            }
+            resolved.push(preset_filters);
+        }
+
+        for (datasource, preset_filters) in self.datasources.iter_mut().zip(resolved) {
+            datasource.preset_filters = preset_filters;
+        }
+
+        Ok(())
+    }
+}
+
+/// Prefix of environment variables configuring the agent without a config file
+pub const ENV_PREFIX: &str = "TSIGHT_";
+
+/// Read a non-empty variable through `lookup`
+/// Check that the endpoint of `section` listening on `address` has an API
+/// key, unless it's a loopback address
+fn check_endpoint_api_key(
+    section: &str,
+    address: &str,
+    api_key: Option<&String>,
+) -> Result<(), String> {
+    let is_loopback = match address.parse::<std::net::SocketAddr>() {
+        Ok(address) => address.ip().is_loopback(),
+        Err(_) => address
+            .rsplit_once(':')
+            .is_some_and(|(host, _)| host.eq_ignore_ascii_case("localhost")),
+    };
+    if api_key.is_none() && !is_loopback {
+        return Err(format!(
+            "{}.api_key must be set to listen on {}, which isn't a loopback address",
+            section, address
+        ));
+    }
+    Ok(())
+}
+
+fn env_value(lookup: &impl Fn(&str) -> Option<String>, name: &str) -> Option<String> {
+    lookup(&format!("{}{}", ENV_PREFIX, name)).filter(|value| !value.is_empty())
+}
+
+/// Read a variable that must be set
+fn required_env_value(
+    lookup: &impl Fn(&str) -> Option<String>,
+    name: &str,
+) -> Result<String, config::ConfigError> {
+    env_value(lookup, name).ok_or_else(|| {
+        config::ConfigError::Message(format!(
+            "environment variable {}{} is required",
+            ENV_PREFIX, name
+        ))
+    })
+}
+
+/// Build the datasource `TSIGHT_DATASOURCE_<index>_*` variables describe
+fn datasource_from_env(
+    lookup: &impl Fn(&str) -> Option<String>,
+    index: usize,
+) -> Result<Option<DataSource>, config::ConfigError> {
+    let var = |field: &str| format!("DATASOURCE_{}_{}", index, field);
+
+    let Some(source_type) = env_value(lookup, &var("TYPE")) else {
+        return Ok(None);
+    };
+    let invalid = |field: &str, e: String| {
+        config::ConfigError::Message(format!(
+            "invalid value of {}{}: {}",
+            ENV_PREFIX,
+            var(field),
+            e
+        ))
+    };
+
+    // Hosts are only required without a connection string
+    let url = env_value(lookup, &var("URL"));
+    let hosts = match url {
+        Some(_) => env_value(lookup, &var("HOSTS")).unwrap_or_default(),
+        None => required_env_value(lookup, &var("HOSTS"))?,
+    };
+    let mut datasource = DataSource {
+        name: env_value(lookup, &var("NAME")).unwrap_or_else(|| format!("datasource_{}", index)),
+        source_type: source_type.parse().map_err(|e| invalid("TYPE", e))?,
+        hosts: hosts
+            .split(',')
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty())
+            .collect(),
+        username: env_value(lookup, &var("USERNAME")).unwrap_or_default(),
+        password: env_value(lookup, &var("PASSWORD")).unwrap_or_default(),
+        username_file: env_value(lookup, &var("USERNAME_FILE")).map(PathBuf::from),
+        password_file: env_value(lookup, &var("PASSWORD_FILE")).map(PathBuf::from),
+        url,
+        timeout: default_timeout(),
+        ..Default::default()
+    };
+    if let Some(timeout) = env_value(lookup, &var("TIMEOUT")) {
+        datasource.timeout = timeout
+            .parse()
+            .map_err(|e: std::num::ParseIntError| invalid("TIMEOUT", e.to_string()))?;
+    }
+
+    Ok(Some(datasource))
+}
+
+impl Config {
+    /// Build the configuration from `TSIGHT_*` environment variables
+    ///
+    /// Returns `None` when `TSIGHT_SERVER_URL` isn't set
+    pub fn from_env() -> Result<Option<Self>, config::ConfigError> {
+        Self::from_env_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Build the configuration from variables provided by `lookup`
+    pub fn from_env_lookup(
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<Self>, config::ConfigError> {
+        let Some(server_url) = env_value(&lookup, "SERVER_URL") else {
+            return Ok(None);
+        };
+
+        let mut config = Config {
+            server: ServerConfig {
+                api_key: env_value(&lookup, "API_KEY").unwrap_or_default(),
+                server_url,
+                path_prefix: env_value(&lookup, "SERVER_PATH_PREFIX"),
+                ..Default::default()
+            },
+            listener: env_value(&lookup, "LISTEN_ADDRESS")
+                .map(|address| ListenerConfig { address }),
+            audit: env_value(&lookup, "AUDIT_LOG")
+                .map(|path| AuditConfig::new(PathBuf::from(path))),
+            ..Default::default()
+        };
+        if let Some(format) = env_value(&lookup, "LOG_FORMAT") {
+            let format = format.parse().map_err(|e| {
+                config::ConfigError::Message(format!(
+                    "invalid value of {}LOG_FORMAT: {}",
+                    ENV_PREFIX, e
+                ))
+            })?;
+            config.logging.get_or_insert_with(Default::default).format = format;
+        }
+        if let Some(path) = env_value(&lookup, "LOG_FILE") {
+            config.logging.get_or_insert_with(Default::default).file =
+                Some(LogFileConfig::new(PathBuf::from(path)));
+        }
+
+        let mut index = 0;
+        while let Some(datasource) = datasource_from_env(&lookup, index)? {
+            if config
+                .datasources
+                .iter()
+                .any(|ds| ds.name == datasource.name)
+            {
+                return Err(config::ConfigError::Message(format!(
+                    "duplicate datasource name '{}'",
+                    datasource.name
+                )));
+            }
+            config.datasources.push(datasource);
+            index += 1;
+        }
+        config
+            .resolve_datasource_urls()
+            .map_err(config::ConfigError::Message)?;
+        config
+            .check_credential_files()
+            .map_err(config::ConfigError::Message)?;
+
+        Ok(Some(config))
+    }
+}
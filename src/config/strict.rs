@@ -0,0 +1,157 @@
+//! Detection of unknown configuration keys
+//!
+//! Keys the configuration structs don't define are collected while
+//! deserializing and mapped back to their line in the YAML file, so a typo
+//! like `sql_filter_exclude` doesn't silently disable filtering.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+
+/// Key present in a config file but unknown to the agent
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownKey {
+    pub file: PathBuf,
+    /// Dotted path of the key, e.g. `datasources.0.sql_filter_exclude`
+    pub path: String,
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(
+                f,
+                "{}:{}: unknown key '{}'",
+                self.file.display(),
+                line,
+                self.path
+            ),
+            None => write!(f, "{}: unknown key '{}'", self.file.display(), self.path),
+        }
+    }
+}
+
+/// Deserialize `settings`, collecting the paths of keys that were ignored
+pub fn deserialize_tracking<T: serde::de::DeserializeOwned>(
+    settings: config::Config,
+) -> Result<(T, Vec<String>), config::ConfigError> {
+    let mut ignored = Vec::new();
+    let value = serde_ignored::deserialize(settings, |path| {
+        let mut segments = Vec::new();
+        collect_segments(&path, &mut segments);
+        ignored.push(segments.join("."));
+    })?;
+    Ok((value, ignored))
+}
+
+/// Flatten a serde_ignored path into key and index segments
+fn collect_segments(path: &serde_ignored::Path, segments: &mut Vec<String>) {
+    match path {
+        serde_ignored::Path::Root => {}
+        serde_ignored::Path::Seq { parent, index } => {
+            collect_segments(parent, segments);
+            segments.push(index.to_string());
+        }
+        serde_ignored::Path::Map { parent, key } => {
+            collect_segments(parent, segments);
+            segments.push(key.clone());
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => collect_segments(parent, segments),
+    }
+}
+
+/// Resolve the line of each ignored key path in the YAML file at `file`
+pub fn locate_unknown_keys(file: &Path, paths: Vec<String>) -> Vec<UnknownKey> {
+    let lines = std::fs::read_to_string(file)
+        .ok()
+        .map(|source| key_lines(&source))
+        .unwrap_or_default();
+
+    paths
+        .into_iter()
+        .map(|path| UnknownKey {
+            file: file.to_path_buf(),
+            line: lines.get(&path).copied(),
+            path,
+        })
+        .collect()
+}
+
+/// Position inside a YAML collection while walking parser events
+enum Frame {
+    Mapping { key: Option<String> },
+    Sequence { index: usize },
+}
+
+/// Records the line of every mapping key by its dotted path
+#[derive(Default)]
+struct KeyLineCollector {
+    stack: Vec<Frame>,
+    lines: HashMap<String, usize>,
+}
+
+impl KeyLineCollector {
+    /// Dotted path of the node currently being visited
+    fn path(&self) -> Vec<String> {
+        self.stack
+            .iter()
+            .filter_map(|frame| match frame {
+                Frame::Mapping { key } => key.clone(),
+                Frame::Sequence { index } => Some(index.to_string()),
+            })
+            .collect()
+    }
+
+    /// Move past a completed value in the enclosing collection
+    fn value_done(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Mapping { key }) => *key = None,
+            Some(Frame::Sequence { index }) => *index += 1,
+            None => {}
+        }
+    }
+
+    /// Handle a scalar, which is either a mapping key or a value
+    fn scalar(&mut self, value: String, mark: Marker) {
+        if let Some(Frame::Mapping { key: None }) = self.stack.last() {
+            let mut path = self.path();
+            path.push(value.clone());
+            self.lines.entry(path.join(".")).or_insert(mark.line());
+            if let Some(Frame::Mapping { key }) = self.stack.last_mut() {
+                *key = Some(value);
+            }
+        } else {
+            self.value_done();
+        }
+    }
+}
+
+impl MarkedEventReceiver for KeyLineCollector {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::MappingStart(..) => self.stack.push(Frame::Mapping { key: None }),
+            Event::SequenceStart(..) => self.stack.push(Frame::Sequence { index: 0 }),
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.value_done();
+            }
+            Event::Scalar(value, ..) => self.scalar(value, mark),
+            Event::Alias(_) => self.value_done(),
+            _ => {}
+        }
+    }
+}
+
+/// Lines of all mapping keys in a YAML document, keyed by dotted path
+fn key_lines(source: &str) -> HashMap<String, usize> {
+    let mut collector = KeyLineCollector::default();
+    let mut parser = Parser::new_from_str(source);
+    // A malformed file is reported by the config parser, lines are best effort
+    let _ = parser.load(&mut collector, false);
+    collector.lines
+}
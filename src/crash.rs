@@ -0,0 +1,140 @@
+//! Crash reporting for the agent's long-running loops
+//!
+//! The panic hook logs every panic with its stack trace. Loops run through
+//! [`supervise`] are restarted after a panic instead of silently ending
+//! their task, and each crash is reported to the server.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::client::ServerApi;
+
+/// Time before a crashed loop is restarted
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+thread_local! {
+    /// Panic last caught by the hook on this thread, taken by the loop it crashed
+    static LAST_PANIC: RefCell<Option<Panic>> = const { RefCell::new(None) };
+}
+
+/// Panic captured by the hook
+struct Panic {
+    message: String,
+    location: Option<String>,
+    backtrace: String,
+}
+
+/// Crash of a supervised loop reported to the server
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CrashReport {
+    /// Name of the loop that crashed, e.g. `job_agent`
+    pub component: String,
+    pub message: String,
+    /// Source location of the panic, e.g. `src/agent/mod.rs:42:5`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Stack trace, empty when the panic hook isn't installed
+    pub backtrace: String,
+    /// Version of the agent
+    pub version: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Log every panic with its stack trace, regardless of `RUST_BACKTRACE`
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info: &PanicHookInfo| {
+        let panic = Panic {
+            message: panic_message(info.payload()),
+            location: info.location().map(|location| location.to_string()),
+            backtrace: Backtrace::force_capture().to_string(),
+        };
+        log::error!(
+            "Panic at {}: {}\n{}",
+            panic.location.as_deref().unwrap_or("unknown location"),
+            panic.message,
+            panic.backtrace
+        );
+        LAST_PANIC.with(|last| *last.borrow_mut() = Some(panic));
+    }));
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+/// Future completing with the panic of the future it wraps
+struct CatchPanic<F> {
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future<Output = ()>> Future for CatchPanic<F> {
+    type Output = Result<(), Panic>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.future.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(payload) => {
+                let panic = LAST_PANIC.with(|last| last.borrow_mut().take());
+                Poll::Ready(Err(panic.unwrap_or_else(|| Panic {
+                    message: panic_message(payload.as_ref()),
+                    location: None,
+                    backtrace: String::new(),
+                })))
+            }
+        }
+    }
+}
+
+/// Run the loop created by `run` until it returns, restarting it after each
+/// panic and reporting the crash to the server
+pub async fn supervise<F, Fut>(component: &str, server_client: Arc<dyn ServerApi>, run: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    loop {
+        let panic = match (CatchPanic {
+            future: Box::pin(run()),
+        })
+        .await
+        {
+            Ok(()) => return,
+            Err(panic) => panic,
+        };
+
+        log::error!(
+            "{} crashed: {}, restarting in {:?}",
+            component,
+            panic.message,
+            RESTART_DELAY
+        );
+        let report = CrashReport {
+            component: component.to_string(),
+            message: panic.message,
+            location: panic.location,
+            backtrace: panic.backtrace,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            occurred_at: Utc::now(),
+        };
+        if let Err(e) = server_client.report_crash(&report).await {
+            log::warn!("Failed to report crash of {}: {:#}", component, e);
+        }
+        tokio::time::sleep(RESTART_DELAY).await;
+    }
+}
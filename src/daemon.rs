@@ -0,0 +1,163 @@
+//! Running the agent as a classic Unix daemon
+//!
+//! `--daemon` detaches the agent from the terminal with a double fork, for
+//! hosts without a service manager, and `--pid-file` records its PID under
+//! an exclusive lock, so a second agent started with the same PID file
+//! refuses to run instead of processing the same queues twice.
+
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// PID file locked for as long as the agent runs, removed when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidFile {
+    /// Open or create the PID file at `path` and lock it, failing if another
+    /// agent holds the lock
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Failed to open PID file {}", path.display()))?;
+        let locked = try_lock(&file)
+            .with_context(|| format!("Failed to lock PID file {}", path.display()))?;
+        if !locked {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            let pid = match pid.trim() {
+                "" => String::new(),
+                pid => format!(" (PID {})", pid),
+            };
+            bail!(
+                "Another agent is already running with PID file {}{}",
+                path.display(),
+                pid
+            );
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    /// Record the PID of the current process, once it's done daemonizing
+    pub fn write_pid(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        writeln!(self.file, "{}", std::process::id())?;
+        self.file
+            .sync_all()
+            .with_context(|| format!("Failed to write PID file {}", self.path.display()))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::warn!("Failed to remove PID file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Take an exclusive lock of `file`, returning false if another open file
+/// holds it. The lock is released once every descriptor of the file closed,
+/// including those inherited by forked children
+#[cfg(unix)]
+fn try_lock(file: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the descriptor stays open for the duration of the call
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    if error.kind() == io::ErrorKind::WouldBlock {
+        return Ok(false);
+    }
+    Err(error)
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> io::Result<bool> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "PID files are only supported on Unix",
+    ))
+}
+
+/// Detach from the terminal: fork twice so the agent is neither a session
+/// leader nor a child of the shell, read stdin from `/dev/null` and append
+/// stdout and stderr to `log_path`, or discard them without one
+///
+/// The working directory is kept so relative paths of the configuration
+/// still resolve. Forking is only safe before any other thread started, so
+/// this must run before the tokio runtime is built
+#[cfg(unix)]
+pub fn daemonize(log_path: Option<&Path>) -> Result<()> {
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .context("Failed to open /dev/null")?;
+    // Opened before forking, so a bad path is reported on the terminal
+    let output = match log_path {
+        Some(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?,
+        None => null.try_clone()?,
+    };
+
+    fork_and_exit_parent()?;
+    // SAFETY: setsid has no preconditions, the child isn't a group leader
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error()).context("Failed to start a new session");
+    }
+    fork_and_exit_parent()?;
+
+    redirect(&null, libc::STDIN_FILENO)?;
+    redirect(&output, libc::STDOUT_FILENO)?;
+    redirect(&output, libc::STDERR_FILENO)
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_log_path: Option<&Path>) -> Result<()> {
+    bail!("--daemon is only supported on Unix, install a Windows service with `service install`")
+}
+
+#[cfg(unix)]
+fn fork_and_exit_parent() -> Result<()> {
+    // SAFETY: no other thread runs yet, so the child starts from a consistent state
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()).context("Failed to fork"),
+        0 => Ok(()),
+        // Leave without running destructors or flushing buffers the child shares
+        // SAFETY: _exit only ends the process
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+#[cfg(unix)]
+fn redirect(file: &File, descriptor: i32) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: both descriptors are valid, dup2 replaces the standard stream
+    if unsafe { libc::dup2(file.as_raw_fd(), descriptor) } == -1 {
+        return Err(io::Error::last_os_error()).context("Failed to redirect standard streams");
+    }
+    Ok(())
+}
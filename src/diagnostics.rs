@@ -0,0 +1,143 @@
+//! Self-diagnostics run when the server enqueues a diagnostics job
+//!
+//! The report summarizes the agent's configuration without credentials,
+//! checks the server and every datasource like the readiness checks do, and
+//! times each check, so support can debug an agent without shell access.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Instant;
+use tokio::task::JoinSet;
+
+use crate::client::ServerApi;
+use crate::config::GlobalFilters;
+use crate::health::{check_connection, CHECK_OK};
+use crate::models::DataSource;
+
+/// Self-check of the agent submitted as the result of a diagnostics job
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DiagnosticsReport {
+    /// Version of the agent
+    pub version: String,
+    /// Operating system and architecture the agent runs on, e.g. `linux/x86_64`
+    pub platform: String,
+    pub generated_at: DateTime<Utc>,
+    pub config: ConfigSummary,
+    pub server: CheckResult,
+    /// Connectivity of each datasource by name
+    pub datasources: BTreeMap<String, CheckResult>,
+}
+
+/// Outcome of a check
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CheckResult {
+    /// `ok`, or why the check failed or was skipped
+    pub status: String,
+    /// Time the check took, unset when it was skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+}
+
+/// Configuration of the agent without hosts or credentials
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ConfigSummary {
+    pub datasources: Vec<DatasourceSummary>,
+    /// Whether global filter rules are configured
+    pub global_filters: bool,
+}
+
+/// Settings of a datasource relevant to its tasks
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DatasourceSummary {
+    pub name: String,
+    pub source_type: String,
+    pub hosts: usize,
+    pub enabled: bool,
+    pub timeout: u64,
+    /// Whether filter rules apply to the datasource's results
+    pub filters: bool,
+    /// Number of tables with row filters
+    pub row_filters: usize,
+    pub discovery: bool,
+}
+
+impl DatasourceSummary {
+    fn of(datasource: &DataSource, global_filters: Option<&GlobalFilters>) -> Self {
+        Self {
+            name: datasource.name.clone(),
+            source_type: datasource.source_type.to_string(),
+            hosts: datasource.hosts.len(),
+            enabled: datasource.enabled,
+            timeout: datasource.timeout,
+            filters: datasource.effective_filters(global_filters).is_some(),
+            row_filters: datasource.row_filters.as_ref().map_or(0, |rows| rows.len()),
+            discovery: datasource.discovery.enabled,
+        }
+    }
+}
+
+/// Run all checks concurrently and build the report
+pub async fn run(
+    server_client: &dyn ServerApi,
+    datasources: &[DataSource],
+    global_filters: Option<&GlobalFilters>,
+) -> DiagnosticsReport {
+    let mut connections = JoinSet::new();
+    let mut datasource_results = BTreeMap::new();
+    for datasource in datasources {
+        match datasource.unavailable_reason(Utc::now()) {
+            Some(reason) => {
+                let result = CheckResult {
+                    status: reason,
+                    latency_ms: None,
+                };
+                datasource_results.insert(datasource.name.clone(), result);
+            }
+            None => {
+                let datasource = datasource.clone();
+                connections.spawn(async move {
+                    let started = Instant::now();
+                    let status = check_connection(&datasource).await;
+                    let result = CheckResult {
+                        status,
+                        latency_ms: Some(started.elapsed().as_millis() as u64),
+                    };
+                    (datasource.name, result)
+                });
+            }
+        }
+    }
+    let started = Instant::now();
+    let server = CheckResult {
+        status: match server_client.check_reachable().await {
+            Ok(()) => CHECK_OK.to_string(),
+            Err(e) => format!("{:#}", e),
+        },
+        latency_ms: Some(started.elapsed().as_millis() as u64),
+    };
+
+    while let Some(joined) = connections.join_next().await {
+        match joined {
+            Ok((name, result)) => {
+                datasource_results.insert(name, result);
+            }
+            Err(e) => log::warn!("Datasource diagnostics check failed: {}", e),
+        }
+    }
+
+    DiagnosticsReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        platform: format!("{}/{}", std::env::consts::OS, std::env::consts::ARCH),
+        generated_at: Utc::now(),
+        config: ConfigSummary {
+            datasources: datasources
+                .iter()
+                .map(|datasource| DatasourceSummary::of(datasource, global_filters))
+                .collect(),
+            global_filters: global_filters.is_some(),
+        },
+        server,
+        datasources: datasource_results,
+    }
+}
@@ -0,0 +1,181 @@
+//! Downsampling and gap filling of time series records
+//!
+//! Tasks can ask for their records to be bucketed to a resolution, for
+//! buckets without points to be filled and for series to be capped to a
+//! number of points, so the agent submits what a chart draws rather than
+//! every row of the query. Each series, as told apart by its labels, is
+//! downsampled on its own, with buckets shared by all series.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use thiserror::Error;
+
+use crate::models::Record;
+
+/// Buckets a series may be filled to
+const MAX_FILLED_POINTS: i64 = 100_000;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum DownsampleError {
+    #[error("Downsampling needs bucket_ms or max_points")]
+    NoResolution,
+    #[error("bucket_ms and max_points must be positive")]
+    NotPositive,
+    #[error("Filling gaps would make {0} points, more than {MAX_FILLED_POINTS}")]
+    TooManyPoints(i64),
+}
+
+/// How the values of the points of a bucket are combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregate {
+    #[default]
+    Avg,
+    Sum,
+    Min,
+    Max,
+    /// Value of the earliest point
+    First,
+    /// Value of the latest point
+    Last,
+}
+
+/// How buckets without points are filled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fill {
+    /// Leave them out
+    #[default]
+    None,
+    /// Report every value of the series as zero
+    Zero,
+    /// Report every value of the series as `null`
+    Null,
+    /// Repeat the values of the previous bucket of the series
+    Previous,
+}
+
+/// Downsampling of a task's records, as set by its `ts_mapping`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Downsample {
+    /// Width of buckets in milliseconds, aligned to multiples of it
+    #[serde(default)]
+    pub bucket_ms: Option<i64>,
+    #[serde(default)]
+    pub aggregate: Aggregate,
+    #[serde(default)]
+    pub fill: Fill,
+    /// Points per series at most, widening buckets to a multiple of
+    /// `bucket_ms` as needed
+    #[serde(default)]
+    pub max_points: Option<i64>,
+}
+
+impl Downsample {
+    /// Bucket `records`, each one at the start of its bucket, ordered by
+    /// series and time
+    pub fn apply(&self, records: Vec<Record>) -> Result<Vec<Record>, DownsampleError> {
+        if self.bucket_ms.is_none() && self.max_points.is_none() {
+            return Err(DownsampleError::NoResolution);
+        }
+        if self.bucket_ms.is_some_and(|ms| ms <= 0) || self.max_points.is_some_and(|n| n <= 0) {
+            return Err(DownsampleError::NotPositive);
+        }
+        let (Some(first), Some(last)) = (
+            records.iter().map(|record| record.t).min(),
+            records.iter().map(|record| record.t).max(),
+        ) else {
+            return Ok(records);
+        };
+
+        // Buckets start at a multiple of `bucket_ms` and are widened to fit
+        // the whole range in `max_points`
+        let step = self.bucket_ms.unwrap_or(1);
+        let origin = first - first.rem_euclid(step);
+        let mut width = step;
+        if let Some(max_points) = self.max_points {
+            let needed = (last - origin) / max_points + 1;
+            width = width.max((needed + step - 1) / step * step);
+        }
+        let buckets = (last - origin) / width + 1;
+        if self.fill != Fill::None && buckets > MAX_FILLED_POINTS {
+            return Err(DownsampleError::TooManyPoints(buckets));
+        }
+
+        let mut series: BTreeMap<BTreeMap<String, String>, BTreeMap<i64, Vec<Record>>> =
+            BTreeMap::new();
+        for record in records {
+            let bucket = (record.t - origin) / width;
+            series
+                .entry(record.labels.clone())
+                .or_default()
+                .entry(bucket)
+                .or_default()
+                .push(record);
+        }
+
+        let mut downsampled = Vec::new();
+        for (labels, points) in series {
+            let names: Vec<String> = points
+                .values()
+                .flatten()
+                .flat_map(|record| record.values.keys().cloned())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+            let filled: Vec<i64> = match self.fill {
+                Fill::None => points.keys().copied().collect(),
+                _ => (0..buckets).collect(),
+            };
+            let mut previous: Option<BTreeMap<String, f64>> = None;
+            for bucket in filled {
+                let values = match points.get(&bucket) {
+                    Some(records) => self.combine(records),
+                    None => match self.fill {
+                        Fill::None => continue,
+                        Fill::Zero => names.iter().map(|name| (name.clone(), 0.0)).collect(),
+                        // Serialized as `null`, as JSON has no NaN
+                        Fill::Null => names.iter().map(|name| (name.clone(), f64::NAN)).collect(),
+                        Fill::Previous => match &previous {
+                            Some(values) => values.clone(),
+                            None => continue,
+                        },
+                    },
+                };
+                previous = Some(values.clone());
+                downsampled.push(Record {
+                    t: origin + bucket * width,
+                    values,
+                    labels: labels.clone(),
+                });
+            }
+        }
+        Ok(downsampled)
+    }
+
+    /// Values of a bucket from the values of its points
+    fn combine(&self, records: &[Record]) -> BTreeMap<String, f64> {
+        let mut records: Vec<&Record> = records.iter().collect();
+        records.sort_by_key(|record| record.t);
+        let mut samples: BTreeMap<&str, Vec<f64>> = BTreeMap::new();
+        for record in records {
+            for (name, value) in &record.values {
+                samples.entry(name).or_default().push(*value);
+            }
+        }
+        samples
+            .into_iter()
+            .map(|(name, values)| {
+                let value = match self.aggregate {
+                    Aggregate::Avg => values.iter().sum::<f64>() / values.len() as f64,
+                    Aggregate::Sum => values.iter().sum(),
+                    Aggregate::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+                    Aggregate::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                    Aggregate::First => values[0],
+                    Aggregate::Last => values[values.len() - 1],
+                };
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,264 @@
+//! Categories of the errors agents run into
+//!
+//! Errors are classified by their type rather than their message, counted
+//! per category, and periodically reported to the server as a summary
+//! instead of one message per failure.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::agent::QueryTimedOut;
+use crate::client::{BackoffRequested, ServerApi};
+use crate::executors::base::QueryError;
+use crate::policy::PolicyError;
+use crate::signing::SignatureError;
+
+/// Interval of error summaries when none is configured
+pub const DEFAULT_REPORT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Category of an error
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// The server had no task or job to hand out
+    NoWork,
+    /// Every datasource is disabled or in a maintenance window
+    NoDatasources,
+    /// The server asked the agent to back off
+    Backoff,
+    /// A request to the server failed
+    Server,
+    /// A task named a datasource the agent doesn't know
+    UnknownDatasource,
+    /// A task's datasource is disabled or in a maintenance window
+    DatasourceUnavailable,
+    /// A query was rejected by the datasource's query policy
+    Policy,
+    /// The datasource couldn't be reached
+    Connection,
+    /// The datasource failed to run a query
+    Query,
+    /// A task or job ran longer than its timeout
+    Timeout,
+    Other,
+}
+
+impl ErrorCategory {
+    pub const ALL: [ErrorCategory; 11] = [
+        ErrorCategory::NoWork,
+        ErrorCategory::NoDatasources,
+        ErrorCategory::Backoff,
+        ErrorCategory::Server,
+        ErrorCategory::UnknownDatasource,
+        ErrorCategory::DatasourceUnavailable,
+        ErrorCategory::Policy,
+        ErrorCategory::Connection,
+        ErrorCategory::Query,
+        ErrorCategory::Timeout,
+        ErrorCategory::Other,
+    ];
+
+    /// Classify an error by the types in its chain
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(categorized) = cause.downcast_ref::<CategorizedError>() {
+                return categorized.category;
+            }
+            if let Some(query_error) = cause.downcast_ref::<QueryError>() {
+                return Self::of_query(query_error);
+            }
+            if cause.is::<NoWorkAvailable>() {
+                return Self::NoWork;
+            }
+            if cause.is::<NoDatasourcesAvailable>() {
+                return Self::NoDatasources;
+            }
+            if cause.is::<BackoffRequested>() {
+                return Self::Backoff;
+            }
+            if cause.is::<QueryTimedOut>() {
+                return Self::Timeout;
+            }
+            if cause.is::<PolicyError>() || cause.is::<SignatureError>() {
+                return Self::Policy;
+            }
+            if cause.is::<reqwest::Error>() {
+                return Self::Server;
+            }
+        }
+        Self::Other
+    }
+
+    /// Classify an error of an executor
+    pub fn of_query(error: &QueryError) -> Self {
+        match error {
+            QueryError::ConnectionError(_) => Self::Connection,
+            QueryError::ExecutionError(_) => Self::Query,
+        }
+    }
+
+    /// Whether the error is expected while there's nothing to do, so it's
+    /// neither counted as a failure nor worth more than a warning
+    pub fn is_idle(self) -> bool {
+        matches!(self, Self::NoWork | Self::NoDatasources)
+    }
+
+    /// Name of the category, as used in metrics and summaries
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::NoWork => "no_work",
+            Self::NoDatasources => "no_datasources",
+            Self::Backoff => "backoff",
+            Self::Server => "server",
+            Self::UnknownDatasource => "unknown_datasource",
+            Self::DatasourceUnavailable => "datasource_unavailable",
+            Self::Policy => "policy",
+            Self::Connection => "connection",
+            Self::Query => "query",
+            Self::Timeout => "timeout",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error whose message was rewritten, e.g. prefixed or redacted, keeping
+/// the category of the error it replaced
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct CategorizedError {
+    pub category: ErrorCategory,
+    pub message: String,
+}
+
+impl CategorizedError {
+    pub fn new(category: ErrorCategory, message: impl Into<String>) -> Self {
+        Self {
+            category,
+            message: message.into(),
+        }
+    }
+}
+
+/// The server has no task or job to hand out
+#[derive(Debug, thiserror::Error)]
+#[error("No {0} available")]
+pub struct NoWorkAvailable(pub &'static str);
+
+impl NoWorkAvailable {
+    pub const TASKS: Self = Self("tasks");
+    pub const JOBS: Self = Self("jobs");
+}
+
+/// Error returned instead of acquiring work while every datasource is
+/// disabled or in a maintenance window
+#[derive(Debug, thiserror::Error)]
+#[error("No datasources available: all are disabled or in a maintenance window")]
+pub struct NoDatasourcesAvailable;
+
+/// Errors of one category within a summary
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CategoryErrors {
+    pub count: u64,
+    /// Message of the most recent error, redacted like task errors
+    pub last_error: String,
+}
+
+/// Errors by category since the previous summary
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ErrorSummary {
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub errors: BTreeMap<ErrorCategory, CategoryErrors>,
+}
+
+/// Errors collected for the next summary
+pub struct ErrorTracker {
+    since: Mutex<DateTime<Utc>>,
+    errors: Mutex<BTreeMap<ErrorCategory, CategoryErrors>>,
+}
+
+static ERROR_TRACKER: LazyLock<ErrorTracker> = LazyLock::new(ErrorTracker::new);
+
+/// Get the process-wide error tracker
+pub fn error_tracker() -> &'static ErrorTracker {
+    &ERROR_TRACKER
+}
+
+impl ErrorTracker {
+    pub fn new() -> Self {
+        Self {
+            since: Mutex::new(Utc::now()),
+            errors: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Count an error in its category
+    pub fn record(&self, category: ErrorCategory, message: &str) {
+        let mut errors = self.errors.lock().unwrap();
+        let entry = errors.entry(category).or_insert_with(|| CategoryErrors {
+            count: 0,
+            last_error: String::new(),
+        });
+        entry.count += 1;
+        entry.last_error = message.to_string();
+    }
+
+    /// Take the errors collected since the previous summary, unless there
+    /// were none
+    pub fn take_summary(&self) -> Option<ErrorSummary> {
+        let errors = std::mem::take(&mut *self.errors.lock().unwrap());
+        let until = Utc::now();
+        let since = std::mem::replace(&mut *self.since.lock().unwrap(), until);
+        (!errors.is_empty()).then_some(ErrorSummary {
+            since,
+            until,
+            errors,
+        })
+    }
+
+    /// Report the errors collected since the previous summary, if any
+    pub async fn report(&self, server_client: &dyn ServerApi) -> Result<()> {
+        match self.take_summary() {
+            Some(summary) => server_client.report_errors(&summary).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for ErrorTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Report the summary of the process-wide errors every `interval`
+///
+/// Returns `None` when `interval` is zero
+pub fn spawn_error_reporting(
+    server_client: Arc<dyn ServerApi>,
+    interval: Duration,
+) -> Option<JoinHandle<()>> {
+    if interval.is_zero() {
+        return None;
+    }
+    Some(tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = error_tracker().report(server_client.as_ref()).await {
+                log::warn!("Failed to report error summary: {:#}", e);
+            }
+        }
+    }))
+}
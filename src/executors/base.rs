@@ -1,6 +1,11 @@
+use crate::filters::FilterStats;
 use anyhow::Result;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
 use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
 
 #[derive(Error, Debug)]
 pub enum QueryError {
@@ -10,13 +15,143 @@ pub enum QueryError {
     ExecutionError(String),
 }
 
+/// Timing and resource usage of a task or job, submitted with its results
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionStats {
+    /// Milliseconds from the server queueing the task until its queries
+    /// started, when the server reports when it was queued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_wait_ms: Option<u64>,
+    /// Milliseconds the queries ran
+    pub execution_ms: u64,
+    /// Rows the datasource returned, before filters
+    pub rows_returned: u64,
+    /// Rows left out by filters, sampling and privacy rules
+    pub rows_filtered: u64,
+    /// Rows the datasource read to answer the queries
+    pub rows_read: u64,
+    /// Bytes the datasource read to answer the queries
+    pub bytes_read: u64,
+}
+
+/// Limits of schema discovery
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryOptions {
+    /// Time after which a table is recorded with partial information
+    pub table_timeout: Option<Duration>,
+    /// Tables recorded with partial information right away, as `database.table`
+    pub skip_tables: HashSet<String>,
+    /// Number of the most frequent values reported per string column
+    pub sample_values: Option<usize>,
+    /// Receives each table's schema as soon as it's discovered
+    pub progress: Option<UnboundedSender<crate::executors::clickhouse_source::TableSchema>>,
+}
+
+/// Privileges of the datasource's user relevant to the read-only check
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserAccess {
+    /// Whether the user's settings forbid writes, such as ClickHouse's
+    /// `readonly`, making its grants irrelevant
+    pub readonly: bool,
+    /// Grant statements of the user, e.g. `GRANT SELECT ON db.* TO agent`
+    pub grants: Vec<String>,
+}
+
 #[async_trait]
 pub trait QueryExecutor: Send + Sync {
-    async fn execute_ts(&self, query: &str) -> Result<Vec<crate::models::Record>, QueryError>;
-    async fn execute_job(&self, query: &str) -> Result<Vec<crate::models::JobType>, QueryError>;
+    async fn execute_ts(&self, query: &str) -> Result<Vec<crate::models::Record>, QueryError> {
+        self.execute_ts_with(query, &crate::timeseries::TsMapping::default())
+            .await
+            .map(|(records, _)| records)
+    }
+    /// Execute a task query, mapping its rows to records with `mapping` and
+    /// also returning how many rows had `NULL`s
+    async fn execute_ts_with(
+        &self,
+        query: &str,
+        mapping: &crate::timeseries::TsMapping,
+    ) -> Result<(Vec<crate::models::Record>, crate::timeseries::NullStats), QueryError>;
+    async fn execute_job(&self, query: &str) -> Result<Vec<crate::models::JobType>, QueryError> {
+        self.execute_job_with_stats(query)
+            .await
+            .map(|(rows, _)| rows)
+    }
+    /// Execute a job query, also returning what the filters dropped or rewrote
+    async fn execute_job_with_stats(
+        &self,
+        query: &str,
+    ) -> Result<(Vec<crate::models::JobType>, FilterStats), QueryError> {
+        self.execute_job_with_columns(query)
+            .await
+            .map(|(rows, stats, _)| (rows, stats))
+    }
+    /// Execute a job query, also returning what the filters dropped or
+    /// rewrote and the columns of the results
+    async fn execute_job_with_columns(
+        &self,
+        query: &str,
+    ) -> Result<
+        (
+            Vec<crate::models::JobType>,
+            FilterStats,
+            Vec<crate::executors::clickhouse_source::ResultColumn>,
+        ),
+        QueryError,
+    >;
+    /// Execute a job query like `execute_job_with_columns`, spilling rows to
+    /// disk once they exceed the executor's memory budget
+    async fn execute_job_rows(
+        &self,
+        query: &str,
+    ) -> Result<
+        (
+            crate::spill::JobRows,
+            FilterStats,
+            Vec<crate::executors::clickhouse_source::ResultColumn>,
+        ),
+        QueryError,
+    > {
+        self.execute_job_with_columns(query)
+            .await
+            .map(|(rows, stats, columns)| (rows.into(), stats, columns))
+    }
+    /// Execute a statement returning no rows, such as `SET`
+    async fn execute_statement(&self, query: &str) -> Result<(), QueryError>;
+    /// Run later queries on the session `session_id`, so settings changed by
+    /// a statement apply to the queries after it
+    fn set_session(&mut self, session_id: Option<String>);
     async fn connect(&mut self) -> Result<(), QueryError>;
     async fn discover_schemas(
         &self,
+    ) -> Result<Vec<crate::executors::clickhouse_source::TableSchema>, QueryError> {
+        self.discover_schemas_with(&DiscoveryOptions::default())
+            .await
+    }
+    /// Discover schemas within the given limits
+    async fn discover_schemas_with(
+        &self,
+        options: &DiscoveryOptions,
     ) -> Result<Vec<crate::executors::clickhouse_source::TableSchema>, QueryError>;
-    fn filter_job_results(&self, rows: Vec<crate::models::JobType>) -> Vec<crate::models::JobType>;
+    fn filter_job_results(&self, rows: Vec<crate::models::JobType>) -> Vec<crate::models::JobType> {
+        self.filter_job_results_with_stats(rows).0
+    }
+    fn filter_job_results_with_stats(
+        &self,
+        rows: Vec<crate::models::JobType>,
+    ) -> (Vec<crate::models::JobType>, FilterStats);
+    /// Privileges of the user the executor connects as
+    async fn user_access(&self) -> Result<UserAccess, QueryError> {
+        Err(QueryError::ExecutionError(
+            "Checking user privileges is not supported".to_string(),
+        ))
+    }
+    /// Host the executor sends queries to
+    fn host(&self) -> Option<&str> {
+        None
+    }
+    /// Rows returned and filtered and resources used by the queries run so
+    /// far, without timings
+    fn execution_stats(&self) -> ExecutionStats {
+        ExecutionStats::default()
+    }
 }
@@ -1,25 +1,112 @@
-use super::base::{QueryError, QueryExecutor};
-use crate::config::GlobalFilters;
-use crate::filters::SqlFilters;
+use super::base::{DiscoveryOptions, ExecutionStats, QueryError, QueryExecutor, UserAccess};
+use super::json_rows::{JsonRowsParser, LineError};
+use crate::config::{FilterAction, GlobalFilters};
+use crate::filters::{FilterStats, RuleMatch, SqlFilters};
+use crate::kerberos::KerberosAuth;
+use crate::logging::phase_span;
 use crate::models::{JobType, Record};
+use crate::redact::{redact_literals, redact_message};
+use crate::spill::JobRows;
+use crate::timeseries::{NullStats, TsMapping};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use clickhouse::query::Query;
 use clickhouse::Client;
 use reqwest;
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tracing::Instrument;
 
 /// Information about a database column
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct ColumnInfo {
     /// Simplified type name (int, float, string, etc.)
     pub type_name: String,
     /// Number of unique values in the column (if available)
     pub cardinality: Option<u64>,
+    /// Smallest value of a numeric column
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// Largest value of a numeric column
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Average value of a numeric column
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg: Option<f64>,
+    /// Fraction of NULL values of a numeric column
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub null_ratio: Option<f64>,
+    /// Most frequent values of a string column, as left by the value filters
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sample_values: Vec<String>,
+    /// Set for columns that can hold NULL
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub nullable: bool,
+    /// Set for columns stored as `LowCardinality(...)`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub low_cardinality: bool,
+    /// Names of the variants of an `Enum8`/`Enum16` column, in definition order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enum_values: Vec<String>,
+}
+
+impl ColumnInfo {
+    /// Create column information describing a ClickHouse type
+    pub fn from_clickhouse_type(ch_type: &str) -> Self {
+        let inner = unwrap_type(ch_type);
+        // Wrappers nest either way, as in `LowCardinality(Nullable(String))`
+        let wrappers = &ch_type[..ch_type.len() - inner.len()];
+
+        Self {
+            type_name: simplify_type(inner),
+            nullable: wrappers.contains("Nullable("),
+            low_cardinality: wrappers.contains("LowCardinality("),
+            enum_values: enum_values(inner),
+            ..Default::default()
+        }
+    }
+}
+
+/// Column of a query's results, as described by ClickHouse
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ResultColumn {
+    pub name: String,
+    /// Simplified type name, as in `ColumnInfo::type_name`
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub nullable: bool,
+}
+
+impl ResultColumn {
+    /// Create a result column from its name and ClickHouse type
+    pub fn from_clickhouse_type(name: &str, ch_type: &str) -> Self {
+        let info = ColumnInfo::from_clickhouse_type(ch_type);
+        Self {
+            name: name.to_string(),
+            type_name: info.type_name,
+            nullable: info.nullable,
+        }
+    }
+
+    /// Column of a result not described by ClickHouse, typed after one of
+    /// its values
+    pub fn from_value(name: &str, value: &Value) -> Self {
+        let ch_type = match value {
+            Value::Null => "Nullable(String)",
+            Value::Bool(_) => "Bool",
+            Value::Number(number) if number.is_f64() => "Float64",
+            Value::Number(_) => "Int64",
+            Value::String(_) => "String",
+            Value::Array(_) => "Array(String)",
+            Value::Object(_) => "JSON",
+        };
+        Self::from_clickhouse_type(name, ch_type)
+    }
 }
 
 /// Schema information for a database table
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct TableSchema {
     /// Database name
     pub database: String,
@@ -29,6 +116,26 @@ pub struct TableSchema {
     pub row_count: u64,
     /// Map of column names to their information
     pub columns: HashMap<String, ColumnInfo>,
+    /// Set when discovery of the table timed out or was skipped, leaving
+    /// cardinalities and possibly the row count unknown
+    #[serde(default)]
+    pub partial: bool,
+    /// Latest value of each date and time column, absent for columns
+    /// without values
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub freshness: BTreeMap<String, DateTime<Utc>>,
+    /// Rows gained since the previous discovery, when growth is tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub growth: Option<RowGrowth>,
+}
+
+/// Change of a table's row count between two discoveries
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RowGrowth {
+    /// Rows gained, negative when rows were removed
+    pub row_delta: i64,
+    /// Rows gained per hour, `None` when both discoveries ran at the same time
+    pub rows_per_hour: Option<f64>,
 }
 
 /// Configuration for database and table filtering
@@ -96,8 +203,8 @@ impl FilterConfig {
         false
     }
 
-    /// Check if a table should be excluded
-    pub fn should_exclude_table(&self, table_name: &str) -> bool {
+    /// Check if a table of a database should be excluded
+    pub fn should_exclude_table(&self, db_name: &str, table_name: &str) -> bool {
         // Check built-in exclusions
         if self.excluded_tables.contains(table_name) {
             return true;
@@ -105,7 +212,7 @@ impl FilterConfig {
 
         // Check global filters
         if let Some(filters) = &self.sql_filters {
-            if filters.should_exclude_table(table_name) {
+            if filters.should_exclude_qualified_table(db_name, table_name) {
                 return true;
             }
         }
@@ -130,6 +237,163 @@ impl FilterConfig {
 
         false
     }
+
+    /// Get the action applied to values of a column
+    pub fn column_action(&self, column_name: &str) -> Option<FilterAction> {
+        self.sql_filters
+            .as_ref()
+            .and_then(|filters| filters.column_action(column_name))
+    }
+
+    /// Report columns whose values a column rule rewrites as strings,
+    /// whatever their type
+    pub fn retype_columns(&self, columns: &mut [ResultColumn]) {
+        for column in columns {
+            if matches!(
+                self.column_action(&column.name),
+                Some(FilterAction::Mask | FilterAction::RedactValue | FilterAction::Hash)
+            ) {
+                column.type_name = "string".into();
+            }
+        }
+    }
+
+    /// Get the action applied to a value
+    pub fn value_action(&self, value: &str) -> Option<FilterAction> {
+        self.sql_filters
+            .as_ref()
+            .and_then(|filters| filters.value_action(value))
+    }
+
+    /// Get the action applied to values under a key of a nested object
+    pub fn nested_key_action(&self, key: &str) -> Option<FilterAction> {
+        self.sql_filters
+            .as_ref()
+            .and_then(|filters| filters.nested_key_action(key))
+    }
+
+    /// Get the rule applied to values of a column
+    pub fn column_match(&self, column_name: &str) -> Option<RuleMatch<'_>> {
+        self.sql_filters
+            .as_ref()
+            .and_then(|filters| filters.column_match(column_name))
+    }
+
+    /// Apply value rules to a value and, recursively, to everything nested in
+    /// it, returning `false` if the row holding it must be dropped
+    pub fn filter_value(&self, value: &mut Value, stats: &mut FilterStats) -> bool {
+        let Some(filters) = &self.sql_filters else {
+            return true;
+        };
+
+        match value {
+            Value::String(value_str) => {
+                let rule_match = filters.value_match(value_str);
+                self.apply_rule_match(rule_match, value, stats)
+            }
+            Value::Number(number) => {
+                let rule_match = number.as_f64().and_then(|n| filters.number_match(n));
+                self.apply_rule_match(rule_match, value, stats)
+            }
+            Value::Array(items) => items.iter_mut().all(|item| self.filter_value(item, stats)),
+            Value::Object(fields) => {
+                fields
+                    .iter_mut()
+                    .all(|(key, field)| match filters.nested_key_match(key) {
+                        Some(rule_match) => self.apply_rule_match(Some(rule_match), field, stats),
+                        None => self.filter_value(field, stats),
+                    })
+            }
+            _ => true,
+        }
+    }
+
+    /// Pass a sample value of a column through the rules applied to job
+    /// results, `None` if it must not be reported
+    pub fn filter_sample(&self, column_name: &str, sample: String) -> Option<String> {
+        let mut value = Value::String(sample);
+        let mut stats = FilterStats::default();
+        let keep = match self.column_match(column_name) {
+            Some(rule_match) => self.apply_rule_match(Some(rule_match), &mut value, &mut stats),
+            None => self.filter_value(&mut value, &mut stats),
+        };
+
+        match value {
+            Value::String(sample) if keep => Some(sample),
+            _ => None,
+        }
+    }
+
+    /// Apply a matched rule to a value and record it, returning `false` if the
+    /// row must be dropped
+    fn apply_rule_match(
+        &self,
+        rule_match: Option<RuleMatch>,
+        value: &mut Value,
+        stats: &mut FilterStats,
+    ) -> bool {
+        let Some(rule_match) = rule_match else {
+            return true;
+        };
+
+        stats.record(&rule_match);
+        match rule_match.action {
+            FilterAction::DropRow => false,
+            action => {
+                *value = self.apply_action(action, value);
+                true
+            }
+        }
+    }
+
+    /// Rewrite a value according to an action
+    pub fn apply_action(&self, action: FilterAction, value: &Value) -> Value {
+        match &self.sql_filters {
+            Some(filters) => filters.apply_action(action, value),
+            None => value.clone(),
+        }
+    }
+}
+
+/// Header of ClickHouse HTTP responses summarizing the query's progress
+const SUMMARY_HEADER: &str = "X-ClickHouse-Summary";
+
+/// Rows a thread filters at least, below which threads cost more than they
+/// save
+const FILTER_CHUNK_ROWS: usize = 8192;
+
+/// ClickHouse client authenticating each query with a fresh Kerberos token
+/// when the datasource uses Kerberos
+#[derive(Clone)]
+struct QueryClient {
+    client: Client,
+    kerberos: Option<Arc<KerberosAuth>>,
+}
+
+impl QueryClient {
+    /// `Authorization` header of the next request, unless it authenticates
+    /// with the user and password
+    async fn authorization(&self) -> Result<Option<String>, QueryError> {
+        match &self.kerberos {
+            Some(kerberos) => kerberos
+                .authorization()
+                .await
+                .map(Some)
+                .map_err(|e| QueryError::ConnectionError(format!("{:#}", e))),
+            None => Ok(None),
+        }
+    }
+
+    async fn query(&self, query: &str) -> Result<Query, QueryError> {
+        Ok(match self.authorization().await? {
+            Some(authorization) => self
+                .client
+                .clone()
+                .with_header("Authorization", authorization)
+                .query(query),
+            None => self.client.query(query),
+        })
+    }
 }
 
 /// Executor for ClickHouse databases
@@ -137,17 +401,36 @@ pub struct ClickhouseExecutor {
     url: String,
     username: String,
     password: String,
-    client: Arc<Client>,
+    /// Default database of queries, the user's default when unset
+    database: Option<String>,
+    client: QueryClient,
+    /// HTTP client queries sent over HTTP use, shared by the datasource's
+    /// executors
+    http: reqwest::Client,
     filter_config: FilterConfig,
+    /// Whether job results keep 64-bit integers and decimals as strings
+    exact_numbers: bool,
+    /// Bytes the rows of a job may take in memory before they're spilled
+    memory_budget: Option<u64>,
+    /// HTTP session queries run on, keeping settings between them
+    session_id: Option<String>,
+    /// Rows and resources of the queries run so far
+    stats: Mutex<ExecutionStats>,
 }
 
 impl ClickhouseExecutor {
+    /// Redact literals and filtered values from an error message or log line
+    fn redact(&self, message: &str) -> String {
+        redact_message(message, self.filter_config.sql_filters.as_ref())
+    }
+
     /// Get list of databases from the ClickHouse server
     async fn get_databases(&self) -> Result<Vec<String>, QueryError> {
         let query = "SELECT name FROM system.databases";
         let databases = self
             .client
             .query(query)
+            .await?
             .fetch_all::<String>()
             .await
             .map_err(|e| QueryError::ExecutionError(e.to_string()))?;
@@ -167,6 +450,7 @@ impl ClickhouseExecutor {
         let tables = self
             .client
             .query(&query)
+            .await?
             .fetch_all::<String>()
             .await
             .map_err(|e| QueryError::ExecutionError(e.to_string()))?;
@@ -174,7 +458,7 @@ impl ClickhouseExecutor {
         // Apply table filtering
         let filtered_tables = tables
             .into_iter()
-            .filter(|table| !self.filter_config.should_exclude_table(table))
+            .filter(|table| !self.filter_config.should_exclude_table(database, table))
             .collect();
 
         Ok(filtered_tables)
@@ -182,6 +466,15 @@ impl ClickhouseExecutor {
 
     /// Discover schemas for all databases and tables
     pub async fn discover_schemas(&self) -> Result<Vec<TableSchema>, QueryError> {
+        self.discover_schemas_with(&DiscoveryOptions::default())
+            .await
+    }
+
+    /// Discover schemas for all databases and tables within the given limits
+    pub async fn discover_schemas_with(
+        &self,
+        options: &DiscoveryOptions,
+    ) -> Result<Vec<TableSchema>, QueryError> {
         log::debug!("Discovering clickhouse schemas");
 
         let mut schemas: Vec<TableSchema> = Vec::new();
@@ -203,7 +496,7 @@ impl ClickhouseExecutor {
             })?;
 
             // Process tables in parallel for better performance
-            let table_schemas = self.discover_tables(&db, &tables).await?;
+            let table_schemas = self.discover_tables(&db, &tables, options).await?;
             schemas.extend(table_schemas);
         }
 
@@ -215,6 +508,7 @@ impl ClickhouseExecutor {
         &self,
         db: &str,
         tables: &[String],
+        options: &DiscoveryOptions,
     ) -> Result<Vec<TableSchema>, QueryError> {
         let mut table_futures = Vec::new();
         let mut table_schemas = Vec::new();
@@ -226,18 +520,75 @@ impl ClickhouseExecutor {
             let table_owned = table.clone();
             let client = self.client.clone();
             let filter_config = self.filter_config.clone();
+            let skip = options.skip_tables.contains(&format!("{}.{}", db, table));
+            let table_timeout = options.table_timeout;
+            let sample_values = options.sample_values;
 
             table_futures.push(tokio::spawn(async move {
+                if skip {
+                    log::info!(
+                        "Skipping discovery of table {}.{} after repeated timeouts",
+                        db_owned,
+                        table_owned
+                    );
+                    return Self::discover_partial_table_schema(
+                        &client,
+                        &db_owned,
+                        &table_owned,
+                        &filter_config,
+                    )
+                    .await;
+                }
+
                 log::debug!("Discovering table: {}.{}", db_owned, table_owned);
-                Self::discover_table_schema(&client, &db_owned, &table_owned, Some(&filter_config))
-                    .await
+                let discovery = Self::discover_table_schema(
+                    &client,
+                    &db_owned,
+                    &table_owned,
+                    Some(&filter_config),
+                    sample_values,
+                );
+                let Some(table_timeout) = table_timeout else {
+                    return discovery.await;
+                };
+                match tokio::time::timeout(table_timeout, discovery).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        log::warn!(
+                            "Discovery of table {}.{} timed out after {:?}",
+                            db_owned,
+                            table_owned,
+                            table_timeout
+                        );
+                        let partial = Self::discover_partial_table_schema(
+                            &client,
+                            &db_owned,
+                            &table_owned,
+                            &filter_config,
+                        );
+                        tokio::time::timeout(table_timeout, partial)
+                            .await
+                            .map_err(|_| {
+                                QueryError::ExecutionError(format!(
+                                    "Partial discovery of table {}.{} timed out",
+                                    db_owned, table_owned
+                                ))
+                            })?
+                    }
+                }
             }));
         }
 
         // Wait for all table discoveries to complete
         for future in table_futures {
             match future.await {
-                Ok(Ok(schema)) => table_schemas.push(schema),
+                Ok(Ok(schema)) => {
+                    if let Some(progress) = &options.progress {
+                        // Discovery goes on even if nobody follows its progress
+                        let _ = progress.send(schema.clone());
+                    }
+                    table_schemas.push(schema)
+                }
                 Ok(Err(e)) => log::error!("Table discovery error: {}", e),
                 Err(e) => log::error!("Task join error: {}", e),
             }
@@ -246,12 +597,177 @@ impl ClickhouseExecutor {
         Ok(table_schemas)
     }
 
+    /// Discover column types and the row count of a table from system
+    /// tables only, without scanning the table itself
+    async fn discover_partial_table_schema(
+        client: &QueryClient,
+        db: &str,
+        table: &str,
+        filter_config: &FilterConfig,
+    ) -> Result<TableSchema, QueryError> {
+        let columns_query = format!(
+            "SELECT name, type FROM system.columns WHERE database = '{}' AND table = '{}'",
+            db, table
+        );
+        let columns: Vec<(String, String)> = client
+            .query(&columns_query)
+            .await?
+            .fetch_all()
+            .await
+            .map_err(|e| QueryError::ExecutionError(e.to_string()))?;
+
+        // Views and some engines don't report their row count
+        let rows_query = format!(
+            "SELECT ifNull(total_rows, 0) FROM system.tables WHERE database = '{}' AND name = '{}'",
+            db, table
+        );
+        let row_count: u64 = client
+            .query(&rows_query)
+            .await?
+            .fetch_one()
+            .await
+            .map_err(|e| QueryError::ExecutionError(e.to_string()))?;
+
+        Ok(TableSchema {
+            database: db.to_string(),
+            table: table.to_string(),
+            row_count,
+            columns: columns
+                .into_iter()
+                .filter(|(name, _)| !filter_config.should_exclude_column(name))
+                .map(|(name, type_)| (name, ColumnInfo::from_clickhouse_type(&type_)))
+                .collect(),
+            partial: true,
+            freshness: BTreeMap::new(),
+            growth: None,
+        })
+    }
+
+    /// Get min, max, average and NULL ratio of numeric columns in a single
+    /// pass over the table, `None` for statistics of empty tables
+    async fn discover_numeric_stats(
+        client: &QueryClient,
+        db: &str,
+        table: &str,
+        columns: &[String],
+    ) -> Result<Vec<[Option<f64>; 4]>, QueryError> {
+        // Statistics are returned as a single array, NaN standing for NULL
+        let stats: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                let column = format!("`{}`", column.replace('`', "\\`"));
+                format!(
+                    "ifNull(toFloat64(min({c})), nan), ifNull(toFloat64(max({c})), nan), \
+                     ifNull(toFloat64(avg({c})), nan), countIf(isNull({c})) / count()",
+                    c = column
+                )
+            })
+            .collect();
+        let query = format!(
+            "SELECT [toFloat64(count()), {}] FROM {}.{}",
+            stats.join(", "),
+            db,
+            table
+        );
+
+        let values: Vec<f64> = client
+            .query(&query)
+            .await?
+            .fetch_one()
+            .await
+            .map_err(|e| QueryError::ExecutionError(e.to_string()))?;
+
+        let row_count = values.first().copied().unwrap_or_default();
+        Ok(values
+            .get(1..)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .map(|chunk| {
+                let stat = |value: f64| (row_count > 0.0 && value.is_finite()).then_some(value);
+                [
+                    stat(chunk[0]),
+                    stat(chunk[1]),
+                    stat(chunk[2]),
+                    stat(chunk[3]),
+                ]
+            })
+            .collect())
+    }
+
+    /// Get the latest value of each date and time column in a single pass
+    /// over the table, `None` for columns without values
+    async fn discover_freshness(
+        client: &QueryClient,
+        db: &str,
+        table: &str,
+        columns: &[String],
+    ) -> Result<Vec<Option<DateTime<Utc>>>, QueryError> {
+        // Timestamps are returned as a single array, zero standing for NULL
+        let latest: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                format!(
+                    "ifNull(toInt64(toUnixTimestamp(toDateTime(max(`{}`)))), 0)",
+                    column.replace('`', "\\`")
+                )
+            })
+            .collect();
+        let query = format!("SELECT [{}] FROM {}.{}", latest.join(", "), db, table);
+
+        let values: Vec<i64> = client
+            .query(&query)
+            .await?
+            .fetch_one()
+            .await
+            .map_err(|e| QueryError::ExecutionError(e.to_string()))?;
+
+        Ok(values
+            .into_iter()
+            .map(|seconds| {
+                (seconds > 0)
+                    .then(|| DateTime::from_timestamp(seconds, 0))
+                    .flatten()
+            })
+            .collect())
+    }
+
+    /// Get up to `limit` of the most frequent non-NULL values of each string
+    /// column in a single pass over the table
+    async fn discover_sample_values(
+        client: &QueryClient,
+        db: &str,
+        table: &str,
+        columns: &[String],
+        limit: usize,
+    ) -> Result<Vec<Vec<String>>, QueryError> {
+        let samples: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                let column = format!("`{}`", column.replace('`', "\\`"));
+                format!(
+                    "topKIf({n})(toString(assumeNotNull({c})), isNotNull({c}))",
+                    n = limit,
+                    c = column
+                )
+            })
+            .collect();
+        let query = format!("SELECT [{}] FROM {}.{}", samples.join(", "), db, table);
+
+        client
+            .query(&query)
+            .await?
+            .fetch_one()
+            .await
+            .map_err(|e| QueryError::ExecutionError(e.to_string()))
+    }
+
     /// Discover schema for a single table
     async fn discover_table_schema(
-        client: &Client,
+        client: &QueryClient,
         db: &String,
         table: &String,
         filter_config: Option<&FilterConfig>,
+        sample_values: Option<usize>,
     ) -> Result<TableSchema, QueryError> {
         // Get columns
         let columns_query = format!(
@@ -261,11 +777,15 @@ impl ClickhouseExecutor {
 
         let columns: Vec<(String, String)> = client
             .query(&columns_query)
+            .await?
             .fetch_all()
             .await
             .map_err(|e| QueryError::ExecutionError(e.to_string()))?;
 
         let mut column_info = HashMap::new();
+        let mut numeric_columns = Vec::new();
+        let mut time_columns = Vec::new();
+        let mut string_columns = Vec::new();
 
         // Get cardinality for each column
         for (name, type_) in columns {
@@ -281,44 +801,118 @@ impl ClickhouseExecutor {
 
             let cardinality_query = format!("SELECT uniq({}) FROM {}.{}", name, db, table);
 
-            let cardinality: Option<u64> = match client.query(&cardinality_query).fetch_one().await
-            {
-                Ok(count) => Some(count),
-                Err(e) => {
-                    log::warn!(
-                        "Failed to get cardinality for {}.{}.{}: {}",
-                        db,
-                        table,
-                        name,
-                        e
-                    );
-                    None
-                }
-            };
-
+            let cardinality: Option<u64> =
+                match client.query(&cardinality_query).await?.fetch_one().await {
+                    Ok(count) => Some(count),
+                    Err(e) => {
+                        log::warn!(
+                            "Failed to get cardinality for {}.{}.{}: {}",
+                            db,
+                            table,
+                            name,
+                            e
+                        );
+                        None
+                    }
+                };
+
+            if is_numeric_type(&type_) {
+                numeric_columns.push(name.clone());
+            } else if is_time_type(&type_) {
+                time_columns.push(name.clone());
+            } else if is_string_type(&type_) {
+                string_columns.push(name.clone());
+            }
             column_info.insert(
                 name,
                 ColumnInfo {
-                    type_name: simplify_type(&type_),
                     cardinality,
+                    ..ColumnInfo::from_clickhouse_type(&type_)
                 },
             );
         }
 
+        if !numeric_columns.is_empty() {
+            match Self::discover_numeric_stats(client, db, table, &numeric_columns).await {
+                Ok(stats) => {
+                    for (name, [min, max, avg, null_ratio]) in numeric_columns.iter().zip(stats) {
+                        if let Some(info) = column_info.get_mut(name) {
+                            info.min = min;
+                            info.max = max;
+                            info.avg = avg;
+                            info.null_ratio = null_ratio;
+                        }
+                    }
+                }
+                Err(e) => log::warn!(
+                    "Failed to get numeric statistics for {}.{}: {}",
+                    db,
+                    table,
+                    e
+                ),
+            }
+        }
+
+        let mut freshness = BTreeMap::new();
+        if !time_columns.is_empty() {
+            match Self::discover_freshness(client, db, table, &time_columns).await {
+                Ok(latest) => {
+                    for (name, latest) in time_columns.into_iter().zip(latest) {
+                        if let Some(latest) = latest {
+                            freshness.insert(name, latest);
+                        }
+                    }
+                }
+                Err(e) => log::warn!("Failed to get freshness for {}.{}: {}", db, table, e),
+            }
+        }
+
+        let sample_values = sample_values.filter(|limit| *limit > 0);
+        if let (Some(limit), false) = (sample_values, string_columns.is_empty()) {
+            match Self::discover_sample_values(client, db, table, &string_columns, limit).await {
+                Ok(samples) => {
+                    for (name, samples) in string_columns.iter().zip(samples) {
+                        let mut samples: Vec<String> = samples
+                            .into_iter()
+                            .filter_map(|sample| match filter_config {
+                                Some(filter_config) => filter_config.filter_sample(name, sample),
+                                None => Some(sample),
+                            })
+                            .collect();
+                        // Masking can make different values equal
+                        let mut seen = HashSet::new();
+                        samples.retain(|sample| seen.insert(sample.clone()));
+                        if let Some(info) = column_info.get_mut(name) {
+                            info.sample_values = samples;
+                        }
+                    }
+                }
+                Err(e) => log::warn!("Failed to get sample values for {}.{}: {}", db, table, e),
+            }
+        }
+
         // Get row count
         let count_query = format!("SELECT count() FROM {}.{}", db, table);
-        let row_count = client.query(&count_query).fetch_one().await.map_err(|e| {
-            QueryError::ExecutionError(format!(
-                "Failed to get row count for {}.{}: {}",
-                db, table, e
-            ))
-        })?;
+        let row_count = client
+            .query(&count_query)
+            .await?
+            .fetch_one()
+            .await
+            .map_err(|e| {
+                QueryError::ExecutionError(format!(
+                    "Failed to get row count for {}.{}: {}",
+                    db, table, e
+                ))
+            })?;
 
         Ok(TableSchema {
             database: db.to_string(),
             table: table.to_string(),
             row_count,
             columns: column_info,
+            partial: false,
+            freshness,
+            growth: None,
         })
     }
 
@@ -343,14 +937,97 @@ impl ClickhouseExecutor {
             .with_database("default");
 
         Ok(Self {
-            client: Arc::new(client),
+            client: QueryClient {
+                client,
+                kerberos: None,
+            },
             url: host.to_string(),
             username: username.to_string(),
             password: password.to_string(),
+            database: None,
+            http: crate::http::default_client(),
             filter_config,
+            exact_numbers: false,
+            memory_budget: None,
+            session_id: None,
+            stats: Mutex::default(),
         })
     }
 
+    /// Authenticate with Kerberos tickets of `auth` instead of the user and
+    /// password
+    pub fn with_kerberos(mut self, auth: KerberosAuth) -> Self {
+        self.client = QueryClient {
+            client: Client::default()
+                .with_url(&self.url)
+                .with_database(self.database.as_deref().unwrap_or("default")),
+            kerberos: Some(Arc::new(auth)),
+        };
+        self
+    }
+
+    /// Run queries in `database` instead of the user's default database
+    pub fn with_database(mut self, database: Option<&str>) -> Self {
+        if let Some(database) = database {
+            self.client.client = self.client.client.clone().with_database(database);
+            self.database = Some(database.to_string());
+        }
+        self
+    }
+
+    /// Filters applied to the schemas and results of the executor
+    pub fn filter_config(&self) -> &FilterConfig {
+        &self.filter_config
+    }
+
+    /// Send queries over HTTP with `client`, reusing its pooled connections
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.http = client;
+        self
+    }
+
+    /// Keep 64-bit and larger integers and decimals in job results as the
+    /// strings ClickHouse formats them as, instead of JSON numbers which may
+    /// lose precision
+    pub fn with_exact_numbers(mut self, exact_numbers: bool) -> Self {
+        self.exact_numbers = exact_numbers;
+        self
+    }
+
+    /// Spill the rows of a job to a temporary file once they take more than
+    /// `megabytes` in memory
+    pub fn with_memory_budget(mut self, megabytes: Option<u64>) -> Self {
+        self.memory_budget = megabytes.map(|megabytes| megabytes * 1024 * 1024);
+        self
+    }
+
+    /// Insert `rows` into `table` as one asynchronous insert, returning once
+    /// ClickHouse flushed it. Only the agent's own data is written, never
+    /// anything a task asked for
+    pub async fn insert_json_rows(&self, table: &str, rows: &[Value]) -> Result<(), QueryError> {
+        let mut body = format!("INSERT INTO {} FORMAT JSONEachRow\n", table);
+        for row in rows {
+            body.push_str(&row.to_string());
+            body.push('\n');
+        }
+        self.send_query(
+            body,
+            &[("async_insert", "1"), ("wait_for_async_insert", "1")],
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Settings making ClickHouse format numbers in job results as JSON
+    /// numbers, or as strings with `exact_numbers`
+    fn number_settings(&self) -> [(&'static str, &'static str); 2] {
+        let quote = if self.exact_numbers { "1" } else { "0" };
+        [
+            ("output_format_json_quote_64bit_integers", quote),
+            ("output_format_json_quote_decimals", quote),
+        ]
+    }
+
     /// Create a new ClickHouse executor with custom filter configuration
     pub fn with_filter_config(
         host: &str,
@@ -365,27 +1042,168 @@ impl ClickhouseExecutor {
             .with_database("default");
 
         Ok(Self {
-            client: Arc::new(client),
+            client: QueryClient {
+                client,
+                kerberos: None,
+            },
             url: host.to_string(),
             username: username.to_string(),
             password: password.to_string(),
+            database: None,
+            http: crate::http::default_client(),
             filter_config,
+            exact_numbers: false,
+            memory_budget: None,
+            session_id: None,
+            stats: Mutex::default(),
         })
     }
 }
 
-/// Convert ClickHouse type to simplified type name
+/// Error reading or writing job rows spilled to disk
+fn spill_error(e: std::io::Error) -> QueryError {
+    QueryError::ExecutionError(format!("Failed to spill job rows to disk: {}", e))
+}
+
+/// Threads filtering the rows of a result, one per available core
+fn filter_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |threads| threads.get())
+}
+
+/// Get the arguments of a parameterized type such as `Array(String)`
+fn type_args<'a>(ch_type: &'a str, name: &str) -> Option<&'a str> {
+    ch_type
+        .strip_prefix(name)?
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+/// Strip `Nullable` and `LowCardinality` wrappers off a ClickHouse type
+fn unwrap_type(ch_type: &str) -> &str {
+    let mut ch_type = ch_type;
+    while let Some(inner) =
+        type_args(ch_type, "Nullable").or_else(|| type_args(ch_type, "LowCardinality"))
+    {
+        ch_type = inner;
+    }
+    ch_type
+}
+
+/// Split type arguments on top-level commas, dropping element names of
+/// named tuples
+fn split_type_args(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quoted, mut escaped, mut start) = (0, false, false, 0);
+    for (i, c) in args.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                parts.push(&args[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&args[start..]);
+
+    parts
+        .into_iter()
+        .map(|part| {
+            let part = part.trim();
+            // Names come before the element type, which starts with a letter
+            match part.split_once(' ') {
+                Some((name, element))
+                    if !name.contains('(') && element.starts_with(char::is_alphabetic) =>
+                {
+                    element.trim()
+                }
+                _ => part,
+            }
+        })
+        .collect()
+}
+
+/// Check if a ClickHouse type, possibly Nullable, holds numbers
+fn is_numeric_type(ch_type: &str) -> bool {
+    let ch_type = unwrap_type(ch_type);
+    ["Int", "UInt", "Float", "Decimal"]
+        .iter()
+        .any(|prefix| ch_type.starts_with(prefix))
+}
+
+/// Check whether a ClickHouse type holds dates or timestamps
+fn is_time_type(ch_type: &str) -> bool {
+    unwrap_type(ch_type).starts_with("Date")
+}
+
+/// Check whether a ClickHouse type, possibly Nullable or LowCardinality,
+/// holds strings
+fn is_string_type(ch_type: &str) -> bool {
+    let ch_type = unwrap_type(ch_type);
+    ch_type == "String" || ch_type.starts_with("FixedString(")
+}
+
+/// Names of the variants of an enum type such as `Enum8('a' = 1, 'b' = 2)`,
+/// empty for other types
+fn enum_values(ch_type: &str) -> Vec<String> {
+    let Some(definition) = ["Enum8(", "Enum16("]
+        .iter()
+        .find_map(|prefix| ch_type.strip_prefix(prefix))
+    else {
+        return Vec::new();
+    };
+
+    let mut values = Vec::new();
+    let mut chars = definition.chars();
+    // Each variant is a quoted name followed by ` = <number>`
+    while chars.by_ref().any(|c| c == '\'') {
+        let mut name = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => name.extend(chars.next()),
+                '\'' => break,
+                c => name.push(c),
+            }
+        }
+        values.push(name);
+    }
+    values
+}
+
+/// Convert ClickHouse type to simplified type name, describing arrays, maps
+/// and tuples by their element types, e.g. `array<int>`
 fn simplify_type(ch_type: &str) -> String {
-    if ch_type.starts_with("Int") || ch_type.starts_with("UInt") {
+    let ch_type = unwrap_type(ch_type);
+    let elements = |args: &str| -> String {
+        split_type_args(args)
+            .into_iter()
+            .map(simplify_type)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    if let Some(args) = type_args(ch_type, "Array") {
+        format!("array<{}>", simplify_type(args))
+    } else if let Some(args) = type_args(ch_type, "Map") {
+        format!("map<{}>", elements(args))
+    } else if let Some(args) = type_args(ch_type, "Tuple") {
+        format!("tuple<{}>", elements(args))
+    } else if ch_type.starts_with("Int") || ch_type.starts_with("UInt") {
         "int".into()
     } else if ch_type.starts_with("Float") {
         "float".into()
     } else if ch_type == "Bool" || ch_type == "Boolean" {
         "bool".into()
-    } else if ch_type == "Date" {
-        "date".into()
     } else if ch_type.starts_with("DateTime") {
         "datetime".into()
+    } else if ch_type.starts_with("Date") {
+        "date".into()
+    } else if ch_type.starts_with("Enum8(") || ch_type.starts_with("Enum16(") {
+        "enum".into()
     } else {
         "string".into()
     }
@@ -393,22 +1211,33 @@ fn simplify_type(ch_type: &str) -> String {
 
 #[async_trait]
 impl QueryExecutor for ClickhouseExecutor {
-    async fn discover_schemas(&self) -> Result<Vec<TableSchema>, QueryError> {
-        self.discover_schemas().await
+    async fn discover_schemas_with(
+        &self,
+        options: &DiscoveryOptions,
+    ) -> Result<Vec<TableSchema>, QueryError> {
+        self.discover_schemas_with(options).await
     }
 
-    async fn execute_ts(&self, query: &str) -> Result<Vec<Record>, QueryError> {
-        log::debug!("Executing time series query: {}", query);
-
-        let rows: Vec<Record> = self
-            .client
-            .query(query)
-            .fetch_all::<Record>()
-            .await
-            .map_err(|e| {
-                log::error!("Query execution error: {}", e);
-                QueryError::ExecutionError(e.to_string())
-            })?;
+    async fn execute_ts_with(
+        &self,
+        query: &str,
+        mapping: &TsMapping,
+    ) -> Result<(Vec<Record>, NullStats), QueryError> {
+        log::debug!("Executing time series query: {}", redact_literals(query));
+
+        // ISO date-times keep the time zone and sub-second precision
+        let (rows, _) = self
+            .fetch_json_rows(query, &[("date_time_output_format", "iso")])
+            .instrument(phase_span!("query"))
+            .await?;
+        let returned = rows.len();
+        let (rows, null_stats) = mapping.records(rows).map_err(|e| {
+            log::error!("Time series mapping error: {}", e);
+            QueryError::ExecutionError(e.to_string())
+        })?;
+        let mapped = rows.len();
+        let rows = phase_span!("filter").in_scope(|| self.filter_labels(rows));
+        self.record_rows(returned, mapped - rows.len());
 
         log::debug!("Query executed successfully, returned {} rows", rows.len());
 
@@ -416,109 +1245,151 @@ impl QueryExecutor for ClickhouseExecutor {
             log::trace!("Query results: {:?}", &rows);
         }
 
-        Ok(rows)
+        Ok((rows, null_stats))
     }
 
     /// Filter job results based on global filters
-    fn filter_job_results(&self, rows: Vec<JobType>) -> Vec<JobType> {
+    fn filter_job_results_with_stats(&self, rows: Vec<JobType>) -> (Vec<JobType>, FilterStats) {
+        let mut stats = FilterStats::default();
         if self.filter_config.sql_filters.is_none() {
-            return rows;
+            return (rows, stats);
         }
 
-        let mut filtered_rows = Vec::new();
+        let filtered_rows = self.filter_rows(rows, &mut stats);
+        let filtered_rows = self.filter_result_set(filtered_rows, &mut stats);
+        (filtered_rows, stats)
+    }
 
-        for row in rows {
-            let mut should_include_row = true;
-
-            // Check each value in the row
-            for (key, value) in &row {
-                // Check if column should be excluded
-                if self.filter_config.should_exclude_column(key) {
-                    should_include_row = false;
-                    break;
-                }
+    async fn execute_job_with_columns(
+        &self,
+        query: &str,
+    ) -> Result<(Vec<JobType>, FilterStats, Vec<ResultColumn>), QueryError> {
+        let (rows, stats, columns) = self.execute_job_rows(query).await?;
+        let rows = rows.into_vec().map_err(spill_error)?;
+        Ok((rows, stats, columns))
+    }
 
-                // Check if value should be excluded
-                if let Some(value_str) = value.as_str() {
-                    // Remove all spaces from the value before checking
-                    let trimmed_value = value_str.replace(" ", "");
-                    if self.filter_config.should_exclude_value(&trimmed_value) {
-                        should_include_row = false;
-                        break;
-                    }
+    async fn execute_job_rows(
+        &self,
+        query: &str,
+    ) -> Result<(JobRows, FilterStats, Vec<ResultColumn>), QueryError> {
+        log::debug!("Executing job query: {}", redact_literals(query));
+
+        // Rules over the whole result set need all rows at hand, so rows are
+        // only spilled without them
+        let result_set_rules = self
+            .filter_config
+            .sql_filters
+            .as_ref()
+            .is_some_and(SqlFilters::has_result_set_rules);
+        let budget = self.memory_budget.filter(|_| !result_set_rules);
+
+        // Rows are filtered in batches as they arrive, so those left out are
+        // never held in memory for long
+        let batch_rows = FILTER_CHUNK_ROWS * filter_threads();
+        let mut stats = FilterStats::default();
+        let mut returned = 0;
+        let mut batch = Vec::new();
+        let mut rows = JobRows::new(budget);
+        let mut columns = self
+            .stream_json_rows(query, &self.number_settings(), |row| {
+                returned += 1;
+                batch.push(row);
+                if batch.len() >= batch_rows {
+                    let filtered = self.filter_rows(std::mem::take(&mut batch), &mut stats);
+                    filtered.into_iter().for_each(|row| rows.push(row));
                 }
+            })
+            .instrument(phase_span!("query"))
+            .await?;
+
+        let rows = phase_span!("filter").in_scope(|| {
+            let filtered = self.filter_rows(batch, &mut stats);
+            filtered.into_iter().for_each(|row| rows.push(row));
+            let rows = rows.finish().map_err(spill_error)?;
+            if !result_set_rules {
+                return Ok(rows);
             }
-
-            // Only include the row if it passed all filters
-            if should_include_row {
-                filtered_rows.push(row);
-            }
+            // Rules over the whole result set apply once all rows arrived
+            let rows = rows.into_vec().map_err(spill_error)?;
+            Ok::<_, QueryError>(self.filter_result_set(rows, &mut stats).into())
+        })?;
+        self.record_rows(returned, returned - rows.len());
+        if rows.is_spilled() {
+            log::debug!("Job rows exceeded the memory budget and were spilled to disk");
         }
 
-        filtered_rows
-    }
+        self.filter_config.retype_columns(&mut columns);
 
-    async fn execute_job(&self, query: &str) -> Result<Vec<JobType>, QueryError> {
-        log::debug!("Executing job query: {}", query);
+        log::debug!(
+            "Job query executed successfully, returned {} rows",
+            rows.len()
+        );
 
-        // Use reqwest client for JSONEachRow format
-        let client = reqwest::Client::new();
-        let full_query = format!("{} FORMAT JSONEachRow", query);
+        Ok((rows, stats, columns))
+    }
 
-        // Send request to ClickHouse server
-        let response = client
-            .post(self.url.clone())
-            .basic_auth(self.username.clone(), Some(self.password.clone()))
-            .body(full_query)
-            .send()
-            .await
-            .map_err(|e| {
-                log::error!("HTTP request error: {}", e);
-                QueryError::ConnectionError(e.to_string())
-            })?
-            .error_for_status()
-            .map_err(|e| {
-                log::error!("HTTP response error: {}", e);
-                QueryError::ExecutionError(e.to_string())
-            })?;
+    async fn execute_statement(&self, query: &str) -> Result<(), QueryError> {
+        log::debug!("Executing statement: {}", redact_literals(query));
+        self.send_query(query.to_string(), &[]).await.map(|_| ())
+    }
 
-        // Parse response text
-        let text = response
-            .text()
-            .await
-            .map_err(|e| QueryError::ExecutionError(e.to_string()))?;
+    fn set_session(&mut self, session_id: Option<String>) {
+        self.session_id = session_id;
+    }
 
-        // Parse each line as a JSON object
-        let rows_res: Result<Vec<HashMap<String, Value>>, _> = text
-            .lines()
-            .filter(|line| !line.trim().is_empty())
-            .map(|line| {
-                serde_json::from_str(line).map_err(|e| {
-                    log::error!("JSON parsing error for line: {}", line);
-                    e
-                })
-            })
-            .collect();
+    fn host(&self) -> Option<&str> {
+        Some(&self.url)
+    }
 
-        let mut rows = rows_res.map_err(|e| QueryError::ExecutionError(e.to_string()))?;
+    fn execution_stats(&self) -> ExecutionStats {
+        self.stats.lock().unwrap().clone()
+    }
 
-        // Apply filters to the result rows
-        if self.filter_config.sql_filters.is_some() {
-            rows = self.filter_job_results(rows);
+    async fn user_access(&self) -> Result<UserAccess, QueryError> {
+        let (rows, _) = self
+            .fetch_json_rows("SELECT getSetting('readonly') AS readonly", &[])
+            .await?;
+        // UInt64 settings come back as strings unless configured otherwise
+        let readonly = match rows.first().and_then(|row| row.get("readonly")) {
+            Some(Value::String(level)) => level != "0",
+            Some(Value::Number(level)) => level.as_u64() != Some(0),
+            _ => false,
+        };
+        if readonly {
+            return Ok(UserAccess {
+                readonly,
+                grants: Vec::new(),
+            });
         }
 
-        log::debug!(
-            "Job query executed successfully, returned {} rows",
-            rows.len()
-        );
-
-        Ok(rows)
+        // FINAL includes the privileges of granted roles, older servers only
+        // know plain SHOW GRANTS
+        let rows = match self.fetch_json_rows("SHOW GRANTS FINAL", &[]).await {
+            Ok((rows, _)) => rows,
+            Err(_) => self.fetch_json_rows("SHOW GRANTS", &[]).await?.0,
+        };
+        let grants = rows
+            .iter()
+            .filter_map(|row| row.values().next())
+            .filter_map(|grant| grant.as_str().map(str::to_string))
+            .collect();
+        Ok(UserAccess { readonly, grants })
     }
 
     async fn connect(&mut self) -> Result<(), QueryError> {
         log::debug!("Testing connection to ClickHouse server at {}", self.url);
 
-        match self.execute_ts("SELECT 1").await {
+        let result = match self.client.query("SELECT 1").await {
+            Ok(query) => query
+                .fetch_one::<u8>()
+                .await
+                .map(|_| ())
+                .map_err(|e| QueryError::ConnectionError(self.redact(&e.to_string()))),
+            Err(e) => Err(e),
+        };
+
+        match result {
             Ok(_) => {
                 log::info!("Successfully connected to ClickHouse server");
                 Ok(())
@@ -530,3 +1401,262 @@ impl QueryExecutor for ClickhouseExecutor {
         }
     }
 }
+
+impl ClickhouseExecutor {
+    /// Apply column and value filters to each value of a row, returning
+    /// whether the row is kept
+    fn filter_row(&self, row: &mut JobType, stats: &mut FilterStats) -> bool {
+        let mut row_stats = FilterStats::default();
+        let kept = row.iter_mut().all(|(key, value)| {
+            // Column rules take precedence over value rules, which also
+            // apply to values nested in JSON, Map and Array columns
+            match self.filter_config.column_match(key) {
+                Some(rule_match) => {
+                    self.filter_config
+                        .apply_rule_match(Some(rule_match), value, &mut row_stats)
+                }
+                None => self.filter_config.filter_value(value, &mut row_stats),
+            }
+        });
+        // Values rewritten before a later column dropped the row are never
+        // submitted
+        if !kept {
+            row_stats.masked_values = 0;
+        }
+        stats.merge(&row_stats);
+        kept
+    }
+
+    /// Apply column and value filters to each row, keeping those that pass
+    /// in their order
+    ///
+    /// Large sets of rows are split into contiguous chunks filtered on
+    /// separate threads, whose results are concatenated in order
+    fn filter_rows(&self, rows: Vec<JobType>, stats: &mut FilterStats) -> Vec<JobType> {
+        if self.filter_config.sql_filters.is_none() {
+            return rows;
+        }
+        let filter_chunk = |rows: Vec<JobType>| {
+            let mut stats = FilterStats::default();
+            let rows: Vec<JobType> = rows
+                .into_iter()
+                .filter_map(|mut row| self.filter_row(&mut row, &mut stats).then_some(row))
+                .collect();
+            (rows, stats)
+        };
+
+        let threads = filter_threads().min(rows.len().div_ceil(FILTER_CHUNK_ROWS));
+        if threads <= 1 {
+            let (rows, chunk_stats) = filter_chunk(rows);
+            stats.merge(&chunk_stats);
+            return rows;
+        }
+
+        let chunk_size = rows.len().div_ceil(threads);
+        let mut chunks = Vec::with_capacity(threads);
+        let mut rows = rows.into_iter();
+        loop {
+            let chunk: Vec<JobType> = rows.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            chunks.push(chunk);
+        }
+
+        let filtered = std::thread::scope(|scope| {
+            let handles: Vec<_> = chunks
+                .into_iter()
+                .map(|chunk| scope.spawn(|| filter_chunk(chunk)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("filter thread panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        let mut kept = Vec::with_capacity(filtered.iter().map(|(rows, _)| rows.len()).sum());
+        for (rows, chunk_stats) in filtered {
+            kept.extend(rows);
+            stats.merge(&chunk_stats);
+        }
+        kept
+    }
+
+    /// Apply the rules of the filters spanning all rows of a result, such
+    /// as privacy thresholds and row limits, to rows that passed the
+    /// per-row filters
+    fn filter_result_set(&self, rows: Vec<JobType>, stats: &mut FilterStats) -> Vec<JobType> {
+        match &self.filter_config.sql_filters {
+            Some(filters) => {
+                let rows = filters.protect_rows(rows, stats);
+                filters.limit_rows(rows, stats)
+            }
+            None => rows,
+        }
+    }
+
+    /// Apply column and value filters to the labels of time series records,
+    /// dropping records with an excluded label and rewriting masked ones
+    pub fn filter_labels(&self, records: Vec<Record>) -> Vec<Record> {
+        if self.filter_config.sql_filters.is_none() {
+            return records;
+        }
+
+        let mut stats = FilterStats::default();
+        records
+            .into_iter()
+            .filter_map(|mut record| {
+                let mut labels: JobType = record
+                    .labels
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+                    .collect();
+                if !self.filter_row(&mut labels, &mut stats) {
+                    return None;
+                }
+                record.labels = labels
+                    .into_iter()
+                    .filter_map(|(key, value)| match value {
+                        Value::Null => None,
+                        Value::String(text) => Some((key, text)),
+                        value => Some((key, value.to_string())),
+                    })
+                    .collect();
+                Some(record)
+            })
+            .collect()
+    }
+
+    /// Run a query over HTTP with extra ClickHouse settings, parsing each
+    /// row as a JSON object keyed by column name and returning the columns
+    /// along with the rows
+    async fn fetch_json_rows(
+        &self,
+        query: &str,
+        settings: &[(&str, &str)],
+    ) -> Result<(Vec<JobType>, Vec<ResultColumn>), QueryError> {
+        let mut rows = Vec::new();
+        let columns = self
+            .stream_json_rows(query, settings, |row| rows.push(row))
+            .await?;
+        Ok((rows, columns))
+    }
+
+    /// Run a query over HTTP with extra ClickHouse settings, passing each
+    /// row to `on_row` as soon as it arrives and returning the columns
+    ///
+    /// The response body is parsed chunk by chunk rather than read whole
+    async fn stream_json_rows(
+        &self,
+        query: &str,
+        settings: &[(&str, &str)],
+        mut on_row: impl FnMut(JobType),
+    ) -> Result<Vec<ResultColumn>, QueryError> {
+        let full_query = format!("{} FORMAT JSONCompactEachRowWithNamesAndTypes", query);
+        let mut response = self.send_request(full_query, settings).await?;
+
+        let mut parser = JsonRowsParser::default();
+        let parse_error = |e: LineError| {
+            log::error!("JSON parsing error for line: {}", self.redact(&e.line));
+            QueryError::ExecutionError(e.error.to_string())
+        };
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| QueryError::ExecutionError(e.to_string()))?
+        {
+            parser.feed(&chunk, &mut on_row).map_err(parse_error)?;
+        }
+        parser.finish(&mut on_row).map_err(parse_error)?;
+
+        Ok(parser
+            .names()
+            .iter()
+            .zip(parser.types())
+            .map(|(name, ch_type)| ResultColumn::from_clickhouse_type(name, ch_type))
+            .collect())
+    }
+
+    /// Count rows a query returned and the filters left out
+    fn record_rows(&self, returned: usize, filtered: usize) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.rows_returned += returned as u64;
+        stats.rows_filtered += filtered as u64;
+    }
+
+    /// Count rows and bytes read as reported in the `X-ClickHouse-Summary`
+    /// header, whose numbers are strings such as `{"read_rows":"10", ...}`
+    fn record_summary(&self, response: &reqwest::Response) {
+        let Some(summary) = response
+            .headers()
+            .get(SUMMARY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| serde_json::from_str::<HashMap<String, Value>>(value).ok())
+        else {
+            return;
+        };
+        let count = |key: &str| match summary.get(key) {
+            Some(Value::String(text)) => text.parse().unwrap_or(0),
+            Some(value) => value.as_u64().unwrap_or(0),
+            None => 0,
+        };
+        let mut stats = self.stats.lock().unwrap();
+        stats.rows_read += count("read_rows");
+        stats.bytes_read += count("read_bytes");
+    }
+
+    /// Send a query over HTTP on the executor's session if any, returning
+    /// the response body
+    async fn send_query(
+        &self,
+        query: String,
+        settings: &[(&str, &str)],
+    ) -> Result<String, QueryError> {
+        self.send_request(query, settings)
+            .await?
+            .text()
+            .await
+            .map_err(|e| QueryError::ExecutionError(e.to_string()))
+    }
+
+    /// Send a query over HTTP on the executor's session if any, returning
+    /// the response once its headers arrived
+    async fn send_request(
+        &self,
+        query: String,
+        settings: &[(&str, &str)],
+    ) -> Result<reqwest::Response, QueryError> {
+        let mut request = self.http.post(self.url.clone());
+        if let Some(session_id) = &self.session_id {
+            request = request.query(&[("session_id", session_id)]);
+        }
+        if let Some(database) = &self.database {
+            request = request.query(&[("database", database)]);
+        }
+
+        // Send request to ClickHouse server, which cancels the query if the
+        // connection closes, e.g. when the task times out
+        request = match self.client.authorization().await? {
+            Some(authorization) => request.header(reqwest::header::AUTHORIZATION, authorization),
+            None => request.basic_auth(self.username.clone(), Some(self.password.clone())),
+        };
+        let response = request
+            .query(&[("cancel_http_readonly_queries_on_client_close", "1")])
+            .query(settings)
+            .body(query)
+            .send()
+            .await
+            .map_err(|e| {
+                log::error!("HTTP request error: {}", e);
+                QueryError::ConnectionError(e.to_string())
+            })?
+            .error_for_status()
+            .map_err(|e| {
+                let message = self.redact(&e.to_string());
+                log::error!("HTTP response error: {}", message);
+                QueryError::ExecutionError(message)
+            })?;
+        self.record_summary(&response);
+        Ok(response)
+    }
+}
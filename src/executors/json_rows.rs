@@ -0,0 +1,93 @@
+//! Incremental parser of `JSONCompactEachRowWithNamesAndTypes` results
+//!
+//! The body is fed in chunks as they arrive from ClickHouse and each row is
+//! handed over as soon as its line is complete, so a large result is never
+//! held in memory both as text and as parsed rows.
+
+use serde_json::Value;
+
+use crate::models::JobType;
+
+/// Line of a result body that isn't valid JSON
+#[derive(Debug)]
+pub struct LineError {
+    pub line: String,
+    pub error: serde_json::Error,
+}
+
+/// Parser of a result body fed in chunks
+///
+/// The first two lines hold the names and types of the columns, and each
+/// following one the values of a row in the same order. ClickHouse escapes
+/// line breaks inside values, so every line break ends a row
+#[derive(Debug, Default)]
+pub struct JsonRowsParser {
+    /// Start of a line not yet complete
+    buffer: Vec<u8>,
+    names: Option<Vec<String>>,
+    types: Option<Vec<String>>,
+}
+
+impl JsonRowsParser {
+    /// Column names, once the first line was parsed
+    pub fn names(&self) -> &[String] {
+        self.names.as_deref().unwrap_or_default()
+    }
+
+    /// Column types, once the second line was parsed
+    pub fn types(&self) -> &[String] {
+        self.types.as_deref().unwrap_or_default()
+    }
+
+    /// Parse the lines completed by `chunk`, passing each row to `on_row`
+    pub fn feed(
+        &mut self,
+        chunk: &[u8],
+        on_row: &mut impl FnMut(JobType),
+    ) -> Result<(), LineError> {
+        let mut rest = chunk;
+        while let Some(end) = rest.iter().position(|&byte| byte == b'\n') {
+            if self.buffer.is_empty() {
+                self.parse_line(&rest[..end], on_row)?;
+            } else {
+                // The line started in an earlier chunk
+                let mut line = std::mem::take(&mut self.buffer);
+                line.extend_from_slice(&rest[..end]);
+                self.parse_line(&line, on_row)?;
+            }
+            rest = &rest[end + 1..];
+        }
+        self.buffer.extend_from_slice(rest);
+        Ok(())
+    }
+
+    /// Parse the last line if the body didn't end with a line break
+    pub fn finish(&mut self, on_row: &mut impl FnMut(JobType)) -> Result<(), LineError> {
+        let line = std::mem::take(&mut self.buffer);
+        self.parse_line(&line, on_row)
+    }
+
+    fn parse_line(
+        &mut self,
+        line: &[u8],
+        on_row: &mut impl FnMut(JobType),
+    ) -> Result<(), LineError> {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        let line_error = |error| LineError {
+            line: String::from_utf8_lossy(line).into_owned(),
+            error,
+        };
+
+        if self.names.is_none() {
+            self.names = Some(serde_json::from_slice(line).map_err(line_error)?);
+        } else if self.types.is_none() {
+            self.types = Some(serde_json::from_slice(line).map_err(line_error)?);
+        } else {
+            let values: Vec<Value> = serde_json::from_slice(line).map_err(line_error)?;
+            on_row(self.names().iter().cloned().zip(values).collect());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//! Executor answering queries with canned results instead of a database
+//!
+//! A `mock` datasource runs the whole acquire, execute, filter and submit
+//! loop without ClickHouse, for integration tests or to try the agent out.
+//! Each query is answered by the first configured result whose
+//! `query_regex` matches it, and its rows go through the datasource's
+//! filters like the rows of a real database would.
+
+use async_trait::async_trait;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use super::base::{DiscoveryOptions, ExecutionStats, QueryError, QueryExecutor, UserAccess};
+use super::clickhouse_source::{ClickhouseExecutor, ColumnInfo, ResultColumn, TableSchema};
+use crate::config::{GlobalFilters, MockConfig, MockResult};
+use crate::filters::FilterStats;
+use crate::models::{JobType, Record};
+use crate::timeseries::{NullStats, TsMapping};
+
+/// Executor of a `mock` datasource
+pub struct MockExecutor {
+    config: MockConfig,
+    /// Compiled `query_regex` of each result, in the same order
+    patterns: Vec<Option<Regex>>,
+    /// Applies the datasource's filters. Never connects to its host
+    filters: ClickhouseExecutor,
+    /// Rows returned and filtered by the queries run so far
+    stats: Mutex<ExecutionStats>,
+}
+
+impl MockExecutor {
+    /// Create an executor answering with the results of `config`, filtered
+    /// by `filters`
+    pub fn new(config: MockConfig, filters: Option<GlobalFilters>) -> Result<Self, QueryError> {
+        let patterns = config
+            .results
+            .iter()
+            .map(|result| {
+                result
+                    .query_regex
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .map_err(|e| QueryError::ExecutionError(format!("Invalid query_regex: {}", e)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            config,
+            patterns,
+            filters: ClickhouseExecutor::with_global_filters("http://localhost", "", "", filters)?,
+            stats: Mutex::default(),
+        })
+    }
+
+    /// Result answering `query`, failing if there's none or it's an error
+    fn result(&self, query: &str) -> Result<&MockResult, QueryError> {
+        let result = self
+            .config
+            .results
+            .iter()
+            .zip(&self.patterns)
+            .find(|(_, pattern)| pattern.as_ref().is_none_or(|regex| regex.is_match(query)))
+            .map(|(result, _)| result)
+            .ok_or_else(|| {
+                QueryError::ExecutionError("No mock result matches the query".to_string())
+            })?;
+        match &result.error {
+            Some(error) => Err(QueryError::ExecutionError(error.clone())),
+            None => Ok(result),
+        }
+    }
+
+    fn record_rows(&self, returned: usize, filtered: usize) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.rows_returned += returned as u64;
+        stats.rows_filtered += filtered as u64;
+    }
+}
+
+/// Columns of a result, as configured or inferred from the values of its
+/// first row in name order
+fn result_columns(result: &MockResult) -> Vec<ResultColumn> {
+    if !result.columns.is_empty() {
+        return result
+            .columns
+            .iter()
+            .map(|column| ResultColumn::from_clickhouse_type(&column.name, &column.type_name))
+            .collect();
+    }
+    let Some(row) = result.rows.first() else {
+        return Vec::new();
+    };
+    row.iter()
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(name, value)| ResultColumn::from_value(name, value))
+        .collect()
+}
+
+#[async_trait]
+impl QueryExecutor for MockExecutor {
+    async fn execute_ts_with(
+        &self,
+        query: &str,
+        mapping: &TsMapping,
+    ) -> Result<(Vec<Record>, NullStats), QueryError> {
+        let rows = self.result(query)?.rows.clone();
+        let returned = rows.len();
+        let (records, null_stats) = mapping
+            .records(rows)
+            .map_err(|e| QueryError::ExecutionError(e.to_string()))?;
+        let mapped = records.len();
+        let records = self.filters.filter_labels(records);
+        self.record_rows(returned, mapped - records.len());
+        Ok((records, null_stats))
+    }
+
+    async fn execute_job_with_columns(
+        &self,
+        query: &str,
+    ) -> Result<(Vec<JobType>, FilterStats, Vec<ResultColumn>), QueryError> {
+        let result = self.result(query)?;
+        let mut columns = result_columns(result);
+        let returned = result.rows.len();
+        let (rows, stats) = self
+            .filters
+            .filter_job_results_with_stats(result.rows.clone());
+        self.record_rows(returned, returned - rows.len());
+        self.filters.filter_config().retype_columns(&mut columns);
+        Ok((rows, stats, columns))
+    }
+
+    async fn execute_statement(&self, _query: &str) -> Result<(), QueryError> {
+        Ok(())
+    }
+
+    fn set_session(&mut self, _session_id: Option<String>) {}
+
+    async fn connect(&mut self) -> Result<(), QueryError> {
+        Ok(())
+    }
+
+    async fn discover_schemas_with(
+        &self,
+        _options: &DiscoveryOptions,
+    ) -> Result<Vec<TableSchema>, QueryError> {
+        let filter_config = self.filters.filter_config();
+        Ok(self
+            .config
+            .tables
+            .iter()
+            .filter(|table| !filter_config.should_exclude_database(&table.database))
+            .filter(|table| !filter_config.should_exclude_table(&table.database, &table.table))
+            .map(|table| TableSchema {
+                database: table.database.clone(),
+                table: table.table.clone(),
+                row_count: table.row_count,
+                columns: table
+                    .columns
+                    .iter()
+                    .filter(|column| !filter_config.should_exclude_column(&column.name))
+                    .map(|column| {
+                        (
+                            column.name.clone(),
+                            ColumnInfo::from_clickhouse_type(&column.type_name),
+                        )
+                    })
+                    .collect(),
+                partial: false,
+                freshness: BTreeMap::new(),
+                growth: None,
+            })
+            .collect())
+    }
+
+    fn filter_job_results_with_stats(&self, rows: Vec<JobType>) -> (Vec<JobType>, FilterStats) {
+        self.filters.filter_job_results_with_stats(rows)
+    }
+
+    /// Mock datasources can't be written to
+    async fn user_access(&self) -> Result<UserAccess, QueryError> {
+        Ok(UserAccess {
+            readonly: true,
+            grants: Vec::new(),
+        })
+    }
+
+    fn execution_stats(&self) -> ExecutionStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
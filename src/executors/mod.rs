@@ -1,29 +1,71 @@
 pub mod base;
 pub mod clickhouse_source;
-use crate::config::GlobalFilters;
-use crate::executors::{base::QueryExecutor, clickhouse_source::ClickhouseExecutor};
+pub mod json_rows;
+pub mod mock;
+use crate::config::{DatasourceAuth, GlobalFilters};
+use crate::executors::{
+    base::QueryExecutor, clickhouse_source::ClickhouseExecutor, mock::MockExecutor,
+};
+use crate::host_resolver::host_resolver;
+use crate::http::datasource_client;
+use crate::kerberos::KerberosAuth;
+use crate::load_balancer::load_balancer;
 use crate::models::{DataSource, DataSourceType};
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 
 /// Create an appropriate executor based on the datasource type
+///
+/// Filter rules defined on the datasource override the matching global ones
 pub async fn create_executor(
     datasource: &DataSource,
     global_filters: Option<GlobalFilters>,
 ) -> Result<Box<dyn QueryExecutor>> {
-    let host: &String = datasource
-        .hosts
-        .first()
+    // Mock datasources have no hosts to connect to
+    if datasource.source_type == DataSourceType::Mock {
+        let filters = datasource.effective_filters(global_filters.as_ref());
+        return Ok(Box::new(MockExecutor::new(
+            datasource.mock.clone(),
+            filters,
+        )?));
+    }
+
+    // SRV and Consul hosts are resolved to their current replicas, one of
+    // which is picked with the datasource's strategy
+    let hosts = host_resolver().resolve_hosts(datasource).await?;
+    let host = &load_balancer()
+        .select(datasource, &hosts)
         .ok_or_else(|| anyhow!("No host specified for Clickhouse datasource"))?;
 
     match datasource.source_type {
-        DataSourceType::Clickhouse => Ok(Box::new(ClickhouseExecutor::with_global_filters(
-            host,
-            &datasource.username,
-            &datasource.password,
-            global_filters,
-        )?)),
+        DataSourceType::Clickhouse => {
+            // Read on every executor, so rotated credential files are used
+            let (username, password) = datasource.credentials()?;
+            let executor = ClickhouseExecutor::with_global_filters(
+                host,
+                &username,
+                &password,
+                datasource.effective_filters(global_filters.as_ref()),
+            )?
+            .with_exact_numbers(datasource.exact_numbers)
+            .with_database(datasource.database.as_deref())
+            .with_memory_budget(datasource.job_memory_budget_mb)
+            .with_http_client(datasource_client(&datasource.name));
+            let executor = match &datasource.auth {
+                DatasourceAuth::Password => executor,
+                DatasourceAuth::Kerberos(config) => {
+                    executor.with_kerberos(KerberosAuth::new(config, host)?)
+                }
+                DatasourceAuth::Iam(_) => {
+                    bail!(
+                        "IAM authentication is only supported by PostgreSQL and MySQL datasources"
+                    )
+                }
+            };
+            Ok(Box::new(executor))
+        }
         DataSourceType::PostgreSQL => Err(anyhow!("PostgreSQL executor not implemented")),
         DataSourceType::MySQL => Err(anyhow!("MySQL executor not implemented")),
         DataSourceType::Prometheus => Err(anyhow!("Prometheus executor not implemented")),
+        DataSourceType::Mock => unreachable!("mock executors are created above"),
     }
 }
@@ -1,179 +1,1097 @@
-use crate::config::{GlobalFilters, SqlFilterRules};
-use regex::Regex;
+use crate::config::{
+    FilterAction, FilterRule, GlobalFilters, MatchMode, NumericRange, RuleAction, SqlFilterRules,
+};
+use crate::models::JobType;
+use crate::privacy::Privacy;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+
+/// Replacement for values of the `redact_value` action
+pub const REDACTED_VALUE: &str = "[REDACTED]";
+
+/// Number of trailing characters the `mask` action keeps visible
+const MASK_VISIBLE_SUFFIX: usize = 4;
+
+/// Error building filters from configuration
+#[derive(Debug, thiserror::Error)]
+pub enum FilterError {
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+    #[error("the hash filter action requires global_filters.hash_key to be set")]
+    MissingHashKey,
+    #[error("sample_rate must be greater than 0 and at most 1, got {0}")]
+    InvalidSampleRate(f64),
+    #[error("invalid privacy settings: {0}")]
+    InvalidPrivacy(String),
+}
+
+/// Name reported for values rejected because no allow rule matched them
+pub const ALLOW_LIST_RULE: &str = "sql_filters_allow";
+
+/// Filter pattern along with its action and the rule it came from
+#[derive(Debug, Clone)]
+struct RulePattern {
+    regex: Regex,
+    action: FilterAction,
+    rule: String,
+}
+
+/// Strings shorter than this are never considered high-entropy
+pub const ENTROPY_MIN_LENGTH: usize = 16;
+
+/// Non-regex condition of an exclude rule on values
+#[derive(Debug, Clone)]
+enum ValueCheck {
+    MaxLength(usize),
+    NumericRange(NumericRange),
+    MaxEntropy(f64),
+}
+
+impl ValueCheck {
+    /// Check if a string breaks the condition
+    fn matches_str(&self, value: &str) -> bool {
+        match self {
+            ValueCheck::MaxLength(max_length) => value.chars().count() > *max_length,
+            ValueCheck::NumericRange(range) => value
+                .trim()
+                .parse::<f64>()
+                .is_ok_and(|number| out_of_range(range, number)),
+            ValueCheck::MaxEntropy(max_entropy) => {
+                value.chars().count() >= ENTROPY_MIN_LENGTH && shannon_entropy(value) > *max_entropy
+            }
+        }
+    }
+
+    /// Check if a number breaks the condition
+    fn matches_number(&self, number: f64) -> bool {
+        match self {
+            ValueCheck::NumericRange(range) => out_of_range(range, number),
+            ValueCheck::MaxLength(_) | ValueCheck::MaxEntropy(_) => false,
+        }
+    }
+}
+
+/// Value check along with its action and the rule it came from
+#[derive(Debug, Clone)]
+struct RuleCheck {
+    check: ValueCheck,
+    /// Condition as reported by explanations, e.g. `max_length 64`
+    description: String,
+    action: FilterAction,
+    rule: String,
+}
+
+impl RuleCheck {
+    fn rule_match(&self) -> RuleMatch<'_> {
+        RuleMatch {
+            action: self.action,
+            rule: &self.rule,
+            pattern: Some(&self.description),
+        }
+    }
+}
+
+/// Condition of an ordered rule on a name or value
+#[derive(Debug, Clone)]
+enum Condition {
+    Pattern(Regex),
+    /// Pattern matched against qualified `database.table` names only
+    Qualified(Regex),
+    /// Value check along with its description
+    Check(ValueCheck, String),
+}
+
+/// Condition of an ordered rule along with its action and the rule's name
+#[derive(Debug, Clone)]
+struct OrderedPattern {
+    condition: Condition,
+    action: RuleAction,
+    rule: String,
+}
+
+impl OrderedPattern {
+    /// Check if the condition holds for a name, or for a value whose patterns
+    /// are matched with spaces removed
+    fn matches(&self, text: &str, stripped: &str) -> bool {
+        match &self.condition {
+            Condition::Pattern(regex) => regex.is_match(stripped),
+            Condition::Check(check, _) => check.matches_str(text),
+            Condition::Qualified(_) => false,
+        }
+    }
+
+    /// Check if the condition holds for a table of a database
+    fn matches_table(&self, table_name: &str, qualified_name: &str) -> bool {
+        match &self.condition {
+            Condition::Pattern(regex) => regex.is_match(table_name),
+            Condition::Qualified(regex) => regex.is_match(qualified_name),
+            Condition::Check(..) => false,
+        }
+    }
+
+    fn rule_match(&self) -> RuleMatch<'_> {
+        RuleMatch {
+            action: match self.action {
+                RuleAction::Allow | RuleAction::Deny => FilterAction::DropRow,
+                RuleAction::Mask => FilterAction::Mask,
+                RuleAction::RedactValue => FilterAction::RedactValue,
+                RuleAction::Hash => FilterAction::Hash,
+            },
+            rule: &self.rule,
+            pattern: Some(match &self.condition {
+                Condition::Pattern(regex) | Condition::Qualified(regex) => regex.as_str(),
+                Condition::Check(_, description) => description,
+            }),
+        }
+    }
+
+    fn decision(&self) -> Decision<'_> {
+        match self.action {
+            RuleAction::Allow => Decision::Allowed(self.rule_match()),
+            _ => Decision::Excluded(self.rule_match()),
+        }
+    }
+}
+
+/// Conditions of the ordered `rules` list by what they apply to, each list
+/// in the order of the rules
+#[derive(Debug, Clone, Default)]
+struct OrderedRules {
+    databases: Vec<OrderedPattern>,
+    tables: Vec<OrderedPattern>,
+    columns: Vec<OrderedPattern>,
+    values: Vec<OrderedPattern>,
+}
+
+impl OrderedRules {
+    /// Decision of the first rule whose condition holds
+    fn decide<'a>(
+        patterns: &'a [OrderedPattern],
+        matches: impl Fn(&OrderedPattern) -> bool,
+    ) -> Option<Decision<'a>> {
+        patterns
+            .iter()
+            .find(|pattern| matches(pattern))
+            .map(OrderedPattern::decision)
+    }
+}
+
+/// Exclude or allow rule matching a column or value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RuleMatch<'a> {
+    pub action: FilterAction,
+    /// Rule name, `sql_filters_exclude[<index>]` for unnamed rules
+    pub rule: &'a str,
+    /// Pattern that matched, `None` for values no allow rule matched
+    pub pattern: Option<&'a str>,
+}
+
+/// Outcome of checking a database, table, column or value against the rules
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision<'a> {
+    /// No rule matched
+    Kept,
+    /// An allow rule matched and no exclude rule did
+    Allowed(RuleMatch<'a>),
+    /// Allow rules are set but none of them matched
+    NotAllowed,
+    /// An exclude rule matched, dropping or rewriting the data
+    Excluded(RuleMatch<'a>),
+}
+
+impl<'a> Decision<'a> {
+    /// Rule rejecting or rewriting the data, `None` when it is kept as is
+    pub fn rule_match(self) -> Option<RuleMatch<'a>> {
+        match self {
+            Decision::NotAllowed => Some(RuleMatch {
+                action: FilterAction::DropRow,
+                rule: ALLOW_LIST_RULE,
+                pattern: None,
+            }),
+            Decision::Excluded(rule_match) => Some(rule_match),
+            Decision::Kept | Decision::Allowed(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Decision<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (outcome, rule_match) = match self {
+            Decision::Kept => return write!(f, "kept, no rule matched"),
+            Decision::NotAllowed => {
+                return write!(f, "excluded, no {} rule matched", ALLOW_LIST_RULE)
+            }
+            Decision::Allowed(rule_match) => ("kept, allowed", rule_match),
+            Decision::Excluded(rule_match) => match rule_match.action {
+                FilterAction::DropRow => ("excluded", rule_match),
+                FilterAction::Mask => ("masked", rule_match),
+                FilterAction::RedactValue => ("redacted", rule_match),
+                FilterAction::Hash => ("hashed", rule_match),
+            },
+        };
+        write!(f, "{} by rule '{}'", outcome, rule_match.rule)?;
+        if let Some(pattern) = rule_match.pattern {
+            write!(f, " (pattern '{}')", pattern)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts of data dropped or rewritten by filters while processing a job
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilterStats {
+    pub dropped_rows: u64,
+    /// Values of kept rows rewritten by `mask`, `redact_value` or `hash` rules
+    pub masked_values: u64,
+    /// Number of times each rule triggered, by rule name
+    pub rules: BTreeMap<String, u64>,
+    /// Rows left out by `sample_rate` or `max_rows`
+    pub sampled_rows: u64,
+    /// Groups below `privacy.min_group_size` left out
+    pub suppressed_rows: u64,
+    /// Aggregate values `privacy` added noise to
+    pub noised_values: u64,
+}
+
+impl FilterStats {
+    /// Record a rule that triggered
+    pub fn record(&mut self, rule_match: &RuleMatch) {
+        match rule_match.action {
+            FilterAction::DropRow => self.dropped_rows += 1,
+            _ => self.masked_values += 1,
+        }
+        *self.rules.entry(rule_match.rule.to_string()).or_default() += 1;
+    }
+
+    /// Check if no filter rule triggered and rows were neither sampled out
+    /// nor changed for privacy
+    pub fn is_empty(&self) -> bool {
+        self.dropped_rows == 0
+            && self.masked_values == 0
+            && !self.sampled()
+            && self.suppressed_rows == 0
+            && self.noised_values == 0
+    }
+
+    /// Check if submitted rows are a subset of the rows that passed the filters
+    pub fn sampled(&self) -> bool {
+        self.sampled_rows > 0
+    }
+
+    /// Add the counts of another query of the same job
+    pub fn merge(&mut self, other: &FilterStats) {
+        self.dropped_rows += other.dropped_rows;
+        self.masked_values += other.masked_values;
+        for (rule, count) in &other.rules {
+            *self.rules.entry(rule.clone()).or_default() += count;
+        }
+        self.sampled_rows += other.sampled_rows;
+        self.suppressed_rows += other.suppressed_rows;
+        self.noised_values += other.noised_values;
+    }
+}
+
+impl std::fmt::Display for FilterStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} rows dropped, {} values masked",
+            self.dropped_rows, self.masked_values
+        )?;
+        if !self.rules.is_empty() {
+            let rules: Vec<String> = self
+                .rules
+                .iter()
+                .map(|(rule, count)| format!("{}: {}", rule, count))
+                .collect();
+            write!(f, " ({})", rules.join(", "))?;
+        }
+        if self.sampled() {
+            write!(f, ", {} rows sampled out", self.sampled_rows)?;
+        }
+        if self.suppressed_rows > 0 {
+            write!(f, ", {} groups suppressed", self.suppressed_rows)?;
+        }
+        if self.noised_values > 0 {
+            write!(f, ", {} values noised", self.noised_values)?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct SqlFilters {
     // Exclude filters
-    exclude_database_patterns: Vec<Regex>,
-    exclude_table_patterns: Vec<Regex>,
-    exclude_column_name_patterns: Vec<Regex>,
-    exclude_column_value_patterns: Vec<Regex>,
+    exclude_database_patterns: Vec<RulePattern>,
+    exclude_table_patterns: Vec<RulePattern>,
+    exclude_qualified_table_patterns: Vec<RulePattern>,
+    exclude_column_name_patterns: Vec<RulePattern>,
+    exclude_column_value_patterns: Vec<RulePattern>,
+    exclude_value_checks: Vec<RuleCheck>,
+
+    // Ordered rules, evaluated first
+    ordered_rules: OrderedRules,
 
     // Allow filters
-    allow_database_patterns: Vec<Regex>,
-    allow_table_patterns: Vec<Regex>,
-    allow_column_name_patterns: Vec<Regex>,
-    allow_column_value_patterns: Vec<Regex>,
+    allow_database_patterns: Vec<RulePattern>,
+    allow_table_patterns: Vec<RulePattern>,
+    allow_qualified_table_patterns: Vec<RulePattern>,
+    allow_column_name_patterns: Vec<RulePattern>,
+    allow_column_value_patterns: Vec<RulePattern>,
+
+    // Key of the hash action
+    hash_key: Option<Vec<u8>>,
+
+    // Bounds of job results
+    max_rows: Option<usize>,
+    sample_rate: Option<f64>,
+    privacy: Option<Privacy>,
 }
 
 impl SqlFilters {
-    pub fn new(global_filters: Option<&GlobalFilters>) -> Result<Self, regex::Error> {
+    pub fn new(global_filters: Option<&GlobalFilters>) -> Result<Self, FilterError> {
         let mut filters = SqlFilters {
             exclude_database_patterns: Vec::new(),
             exclude_table_patterns: Vec::new(),
+            exclude_qualified_table_patterns: Vec::new(),
             exclude_column_name_patterns: Vec::new(),
             exclude_column_value_patterns: Vec::new(),
+            exclude_value_checks: Vec::new(),
+            ordered_rules: OrderedRules::default(),
             allow_database_patterns: Vec::new(),
             allow_table_patterns: Vec::new(),
+            allow_qualified_table_patterns: Vec::new(),
             allow_column_name_patterns: Vec::new(),
             allow_column_value_patterns: Vec::new(),
+            hash_key: None,
+            max_rows: None,
+            sample_rate: None,
+            privacy: None,
         };
 
         if let Some(global_filters) = global_filters {
+            // Process ordered rules
+            if let Some(rules) = &global_filters.rules {
+                for (index, rule) in rules.iter().enumerate() {
+                    filters.add_ordered_rule(rule, index)?;
+                }
+            }
+
             // Process exclude filters
             if let Some(exclude_rules) = &global_filters.sql_filters_exclude {
-                for rule in exclude_rules {
-                    filters.add_exclude_patterns(rule)?;
+                for (index, rule) in exclude_rules.iter().enumerate() {
+                    filters.add_exclude_patterns(rule, index)?;
                 }
             }
 
             // Process allow filters
             if let Some(allow_rules) = &global_filters.sql_filters_allow {
-                for rule in allow_rules {
-                    filters.add_allow_patterns(rule)?;
+                for (index, rule) in allow_rules.iter().enumerate() {
+                    filters.add_allow_patterns(rule, index)?;
                 }
             }
+
+            filters.hash_key = global_filters
+                .hash_key
+                .as_ref()
+                .map(|key| key.as_bytes().to_vec());
+
+            if let Some(rate) = global_filters.sample_rate {
+                if !(rate > 0.0 && rate <= 1.0) {
+                    return Err(FilterError::InvalidSampleRate(rate));
+                }
+            }
+            filters.max_rows = global_filters.max_rows;
+            filters.sample_rate = global_filters.sample_rate;
+            filters.privacy = global_filters
+                .privacy
+                .as_ref()
+                .map(Privacy::new)
+                .transpose()?;
+        }
+
+        let uses_hash = filters
+            .exclude_column_name_patterns
+            .iter()
+            .chain(&filters.exclude_column_value_patterns)
+            .map(|pattern| pattern.action)
+            .chain(
+                filters
+                    .exclude_value_checks
+                    .iter()
+                    .map(|check| check.action),
+            )
+            .any(|action| action == FilterAction::Hash);
+        let ordered_hash = [
+            &filters.ordered_rules.columns,
+            &filters.ordered_rules.values,
+        ]
+        .into_iter()
+        .flatten()
+        .any(|pattern| pattern.action == RuleAction::Hash);
+        if (uses_hash || ordered_hash) && filters.hash_key.is_none() {
+            return Err(FilterError::MissingHashKey);
         }
 
         Ok(filters)
     }
 
-    fn add_exclude_patterns(&mut self, rules: &SqlFilterRules) -> Result<(), regex::Error> {
-        if let Some(patterns) = &rules.database_regexes {
-            for pattern in patterns {
-                self.exclude_database_patterns.push(Regex::new(pattern)?);
-            }
-        }
+    fn add_exclude_patterns(
+        &mut self,
+        rules: &SqlFilterRules,
+        index: usize,
+    ) -> Result<(), FilterError> {
+        let rule = rule_name(rules, "sql_filters_exclude", index);
+        let action = rules.action.unwrap_or_default();
 
-        if let Some(patterns) = &rules.table_regexes {
-            for pattern in patterns {
-                self.exclude_table_patterns.push(Regex::new(pattern)?);
-            }
+        // Databases and tables are either discovered or not, so their rules
+        // always exclude
+        push_patterns(
+            &mut self.exclude_database_patterns,
+            rules,
+            &rules.database_regexes,
+            &rule,
+            FilterAction::DropRow,
+        )?;
+        push_patterns(
+            &mut self.exclude_table_patterns,
+            rules,
+            &rules.table_regexes,
+            &rule,
+            FilterAction::DropRow,
+        )?;
+        push_patterns(
+            &mut self.exclude_qualified_table_patterns,
+            rules,
+            &rules.qualified_table_regexes,
+            &rule,
+            FilterAction::DropRow,
+        )?;
+        push_patterns(
+            &mut self.exclude_column_name_patterns,
+            rules,
+            &rules.column_name_regexes,
+            &rule,
+            action,
+        )?;
+        push_patterns(
+            &mut self.exclude_column_value_patterns,
+            rules,
+            &rules.column_value_regexes,
+            &rule,
+            action,
+        )?;
+
+        self.exclude_value_checks
+            .extend(
+                value_checks(rules)
+                    .into_iter()
+                    .map(|(check, description)| RuleCheck {
+                        check,
+                        description,
+                        action,
+                        rule: rule.clone(),
+                    }),
+            );
+
+        Ok(())
+    }
+
+    fn add_ordered_rule(&mut self, rule: &FilterRule, index: usize) -> Result<(), FilterError> {
+        let name = rule
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("rules[{}]", index));
+        let matcher = &rule.matcher;
+        let compile_as = |patterns: &Option<Vec<String>>,
+                          condition: fn(Regex) -> Condition,
+                          action: RuleAction| {
+            patterns
+                .iter()
+                .flatten()
+                .map(|pattern| {
+                    Ok(OrderedPattern {
+                        condition: condition(compile_pattern(matcher, pattern)?),
+                        action,
+                        rule: name.clone(),
+                    })
+                })
+                .collect::<Result<Vec<_>, FilterError>>()
+        };
+        let compile = |patterns: &Option<Vec<String>>, action: RuleAction| {
+            compile_as(patterns, Condition::Pattern, action)
+        };
+
+        // Databases and tables are either discovered or not, so rules
+        // rewriting values deny them
+        let listing_action = match rule.action {
+            RuleAction::Allow => RuleAction::Allow,
+            _ => RuleAction::Deny,
+        };
+        let rules = &mut self.ordered_rules;
+        rules
+            .databases
+            .extend(compile(&matcher.database_regexes, listing_action)?);
+        rules
+            .tables
+            .extend(compile(&matcher.table_regexes, listing_action)?);
+        rules.tables.extend(compile_as(
+            &matcher.qualified_table_regexes,
+            Condition::Qualified,
+            listing_action,
+        )?);
+        rules
+            .columns
+            .extend(compile(&matcher.column_name_regexes, rule.action)?);
+        rules
+            .values
+            .extend(compile(&matcher.column_value_regexes, rule.action)?);
+        rules.values.extend(
+            value_checks(matcher)
+                .into_iter()
+                .map(|(check, description)| OrderedPattern {
+                    condition: Condition::Check(check, description),
+                    action: rule.action,
+                    rule: name.clone(),
+                }),
+        );
+
+        Ok(())
+    }
+
+    fn add_allow_patterns(
+        &mut self,
+        rules: &SqlFilterRules,
+        index: usize,
+    ) -> Result<(), FilterError> {
+        let rule = rule_name(rules, ALLOW_LIST_RULE, index);
+        let action = FilterAction::DropRow;
+
+        push_patterns(
+            &mut self.allow_database_patterns,
+            rules,
+            &rules.database_regexes,
+            &rule,
+            action,
+        )?;
+        push_patterns(
+            &mut self.allow_table_patterns,
+            rules,
+            &rules.table_regexes,
+            &rule,
+            action,
+        )?;
+        push_patterns(
+            &mut self.allow_qualified_table_patterns,
+            rules,
+            &rules.qualified_table_regexes,
+            &rule,
+            action,
+        )?;
+        push_patterns(
+            &mut self.allow_column_name_patterns,
+            rules,
+            &rules.column_name_regexes,
+            &rule,
+            action,
+        )?;
+        push_patterns(
+            &mut self.allow_column_value_patterns,
+            rules,
+            &rules.column_value_regexes,
+            &rule,
+            action,
+        )
+    }
+
+    pub fn should_exclude_database(&self, db_name: &str) -> bool {
+        self.explain_database(db_name).rule_match().is_some()
+    }
+
+    pub fn should_exclude_table(&self, table_name: &str) -> bool {
+        self.explain_table(table_name).rule_match().is_some()
+    }
+
+    pub fn should_exclude_qualified_table(&self, db_name: &str, table_name: &str) -> bool {
+        self.explain_qualified_table(db_name, table_name)
+            .rule_match()
+            .is_some()
+    }
+
+    pub fn should_exclude_column(&self, column_name: &str) -> bool {
+        // Columns with values rewritten by an action are still reported
+        self.column_action(column_name) == Some(FilterAction::DropRow)
+    }
+
+    pub fn should_exclude_value(&self, value: &str) -> bool {
+        self.value_action(value) == Some(FilterAction::DropRow)
+    }
+
+    /// Apply `privacy` settings to aggregate job rows, if any
+    pub fn protect_rows(&self, rows: Vec<JobType>, stats: &mut FilterStats) -> Vec<JobType> {
+        match &self.privacy {
+            Some(privacy) => privacy.apply(rows, stats),
+            None => rows,
         }
+    }
 
-        if let Some(patterns) = &rules.column_name_regexes {
-            for pattern in patterns {
-                self.exclude_column_name_patterns.push(Regex::new(pattern)?);
+    /// Whether rules span all rows of a job result, i.e. `privacy`,
+    /// `sample_rate` or `max_rows`
+    pub fn has_result_set_rules(&self) -> bool {
+        self.privacy.is_some() || self.sample_rate.is_some() || self.max_rows.is_some()
+    }
+
+    /// Bound job rows by `sample_rate` and `max_rows`, counting rows left out
+    ///
+    /// Rows are kept with probability `sample_rate`. Without sampling,
+    /// `max_rows` keeps the first rows so ordered results stay meaningful;
+    /// with sampling, the cap draws a uniform reservoir sample instead.
+    pub fn limit_rows<T>(&self, rows: Vec<T>, stats: &mut FilterStats) -> Vec<T> {
+        let total = rows.len();
+        let mut rng = rand::thread_rng();
+
+        let rows = match self.sample_rate {
+            Some(rate) if rate < 1.0 => rows.into_iter().filter(|_| rng.gen_bool(rate)).collect(),
+            _ => rows,
+        };
+
+        let rows = match self.max_rows {
+            Some(max_rows) if rows.len() > max_rows => {
+                if self.sample_rate.is_some() {
+                    reservoir_sample(rows, max_rows, &mut rng)
+                } else {
+                    let mut rows = rows;
+                    rows.truncate(max_rows);
+                    rows
+                }
             }
+            _ => rows,
+        };
+
+        stats.sampled_rows += (total - rows.len()) as u64;
+        rows
+    }
+
+    /// First pattern matching `text`, either among patterns dropping rows or
+    /// among those rewriting values
+    fn find_match<'a>(
+        patterns: &'a [RulePattern],
+        text: &str,
+        drop_row: bool,
+    ) -> Option<RuleMatch<'a>> {
+        patterns
+            .iter()
+            .filter(|pattern| (pattern.action == FilterAction::DropRow) == drop_row)
+            .find(|pattern| pattern.regex.is_match(text))
+            .map(|pattern| RuleMatch {
+                action: pattern.action,
+                rule: &pattern.rule,
+                pattern: Some(pattern.regex.as_str()),
+            })
+    }
+
+    /// Check `text` against allow rules first, then against exclude rules
+    /// dropping rows, then against those rewriting values
+    fn decide<'a>(
+        allow: &'a [RulePattern],
+        exclude: &'a [RulePattern],
+        text: &str,
+    ) -> Decision<'a> {
+        let allowed_by = Self::find_match(allow, text, true);
+        if !allow.is_empty() && allowed_by.is_none() {
+            return Decision::NotAllowed;
         }
 
-        if let Some(patterns) = &rules.column_value_regexes {
-            for pattern in patterns {
-                self.exclude_column_value_patterns
-                    .push(Regex::new(pattern)?);
-            }
+        let excluded_by = Self::find_match(exclude, text, true)
+            .or_else(|| Self::find_match(exclude, text, false));
+        match (excluded_by, allowed_by) {
+            (Some(rule_match), _) => Decision::Excluded(rule_match),
+            (None, Some(rule_match)) => Decision::Allowed(rule_match),
+            (None, None) => Decision::Kept,
         }
+    }
 
-        Ok(())
+    /// Explain which rule decides whether a database is discovered
+    pub fn explain_database(&self, db_name: &str) -> Decision<'_> {
+        if let Some(decision) = OrderedRules::decide(&self.ordered_rules.databases, |p| {
+            p.matches(db_name, db_name)
+        }) {
+            return decision;
+        }
+        Self::decide(
+            &self.allow_database_patterns,
+            &self.exclude_database_patterns,
+            db_name,
+        )
     }
 
-    fn add_allow_patterns(&mut self, rules: &SqlFilterRules) -> Result<(), regex::Error> {
-        if let Some(patterns) = &rules.database_regexes {
-            for pattern in patterns {
-                self.allow_database_patterns.push(Regex::new(pattern)?);
-            }
+    /// Explain which rule decides whether a table of a database is discovered,
+    /// checking both table name and qualified `database.table` patterns
+    pub fn explain_qualified_table(&self, db_name: &str, table_name: &str) -> Decision<'_> {
+        let qualified_name = format!("{}.{}", db_name, table_name);
+        if let Some(decision) = OrderedRules::decide(&self.ordered_rules.tables, |p| {
+            p.matches_table(table_name, &qualified_name)
+        }) {
+            return decision;
         }
 
-        if let Some(patterns) = &rules.table_regexes {
-            for pattern in patterns {
-                self.allow_table_patterns.push(Regex::new(pattern)?);
-            }
+        let allowed_by =
+            Self::find_match(&self.allow_table_patterns, table_name, true).or_else(|| {
+                Self::find_match(&self.allow_qualified_table_patterns, &qualified_name, true)
+            });
+        let has_allow_rules = !self.allow_table_patterns.is_empty()
+            || !self.allow_qualified_table_patterns.is_empty();
+        if has_allow_rules && allowed_by.is_none() {
+            return Decision::NotAllowed;
         }
 
-        if let Some(patterns) = &rules.column_name_regexes {
-            for pattern in patterns {
-                self.allow_column_name_patterns.push(Regex::new(pattern)?);
-            }
+        let excluded_by =
+            Self::find_match(&self.exclude_table_patterns, table_name, true).or_else(|| {
+                Self::find_match(
+                    &self.exclude_qualified_table_patterns,
+                    &qualified_name,
+                    true,
+                )
+            });
+        match (excluded_by, allowed_by) {
+            (Some(rule_match), _) => Decision::Excluded(rule_match),
+            (None, Some(rule_match)) => Decision::Allowed(rule_match),
+            (None, None) => Decision::Kept,
         }
+    }
 
-        if let Some(patterns) = &rules.column_value_regexes {
-            for pattern in patterns {
-                self.allow_column_value_patterns.push(Regex::new(pattern)?);
-            }
+    /// Explain which rule decides whether a table is discovered, by its name
+    /// alone
+    pub fn explain_table(&self, table_name: &str) -> Decision<'_> {
+        if let Some(decision) = OrderedRules::decide(&self.ordered_rules.tables, |p| {
+            p.matches(table_name, table_name)
+        }) {
+            return decision;
         }
+        Self::decide(
+            &self.allow_table_patterns,
+            &self.exclude_table_patterns,
+            table_name,
+        )
+    }
 
-        Ok(())
+    /// Explain which rule applies to the values of a column
+    pub fn explain_column(&self, column_name: &str) -> Decision<'_> {
+        if let Some(decision) = OrderedRules::decide(&self.ordered_rules.columns, |p| {
+            p.matches(column_name, column_name)
+        }) {
+            return decision;
+        }
+        Self::decide(
+            &self.allow_column_name_patterns,
+            &self.exclude_column_name_patterns,
+            column_name,
+        )
     }
 
-    pub fn should_exclude_database(&self, db_name: &str) -> bool {
-        // If there are allow patterns and none match, exclude the database
-        if !self.allow_database_patterns.is_empty() {
-            let allowed = self
-                .allow_database_patterns
-                .iter()
-                .any(|pattern| pattern.is_match(db_name));
-            if !allowed {
-                return true;
-            }
+    /// Explain which rule applies to a value
+    ///
+    /// Spaces are ignored by value patterns, so separated card numbers and
+    /// the like still match, but not by length, range and entropy checks.
+    /// Among allow and exclude lists, rules dropping rows take precedence
+    /// over those rewriting values.
+    pub fn explain_value(&self, value: &str) -> Decision<'_> {
+        let stripped = value.replace(' ', "");
+        if let Some(decision) =
+            OrderedRules::decide(&self.ordered_rules.values, |p| p.matches(value, &stripped))
+        {
+            return decision;
         }
 
-        // If any exclude pattern matches, exclude the database
-        self.exclude_database_patterns
-            .iter()
-            .any(|pattern| pattern.is_match(db_name))
+        let decision = Self::decide(
+            &self.allow_column_value_patterns,
+            &self.exclude_column_value_patterns,
+            &stripped,
+        );
+        let check = |drop_row| self.find_value_check(drop_row, |c| c.matches_str(value));
+
+        let checked = match decision {
+            Decision::NotAllowed => None,
+            Decision::Excluded(rule_match) if rule_match.action == FilterAction::DropRow => None,
+            Decision::Excluded(_) => check(true),
+            Decision::Kept | Decision::Allowed(_) => check(true).or_else(|| check(false)),
+        };
+        checked.map(Decision::Excluded).unwrap_or(decision)
     }
 
-    pub fn should_exclude_table(&self, table_name: &str) -> bool {
-        // If there are allow patterns and none match, exclude the table
-        if !self.allow_table_patterns.is_empty() {
-            let allowed = self
-                .allow_table_patterns
-                .iter()
-                .any(|pattern| pattern.is_match(table_name));
-            if !allowed {
-                return true;
-            }
+    /// Rule applied to a numeric value, which only range checks apply to
+    pub fn number_match(&self, number: f64) -> Option<RuleMatch<'_>> {
+        let ordered = OrderedRules::decide(&self.ordered_rules.values, |p| match &p.condition {
+            Condition::Check(check, _) => check.matches_number(number),
+            Condition::Pattern(_) | Condition::Qualified(_) => false,
+        });
+        if let Some(decision) = ordered {
+            return decision.rule_match();
         }
+        self.find_value_check(true, |c| c.matches_number(number))
+            .or_else(|| self.find_value_check(false, |c| c.matches_number(number)))
+    }
 
-        // If any exclude pattern matches, exclude the table
-        self.exclude_table_patterns
+    /// First value check broken, either among checks dropping rows or among
+    /// those rewriting values
+    fn find_value_check(
+        &self,
+        drop_row: bool,
+        matches: impl Fn(&ValueCheck) -> bool,
+    ) -> Option<RuleMatch<'_>> {
+        self.exclude_value_checks
             .iter()
-            .any(|pattern| pattern.is_match(table_name))
+            .filter(|check| (check.action == FilterAction::DropRow) == drop_row)
+            .find(|check| matches(&check.check))
+            .map(RuleCheck::rule_match)
     }
 
-    pub fn should_exclude_column(&self, column_name: &str) -> bool {
-        // If there are allow patterns and none match, exclude the column
-        if !self.allow_column_name_patterns.is_empty() {
-            let allowed = self
-                .allow_column_name_patterns
-                .iter()
-                .any(|pattern| pattern.is_match(column_name));
-            if !allowed {
-                return true;
-            }
+    /// Rule applied to all values of a column, `None` when they are kept as is
+    pub fn column_match(&self, column_name: &str) -> Option<RuleMatch<'_>> {
+        self.explain_column(column_name).rule_match()
+    }
+
+    /// Action applied to all values of a column, `None` when they are kept as is
+    pub fn column_action(&self, column_name: &str) -> Option<FilterAction> {
+        self.column_match(column_name).map(|m| m.action)
+    }
+
+    /// Rewrite a value according to a `mask`, `redact_value` or `hash` action
+    pub fn apply_action(&self, action: FilterAction, value: &Value) -> Value {
+        match (action, value) {
+            (_, Value::Null) | (FilterAction::DropRow, _) => value.clone(),
+            (FilterAction::RedactValue, _) => Value::String(REDACTED_VALUE.to_string()),
+            (FilterAction::Mask, Value::String(value)) => Value::String(mask_str(value)),
+            (FilterAction::Mask, value) => Value::String(mask_str(&value.to_string())),
+            (FilterAction::Hash, Value::String(value)) => Value::String(self.hash_str(value)),
+            (FilterAction::Hash, value) => Value::String(self.hash_str(&value.to_string())),
         }
+    }
 
-        // If any exclude pattern matches, exclude the column
-        self.exclude_column_name_patterns
-            .iter()
-            .any(|pattern| pattern.is_match(column_name))
+    /// Hex-encoded HMAC-SHA256 of a value, equal values always hash the same
+    fn hash_str(&self, value: &str) -> String {
+        // new() guarantees the key is set when any rule uses the hash action
+        let key = self.hash_key.as_deref().unwrap_or_default();
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(value.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
     }
 
-    pub fn should_exclude_value(&self, value: &str) -> bool {
-        // If there are allow patterns and none match, exclude the value
-        if !self.allow_column_value_patterns.is_empty() {
-            let allowed = self
-                .allow_column_value_patterns
-                .iter()
-                .any(|pattern| pattern.is_match(value));
-            if !allowed {
-                return true;
+    /// Rule applied to values under a key of a nested object
+    ///
+    /// Unlike top-level columns, nested keys are only checked against ordered
+    /// and exclude rules, as an allow list of column names can't anticipate
+    /// them
+    pub fn nested_key_match(&self, key: &str) -> Option<RuleMatch<'_>> {
+        if let Some(decision) =
+            OrderedRules::decide(&self.ordered_rules.columns, |p| p.matches(key, key))
+        {
+            return decision.rule_match();
+        }
+        Self::find_match(&self.exclude_column_name_patterns, key, true)
+            .or_else(|| Self::find_match(&self.exclude_column_name_patterns, key, false))
+    }
+
+    /// Action applied to values under a key of a nested object
+    pub fn nested_key_action(&self, key: &str) -> Option<FilterAction> {
+        self.nested_key_match(key).map(|m| m.action)
+    }
+
+    /// Rule applied to a single value, `None` when it is kept as is
+    pub fn value_match(&self, value: &str) -> Option<RuleMatch<'_>> {
+        self.explain_value(value).rule_match()
+    }
+
+    /// Action applied to a single value, `None` when it is kept as is
+    pub fn value_action(&self, value: &str) -> Option<FilterAction> {
+        self.value_match(value).map(|m| m.action)
+    }
+}
+
+/// Uniformly pick `size` items keeping their original order (Algorithm R)
+fn reservoir_sample<T>(items: Vec<T>, size: usize, rng: &mut impl Rng) -> Vec<T> {
+    let mut reservoir: Vec<(usize, T)> = Vec::with_capacity(size);
+    for (index, item) in items.into_iter().enumerate() {
+        if index < size {
+            reservoir.push((index, item));
+        } else {
+            let slot = rng.gen_range(0..=index);
+            if slot < size {
+                reservoir[slot] = (index, item);
             }
         }
+    }
+    reservoir.sort_by_key(|(index, _)| *index);
+    reservoir.into_iter().map(|(_, item)| item).collect()
+}
 
-        // If any exclude pattern matches, exclude the value
-        self.exclude_column_value_patterns
-            .iter()
-            .any(|pattern| pattern.is_match(value))
+/// Check if a number is below the minimum or above the maximum of a range
+fn out_of_range(range: &NumericRange, number: f64) -> bool {
+    range.min.is_some_and(|min| number < min) || range.max.is_some_and(|max| number > max)
+}
+
+/// Shannon entropy of a string's characters in bits per character
+fn shannon_entropy(value: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in value.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let length = value.chars().count() as f64;
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / length;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Value checks of a rule along with their descriptions
+fn value_checks(rules: &SqlFilterRules) -> Vec<(ValueCheck, String)> {
+    let mut checks = Vec::new();
+    if let Some(max_length) = rules.max_length {
+        checks.push((
+            ValueCheck::MaxLength(max_length),
+            format!("max_length {}", max_length),
+        ));
+    }
+    if let Some(range) = rules.numeric_range {
+        let bound = |bound: Option<f64>| bound.map(|b| b.to_string()).unwrap_or_default();
+        checks.push((
+            ValueCheck::NumericRange(range),
+            format!("numeric_range [{}, {}]", bound(range.min), bound(range.max)),
+        ));
+    }
+    if let Some(max_entropy) = rules.max_entropy {
+        checks.push((
+            ValueCheck::MaxEntropy(max_entropy),
+            format!("max_entropy {}", max_entropy),
+        ));
+    }
+    checks
+}
+
+/// Name of a rule, `<kind>[<index>]` when the config doesn't name it
+fn rule_name(rules: &SqlFilterRules, kind: &str, index: usize) -> String {
+    rules
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("{}[{}]", kind, index))
+}
+
+/// Compile a pattern of a rule according to its match mode and case sensitivity
+fn compile_pattern(rules: &SqlFilterRules, pattern: &str) -> Result<Regex, regex::Error> {
+    let pattern = match rules.match_mode.unwrap_or_default() {
+        MatchMode::Regex => pattern.to_string(),
+        MatchMode::Exact => format!("^{}$", regex::escape(pattern)),
+        MatchMode::Substring => regex::escape(pattern),
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(rules.case_insensitive)
+        .build()
+}
+
+/// Compile the patterns of a rule and add them to `target`
+fn push_patterns(
+    target: &mut Vec<RulePattern>,
+    rules: &SqlFilterRules,
+    patterns: &Option<Vec<String>>,
+    rule: &str,
+    action: FilterAction,
+) -> Result<(), FilterError> {
+    for pattern in patterns.iter().flatten() {
+        target.push(RulePattern {
+            regex: compile_pattern(rules, pattern)?,
+            action,
+            rule: rule.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Mask all alphanumeric characters except the last few, keeping separators.
+/// Values too short for the rest to hide the visible part, such as CVVs and
+/// PINs, are masked entirely
+fn mask_str(value: &str) -> String {
+    let len = value.chars().count();
+    let visible_from = if len <= 2 * MASK_VISIBLE_SUFFIX {
+        len
+    } else {
+        len - MASK_VISIBLE_SUFFIX
+    };
+    value
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if i < visible_from && c.is_alphanumeric() {
+                '*'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Prefix of filter presets compiled into the agent
+pub const BUILTIN_PRESET_PREFIX: &str = "builtin:";
+
+/// Exclude rules for the given column name and value patterns
+fn exclude_preset(column_name_regexes: &[&str], column_value_regexes: &[&str]) -> GlobalFilters {
+    let to_vec = |patterns: &[&str]| Some(patterns.iter().map(|p| p.to_string()).collect());
+    GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            column_name_regexes: to_vec(column_name_regexes),
+            column_value_regexes: to_vec(column_value_regexes),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    }
+}
+
+/// Get a built-in filter preset by its full name, e.g. `builtin:pii`
+pub fn builtin_preset(name: &str) -> Option<GlobalFilters> {
+    match name.strip_prefix(BUILTIN_PRESET_PREFIX)? {
+        "pii" => Some(exclude_preset(
+            &[
+                r"(?i)e_?mail",
+                r"(?i)phone",
+                r"(?i)(^|_)ssn($|_)",
+                r"(?i)passport",
+                r"(?i)(first|last|full|middle)_?name",
+                r"(?i)(^|_)(address|street|zip_?code|postal_?code)($|_)",
+                r"(?i)birth",
+                r"(?i)ip_?addr",
+                r"(?i)password|secret|token",
+            ],
+            &[
+                // Values are checked with spaces removed
+                r"^[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}$",
+                r"^\d{3}-\d{2}-\d{4}$",
+                r"^\+\d{10,15}$",
+                r"^(25[0-5]|2[0-4]\d|1?\d?\d)(\.(25[0-5]|2[0-4]\d|1?\d?\d)){3}$",
+            ],
+        )),
+        "finance" => Some(exclude_preset(
+            &[
+                r"(?i)card_?(number|num|no)|(^|_)pan($|_)",
+                r"(?i)(^|_)(cvv|cvc)($|_)",
+                r"(?i)iban",
+                r"(?i)(account|routing)_?(number|num|no)",
+                r"(?i)(^|_)(swift|bic)($|_)",
+                r"(?i)salary",
+            ],
+            &[
+                // Visa, Mastercard, Amex and Discover card numbers
+                r"^(4\d{3}|5[1-5]\d{2}|6011)-?\d{4}-?\d{4}-?\d{4}$",
+                r"^3[47]\d{2}-?\d{6}-?\d{5}$",
+                r"^[A-Z]{2}\d{2}[A-Z0-9]{11,30}$",
+            ],
+        )),
+        _ => None,
     }
 }
@@ -0,0 +1,445 @@
+//! Endpoint for Grafana's JSON datasources
+//!
+//! Implements the routes the JSON datasource plugin (and the older
+//! SimpleJson one) calls, so an existing Grafana can query the agent's
+//! datasources directly. Each query target runs like a task from the
+//! server: its signature, the datasource's query policy and filters, time
+//! macros, timeout, audit log and slow query log all apply. Time series
+//! targets map rows to records like tasks do, table targets return rows like
+//! jobs do. Infinity can use `POST /query` as a JSON URL too.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{info, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::agent::BaseAgent;
+use crate::client::{AcquireResultBody, JobKind, TimeRange};
+use crate::config::GrafanaConfig;
+use crate::executors::clickhouse_source::ResultColumn;
+use crate::listener::carries_api_key;
+use crate::models::Record;
+use crate::timeseries::TsMapping;
+
+/// Largest request body accepted
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Body of `POST /query`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryRequest {
+    pub range: Option<Range>,
+    pub interval_ms: Option<u64>,
+    #[serde(default)]
+    pub targets: Vec<Target>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Range {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+/// Query of a panel
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Target {
+    /// SQL of the query, with time macros
+    #[serde(default)]
+    pub target: String,
+    #[serde(default)]
+    pub ref_id: String,
+    #[serde(default, rename = "type")]
+    pub kind: TargetKind,
+    /// Datasource and time series mapping of the query, as an object or
+    /// JSON text. SimpleJson calls it `data`
+    #[serde(default, alias = "data")]
+    pub payload: Option<Value>,
+    #[serde(default)]
+    pub hide: bool,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetKind {
+    #[default]
+    Timeserie,
+    Table,
+}
+
+/// Options of a target besides its query
+#[derive(Debug, Deserialize, Default)]
+pub struct TargetPayload {
+    /// Datasource to query, optional when Grafana may query only one
+    pub datasource: Option<String>,
+    /// Signature of the query, when query signing is configured
+    pub signature: Option<String>,
+    /// Mapping of time series rows, with the time in `t` and all other
+    /// numeric columns as values by default
+    #[serde(flatten)]
+    pub mapping: TsMapping,
+}
+
+/// Result of a target
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum QueryResult {
+    /// Points of a series as `[value, milliseconds]`
+    Series {
+        target: String,
+        datapoints: Vec<(f64, i64)>,
+    },
+    Table {
+        #[serde(rename = "type")]
+        kind: &'static str,
+        columns: Vec<TableColumn>,
+        rows: Vec<Vec<Value>>,
+    },
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct TableColumn {
+    pub text: String,
+    #[serde(rename = "type")]
+    pub type_name: &'static str,
+}
+
+/// Runs the targets of Grafana's queries on the datasources of the agents
+pub struct GrafanaApi {
+    /// Agent of each tenant, with the datasources Grafana may query
+    agents: Vec<BaseAgent>,
+    api_key: Option<String>,
+}
+
+impl GrafanaApi {
+    /// Query the datasources of `agents` that `config` exposes
+    pub fn new(config: &GrafanaConfig, agents: Vec<BaseAgent>) -> Self {
+        let agents = agents
+            .into_iter()
+            .map(|mut agent| {
+                if let Some(exposed) = &config.datasources {
+                    agent.datasources.retain(|ds| exposed.contains(&ds.name));
+                }
+                agent
+            })
+            .filter(|agent| !agent.datasources.is_empty())
+            .collect();
+        Self {
+            agents,
+            api_key: config.api_key.clone(),
+        }
+    }
+
+    /// Names of the datasources Grafana may query
+    pub fn datasources(&self) -> Vec<String> {
+        self.agents
+            .iter()
+            .flat_map(|agent| agent.datasources.iter().map(|ds| ds.name.clone()))
+            .collect()
+    }
+
+    /// Check that a request carries the API key as a bearer token or basic
+    /// auth password
+    pub fn is_authorized(&self, authorization: Option<&str>) -> bool {
+        carries_api_key(self.api_key.as_deref(), authorization)
+    }
+
+    /// Run the targets of a query that aren't hidden, in order
+    pub async fn query(&self, request: &QueryRequest) -> Result<Vec<QueryResult>> {
+        let mut results = Vec::new();
+        for target in request.targets.iter().filter(|target| !target.hide) {
+            results.extend(self.run_target(request, target).await?);
+        }
+        Ok(results)
+    }
+
+    async fn run_target(
+        &self,
+        request: &QueryRequest,
+        target: &Target,
+    ) -> Result<Vec<QueryResult>> {
+        let payload: TargetPayload = match &target.payload {
+            None | Some(Value::Null) => Ok(TargetPayload::default()),
+            Some(Value::String(text)) if text.trim().is_empty() => Ok(TargetPayload::default()),
+            Some(Value::String(text)) => serde_json::from_str(text),
+            Some(payload) => serde_json::from_value(payload.clone()),
+        }
+        .with_context(|| format!("Invalid payload of target {}", target.ref_id))?;
+
+        let datasources = self.datasources();
+        let datasource_name = match (payload.datasource, datasources.as_slice()) {
+            (Some(name), _) => name,
+            (None, [only]) => only.clone(),
+            (None, _) => {
+                return Err(anyhow!(
+                    "Target {} names no datasource, one of: {}",
+                    target.ref_id,
+                    datasources.join(", ")
+                ))
+            }
+        };
+        let agent = self
+            .agents
+            .iter()
+            .find(|agent| {
+                agent
+                    .datasources
+                    .iter()
+                    .any(|ds| ds.name == datasource_name)
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "Datasource {} of target {} can't be queried from Grafana",
+                    datasource_name,
+                    target.ref_id
+                )
+            })?;
+
+        let query_request = AcquireResultBody {
+            id: format!(
+                "grafana-{}-{:08x}",
+                target.ref_id,
+                rand::thread_rng().gen::<u32>()
+            ),
+            datasource_name,
+            query: target.target.clone(),
+            queries: None,
+            ts_mapping: Some(payload.mapping),
+            timeout: None,
+            enqueued_at: None,
+            kind: JobKind::Query,
+            signature: payload.signature,
+            time_range: request.range.as_ref().map(|range| TimeRange {
+                from: range.from,
+                to: range.to,
+                interval_ms: request.interval_ms,
+            }),
+        };
+
+        let result = match target.kind {
+            TargetKind::Timeserie => agent
+                .process_query(&query_request)
+                .await
+                .map(|(records, _, _)| series(records)),
+            TargetKind::Table => match agent.process_job(&query_request).await {
+                Ok((rows, _, columns, _)) => rows
+                    .into_vec()
+                    .map(|rows| vec![table(rows, columns)])
+                    .map_err(Into::into),
+                Err(e) => Err(e),
+            },
+        };
+        result.map_err(|e| {
+            anyhow!(
+                "Target {} failed: {}",
+                target.ref_id,
+                agent.redact(&query_request, &format!("{:#}", e))
+            )
+        })
+    }
+}
+
+/// Series of records, one per value column and set of labels
+fn series(records: Vec<Record>) -> Vec<QueryResult> {
+    let mut series: BTreeMap<String, Vec<(f64, i64)>> = BTreeMap::new();
+    for record in records {
+        let labels = record
+            .labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, value))
+            .collect::<Vec<_>>()
+            .join(", ");
+        for (name, value) in record.values {
+            let target = match labels.as_str() {
+                "" => name,
+                labels => format!("{} {{{}}}", name, labels),
+            };
+            series.entry(target).or_default().push((value, record.t));
+        }
+    }
+    series
+        .into_iter()
+        .map(|(target, mut datapoints)| {
+            datapoints.sort_by_key(|&(_, t)| t);
+            QueryResult::Series { target, datapoints }
+        })
+        .collect()
+}
+
+/// Table of job rows, with the columns of the results or else those of the
+/// rows
+fn table(rows: Vec<crate::models::JobType>, columns: Vec<ResultColumn>) -> QueryResult {
+    let columns = if columns.is_empty() {
+        let mut names: Vec<_> = rows.iter().flat_map(|row| row.keys().cloned()).collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| ResultColumn {
+                name,
+                type_name: "string".to_string(),
+                nullable: true,
+            })
+            .collect()
+    } else {
+        columns
+    };
+    QueryResult::Table {
+        kind: "table",
+        rows: rows
+            .into_iter()
+            .map(|mut row| {
+                columns
+                    .iter()
+                    .map(|column| row.remove(&column.name).unwrap_or(Value::Null))
+                    .collect()
+            })
+            .collect(),
+        columns: columns
+            .into_iter()
+            .map(|column| TableColumn {
+                type_name: match column.type_name.as_str() {
+                    "int" | "float" => "number",
+                    "date" | "datetime" => "time",
+                    _ => "string",
+                },
+                text: column.name,
+            })
+            .collect(),
+    }
+}
+
+/// Grafana endpoint bound to its address
+pub struct GrafanaEndpoint {
+    listener: TcpListener,
+    api: Arc<GrafanaApi>,
+}
+
+impl GrafanaEndpoint {
+    /// Bind the endpoint to the configured address
+    pub async fn bind(config: &GrafanaConfig, agents: Vec<BaseAgent>) -> Result<Self> {
+        let listener = TcpListener::bind(&config.address)
+            .await
+            .with_context(|| format!("Failed to bind Grafana endpoint to {}", config.address))?;
+        Ok(Self {
+            listener,
+            api: Arc::new(GrafanaApi::new(config, agents)),
+        })
+    }
+
+    /// Address the endpoint is bound to
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve connections until the task is dropped
+    pub async fn serve(self) {
+        if let Ok(address) = self.listener.local_addr() {
+            info!(
+                "Serving datasources {} to Grafana on http://{}",
+                self.api.datasources().join(", "),
+                address
+            );
+        }
+        loop {
+            let (stream, _) = match self.listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("Failed to accept Grafana connection: {}", e);
+                    continue;
+                }
+            };
+            let api = self.api.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|request| handle(request, api.clone()));
+                let connection =
+                    http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+                if let Err(e) = connection.await {
+                    warn!("Grafana connection failed: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Serve connections in the background
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(self.serve())
+    }
+}
+
+/// Route a request to its endpoint
+async fn handle(
+    request: Request<Incoming>,
+    api: Arc<GrafanaApi>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !api.is_authorized(authorization) {
+        return Ok(error_response(StatusCode::UNAUTHORIZED, "Unauthorized"));
+    }
+
+    let response = match (request.method().clone(), request.uri().path()) {
+        (Method::GET, "/") => Response::new(Full::new(Bytes::from_static(b"OK\n"))),
+        (Method::POST, "/search") => json_response(&api.datasources()),
+        (Method::POST, "/annotations" | "/tag-keys" | "/tag-values") => {
+            json_response(&Vec::<Value>::new())
+        }
+        (Method::POST, "/query") => {
+            let body = match Limited::new(request.into_body(), MAX_BODY_BYTES)
+                .collect()
+                .await
+            {
+                Ok(body) => body.to_bytes(),
+                Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, &e.to_string())),
+            };
+            match serde_json::from_slice::<QueryRequest>(&body) {
+                Ok(query) => match api.query(&query).await {
+                    Ok(results) => json_response(&results),
+                    Err(e) => {
+                        warn!("Grafana query failed: {}", e);
+                        error_response(StatusCode::BAD_REQUEST, &e.to_string())
+                    }
+                },
+                Err(e) => error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!("Invalid query request: {}", e),
+                ),
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    };
+    Ok(response)
+}
+
+fn json_response<T: Serialize>(body: &T) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(
+        serde_json::to_vec(body).unwrap_or_default(),
+    )));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// Error as Grafana's JSON datasources show it
+fn error_response(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    let mut response = json_response(&json!({ "error": message, "message": message }));
+    *response.status_mut() = status;
+    response
+}
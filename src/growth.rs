@@ -0,0 +1,85 @@
+//! Row counts kept between discoveries, reporting how fast tables grow
+
+use crate::executors::clickhouse_source::{RowGrowth, TableSchema};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Row count of a table as of a discovery
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct RowCount {
+    row_count: u64,
+    at: DateTime<Utc>,
+}
+
+/// Row counts of the previous discovery, replaced by those of the current one
+pub struct GrowthTracker {
+    path: PathBuf,
+    previous: BTreeMap<String, RowCount>,
+    current: BTreeMap<String, RowCount>,
+    now: DateTime<Utc>,
+}
+
+impl GrowthTracker {
+    /// Load the row counts of the previous discovery from `path`, starting
+    /// afresh when there are none or they can't be read
+    pub fn load(path: &Path, now: DateTime<Utc>) -> Self {
+        let previous = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                log::warn!("Ignoring invalid row counts {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+
+        Self {
+            path: path.to_path_buf(),
+            previous,
+            current: BTreeMap::new(),
+            now,
+        }
+    }
+
+    /// Set the growth of tables known from the previous discovery and record
+    /// their current row counts
+    ///
+    /// Partial tables may lack their row count, so they keep the previous one.
+    pub fn track(&mut self, schemas: &mut [TableSchema]) {
+        for schema in schemas {
+            let table = format!("{}.{}", schema.database, schema.table);
+            let previous = self.previous.get(&table).copied();
+            if schema.partial {
+                if let Some(previous) = previous {
+                    self.current.insert(table, previous);
+                }
+                continue;
+            }
+
+            schema.growth = previous.map(|previous| {
+                let row_delta = schema.row_count as i64 - previous.row_count as i64;
+                let hours = (self.now - previous.at).num_milliseconds() as f64 / 3_600_000.0;
+                RowGrowth {
+                    row_delta,
+                    rows_per_hour: (hours > 0.0).then(|| row_delta as f64 / hours),
+                }
+            });
+            self.current.insert(
+                table,
+                RowCount {
+                    row_count: schema.row_count,
+                    at: self.now,
+                },
+            );
+        }
+    }
+
+    /// Save the row counts recorded by `track`, dropping tables that weren't
+    /// discovered this time
+    pub fn save(&self) -> Result<()> {
+        let content = serde_json::to_string_pretty(&self.current)?;
+        std::fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write row counts {}", self.path.display()))
+    }
+}
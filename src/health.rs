@@ -0,0 +1,307 @@
+//! Readiness checks served by the local listener
+//!
+//! The agent is ready when the server can be reached and at least one
+//! datasource accepts connections. The listener only starts once the
+//! configuration is loaded and its secrets are resolved, so that's implied.
+//! With several tenants, the servers of all of them must be reachable.
+//!
+//! Datasources with a probe interval are also probed in the background, and
+//! each change of their connectivity is reported to the server, so an
+//! unreachable database shows up before its tasks start failing. Those with
+//! a host probe interval have each of their hosts probed, so the load
+//! balancer avoids unhealthy hosts.
+
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::client::{DatasourceStatus, ServerApi};
+use crate::executors::create_executor;
+use crate::host_resolver::host_resolver;
+use crate::load_balancer::{load_balancer, HostHealth};
+use crate::metrics::metrics;
+use crate::models::DataSource;
+use crate::notify::conditions;
+use crate::status::activity;
+
+/// Outcome of a passed check
+pub const CHECK_OK: &str = "ok";
+
+/// Time a datasource gets to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of the readiness checks, each check reporting `ok` or why it failed
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub server: String,
+    /// Servers of the other tenants by tenant name, when there are several
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tenants: BTreeMap<String, String>,
+    /// Datasources by name, disabled ones and those in a maintenance window
+    /// report why they're skipped
+    pub datasources: BTreeMap<String, String>,
+}
+
+/// Checks whether the agent can process tasks
+pub struct Readiness {
+    server_client: Arc<dyn ServerApi>,
+    /// Server clients of the other tenants by tenant name
+    tenants: Vec<(String, Arc<dyn ServerApi>)>,
+    datasources: Vec<DataSource>,
+}
+
+impl Readiness {
+    pub fn new(server_client: Arc<dyn ServerApi>, datasources: Vec<DataSource>) -> Self {
+        Self {
+            server_client,
+            tenants: Vec::new(),
+            datasources,
+        }
+    }
+
+    /// Also check the server of another tenant, and its datasources
+    pub fn with_tenant(
+        mut self,
+        name: &str,
+        server_client: Arc<dyn ServerApi>,
+        datasources: Vec<DataSource>,
+    ) -> Self {
+        self.tenants.push((name.to_string(), server_client));
+        self.datasources.extend(datasources);
+        self
+    }
+
+    /// Check the server and all datasources concurrently
+    pub async fn check(&self) -> ReadinessReport {
+        let now = Utc::now();
+        let mut connections = JoinSet::new();
+        let mut datasources = BTreeMap::new();
+        for datasource in &self.datasources {
+            match datasource.unavailable_reason(now) {
+                Some(reason) => {
+                    datasources.insert(datasource.name.clone(), reason);
+                }
+                None => {
+                    let datasource = datasource.clone();
+                    connections.spawn(async move {
+                        let outcome = check_connection(&datasource).await;
+                        (datasource.name, outcome)
+                    });
+                }
+            }
+        }
+
+        let server = check_server(self.server_client.as_ref()).await;
+        let mut tenants = BTreeMap::new();
+        for (name, server_client) in &self.tenants {
+            tenants.insert(name.clone(), check_server(server_client.as_ref()).await);
+        }
+        while let Some(joined) = connections.join_next().await {
+            match joined {
+                Ok((name, outcome)) => {
+                    datasources.insert(name, outcome);
+                }
+                Err(e) => log::warn!("Datasource readiness check failed: {}", e),
+            }
+        }
+
+        let ready = server == CHECK_OK
+            && tenants.values().all(|outcome| outcome == CHECK_OK)
+            && datasources.values().any(|outcome| outcome == CHECK_OK);
+        ReadinessReport {
+            ready,
+            server,
+            tenants,
+            datasources,
+        }
+    }
+}
+
+/// Check a server is reachable, returning `ok` or the error
+async fn check_server(server_client: &dyn ServerApi) -> String {
+    match server_client.check_reachable().await {
+        Ok(()) => CHECK_OK.to_string(),
+        Err(e) => format!("{:#}", e),
+    }
+}
+
+/// Connect to a datasource, returning `ok` or the error
+pub(crate) async fn check_connection(datasource: &DataSource) -> String {
+    let connect = async {
+        let mut executor = create_executor(datasource, None).await?;
+        executor.connect().await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
+        Ok(Ok(())) => CHECK_OK.to_string(),
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => format!("Connection timed out after {}s", CONNECT_TIMEOUT.as_secs()),
+    }
+}
+
+/// Probes a datasource's connectivity, reporting when it changes
+pub struct ConnectivityProbe {
+    datasource: DataSource,
+    server_client: Arc<dyn ServerApi>,
+    /// Reachability last reported to the server
+    reported: Option<bool>,
+}
+
+impl ConnectivityProbe {
+    pub fn new(datasource: DataSource, server_client: Arc<dyn ServerApi>) -> Self {
+        Self {
+            datasource,
+            server_client,
+            reported: None,
+        }
+    }
+
+    /// Connect to the datasource and report its status if it changed since
+    /// the last report. Disabled datasources and those in a maintenance
+    /// window are skipped, returning `None`
+    pub async fn probe(&mut self) -> Option<DatasourceStatus> {
+        if let Some(reason) = self.datasource.unavailable_reason(Utc::now()) {
+            log::debug!("Skipping connectivity probe: {}", reason);
+            return None;
+        }
+
+        let started = Instant::now();
+        let outcome = check_connection(&self.datasource).await;
+        let reachable = outcome == CHECK_OK;
+        let status = DatasourceStatus {
+            reachable,
+            error: (!reachable).then_some(outcome),
+            latency_ms: started.elapsed().as_millis() as u64,
+            checked_at: Utc::now(),
+            labels: self.datasource.labels.clone(),
+        };
+        activity().record_datasource_status(&self.datasource.name, &status);
+        conditions().record_datasource_status(&self.datasource.name, &status);
+        if self.reported == Some(reachable) {
+            return Some(status);
+        }
+
+        let name = &self.datasource.name;
+        match &status.error {
+            None => log::info!("Datasource {} is reachable", name),
+            Some(error) => log::warn!("Datasource {} is unreachable: {}", name, error),
+        }
+        // Left unchanged on failure, so the next probe reports again
+        match self
+            .server_client
+            .report_datasource_status(name, &status)
+            .await
+        {
+            Ok(()) => self.reported = Some(reachable),
+            Err(e) => log::warn!("Failed to report status of datasource {}: {:#}", name, e),
+        }
+        Some(status)
+    }
+}
+
+/// Probe a datasource every `probe_interval`, starting right away
+///
+/// Returns `None` when the datasource has no probe interval configured
+pub fn spawn_connectivity_probe(
+    datasource: DataSource,
+    server_client: Arc<dyn ServerApi>,
+) -> Option<JoinHandle<()>> {
+    let interval = datasource
+        .probe_interval
+        .filter(|interval| !interval.is_zero())?;
+
+    log::info!(
+        "Probing connectivity of datasource {} every {:?}",
+        datasource.name,
+        interval
+    );
+    let mut probe = ConnectivityProbe::new(datasource, server_client);
+    Some(tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            probe.probe().await;
+        }
+    }))
+}
+
+/// Probe every host of a datasource concurrently, recording their health
+/// and latency for the load balancer
+pub async fn probe_hosts(datasource: &DataSource) {
+    let hosts = match host_resolver().resolve_hosts(datasource).await {
+        Ok(hosts) => hosts,
+        Err(e) => {
+            log::warn!(
+                "Failed to resolve hosts of datasource {}: {:#}",
+                datasource.name,
+                e
+            );
+            return;
+        }
+    };
+
+    let mut probes = JoinSet::new();
+    for host in hosts {
+        let datasource = DataSource {
+            hosts: vec![host.clone()],
+            ..datasource.clone()
+        };
+        probes.spawn(async move {
+            let started = Instant::now();
+            let outcome = check_connection(&datasource).await;
+            (host, outcome, started.elapsed())
+        });
+    }
+    while let Some(joined) = probes.join_next().await {
+        let Ok((host, outcome, latency)) = joined else {
+            continue;
+        };
+        let healthy = outcome == CHECK_OK;
+        let previous = load_balancer().health(&datasource.name, &host);
+        if previous.map(|health| health.healthy) != Some(healthy) {
+            if healthy {
+                log::info!("Host {} of datasource {} is healthy", host, datasource.name);
+            } else {
+                log::warn!(
+                    "Host {} of datasource {} is unhealthy: {}",
+                    host,
+                    datasource.name,
+                    outcome
+                );
+            }
+        }
+        load_balancer().record_probe(&datasource.name, &host, HostHealth { healthy, latency });
+        metrics().record_host_probe(&datasource.name, &host, healthy, latency);
+    }
+}
+
+/// Probe the hosts of a datasource every `host_probe_interval`, starting
+/// right away
+///
+/// Returns `None` when the datasource has no host probe interval configured
+pub fn spawn_host_probes(datasource: DataSource) -> Option<JoinHandle<()>> {
+    let interval = datasource
+        .host_probe_interval
+        .filter(|interval| !interval.is_zero())?;
+
+    log::info!(
+        "Probing hosts of datasource {} every {:?}",
+        datasource.name,
+        interval
+    );
+    Some(tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            if datasource.unavailable_reason(Utc::now()).is_none() {
+                probe_hosts(&datasource).await;
+            }
+        }
+    }))
+}
@@ -0,0 +1,439 @@
+//! Resolution of datasource hosts published in DNS SRV records or Consul
+//!
+//! A datasource host written as `srv+_clickhouse._tcp.example.com` or
+//! `consul+clickhouse` stands for the replica set currently registered under
+//! that name, looked up when an executor connects. Results are cached for
+//! the records' TTL, or `CONSUL_REFRESH` for Consul, and the last known
+//! replica set is kept when a refresh fails. Prefixing the name with
+//! `https://`, as in `srv+https://_clickhouse._tcp.example.com`, makes the
+//! agent connect to ClickHouse replicas over HTTPS.
+
+use anyhow::{anyhow, bail, Context, Result};
+use rand::Rng;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::models::{DataSource, DataSourceType};
+
+/// Prefix of hosts resolved with DNS SRV records
+pub const SRV_PREFIX: &str = "srv+";
+
+/// Prefix of hosts resolved with the Consul health API
+pub const CONSUL_PREFIX: &str = "consul+";
+
+/// Time Consul services are cached for
+const CONSUL_REFRESH: Duration = Duration::from_secs(30);
+
+/// Bounds of the time SRV records are cached for, whatever their TTL
+const MIN_SRV_TTL: Duration = Duration::from_secs(5);
+const MAX_SRV_TTL: Duration = Duration::from_secs(300);
+
+/// Time a DNS server or Consul agent has to answer
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// DNS record type of service locations
+const TYPE_SRV: u16 = 33;
+
+/// DNS pseudo record type advertising a larger UDP payload size
+const TYPE_OPT: u16 = 41;
+
+/// Whether a host is resolved by the agent rather than used as written
+pub fn is_dynamic(host: &str) -> bool {
+    host.starts_with(SRV_PREFIX) || host.starts_with(CONSUL_PREFIX)
+}
+
+/// Replica of a service, as published in an SRV record or Consul
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceTarget {
+    pub host: String,
+    pub port: u16,
+    pub priority: u16,
+    pub weight: u16,
+}
+
+/// Cached replicas of a dynamic host
+struct CachedHosts {
+    hosts: Vec<String>,
+    expires: Instant,
+}
+
+/// Looks up and caches the replicas of dynamic hosts
+pub struct HostResolver {
+    nameserver: SocketAddr,
+    consul_address: String,
+    consul_token: Option<String>,
+    client: Client,
+    cache: Mutex<HashMap<String, CachedHosts>>,
+}
+
+static HOST_RESOLVER: LazyLock<HostResolver> = LazyLock::new(HostResolver::from_system);
+
+/// Get the process-wide host resolver
+pub fn host_resolver() -> &'static HostResolver {
+    &HOST_RESOLVER
+}
+
+/// First nameserver of `/etc/resolv.conf`, the local resolver without one
+fn system_nameserver() -> SocketAddr {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .ok()
+        .and_then(|conf| {
+            conf.lines().find_map(|line| {
+                let address = line.trim().strip_prefix("nameserver")?.trim();
+                let address = address.split('%').next()?.parse().ok()?;
+                Some(SocketAddr::new(address, 53))
+            })
+        })
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 53)))
+}
+
+impl HostResolver {
+    /// Resolver using the system's nameserver and the Consul agent of the
+    /// `CONSUL_HTTP_ADDR` and `CONSUL_HTTP_TOKEN` env variables
+    pub fn from_system() -> Self {
+        let consul_address = std::env::var("CONSUL_HTTP_ADDR")
+            .unwrap_or_else(|_| "http://127.0.0.1:8500".to_string());
+        Self::new(
+            system_nameserver(),
+            &consul_address,
+            std::env::var("CONSUL_HTTP_TOKEN").ok(),
+        )
+    }
+
+    pub fn new(nameserver: SocketAddr, consul_address: &str, consul_token: Option<String>) -> Self {
+        // CONSUL_HTTP_ADDR may omit the scheme
+        let consul_address = if consul_address.contains("://") {
+            consul_address.trim_end_matches('/').to_string()
+        } else {
+            format!("http://{}", consul_address.trim_end_matches('/'))
+        };
+        Self {
+            nameserver,
+            consul_address,
+            consul_token,
+            client: Client::new(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Hosts of `datasource` with dynamic ones replaced by their current
+    /// replicas, most preferred first
+    pub async fn resolve_hosts(&self, datasource: &DataSource) -> Result<Vec<String>> {
+        let mut hosts = Vec::with_capacity(datasource.hosts.len());
+        for host in &datasource.hosts {
+            if is_dynamic(host) {
+                hosts.extend(self.resolve(host, &datasource.source_type).await?);
+            } else {
+                hosts.push(host.clone());
+            }
+        }
+        Ok(hosts)
+    }
+
+    /// Current replicas of the dynamic host `host`, in the form executors of
+    /// `source_type` expect
+    pub async fn resolve(&self, host: &str, source_type: &DataSourceType) -> Result<Vec<String>> {
+        let key = format!("{}|{}", source_type, host);
+        let stale = {
+            let cache = self.cache.lock().unwrap();
+            match cache.get(&key) {
+                Some(cached) if cached.expires > Instant::now() => return Ok(cached.hosts.clone()),
+                cached => cached.map(|cached| cached.hosts.clone()),
+            }
+        };
+
+        match self.lookup(host, source_type).await {
+            Ok((hosts, ttl)) => {
+                if stale.as_ref() != Some(&hosts) {
+                    log::info!("Resolved {} to {}", host, hosts.join(", "));
+                }
+                self.cache.lock().unwrap().insert(
+                    key,
+                    CachedHosts {
+                        hosts: hosts.clone(),
+                        expires: Instant::now() + ttl,
+                    },
+                );
+                Ok(hosts)
+            }
+            Err(e) => match stale {
+                Some(hosts) => {
+                    log::warn!(
+                        "Failed to resolve {}, keeping its last replicas: {:#}",
+                        host,
+                        e
+                    );
+                    Ok(hosts)
+                }
+                None => Err(e.context(format!("Failed to resolve {}", host))),
+            },
+        }
+    }
+
+    /// Look up the replicas of `host` and the time to cache them for
+    async fn lookup(
+        &self,
+        host: &str,
+        source_type: &DataSourceType,
+    ) -> Result<(Vec<String>, Duration)> {
+        let (name, lookup_srv) = match host.strip_prefix(SRV_PREFIX) {
+            Some(name) => (name, true),
+            None => (&host[CONSUL_PREFIX.len()..], false),
+        };
+        let (scheme, name) = match name.split_once("://") {
+            Some((scheme @ ("http" | "https"), name)) => (scheme, name),
+            Some((scheme, _)) => bail!("unsupported scheme '{}'", scheme),
+            None => ("http", name),
+        };
+
+        let (mut targets, ttl) = if lookup_srv {
+            let (targets, ttl) = self.lookup_srv(name).await?;
+            (targets, ttl.clamp(MIN_SRV_TTL, MAX_SRV_TTL))
+        } else {
+            (self.lookup_consul(name).await?, CONSUL_REFRESH)
+        };
+        if targets.is_empty() {
+            bail!("no replicas are registered");
+        }
+        // Lower priorities first, then higher weights
+        targets.sort_by(|a, b| {
+            (a.priority, std::cmp::Reverse(a.weight))
+                .cmp(&(b.priority, std::cmp::Reverse(b.weight)))
+        });
+
+        let hosts = targets
+            .iter()
+            .map(|target| {
+                let address = if target.host.contains(':') {
+                    format!("[{}]:{}", target.host, target.port)
+                } else {
+                    format!("{}:{}", target.host, target.port)
+                };
+                match source_type {
+                    DataSourceType::Clickhouse
+                    | DataSourceType::Prometheus
+                    | DataSourceType::Mock => {
+                        format!("{}://{}", scheme, address)
+                    }
+                    DataSourceType::PostgreSQL | DataSourceType::MySQL => address,
+                }
+            })
+            .collect();
+        Ok((hosts, ttl))
+    }
+
+    /// SRV records of `name` and their shortest TTL
+    pub async fn lookup_srv(&self, name: &str) -> Result<(Vec<ServiceTarget>, Duration)> {
+        let id = rand::thread_rng().gen::<u16>();
+        let query = srv_query(id, name)?;
+
+        let socket = UdpSocket::bind(match self.nameserver {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        })
+        .await?;
+        socket.connect(self.nameserver).await?;
+        socket.send(&query).await?;
+        let mut response = vec![0; 4096];
+        let length = tokio::time::timeout(LOOKUP_TIMEOUT, socket.recv(&mut response))
+            .await
+            .map_err(|_| anyhow!("DNS server {} didn't answer", self.nameserver))??;
+        response.truncate(length);
+
+        // Answers too large for UDP are only sent over TCP
+        if response.len() > 2 && response[2] & 0x02 != 0 {
+            response = tokio::time::timeout(LOOKUP_TIMEOUT, self.query_tcp(&query))
+                .await
+                .map_err(|_| anyhow!("DNS server {} didn't answer", self.nameserver))??;
+        }
+        parse_srv_response(id, &response)
+    }
+
+    /// Send `query` over TCP, returning the response
+    async fn query_tcp(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let mut stream = TcpStream::connect(self.nameserver).await?;
+        stream.write_u16(query.len() as u16).await?;
+        stream.write_all(query).await?;
+        let length = stream.read_u16().await?;
+        let mut response = vec![0; length as usize];
+        stream.read_exact(&mut response).await?;
+        Ok(response)
+    }
+
+    /// Healthy instances of the Consul service `name`
+    pub async fn lookup_consul(&self, name: &str) -> Result<Vec<ServiceTarget>> {
+        let url = format!(
+            "{}/v1/health/service/{}?passing=true",
+            self.consul_address, name
+        );
+        let mut request = self.client.get(&url).timeout(LOOKUP_TIMEOUT);
+        if let Some(token) = &self.consul_token {
+            request = request.header("X-Consul-Token", token);
+        }
+        let entries: Vec<ConsulEntry> = request
+            .send()
+            .await
+            .context("Consul request failed")?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid Consul response")?;
+
+        Ok(entries
+            .into_iter()
+            .map(|entry| ServiceTarget {
+                // Services registered without an address listen on their node's
+                host: if entry.service.address.is_empty() {
+                    entry.node.address
+                } else {
+                    entry.service.address
+                },
+                port: entry.service.port,
+                priority: 0,
+                weight: 1,
+            })
+            .collect())
+    }
+}
+
+/// Instance of a service in Consul's health API
+#[derive(Debug, Deserialize)]
+struct ConsulEntry {
+    #[serde(rename = "Node")]
+    node: ConsulNode,
+    #[serde(rename = "Service")]
+    service: ConsulService,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsulNode {
+    #[serde(rename = "Address")]
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsulService {
+    #[serde(rename = "Address", default)]
+    address: String,
+    #[serde(rename = "Port")]
+    port: u16,
+}
+
+/// DNS query for the SRV records of `name`
+fn srv_query(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(64);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question and the OPT record
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("invalid service name '{}'", name);
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    // Root name, OPT type, 4096 bytes payload size, no flags or options
+    query.push(0);
+    query.extend_from_slice(&TYPE_OPT.to_be_bytes());
+    query.extend_from_slice(&4096u16.to_be_bytes());
+    query.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    Ok(query)
+}
+
+/// Big-endian 16-bit integer at `offset` of a DNS message
+fn read_u16(message: &[u8], offset: usize) -> Result<u16> {
+    message
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| anyhow!("truncated DNS response"))
+}
+
+/// Big-endian 32-bit integer at `offset` of a DNS message
+fn read_u32(message: &[u8], offset: usize) -> Result<u32> {
+    Ok(((read_u16(message, offset)? as u32) << 16) | read_u16(message, offset + 2)? as u32)
+}
+
+/// Name at `offset` of a DNS message, following compression pointers, and
+/// the offset after it
+fn read_name(message: &[u8], offset: usize) -> Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut position = offset;
+    let mut end = None;
+    // Bounds the pointers followed, so a pointer loop can't hang the agent
+    for _ in 0..128 {
+        let length = *message
+            .get(position)
+            .ok_or_else(|| anyhow!("truncated DNS response"))? as usize;
+        if length == 0 {
+            return Ok((labels.join("."), end.unwrap_or(position + 1)));
+        }
+        if length & 0xc0 == 0xc0 {
+            let pointer = read_u16(message, position)? as usize & 0x3fff;
+            end.get_or_insert(position + 2);
+            position = pointer;
+            continue;
+        }
+        let label = message
+            .get(position + 1..position + 1 + length)
+            .ok_or_else(|| anyhow!("truncated DNS response"))?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        position += 1 + length;
+    }
+    bail!("invalid name in DNS response")
+}
+
+/// SRV records in the answer to the query `id`, and their shortest TTL
+fn parse_srv_response(id: u16, message: &[u8]) -> Result<(Vec<ServiceTarget>, Duration)> {
+    if read_u16(message, 0)? != id {
+        bail!("DNS response doesn't match the query");
+    }
+    match message[3] & 0x0f {
+        0 => {}
+        3 => bail!("no such service"),
+        code => bail!("DNS server failed with code {}", code),
+    }
+    let questions = read_u16(message, 4)?;
+    let answers = read_u16(message, 6)?;
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = read_name(message, offset)?.1 + 4;
+    }
+
+    let mut targets = Vec::new();
+    let mut ttl = MAX_SRV_TTL;
+    for _ in 0..answers {
+        offset = read_name(message, offset)?.1;
+        let record_type = read_u16(message, offset)?;
+        let record_ttl = read_u32(message, offset + 4)?;
+        let length = read_u16(message, offset + 8)? as usize;
+        let data = offset + 10;
+        offset = data + length;
+        if record_type != TYPE_SRV {
+            continue;
+        }
+
+        let (host, _) = read_name(message, data + 6)?;
+        // A target of "." means the service isn't available
+        if host.is_empty() {
+            continue;
+        }
+        targets.push(ServiceTarget {
+            host,
+            port: read_u16(message, data + 4)?,
+            priority: read_u16(message, data)?,
+            weight: read_u16(message, data + 2)?,
+        });
+        ttl = ttl.min(Duration::from_secs(record_ttl as u64));
+    }
+    Ok((targets, ttl))
+}
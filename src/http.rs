@@ -0,0 +1,55 @@
+//! HTTP clients shared across the agent
+//!
+//! Each client owns a connection pool, so building one per request opens a
+//! new connection every time. Clients are instead built once with the same
+//! pool settings: one for the server, shared by all agents, and one per
+//! datasource, shared by every executor created for it.
+
+use reqwest::{Client, ClientBuilder};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// Idle connections kept open to each host
+const POOL_MAX_IDLE_PER_HOST: usize = 16;
+
+/// Time an idle connection stays in the pool
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Interval of TCP keepalive probes, so idle connections aren't silently
+/// dropped by firewalls and load balancers
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// Clients of datasources, keyed by datasource name
+static DATASOURCE_CLIENTS: LazyLock<Mutex<HashMap<String, Client>>> = LazyLock::new(Mutex::default);
+
+/// Client of executors not created for a configured datasource
+static DEFAULT_CLIENT: LazyLock<Client> = LazyLock::new(|| build(client_builder()));
+
+/// Client builder with the agent's connection pool settings
+pub fn client_builder() -> ClientBuilder {
+    Client::builder()
+        .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .tcp_keepalive(TCP_KEEPALIVE)
+}
+
+/// Get the client of the datasource `name`, building it on first use.
+/// Clients share their connection pool with their clones
+pub fn datasource_client(name: &str) -> Client {
+    DATASOURCE_CLIENTS
+        .lock()
+        .unwrap()
+        .entry(name.to_string())
+        .or_insert_with(|| build(client_builder()))
+        .clone()
+}
+
+/// Get the client shared by executors not tied to a datasource
+pub fn default_client() -> Client {
+    DEFAULT_CLIENT.clone()
+}
+
+fn build(builder: ClientBuilder) -> Client {
+    builder.build().expect("Failed to build HTTP client")
+}
@@ -0,0 +1,202 @@
+//! AWS IAM authentication of RDS and Aurora datasources
+//!
+//! Datasources with `auth: {mode: iam}` connect with a short-lived
+//! authentication token instead of a static password: a presigned
+//! `rds-db:connect` request for the datasource's host, port and user, which
+//! the database accepts for 15 minutes. Tokens are reused for 10 minutes and
+//! the credentials of an assumed role until shortly before they expire, so
+//! neither is requested again for every connection.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::aws::{assume_role, presign_url, sha256_hex, AssumedRole, AwsCredentials, SigningScope};
+use crate::config::IamAuthConfig;
+use crate::models::{DataSource, DataSourceType};
+
+/// Time the database accepts a token for
+pub const TOKEN_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+/// Age after which a new token is generated, well before it expires
+const TOKEN_REFRESH: Duration = Duration::from_secs(10 * 60);
+
+/// Time before an assumed role's credentials expire when they're renewed
+const ROLE_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Session name of assumed roles, shown in CloudTrail
+const SESSION_NAME: &str = "tsight-agent";
+
+/// Authentication token of `user` on the database at `host:port`, signed
+/// with `credentials` at `time`
+pub fn generate_token(
+    host: &str,
+    port: u16,
+    user: &str,
+    region: &str,
+    credentials: &AwsCredentials,
+    time: DateTime<Utc>,
+) -> Result<String> {
+    let mut url = reqwest::Url::parse(&format!("https://{}:{}/", host, port))
+        .with_context(|| format!("Invalid database host {}", host))?;
+    url.query_pairs_mut()
+        .append_pair("Action", "connect")
+        .append_pair("DBUser", user);
+    let url = presign_url(
+        "GET",
+        &url,
+        credentials,
+        &SigningScope {
+            region,
+            service: "rds-db",
+        },
+        time,
+        TOKEN_LIFETIME,
+        &sha256_hex(b""),
+    );
+    // The token is the presigned URL without its scheme
+    Ok(url.as_str().trim_start_matches("https://").to_string())
+}
+
+/// Generates and caches the authentication tokens of a datasource
+#[derive(Debug)]
+pub struct IamAuth {
+    host: String,
+    port: u16,
+    user: String,
+    region: String,
+    role_arn: Option<String>,
+    sts_endpoint: String,
+    client: Client,
+    token: Mutex<Option<(String, DateTime<Utc>)>>,
+    role: Mutex<Option<AssumedRole>>,
+}
+
+impl IamAuth {
+    /// Authenticate as the user of `datasource`, a PostgreSQL or MySQL
+    /// database on RDS or Aurora
+    pub fn new(config: &IamAuthConfig, datasource: &DataSource) -> Result<Self> {
+        let default_port = match datasource.source_type {
+            DataSourceType::PostgreSQL => 5432,
+            DataSourceType::MySQL => 3306,
+            _ => bail!(
+                "IAM authentication requires a PostgreSQL or MySQL datasource, {} is {}",
+                datasource.name,
+                datasource.source_type
+            ),
+        };
+        let host = datasource
+            .hosts
+            .first()
+            .ok_or_else(|| anyhow!("No host specified for datasource {}", datasource.name))?;
+        let (host, port) = endpoint(host, default_port)?;
+        let region = config
+            .region
+            .clone()
+            .or_else(|| std::env::var("AWS_REGION").ok())
+            .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+            .ok_or_else(|| anyhow!("AWS region is not configured"))?;
+        let sts_endpoint = config
+            .sts_endpoint_url
+            .clone()
+            .unwrap_or_else(|| format!("https://sts.{}.amazonaws.com", region));
+
+        Ok(Self {
+            host,
+            port,
+            user: datasource.credentials()?.0,
+            region,
+            role_arn: config.role_arn.clone(),
+            sts_endpoint,
+            client: Client::new(),
+            token: Mutex::new(None),
+            role: Mutex::new(None),
+        })
+    }
+
+    /// Password of the next connection, a token generated within the last
+    /// 10 minutes
+    pub async fn password(&self) -> Result<String> {
+        let mut cached = self.token.lock().await;
+        let now = Utc::now();
+        if let Some((token, generated)) = cached.as_ref() {
+            if now
+                .signed_duration_since(*generated)
+                .to_std()
+                .unwrap_or_default()
+                < TOKEN_REFRESH
+            {
+                return Ok(token.clone());
+            }
+        }
+
+        let credentials = self.credentials().await?;
+        let token = generate_token(
+            &self.host,
+            self.port,
+            &self.user,
+            &self.region,
+            &credentials,
+            now,
+        )?;
+        log::debug!(
+            "Generated IAM authentication token for {}@{}",
+            self.user,
+            self.host
+        );
+        *cached = Some((token.clone(), now));
+        Ok(token)
+    }
+
+    /// Credentials signing tokens, those of the role when one is configured
+    async fn credentials(&self) -> Result<AwsCredentials> {
+        let own = AwsCredentials::resolve(None, &self.client).await?;
+        let Some(role_arn) = &self.role_arn else {
+            return Ok(own);
+        };
+
+        let mut role = self.role.lock().await;
+        let margin = chrono::Duration::from_std(ROLE_REFRESH_MARGIN)?;
+        if let Some(assumed) = role
+            .as_ref()
+            .filter(|assumed| assumed.expiration - margin > Utc::now())
+        {
+            return Ok(assumed.credentials.clone());
+        }
+        let assumed = assume_role(
+            &self.client,
+            &self.sts_endpoint,
+            &self.region,
+            &own,
+            role_arn,
+            SESSION_NAME,
+        )
+        .await?;
+        log::info!(
+            "Assumed role {} for IAM authentication until {}",
+            role_arn,
+            assumed.expiration
+        );
+        let credentials = assumed.credentials.clone();
+        *role = Some(assumed);
+        Ok(credentials)
+    }
+}
+
+/// Host and port of a datasource host such as `db.example.com:5432` or
+/// `postgres://db.example.com`
+fn endpoint(host: &str, default_port: u16) -> Result<(String, u16)> {
+    let url = if host.contains("://") {
+        host.to_string()
+    } else {
+        format!("tcp://{}", host)
+    };
+    let url =
+        reqwest::Url::parse(&url).with_context(|| format!("Invalid database host {}", host))?;
+    let name = url
+        .host_str()
+        .ok_or_else(|| anyhow!("Invalid database host {}", host))?;
+    Ok((name.to_string(), url.port().unwrap_or(default_port)))
+}
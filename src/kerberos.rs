@@ -0,0 +1,465 @@
+//! Kerberos authentication of datasource requests
+//!
+//! Datasources with `auth: {mode: kerberos}` authenticate every HTTP request
+//! with an `Authorization: Negotiate` header carrying a fresh SPNEGO token,
+//! as servers keep a replay cache and reject tokens seen before. Tickets are
+//! obtained with the configured keytab and kept in an in-memory credential
+//! cache of the process, renewed by the Kerberos library once they expire.
+//!
+//! No Kerberos bindings are linked in: the system's MIT GSSAPI library,
+//! `libgssapi_krb5`, is loaded the first time a token is needed, so agents
+//! without Kerberos datasources run on hosts without it.
+
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::config::KerberosConfig;
+
+/// Credentials and target of the Kerberos tokens of a datasource
+#[derive(Debug)]
+#[cfg_attr(not(unix), allow(dead_code))]
+pub struct KerberosAuth {
+    keytab: PathBuf,
+    principal: Option<String>,
+    /// Host-based name of the datasource's service, e.g. `HTTP@ch.example.com`
+    target: String,
+    /// In-memory credential cache of the keytab's tickets
+    ccache: String,
+}
+
+impl KerberosAuth {
+    /// Authenticate requests to the datasource at `url` with `config`
+    pub fn new(config: &KerberosConfig, url: &str) -> Result<Self> {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Kerberos requires a host URL such as http://host:8123, got {}",
+                    url
+                )
+            })?;
+        let ccache = format!(
+            "MEMORY:tsight-agent:{}:{}",
+            config.keytab.display(),
+            config.principal.as_deref().unwrap_or_default()
+        );
+        Ok(Self {
+            keytab: config.keytab.clone(),
+            principal: config.principal.clone(),
+            target: format!("{}@{}", config.service, host),
+            ccache,
+        })
+    }
+
+    /// Host-based name of the service tokens are made for
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// `Authorization` header value for a single request
+    ///
+    /// Getting a ticket may contact the KDC, so the library is called on a
+    /// blocking thread
+    pub async fn authorization(self: &Arc<Self>) -> Result<String> {
+        let auth = self.clone();
+        let token = tokio::task::spawn_blocking(move || auth.token())
+            .await
+            .context("Kerberos token generation panicked")??;
+        Ok(format!("Negotiate {}", STANDARD.encode(token)))
+    }
+
+    #[cfg(unix)]
+    fn token(&self) -> Result<Vec<u8>> {
+        gssapi::init_token(
+            &self.keytab,
+            self.principal.as_deref(),
+            &self.ccache,
+            &self.target,
+        )
+        .with_context(|| format!("Kerberos authentication to {} failed", self.target))
+    }
+
+    #[cfg(not(unix))]
+    fn token(&self) -> Result<Vec<u8>> {
+        anyhow::bail!("Kerberos authentication is only supported on Unix")
+    }
+}
+
+/// Calls into the system's GSSAPI library, following RFC 2744 and MIT's
+/// `gss_acquire_cred_from` extension
+#[cfg(unix)]
+mod gssapi {
+    use anyhow::{anyhow, bail, Result};
+    use std::ffi::{c_char, c_int, c_void, CString};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+    use std::sync::OnceLock;
+
+    type Status = u32;
+
+    #[repr(C)]
+    struct Buffer {
+        length: usize,
+        value: *mut c_void,
+    }
+
+    impl Buffer {
+        fn empty() -> Self {
+            Self {
+                length: 0,
+                value: ptr::null_mut(),
+            }
+        }
+
+        fn of(bytes: &[u8]) -> Self {
+            Self {
+                length: bytes.len(),
+                value: bytes.as_ptr() as *mut c_void,
+            }
+        }
+    }
+
+    #[repr(C)]
+    struct Oid {
+        length: u32,
+        elements: *mut c_void,
+    }
+
+    #[repr(C)]
+    struct KeyValue {
+        key: *const c_char,
+        value: *const c_char,
+    }
+
+    #[repr(C)]
+    struct KeyValueSet {
+        count: u32,
+        elements: *const KeyValue,
+    }
+
+    type Name = *mut c_void;
+    type Credential = *mut c_void;
+    type Context = *mut c_void;
+
+    /// DER contents of the OIDs used: the Kerberos principal name type
+    /// 1.2.840.113554.1.2.2.1, the host-based service name type
+    /// 1.2.840.113554.1.2.1.4 and the SPNEGO mechanism 1.3.6.1.5.5.2
+    const KRB5_PRINCIPAL_NAME: &[u8] = b"\x2a\x86\x48\x86\xf7\x12\x01\x02\x02\x01";
+    const HOSTBASED_SERVICE: &[u8] = b"\x2a\x86\x48\x86\xf7\x12\x01\x02\x01\x04";
+    const SPNEGO: &[u8] = b"\x2b\x06\x01\x05\x05\x02";
+
+    const COMPLETE: Status = 0;
+    const CONTINUE_NEEDED: Status = 1;
+    const INITIATE: c_int = 1;
+    const GSS_CODE: c_int = 1;
+    const MECH_CODE: c_int = 2;
+
+    /// Names the library is installed as on Linux and macOS
+    const LIBRARIES: &[&str] = &[
+        "libgssapi_krb5.so.2",
+        "libgssapi_krb5.so",
+        "libgssapi_krb5.dylib",
+    ];
+
+    type ImportName = unsafe extern "C" fn(*mut Status, *mut Buffer, *mut Oid, *mut Name) -> Status;
+    type ReleaseName = unsafe extern "C" fn(*mut Status, *mut Name) -> Status;
+    type AcquireCredFrom = unsafe extern "C" fn(
+        *mut Status,
+        Name,
+        u32,
+        *mut c_void,
+        c_int,
+        *const KeyValueSet,
+        *mut Credential,
+        *mut c_void,
+        *mut u32,
+    ) -> Status;
+    type ReleaseCred = unsafe extern "C" fn(*mut Status, *mut Credential) -> Status;
+    type InitSecContext = unsafe extern "C" fn(
+        *mut Status,
+        Credential,
+        *mut Context,
+        Name,
+        *mut Oid,
+        u32,
+        u32,
+        *mut c_void,
+        *mut Buffer,
+        *mut *mut Oid,
+        *mut Buffer,
+        *mut u32,
+        *mut u32,
+    ) -> Status;
+    type DeleteSecContext = unsafe extern "C" fn(*mut Status, *mut Context, *mut Buffer) -> Status;
+    type ReleaseBuffer = unsafe extern "C" fn(*mut Status, *mut Buffer) -> Status;
+    type DisplayStatus =
+        unsafe extern "C" fn(*mut Status, Status, c_int, *mut Oid, *mut u32, *mut Buffer) -> Status;
+
+    /// Functions of the loaded library
+    struct Library {
+        import_name: ImportName,
+        release_name: ReleaseName,
+        acquire_cred_from: AcquireCredFrom,
+        release_cred: ReleaseCred,
+        init_sec_context: InitSecContext,
+        delete_sec_context: DeleteSecContext,
+        release_buffer: ReleaseBuffer,
+        display_status: DisplayStatus,
+    }
+
+    // The library is never unloaded, so its functions stay valid
+    static LIBRARY: OnceLock<Result<Library, String>> = OnceLock::new();
+
+    fn library() -> Result<&'static Library> {
+        LIBRARY
+            .get_or_init(load)
+            .as_ref()
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    fn load() -> Result<Library, String> {
+        // SAFETY: the names are NUL-terminated, and a failed dlopen returns null
+        let handle = LIBRARIES
+            .iter()
+            .map(|name| CString::new(*name).expect("library names have no NUL"))
+            .map(|name| unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) })
+            .find(|handle| !handle.is_null())
+            .ok_or_else(|| {
+                format!(
+                    "Failed to load the GSSAPI library, install MIT Kerberos ({})",
+                    LIBRARIES.join(", ")
+                )
+            })?;
+
+        macro_rules! symbol {
+            ($name:literal, $signature:ty) => {{
+                let name = CString::new($name).expect("symbol names have no NUL");
+                // SAFETY: the handle is open, and the symbol is cast to the
+                // signature RFC 2744 and the MIT headers declare for it
+                let symbol = unsafe { libc::dlsym(handle, name.as_ptr()) };
+                if symbol.is_null() {
+                    return Err(format!("GSSAPI library lacks {}", $name));
+                }
+                unsafe { std::mem::transmute::<*mut c_void, $signature>(symbol) }
+            }};
+        }
+
+        Ok(Library {
+            import_name: symbol!("gss_import_name", ImportName),
+            release_name: symbol!("gss_release_name", ReleaseName),
+            acquire_cred_from: symbol!("gss_acquire_cred_from", AcquireCredFrom),
+            release_cred: symbol!("gss_release_cred", ReleaseCred),
+            init_sec_context: symbol!("gss_init_sec_context", InitSecContext),
+            delete_sec_context: symbol!("gss_delete_sec_context", DeleteSecContext),
+            release_buffer: symbol!("gss_release_buffer", ReleaseBuffer),
+            display_status: symbol!("gss_display_status", DisplayStatus),
+        })
+    }
+
+    fn oid(elements: &'static [u8]) -> Oid {
+        Oid {
+            length: elements.len() as u32,
+            elements: elements.as_ptr() as *mut c_void,
+        }
+    }
+
+    /// Whether a major status is an error rather than a supplementary status
+    fn is_error(major: Status) -> bool {
+        major & 0xffff_0000 != 0
+    }
+
+    /// Messages of a failed call, from both the GSSAPI and the mechanism
+    fn error(library: &Library, call: &str, major: Status, minor: Status) -> anyhow::Error {
+        let mut messages = Vec::new();
+        for (code, kind) in [(major, GSS_CODE), (minor, MECH_CODE)] {
+            if code == 0 {
+                continue;
+            }
+            let mut context = 0;
+            loop {
+                let mut minor = 0;
+                let mut message = Buffer::empty();
+                // SAFETY: all pointers are valid, the message is released below
+                let status = unsafe {
+                    (library.display_status)(
+                        &mut minor,
+                        code,
+                        kind,
+                        ptr::null_mut(),
+                        &mut context,
+                        &mut message,
+                    )
+                };
+                if is_error(status) {
+                    break;
+                }
+                if !message.value.is_null() {
+                    // SAFETY: the library returned `length` bytes at `value`
+                    let bytes = unsafe {
+                        std::slice::from_raw_parts(message.value as *const u8, message.length)
+                    };
+                    messages.push(String::from_utf8_lossy(bytes).trim().to_string());
+                    unsafe { (library.release_buffer)(&mut minor, &mut message) };
+                }
+                if context == 0 {
+                    break;
+                }
+            }
+        }
+        messages.retain(|message| !message.is_empty());
+        anyhow!("{}: {}", call, messages.join(", "))
+    }
+
+    /// Import a name of the given type
+    fn import_name(library: &Library, name: &str, name_type: &'static [u8]) -> Result<Name> {
+        let mut minor = 0;
+        let mut output = ptr::null_mut();
+        let mut buffer = Buffer::of(name.as_bytes());
+        let mut name_type = oid(name_type);
+        // SAFETY: the buffer and OID outlive the call, which copies the name
+        let major =
+            unsafe { (library.import_name)(&mut minor, &mut buffer, &mut name_type, &mut output) };
+        if is_error(major) {
+            return Err(error(library, "gss_import_name", major, minor));
+        }
+        Ok(output)
+    }
+
+    /// Handles released when dropped, so every early return cleans up
+    struct Handles<'a> {
+        library: &'a Library,
+        names: Vec<Name>,
+        credential: Credential,
+        context: Context,
+    }
+
+    impl Drop for Handles<'_> {
+        fn drop(&mut self) {
+            let mut minor = 0;
+            // SAFETY: each handle is either null or owned by this struct
+            unsafe {
+                if !self.context.is_null() {
+                    (self.library.delete_sec_context)(
+                        &mut minor,
+                        &mut self.context,
+                        ptr::null_mut(),
+                    );
+                }
+                if !self.credential.is_null() {
+                    (self.library.release_cred)(&mut minor, &mut self.credential);
+                }
+                for name in &mut self.names {
+                    (self.library.release_name)(&mut minor, name);
+                }
+            }
+        }
+    }
+
+    /// First SPNEGO token of a context with `target`, authenticating as
+    /// `principal` with the keys of `keytab`
+    pub fn init_token(
+        keytab: &Path,
+        principal: Option<&str>,
+        ccache: &str,
+        target: &str,
+    ) -> Result<Vec<u8>> {
+        let library = library()?;
+        let mut handles = Handles {
+            library,
+            names: Vec::new(),
+            credential: ptr::null_mut(),
+            context: ptr::null_mut(),
+        };
+
+        let desired_name = match principal {
+            Some(principal) => {
+                let name = import_name(library, principal, KRB5_PRINCIPAL_NAME)?;
+                handles.names.push(name);
+                name
+            }
+            None => ptr::null_mut(),
+        };
+        let target_name = import_name(library, target, HOSTBASED_SERVICE)?;
+        handles.names.push(target_name);
+
+        let keytab = CString::new(keytab.as_os_str().as_bytes())?;
+        let ccache = CString::new(ccache)?;
+        let store = [
+            KeyValue {
+                key: c"client_keytab".as_ptr(),
+                value: keytab.as_ptr(),
+            },
+            KeyValue {
+                key: c"ccache".as_ptr(),
+                value: ccache.as_ptr(),
+            },
+        ];
+        let store = KeyValueSet {
+            count: store.len() as u32,
+            elements: store.as_ptr(),
+        };
+
+        let mut minor = 0;
+        // SAFETY: the credential store's strings outlive the call, and the
+        // credential is released by `handles`
+        let major = unsafe {
+            (library.acquire_cred_from)(
+                &mut minor,
+                desired_name,
+                0,
+                ptr::null_mut(),
+                INITIATE,
+                &store,
+                &mut handles.credential,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if is_error(major) {
+            return Err(error(library, "gss_acquire_cred_from", major, minor));
+        }
+
+        let mut mechanism = oid(SPNEGO);
+        let mut token = Buffer::empty();
+        // SAFETY: the names and credential are valid handles, the context is
+        // deleted by `handles` and the token released below
+        let major = unsafe {
+            (library.init_sec_context)(
+                &mut minor,
+                handles.credential,
+                &mut handles.context,
+                target_name,
+                &mut mechanism,
+                0,
+                0,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut token,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if is_error(major) {
+            return Err(error(library, "gss_init_sec_context", major, minor));
+        }
+
+        let bytes = if token.value.is_null() {
+            Vec::new()
+        } else {
+            // SAFETY: the library returned `length` bytes at `value`
+            unsafe { std::slice::from_raw_parts(token.value as *const u8, token.length) }.to_vec()
+        };
+        // SAFETY: the token was allocated by the library
+        unsafe { (library.release_buffer)(&mut minor, &mut token) };
+        if (major != COMPLETE && major != CONTINUE_NEEDED) || bytes.is_empty() {
+            bail!("gss_init_sec_context returned no token");
+        }
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,73 @@
+//! Metadata of the pod the agent runs in on Kubernetes
+//!
+//! Detected from the `KUBERNETES_SERVICE_HOST` variable Kubernetes sets in
+//! every container, and sent with datasource registrations and status
+//! reports so the server can tell which pod and node serve a datasource. The
+//! pod's name, namespace and node are read from `POD_NAME`, `POD_NAMESPACE`
+//! and `NODE_NAME`, set from the downward API in the pod spec; without them
+//! the name falls back to the hostname and the namespace to the one of the
+//! service account, while the node stays unknown.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::leadership::host_name;
+
+/// Namespace of the service account mounted into pods
+pub const NAMESPACE_FILE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+static POD_METADATA: LazyLock<Option<PodMetadata>> = LazyLock::new(|| {
+    PodMetadata::detect(|name| std::env::var(name).ok(), Path::new(NAMESPACE_FILE))
+});
+
+/// Metadata of the pod, unless the agent doesn't run in Kubernetes
+pub fn pod_metadata() -> Option<&'static PodMetadata> {
+    POD_METADATA.as_ref()
+}
+
+/// Pod the agent runs in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PodMetadata {
+    pub pod_name: String,
+    /// Namespace of the pod, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Node the pod is scheduled on, when `NODE_NAME` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_name: Option<String>,
+}
+
+impl PodMetadata {
+    /// Metadata from environment variables looked up with `env` and the
+    /// service account's `namespace_file`, unless not in Kubernetes
+    pub fn detect(env: impl Fn(&str) -> Option<String>, namespace_file: &Path) -> Option<Self> {
+        let env = |name: &str| env(name).filter(|value| !value.trim().is_empty());
+        env("KUBERNETES_SERVICE_HOST")?;
+        Some(Self {
+            pod_name: env("POD_NAME")
+                .or_else(|| env("HOSTNAME"))
+                .unwrap_or_else(host_name),
+            namespace: env("POD_NAMESPACE").or_else(|| {
+                std::fs::read_to_string(namespace_file)
+                    .ok()
+                    .map(|namespace| namespace.trim().to_string())
+                    .filter(|namespace| !namespace.is_empty())
+            }),
+            node_name: env("NODE_NAME"),
+        })
+    }
+}
+
+impl std::fmt::Display for PodMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.namespace {
+            Some(namespace) => write!(f, "pod {}/{}", namespace, self.pod_name)?,
+            None => write!(f, "pod {}", self.pod_name)?,
+        }
+        if let Some(node_name) = &self.node_name {
+            write!(f, " on node {}", node_name)?;
+        }
+        Ok(())
+    }
+}
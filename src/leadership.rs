@@ -0,0 +1,197 @@
+//! Leased leadership of agents running as an active/standby pair
+//!
+//! Two agents serving the same datasources would both run every task, so
+//! with `leadership` configured they take turns holding a lease on the
+//! server instead. Only the holder acquires tasks; the standby keeps asking
+//! for the lease and gets it once the leader stops renewing it, whether it
+//! crashed, lost its network or shut down and released it. A leader that
+//! can't reach the server steps down when its lease would have expired, so
+//! both agents never lead at once.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::client::{LeaseRequest, ServerApi};
+use crate::config::LeadershipConfig;
+use crate::shutdown;
+
+/// Whether the agent currently holds the lease, shared with its agent loops
+#[derive(Clone)]
+pub struct Leadership(watch::Receiver<bool>);
+
+impl Leadership {
+    pub fn is_leader(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+/// Acquires and renews the lease of one server
+pub struct LeaseKeeper {
+    server_client: Arc<dyn ServerApi>,
+    holder: String,
+    ttl: Duration,
+    leader: watch::Sender<bool>,
+    /// Time the lease held by the agent expires at, measured from when it
+    /// was requested
+    held_until: Option<Instant>,
+}
+
+impl LeaseKeeper {
+    pub fn new(config: &LeadershipConfig, server_client: Arc<dyn ServerApi>) -> Self {
+        Self {
+            server_client,
+            holder: config.holder.clone().unwrap_or_else(default_holder),
+            ttl: config.lease_ttl,
+            leader: watch::Sender::new(false),
+            held_until: None,
+        }
+    }
+
+    /// Leadership following the outcome of lease requests
+    pub fn leadership(&self) -> Leadership {
+        Leadership(self.leader.subscribe())
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// Acquire or renew the lease, returning whether the agent leads
+    ///
+    /// The request is given up when the lease held expires, or after the
+    /// lease's ttl when standing by, so a server that stops answering can't
+    /// keep the agent leading past its lease.
+    pub async fn renew(&mut self) -> bool {
+        let requested = Instant::now();
+        let request = LeaseRequest {
+            holder: self.holder.clone(),
+            ttl_ms: self.ttl.as_millis() as u64,
+        };
+        let deadline = self.held_until.unwrap_or(requested + self.ttl);
+        let response =
+            tokio::time::timeout_at(deadline.into(), self.server_client.acquire_lease(&request))
+                .await;
+        match response {
+            Ok(Ok(grant)) if grant.granted => {
+                self.held_until = Some(requested + self.ttl);
+                self.set_leader(true, None);
+            }
+            Ok(Ok(grant)) => {
+                self.held_until = None;
+                self.set_leader(false, grant.holder.as_deref());
+            }
+            Ok(Err(e)) => {
+                log::warn!("Failed to renew lease: {:#}", e);
+                self.step_down_if_expired();
+            }
+            Err(_) => {
+                log::warn!("Lease request got no response before the lease expired");
+                self.step_down_if_expired();
+            }
+        }
+        self.is_leader()
+    }
+
+    fn step_down_if_expired(&mut self) {
+        if self.held_until.is_some_and(|until| Instant::now() >= until) {
+            self.held_until = None;
+            self.set_leader(false, None);
+        }
+    }
+
+    /// Give up the lease if the agent holds it
+    pub async fn release(&mut self) {
+        if self.held_until.take().is_none() {
+            return;
+        }
+        self.set_leader(false, None);
+        match self.server_client.release_lease(&self.holder).await {
+            Ok(()) => log::info!("Released lease held as {}", self.holder),
+            Err(e) => log::warn!("Failed to release lease: {:#}", e),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        *self.leader.borrow()
+    }
+
+    fn set_leader(&self, leader: bool, holder: Option<&str>) {
+        let changed = self.leader.send_replace(leader) != leader;
+        match (changed, leader) {
+            (true, true) => log::info!("Acquired lease as {}, acquiring tasks", self.holder),
+            (true, false) => log::warn!("Lost lease held as {}, standing by", self.holder),
+            _ => (),
+        }
+        if !leader && !changed {
+            log::debug!(
+                "Standing by while {} holds the lease",
+                holder.unwrap_or("another agent")
+            );
+        }
+    }
+}
+
+/// Keep requesting the lease of a server until shutdown, releasing it then
+pub fn spawn_lease_renewal(
+    config: &LeadershipConfig,
+    server_client: Arc<dyn ServerApi>,
+) -> Leadership {
+    let mut keeper = LeaseKeeper::new(config, server_client);
+    let leadership = keeper.leadership();
+    let interval = renew_interval(config);
+    log::info!(
+        "Standing by until the lease is acquired as {}",
+        keeper.holder()
+    );
+    tokio::spawn(async move {
+        loop {
+            keeper.renew().await;
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => (),
+                _ = shutdown::requested() => break,
+            }
+        }
+        keeper.release().await;
+    });
+    leadership
+}
+
+/// Interval between lease requests, short enough to renew before expiry
+fn renew_interval(config: &LeadershipConfig) -> Duration {
+    let default = config.lease_ttl / 3;
+    match config.renew_interval {
+        Some(interval) if interval < config.lease_ttl => interval,
+        Some(interval) => {
+            log::warn!(
+                "Lease renew interval {:?} isn't shorter than its ttl, using {:?}",
+                interval,
+                default
+            );
+            default
+        }
+        None => default,
+    }
+}
+
+/// Host name and process id, telling apart agents on one host
+fn default_holder() -> String {
+    format!("{}-{}", host_name(), std::process::id())
+}
+
+/// Host name of the machine, `localhost` if it can't be determined
+#[cfg(unix)]
+pub(crate) fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return "localhost".to_string();
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
@@ -1,6 +1,52 @@
 pub mod agent;
+pub mod anonymize;
+pub mod audit;
+pub mod aws;
+pub mod build_info;
+pub mod cli;
 pub mod client;
 pub mod config;
+pub mod crash;
+pub mod daemon;
+pub mod diagnostics;
+pub mod downsample;
+pub mod errors;
 pub mod executors;
 pub mod filters;
+pub mod grafana;
+pub mod growth;
+pub mod health;
+pub mod host_resolver;
+pub mod http;
+pub mod iam_auth;
+pub mod kerberos;
+pub mod kubernetes;
+pub mod leadership;
+pub mod listener;
+pub mod load_balancer;
+pub mod logging;
+pub mod metrics;
 pub mod models;
+pub mod notify;
+pub mod otlp_metrics;
+pub mod policy;
+pub mod privacy;
+pub mod read_only;
+pub mod redact;
+pub mod remote_read;
+pub mod result_export;
+pub mod rotation;
+pub mod schema_diff;
+pub mod secrets;
+pub mod self_metrics;
+pub mod service;
+pub mod shutdown;
+pub mod signing;
+pub mod slow_query;
+pub mod spill;
+pub mod status;
+pub mod systemd;
+pub mod telemetry;
+pub mod time_macros;
+pub mod timeseries;
+pub mod transform;
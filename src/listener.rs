@@ -0,0 +1,190 @@
+//! Optional local HTTP listener
+//!
+//! Serves `GET /metrics` with the agent's Prometheus metrics, `/healthz`
+//! and `/readyz` for liveness and readiness probes, and the status page at
+//! `/status` and `/status.json`. It's meant for the host, a sidecar or the
+//! orchestrator and has no authentication, so it should
+//! listen on a local or otherwise trusted address.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+
+use crate::config::ListenerConfig;
+use crate::health::Readiness;
+use crate::metrics::metrics;
+use crate::status::{render_html, StatusPage};
+
+/// Content type of the Prometheus text exposition format
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Local HTTP listener bound to its address
+pub struct Listener {
+    listener: TcpListener,
+    readiness: Option<Arc<Readiness>>,
+    status: Option<Arc<StatusPage>>,
+}
+
+impl Listener {
+    /// Bind the listener to the configured address
+    pub async fn bind(config: &ListenerConfig) -> Result<Self> {
+        let listener = TcpListener::bind(&config.address)
+            .await
+            .with_context(|| format!("Failed to bind listener to {}", config.address))?;
+        Ok(Self {
+            listener,
+            readiness: None,
+            status: None,
+        })
+    }
+
+    /// Answer `/readyz` with the outcome of `readiness` checks. Without them
+    /// the agent is reported ready as soon as it's up
+    pub fn with_readiness(mut self, readiness: Readiness) -> Self {
+        self.readiness = Some(Arc::new(readiness));
+        self
+    }
+
+    /// Serve the status page of `status`, answering 404 without it
+    pub fn with_status(mut self, status: StatusPage) -> Self {
+        self.status = Some(Arc::new(status));
+        self
+    }
+
+    /// Address the listener is bound to
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve connections until the task is dropped
+    pub async fn serve(self) {
+        if let Ok(address) = self.listener.local_addr() {
+            info!("Serving metrics on http://{}/metrics", address);
+        }
+        loop {
+            let (stream, _) = match self.listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("Failed to accept listener connection: {}", e);
+                    continue;
+                }
+            };
+            let readiness = self.readiness.clone();
+            let status = self.status.clone();
+            tokio::spawn(async move {
+                let service =
+                    service_fn(|request| handle(request, readiness.clone(), status.clone()));
+                let connection =
+                    http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+                if let Err(e) = connection.await {
+                    warn!("Listener connection failed: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Serve connections in the background
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(self.serve())
+    }
+}
+
+/// Route a request to its endpoint
+async fn handle(
+    request: Request<Incoming>,
+    readiness: Option<Arc<Readiness>>,
+    status: Option<Arc<StatusPage>>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(CONTENT_TYPE, METRICS_CONTENT_TYPE)
+            .body(Full::new(Bytes::from(metrics().render()))),
+        (&Method::GET, "/healthz") => Ok(Response::new(Full::new(Bytes::from_static(b"ok\n")))),
+        (&Method::GET, "/readyz") => match readiness {
+            Some(readiness) => {
+                let report = readiness.check().await;
+                if !report.ready {
+                    debug!("Agent not ready: {:?}", report);
+                }
+                let status = if report.ready {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                Response::builder()
+                    .status(status)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Full::new(Bytes::from(
+                        serde_json::to_vec(&report).unwrap_or_default(),
+                    )))
+            }
+            None => Ok(Response::new(Full::new(Bytes::from_static(b"ok\n")))),
+        },
+        (&Method::GET, "/status") => match status {
+            Some(status) => Response::builder()
+                .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                .body(Full::new(Bytes::from(render_html(&status.report())))),
+            None => not_found(),
+        },
+        (&Method::GET, "/status.json") => match status {
+            Some(status) => Response::builder()
+                .header(CONTENT_TYPE, "application/json")
+                .body(Full::new(Bytes::from(
+                    serde_json::to_vec(&status.report()).unwrap_or_default(),
+                ))),
+            None => not_found(),
+        },
+        _ => not_found(),
+    };
+    Ok(response.expect("static response parts are valid"))
+}
+
+/// Check that the `Authorization` header of a request carries `api_key` as
+/// a bearer token or basic auth password, as endpoints for other tools ask.
+/// Any request passes without a key
+pub fn carries_api_key(api_key: Option<&str>, authorization: Option<&str>) -> bool {
+    let Some(api_key) = api_key else {
+        return true;
+    };
+    let Some((scheme, credentials)) = authorization.and_then(|value| value.split_once(' ')) else {
+        return false;
+    };
+    match scheme.to_ascii_lowercase().as_str() {
+        "bearer" => keys_match(credentials.trim(), api_key),
+        "basic" => base64::engine::general_purpose::STANDARD
+            .decode(credentials.trim())
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| {
+                decoded
+                    .split_once(':')
+                    .map(|(_, password)| keys_match(password, api_key))
+            })
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Compare a presented key with the configured one in constant time, so the
+/// time a request takes doesn't tell how much of a guessed key is right
+fn keys_match(presented: &str, api_key: &str) -> bool {
+    presented.as_bytes().ct_eq(api_key.as_bytes()).into()
+}
+
+fn not_found() -> hyper::http::Result<Response<Full<Bytes>>> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Full::new(Bytes::from_static(b"Not found\n")))
+}
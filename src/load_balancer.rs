@@ -0,0 +1,98 @@
+//! Choice of the host queries of a datasource run on
+//!
+//! Datasources with several hosts, or a dynamic host resolving to several
+//! replicas, pick one for each executor with their `host_strategy`. Hosts
+//! are assumed healthy until a background probe (see
+//! [`crate::health::spawn_host_probes`]) fails to connect to them, and when
+//! no host is healthy all of them are tried rather than none.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use crate::config::HostStrategy;
+use crate::models::DataSource;
+
+/// Outcome of the last probe of a host
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HostHealth {
+    pub healthy: bool,
+    pub latency: Duration,
+}
+
+/// Probe outcomes and round-robin position of a datasource's hosts
+#[derive(Debug, Default)]
+struct DatasourceHosts {
+    health: HashMap<String, HostHealth>,
+    next: usize,
+}
+
+/// Picks hosts of datasources, keyed by datasource name
+#[derive(Debug, Default)]
+pub struct LoadBalancer {
+    datasources: Mutex<HashMap<String, DatasourceHosts>>,
+}
+
+static LOAD_BALANCER: LazyLock<LoadBalancer> = LazyLock::new(LoadBalancer::default);
+
+/// Get the process-wide load balancer
+pub fn load_balancer() -> &'static LoadBalancer {
+    &LOAD_BALANCER
+}
+
+impl LoadBalancer {
+    /// Pick one of `hosts`, the resolved hosts of `datasource`, with its
+    /// strategy. Returns `None` when there are no hosts
+    pub fn select(&self, datasource: &DataSource, hosts: &[String]) -> Option<String> {
+        if hosts.len() <= 1 {
+            return hosts.first().cloned();
+        }
+
+        let mut datasources = self.datasources.lock().unwrap();
+        let state = datasources.entry(datasource.name.clone()).or_default();
+        let health = |host: &String| state.health.get(host).copied();
+        let mut candidates: Vec<&String> = hosts
+            .iter()
+            .filter(|host| health(host).is_none_or(|health| health.healthy))
+            .collect();
+        if candidates.is_empty() {
+            candidates = hosts.iter().collect();
+        }
+
+        let host = match datasource.host_strategy {
+            HostStrategy::FirstHealthy => candidates[0],
+            HostStrategy::RoundRobin => {
+                let host = candidates[state.next % candidates.len()];
+                state.next = state.next.wrapping_add(1);
+                host
+            }
+            // Hosts not probed yet come after those with a known latency
+            HostStrategy::Nearest => candidates
+                .iter()
+                .min_by_key(|host| health(host).map_or(Duration::MAX, |health| health.latency))
+                .copied()
+                .unwrap_or(candidates[0]),
+        };
+        Some(host.clone())
+    }
+
+    /// Record the outcome of a probe of `host` of the datasource `datasource`
+    pub fn record_probe(&self, datasource: &str, host: &str, health: HostHealth) {
+        self.datasources
+            .lock()
+            .unwrap()
+            .entry(datasource.to_string())
+            .or_default()
+            .health
+            .insert(host.to_string(), health);
+    }
+
+    /// Outcome of the last probe of `host` of `datasource`, if it was probed
+    pub fn health(&self, datasource: &str, host: &str) -> Option<HostHealth> {
+        self.datasources
+            .lock()
+            .unwrap()
+            .get(datasource)
+            .and_then(|state| state.health.get(host).copied())
+    }
+}
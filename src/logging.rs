@@ -0,0 +1,145 @@
+//! Log output of the agent
+//!
+//! Records of the `log` macros are forwarded to a `tracing` subscriber, so
+//! they carry the fields of the span they're emitted in, such as the
+//! `task_id`, `queue` and `datasource` of the task being processed. Output
+//! starts as plain text on stderr, as the format and destination are only
+//! known once the configuration is loaded, and switches to JSON lines or a
+//! rotated file if the configuration asks for it.
+//!
+//! The subscriber also holds the optional OpenTelemetry layer, see
+//! [`crate::telemetry`], which receives the agent's spans at any `RUST_LOG`
+//! level.
+
+use anyhow::{Context, Result};
+use std::sync::Mutex;
+use tracing::Metadata;
+use tracing_subscriber::filter::{filter_fn, FilterExt, Filtered, Targets};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer, Registry};
+
+use crate::config::{LogFormat, LoggingConfig};
+use crate::rotation::{RotatingFile, MEGABYTE};
+
+/// Level of records logged when `RUST_LOG` isn't set
+const DEFAULT_FILTER: &str = "error";
+
+/// Target of the agent's spans and events
+const AGENT_TARGET: &str = "tsight_agent";
+
+/// Target of spans timing the phases of a task, which are only exported as
+/// traces. Log lines keep the fields of the task they belong to instead
+pub const PHASE_TARGET: &str = "tsight_agent::phase";
+
+/// Span of a phase of a task, such as `acquire` or `submit`, within the
+/// current span or `parent`
+macro_rules! phase_span {
+    ($name:literal) => {
+        tracing::info_span!(target: $crate::logging::PHASE_TARGET, $name)
+    };
+    (parent: $parent:expr, $name:literal) => {
+        tracing::info_span!(target: $crate::logging::PHASE_TARGET, parent: $parent, $name)
+    };
+}
+pub(crate) use phase_span;
+
+/// Layer receiving the spans of the agent, such as the OpenTelemetry layer
+pub type TraceLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+type TraceSubscriber =
+    Layered<Filtered<reload::Layer<Option<TraceLayer>, Registry>, Targets, Registry>, Registry>;
+
+/// Layer writing the log lines
+type FormatLayer = Box<dyn Layer<TraceSubscriber> + Send + Sync>;
+
+/// Handle changing the layers of the installed subscriber
+pub struct LogHandle {
+    format: reload::Handle<FormatLayer, TraceSubscriber>,
+    trace: reload::Handle<Option<TraceLayer>, Registry>,
+}
+
+/// Install the subscriber logging plain text to stderr, filtered by `RUST_LOG`
+pub fn init() -> LogHandle {
+    let (trace, trace_handle) = reload::Layer::new(None);
+    let (format, format_handle) =
+        reload::Layer::new(format_layer(LogFormat::Text, std::io::stderr));
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let agent_targets = Targets::new().with_target(AGENT_TARGET, tracing::Level::INFO);
+    // Fails only when a subscriber is already installed, which then keeps logging
+    let _ = tracing_subscriber::registry()
+        .with(trace.with_filter(agent_targets))
+        .with(format.with_filter(log_filter(filter)))
+        .try_init();
+    LogHandle {
+        format: format_handle,
+        trace: trace_handle,
+    }
+}
+
+impl LogHandle {
+    /// Log in the configured format and to the configured file from now on
+    pub fn configure(&self, config: &LoggingConfig) -> Result<()> {
+        let layer = match &config.file {
+            Some(file) => {
+                let writer =
+                    RotatingFile::open(&file.path, file.max_size_mb * MEGABYTE, file.max_files)
+                        .and_then(|writer| writer.with_period(file.rotation))
+                        .with_context(|| {
+                            format!("Failed to open log file {}", file.path.display())
+                        })?;
+                format_layer(config.format, Mutex::new(writer))
+            }
+            None => format_layer(config.format, std::io::stderr),
+        };
+        if let Err(e) = self.format.reload(layer) {
+            log::warn!("Failed to switch log output: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Pass the agent's spans to `layer` from now on
+    pub fn set_trace_layer(&self, layer: TraceLayer) {
+        if let Err(e) = self.trace.reload(Some(layer)) {
+            log::warn!("Failed to install tracing layer: {}", e);
+        }
+    }
+}
+
+/// Filter of log lines: events as `filter` allows, and the agent's spans
+/// other than task phases regardless of level, so lines logged within them
+/// always carry their fields
+pub fn log_filter<S>(filter: EnvFilter) -> impl tracing_subscriber::layer::Filter<S>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    let phase_span = filter_fn(|metadata: &Metadata<'_>| metadata.target() == PHASE_TARGET);
+    let agent_span = filter_fn(|metadata: &Metadata<'_>| {
+        metadata.is_span()
+            && metadata.target().starts_with(AGENT_TARGET)
+            && metadata.target() != PHASE_TARGET
+    });
+    filter.and(phase_span.not()).or(agent_span)
+}
+
+/// Layer writing records to `writer` in `format`. JSON lines hold the
+/// fields of the event and of the span it's emitted in, under `span`
+pub fn format_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
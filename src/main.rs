@@ -1,11 +1,55 @@
 use anyhow::{anyhow, Context, Result};
-use log::{error, info};
+use clap::Parser;
+use log::{error, info, warn};
 use std::env;
 use std::fs;
+use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
-use tsight_agent::agent::{discover_and_submit_schemas, initialize_agents};
-use tsight_agent::client::ServerClient;
-use tsight_agent::config::Config;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tsight_agent::agent::{
+    discover_and_submit_schemas, initialize_tenant_agents, spawn_scheduled_discovery, BaseAgent,
+};
+use tsight_agent::audit;
+use tsight_agent::build_info;
+use tsight_agent::cli::{
+    self, select_datasources, Cli, Command, ConfigCommand, FiltersCommand, SecretCommand,
+    ServiceCommand,
+};
+use tsight_agent::client::{self, ServerApi};
+use tsight_agent::config::example::example_config;
+use tsight_agent::config::{Config, GlobalFilters, Tenant};
+use tsight_agent::crash;
+use tsight_agent::daemon::{self, PidFile};
+use tsight_agent::diagnostics::CheckResult;
+use tsight_agent::errors::{spawn_error_reporting, DEFAULT_REPORT_INTERVAL};
+use tsight_agent::filters::SqlFilters;
+use tsight_agent::grafana::GrafanaEndpoint;
+use tsight_agent::health::{spawn_connectivity_probe, spawn_host_probes, Readiness};
+use tsight_agent::kubernetes;
+use tsight_agent::leadership::spawn_lease_renewal;
+use tsight_agent::listener::Listener;
+use tsight_agent::logging::{self, LogHandle};
+use tsight_agent::models::DataSource;
+use tsight_agent::notify::spawn_notifications;
+use tsight_agent::otlp_metrics;
+use tsight_agent::read_only::check_read_only;
+use tsight_agent::remote_read::RemoteReadEndpoint;
+use tsight_agent::result_export;
+use tsight_agent::secrets::encrypted::{KeySource, DEFAULT_KEY_FILE};
+use tsight_agent::secrets::keyring::KeyringProvider;
+use tsight_agent::secrets::SecretResolver;
+use tsight_agent::self_metrics::spawn_self_metrics;
+use tsight_agent::service;
+use tsight_agent::shutdown;
+use tsight_agent::signing;
+use tsight_agent::slow_query;
+use tsight_agent::status::StatusPage;
+use tsight_agent::systemd;
+use tsight_agent::telemetry;
+
+/// Name of the agent directory inside platform config locations
+const CONFIG_DIR_NAME: &str = "tsight_agent";
 
 /// Get the platform-specific default config path
 fn get_default_config_path() -> PathBuf {
@@ -17,12 +61,39 @@ fn get_default_config_path() -> PathBuf {
         // macOS: ~/Library/Application Support/tsight_agent/config.yaml
         let home = env::var("HOME").unwrap_or_else(|_| String::from("/Users/user"));
         PathBuf::from(home).join("Library").join("Application Support").join("tsight_agent").join("config.yaml")
+    } else if cfg!(target_os = "windows") {
+        // Windows: %APPDATA%\tsight_agent\config.yaml, machine-wide path when no user profile
+        env::var_os("APPDATA")
+            .filter(|appdata| !appdata.is_empty())
+            .map(|appdata| {
+                PathBuf::from(appdata)
+                    .join(CONFIG_DIR_NAME)
+                    .join("config.yaml")
+            })
+            .or_else(get_service_config_path)
+            .unwrap_or_else(|| PathBuf::from("config.yaml"))
     } else {
-        // Default to local config.yaml for other platforms (including Windows)
+        // Default to local config.yaml for other platforms
         PathBuf::from("config.yaml")
     }
 }
 
+/// Get the machine-wide config path used when running as a Windows service
+fn get_service_config_path() -> Option<PathBuf> {
+    if !cfg!(target_os = "windows") {
+        return None;
+    }
+    // Windows: %ProgramData%\tsight_agent\config.yaml
+    let program_data = env::var_os("ProgramData")
+        .filter(|program_data| !program_data.is_empty())
+        .unwrap_or_else(|| "C:\\ProgramData".into());
+    Some(
+        PathBuf::from(program_data)
+            .join(CONFIG_DIR_NAME)
+            .join("config.yaml"),
+    )
+}
+
 /// Ensure the configuration directory exists
 fn ensure_config_dir_exists() -> Result<()> {
     let default_path = get_default_config_path();
@@ -30,7 +101,8 @@ fn ensure_config_dir_exists() -> Result<()> {
         anyhow!("Could not determine parent directory of config path")
     )?;
     
-    if !config_dir.exists() {
+    // A relative fallback path has an empty parent, which is the working directory
+    if !config_dir.as_os_str().is_empty() && !config_dir.exists() {
         info!("Creating configuration directory: {}", config_dir.display());
         fs::create_dir_all(config_dir).context("Failed to create configuration directory")?;
     }
@@ -38,8 +110,31 @@ fn ensure_config_dir_exists() -> Result<()> {
     Ok(())
 }
 
-/// Load configuration from the default paths
-pub fn load_config() -> Result<Config> {
+/// Environment variable overriding the configuration file path
+const CONFIG_ENV_VAR: &str = "TSIGHT_CONFIG";
+
+/// Get the explicitly requested configuration path: the `--config` flag
+/// takes precedence over the `TSIGHT_CONFIG` environment variable
+fn config_path_override(cli_path: Option<PathBuf>) -> Option<PathBuf> {
+    cli_path.or_else(|| {
+        env::var(CONFIG_ENV_VAR)
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    })
+}
+
+/// Load configuration from an explicitly requested path or the default paths
+pub fn load_config(config_override: Option<PathBuf>) -> Result<Config> {
+    // An explicit path must exist, never fall back to defaults
+    if let Some(path) = config_override {
+        if !path.exists() {
+            return Err(anyhow!("Configuration file not found: {}", path.display()));
+        }
+        info!("Using configuration from explicit path: {}", path.display());
+        return load_config_from_path(&path);
+    }
+
     // First try platform-specific default location
     let default_path = get_default_config_path();
     
@@ -48,6 +143,15 @@ pub fn load_config() -> Result<Config> {
         return load_config_from_path(&default_path);
     }
     
+    // Then try the machine-wide location used by the Windows service
+    if let Some(service_path) = get_service_config_path().filter(|path| path.exists()) {
+        info!(
+            "Using configuration from service path: {}",
+            service_path.display()
+        );
+        return load_config_from_path(&service_path);
+    }
+
     // Then try local config.yaml
     let local_path = Path::new("config.yaml");
     if local_path.exists() {
@@ -55,6 +159,14 @@ pub fn load_config() -> Result<Config> {
         return load_config_from_path(local_path);
     }
     
+    // Finally try configuration from TSIGHT_* environment variables
+    let env_config =
+        Config::from_env().context("Failed to load configuration from environment variables")?;
+    if let Some(config) = env_config {
+        info!("Using configuration from environment variables");
+        return Ok(config);
+    }
+
     // Ensure the config directory exists for future use
     if let Err(e) = ensure_config_dir_exists() {
         info!("Note: {}", e);
@@ -74,54 +186,625 @@ pub fn load_config_from_path(path: &Path) -> Result<Config> {
     Ok(config)
 }
 
+/// Get the configuration file the agent would load, if there is one
+fn find_config_path(config_override: Option<PathBuf>) -> Option<PathBuf> {
+    config_override.or_else(|| {
+        [
+            Some(get_default_config_path()),
+            get_service_config_path(),
+            Some(PathBuf::from("config.yaml")),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|path| path.exists())
+    })
+}
+
+/// Read a secret from the terminal without echo, or from piped stdin
+fn read_secret(prompt: &str) -> Result<String> {
+    let secret = if std::io::stdin().is_terminal() {
+        rpassword::prompt_password(prompt)?
+    } else {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+
+    if secret.is_empty() {
+        return Err(anyhow!("Secret must not be empty"));
+    }
+    Ok(secret)
+}
+
+/// Run `secret set <entry>`, storing a secret in the OS keyring, or
+/// `secret encrypt`, printing a secret encrypted with the local key
+async fn run_secret_command(
+    command: &SecretCommand,
+    config_override: Option<PathBuf>,
+) -> Result<()> {
+    match command {
+        SecretCommand::Set { entry, service } => {
+            let secret = read_secret(&format!("Secret for '{}': ", entry))?;
+            KeyringProvider::new(service.clone())
+                .store(entry, &secret)
+                .await?;
+            println!(
+                "Stored keyring entry '{}', reference it as keyring:{}",
+                entry, entry
+            );
+            Ok(())
+        }
+        SecretCommand::Encrypt {
+            key_file,
+            keyring_entry,
+            service,
+        } => {
+            let source = match (key_file, keyring_entry) {
+                (_, Some(entry)) => KeySource::Keyring {
+                    service: service.clone(),
+                    entry: entry.clone(),
+                },
+                (Some(path), None) => KeySource::File(path.clone()),
+                (None, None) => {
+                    let config_path =
+                        find_config_path(config_override).unwrap_or_else(get_default_config_path);
+                    let config_dir = config_path.parent().unwrap_or(Path::new(""));
+                    KeySource::File(config_dir.join(DEFAULT_KEY_FILE))
+                }
+            };
+            let (key, created) = source.load_or_create()?;
+            if created {
+                // Printed to stderr, so stdout holds nothing but the value
+                eprintln!("Created a new encryption key in {}, keep it out of backups and version control", source);
+            }
+            let secret = read_secret("Secret to encrypt: ")?;
+            println!("{}", key.encrypt(&secret));
+            Ok(())
+        }
+    }
+}
+
+/// Run `filters explain`, printing which filter rule matches the given
+/// database, table, column and value
+fn run_filters_command(command: &FiltersCommand, config_override: Option<PathBuf>) -> Result<()> {
+    let FiltersCommand::Explain {
+        value,
+        column,
+        table,
+        database,
+        datasource,
+    } = command;
+    let checks = [
+        ("database", database),
+        ("table", table),
+        ("column", column),
+        ("value", value),
+    ];
+    if checks.iter().all(|(_, input)| input.is_none()) {
+        return Err(anyhow!(
+            "Pass at least one of --database, --table, --column and --value"
+        ));
+    }
+
+    let config = load_config(config_override)?;
+    let global_filters = match datasource {
+        Some(name) => select_datasources(&config, Some(name))?[0]
+            .effective_filters(config.global_filters.as_ref()),
+        None => config.global_filters.clone(),
+    };
+    let filters = SqlFilters::new(global_filters.as_ref())?;
+
+    for (kind, input) in checks {
+        let Some(input) = input else { continue };
+        let decision = match kind {
+            "database" => filters.explain_database(input),
+            "table" => match input.split_once('.') {
+                Some((database, table)) => filters.explain_qualified_table(database, table),
+                None => filters.explain_table(input),
+            },
+            "column" => filters.explain_column(input),
+            _ => filters.explain_value(input),
+        };
+        println!("{} '{}': {}", kind, input, decision);
+    }
+    Ok(())
+}
+
+/// Load the configuration and resolve its secrets for a one-off command
+async fn load_resolved_config(config_override: Option<PathBuf>) -> Result<Config> {
+    let mut config = load_config(config_override)?;
+    resolve_secrets(&mut config).await?;
+    Ok(config)
+}
+
+/// Print the outcome of a check, with its latency unless it was skipped
+fn print_check(name: &str, check: &CheckResult) {
+    match check.latency_ms {
+        Some(latency_ms) => println!("{}: {} ({}ms)", name, check.status, latency_ms),
+        None => println!("{}: {}", name, check.status),
+    }
+}
+
+/// Run a command other than `run`
+async fn run_command(command: &Command, config_override: Option<PathBuf>) -> Result<()> {
+    match command {
+        Command::Run | Command::Service(ServiceCommand::Run { .. }) => {
+            unreachable!("the agent runs until it's stopped")
+        }
+        Command::Version => {
+            println!("tsight_agent {}", build_info::long_version());
+            Ok(())
+        }
+        Command::Secret(command) => run_secret_command(command, config_override).await,
+        Command::Filters(command) => run_filters_command(command, config_override),
+        Command::Config(ConfigCommand::Example { datasource }) => {
+            print!("{}", example_config(datasource));
+            Ok(())
+        }
+        Command::Service(ServiceCommand::Install { name }) => {
+            service::install(name, config_override.as_deref())?;
+            println!(
+                "Installed service {}, start it with `sc.exe start {}`",
+                name, name
+            );
+            Ok(())
+        }
+        Command::Service(ServiceCommand::Uninstall { name }) => {
+            service::uninstall(name)?;
+            println!("Removed service {}", name);
+            Ok(())
+        }
+        Command::Validate => {
+            let config = load_config(config_override)?;
+            cli::validate(&config)?;
+            println!(
+                "Configuration is valid, {} datasource(s) configured",
+                config.datasources.len()
+            );
+            Ok(())
+        }
+        Command::Discover(args) => {
+            let config = load_resolved_config(config_override).await?;
+            let datasources = select_datasources(&config, args.datasources.datasource.as_deref())?;
+            if !args.submit {
+                let schemas = cli::preview(&config, &datasources).await?;
+                println!("{}", serde_json::to_string_pretty(&schemas)?);
+                return Ok(());
+            }
+            // Schemas are submitted to the server of each datasource's tenant
+            let mut result = Ok(());
+            for (tenant, datasources) in by_tenant(&config, &datasources) {
+                let server_client = client::from_config(&tenant.server)?;
+                if let Err(e) = cli::discover(&config, &datasources, server_client.as_ref()).await {
+                    result = Err(e);
+                }
+            }
+            result
+        }
+        Command::TestConnection(args) => {
+            let config = load_resolved_config(config_override).await?;
+            let datasources = select_datasources(&config, args.datasource.as_deref())?;
+            let mut passed = true;
+            for (tenant, datasources) in by_tenant(&config, &datasources) {
+                let server_client = client::from_config(&tenant.server)?;
+                let (report, tenant_passed) =
+                    cli::test_connection(&config, &datasources, server_client.as_ref()).await;
+                if config.tenants.is_empty() {
+                    print_check("server", &report.server);
+                } else {
+                    print_check(&format!("server of tenant {}", tenant.name), &report.server);
+                }
+                for (name, check) in &report.datasources {
+                    print_check(&format!("datasource {}", name), check);
+                }
+                passed &= tenant_passed;
+            }
+            if !passed {
+                return Err(anyhow!("Connection test failed"));
+            }
+            Ok(())
+        }
+        Command::Query { datasource, sql } => {
+            let config = load_resolved_config(config_override).await?;
+            let datasource = select_datasources(&config, Some(datasource))?[0];
+            for row in cli::query(&config, datasource, sql).await? {
+                println!("{}", serde_json::to_string(&row)?);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Group selected datasources by the tenant whose server they report to,
+/// leaving out tenants without any of them unless none are selected
+fn by_tenant<'a>(
+    config: &Config,
+    datasources: &[&'a DataSource],
+) -> Vec<(Tenant, Vec<&'a DataSource>)> {
+    config
+        .tenants()
+        .into_iter()
+        .filter_map(|tenant| {
+            let selected: Vec<&DataSource> = datasources
+                .iter()
+                .copied()
+                .filter(|ds| tenant.datasources.iter().any(|t| t.name == ds.name))
+                .collect();
+            (datasources.is_empty() || !selected.is_empty()).then_some((tenant, selected))
+        })
+        .collect()
+}
+
+/// Replace secret references in the config with values from secret providers
+pub async fn resolve_secrets(config: &mut Config) -> Result<()> {
+    let resolver = SecretResolver::from_config(config.secrets.as_ref())
+        .context("Failed to initialize secret providers")?;
+    resolver.resolve_config(config).await?;
+    resolver.spawn_renewal();
+    Ok(())
+}
+
 /// Start schema discovery process
-pub async fn start_schema_discovery(config: &Config) -> Result<()> {
+pub async fn start_schema_discovery(
+    datasources: &[DataSource],
+    server_client: &dyn ServerApi,
+    global_filters: Option<GlobalFilters>,
+) -> Result<()> {
     info!("Starting schema discovery...");
-    let server_client = ServerClient::new(
-        config.server.api_key.clone(),
-        config.server.server_url.clone(),
-    );
-    let datasources = config.datasources.clone();
-    let global_filters = config.global_filters.clone();
+    discover_and_submit_schemas(datasources, server_client, global_filters).await
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let log_handle = logging::init();
+    crash::install_panic_hook();
+    let config_override = config_path_override(cli.config.clone());
 
-    discover_and_submit_schemas(&datasources, &server_client, global_filters).await
+    if let Err(e) = run(&cli, config_override, log_handle) {
+        error!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Build the runtime, which must wait until the agent is done daemonizing
+fn runtime() -> Result<tokio::runtime::Runtime> {
+    tokio::runtime::Runtime::new().context("Failed to start the async runtime")
 }
 
-#[tokio::main]
-async fn main() {
-    env_logger::init();
-    info!("Starting TSight Agent");
+/// Run the command of `cli`
+fn run(cli: &Cli, config_override: Option<PathBuf>, log_handle: LogHandle) -> Result<()> {
+    if (cli.daemon || cli.pid_file.is_some()) && *cli.command() != Command::Run {
+        return Err(anyhow!(
+            "--daemon and --pid-file only apply to running the agent"
+        ));
+    }
 
-    // Load configuration
-    let config = match load_config() {
-        Ok(config) => {
+    match cli.command() {
+        Command::Run => {
+            info!("Starting TSight Agent {}", build_info::build_info());
+            let config = load_config(config_override)?;
             info!("Configuration loaded successfully");
-            config
+
+            // Locked before forking, so a second agent fails on the terminal
+            let mut pid_file = cli.pid_file.as_deref().map(PidFile::acquire).transpose()?;
+            if cli.daemon {
+                let log_path = config
+                    .logging
+                    .as_ref()
+                    .and_then(|logging| logging.file.as_ref())
+                    .map(|file| file.path.as_path());
+                match log_path {
+                    Some(path) => info!("Running in the background, logging to {}", path.display()),
+                    None => {
+                        warn!("Running in the background without logging.file, discarding logs")
+                    }
+                }
+                daemon::daemonize(log_path)?;
+            }
+            if let Some(pid_file) = &mut pid_file {
+                pid_file.write_pid()?;
+                info!("Wrote PID to {}", pid_file.path().display());
+            }
+
+            runtime()?.block_on(run_agent(config, &log_handle));
+            Ok(())
+        }
+        Command::Service(ServiceCommand::Run { name }) => {
+            // The service manager blocks this thread until the service stopped
+            let runtime = runtime()?;
+            service::run(name, move || {
+                info!("Starting TSight Agent {}", build_info::build_info());
+                match load_config(config_override) {
+                    Ok(config) => runtime.block_on(run_agent(config, &log_handle)),
+                    Err(e) => error!("{:#}", e),
+                }
+            })
+        }
+        command => runtime()?.block_on(run_command(command, config_override)),
+    }
+}
+
+/// Run the agent until SIGTERM, Ctrl-C or the service manager stops it
+async fn run_agent(mut config: Config, log_handle: &LogHandle) {
+    if let Some(logging_config) = &config.logging {
+        if let Err(e) = log_handle.configure(logging_config) {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(pod) = kubernetes::pod_metadata() {
+        info!("Running in Kubernetes as {}", pod);
+    }
+
+    // Kept alive for the agent's lifetime, spans are exported in batches
+    let _tracer_provider = match config.opentelemetry.as_ref().map(telemetry::init) {
+        Some(Ok((layer, provider))) => {
+            log_handle.set_trace_layer(layer);
+            info!("Exporting traces to OTLP collector");
+            Some(provider)
         }
-        Err(e) => {
+        Some(Err(e)) => {
             error!("{:#}", e);
             std::process::exit(1);
         }
+        None => None,
     };
 
-    // Initialize all agents
-    let (hp_agent, job_agent, main_agent) = initialize_agents(&config);
+    if let Some(audit_config) = &config.audit {
+        if let Err(e) = audit::init(audit_config) {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+        info!("Writing audit log to {}", audit_config.path.display());
+    }
+
+    if let Some(signing_config) = &config.query_signing {
+        if let Err(e) = signing::init(signing_config) {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+        info!(
+            "Only running queries signed with one of {} public keys",
+            signing_config.public_keys.len()
+        );
+    }
+
+    if let Some(slow_query_config) = &config.slow_query {
+        if let Err(e) = slow_query::init(slow_query_config) {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(e) = resolve_secrets(&mut config).await {
+        error!("{:#}", e);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = check_read_only(&config.datasources).await {
+        error!("{:#}", e);
+        std::process::exit(1);
+    }
+
+    // Agents of a tenant share one client, and with it the request rate limit
+    let mut tenants: Vec<(Tenant, Arc<dyn ServerApi>)> = Vec::new();
+    for tenant in config.tenants() {
+        match client::from_config(&tenant.server) {
+            Ok(server_client) => tenants.push((tenant, server_client)),
+            Err(e) => {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(notifications) = &config.notifications {
+        if let Err(e) = spawn_notifications(notifications) {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(self_metrics_config) = &config.self_metrics {
+        if let Err(e) = spawn_self_metrics(self_metrics_config, &config.datasources) {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(otlp_metrics_config) = &config.otlp_metrics {
+        if let Err(e) = otlp_metrics::init(otlp_metrics_config) {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+        info!(
+            "Exporting time series results to {}",
+            otlp_metrics_config.endpoint
+        );
+    }
 
-    // Spawn high priority queue agent
-    tokio::spawn(async move { hp_agent.run().await });
+    if let Some(result_export_config) = &config.result_export {
+        if let Err(e) = result_export::init(result_export_config) {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+        info!(
+            "Exporting job results to bucket {}",
+            result_export_config.bucket
+        );
+    }
 
-    // Spawn job processing agent
-    tokio::spawn(async move { job_agent.run().await });
+    if let Some(listener_config) = &config.listener {
+        match Listener::bind(listener_config).await {
+            Ok(listener) => {
+                let (first, server_client) = &tenants[0];
+                let mut readiness =
+                    Readiness::new(server_client.clone(), first.datasources.clone());
+                for (tenant, server_client) in &tenants[1..] {
+                    readiness = readiness.with_tenant(
+                        &tenant.name,
+                        server_client.clone(),
+                        tenant.datasources.clone(),
+                    );
+                }
+                listener
+                    .with_readiness(readiness)
+                    .with_status(StatusPage::new(&config))
+                    .spawn();
+            }
+            Err(e) => {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    // Start schema discovery
-    tokio::spawn(async move {
-        if let Err(e) = start_schema_discovery(&config).await {
-            error!("Failed to discover schemas: {:#}", e);
+    if let Some(grafana_config) = &config.grafana {
+        let agents = tenants
+            .iter()
+            .map(|(tenant, server_client)| {
+                BaseAgent::with_filters(
+                    server_client.clone(),
+                    tenant.datasources.clone(),
+                    config.global_filters.clone(),
+                )
+            })
+            .collect();
+        match GrafanaEndpoint::bind(grafana_config, agents).await {
+            Ok(endpoint) => {
+                endpoint.spawn();
+            }
+            Err(e) => {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
         }
-    });
+    }
+
+    if let Some(remote_read_config) = &config.remote_read {
+        let agents = tenants
+            .iter()
+            .map(|(tenant, server_client)| {
+                BaseAgent::with_filters(
+                    server_client.clone(),
+                    tenant.datasources.clone(),
+                    config.global_filters.clone(),
+                )
+            })
+            .collect();
+        match RemoteReadEndpoint::bind(remote_read_config, agents).await {
+            Ok(endpoint) => {
+                endpoint.spawn();
+            }
+            Err(e) => {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Agent loops, each restarted if it panics, run until shutdown is requested
+    let mut agents = JoinSet::new();
+    for (tenant, server_client) in &tenants {
+        if !config.tenants.is_empty() {
+            info!(
+                "Starting agents of tenant {} with {} datasource(s)",
+                tenant.name,
+                tenant.datasources.len()
+            );
+        }
+        for datasource in &tenant.datasources {
+            spawn_connectivity_probe(datasource.clone(), server_client.clone());
+            spawn_host_probes(datasource.clone());
+        }
+        spawn_error_reporting(
+            server_client.clone(),
+            config
+                .error_report_interval
+                .unwrap_or(DEFAULT_REPORT_INTERVAL),
+        );
+
+        let (hp_agent, job_agent, main_agent) = initialize_tenant_agents(
+            &tenant.datasources,
+            config.global_filters.clone(),
+            server_client.clone(),
+        );
+        // Agents paired with a standby only acquire while holding the lease
+        let leadership = config
+            .leadership
+            .as_ref()
+            .map(|leadership| spawn_lease_renewal(leadership, server_client.clone()));
+        for (component, agent) in [
+            ("high_priority_agent", hp_agent),
+            ("job_agent", job_agent),
+            ("observation_agent", main_agent),
+        ] {
+            let agent = match &leadership {
+                Some(leadership) => agent.with_leadership(leadership.clone()),
+                None => agent,
+            };
+            agents.spawn(crash::supervise(
+                component,
+                server_client.clone(),
+                move || {
+                    let agent = agent.clone();
+                    async move { agent.run().await }
+                },
+            ));
+        }
+    }
+
+    // Report readiness to systemd once the server hands out work, and keep its watchdog fed
+    systemd::spawn_ready_notification(systemd::notifier(), systemd::liveness());
+    if let Some(timeout) = systemd::watchdog_timeout() {
+        info!("Pinging systemd watchdog, timeout {:?}", timeout);
+        systemd::spawn_watchdog(systemd::notifier(), systemd::liveness(), timeout);
+    }
+
+    // Start schema discovery, then keep rediscovering datasources with an interval
+    for (tenant, server_client) in tenants {
+        let global_filters = config.global_filters.clone();
+        tokio::spawn(async move {
+            if let Err(e) = start_schema_discovery(
+                &tenant.datasources,
+                server_client.as_ref(),
+                global_filters.clone(),
+            )
+            .await
+            {
+                error!("Failed to discover schemas: {:#}", e);
+            }
+            for datasource in &tenant.datasources {
+                spawn_scheduled_discovery(
+                    datasource.clone(),
+                    server_client.clone(),
+                    global_filters.clone(),
+                );
+            }
+        });
+    }
 
     info!("Starting main processing loop");
-    main_agent.run().await;
+    tokio::select! {
+        _ = shutdown::signal() => (),
+        _ = shutdown::requested() => (),
+    }
+
+    info!(
+        "Shutting down, waiting up to {:?} for running tasks",
+        shutdown::SHUTDOWN_TIMEOUT
+    );
+    systemd::notifier().notify_status(systemd::STOPPING, "Finishing running tasks");
+    shutdown::request();
+    let finished = tokio::time::timeout(shutdown::SHUTDOWN_TIMEOUT, async {
+        while agents.join_next().await.is_some() {}
+    })
+    .await;
+    if finished.is_err() {
+        warn!("Running tasks didn't finish in time, stopping anyway");
+    }
+    info!("TSight Agent stopped");
 }
 
 #[cfg(test)]
@@ -159,6 +842,12 @@ datasources:
         assert_eq!(config.datasources[0].name, "test_source");
     }
     
+    #[test]
+    fn test_load_config_with_missing_override() {
+        let result = load_config(Some(PathBuf::from("/nonexistent/tsight.yaml")));
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_get_default_config_path() {
         // This test just ensures the function returns a path
@@ -168,4 +857,13 @@ datasources:
         // The path should end with config.yaml
         assert!(path.to_str().unwrap().ends_with("config.yaml"));
     }
+
+    #[test]
+    fn test_get_service_config_path() {
+        let path = get_service_config_path();
+        assert_eq!(path.is_some(), cfg!(target_os = "windows"));
+        if let Some(path) = path {
+            assert!(path.ends_with("tsight_agent/config.yaml"));
+        }
+    }
 }
@@ -0,0 +1,281 @@
+//! Prometheus metrics of the agent
+//!
+//! Metrics are collected in a process-wide registry and served in the text
+//! exposition format by the optional local listener, see [`crate::listener`].
+
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use crate::errors::ErrorCategory;
+use crate::filters::FilterStats;
+
+/// Queue of the high priority observation agent
+pub const HIGH_PRIORITY_QUEUE: &str = "high_priority";
+/// Queue of the main observation agent
+pub const OBSERVATION_QUEUE: &str = "observation";
+/// Queue of the job agent
+pub const JOB_QUEUE: &str = "job";
+
+const QUEUES: [&str; 3] = [HIGH_PRIORITY_QUEUE, OBSERVATION_QUEUE, JOB_QUEUE];
+
+/// Histogram buckets of query durations in seconds, up to the default timeout
+const QUERY_DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Metrics collected by the agent
+pub struct Metrics {
+    registry: Registry,
+    tasks_processed: IntCounterVec,
+    tasks_failed: IntCounterVec,
+    acquire_duration: HistogramVec,
+    task_duration: HistogramVec,
+    query_duration: HistogramVec,
+    filtered_rows: IntCounterVec,
+    masked_values: IntCounterVec,
+    errors: IntCounterVec,
+    host_queries: IntCounterVec,
+    host_healthy: IntGaugeVec,
+    host_latency: GaugeVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Get the process-wide metrics
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let tasks_processed = IntCounterVec::new(
+            Opts::new(
+                "tsight_tasks_processed_total",
+                "Tasks and jobs whose results were submitted",
+            ),
+            &["queue"],
+        )
+        .unwrap();
+        let tasks_failed = IntCounterVec::new(
+            Opts::new(
+                "tsight_tasks_failed_total",
+                "Tasks and jobs that failed after being acquired",
+            ),
+            &["queue"],
+        )
+        .unwrap();
+        let acquire_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "tsight_acquire_duration_seconds",
+                "Duration of requests acquiring the next task or job",
+            ),
+            &["queue"],
+        )
+        .unwrap();
+        let task_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "tsight_task_duration_seconds",
+                "Duration of processing acquired tasks and jobs, up to submitting their results",
+            )
+            .buckets(QUERY_DURATION_BUCKETS.to_vec()),
+            &["queue"],
+        )
+        .unwrap();
+        let query_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "tsight_query_duration_seconds",
+                "Duration of task and job queries, including timed out ones",
+            )
+            .buckets(QUERY_DURATION_BUCKETS.to_vec()),
+            &["datasource"],
+        )
+        .unwrap();
+        let filtered_rows = IntCounterVec::new(
+            Opts::new(
+                "tsight_filtered_rows_total",
+                "Job rows left out of results by filters, sampling or privacy",
+            ),
+            &["datasource", "reason"],
+        )
+        .unwrap();
+        let masked_values = IntCounterVec::new(
+            Opts::new(
+                "tsight_masked_values_total",
+                "Job values rewritten by mask, redact_value or hash rules",
+            ),
+            &["datasource"],
+        )
+        .unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "tsight_errors_total",
+                "Errors of the agent loops by category, including idle polls",
+            ),
+            &["category"],
+        )
+        .unwrap();
+        let host_queries = IntCounterVec::new(
+            Opts::new(
+                "tsight_host_queries_total",
+                "Tasks and jobs served by each host of a datasource",
+            ),
+            &["datasource", "host"],
+        )
+        .unwrap();
+        let host_healthy = IntGaugeVec::new(
+            Opts::new(
+                "tsight_host_healthy",
+                "Whether the last probe of a datasource host succeeded",
+            ),
+            &["datasource", "host"],
+        )
+        .unwrap();
+        let host_latency = GaugeVec::new(
+            Opts::new(
+                "tsight_host_probe_latency_seconds",
+                "Duration of the last probe of a datasource host",
+            ),
+            &["datasource", "host"],
+        )
+        .unwrap();
+
+        registry
+            .register(Box::new(tasks_processed.clone()))
+            .unwrap();
+        registry.register(Box::new(tasks_failed.clone())).unwrap();
+        registry
+            .register(Box::new(acquire_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(task_duration.clone())).unwrap();
+        registry.register(Box::new(query_duration.clone())).unwrap();
+        registry.register(Box::new(filtered_rows.clone())).unwrap();
+        registry.register(Box::new(masked_values.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(host_queries.clone())).unwrap();
+        registry.register(Box::new(host_healthy.clone())).unwrap();
+        registry.register(Box::new(host_latency.clone())).unwrap();
+
+        // Report every queue from the start rather than once it sees a task
+        for queue in QUEUES {
+            tasks_processed.with_label_values(&[queue]);
+            tasks_failed.with_label_values(&[queue]);
+        }
+        for category in ErrorCategory::ALL {
+            errors.with_label_values(&[category.as_str()]);
+        }
+
+        Self {
+            registry,
+            tasks_processed,
+            tasks_failed,
+            acquire_duration,
+            task_duration,
+            query_duration,
+            filtered_rows,
+            masked_values,
+            errors,
+            host_queries,
+            host_healthy,
+            host_latency,
+        }
+    }
+
+    /// Record the outcome of a task or job acquired from `queue`, and how
+    /// long it took
+    pub fn record_task(&self, queue: &str, succeeded: bool, duration: Duration) {
+        let counter = if succeeded {
+            &self.tasks_processed
+        } else {
+            &self.tasks_failed
+        };
+        counter.with_label_values(&[queue]).inc();
+        self.task_duration
+            .with_label_values(&[queue])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record an error of an agent loop
+    pub fn record_error(&self, category: ErrorCategory) {
+        self.errors.with_label_values(&[category.as_str()]).inc();
+    }
+
+    /// Record how long acquiring from `queue` took, whether or not it
+    /// returned work
+    pub fn record_acquire(&self, queue: &str, duration: Duration) {
+        self.acquire_duration
+            .with_label_values(&[queue])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record how long a query on `datasource` ran
+    pub fn record_query(&self, datasource: &str, duration: Duration) {
+        self.query_duration
+            .with_label_values(&[datasource])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Record that `host` of `datasource` served a task or job
+    pub fn record_host_query(&self, datasource: &str, host: &str) {
+        self.host_queries
+            .with_label_values(&[datasource, host])
+            .inc();
+    }
+
+    /// Record the outcome of a probe of `host` of `datasource`
+    pub fn record_host_probe(
+        &self,
+        datasource: &str,
+        host: &str,
+        healthy: bool,
+        latency: Duration,
+    ) {
+        self.host_healthy
+            .with_label_values(&[datasource, host])
+            .set(healthy as i64);
+        self.host_latency
+            .with_label_values(&[datasource, host])
+            .set(latency.as_secs_f64());
+    }
+
+    /// Record the rows and values filters changed in results of `datasource`
+    pub fn record_filters(&self, datasource: &str, stats: &FilterStats) {
+        for (reason, rows) in [
+            ("filter", stats.dropped_rows),
+            ("sample", stats.sampled_rows),
+            ("privacy", stats.suppressed_rows),
+        ] {
+            if rows > 0 {
+                self.filtered_rows
+                    .with_label_values(&[datasource, reason])
+                    .inc_by(rows);
+            }
+        }
+        if stats.masked_values > 0 {
+            self.masked_values
+                .with_label_values(&[datasource])
+                .inc_by(stats.masked_values);
+        }
+    }
+
+    /// Current values of all metrics
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.registry.gather()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // Encoding into a buffer only fails for invalid metric families,
+        // which the registry rejects on registration
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
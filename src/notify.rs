@@ -0,0 +1,368 @@
+//! Webhook notifications of sustained local failures
+//!
+//! Some failures can't be seen by the server: a datasource the agent can't
+//! connect to, a server the agent can't reach, or credentials the server
+//! keeps rejecting. Their occurrences are collected in process-wide
+//! [`Conditions`], and with `notifications` configured a [`Notifier`]
+//! checks them periodically, posting to each webhook when a condition has
+//! lasted long enough and again once it's resolved.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use humantime_serde::re::humantime::format_duration;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::build_info::build_info;
+use crate::client::DatasourceStatus;
+use crate::config::{NotificationsConfig, WebhookConfig, WebhookFormat};
+use crate::http::client_builder;
+use crate::leadership::host_name;
+
+/// Interval of condition checks
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Time a webhook gets to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Condition a notification is about
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// A probed datasource stayed unreachable
+    DatasourceDown,
+    /// A server couldn't be connected to
+    ServerUnreachable,
+    /// A server repeatedly rejected the agent's credentials
+    AuthFailures,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationState {
+    Firing,
+    Resolved,
+}
+
+/// Notification posted to webhooks, as the body of generic ones
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Notification {
+    pub condition: Condition,
+    pub state: NotificationState,
+    /// Name of the datasource or URL of the server the condition is about
+    pub subject: String,
+    pub message: String,
+    /// Time the condition started
+    pub since: DateTime<Utc>,
+    /// Host name of the agent
+    pub agent: String,
+    pub version: String,
+}
+
+/// Failure lasting since a point in time
+#[derive(Debug, Clone)]
+struct Outage {
+    since: DateTime<Utc>,
+    error: String,
+}
+
+/// Occurrences of the conditions of notifications
+pub struct Conditions {
+    datasources_down: Mutex<BTreeMap<String, Outage>>,
+    servers_down: Mutex<BTreeMap<String, Outage>>,
+    auth_failures: Mutex<BTreeMap<String, VecDeque<DateTime<Utc>>>>,
+}
+
+static CONDITIONS: LazyLock<Conditions> = LazyLock::new(Conditions::new);
+
+/// Get the conditions of the process's agents
+pub fn conditions() -> &'static Conditions {
+    &CONDITIONS
+}
+
+impl Conditions {
+    pub fn new() -> Self {
+        Self {
+            datasources_down: Mutex::new(BTreeMap::new()),
+            servers_down: Mutex::new(BTreeMap::new()),
+            auth_failures: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Record the outcome of a connectivity probe of a datasource
+    pub fn record_datasource_status(&self, datasource: &str, status: &DatasourceStatus) {
+        let mut down = self.datasources_down.lock().unwrap();
+        match &status.error {
+            Some(error) if !status.reachable => {
+                down.entry(datasource.to_string())
+                    .or_insert_with(|| Outage {
+                        since: status.checked_at,
+                        error: String::new(),
+                    })
+                    .error = error.clone();
+            }
+            _ => {
+                down.remove(datasource);
+            }
+        }
+    }
+
+    /// Record that a request to the server at `server_url` got a response
+    pub fn record_server_reachable(&self, server_url: &str) {
+        self.servers_down.lock().unwrap().remove(server_url);
+    }
+
+    /// Record that the server at `server_url` couldn't be connected to
+    pub fn record_server_unreachable(&self, server_url: &str, error: &str, at: DateTime<Utc>) {
+        self.servers_down
+            .lock()
+            .unwrap()
+            .entry(server_url.to_string())
+            .or_insert_with(|| Outage {
+                since: at,
+                error: String::new(),
+            })
+            .error = error.to_string();
+    }
+
+    /// Record that the server at `server_url` rejected the agent's credentials
+    pub fn record_auth_failure(&self, server_url: &str, at: DateTime<Utc>) {
+        self.auth_failures
+            .lock()
+            .unwrap()
+            .entry(server_url.to_string())
+            .or_default()
+            .push_back(at);
+    }
+
+    /// Conditions lasting long enough to notify about at `now`, by
+    /// condition and subject
+    fn sustained(
+        &self,
+        config: &NotificationsConfig,
+        now: DateTime<Utc>,
+    ) -> BTreeMap<(Condition, String), (DateTime<Utc>, String)> {
+        let mut sustained = BTreeMap::new();
+        let lasted = |since: DateTime<Utc>, threshold: Duration| {
+            (now - since).to_std().unwrap_or_default() >= threshold
+        };
+
+        for (datasource, outage) in self.datasources_down.lock().unwrap().iter() {
+            if lasted(outage.since, config.datasource_down_after) {
+                let message = format!(
+                    "Datasource {} has been unreachable for over {}: {}",
+                    datasource,
+                    format_duration(config.datasource_down_after),
+                    outage.error
+                );
+                sustained.insert(
+                    (Condition::DatasourceDown, datasource.clone()),
+                    (outage.since, message),
+                );
+            }
+        }
+
+        for (server_url, outage) in self.servers_down.lock().unwrap().iter() {
+            if lasted(outage.since, config.server_down_after) {
+                let message = format!(
+                    "Server {} has been unreachable for over {}: {}",
+                    server_url,
+                    format_duration(config.server_down_after),
+                    outage.error
+                );
+                sustained.insert(
+                    (Condition::ServerUnreachable, server_url.clone()),
+                    (outage.since, message),
+                );
+            }
+        }
+
+        let mut auth_failures = self.auth_failures.lock().unwrap();
+        auth_failures.retain(|server_url, failures| {
+            // Failures that happened a whole window ago no longer count
+            while failures
+                .front()
+                .is_some_and(|&at| lasted(at, config.auth_failure_window))
+            {
+                failures.pop_front();
+            }
+            if config.auth_failures > 0 && failures.len() >= config.auth_failures {
+                let message = format!(
+                    "Server {} rejected the agent's credentials {} times in the last {}",
+                    server_url,
+                    failures.len(),
+                    format_duration(config.auth_failure_window)
+                );
+                sustained.insert(
+                    (Condition::AuthFailures, server_url.clone()),
+                    (failures[0], message),
+                );
+            }
+            !failures.is_empty()
+        });
+        sustained
+    }
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Posts notifications of conditions as they start lasting long enough and
+/// as they're resolved
+pub struct Notifier {
+    config: NotificationsConfig,
+    conditions: &'static Conditions,
+    client: reqwest::Client,
+    agent: String,
+    /// Conditions notified about and not resolved yet
+    firing: BTreeMap<(Condition, String), Notification>,
+}
+
+impl Notifier {
+    /// Notifier of the process-wide conditions
+    pub fn new(config: &NotificationsConfig) -> Result<Self> {
+        Self::with_conditions(config, conditions())
+    }
+
+    pub fn with_conditions(
+        config: &NotificationsConfig,
+        conditions: &'static Conditions,
+    ) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            conditions,
+            client: client_builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .context("Failed to build webhook client")?,
+            agent: host_name(),
+            firing: BTreeMap::new(),
+        })
+    }
+
+    /// Notifications of conditions that started lasting long enough or got
+    /// resolved since the previous evaluation
+    pub fn evaluate(&mut self, now: DateTime<Utc>) -> Vec<Notification> {
+        let sustained = self.conditions.sustained(&self.config, now);
+        let mut notifications = Vec::new();
+
+        let resolved: Vec<_> = self
+            .firing
+            .keys()
+            .filter(|key| !sustained.contains_key(key))
+            .cloned()
+            .collect();
+        for key in resolved {
+            let Some(firing) = self.firing.remove(&key) else {
+                continue;
+            };
+            let message = match firing.condition {
+                Condition::DatasourceDown => {
+                    format!("Datasource {} is reachable again", firing.subject)
+                }
+                Condition::ServerUnreachable => {
+                    format!("Server {} is reachable again", firing.subject)
+                }
+                Condition::AuthFailures => format!(
+                    "Server {} stopped rejecting the agent's credentials",
+                    firing.subject
+                ),
+            };
+            notifications.push(Notification {
+                state: NotificationState::Resolved,
+                message,
+                ..firing
+            });
+        }
+
+        for ((condition, subject), (since, message)) in sustained {
+            let key = (condition, subject.clone());
+            if self.firing.contains_key(&key) {
+                continue;
+            }
+            let notification = Notification {
+                condition,
+                state: NotificationState::Firing,
+                subject,
+                message,
+                since,
+                agent: self.agent.clone(),
+                version: build_info().version.clone(),
+            };
+            self.firing.insert(key, notification.clone());
+            notifications.push(notification);
+        }
+        notifications
+    }
+
+    /// Evaluate the conditions and post their notifications to every webhook
+    pub async fn check(&mut self) {
+        for notification in self.evaluate(Utc::now()) {
+            match notification.state {
+                NotificationState::Firing => log::warn!("{}", notification.message),
+                NotificationState::Resolved => log::info!("{}", notification.message),
+            }
+            for webhook in &self.config.webhooks {
+                if let Err(e) = self.send(webhook, &notification).await {
+                    log::warn!("Failed to notify webhook: {:#}", e);
+                }
+            }
+        }
+    }
+
+    async fn send(&self, webhook: &WebhookConfig, notification: &Notification) -> Result<()> {
+        let body = match webhook.format {
+            WebhookFormat::Generic => serde_json::to_value(notification)?,
+            WebhookFormat::Slack => json!({ "text": slack_text(notification) }),
+        };
+        let mut request = self.client.post(&webhook.url).json(&body);
+        for (name, value) in webhook.headers.iter().flatten() {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.context("Request failed")?;
+        if !response.status().is_success() {
+            bail!("Webhook answered {}", response.status());
+        }
+        Ok(())
+    }
+}
+
+fn slack_text(notification: &Notification) -> String {
+    let icon = match notification.state {
+        NotificationState::Firing => ":rotating_light:",
+        NotificationState::Resolved => ":white_check_mark:",
+    };
+    format!(
+        "{} *tsight-agent on {}*: {}",
+        icon, notification.agent, notification.message
+    )
+}
+
+/// Check the conditions of notifications every `CHECK_INTERVAL`
+///
+/// Returns `None` when no webhook is configured
+pub fn spawn_notifications(config: &NotificationsConfig) -> Result<Option<JoinHandle<()>>> {
+    if config.webhooks.is_empty() {
+        return Ok(None);
+    }
+    let mut notifier = Notifier::new(config)?;
+    log::info!(
+        "Notifying {} webhook(s) of sustained failures",
+        config.webhooks.len()
+    );
+    Ok(Some(tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(CHECK_INTERVAL);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            notifier.check().await;
+        }
+    })))
+}
@@ -0,0 +1,221 @@
+//! OTLP export of time series task results
+//!
+//! With `otlp_metrics` configured, the records of observation tasks are
+//! mirrored to a collector as gauges while they're submitted to the server,
+//! so an existing observability stack gets the same data without querying
+//! the datasources again. Each value column becomes a metric named after it,
+//! with the record's labels, its datasource and, for multi-query tasks, its
+//! result set as attributes. Gauges are batched and posted as OTLP/HTTP
+//! protobuf in the background; when the collector falls behind, records are
+//! dropped rather than holding up tasks.
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::{
+    metric, number_data_point, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use prost::Message;
+use reqwest::header::CONTENT_TYPE;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::build_info::build_info;
+use crate::config::OtlpMetricsConfig;
+use crate::http::client_builder;
+use crate::leadership::host_name;
+use crate::models::Record;
+use crate::telemetry::DEFAULT_SERVICE_NAME;
+
+static METRIC_EXPORTER: OnceLock<MetricExporter> = OnceLock::new();
+
+/// Path of the metrics endpoint below the collector's OTLP/HTTP address
+const METRICS_PATH: &str = "/v1/metrics";
+
+/// Gauge batches waiting to be posted, beyond which records are dropped
+const QUEUE_CAPACITY: usize = 256;
+
+/// Most batches combined into one request
+const MAX_BATCHES_PER_REQUEST: usize = 32;
+
+/// Time the collector gets to answer
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Export the results of all agents to the configured collector
+pub fn init(config: &OtlpMetricsConfig) -> Result<()> {
+    let exporter = MetricExporter::spawn(config)?;
+    METRIC_EXPORTER
+        .set(exporter)
+        .map_err(|_| anyhow!("OTLP metric export is already initialized"))
+}
+
+/// The metric exporter, unless none is configured
+pub fn metric_exporter() -> Option<&'static MetricExporter> {
+    METRIC_EXPORTER.get()
+}
+
+/// Queues gauges of task results for a background task posting them
+pub struct MetricExporter {
+    sender: mpsc::Sender<Vec<Metric>>,
+    metric_prefix: String,
+    datasources: Option<Vec<String>>,
+}
+
+impl MetricExporter {
+    /// Create an exporter, posting to the collector from a spawned task
+    pub fn spawn(config: &OtlpMetricsConfig) -> Result<Self> {
+        let url = format!("{}{}", config.endpoint.trim_end_matches('/'), METRICS_PATH);
+        let mut request = client_builder()
+            .timeout(EXPORT_TIMEOUT)
+            .build()
+            .context("Failed to build OTLP client")?
+            .post(&url)
+            .header(CONTENT_TYPE, "application/x-protobuf");
+        for (name, value) in config.headers.iter().flatten() {
+            request = request.header(name, value);
+        }
+        let resource = Resource {
+            attributes: vec![
+                attribute(
+                    "service.name",
+                    config
+                        .service_name
+                        .as_deref()
+                        .unwrap_or(DEFAULT_SERVICE_NAME),
+                ),
+                attribute("service.version", &build_info().version),
+                attribute("host.name", &host_name()),
+            ],
+            ..Default::default()
+        };
+
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(post_batches(request, resource, receiver));
+        Ok(Self {
+            sender,
+            metric_prefix: config.metric_prefix.clone(),
+            datasources: config.datasources.clone(),
+        })
+    }
+
+    /// Queue the records of a task on `datasource` for export, unless its
+    /// results aren't exported
+    pub fn export(&self, datasource: &str, result_set: Option<&str>, records: &[Record]) {
+        if records.is_empty()
+            || self
+                .datasources
+                .as_ref()
+                .is_some_and(|exported| !exported.iter().any(|name| name == datasource))
+        {
+            return;
+        }
+        let metrics = gauges(&self.metric_prefix, datasource, result_set, records);
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.sender.try_send(metrics) {
+            warn!(
+                "OTLP metric export is falling behind, dropped {} records of {}",
+                records.len(),
+                datasource
+            );
+        }
+    }
+}
+
+/// Gauges of records, one per value column, with a point per record
+pub fn gauges(
+    metric_prefix: &str,
+    datasource: &str,
+    result_set: Option<&str>,
+    records: &[Record],
+) -> Vec<Metric> {
+    let mut points: BTreeMap<&str, Vec<NumberDataPoint>> = BTreeMap::new();
+    for record in records {
+        let mut attributes: Vec<_> = record
+            .labels
+            .iter()
+            .map(|(name, value)| attribute(name, value))
+            .collect();
+        attributes.push(attribute("datasource", datasource));
+        if let Some(result_set) = result_set {
+            attributes.push(attribute("result_set", result_set));
+        }
+        for (name, &value) in &record.values {
+            points.entry(name).or_default().push(NumberDataPoint {
+                attributes: attributes.clone(),
+                time_unix_nano: (record.t.max(0) as u64).saturating_mul(1_000_000),
+                value: Some(number_data_point::Value::AsDouble(value)),
+                ..Default::default()
+            });
+        }
+    }
+    points
+        .into_iter()
+        .map(|(name, data_points)| Metric {
+            name: format!("{}{}", metric_prefix, name),
+            data: Some(metric::Data::Gauge(Gauge { data_points })),
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+/// Post queued gauges until the exporter is dropped, combining the batches
+/// that piled up during the previous request
+async fn post_batches(
+    request: reqwest::RequestBuilder,
+    resource: Resource,
+    mut receiver: mpsc::Receiver<Vec<Metric>>,
+) {
+    while let Some(mut metrics) = receiver.recv().await {
+        for _ in 1..MAX_BATCHES_PER_REQUEST {
+            match receiver.try_recv() {
+                Ok(more) => metrics.extend(more),
+                Err(_) => break,
+            }
+        }
+        let body = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(resource.clone()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(InstrumentationScope {
+                        name: DEFAULT_SERVICE_NAME.to_string(),
+                        version: build_info().version.clone(),
+                        ..Default::default()
+                    }),
+                    metrics,
+                    schema_url: String::new(),
+                }],
+                schema_url: String::new(),
+            }],
+        };
+        let Some(request) = request.try_clone() else {
+            return;
+        };
+        if let Err(e) = post(request, body).await {
+            warn!("Failed to export metrics to the OTLP collector: {:#}", e);
+        }
+    }
+}
+
+async fn post(request: reqwest::RequestBuilder, body: ExportMetricsServiceRequest) -> Result<()> {
+    let response = request
+        .body(body.encode_to_vec())
+        .send()
+        .await
+        .context("Request failed")?;
+    if !response.status().is_success() {
+        bail!("Collector answered {}", response.status());
+    }
+    Ok(())
+}
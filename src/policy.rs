@@ -0,0 +1,619 @@
+//! Checks of queries received from the server against a datasource's policy
+//!
+//! Queries are parsed before execution, so a compromised or misbehaving
+//! server can't make the agent run `INSERT`, `DROP` or anything else besides
+//! the statements the datasource accepts, nor read tables and databases the
+//! filter rules exclude by querying them directly.
+
+use crate::config::{QueryPolicy, StatementKind};
+use crate::filters::SqlFilters;
+use sqlparser::ast::{
+    DescribeAlias, Expr, FunctionArg, FunctionArgExpr, FunctionArguments, Ident, ObjectName, Query,
+    SetExpr, Statement, TableAlias, TableFactor, Value, Visit, VisitMut, Visitor, VisitorMut,
+};
+use sqlparser::dialect::ClickHouseDialect;
+use sqlparser::parser::Parser;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+/// Database of tables referenced without one, unless the datasource sets
+/// another
+pub const DEFAULT_DATABASE: &str = "default";
+
+/// Table functions generating data rather than reading it from elsewhere
+const GENERATOR_TABLE_FUNCTIONS: &[&str] = &[
+    "numbers",
+    "numbers_mt",
+    "zeros",
+    "zeros_mt",
+    "generate_series",
+    "generaterandom",
+    "values",
+];
+
+/// Prefixes of functions reading the dictionary or `Join` table named by
+/// their first argument, such as `dictGet` or `joinGetOrNull`, lowercase
+const TABLE_READING_FUNCTION_PREFIXES: &[&str] = &["dictget", "dicthas", "dictisin", "joinget"];
+
+/// Query not accepted by a datasource's policy
+#[derive(Debug, thiserror::Error)]
+#[error("query rejected by policy: {0}")]
+pub struct PolicyError(String);
+
+/// Check that `sql` is a single statement of a kind the policy accepts, and
+/// that it only references tables and databases `filters` don't exclude,
+/// with tables referenced without a database read from `database`
+pub fn check_query(
+    policy: &QueryPolicy,
+    filters: Option<&SqlFilters>,
+    database: &str,
+    sql: &str,
+) -> Result<(), PolicyError> {
+    if !policy.enabled {
+        return Ok(());
+    }
+
+    let statements = Parser::parse_sql(&ClickHouseDialect {}, sql)
+        .map_err(|e| PolicyError(format!("failed to parse query: {}", e)))?;
+
+    let statement = match statements.as_slice() {
+        [statement] => statement,
+        [] => return Err(PolicyError("query is empty".to_string())),
+        _ => {
+            return Err(PolicyError(format!(
+                "expected a single statement, got {}",
+                statements.len()
+            )))
+        }
+    };
+
+    match statement_kind(statement) {
+        Some(kind) if policy.allowed_statements.contains(&kind) => {
+            check_explained_statement(policy, statement)?;
+            match filters {
+                Some(filters) => check_table_access(filters, database, statement),
+                None => Ok(()),
+            }
+        }
+        Some(kind) => Err(PolicyError(format!(
+            "{} statements are not allowed",
+            format!("{:?}", kind).to_uppercase()
+        ))),
+        None => Err(PolicyError(
+            "only read-only statements are allowed".to_string(),
+        )),
+    }
+}
+
+/// Kind of a read-only statement, `None` for anything that may modify data
+fn statement_kind(statement: &Statement) -> Option<StatementKind> {
+    match statement {
+        Statement::Query(query) if is_read_only_query(query) => Some(StatementKind::Select),
+        Statement::ShowFunctions { .. }
+        | Statement::ShowVariable { .. }
+        | Statement::ShowStatus { .. }
+        | Statement::ShowVariables { .. }
+        | Statement::ShowCreate { .. }
+        | Statement::ShowColumns { .. }
+        | Statement::ShowDatabases { .. }
+        | Statement::ShowSchemas { .. }
+        | Statement::ShowObjects(_)
+        | Statement::ShowTables { .. }
+        | Statement::ShowViews { .. }
+        | Statement::ShowCollation { .. } => Some(StatementKind::Show),
+        Statement::ExplainTable { .. } => Some(StatementKind::Describe),
+        Statement::Explain {
+            describe_alias: DescribeAlias::Explain,
+            analyze: false,
+            ..
+        } => Some(StatementKind::Explain),
+        Statement::SetVariable { .. } => Some(StatementKind::Set),
+        _ => None,
+    }
+}
+
+/// Check the statement wrapped by `EXPLAIN`, which must be accepted on its own
+fn check_explained_statement(
+    policy: &QueryPolicy,
+    statement: &Statement,
+) -> Result<(), PolicyError> {
+    let Statement::Explain { statement, .. } = statement else {
+        return Ok(());
+    };
+    match statement_kind(statement) {
+        Some(kind) if policy.allowed_statements.contains(&kind) => {
+            check_explained_statement(policy, statement)
+        }
+        _ => Err(PolicyError(
+            "EXPLAIN is only allowed for accepted statements".to_string(),
+        )),
+    }
+}
+
+/// Check that a query only reads data: no `SELECT INTO`, locking clauses or
+/// data-modifying statements nested in it
+fn is_read_only_query(query: &Query) -> bool {
+    let ctes_read_only = query
+        .with
+        .iter()
+        .flat_map(|with| &with.cte_tables)
+        .all(|cte| is_read_only_query(&cte.query));
+    ctes_read_only && query.locks.is_empty() && is_read_only_set_expr(&query.body)
+}
+
+fn is_read_only_set_expr(body: &SetExpr) -> bool {
+    match body {
+        SetExpr::Select(select) => select.into.is_none(),
+        SetExpr::Query(query) => is_read_only_query(query),
+        SetExpr::SetOperation { left, right, .. } => {
+            is_read_only_set_expr(left) && is_read_only_set_expr(right)
+        }
+        SetExpr::Values(_) | SetExpr::Table(_) => true,
+        _ => false,
+    }
+}
+
+/// Check every table a statement references against the table and database
+/// rules of `filters`
+fn check_table_access(
+    filters: &SqlFilters,
+    database: &str,
+    statement: &Statement,
+) -> Result<(), PolicyError> {
+    let mut visitor = TableAccessVisitor {
+        filters,
+        database,
+        cte_scopes: CteScopes::default(),
+        in_table_function: false,
+    };
+    match statement.visit(&mut visitor) {
+        ControlFlow::Break(error) => Err(error),
+        ControlFlow::Continue(()) => Ok(()),
+    }
+}
+
+/// Walks a statement, breaking on the first table the filters exclude
+struct TableAccessVisitor<'a> {
+    filters: &'a SqlFilters,
+    /// Database of tables referenced without one
+    database: &'a str,
+    /// Names of common table expressions, which aren't tables
+    cte_scopes: CteScopes,
+    /// Set while visiting a table function, whose name isn't a table
+    in_table_function: bool,
+}
+
+impl Visitor for TableAccessVisitor<'_> {
+    type Break = PolicyError;
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        self.cte_scopes.enter(query);
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _: &Query) -> ControlFlow<Self::Break> {
+        self.cte_scopes.leave();
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<Self::Break> {
+        let function = match table_factor {
+            TableFactor::Table {
+                name,
+                args: Some(_),
+                ..
+            }
+            | TableFactor::Function { name, .. } => Some(name.to_string()),
+            TableFactor::TableFunction { expr, .. } => Some(expr.to_string()),
+            _ => None,
+        };
+
+        if let Some(function) = function {
+            self.in_table_function = true;
+            let is_generator = GENERATOR_TABLE_FUNCTIONS
+                .iter()
+                .any(|name| function.eq_ignore_ascii_case(name));
+            if !is_generator {
+                return ControlFlow::Break(PolicyError(format!(
+                    "table function '{}' is not allowed",
+                    function
+                )));
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_table_factor(&mut self, _: &TableFactor) -> ControlFlow<Self::Break> {
+        self.in_table_function = false;
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<Self::Break> {
+        if self.in_table_function {
+            return ControlFlow::Continue(());
+        }
+
+        match relation_table(relation, self.database, &self.cte_scopes) {
+            Some((database, table)) => self.check_table(database, table),
+            None => ControlFlow::Continue(()),
+        }
+    }
+
+    // Dictionaries and `Join` tables are read by functions without a FROM
+    // clause, so the names they're given are checked like tables
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        let Expr::Function(function) = expr else {
+            return ControlFlow::Continue(());
+        };
+        let Some(name) = function.name.0.last().and_then(|part| part.as_ident()) else {
+            return ControlFlow::Continue(());
+        };
+        let name = name.value.to_lowercase();
+        if !TABLE_READING_FUNCTION_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        {
+            return ControlFlow::Continue(());
+        }
+
+        let source = match &function.args {
+            FunctionArguments::List(list) => list.args.first(),
+            _ => None,
+        };
+        match source.and_then(|arg| argument_table(arg, self.database)) {
+            Some((database, table)) => self.check_table(&database, &table),
+            None => ControlFlow::Break(PolicyError(format!(
+                "function '{}' must name its dictionary or table with a literal",
+                function.name
+            ))),
+        }
+    }
+}
+
+impl TableAccessVisitor<'_> {
+    fn check_table(&self, database: &str, table: &str) -> ControlFlow<PolicyError> {
+        if self.filters.should_exclude_database(database) {
+            return ControlFlow::Break(PolicyError(format!(
+                "database '{}' is excluded by filters",
+                database
+            )));
+        }
+        if self.filters.should_exclude_qualified_table(database, table) {
+            return ControlFlow::Break(PolicyError(format!(
+                "table '{}.{}' is excluded by filters",
+                database, table
+            )));
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Database and table named by a function argument, as a string such as
+/// `'db.dictionary'` or an identifier, in `database` unless it names another
+fn argument_table(arg: &FunctionArg, database: &str) -> Option<(String, String)> {
+    let FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) = arg else {
+        return None;
+    };
+    match expr {
+        Expr::Value(value) => match &value.value {
+            Value::SingleQuotedString(name) => Some(match name.rsplit_once('.') {
+                Some((database, table)) => (database.to_string(), table.to_string()),
+                None => (database.to_string(), name.clone()),
+            }),
+            _ => None,
+        },
+        Expr::Identifier(table) => Some((database.to_string(), table.value.clone())),
+        Expr::CompoundIdentifier(parts) => match parts.as_slice() {
+            [database, table] => Some((database.value.clone(), table.value.clone())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Names of the common table expressions of the queries being visited, one
+/// scope per query, so a name only hides tables where the query defining it
+/// is visible
+#[derive(Default)]
+struct CteScopes(Vec<CteScope>);
+
+/// Common table expressions of a query, in the order they're defined
+struct CteScope {
+    names: Vec<String>,
+    recursive: bool,
+    /// Expressions whose bodies were visited, visible to the next ones
+    visited: usize,
+    /// Whether the query is the body of a common table expression of the
+    /// enclosing one
+    is_cte: bool,
+}
+
+impl CteScope {
+    /// Names visible so far: a name hides tables in the expressions defined
+    /// after it and in the query body, but not in its own body unless the
+    /// query is `RECURSIVE`
+    fn visible(&self) -> &[String] {
+        match self.recursive {
+            true => &self.names,
+            false => &self.names[..self.visited],
+        }
+    }
+}
+
+impl CteScopes {
+    /// Make the common table expressions of `query` visible, as their bodies
+    /// are visited, until it's left
+    fn enter(&mut self, query: &Query) {
+        // Bodies of common table expressions are the first queries visited
+        // within the one defining them
+        let is_cte = self
+            .0
+            .last()
+            .is_some_and(|parent| parent.visited < parent.names.len());
+        self.0.push(CteScope {
+            names: query
+                .with
+                .iter()
+                .flat_map(|with| &with.cte_tables)
+                .map(|cte| cte.alias.name.value.clone())
+                .collect(),
+            recursive: query.with.as_ref().is_some_and(|with| with.recursive),
+            visited: 0,
+            is_cte,
+        });
+    }
+
+    fn leave(&mut self) {
+        let scope = self.0.pop();
+        if let (Some(scope), Some(parent)) = (scope, self.0.last_mut()) {
+            if scope.is_cte {
+                parent.visited += 1;
+            }
+        }
+    }
+
+    /// Whether `name` is a common table expression of an enclosing query
+    fn is_visible(&self, name: &str) -> bool {
+        self.0
+            .iter()
+            .any(|scope| scope.visible().iter().any(|visible| visible == name))
+    }
+}
+
+/// Database and table of a relation, in `database` unless it names another,
+/// `None` for names of common table expressions
+fn relation_table<'a>(
+    relation: &'a ObjectName,
+    database: &'a str,
+    cte_scopes: &CteScopes,
+) -> Option<(&'a str, &'a str)> {
+    let parts: Vec<&str> = relation
+        .0
+        .iter()
+        .filter_map(|part| part.as_ident())
+        .map(|ident| ident.value.as_str())
+        .collect();
+    match parts.as_slice() {
+        [table] if cte_scopes.is_visible(table) => None,
+        [table] => Some((database, *table)),
+        [.., database, table] => Some((*database, *table)),
+        [] => None,
+    }
+}
+
+/// Restrict every read of a table with a row filter to the rows matching the
+/// filter, by replacing the table with a filtered subquery
+///
+/// `row_filters` maps `database.table`, or just `table` in `database`, to a
+/// SQL condition, and tables referenced without a database are read from
+/// `database`. Queries not reading any of those tables are returned unchanged.
+pub fn apply_row_filters(
+    row_filters: Option<&HashMap<String, String>>,
+    database: &str,
+    sql: &str,
+) -> Result<String, PolicyError> {
+    let row_filters = match row_filters {
+        Some(row_filters) if !row_filters.is_empty() => row_filters,
+        _ => return Ok(sql.to_string()),
+    };
+
+    let mut filters = HashMap::new();
+    for (key, condition) in row_filters {
+        let table = match key.rsplit_once('.') {
+            Some((database, table)) => (database.to_string(), table.to_string()),
+            None => (database.to_string(), key.clone()),
+        };
+        filters.insert(table, condition.as_str());
+    }
+
+    let mut statements = Parser::parse_sql(&ClickHouseDialect {}, sql)
+        .map_err(|e| PolicyError(format!("failed to parse query: {}", e)))?;
+
+    let mut rewriter = RowFilterRewriter {
+        filters,
+        database,
+        cte_scopes: CteScopes::default(),
+        rewrites: 0,
+    };
+    for statement in &mut statements {
+        if let ControlFlow::Break(error) = VisitMut::visit(statement, &mut rewriter) {
+            return Err(error);
+        }
+    }
+
+    if rewriter.rewrites == 0 {
+        return Ok(sql.to_string());
+    }
+    Ok(statements
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; "))
+}
+
+/// Replaces tables having a row filter with filtered subqueries
+struct RowFilterRewriter<'a> {
+    /// Row filter conditions keyed by `(database, table)`
+    filters: HashMap<(String, String), &'a str>,
+    /// Database of tables referenced without one
+    database: &'a str,
+    /// Names of common table expressions, which aren't tables
+    cte_scopes: CteScopes,
+    rewrites: usize,
+}
+
+impl RowFilterRewriter<'_> {
+    /// Subquery reading only the rows of `table` matching `condition`,
+    /// taking the table out of the query
+    fn filtered_subquery(
+        &self,
+        table: &mut TableFactor,
+        condition: &str,
+    ) -> Result<Box<Query>, PolicyError> {
+        let invalid = |e| PolicyError(format!("invalid row filter '{}': {}", condition, e));
+        let mut subquery = Parser::new(&ClickHouseDialect {})
+            .try_with_sql(&format!("SELECT * FROM t WHERE {}", condition))
+            .and_then(|mut parser| parser.parse_query())
+            .map_err(invalid)?;
+
+        match subquery.body.as_mut() {
+            SetExpr::Select(select) if select.from.len() == 1 => {
+                std::mem::swap(&mut select.from[0].relation, table);
+                Ok(subquery)
+            }
+            _ => Err(PolicyError(format!(
+                "invalid row filter '{}': not a single condition",
+                condition
+            ))),
+        }
+    }
+}
+
+impl RowFilterRewriter<'_> {
+    /// Reject `PREWHERE` on a select reading a table with a row filter, as it
+    /// would apply to the subquery replacing the table rather than the table
+    fn check_prewhere(&self, query: &Query, body: &SetExpr) -> Result<(), PolicyError> {
+        let select = match body {
+            SetExpr::Select(select) => select,
+            SetExpr::SetOperation { left, right, .. } => {
+                self.check_prewhere(query, left)?;
+                return self.check_prewhere(query, right);
+            }
+            _ => return Ok(()),
+        };
+        if select.prewhere.is_none() {
+            return Ok(());
+        }
+
+        // Common table expressions of the query itself are only visible once
+        // their bodies were visited
+        let is_cte = |name: &str| {
+            query
+                .with
+                .iter()
+                .flat_map(|with| &with.cte_tables)
+                .any(|cte| cte.alias.name.value == name)
+        };
+        let relations = select.from.iter().flat_map(|table| {
+            std::iter::once(&table.relation).chain(table.joins.iter().map(|join| &join.relation))
+        });
+        for relation in relations {
+            let TableFactor::Table {
+                name, args: None, ..
+            } = relation
+            else {
+                continue;
+            };
+            let Some((database, table)) = relation_table(name, self.database, &self.cte_scopes)
+            else {
+                continue;
+            };
+            if name.0.len() == 1 && is_cte(table) {
+                continue;
+            }
+            if self
+                .filters
+                .contains_key(&(database.to_string(), table.to_string()))
+            {
+                return Err(PolicyError(format!(
+                    "PREWHERE can't be used with table '{}.{}', which has a row filter",
+                    database, table
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl VisitorMut for RowFilterRewriter<'_> {
+    type Break = PolicyError;
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<Self::Break> {
+        self.cte_scopes.enter(query);
+        match self.check_prewhere(query, &query.body) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(error) => ControlFlow::Break(error),
+        }
+    }
+
+    fn post_visit_query(&mut self, _: &mut Query) -> ControlFlow<Self::Break> {
+        self.cte_scopes.leave();
+        ControlFlow::Continue(())
+    }
+
+    // Tables are replaced after their children were visited, so the table
+    // moved into the subquery isn't rewritten again
+    fn post_visit_table_factor(
+        &mut self,
+        table_factor: &mut TableFactor,
+    ) -> ControlFlow<Self::Break> {
+        let TableFactor::Table {
+            name,
+            alias,
+            args: None,
+            ..
+        } = table_factor
+        else {
+            return ControlFlow::Continue(());
+        };
+
+        let Some((database, table)) = relation_table(name, self.database, &self.cte_scopes) else {
+            return ControlFlow::Continue(());
+        };
+        let Some(condition) = self
+            .filters
+            .get(&(database.to_string(), table.to_string()))
+            .copied()
+        else {
+            return ControlFlow::Continue(());
+        };
+        // The parser reads the `FINAL` modifier as an alias, which the
+        // subquery would drop
+        if alias.as_ref().is_some_and(|alias| {
+            alias.name.quote_style.is_none() && alias.name.value.eq_ignore_ascii_case("FINAL")
+        }) {
+            return ControlFlow::Break(PolicyError(format!(
+                "FINAL can't be used on table '{}.{}', which has a row filter",
+                database, table
+            )));
+        }
+
+        // Keep the name columns are qualified with in the rest of the query
+        let alias = alias.take().unwrap_or_else(|| TableAlias {
+            name: Ident::new(table),
+            columns: Vec::new(),
+        });
+        match self.filtered_subquery(table_factor, condition) {
+            Ok(subquery) => {
+                *table_factor = TableFactor::Derived {
+                    lateral: false,
+                    subquery,
+                    alias: Some(alias),
+                };
+                self.rewrites += 1;
+                ControlFlow::Continue(())
+            }
+            Err(error) => ControlFlow::Break(error),
+        }
+    }
+}
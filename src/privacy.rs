@@ -0,0 +1,134 @@
+//! Differential privacy post-processing of aggregate job results
+
+use crate::config::PrivacyConfig;
+use crate::filters::{FilterError, FilterStats};
+use crate::models::JobType;
+use rand::Rng;
+use serde_json::{Number, Value};
+use std::collections::HashSet;
+
+/// Validated privacy settings applied to job rows
+#[derive(Debug, Clone)]
+pub struct Privacy {
+    /// Scale of the Laplace noise, `sensitivity / epsilon`
+    noise_scale: Option<f64>,
+    noise_columns: HashSet<String>,
+    count_column: Option<String>,
+    min_group_size: Option<u64>,
+}
+
+impl Privacy {
+    /// Validate privacy settings
+    pub fn new(config: &PrivacyConfig) -> Result<Self, FilterError> {
+        let invalid = |message: &str| FilterError::InvalidPrivacy(message.to_string());
+
+        if !(config.sensitivity.is_finite() && config.sensitivity > 0.0) {
+            return Err(invalid("sensitivity must be a positive number"));
+        }
+        let noise_scale = match config.epsilon {
+            Some(epsilon) if !(epsilon.is_finite() && epsilon > 0.0) => {
+                return Err(invalid("epsilon must be a positive number"));
+            }
+            Some(epsilon) => Some(config.sensitivity / epsilon),
+            None if !config.noise_columns.is_empty() => {
+                return Err(invalid("noise_columns require epsilon to be set"));
+            }
+            None => None,
+        };
+        if config.min_group_size.is_some() && config.count_column.is_none() {
+            return Err(invalid("min_group_size requires count_column to be set"));
+        }
+
+        Ok(Self {
+            noise_scale,
+            noise_columns: config.noise_columns.iter().cloned().collect(),
+            count_column: config.count_column.clone(),
+            min_group_size: config.min_group_size,
+        })
+    }
+
+    /// Suppress groups below the minimum size, then add noise to aggregates
+    ///
+    /// Groups are suppressed based on their true size. Rows without a
+    /// numeric count are suppressed as well, as their size is unknown.
+    pub fn apply(&self, rows: Vec<JobType>, stats: &mut FilterStats) -> Vec<JobType> {
+        let mut rows = match (&self.count_column, self.min_group_size) {
+            (Some(count_column), Some(min_group_size)) => {
+                let total = rows.len();
+                let rows: Vec<JobType> = rows
+                    .into_iter()
+                    .filter(|row| {
+                        row.get(count_column)
+                            .and_then(numeric_value)
+                            .is_some_and(|count| count >= min_group_size as f64)
+                    })
+                    .collect();
+                stats.suppressed_rows += (total - rows.len()) as u64;
+                rows
+            }
+            _ => rows,
+        };
+
+        if let Some(scale) = self.noise_scale {
+            let mut rng = rand::thread_rng();
+            for row in rows.iter_mut() {
+                for (column, value) in row.iter_mut() {
+                    if !self.noise_columns.contains(column) {
+                        continue;
+                    }
+                    if let Some(noised) = add_noise(value, laplace(scale, &mut rng)) {
+                        *value = noised;
+                        stats.noised_values += 1;
+                    }
+                }
+            }
+        }
+
+        rows
+    }
+}
+
+/// Draw from a Laplace distribution centered on 0 by inverse transform
+fn laplace(scale: f64, rng: &mut impl Rng) -> f64 {
+    loop {
+        let u: f64 = rng.gen::<f64>() - 0.5;
+        let tail = 1.0 - 2.0 * u.abs();
+        // The lower end of the range would be an infinite draw
+        if tail > 0.0 {
+            return -scale * u.signum() * tail.ln();
+        }
+    }
+}
+
+/// Numeric value of a JSON number or of a string holding one, as ClickHouse
+/// quotes 64-bit integers
+fn numeric_value(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(string) => string.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Add noise to a numeric value keeping its representation: integers are
+/// rounded and quoted numbers stay quoted
+fn add_noise(value: &Value, noise: f64) -> Option<Value> {
+    match value {
+        Value::Number(number) if number.is_f64() => {
+            Number::from_f64(number.as_f64()? + noise).map(Value::Number)
+        }
+        Value::Number(number) => Some(Value::from((number.as_f64()? + noise).round() as i64)),
+        Value::String(string) => {
+            let string = string.trim();
+            if let Ok(integer) = string.parse::<i64>() {
+                Some(Value::String(
+                    ((integer as f64 + noise).round() as i64).to_string(),
+                ))
+            } else {
+                let number = string.parse::<f64>().ok()?;
+                Some(Value::String((number + noise).to_string()))
+            }
+        }
+        _ => None,
+    }
+}
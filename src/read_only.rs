@@ -0,0 +1,186 @@
+//! Startup check that datasource users can't write
+//!
+//! The agent only ever reads, so a datasource user allowed to insert, alter
+//! or drop is a misconfiguration: combined with a disabled query policy or a
+//! parser bug, one bad query from the server could change data. Each
+//! datasource's user is checked once at startup, logging a warning for
+//! every write privilege, or refusing to start with `read_only_check:
+//! enforce`.
+
+use anyhow::{bail, Result};
+use chrono::Utc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+use crate::config::ReadOnlyCheck;
+use crate::executors::base::UserAccess;
+use crate::executors::create_executor;
+use crate::models::DataSource;
+
+/// Time a datasource gets to report its user's privileges
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Privileges that only read data or metadata
+const READ_PRIVILEGES: &[&str] = &[
+    "SELECT",
+    "SHOW",
+    "DICTGET",
+    "INTROSPECTION",
+    "ADDRESSTOLINE",
+    "ADDRESSTOLINEWITHINLINES",
+    "ADDRESSTOSYMBOL",
+    "DEMANGLE",
+];
+
+/// Write privileges among `access`, e.g. `INSERT ON db.*`, empty for a
+/// read-only user
+///
+/// Grants of roles whose privileges the server didn't expand are reported
+/// too, since they can't be told apart from powerful ones
+pub fn write_privileges(access: &UserAccess) -> Vec<String> {
+    if access.readonly {
+        return Vec::new();
+    }
+    access
+        .grants
+        .iter()
+        .flat_map(|grant| grant_write_privileges(grant))
+        .collect()
+}
+
+/// Write privileges of a `GRANT ... ON ... TO ...` statement
+fn grant_write_privileges(grant: &str) -> Vec<String> {
+    let Some(rest) = grant.trim().strip_prefix("GRANT ") else {
+        // REVOKE statements only take privileges away
+        return Vec::new();
+    };
+    let rest = rest.split(" TO ").next().unwrap_or(rest);
+    let Some((privileges, target)) = rest.split_once(" ON ") else {
+        return vec![format!("role {}", rest.trim())];
+    };
+    split_privileges(privileges)
+        .into_iter()
+        .filter(|privilege| !is_read_privilege(privilege))
+        .map(|privilege| format!("{} ON {}", privilege, target.trim()))
+        .collect()
+}
+
+/// Split a privilege list at commas outside of column lists such as
+/// `SELECT(id, name)`
+fn split_privileges(privileges: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in privileges.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(privileges[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(privileges[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn is_read_privilege(privilege: &str) -> bool {
+    let name = privilege
+        .split('(')
+        .next()
+        .unwrap_or(privilege)
+        .trim()
+        .to_uppercase();
+    READ_PRIVILEGES
+        .iter()
+        .any(|read| name == *read || name.starts_with(&format!("{} ", read)))
+}
+
+/// Check the users of all datasources concurrently, failing if a datasource
+/// enforcing the check has a user that can write
+///
+/// Datasources that are disabled, in a maintenance window or unreachable
+/// are skipped with a warning rather than blocking the agent's start
+pub async fn check_read_only(datasources: &[DataSource]) -> Result<()> {
+    let now = Utc::now();
+    let mut checks = JoinSet::new();
+    for datasource in datasources {
+        if datasource.read_only_check == ReadOnlyCheck::Off
+            || datasource.unavailable_reason(now).is_some()
+        {
+            continue;
+        }
+        let datasource = datasource.clone();
+        checks.spawn(async move {
+            let outcome = user_write_privileges(&datasource).await;
+            (datasource, outcome)
+        });
+    }
+
+    let mut violations = Vec::new();
+    while let Some(joined) = checks.join_next().await {
+        let (datasource, outcome) = match joined {
+            Ok(checked) => checked,
+            Err(e) => {
+                log::warn!("Read-only check failed: {}", e);
+                continue;
+            }
+        };
+        let privileges = match outcome {
+            Ok(privileges) => privileges,
+            Err(e) => {
+                log::warn!(
+                    "Couldn't check that datasource '{}' is read-only: {:#}",
+                    datasource.name,
+                    e
+                );
+                continue;
+            }
+        };
+        if privileges.is_empty() {
+            log::debug!("Datasource '{}' user is read-only", datasource.name);
+            continue;
+        }
+
+        let (username, _) = datasource.credentials().unwrap_or_default();
+        let message = format!(
+            "Datasource '{}' user '{}' can write: {}",
+            datasource.name,
+            username,
+            privileges.join(", ")
+        );
+        if datasource.read_only_check == ReadOnlyCheck::Enforce {
+            violations.push(message);
+        } else {
+            log::warn!(
+                "{}. The agent only needs SELECT, use a read-only user or set read_only_check: enforce to refuse to start",
+                message
+            );
+        }
+    }
+
+    if !violations.is_empty() {
+        violations.sort();
+        bail!(
+            "{}. Use a read-only user, or set read_only_check: warn to start anyway",
+            violations.join("; ")
+        );
+    }
+    Ok(())
+}
+
+/// Write privileges of the datasource's user
+async fn user_write_privileges(datasource: &DataSource) -> Result<Vec<String>> {
+    let check = async {
+        let executor = create_executor(datasource, None).await?;
+        let access = executor.user_access().await?;
+        Ok(write_privileges(&access))
+    };
+    match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(outcome) => outcome,
+        Err(_) => bail!("timed out after {}s", CHECK_TIMEOUT.as_secs()),
+    }
+}
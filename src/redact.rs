@@ -0,0 +1,86 @@
+//! Redaction of sensitive values from error messages and logs
+
+use crate::filters::{Decision, SqlFilters, REDACTED_VALUE};
+
+/// Placeholder replacing quoted string literals
+pub const REDACTED_LITERAL: &str = "'?'";
+
+/// Characters separating the words of a message matched against value
+/// filters, along with whitespace
+const WORD_SEPARATORS: &[char] = &[
+    '(', ')', '[', ']', '{', '}', '<', '>', ',', ';', ':', '=', '"', '\'', '`',
+];
+
+/// Replace quoted string literals in a query or error message with `'?'`
+///
+/// A quote preceded by a letter or digit is taken for an apostrophe, so
+/// messages like "table doesn't exist" are kept intact.
+pub fn redact_literals(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut previous = None;
+
+    while let Some(c) = chars.next() {
+        if c != '\'' || previous.is_some_and(|p: char| p.is_alphanumeric()) {
+            redacted.push(c);
+            previous = Some(c);
+            continue;
+        }
+
+        // Skip the literal up to its closing quote, which is escaped either
+        // with a backslash or by doubling it
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '\'' if chars.peek() == Some(&'\'') => {
+                    chars.next();
+                }
+                '\'' => break,
+                _ => (),
+            }
+        }
+        redacted.push_str(REDACTED_LITERAL);
+        previous = Some('\'');
+    }
+
+    redacted
+}
+
+/// Replace words of an error message or log line excluded by value filters
+/// with `[REDACTED]`
+///
+/// Words are separated by whitespace, quotes, brackets and punctuation such
+/// as `=` or `:`, so values of `key=value` pairs and JSON are matched on
+/// their own. Only explicit exclusions apply, as words of a message are not
+/// expected to match value allow-lists.
+pub fn redact_values(message: &str, filters: Option<&SqlFilters>) -> String {
+    let Some(filters) = filters else {
+        return message.to_string();
+    };
+    let is_separator = |c: char| c.is_whitespace() || WORD_SEPARATORS.contains(&c);
+
+    let mut redacted = String::with_capacity(message.len());
+    let mut rest = message;
+    while !rest.is_empty() {
+        let (word, tail) = rest.split_at(rest.find(is_separator).unwrap_or(rest.len()));
+        let excluded = !word.is_empty()
+            && word != "?"
+            && matches!(filters.explain_value(word), Decision::Excluded(_));
+        redacted.push_str(if excluded { REDACTED_VALUE } else { word });
+
+        let (separators, next) =
+            tail.split_at(tail.find(|c| !is_separator(c)).unwrap_or(tail.len()));
+        redacted.push_str(separators);
+        rest = next;
+    }
+
+    redacted
+}
+
+/// Redact a message embedding a query, like most database errors: both
+/// quoted literals and values excluded by filters are replaced
+pub fn redact_message(message: &str, filters: Option<&SqlFilters>) -> String {
+    redact_values(&redact_literals(message), filters)
+}
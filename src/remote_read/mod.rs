@@ -0,0 +1,356 @@
+//! Endpoint for Prometheus' remote read
+//!
+//! Prometheus configured with a `remote_read` URL pointing here sends the
+//! time range and label matchers of its selectors, and gets back the series
+//! of the configured metrics that match. Each metric is a query template run
+//! like a task from the server: the datasource's query policy and filters,
+//! time macros, timeout, audit log and slow query log all apply, so only
+//! filtered rows leave the agent. Bodies are Snappy compressed protobuf, and
+//! only the samples response type is served, which Prometheus falls back to.
+
+pub mod proto;
+pub mod snappy;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::DateTime;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{info, warn};
+use prost::Message;
+use rand::Rng;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::agent::BaseAgent;
+use crate::client::{AcquireResultBody, JobKind, TimeRange};
+use crate::config::{RemoteReadConfig, RemoteReadMetric};
+use crate::listener::carries_api_key;
+use crate::timeseries::TsMapping;
+use proto::{Label, LabelMatcher, MatchType, Query, QueryResult, ReadRequest, ReadResponse};
+use proto::{Sample, TimeSeries};
+
+/// Largest compressed request body accepted
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Label holding the metric name
+const METRIC_NAME_LABEL: &str = "__name__";
+
+/// Answers read requests with the series of the configured metrics
+pub struct RemoteReadApi {
+    /// Agent of each tenant, to run the queries of its datasources
+    agents: Vec<BaseAgent>,
+    metrics: Vec<RemoteReadMetric>,
+    api_key: Option<String>,
+}
+
+impl RemoteReadApi {
+    pub fn new(config: &RemoteReadConfig, agents: Vec<BaseAgent>) -> Self {
+        Self {
+            agents,
+            metrics: config.metrics.clone(),
+            api_key: config.api_key.clone(),
+        }
+    }
+
+    /// Names of the metrics Prometheus may read
+    pub fn metrics(&self) -> Vec<String> {
+        self.metrics
+            .iter()
+            .map(|metric| metric.name.clone())
+            .collect()
+    }
+
+    /// Check that a request carries the API key as a bearer token or basic
+    /// auth password
+    pub fn is_authorized(&self, authorization: Option<&str>) -> bool {
+        carries_api_key(self.api_key.as_deref(), authorization)
+    }
+
+    /// Answer the queries of a request, in order
+    pub async fn read(&self, request: &ReadRequest) -> Result<ReadResponse> {
+        let mut results = Vec::with_capacity(request.queries.len());
+        for query in &request.queries {
+            results.push(self.run_query(query).await?);
+        }
+        Ok(ReadResponse { results })
+    }
+
+    async fn run_query(&self, query: &Query) -> Result<QueryResult> {
+        let mut timeseries = Vec::new();
+        for metric in &self.metrics {
+            let name_matches = query
+                .matchers
+                .iter()
+                .filter(|matcher| matcher.name == METRIC_NAME_LABEL)
+                .map(|matcher| matches(matcher, &metric.name))
+                .collect::<Result<Vec<_>>>()?;
+            if name_matches.contains(&false) {
+                continue;
+            }
+            timeseries.extend(self.read_metric(metric, query).await?);
+        }
+        Ok(QueryResult { timeseries })
+    }
+
+    /// Series of a metric matching a query, with their samples in its range
+    async fn read_metric(
+        &self,
+        metric: &RemoteReadMetric,
+        query: &Query,
+    ) -> Result<Vec<TimeSeries>> {
+        let agent = self
+            .agents
+            .iter()
+            .find(|agent| {
+                agent
+                    .datasources
+                    .iter()
+                    .any(|ds| ds.name == metric.datasource)
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "Datasource {} of metric {} isn't configured",
+                    metric.datasource,
+                    metric.name
+                )
+            })?;
+
+        let from = DateTime::from_timestamp_millis(query.start_timestamp_ms)
+            .context("Invalid start of query")?;
+        let to = DateTime::from_timestamp_millis(query.end_timestamp_ms)
+            .context("Invalid end of query")?;
+        let step_ms = query
+            .hints
+            .as_ref()
+            .map(|hints| hints.step_ms)
+            .filter(|&step_ms| step_ms > 0);
+        let query_request = AcquireResultBody {
+            id: format!(
+                "remote-read-{}-{:08x}",
+                metric.name,
+                rand::thread_rng().gen::<u32>()
+            ),
+            datasource_name: metric.datasource.clone(),
+            query: metric.query.clone(),
+            queries: None,
+            ts_mapping: Some(TsMapping {
+                time_column: metric.time_column.clone(),
+                time_unit: metric.time_unit,
+                value_columns: Some(vec![metric.value_column.clone()]),
+                label_columns: metric.label_columns.clone(),
+                ..TsMapping::default()
+            }),
+            timeout: None,
+            enqueued_at: None,
+            kind: JobKind::Query,
+            signature: None,
+            time_range: Some(TimeRange {
+                from,
+                to,
+                interval_ms: step_ms.map(|step_ms| step_ms as u64),
+            }),
+        };
+
+        let (records, _, _) = agent.process_query(&query_request).await.map_err(|e| {
+            anyhow!(
+                "Metric {} failed: {}",
+                metric.name,
+                agent.redact(&query_request, &format!("{:#}", e))
+            )
+        })?;
+
+        let mut series: BTreeMap<Vec<(String, String)>, Vec<Sample>> = BTreeMap::new();
+        for record in records {
+            let Some(&value) = record.values.get(&metric.value_column) else {
+                continue;
+            };
+            if record.t < query.start_timestamp_ms || record.t > query.end_timestamp_ms {
+                continue;
+            }
+            let mut labels: Vec<_> = record.labels.into_iter().collect();
+            labels.push((METRIC_NAME_LABEL.to_string(), metric.name.clone()));
+            labels.sort();
+            series.entry(labels).or_default().push(Sample {
+                value,
+                timestamp: record.t,
+            });
+        }
+
+        let mut timeseries = Vec::new();
+        for (labels, mut samples) in series {
+            if !matches_labels(&query.matchers, &labels)? {
+                continue;
+            }
+            samples.sort_by_key(|sample| sample.timestamp);
+            timeseries.push(TimeSeries {
+                labels: labels
+                    .into_iter()
+                    .map(|(name, value)| Label { name, value })
+                    .collect(),
+                samples,
+            });
+        }
+        Ok(timeseries)
+    }
+}
+
+/// Check a label value against a matcher, with regular expressions anchored
+/// at both ends as in PromQL
+pub fn matches(matcher: &LabelMatcher, value: &str) -> Result<bool> {
+    let regex_matches = || -> Result<bool> {
+        let regex = Regex::new(&format!("^(?:{})$", matcher.value))
+            .with_context(|| format!("Invalid regular expression of label {}", matcher.name))?;
+        Ok(regex.is_match(value))
+    };
+    match MatchType::try_from(matcher.r#type) {
+        Ok(MatchType::Eq) => Ok(value == matcher.value),
+        Ok(MatchType::Neq) => Ok(value != matcher.value),
+        Ok(MatchType::Re) => regex_matches(),
+        Ok(MatchType::Nre) => regex_matches().map(|matched| !matched),
+        Err(_) => bail!("Unknown type {} of label matcher", matcher.r#type),
+    }
+}
+
+/// Check the labels of a series against all matchers, a missing label being
+/// empty
+fn matches_labels(matchers: &[LabelMatcher], labels: &[(String, String)]) -> Result<bool> {
+    for matcher in matchers {
+        let value = labels
+            .iter()
+            .find(|(name, _)| *name == matcher.name)
+            .map_or("", |(_, value)| value.as_str());
+        if !matches(matcher, value)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Remote read endpoint bound to its address
+pub struct RemoteReadEndpoint {
+    listener: TcpListener,
+    api: Arc<RemoteReadApi>,
+}
+
+impl RemoteReadEndpoint {
+    /// Bind the endpoint to the configured address
+    pub async fn bind(config: &RemoteReadConfig, agents: Vec<BaseAgent>) -> Result<Self> {
+        let listener = TcpListener::bind(&config.address).await.with_context(|| {
+            format!("Failed to bind remote read endpoint to {}", config.address)
+        })?;
+        Ok(Self {
+            listener,
+            api: Arc::new(RemoteReadApi::new(config, agents)),
+        })
+    }
+
+    /// Address the endpoint is bound to
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve connections until the task is dropped
+    pub async fn serve(self) {
+        if let Ok(address) = self.listener.local_addr() {
+            info!(
+                "Serving metrics {} to Prometheus on http://{}/api/v1/read",
+                self.api.metrics().join(", "),
+                address
+            );
+        }
+        loop {
+            let (stream, _) = match self.listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("Failed to accept remote read connection: {}", e);
+                    continue;
+                }
+            };
+            let api = self.api.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|request| handle(request, api.clone()));
+                let connection =
+                    http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+                if let Err(e) = connection.await {
+                    warn!("Remote read connection failed: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Serve connections in the background
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(self.serve())
+    }
+}
+
+/// Route a request to its endpoint
+async fn handle(
+    request: Request<Incoming>,
+    api: Arc<RemoteReadApi>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !api.is_authorized(authorization) {
+        return Ok(text_response(StatusCode::UNAUTHORIZED, "Unauthorized"));
+    }
+    if (request.method(), request.uri().path()) != (&Method::POST, "/api/v1/read") {
+        return Ok(text_response(StatusCode::NOT_FOUND, "Not found"));
+    }
+
+    let body = match Limited::new(request.into_body(), MAX_BODY_BYTES)
+        .collect()
+        .await
+    {
+        Ok(body) => body.to_bytes(),
+        Err(e) => return Ok(text_response(StatusCode::BAD_REQUEST, &e.to_string())),
+    };
+    let read_request = match snappy::decompress(&body)
+        .and_then(|body| ReadRequest::decode(body.as_slice()).map_err(Into::into))
+    {
+        Ok(read_request) => read_request,
+        Err(e) => {
+            return Ok(text_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid read request: {:#}", e),
+            ))
+        }
+    };
+
+    let response = match api.read(&read_request).await {
+        Ok(read_response) => {
+            let mut response = Response::new(Full::new(Bytes::from(snappy::compress(
+                &read_response.encode_to_vec(),
+            ))));
+            let headers = response.headers_mut();
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/x-protobuf"),
+            );
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static("snappy"));
+            response
+        }
+        Err(e) => {
+            warn!("Remote read failed: {}", e);
+            text_response(StatusCode::BAD_REQUEST, &e.to_string())
+        }
+    };
+    Ok(response)
+}
+
+fn text_response(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(format!("{}\n", message))));
+    *response.status_mut() = status;
+    response
+}
@@ -0,0 +1,127 @@
+//! Snappy block format, which remote read bodies are compressed with
+//!
+//! Responses are encoded as literals only. That's valid Snappy any decoder
+//! reads, just not smaller than the message, which is fine for a local
+//! listener.
+
+use anyhow::{bail, Context, Result};
+
+/// Largest decompressed body accepted
+pub const MAX_DECOMPRESSED_BYTES: usize = 32 * 1024 * 1024;
+
+/// Decompress a Snappy block
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>> {
+    let (length, mut position) = read_varint(input).context("Missing Snappy length")?;
+    let length = usize::try_from(length).context("Snappy length too large")?;
+    if length > MAX_DECOMPRESSED_BYTES {
+        bail!(
+            "Snappy body of {} bytes exceeds {} bytes",
+            length,
+            MAX_DECOMPRESSED_BYTES
+        );
+    }
+
+    let mut output = Vec::with_capacity(length);
+    while position < input.len() {
+        let tag = input[position];
+        position += 1;
+        match tag & 0b11 {
+            0 => {
+                let (literal_length, bytes) = match tag >> 2 {
+                    length @ 0..=59 => (length as usize + 1, 0),
+                    extra => {
+                        let bytes = (extra - 59) as usize;
+                        let length = read_le(input, position, bytes)?;
+                        (length + 1, bytes)
+                    }
+                };
+                position += bytes;
+                let literal = input
+                    .get(position..position + literal_length)
+                    .context("Truncated Snappy literal")?;
+                output.extend_from_slice(literal);
+                position += literal_length;
+            }
+            kind => {
+                let (copy_length, offset, bytes) = match kind {
+                    1 => {
+                        let low = *input.get(position).context("Truncated Snappy copy")?;
+                        (
+                            ((tag >> 2) & 0b111) as usize + 4,
+                            ((tag as usize >> 5) << 8) | low as usize,
+                            1,
+                        )
+                    }
+                    2 => ((tag >> 2) as usize + 1, read_le(input, position, 2)?, 2),
+                    _ => ((tag >> 2) as usize + 1, read_le(input, position, 4)?, 4),
+                };
+                position += bytes;
+                if offset == 0 || offset > output.len() {
+                    bail!("Invalid Snappy copy offset {}", offset);
+                }
+                // Copies may overlap what they append, so go byte by byte
+                let start = output.len() - offset;
+                for i in 0..copy_length {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+        if output.len() > length {
+            bail!("Snappy body longer than its declared {} bytes", length);
+        }
+    }
+
+    if output.len() != length {
+        bail!(
+            "Snappy body of {} bytes instead of its declared {}",
+            output.len(),
+            length
+        );
+    }
+    Ok(output)
+}
+
+/// Encode bytes as a Snappy block
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / 65536 * 3 + 10);
+    let mut length = input.len() as u64;
+    while length >= 0x80 {
+        output.push(length as u8 | 0x80);
+        length >>= 7;
+    }
+    output.push(length as u8);
+
+    for chunk in input.chunks(65536) {
+        match chunk.len() - 1 {
+            length @ 0..=59 => output.push((length as u8) << 2),
+            length @ 60..=255 => output.extend_from_slice(&[60 << 2, length as u8]),
+            length => {
+                output.push(61 << 2);
+                output.extend_from_slice(&(length as u16).to_le_bytes());
+            }
+        }
+        output.extend_from_slice(chunk);
+    }
+    output
+}
+
+fn read_varint(input: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in input.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn read_le(input: &[u8], position: usize, bytes: usize) -> Result<usize> {
+    let slice = input
+        .get(position..position + bytes)
+        .context("Truncated Snappy element")?;
+    Ok(slice
+        .iter()
+        .rev()
+        .fold(0usize, |value, &byte| (value << 8) | byte as usize))
+}
@@ -0,0 +1,336 @@
+//! Export of job results to an object store
+//!
+//! With `result_export` configured, the rows of jobs too large for the
+//! server API are uploaded to an S3 compatible bucket as gzip compressed
+//! JSON lines, one object per job, and only the object's location, size,
+//! SHA-256 and row count are submitted. GCS is reached through its XML API
+//! with HMAC keys. Rows are compressed into a temporary file first, then
+//! uploaded at once or, past `PART_SIZE`, in parts, so neither the rows nor
+//! the object need to fit in memory.
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{info, warn};
+use reqwest::{Client, Method, Url};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, Write};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::aws::{sha256_hex, sign_request, uri_encode, AwsCredentials, SigningScope};
+use crate::client::manifest::ExportManifest;
+use crate::config::ResultExportConfig;
+use crate::http::client_builder;
+use crate::spill::JobRows;
+
+static RESULT_EXPORTER: OnceLock<ResultExporter> = OnceLock::new();
+
+/// Objects larger than this are uploaded in parts of this size, above the
+/// 5 MiB minimum of S3
+pub const PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// Time each upload request gets
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Export the job results of all agents to the configured bucket
+pub fn init(config: &ResultExportConfig) -> Result<()> {
+    let exporter = ResultExporter::from_config(config)?;
+    RESULT_EXPORTER
+        .set(exporter)
+        .map_err(|_| anyhow!("Result export is already initialized"))
+}
+
+/// The result exporter, unless none is configured
+pub fn result_exporter() -> Option<&'static ResultExporter> {
+    RESULT_EXPORTER.get()
+}
+
+/// Uploads job rows to a bucket
+pub struct ResultExporter {
+    client: Client,
+    endpoint: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    credentials: Option<AwsCredentials>,
+    min_rows: Option<usize>,
+    part_size: usize,
+}
+
+impl ResultExporter {
+    /// Create an exporter from configuration
+    pub fn from_config(config: &ResultExportConfig) -> Result<Self> {
+        let region = config
+            .region
+            .clone()
+            .or_else(|| std::env::var("AWS_REGION").ok())
+            .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+            .ok_or_else(|| anyhow!("Region of the result export bucket is not configured"))?;
+        let endpoint = config
+            .endpoint_url
+            .clone()
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        Url::parse(&endpoint).context("Invalid result export endpoint")?;
+
+        let credentials = match (&config.access_key_id, &config.secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => Some(AwsCredentials {
+                access_key_id: access_key_id.clone(),
+                secret_access_key: secret_access_key.clone(),
+                session_token: config.session_token.clone(),
+            }),
+            _ => None,
+        };
+
+        Ok(Self {
+            client: client_builder()
+                .timeout(UPLOAD_TIMEOUT)
+                .build()
+                .context("Failed to build result export client")?,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            bucket: config.bucket.clone(),
+            prefix: config.prefix.clone(),
+            region,
+            credentials,
+            min_rows: config.min_rows,
+            part_size: PART_SIZE,
+        })
+    }
+
+    /// Upload objects larger than `part_size` in parts of that size
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size;
+        self
+    }
+
+    /// Whether results of `rows` rows are exported rather than submitted
+    pub fn exports(&self, rows: usize) -> bool {
+        self.min_rows.is_none_or(|min_rows| rows >= min_rows)
+    }
+
+    /// Upload the rows of a job, returning the manifest of the object
+    pub async fn export(&self, job_id: &str, rows: JobRows) -> Result<ExportManifest> {
+        let row_count = rows.len() as u64;
+        let (file, byte_size, sha256) = tokio::task::spawn_blocking(move || compress(rows))
+            .await?
+            .context("Failed to compress results")?;
+
+        let key = format!("{}{}.jsonl.gz", self.prefix, job_id);
+        let url = Url::parse(&format!(
+            "{}/{}/{}",
+            self.endpoint,
+            uri_encode(&self.bucket, true),
+            uri_encode(&key, false)
+        ))
+        .context("Invalid object URL")?;
+        let credentials = AwsCredentials::resolve(self.credentials.clone(), &self.client).await?;
+        let upload = Upload {
+            exporter: self,
+            url: &url,
+            credentials: &credentials,
+        };
+        if byte_size as usize <= self.part_size {
+            let mut body = Vec::with_capacity(byte_size as usize);
+            file.take(byte_size).read_to_end(&mut body)?;
+            upload.put(body).await?;
+        } else {
+            upload.put_in_parts(file).await?;
+        }
+
+        info!(
+            "Exported {} rows of job {} to {} ({} bytes)",
+            row_count, job_id, url, byte_size
+        );
+        Ok(ExportManifest {
+            location: url.to_string(),
+            row_count,
+            byte_size,
+            sha256,
+        })
+    }
+}
+
+/// Write rows to a temporary file as gzip compressed JSON lines, returning
+/// it rewound with its size and SHA-256
+fn compress(rows: JobRows) -> io::Result<(File, u64, String)> {
+    let mut encoder = GzEncoder::new(
+        Digesting {
+            inner: BufWriter::new(tempfile::tempfile()?),
+            hasher: Sha256::new(),
+            size: 0,
+        },
+        Compression::default(),
+    );
+    for chunk in rows.into_chunks()? {
+        for row in chunk? {
+            serde_json::to_writer(&mut encoder, &row)?;
+            encoder.write_all(b"\n")?;
+        }
+    }
+    let digesting = encoder.finish()?;
+    let mut file = digesting.inner.into_inner().map_err(|e| e.into_error())?;
+    file.rewind()?;
+    Ok((
+        file,
+        digesting.size,
+        hex::encode(digesting.hasher.finalize()),
+    ))
+}
+
+/// Writer hashing and counting the bytes written through it
+struct Digesting<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> Write for Digesting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Signed requests uploading one object
+struct Upload<'a> {
+    exporter: &'a ResultExporter,
+    url: &'a Url,
+    credentials: &'a AwsCredentials,
+}
+
+impl Upload<'_> {
+    async fn put(&self, body: Vec<u8>) -> Result<()> {
+        self.send(Method::PUT, self.url.clone(), body, "upload results")
+            .await?;
+        Ok(())
+    }
+
+    /// Upload a file with a multipart upload, aborted when a part fails
+    async fn put_in_parts(&self, file: File) -> Result<()> {
+        let mut url = self.url.clone();
+        url.set_query(Some("uploads"));
+        let response = self
+            .send(Method::POST, url, Vec::new(), "start multipart upload")
+            .await?;
+        let upload_id = xml_element(&response.text().await?, "UploadId")
+            .ok_or_else(|| anyhow!("No UploadId in the answer to starting a multipart upload"))?;
+
+        match self.put_parts(file, &upload_id).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let mut url = self.url.clone();
+                url.query_pairs_mut().append_pair("uploadId", &upload_id);
+                if let Err(abort_err) = self
+                    .send(Method::DELETE, url, Vec::new(), "abort multipart upload")
+                    .await
+                {
+                    warn!("{:#}", abort_err);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn put_parts(&self, file: File, upload_id: &str) -> Result<()> {
+        let mut file = file;
+        let mut completion = String::from("<CompleteMultipartUpload>");
+        for number in 1.. {
+            let mut part = Vec::with_capacity(self.exporter.part_size);
+            (&mut file)
+                .take(self.exporter.part_size as u64)
+                .read_to_end(&mut part)?;
+            if part.is_empty() {
+                break;
+            }
+            let mut url = self.url.clone();
+            url.query_pairs_mut()
+                .append_pair("partNumber", &number.to_string())
+                .append_pair("uploadId", upload_id);
+            let response = self
+                .send(Method::PUT, url, part, &format!("upload part {}", number))
+                .await?;
+            let etag = response
+                .headers()
+                .get("etag")
+                .and_then(|etag| etag.to_str().ok())
+                .ok_or_else(|| anyhow!("No ETag in the answer to uploading part {}", number))?;
+            completion.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                number, etag
+            ));
+        }
+        completion.push_str("</CompleteMultipartUpload>");
+
+        let mut url = self.url.clone();
+        url.query_pairs_mut().append_pair("uploadId", upload_id);
+        let response = self
+            .send(
+                Method::POST,
+                url,
+                completion.into_bytes(),
+                "complete multipart upload",
+            )
+            .await?;
+        // Completing may fail after the status was sent, with an error body
+        let body = response.text().await?;
+        if let Some(message) = xml_element(&body, "Message").filter(|_| body.contains("<Error>")) {
+            bail!("Failed to complete multipart upload: {}", message);
+        }
+        Ok(())
+    }
+
+    /// Send a signed request, failing unless it succeeds
+    async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        body: Vec<u8>,
+        action: &str,
+    ) -> Result<reqwest::Response> {
+        let headers = BTreeMap::from([("x-amz-content-sha256".to_string(), sha256_hex(&body))]);
+        let signed = sign_request(
+            method.as_str(),
+            &url,
+            &headers,
+            &body,
+            self.credentials,
+            &SigningScope {
+                region: &self.exporter.region,
+                service: "s3",
+            },
+            chrono::Utc::now(),
+        );
+        let mut request = self.exporter.client.request(method, url).body(body);
+        for (name, value) in &signed {
+            request = request.header(name, value);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to {}", action))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            match xml_element(&body, "Message") {
+                Some(message) => bail!("Failed to {}: {} {}", action, status, message),
+                None => bail!("Failed to {}: {}", action, status),
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Text of the first element named `name` of an XML document
+fn xml_element(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    Some(xml[start..end].to_string())
+}
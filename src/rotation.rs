@@ -0,0 +1,139 @@
+//! Append-only files rotated by size and time
+//!
+//! Once a file would grow past its maximum size, or its rotation period
+//! ends, it's renamed to `<path>.1`, older files shift to `<path>.2` and so
+//! on, and the oldest beyond the number of files kept is deleted.
+
+use chrono::{DateTime, Utc};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::RotationPeriod;
+
+/// Bytes in a megabyte of configured maximum sizes
+pub const MEGABYTE: u64 = 1024 * 1024;
+
+/// File appended to record by record, rotated by size and optionally time
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+    period: RotationPeriod,
+    /// Period the current file was started in
+    started: Option<i64>,
+}
+
+impl RotatingFile {
+    /// Open `path` for appending, creating it and its directory if missing.
+    /// The file is rotated once it would exceed `max_size` bytes, keeping
+    /// `max_files` rotated files
+    pub fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let file = Self::append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_size,
+            max_files,
+            file,
+            size,
+            period: RotationPeriod::Never,
+            started: None,
+        })
+    }
+
+    /// Also rotate the file when `period` ends. An existing file belongs to
+    /// the period it was last modified in
+    pub fn with_period(mut self, period: RotationPeriod) -> io::Result<Self> {
+        let modified: DateTime<Utc> = self.file.metadata()?.modified()?.into();
+        self.period = period;
+        self.started = Self::period_of(period, modified);
+        Ok(self)
+    }
+
+    /// Number of the UTC hour or day `time` falls in
+    fn period_of(period: RotationPeriod, time: DateTime<Utc>) -> Option<i64> {
+        match period {
+            RotationPeriod::Never => None,
+            RotationPeriod::Hourly => Some(time.timestamp().div_euclid(3600)),
+            RotationPeriod::Daily => Some(time.timestamp().div_euclid(86400)),
+        }
+    }
+
+    /// Path of the `index`th most recently rotated file of `path`
+    pub fn rotated_path(path: &Path, index: usize) -> PathBuf {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(format!(".{}", index));
+        PathBuf::from(rotated)
+    }
+
+    /// Append `line` and a newline in a single write
+    pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let mut buffer = Vec::with_capacity(line.len() + 1);
+        buffer.extend_from_slice(line);
+        buffer.push(b'\n');
+        self.write_record(&buffer)
+    }
+
+    /// Append `record` in a single write, rotating the file first if it
+    /// would grow past its maximum size or its period ended. A record longer
+    /// than the maximum size gets a file of its own
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        let len = record.len() as u64;
+        let period = Self::period_of(self.period, Utc::now());
+        if self.size > 0 && (self.size + len > self.max_size || period != self.started) {
+            self.rotate()?;
+        }
+        self.started = period;
+        self.file.write_all(record)?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Shift the rotated files, move the current one to `<path>.1` and start
+    /// a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let oldest = Self::rotated_path(&self.path, self.max_files);
+            if oldest.exists() {
+                fs::remove_file(&oldest)?;
+            }
+            for index in (1..self.max_files).rev() {
+                let rotated = Self::rotated_path(&self.path, index);
+                if rotated.exists() {
+                    fs::rename(&rotated, Self::rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, Self::rotated_path(&self.path, 1))?;
+        }
+        self.file = Self::append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+}
+
+/// Writes each buffer as one record, as log layers write one line at a time
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_record(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,208 @@
+//! Differences between discovered schemas, submitted instead of full schemas
+
+use crate::executors::clickhouse_source::{ColumnInfo, RowGrowth, TableSchema};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Database and table identifying a schema
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TableRef {
+    pub database: String,
+    pub table: String,
+}
+
+/// Changes of a table present in both schemas
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableChange {
+    pub database: String,
+    pub table: String,
+    /// New row count, `None` when unchanged
+    pub row_count: Option<u64>,
+    /// New growth since the previous discovery, `None` when unchanged or
+    /// not tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub growth: Option<RowGrowth>,
+    pub added_columns: HashMap<String, ColumnInfo>,
+    pub removed_columns: Vec<String>,
+    /// Columns whose type or cardinality changed, with their new information
+    pub changed_columns: HashMap<String, ColumnInfo>,
+    /// Date and time columns whose latest value changed, with the new value
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub freshness: BTreeMap<String, DateTime<Utc>>,
+}
+
+/// Tables and columns added, removed or changed since the last submission
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SchemaDiff {
+    pub added: Vec<TableSchema>,
+    pub removed: Vec<TableRef>,
+    pub changed: Vec<TableChange>,
+}
+
+impl SchemaDiff {
+    /// Compute the changes turning `previous` schemas into `current` ones
+    pub fn between(previous: &[TableSchema], current: &[TableSchema]) -> Self {
+        let index = |schemas: &[TableSchema]| -> BTreeMap<TableRef, TableSchema> {
+            schemas
+                .iter()
+                .map(|schema| (table_ref(schema), schema.clone()))
+                .collect()
+        };
+        let previous = index(previous);
+        let current = index(current);
+
+        let mut diff = SchemaDiff {
+            removed: previous
+                .keys()
+                .filter(|table| !current.contains_key(table))
+                .cloned()
+                .collect(),
+            ..Default::default()
+        };
+
+        for (table, schema) in current {
+            let Some(old) = previous.get(&table) else {
+                diff.added.push(schema);
+                continue;
+            };
+            let change = TableChange {
+                row_count: (old.row_count != schema.row_count).then_some(schema.row_count),
+                growth: schema.growth.filter(|_| old.growth != schema.growth),
+                added_columns: schema
+                    .columns
+                    .iter()
+                    .filter(|(name, _)| !old.columns.contains_key(*name))
+                    .map(|(name, info)| (name.clone(), info.clone()))
+                    .collect(),
+                removed_columns: old
+                    .columns
+                    .keys()
+                    .filter(|name| !schema.columns.contains_key(*name))
+                    .cloned()
+                    .collect(),
+                changed_columns: schema
+                    .columns
+                    .iter()
+                    .filter(|(name, info)| old.columns.get(*name).is_some_and(|old| old != *info))
+                    .map(|(name, info)| (name.clone(), info.clone()))
+                    .collect(),
+                freshness: schema
+                    .freshness
+                    .iter()
+                    .filter(|(name, latest)| old.freshness.get(*name) != Some(*latest))
+                    .map(|(name, latest)| (name.clone(), *latest))
+                    .collect(),
+                database: table.database,
+                table: table.table,
+            };
+            if !change.is_empty() {
+                diff.changed.push(change);
+            }
+        }
+
+        diff
+    }
+
+    /// Apply the changes to the schemas they were computed from
+    pub fn apply(&self, schemas: Vec<TableSchema>) -> Vec<TableSchema> {
+        let changes: HashMap<TableRef, &TableChange> = self
+            .changed
+            .iter()
+            .map(|change| {
+                let table = TableRef {
+                    database: change.database.clone(),
+                    table: change.table.clone(),
+                };
+                (table, change)
+            })
+            .collect();
+
+        let mut schemas: Vec<TableSchema> = schemas
+            .into_iter()
+            .filter(|schema| !self.removed.contains(&table_ref(schema)))
+            .map(|mut schema| {
+                if let Some(change) = changes.get(&table_ref(&schema)) {
+                    if let Some(row_count) = change.row_count {
+                        schema.row_count = row_count;
+                    }
+                    if let Some(growth) = change.growth {
+                        schema.growth = Some(growth);
+                    }
+                    for column in &change.removed_columns {
+                        schema.columns.remove(column);
+                        schema.freshness.remove(column);
+                    }
+                    schema.columns.extend(
+                        change
+                            .added_columns
+                            .iter()
+                            .chain(&change.changed_columns)
+                            .map(|(name, info)| (name.clone(), info.clone())),
+                    );
+                    schema.freshness.extend(change.freshness.clone());
+                }
+                schema
+            })
+            .collect();
+        schemas.extend(self.added.iter().cloned());
+        schemas
+    }
+
+    /// Check if the schemas are the same
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl TableChange {
+    fn is_empty(&self) -> bool {
+        self.row_count.is_none()
+            && self.growth.is_none()
+            && self.added_columns.is_empty()
+            && self.removed_columns.is_empty()
+            && self.changed_columns.is_empty()
+            && self.freshness.is_empty()
+    }
+}
+
+impl std::fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} tables added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )
+    }
+}
+
+fn table_ref(schema: &TableSchema) -> TableRef {
+    TableRef {
+        database: schema.database.clone(),
+        table: schema.table.clone(),
+    }
+}
+
+/// Load the schemas last submitted for a datasource, `None` when there are
+/// none or the cache can't be read, in which case a full sync is due
+pub fn load_cached_schemas(path: &Path) -> Option<Vec<TableSchema>> {
+    let content = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(schemas) => Some(schemas),
+        Err(e) => {
+            log::warn!("Ignoring invalid schema cache {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Save the schemas submitted for a datasource
+pub fn save_cached_schemas(path: &Path, schemas: &[TableSchema]) -> Result<()> {
+    let content = serde_json::to_string(schemas)?;
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to write schema cache {}", path.display()))
+}
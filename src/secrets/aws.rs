@@ -0,0 +1,136 @@
+//! AWS Secrets Manager provider
+//!
+//! References have the form `aws-sm:<secret-id>[#<key>]`, where the secret id
+//! is a name or full ARN. Without `#<key>` the whole secret string is used,
+//! otherwise the secret is parsed as JSON and the key extracted.
+
+use super::{extract_key, SecretProvider};
+use crate::aws::{sign_request, AwsCredentials, SigningScope};
+use crate::config::AwsSecretsConfig;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Response of the `GetSecretValue` action
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GetSecretValueResponse {
+    secret_string: Option<String>,
+}
+
+/// Secrets Manager backed secret provider
+pub struct AwsSecretsProvider {
+    region: String,
+    endpoint: String,
+    credentials: Option<AwsCredentials>,
+    client: Client,
+}
+
+impl AwsSecretsProvider {
+    /// Create a provider from configuration
+    pub fn from_config(config: &AwsSecretsConfig) -> Result<Self> {
+        let region = config
+            .region
+            .clone()
+            .or_else(|| std::env::var("AWS_REGION").ok())
+            .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+            .ok_or_else(|| anyhow!("AWS region is not configured"))?;
+
+        let endpoint = config
+            .endpoint_url
+            .clone()
+            .unwrap_or_else(|| format!("https://secretsmanager.{}.amazonaws.com", region));
+
+        let credentials = match (&config.access_key_id, &config.secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => Some(AwsCredentials {
+                access_key_id: access_key_id.clone(),
+                secret_access_key: secret_access_key.clone(),
+                session_token: config.session_token.clone(),
+            }),
+            _ => None,
+        };
+
+        Ok(Self {
+            region,
+            endpoint,
+            credentials,
+            client: Client::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl SecretProvider for AwsSecretsProvider {
+    fn scheme(&self) -> &'static str {
+        "aws-sm"
+    }
+
+    async fn fetch(&self, reference: &str) -> Result<String> {
+        let (secret_id, key) = match reference.rsplit_once('#') {
+            Some((secret_id, key)) => (secret_id, Some(key)),
+            None => (reference, None),
+        };
+
+        let credentials = AwsCredentials::resolve(self.credentials.clone(), &self.client).await?;
+        let url =
+            reqwest::Url::parse(&self.endpoint).context("Invalid Secrets Manager endpoint")?;
+        let payload = json!({ "SecretId": secret_id }).to_string();
+
+        let headers = BTreeMap::from([
+            (
+                "content-type".to_string(),
+                "application/x-amz-json-1.1".to_string(),
+            ),
+            (
+                "x-amz-target".to_string(),
+                "secretsmanager.GetSecretValue".to_string(),
+            ),
+        ]);
+        let signed = sign_request(
+            "POST",
+            &url,
+            &headers,
+            payload.as_bytes(),
+            &credentials,
+            &SigningScope {
+                region: &self.region,
+                service: "secretsmanager",
+            },
+            chrono::Utc::now(),
+        );
+
+        let mut request = self
+            .client
+            .post(url)
+            .body(payload)
+            .timeout(Duration::from_secs(30));
+        for (name, value) in &signed {
+            request = request.header(name, value);
+        }
+
+        let response = request
+            .send()
+            .await
+            .context("Failed to send GetSecretValue request")?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to read AWS secret: {}", response.status()));
+        }
+
+        let secret: GetSecretValueResponse = response
+            .json()
+            .await
+            .context("Failed to parse GetSecretValue response")?;
+        let secret = secret
+            .secret_string
+            .ok_or_else(|| anyhow!("AWS secret '{}' has no string value", secret_id))?;
+
+        match key {
+            Some(key) => extract_key(&secret, key),
+            None => Ok(secret),
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Encrypted credentials in the config
+//!
+//! `tsight_agent secret encrypt` turns a credential into an `enc:v1:<data>`
+//! value with a key kept on the machine, in a key file or the OS keyring.
+//! Encrypted values are decrypted while the config is loaded, so the YAML
+//! only holds ciphertext that is useless without the key.
+//!
+//! Values are encrypted with AES-256-CBC and authenticated with
+//! HMAC-SHA256, with both keys derived from the stored key by HKDF.
+
+use crate::config::SecretsConfig;
+use aes::Aes256;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use cbc::cipher::block_padding::Pkcs7;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::keyring::DEFAULT_KEYRING_SERVICE;
+
+/// Prefix of encrypted values
+pub const ENCRYPTED_PREFIX: &str = "enc:";
+
+/// Key file used unless configured otherwise, next to the config file
+pub const DEFAULT_KEY_FILE: &str = "secret.key";
+
+/// Prefix of values encrypted by this version of the format
+const VERSION_PREFIX: &str = "enc:v1:";
+const KEY_LEN: usize = 32;
+const IV_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+
+type Encryptor = cbc::Encryptor<Aes256>;
+type Decryptor = cbc::Decryptor<Aes256>;
+
+/// Whether `value` is an encrypted credential
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+/// Key encrypting and decrypting credentials
+pub struct EncryptionKey([u8; KEY_LEN]);
+
+impl EncryptionKey {
+    /// Generate a random key
+    pub fn generate() -> Self {
+        let mut key = [0; KEY_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut key);
+        Self(key)
+    }
+
+    /// Parse a key stored as base64
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let key = STANDARD
+            .decode(encoded.trim())
+            .context("Encryption key is not valid base64")?;
+        let key = key
+            .try_into()
+            .map_err(|_| anyhow!("Encryption key must be {} bytes", KEY_LEN))?;
+        Ok(Self(key))
+    }
+
+    /// Key as stored in a key file or keyring entry
+    pub fn encode(&self) -> String {
+        STANDARD.encode(self.0)
+    }
+
+    /// Encrypt `plaintext` into an `enc:v1:` value
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let (encryption_key, authentication_key) = self.derive_keys();
+        let mut iv = [0; IV_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut iv);
+
+        let ciphertext = Encryptor::new(&encryption_key.into(), &iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
+        let tag = tag(&authentication_key, &iv, &ciphertext).finalize();
+
+        let mut data = iv.to_vec();
+        data.extend(ciphertext);
+        data.extend(tag.into_bytes());
+        format!("{}{}", VERSION_PREFIX, STANDARD.encode(data))
+    }
+
+    /// Decrypt an `enc:v1:` value, failing if it was encrypted with another
+    /// key or modified
+    pub fn decrypt(&self, value: &str) -> Result<String> {
+        let encoded = value.strip_prefix(VERSION_PREFIX).ok_or_else(|| {
+            anyhow!("Unsupported encrypted value, expected it to start with {VERSION_PREFIX}")
+        })?;
+        let data = STANDARD
+            .decode(encoded)
+            .context("Encrypted value is not valid base64")?;
+        if data.len() < IV_LEN + TAG_LEN {
+            bail!("Encrypted value is truncated");
+        }
+        let (iv, rest) = data.split_at(IV_LEN);
+        let (ciphertext, expected_tag) = rest.split_at(rest.len() - TAG_LEN);
+
+        let (encryption_key, authentication_key) = self.derive_keys();
+        tag(&authentication_key, iv, ciphertext)
+            .verify_slice(expected_tag)
+            .map_err(|_| anyhow!("Encrypted value doesn't match the key or was modified"))?;
+        let plaintext = Decryptor::new(&encryption_key.into(), iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+            .map_err(|_| anyhow!("Encrypted value has invalid padding"))?;
+        String::from_utf8(plaintext).context("Decrypted value is not valid UTF-8")
+    }
+
+    /// Separate keys for encryption and authentication
+    fn derive_keys(&self) -> ([u8; KEY_LEN], [u8; KEY_LEN]) {
+        let hkdf = Hkdf::<Sha256>::new(None, &self.0);
+        let mut encryption_key = [0; KEY_LEN];
+        let mut authentication_key = [0; KEY_LEN];
+        // Expanding into 32 bytes can't exceed the HKDF output limit
+        hkdf.expand(b"tsight-agent encryption v1", &mut encryption_key)
+            .expect("valid HKDF output length");
+        hkdf.expand(b"tsight-agent authentication v1", &mut authentication_key)
+            .expect("valid HKDF output length");
+        (encryption_key, authentication_key)
+    }
+}
+
+// Keeps the key out of logs and error messages
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// HMAC over the format version, IV and ciphertext
+fn tag(authentication_key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(authentication_key)
+        .expect("HMAC accepts keys of any length");
+    mac.update(VERSION_PREFIX.as_bytes());
+    mac.update(iv);
+    mac.update(ciphertext);
+    mac
+}
+
+/// Where the encryption key is kept
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeySource {
+    /// File holding the base64 encoded key
+    File(PathBuf),
+    /// OS keyring entry holding the base64 encoded key
+    Keyring { service: String, entry: String },
+}
+
+impl KeySource {
+    /// Key source of `secrets.encryption`, with relative key files and the
+    /// default one resolved against `config_dir`
+    pub fn from_config(config: Option<&SecretsConfig>, config_dir: &Path) -> Self {
+        let encryption = config.and_then(|config| config.encryption.as_ref());
+        if let Some(entry) = encryption.and_then(|encryption| encryption.keyring_entry.clone()) {
+            let service = config
+                .and_then(|config| config.keyring.as_ref())
+                .and_then(|keyring| keyring.service.clone())
+                .unwrap_or_else(|| DEFAULT_KEYRING_SERVICE.to_string());
+            return Self::Keyring { service, entry };
+        }
+        let key_file = encryption
+            .and_then(|encryption| encryption.key_file.clone())
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_KEY_FILE));
+        Self::File(config_dir.join(key_file))
+    }
+
+    /// Read the key
+    pub fn load(&self) -> Result<EncryptionKey> {
+        let encoded = match self {
+            Self::File(path) => fs::read_to_string(path).with_context(|| {
+                format!(
+                    "Failed to read encryption key {}, create it with `secret encrypt`",
+                    path.display()
+                )
+            })?,
+            Self::Keyring { service, entry } => read_keyring(service, entry)?,
+        };
+        EncryptionKey::decode(&encoded).with_context(|| format!("Invalid key in {}", self))
+    }
+
+    /// Read the key, generating and storing one if there is none yet.
+    /// Returns whether the key was created
+    pub fn load_or_create(&self) -> Result<(EncryptionKey, bool)> {
+        let exists = match self {
+            Self::File(path) => path.exists(),
+            Self::Keyring { service, entry } => match read_keyring(service, entry) {
+                Ok(_) => true,
+                Err(e) if e.is::<MissingKeyringEntry>() => false,
+                Err(e) => return Err(e),
+            },
+        };
+        if exists {
+            return Ok((self.load()?, false));
+        }
+
+        let key = EncryptionKey::generate();
+        match self {
+            Self::File(path) => write_key_file(path, &key)?,
+            Self::Keyring { service, entry } => write_keyring(service, entry, &key.encode())?,
+        }
+        Ok((key, true))
+    }
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "key file {}", path.display()),
+            Self::Keyring { service, entry } => {
+                write!(f, "keyring entry '{}' of service '{}'", entry, service)
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("No keyring entry '{entry}' for service '{service}'")]
+struct MissingKeyringEntry {
+    service: String,
+    entry: String,
+}
+
+/// Create a key file only its owner can read
+fn write_key_file(path: &Path, key: &EncryptionKey) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create encryption key {}", path.display()))?;
+    std::io::Write::write_all(&mut file, format!("{}\n", key.encode()).as_bytes())
+        .with_context(|| format!("Failed to write encryption key {}", path.display()))
+}
+
+// Keyring backends may drive a runtime of their own, which can't start on a
+// thread of the caller's runtime, so they get a thread of their own
+
+fn read_keyring(service: &str, entry: &str) -> Result<String> {
+    on_own_thread(|| {
+        ::keyring::Entry::new(service, entry)
+            .and_then(|keyring_entry| keyring_entry.get_password())
+            .map_err(|e| match e {
+                ::keyring::Error::NoEntry => anyhow!(MissingKeyringEntry {
+                    service: service.to_string(),
+                    entry: entry.to_string(),
+                }),
+                e => anyhow!(e).context(format!("Failed to read keyring entry '{}'", entry)),
+            })
+    })
+}
+
+fn write_keyring(service: &str, entry: &str, secret: &str) -> Result<()> {
+    on_own_thread(|| {
+        ::keyring::Entry::new(service, entry)
+            .and_then(|keyring_entry| keyring_entry.set_password(secret))
+            .with_context(|| format!("Failed to store keyring entry '{}'", entry))
+    })
+}
+
+fn on_own_thread<T: Send>(f: impl FnOnce() -> Result<T> + Send) -> Result<T> {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(f)
+            .join()
+            .map_err(|_| anyhow!("Keyring access panicked"))?
+    })
+}
@@ -0,0 +1,61 @@
+//! Credentials read from files, such as Kubernetes projected secret volumes
+//!
+//! Datasources with `username_file` or `password_file` read the credential
+//! from the file each time an executor is created, instead of once at
+//! startup. The kubelet replaces the files of a projected volume when its
+//! secret is rotated, so the next query uses the new credential without a
+//! restart. A trailing newline is ignored. When a file can't be read, the
+//! value it held last is used until it can be again.
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+static CREDENTIAL_FILES: LazyLock<CredentialFiles> = LazyLock::new(CredentialFiles::default);
+
+/// Get the process-wide credential file reader
+pub fn credential_files() -> &'static CredentialFiles {
+    &CREDENTIAL_FILES
+}
+
+/// Reads credential files, keeping the value each file held last
+#[derive(Default)]
+pub struct CredentialFiles {
+    last: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl CredentialFiles {
+    /// Current credential in `path`, or the one it held last when it can't
+    /// be read
+    pub fn read(&self, path: &Path) -> Result<String> {
+        let read = std::fs::read_to_string(path)
+            .map(|value| value.trim_end_matches(['\r', '\n']).to_string());
+        let mut last = self.last.lock().unwrap();
+        match read {
+            Ok(value) => {
+                match last.get(path) {
+                    Some(previous) if *previous != value => {
+                        info!("Reloaded rotated credential from {}", path.display())
+                    }
+                    _ => (),
+                }
+                last.insert(path.to_path_buf(), value.clone());
+                Ok(value)
+            }
+            Err(e) => match last.get(path) {
+                Some(previous) => {
+                    warn!(
+                        "Failed to read credential from {}, using its last value: {}",
+                        path.display(),
+                        e
+                    );
+                    Ok(previous.clone())
+                }
+                None => Err(e)
+                    .with_context(|| format!("Failed to read credential from {}", path.display())),
+            },
+        }
+    }
+}
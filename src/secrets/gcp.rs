@@ -0,0 +1,125 @@
+//! GCP Secret Manager provider
+//!
+//! References have the form
+//! `gcp-sm:projects/<project>/secrets/<name>/versions/<version>[#<key>]`.
+//! The access token is taken from config, the `GOOGLE_OAUTH_ACCESS_TOKEN`
+//! environment variable or the GCE/GKE metadata server.
+
+use super::{extract_key, SecretProvider};
+use crate::config::GcpSecretsConfig;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Metadata server endpoint issuing tokens for the attached service account
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+#[derive(Debug, Deserialize)]
+struct MetadataToken {
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SecretPayload {
+    data: String,
+}
+
+/// Response of the `versions.access` method
+#[derive(Debug, Deserialize)]
+struct AccessSecretVersionResponse {
+    payload: SecretPayload,
+}
+
+/// Secret Manager backed secret provider
+pub struct GcpSecretsProvider {
+    endpoint: String,
+    access_token: Option<String>,
+    client: Client,
+}
+
+impl GcpSecretsProvider {
+    /// Create a provider from configuration
+    pub fn from_config(config: &GcpSecretsConfig) -> Self {
+        Self {
+            endpoint: config
+                .endpoint_url
+                .clone()
+                .unwrap_or_else(|| "https://secretmanager.googleapis.com".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            access_token: config
+                .access_token
+                .clone()
+                .or_else(|| std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN").ok()),
+            client: Client::new(),
+        }
+    }
+
+    /// Get an access token for the Secret Manager API
+    async fn access_token(&self) -> Result<String> {
+        if let Some(token) = &self.access_token {
+            return Ok(token.clone());
+        }
+
+        let token: MetadataToken = self
+            .client
+            .get(METADATA_TOKEN_URL)
+            .header("Metadata-Flavor", "Google")
+            .timeout(Duration::from_secs(2))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(token.access_token)
+    }
+}
+
+#[async_trait]
+impl SecretProvider for GcpSecretsProvider {
+    fn scheme(&self) -> &'static str {
+        "gcp-sm"
+    }
+
+    async fn fetch(&self, reference: &str) -> Result<String> {
+        let (name, key) = match reference.rsplit_once('#') {
+            Some((name, key)) => (name, Some(key)),
+            None => (reference, None),
+        };
+
+        let token = self
+            .access_token()
+            .await
+            .context("No GCP access token configured and metadata server is unavailable")?;
+
+        let response = self
+            .client
+            .get(format!("{}/v1/{}:access", self.endpoint, name))
+            .bearer_auth(token)
+            .timeout(Duration::from_secs(30))
+            .send()
+            .await
+            .context("Failed to send secret access request")?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to read GCP secret: {}", response.status()));
+        }
+
+        let secret: AccessSecretVersionResponse = response
+            .json()
+            .await
+            .context("Failed to parse secret access response")?;
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(secret.payload.data)
+            .context("Secret payload is not valid base64")?;
+        let secret = String::from_utf8(data).context("Secret payload is not valid UTF-8")?;
+
+        match key {
+            Some(key) => extract_key(&secret, key),
+            None => Ok(secret),
+        }
+    }
+}
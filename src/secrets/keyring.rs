@@ -0,0 +1,78 @@
+//! OS keyring provider
+//!
+//! References have the form `keyring:<entry>`. Entries are stored under a
+//! single service name in the platform keyring (Secret Service on Linux,
+//! Keychain on macOS, Credential Manager on Windows) and can be populated
+//! with `tsight_agent secret set <entry>`.
+
+use super::SecretProvider;
+use crate::config::KeyringConfig;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+
+/// Service name entries are stored under unless configured otherwise
+pub const DEFAULT_KEYRING_SERVICE: &str = "tsight-agent";
+
+/// OS keyring backed secret provider
+pub struct KeyringProvider {
+    service: String,
+}
+
+impl KeyringProvider {
+    /// Create a provider storing entries under `service`
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+        }
+    }
+
+    /// Create a provider from configuration
+    pub fn from_config(config: Option<&KeyringConfig>) -> Self {
+        Self::new(
+            config
+                .and_then(|config| config.service.clone())
+                .unwrap_or_else(|| DEFAULT_KEYRING_SERVICE.to_string()),
+        )
+    }
+
+    /// Store a secret in the keyring
+    pub async fn store(&self, entry: &str, secret: &str) -> Result<()> {
+        let service = self.service.clone();
+        let entry = entry.to_string();
+        let secret = secret.to_string();
+        tokio::task::spawn_blocking(move || {
+            ::keyring::Entry::new(&service, &entry)
+                .and_then(|keyring_entry| keyring_entry.set_password(&secret))
+                .with_context(|| format!("Failed to store keyring entry '{}'", entry))
+        })
+        .await?
+    }
+}
+
+#[async_trait]
+impl SecretProvider for KeyringProvider {
+    fn scheme(&self) -> &'static str {
+        "keyring"
+    }
+
+    async fn fetch(&self, reference: &str) -> Result<String> {
+        if reference.is_empty() {
+            return Err(anyhow!("Keyring entry name is empty"));
+        }
+
+        // Keyring backends block, keep them off the async workers
+        let service = self.service.clone();
+        let entry = reference.to_string();
+        tokio::task::spawn_blocking(move || {
+            ::keyring::Entry::new(&service, &entry)
+                .and_then(|keyring_entry| keyring_entry.get_password())
+                .map_err(|e| match e {
+                    ::keyring::Error::NoEntry => {
+                        anyhow!("No keyring entry '{}' for service '{}'", entry, service)
+                    }
+                    e => anyhow!(e).context(format!("Failed to read keyring entry '{}'", entry)),
+                })
+        })
+        .await?
+    }
+}
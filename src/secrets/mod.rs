@@ -0,0 +1,136 @@
+//! Secret providers for resolving credentials referenced from config
+//!
+//! Any credential in the config can be given as a reference of the form
+//! `<scheme>:<path>#<key>` (e.g. `vault:kv/data/tsight#ch_password`,
+//! `aws-sm:tsight/prod#password`, `gcp-sm:projects/p/secrets/s/versions/latest`,
+//! `keyring:ch_password`). The resolver replaces such references with values
+//! fetched from the matching provider, so plaintext credentials never have to
+//! be stored on disk. Credentials can also be stored encrypted, see
+//! [`encrypted`].
+
+pub mod aws;
+pub mod encrypted;
+pub mod files;
+pub mod gcp;
+pub mod keyring;
+pub mod vault;
+
+use crate::config::{Config, SecretsConfig};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Backend able to fetch secrets for references with a given scheme
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    /// Scheme handled by this provider, e.g. `vault`
+    fn scheme(&self) -> &'static str;
+
+    /// Fetch the secret for a reference with the scheme prefix stripped
+    async fn fetch(&self, reference: &str) -> Result<String>;
+
+    /// Start background renewal of leases/tokens, if the backend needs it
+    fn spawn_renewal(self: Arc<Self>) {}
+}
+
+/// Split a `path#key` reference into its parts
+pub fn split_reference(reference: &str) -> Result<(&str, &str)> {
+    reference
+        .rsplit_once('#')
+        .filter(|(path, key)| !path.is_empty() && !key.is_empty())
+        .ok_or_else(|| {
+            anyhow!(
+                "Invalid secret reference '{}', expected <path>#<key>",
+                reference
+            )
+        })
+}
+
+/// Extract a key from a secret stored as a JSON object
+pub fn extract_key(secret: &str, key: &str) -> Result<String> {
+    let value: serde_json::Value =
+        serde_json::from_str(secret).context("Secret is not a JSON object")?;
+    match value.get(key) {
+        Some(serde_json::Value::String(value)) => Ok(value.clone()),
+        Some(other) => Ok(other.to_string()),
+        None => Err(anyhow!("Key '{}' not found in secret", key)),
+    }
+}
+
+/// Resolves secret references using the configured providers
+#[derive(Default, Clone)]
+pub struct SecretResolver {
+    providers: Vec<Arc<dyn SecretProvider>>,
+}
+
+impl SecretResolver {
+    /// Create a resolver with the providers enabled in configuration
+    pub fn from_config(config: Option<&SecretsConfig>) -> Result<Self> {
+        let mut resolver = Self::default();
+
+        // The OS keyring needs no setup, so it is always available
+        resolver.add_provider(Arc::new(keyring::KeyringProvider::from_config(
+            config.and_then(|config| config.keyring.as_ref()),
+        )));
+
+        if let Some(config) = config {
+            if let Some(vault) = &config.vault {
+                resolver.add_provider(Arc::new(vault::VaultProvider::from_config(vault)?));
+            }
+            if let Some(aws) = &config.aws {
+                resolver.add_provider(Arc::new(aws::AwsSecretsProvider::from_config(aws)?));
+            }
+            if let Some(gcp) = &config.gcp {
+                resolver.add_provider(Arc::new(gcp::GcpSecretsProvider::from_config(gcp)));
+            }
+        }
+
+        Ok(resolver)
+    }
+
+    /// Register an additional provider
+    pub fn add_provider(&mut self, provider: Arc<dyn SecretProvider>) {
+        self.providers.push(provider);
+    }
+
+    /// Known schemes, including those of providers that are not configured
+    fn is_known_scheme(scheme: &str) -> bool {
+        matches!(scheme, "vault" | "aws-sm" | "gcp-sm")
+    }
+
+    /// Resolve a single value, returning it unchanged if it isn't a reference
+    pub async fn resolve(&self, value: &str) -> Result<String> {
+        let Some((scheme, reference)) = value.split_once(':') else {
+            return Ok(value.to_string());
+        };
+
+        match self.providers.iter().find(|p| p.scheme() == scheme) {
+            Some(provider) => provider
+                .fetch(reference)
+                .await
+                .with_context(|| format!("Failed to resolve {} secret '{}'", scheme, reference)),
+            None if Self::is_known_scheme(scheme) => Err(anyhow!(
+                "Secret reference '{}' requires the '{}' provider, which is not configured",
+                value,
+                scheme
+            )),
+            None => Ok(value.to_string()),
+        }
+    }
+
+    /// Resolve all credential fields of the config in place
+    pub async fn resolve_config(&self, config: &mut Config) -> Result<()> {
+        for value in config.credentials_mut() {
+            *value = self.resolve(value).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Start background lease renewal for all providers
+    pub fn spawn_renewal(&self) {
+        for provider in &self.providers {
+            provider.clone().spawn_renewal();
+        }
+    }
+}
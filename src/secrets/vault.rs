@@ -0,0 +1,168 @@
+//! HashiCorp Vault secret provider
+//!
+//! References have the form `vault:<path>#<key>`, where `<path>` is the API
+//! path below `/v1/` (e.g. `kv/data/tsight` for a KV v2 mount). Leases of
+//! dynamic secrets and the Vault token itself are renewed in the background.
+
+use super::{split_reference, SecretProvider};
+use crate::config::VaultConfig;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Response envelope of Vault read operations
+#[derive(Debug, Deserialize)]
+struct SecretResponse {
+    #[serde(default)]
+    lease_id: String,
+    #[serde(default)]
+    renewable: bool,
+    #[serde(default)]
+    lease_duration: u64,
+    data: Value,
+}
+
+/// Vault-backed secret provider
+pub struct VaultProvider {
+    address: String,
+    token: String,
+    namespace: Option<String>,
+    renew_interval: Duration,
+    client: Client,
+    /// Renewable leases obtained while fetching secrets
+    leases: Mutex<Vec<(String, u64)>>,
+}
+
+impl VaultProvider {
+    /// Create a provider from configuration
+    ///
+    /// The token is taken from `token`, `token_file` or the `VAULT_TOKEN`
+    /// environment variable, in that order.
+    pub fn from_config(config: &VaultConfig) -> Result<Self> {
+        let token = match (&config.token, &config.token_file) {
+            (Some(token), _) => token.clone(),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read Vault token file {}", path))?
+                .trim()
+                .to_string(),
+            (None, None) => std::env::var("VAULT_TOKEN")
+                .map_err(|_| anyhow!("No Vault token configured and VAULT_TOKEN is not set"))?,
+        };
+
+        Ok(Self {
+            address: config.address.trim_end_matches('/').to_string(),
+            token,
+            namespace: config.namespace.clone(),
+            renew_interval: Duration::from_secs(config.renew_interval_secs.max(1)),
+            client: Client::new(),
+            leases: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Build an authorized request to a Vault API path
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let mut request = self
+            .client
+            .request(method, format!("{}/v1/{}", self.address, path))
+            .header("X-Vault-Token", &self.token)
+            .timeout(Duration::from_secs(30));
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        request
+    }
+
+    /// Renew the token and all known leases
+    async fn renew(&self) -> Result<()> {
+        let response = self
+            .request(reqwest::Method::POST, "auth/token/renew-self")
+            .send()
+            .await
+            .context("Failed to send Vault token renewal request")?;
+        if !response.status().is_success() {
+            log::debug!("Vault token was not renewed: {}", response.status());
+        }
+
+        let leases = self.leases.lock().unwrap().clone();
+        for (lease_id, increment) in leases {
+            let response = self
+                .request(reqwest::Method::PUT, "sys/leases/renew")
+                .json(&json!({"lease_id": lease_id, "increment": increment}))
+                .send()
+                .await
+                .context("Failed to send Vault lease renewal request")?;
+            if !response.status().is_success() {
+                return Err(anyhow!(
+                    "Failed to renew Vault lease {}: {}",
+                    lease_id,
+                    response.status()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SecretProvider for VaultProvider {
+    fn scheme(&self) -> &'static str {
+        "vault"
+    }
+
+    async fn fetch(&self, reference: &str) -> Result<String> {
+        let (path, key) = split_reference(reference)?;
+
+        let response = self
+            .request(reqwest::Method::GET, path.trim_start_matches('/'))
+            .send()
+            .await
+            .context("Failed to send Vault read request")?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to read Vault secret: {}",
+                response.status()
+            ));
+        }
+
+        let secret: SecretResponse = response
+            .json()
+            .await
+            .context("Failed to parse Vault response")?;
+
+        if secret.renewable && !secret.lease_id.is_empty() {
+            self.leases
+                .lock()
+                .unwrap()
+                .push((secret.lease_id.clone(), secret.lease_duration));
+        }
+
+        // KV v2 nests the secret under data.data, KV v1 and dynamic engines don't
+        let value = secret
+            .data
+            .get("data")
+            .and_then(|data| data.get(key))
+            .or_else(|| secret.data.get(key))
+            .ok_or_else(|| anyhow!("Key '{}' not found in Vault secret '{}'", key, path))?;
+
+        match value {
+            Value::String(value) => Ok(value.clone()),
+            other => Ok(other.to_string()),
+        }
+    }
+
+    fn spawn_renewal(self: Arc<Self>) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(self.renew_interval).await;
+                if let Err(e) = self.renew().await {
+                    log::error!("Vault renewal failed: {:#}", e);
+                }
+            }
+        });
+    }
+}
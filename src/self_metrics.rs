@@ -0,0 +1,213 @@
+//! Writer of the agent's own metrics to a ClickHouse table
+//!
+//! With `self_metrics` configured, the metrics the listener serves to
+//! Prometheus, from task durations and failures to filter stats, are also
+//! inserted periodically into a table on a ClickHouse datasource, so teams
+//! already on ClickHouse can dashboard the agent with their existing tools.
+//! Each sample is a row; histograms are written as their `_count`, `_sum`
+//! and cumulative `_bucket` series like Prometheus exposes them. Inserts are
+//! asynchronous, batched by ClickHouse, and may use a user of their own so
+//! the datasource's user stays read-only.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use prometheus::proto::{MetricFamily, MetricType};
+use serde_json::{json, Map, Value};
+use tokio::task::JoinHandle;
+
+use crate::config::SelfMetricsConfig;
+use crate::executors::base::QueryExecutor;
+use crate::executors::clickhouse_source::ClickhouseExecutor;
+use crate::host_resolver::host_resolver;
+use crate::http::datasource_client;
+use crate::leadership::host_name;
+use crate::load_balancer::load_balancer;
+use crate::metrics::metrics;
+use crate::models::DataSource;
+
+/// Columns of the table, as created when it's missing
+const COLUMNS: &str = "timestamp DateTime64(3, 'UTC'), agent LowCardinality(String), \
+    metric LowCardinality(String), labels Map(LowCardinality(String), String), value Float64";
+
+/// Inserts samples of the process-wide metrics into a ClickHouse table
+pub struct SelfMetricsWriter {
+    executor: ClickhouseExecutor,
+    table: String,
+    agent: String,
+}
+
+impl SelfMetricsWriter {
+    /// Writer to the configured table on a host of `datasource`
+    pub async fn new(config: &SelfMetricsConfig, datasource: &DataSource) -> Result<Self> {
+        let hosts = host_resolver().resolve_hosts(datasource).await?;
+        let host = load_balancer()
+            .select(datasource, &hosts)
+            .ok_or_else(|| anyhow!("No host specified for datasource {}", datasource.name))?;
+        let (username, password) = datasource.credentials()?;
+        let executor = ClickhouseExecutor::new(
+            &host,
+            config.username.as_ref().unwrap_or(&username),
+            config.password.as_ref().unwrap_or(&password),
+        )?
+        .with_database(datasource.database.as_deref())
+        .with_http_client(datasource_client(&datasource.name));
+        Ok(Self {
+            executor,
+            table: config.table.clone(),
+            agent: host_name(),
+        })
+    }
+
+    /// Create the table unless it exists
+    pub async fn create_table(&self) -> Result<()> {
+        let statement = format!(
+            "CREATE TABLE IF NOT EXISTS {} ({}) ENGINE = MergeTree \
+             PARTITION BY toYYYYMM(timestamp) ORDER BY (metric, agent, timestamp)",
+            self.table, COLUMNS
+        );
+        self.executor
+            .execute_statement(&statement)
+            .await
+            .with_context(|| format!("Failed to create self metrics table {}", self.table))
+    }
+
+    /// Insert the current samples of all metrics, returning how many
+    pub async fn write(&self, at: DateTime<Utc>) -> Result<usize> {
+        let rows = rows(&metrics().gather(), &self.agent, at);
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        self.executor
+            .insert_json_rows(&self.table, &rows)
+            .await
+            .with_context(|| format!("Failed to insert self metrics into {}", self.table))?;
+        Ok(rows.len())
+    }
+}
+
+/// Rows of the samples of metric families taken at `at`
+pub fn rows(families: &[MetricFamily], agent: &str, at: DateTime<Utc>) -> Vec<Value> {
+    let timestamp = at.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+    let row = |metric: String, labels: Map<String, Value>, value: f64| {
+        json!({
+            "timestamp": timestamp,
+            "agent": agent,
+            "metric": metric,
+            "labels": labels,
+            "value": value,
+        })
+    };
+
+    let mut rows = Vec::new();
+    for family in families {
+        let name = family.get_name();
+        for metric in family.get_metric() {
+            let labels: Map<String, Value> = metric
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name().to_string(), json!(label.get_value())))
+                .collect();
+            match family.get_field_type() {
+                MetricType::COUNTER => rows.push(row(
+                    name.to_string(),
+                    labels,
+                    metric.get_counter().get_value(),
+                )),
+                MetricType::GAUGE => rows.push(row(
+                    name.to_string(),
+                    labels,
+                    metric.get_gauge().get_value(),
+                )),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    rows.push(row(
+                        format!("{}_count", name),
+                        labels.clone(),
+                        histogram.get_sample_count() as f64,
+                    ));
+                    rows.push(row(
+                        format!("{}_sum", name),
+                        labels.clone(),
+                        histogram.get_sample_sum(),
+                    ));
+                    for bucket in histogram.get_bucket() {
+                        let mut labels = labels.clone();
+                        labels.insert(
+                            "le".to_string(),
+                            json!(bucket.get_upper_bound().to_string()),
+                        );
+                        rows.push(row(
+                            format!("{}_bucket", name),
+                            labels,
+                            bucket.get_cumulative_count() as f64,
+                        ));
+                    }
+                    let mut labels = labels;
+                    labels.insert("le".to_string(), json!("+Inf"));
+                    rows.push(row(
+                        format!("{}_bucket", name),
+                        labels,
+                        histogram.get_sample_count() as f64,
+                    ));
+                }
+                // The agent registers no summaries or untyped metrics
+                _ => {}
+            }
+        }
+    }
+    rows
+}
+
+/// Insert the agent's metrics periodically into the configured table, unless
+/// its datasource isn't configured
+pub fn spawn_self_metrics(
+    config: &SelfMetricsConfig,
+    datasources: &[DataSource],
+) -> Result<JoinHandle<()>> {
+    let datasource = datasources
+        .iter()
+        .find(|ds| ds.name == config.datasource)
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "Datasource {} of self metrics isn't configured",
+                config.datasource
+            )
+        })?;
+    let config = config.clone();
+    info!(
+        "Writing the agent's metrics to {} on {} every {}s",
+        config.table,
+        config.datasource,
+        config.interval.as_secs()
+    );
+    Ok(tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(config.interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut table_checked = false;
+        loop {
+            ticks.tick().await;
+            // Hosts are resolved again on every write, so a failed host is
+            // left for another one
+            let writer = match SelfMetricsWriter::new(&config, &datasource).await {
+                Ok(writer) => writer,
+                Err(e) => {
+                    warn!("Failed to write self metrics: {:#}", e);
+                    continue;
+                }
+            };
+            if !table_checked {
+                // A user allowed to insert only can't create the table,
+                // which then has to exist already
+                if let Err(e) = writer.create_table().await {
+                    warn!("{:#}", e);
+                }
+                table_checked = true;
+            }
+            if let Err(e) = writer.write(Utc::now()).await {
+                warn!("{:#}", e);
+            }
+        }
+    }))
+}
@@ -0,0 +1,387 @@
+//! Windows service mode
+//!
+//! `service install` registers the agent with the Windows service control
+//! manager to start at boot, and `service run` is what the manager launches:
+//! it reports the service's state and turns stop, shutdown, pause and
+//! continue requests into the agent's [`shutdown`](crate::shutdown) controls,
+//! so the agent runs as a service without a wrapper like NSSM.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Service name used unless `--name` is passed
+pub const DEFAULT_SERVICE_NAME: &str = "tsight-agent";
+/// Name shown in the Services console
+pub const DISPLAY_NAME: &str = "TSight Agent";
+/// Description shown in the Services console
+pub const DESCRIPTION: &str = "Runs queries from the TSight server on your datasources";
+
+/// Arguments the service manager launches the agent with, after the path of
+/// the executable
+pub fn launch_arguments(name: &str, config: Option<&Path>) -> Result<Vec<String>> {
+    let mut arguments = Vec::new();
+    if let Some(config) = config {
+        let config = config
+            .to_str()
+            .context("Configuration path must be valid UTF-8")?;
+        arguments.extend(["--config".to_string(), config.to_string()]);
+    }
+    arguments.extend(["service", "run", "--name", name].map(String::from));
+    Ok(arguments)
+}
+
+/// Join arguments into a command line, quoting them the way Windows
+/// programs split their command line
+pub fn command_line(arguments: &[String]) -> String {
+    arguments
+        .iter()
+        .map(|argument| quote_argument(argument))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn quote_argument(argument: &str) -> String {
+    if !argument.is_empty() && !argument.contains([' ', '\t', '"']) {
+        return argument.to_string();
+    }
+    // Backslashes are only special in front of quotes
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in argument.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        let escapes = if c == '"' {
+            backslashes * 2 + 1
+        } else {
+            backslashes
+        };
+        quoted.extend(std::iter::repeat_n('\\', escapes));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(windows)]
+pub use self::scm::{install, run, uninstall};
+
+#[cfg(not(windows))]
+const UNSUPPORTED: &str = "Windows services are only supported on Windows";
+
+/// Register the agent as a service starting at boot, running with
+/// configuration `config` or the default locations
+#[cfg(not(windows))]
+pub fn install(_name: &str, _config: Option<&Path>) -> Result<()> {
+    anyhow::bail!(UNSUPPORTED)
+}
+
+/// Stop the service if it runs and remove it
+#[cfg(not(windows))]
+pub fn uninstall(_name: &str) -> Result<()> {
+    anyhow::bail!(UNSUPPORTED)
+}
+
+/// Run `agent` as the service `name`, returning once it stopped
+#[cfg(not(windows))]
+pub fn run(_name: &str, _agent: impl FnOnce() + Send + 'static) -> Result<()> {
+    anyhow::bail!(UNSUPPORTED)
+}
+
+#[cfg(windows)]
+mod scm {
+    use anyhow::{anyhow, Context, Result};
+    use std::ffi::c_void;
+    use std::io;
+    use std::path::Path;
+    use std::ptr::{null, null_mut};
+    use std::sync::mpsc;
+    use std::sync::{Mutex, OnceLock};
+    use std::time::Duration;
+    use windows_sys::core::{BOOL, PWSTR};
+    use windows_sys::Win32::Foundation::{
+        ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_NOT_ACTIVE, NO_ERROR,
+    };
+    use windows_sys::Win32::System::Services::{
+        ChangeServiceConfig2W, CloseServiceHandle, ControlService, CreateServiceW, DeleteService,
+        OpenSCManagerW, OpenServiceW, RegisterServiceCtrlHandlerExW, SetServiceStatus,
+        StartServiceCtrlDispatcherW, SC_HANDLE, SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE,
+        SERVICE_ACCEPT_PAUSE_CONTINUE, SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP,
+        SERVICE_AUTO_START, SERVICE_CHANGE_CONFIG, SERVICE_CONFIG_DESCRIPTION,
+        SERVICE_CONTROL_CONTINUE, SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_PAUSE,
+        SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_DESCRIPTIONW, SERVICE_ERROR_NORMAL,
+        SERVICE_PAUSED, SERVICE_QUERY_STATUS, SERVICE_RUNNING, SERVICE_STATUS,
+        SERVICE_STATUS_HANDLE, SERVICE_STOP, SERVICE_STOPPED, SERVICE_STOP_PENDING,
+        SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+    };
+
+    use super::{command_line, launch_arguments, DESCRIPTION, DISPLAY_NAME};
+    use crate::shutdown;
+
+    /// Standard access right to delete an object
+    const DELETE: u32 = 0x0001_0000;
+
+    /// Time the service needs to stop on top of the time tasks get to finish
+    const STOP_MARGIN: Duration = Duration::from_secs(5);
+
+    /// Agent started by the service's main function
+    static AGENT: Mutex<Option<Box<dyn FnOnce() + Send>>> = Mutex::new(None);
+    /// Controls received from the service manager
+    static CONTROLS: OnceLock<mpsc::Sender<u32>> = OnceLock::new();
+    /// Name the service runs as, NUL terminated
+    static NAME: OnceLock<Vec<u16>> = OnceLock::new();
+
+    /// Closes a service manager or service handle when dropped
+    struct Handle(SC_HANDLE);
+
+    impl Handle {
+        fn new(handle: SC_HANDLE, action: &str) -> Result<Self> {
+            if handle.is_null() {
+                return Err(io::Error::last_os_error()).context(format!("Failed to {}", action));
+            }
+            Ok(Self(handle))
+        }
+
+        fn manager(access: u32) -> Result<Self> {
+            // SAFETY: null names select the local computer's active database
+            let handle = unsafe { OpenSCManagerW(null(), null(), access) };
+            Self::new(handle, "connect to the service control manager")
+        }
+    }
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            // SAFETY: the handle is valid and owned by `self`
+            unsafe { CloseServiceHandle(self.0) };
+        }
+    }
+
+    fn wide(value: &str) -> Vec<u16> {
+        value.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    fn check(result: BOOL, action: &str) -> Result<()> {
+        if result == 0 {
+            return Err(io::Error::last_os_error()).context(format!("Failed to {}", action));
+        }
+        Ok(())
+    }
+
+    /// Register the agent as a service starting at boot, running with
+    /// configuration `config` or the default locations
+    pub fn install(name: &str, config: Option<&Path>) -> Result<()> {
+        let executable = std::env::current_exe().context("Failed to locate the agent")?;
+        let executable = executable
+            .to_str()
+            .context("Agent path must be valid UTF-8")?
+            .to_string();
+        // The service doesn't start in the current directory
+        let config = config
+            .map(std::path::absolute)
+            .transpose()
+            .context("Failed to resolve the configuration path")?;
+        let mut arguments = vec![executable];
+        arguments.extend(launch_arguments(name, config.as_deref())?);
+
+        let manager = Handle::manager(SC_MANAGER_CONNECT | SC_MANAGER_CREATE_SERVICE)?;
+        let service_name = wide(name);
+        let display_name = wide(DISPLAY_NAME);
+        let command_line = wide(&command_line(&arguments));
+        // SAFETY: the strings are NUL terminated and outlive the call, a null
+        // account runs the service as LocalSystem
+        let handle = unsafe {
+            CreateServiceW(
+                manager.0,
+                service_name.as_ptr(),
+                display_name.as_ptr(),
+                SERVICE_CHANGE_CONFIG,
+                SERVICE_WIN32_OWN_PROCESS,
+                SERVICE_AUTO_START,
+                SERVICE_ERROR_NORMAL,
+                command_line.as_ptr(),
+                null(),
+                null_mut(),
+                null(),
+                null(),
+                null(),
+            )
+        };
+        let service = Handle::new(handle, &format!("create service {}", name))?;
+
+        let mut description = wide(DESCRIPTION);
+        let info = SERVICE_DESCRIPTIONW {
+            lpDescription: description.as_mut_ptr(),
+        };
+        // SAFETY: `info` matches SERVICE_CONFIG_DESCRIPTION and outlives the call
+        let result = unsafe {
+            ChangeServiceConfig2W(
+                service.0,
+                SERVICE_CONFIG_DESCRIPTION,
+                &info as *const SERVICE_DESCRIPTIONW as *const c_void,
+            )
+        };
+        check(result, "set the service description")
+    }
+
+    /// Stop the service if it runs and remove it
+    pub fn uninstall(name: &str) -> Result<()> {
+        let manager = Handle::manager(SC_MANAGER_CONNECT)?;
+        let service_name = wide(name);
+        // SAFETY: the name is NUL terminated and outlives the call
+        let handle = unsafe {
+            OpenServiceW(
+                manager.0,
+                service_name.as_ptr(),
+                SERVICE_STOP | SERVICE_QUERY_STATUS | DELETE,
+            )
+        };
+        let service = Handle::new(handle, &format!("open service {}", name))?;
+
+        let mut status = SERVICE_STATUS::default();
+        // SAFETY: `status` is a valid output buffer
+        if unsafe { ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) } == 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(ERROR_SERVICE_NOT_ACTIVE as i32) {
+                return Err(error).context(format!("Failed to stop service {}", name));
+            }
+        }
+        // The service is removed once it stopped and every handle is closed
+        // SAFETY: the handle was opened with DELETE access
+        check(
+            unsafe { DeleteService(service.0) },
+            &format!("delete service {}", name),
+        )
+    }
+
+    /// Run `agent` as the service `name`, returning once it stopped
+    pub fn run(name: &str, agent: impl FnOnce() + Send + 'static) -> Result<()> {
+        *AGENT.lock().unwrap() = Some(Box::new(agent));
+        NAME.set(wide(name))
+            .map_err(|_| anyhow!("The service is already running"))?;
+
+        let mut service_name = wide(name);
+        let table = [
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: service_name.as_mut_ptr(),
+                lpServiceProc: Some(service_main),
+            },
+            SERVICE_TABLE_ENTRYW::default(),
+        ];
+        // SAFETY: the table is terminated by a null entry and outlives the
+        // call, which returns once the service stopped
+        check(
+            unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) },
+            "connect to the service control manager, `service run` must be started by it",
+        )
+    }
+
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+        if let Err(e) = run_service() {
+            log::error!("Windows service failed: {:#}", e);
+        }
+    }
+
+    unsafe extern "system" fn handle_control(
+        control: u32,
+        _event_type: u32,
+        _event_data: *mut c_void,
+        _context: *mut c_void,
+    ) -> u32 {
+        match control {
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            SERVICE_CONTROL_STOP
+            | SERVICE_CONTROL_SHUTDOWN
+            | SERVICE_CONTROL_PAUSE
+            | SERVICE_CONTROL_CONTINUE => {
+                // The service thread reports the new state once it acted on it
+                if let Some(controls) = CONTROLS.get() {
+                    let _ = controls.send(control);
+                }
+                NO_ERROR
+            }
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    fn set_status(handle: SERVICE_STATUS_HANDLE, state: u32, wait_hint: Duration) -> Result<()> {
+        let controls_accepted = match state {
+            SERVICE_STOP_PENDING | SERVICE_STOPPED => 0,
+            _ => SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN | SERVICE_ACCEPT_PAUSE_CONTINUE,
+        };
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: controls_accepted,
+            dwWin32ExitCode: NO_ERROR,
+            dwServiceSpecificExitCode: 0,
+            dwCheckPoint: 0,
+            dwWaitHint: wait_hint.as_millis() as u32,
+        };
+        // SAFETY: the handle was returned by RegisterServiceCtrlHandlerExW
+        check(
+            unsafe { SetServiceStatus(handle, &status) },
+            "report the service status",
+        )
+    }
+
+    fn run_service() -> Result<()> {
+        let agent = AGENT.lock().unwrap().take().context("No agent to run")?;
+        let name = NAME.get().context("Service name isn't set")?;
+        let (sender, controls) = mpsc::channel();
+        CONTROLS
+            .set(sender)
+            .map_err(|_| anyhow!("The service is already running"))?;
+
+        // SAFETY: the name is NUL terminated and static
+        let handle =
+            unsafe { RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(handle_control), null()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error())
+                .context("Failed to register the service control handler");
+        }
+
+        set_status(handle, SERVICE_RUNNING, Duration::ZERO)?;
+        let agent = std::thread::spawn(agent);
+
+        let mut stopping = false;
+        while !agent.is_finished() {
+            let Ok(control) = controls.recv_timeout(Duration::from_secs(1)) else {
+                continue;
+            };
+            if stopping {
+                continue;
+            }
+            match control {
+                SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                    log::info!("Service stop requested");
+                    set_status(
+                        handle,
+                        SERVICE_STOP_PENDING,
+                        shutdown::SHUTDOWN_TIMEOUT + STOP_MARGIN,
+                    )?;
+                    shutdown::request();
+                    stopping = true;
+                }
+                SERVICE_CONTROL_PAUSE => {
+                    log::info!("Service paused, not acquiring tasks until it continues");
+                    shutdown::pause();
+                    set_status(handle, SERVICE_PAUSED, Duration::ZERO)?;
+                }
+                SERVICE_CONTROL_CONTINUE => {
+                    log::info!("Service continued");
+                    shutdown::resume();
+                    set_status(handle, SERVICE_RUNNING, Duration::ZERO)?;
+                }
+                _ => (),
+            }
+        }
+
+        if agent.join().is_err() {
+            log::error!("Agent panicked, stopping the service");
+        }
+        set_status(handle, SERVICE_STOPPED, Duration::ZERO)
+    }
+}
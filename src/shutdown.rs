@@ -0,0 +1,74 @@
+//! Graceful shutdown and pausing of the agent
+//!
+//! On SIGTERM or Ctrl-C the agent loops stop acquiring tasks, finish the
+//! ones they're running and return, so `systemctl stop` doesn't cut a task
+//! short or leave it acquired on the server. A paused agent keeps running
+//! but acquires nothing until it's resumed, as the Windows service manager
+//! expects.
+
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Time running tasks get to finish before the agent exits anyway
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+static SHUTDOWN: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+static PAUSED: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+
+/// Ask the agent loops to stop after their current task
+pub fn request() {
+    SHUTDOWN.send_replace(true);
+}
+
+/// Whether shutdown was requested
+pub fn is_requested() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+/// Wait until shutdown is requested
+pub async fn requested() {
+    let mut shutdown = SHUTDOWN.subscribe();
+    // The sender is static, so waiting never fails
+    let _ = shutdown.wait_for(|requested| *requested).await;
+}
+
+/// Ask the agent loops to stop acquiring tasks after their current one
+/// until [`resume`] is called
+pub fn pause() {
+    PAUSED.send_replace(true);
+}
+
+/// Let paused agent loops acquire tasks again
+pub fn resume() {
+    PAUSED.send_replace(false);
+}
+
+/// Whether the agent is paused
+pub fn is_paused() -> bool {
+    *PAUSED.borrow()
+}
+
+/// Wait for SIGTERM, SIGINT or Ctrl-C
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => log::info!("Received SIGTERM"),
+                    _ = tokio::signal::ctrl_c() => log::info!("Received SIGINT"),
+                }
+                return;
+            }
+            Err(e) => log::warn!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("Failed to listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+    log::info!("Received Ctrl-C");
+}
@@ -0,0 +1,115 @@
+//! Signed queries from the server
+//!
+//! With `query_signing` configured, the agent only runs queries accompanied
+//! by an Ed25519 signature made with the private key of one of the pinned
+//! public keys. Whoever takes over the server or its connection can then
+//! still hand out tasks, but not make the agent run queries of their own.
+//!
+//! A signature covers the datasource name and the query text, so a signed
+//! query can't be replayed against another datasource.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use std::sync::OnceLock;
+
+use crate::config::QuerySigningConfig;
+
+/// Prefix of signed messages, versioning their format
+pub const SIGNATURE_CONTEXT: &str = "tsight-query-v1";
+
+const PUBLIC_KEY_LEN: usize = 32;
+
+static VERIFIER: OnceLock<QueryVerifier> = OnceLock::new();
+
+/// Require signed queries for all agents
+pub fn init(config: &QuerySigningConfig) -> Result<()> {
+    let verifier = QueryVerifier::new(config)?;
+    VERIFIER
+        .set(verifier)
+        .map_err(|_| anyhow!("Query signing is already initialized"))
+}
+
+/// The verifier of signed queries, unless query signing is disabled
+pub fn verifier() -> Option<&'static QueryVerifier> {
+    VERIFIER.get()
+}
+
+/// Message signed for `query` on `datasource`: the context, datasource name
+/// and query separated by newlines
+pub fn signed_message(datasource: &str, query: &str) -> Vec<u8> {
+    format!("{}\n{}\n{}", SIGNATURE_CONTEXT, datasource, query).into_bytes()
+}
+
+/// Query without a valid signature
+#[derive(Debug, thiserror::Error)]
+#[error("query rejected: {0}")]
+pub struct SignatureError(String);
+
+/// Checks signatures of queries against the pinned public keys
+pub struct QueryVerifier {
+    keys: Vec<UnparsedPublicKey<Vec<u8>>>,
+}
+
+impl QueryVerifier {
+    pub fn new(config: &QuerySigningConfig) -> Result<Self> {
+        if config.public_keys.is_empty() {
+            bail!("query_signing.public_keys must list at least one key");
+        }
+        let keys = config
+            .public_keys
+            .iter()
+            .enumerate()
+            .map(|(index, encoded)| {
+                let key = STANDARD
+                    .decode(encoded.trim())
+                    .with_context(|| format!("query_signing.public_keys[{}] is not valid base64", index))?;
+                if key.len() != PUBLIC_KEY_LEN {
+                    bail!(
+                        "query_signing.public_keys[{}] must be a raw {}-byte Ed25519 key, got {} bytes",
+                        index,
+                        PUBLIC_KEY_LEN,
+                        key.len()
+                    );
+                }
+                Ok(UnparsedPublicKey::new(&ED25519, key))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { keys })
+    }
+
+    /// Check that `signature` is a base64 signature of `query` on
+    /// `datasource` made with one of the keys
+    pub fn verify(
+        &self,
+        datasource: &str,
+        query: &str,
+        signature: Option<&str>,
+    ) -> Result<(), SignatureError> {
+        let signature =
+            signature.ok_or_else(|| SignatureError("query is not signed".to_string()))?;
+        let signature = STANDARD
+            .decode(signature.trim())
+            .map_err(|_| SignatureError("signature is not valid base64".to_string()))?;
+        // Names with newlines could shift the query into the datasource part
+        if datasource.contains('\n') {
+            return Err(SignatureError(
+                "datasource name contains a newline".to_string(),
+            ));
+        }
+
+        let message = signed_message(datasource, query);
+        if self
+            .keys
+            .iter()
+            .any(|key| key.verify(&message, &signature).is_ok())
+        {
+            Ok(())
+        } else {
+            Err(SignatureError(
+                "signature doesn't match any of the public keys".to_string(),
+            ))
+        }
+    }
+}
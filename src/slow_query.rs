@@ -0,0 +1,65 @@
+//! Log of queries running longer than a configured threshold
+//!
+//! Slow queries are logged as warnings with their redacted text, datasource
+//! and rows returned, and optionally reported to the server, to spot which
+//! server-issued queries are hurting the warehouse.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::config::SlowQueryConfig;
+
+static SLOW_QUERY_LOG: OnceLock<SlowQueryConfig> = OnceLock::new();
+
+/// Log the slow queries of all agents as configured
+pub fn init(config: &SlowQueryConfig) -> Result<()> {
+    SLOW_QUERY_LOG
+        .set(config.clone())
+        .map_err(|_| anyhow!("Slow query log is already initialized"))
+}
+
+/// Settings of the slow query log, unless none is configured
+pub fn slow_query_log() -> Option<&'static SlowQueryConfig> {
+    SLOW_QUERY_LOG.get()
+}
+
+/// Query that ran at least as long as the threshold
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SlowQuery {
+    pub task_id: String,
+    pub datasource: String,
+    /// Query text as run, with literals redacted
+    pub query: String,
+    pub duration_ms: u64,
+    /// Rows returned, unset for statements and failed queries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rows: Option<usize>,
+    pub started_at: DateTime<Utc>,
+}
+
+impl SlowQueryConfig {
+    /// Whether a query that ran for `duration` counts as slow
+    pub fn is_slow(&self, duration: Duration) -> bool {
+        duration >= self.threshold
+    }
+}
+
+impl SlowQuery {
+    /// Log the query as a warning
+    pub fn log(&self) {
+        let rows = self
+            .rows
+            .map_or_else(|| "no".to_string(), |rows| rows.to_string());
+        log::warn!(
+            "Slow query of task {} on datasource {} took {}ms and returned {} rows: {}",
+            self.task_id,
+            self.datasource,
+            self.duration_ms,
+            rows,
+            self.query
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! Job rows kept within a memory budget
+//!
+//! Rows of a job are held in memory until their estimated size exceeds the
+//! datasource's `job_memory_budget_mb`, after which they're written to an
+//! unnamed temporary file as JSON lines. Spilled rows are read back in chunks
+//! no larger than the budget when they're submitted, so a large result never
+//! needs to fit in memory at once. The file is deleted once dropped.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, Write};
+use std::mem::size_of;
+
+use serde_json::Value;
+
+use crate::models::JobType;
+
+/// Rows written to a temporary file
+#[derive(Debug)]
+struct Spill {
+    writer: BufWriter<File>,
+    rows: usize,
+}
+
+/// Rows of a job, in memory or spilled to disk once over budget
+#[derive(Debug, Default)]
+pub struct JobRows {
+    rows: Vec<JobType>,
+    /// Estimated size of `rows`
+    size: u64,
+    /// Bytes rows may take in memory, unbounded when unset
+    budget: Option<u64>,
+    spill: Option<Spill>,
+    /// First error writing to the spill file, after which rows are dropped
+    error: Option<io::Error>,
+}
+
+impl JobRows {
+    /// Rows spilled to disk once they take more than `budget` bytes
+    pub fn new(budget: Option<u64>) -> Self {
+        Self {
+            budget,
+            ..Default::default()
+        }
+    }
+
+    /// Number of rows, in memory and spilled
+    pub fn len(&self) -> usize {
+        self.rows.len() + self.spill.as_ref().map_or(0, |spill| spill.rows)
+    }
+
+    /// Whether there are no rows
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether rows exceeded the budget and were written to disk
+    pub fn is_spilled(&self) -> bool {
+        self.spill.is_some()
+    }
+
+    /// Add a row, spilling all rows to disk if it takes them over budget.
+    /// Errors writing to disk are returned by [`JobRows::finish`]
+    pub fn push(&mut self, row: JobType) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.try_push(row) {
+            self.error = Some(e);
+        }
+    }
+
+    fn try_push(&mut self, row: JobType) -> io::Result<()> {
+        if let Some(spill) = &mut self.spill {
+            return write_row(spill, &row);
+        }
+        self.size += row_size(&row);
+        self.rows.push(row);
+        if self.budget.is_some_and(|budget| self.size > budget) {
+            let mut spill = Spill {
+                writer: BufWriter::new(tempfile::tempfile()?),
+                rows: 0,
+            };
+            for row in self.rows.drain(..) {
+                write_row(&mut spill, &row)?;
+            }
+            self.rows = Vec::new();
+            self.size = 0;
+            self.spill = Some(spill);
+        }
+        Ok(())
+    }
+
+    /// Flush spilled rows to disk, failing if any couldn't be written
+    pub fn finish(mut self) -> io::Result<Self> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if let Some(spill) = &mut self.spill {
+            spill.writer.flush()?;
+        }
+        Ok(self)
+    }
+
+    /// All rows in memory, reading back spilled ones
+    pub fn into_vec(self) -> io::Result<Vec<JobType>> {
+        let mut rows = Vec::with_capacity(self.len());
+        for chunk in self.into_chunks()? {
+            rows.extend(chunk?);
+        }
+        Ok(rows)
+    }
+
+    /// Rows in chunks of at most the budget, a single chunk when not spilled
+    pub fn into_chunks(self) -> io::Result<JobRowChunks> {
+        let source = match self.spill {
+            Some(spill) => {
+                let mut file = spill.writer.into_inner().map_err(|e| e.into_error())?;
+                file.rewind()?;
+                Source::Spilled(BufReader::new(file).lines())
+            }
+            None => Source::Memory(Some(self.rows)),
+        };
+        Ok(JobRowChunks {
+            source,
+            budget: self.budget.unwrap_or(u64::MAX),
+        })
+    }
+}
+
+impl From<Vec<JobType>> for JobRows {
+    fn from(rows: Vec<JobType>) -> Self {
+        Self {
+            rows,
+            ..Default::default()
+        }
+    }
+}
+
+/// Where the chunks of job rows are read from
+enum Source {
+    Memory(Option<Vec<JobType>>),
+    Spilled(io::Lines<BufReader<File>>),
+}
+
+/// Chunks of job rows read back in order
+pub struct JobRowChunks {
+    source: Source,
+    budget: u64,
+}
+
+impl Iterator for JobRowChunks {
+    type Item = io::Result<Vec<JobType>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let lines = match &mut self.source {
+            Source::Memory(rows) => return rows.take().map(Ok),
+            Source::Spilled(lines) => lines,
+        };
+        let mut chunk = Vec::new();
+        let mut size = 0;
+        while size < self.budget {
+            let Some(line) = lines.next() else {
+                break;
+            };
+            let row: JobType = match line.and_then(|line| Ok(serde_json::from_str(&line)?)) {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+            size += row_size(&row);
+            chunk.push(row);
+        }
+        (!chunk.is_empty()).then_some(Ok(chunk))
+    }
+}
+
+fn write_row(spill: &mut Spill, row: &JobType) -> io::Result<()> {
+    serde_json::to_writer(&mut spill.writer, row)?;
+    spill.writer.write_all(b"\n")?;
+    spill.rows += 1;
+    Ok(())
+}
+
+/// Estimated bytes a row takes in memory
+pub fn row_size(row: &JobType) -> u64 {
+    row.iter()
+        .map(|(name, value)| (size_of::<String>() + name.len()) as u64 + value_size(value))
+        .sum()
+}
+
+fn value_size(value: &Value) -> u64 {
+    let nested = match value {
+        Value::String(text) => text.len() as u64,
+        Value::Array(values) => values.iter().map(value_size).sum(),
+        Value::Object(object) => object
+            .iter()
+            .map(|(key, value)| (size_of::<String>() + key.len()) as u64 + value_size(value))
+            .sum(),
+        _ => 0,
+    };
+    size_of::<Value>() as u64 + nested
+}
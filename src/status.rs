@@ -0,0 +1,378 @@
+//! Read-only status page of the local listener
+//!
+//! `GET /status` shows what the agent is up to without going through the
+//! server: each datasource with its last probed connectivity and the rows
+//! and values its filters changed, the tasks in progress by queue, the most
+//! recent tasks with their outcomes and durations, and the effective
+//! configuration with secrets redacted. `GET /status.json` has the same for
+//! scripts. Activity is collected in a process-wide [`Activity`], like
+//! [`crate::metrics`].
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+
+use crate::build_info::{build_info, BuildInfo};
+use crate::client::DatasourceStatus;
+use crate::config::Config;
+use crate::filters::{FilterStats, REDACTED_VALUE};
+
+/// Tasks kept for the status page, newest first
+pub const RECENT_TASKS: usize = 50;
+
+/// Keys of configuration values redacted from the status page
+const SECRET_KEYS: &[&str] = &[
+    "api_key",
+    "client_secret",
+    "password",
+    "hash_key",
+    "token",
+    "secret_access_key",
+    "session_token",
+    "access_token",
+    "extra_headers",
+    "headers",
+    "webhooks",
+];
+
+/// Outcome of a task or job run by the agent
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskOutcome {
+    pub id: String,
+    pub queue: String,
+    pub datasource: String,
+    pub succeeded: bool,
+    /// Redacted error of a failed task
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// Recent activity of the agents of the process
+pub struct Activity {
+    recent: Mutex<VecDeque<TaskOutcome>>,
+    in_progress: Mutex<BTreeMap<String, usize>>,
+    datasources: Mutex<BTreeMap<String, DatasourceStatus>>,
+    filter_stats: Mutex<BTreeMap<String, FilterStats>>,
+}
+
+static ACTIVITY: LazyLock<Activity> = LazyLock::new(Activity::new);
+
+/// Get the activity of the process's agents
+pub fn activity() -> &'static Activity {
+    &ACTIVITY
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_TASKS)),
+            in_progress: Mutex::new(BTreeMap::new()),
+            datasources: Mutex::new(BTreeMap::new()),
+            filter_stats: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Record that a task acquired from `queue` started
+    pub fn start_task(&self, queue: &str) {
+        *self
+            .in_progress
+            .lock()
+            .unwrap()
+            .entry(queue.to_string())
+            .or_default() += 1;
+    }
+
+    /// Record how a task started with `start_task` ended
+    pub fn finish_task(&self, outcome: TaskOutcome) {
+        if let Some(count) = self.in_progress.lock().unwrap().get_mut(&outcome.queue) {
+            *count = count.saturating_sub(1);
+        }
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_TASKS {
+            recent.pop_back();
+        }
+        recent.push_front(outcome);
+    }
+
+    /// Record the outcome of the last connectivity probe of a datasource
+    pub fn record_datasource_status(&self, datasource: &str, status: &DatasourceStatus) {
+        self.datasources
+            .lock()
+            .unwrap()
+            .insert(datasource.to_string(), status.clone());
+    }
+
+    /// Add the rows and values filters changed in results of `datasource`
+    pub fn record_filters(&self, datasource: &str, stats: &FilterStats) {
+        self.filter_stats
+            .lock()
+            .unwrap()
+            .entry(datasource.to_string())
+            .or_default()
+            .merge(stats);
+    }
+
+    /// Most recent tasks, newest first
+    pub fn recent_tasks(&self) -> Vec<TaskOutcome> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Tasks in progress by queue
+    pub fn in_progress(&self) -> BTreeMap<String, usize> {
+        self.in_progress.lock().unwrap().clone()
+    }
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Datasource as shown on the status page
+#[derive(Debug, Clone, Serialize)]
+pub struct DatasourceSummary {
+    pub name: String,
+    pub source_type: String,
+    /// Last connectivity probe, unset until one ran
+    pub status: Option<DatasourceStatus>,
+    /// Filter stats accumulated since the agent started
+    pub filter_stats: FilterStats,
+}
+
+/// Everything on the status page
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub agent: &'static BuildInfo,
+    pub uptime_secs: u64,
+    pub datasources: Vec<DatasourceSummary>,
+    /// Tasks in progress by queue
+    pub in_progress: BTreeMap<String, usize>,
+    /// Most recent tasks, newest first
+    pub recent_tasks: Vec<TaskOutcome>,
+    /// Effective configuration with secrets redacted
+    pub config: Value,
+}
+
+/// Status page of an agent process and its configuration
+pub struct StatusPage {
+    activity: &'static Activity,
+    datasources: Vec<(String, String)>,
+    config: Value,
+    started: std::time::Instant,
+}
+
+impl StatusPage {
+    /// Status page of the process-wide activity
+    pub fn new(config: &Config) -> Self {
+        Self::with_activity(config, activity())
+    }
+
+    pub fn with_activity(config: &Config, activity: &'static Activity) -> Self {
+        Self {
+            activity,
+            datasources: config
+                .datasources
+                .iter()
+                .map(|ds| (ds.name.clone(), ds.source_type.to_string()))
+                .collect(),
+            config: redacted_config(config),
+            started: std::time::Instant::now(),
+        }
+    }
+
+    pub fn report(&self) -> StatusReport {
+        let statuses = self.activity.datasources.lock().unwrap().clone();
+        let filter_stats = self.activity.filter_stats.lock().unwrap().clone();
+        StatusReport {
+            agent: build_info(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            datasources: self
+                .datasources
+                .iter()
+                .map(|(name, source_type)| DatasourceSummary {
+                    name: name.clone(),
+                    source_type: source_type.clone(),
+                    status: statuses.get(name).cloned(),
+                    filter_stats: filter_stats.get(name).cloned().unwrap_or_default(),
+                })
+                .collect(),
+            in_progress: self.activity.in_progress(),
+            recent_tasks: self.activity.recent_tasks(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// Configuration as JSON with credentials, tokens, header values and
+/// passwords in URLs replaced by `[REDACTED]`
+pub fn redacted_config(config: &Config) -> Value {
+    let mut value = serde_json::to_value(config).unwrap_or_default();
+    redact(&mut value);
+    value
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                if SECRET_KEYS.contains(&key.as_str()) {
+                    redact_all(field);
+                } else if let Value::String(text) = field {
+                    redact_url_password(text);
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::String(text) => redact_url_password(text),
+        _ => (),
+    }
+}
+
+/// Redact every string within a value, keeping empty ones to show they're unset
+fn redact_all(value: &mut Value) {
+    match value {
+        Value::String(text) if !text.is_empty() => *text = REDACTED_VALUE.to_string(),
+        Value::Object(fields) => fields.values_mut().for_each(redact_all),
+        Value::Array(items) => items.iter_mut().for_each(redact_all),
+        _ => (),
+    }
+}
+
+fn redact_url_password(text: &mut String) {
+    if let Ok(mut url) = reqwest::Url::parse(text) {
+        if url.password().is_some() && url.set_password(Some(REDACTED_VALUE)).is_ok() {
+            *text = url.to_string();
+        }
+    }
+}
+
+/// Render a report as a self-contained HTML page refreshing every 10s
+pub fn render_html(report: &StatusReport) -> String {
+    let mut html = String::new();
+    html.push_str(concat!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">",
+        "<meta http-equiv=\"refresh\" content=\"10\">",
+        "<title>TSight agent status</title><style>",
+        "body{font-family:sans-serif;margin:2em}",
+        "table{border-collapse:collapse;margin-bottom:1.5em}",
+        "th,td{border:1px solid #ccc;padding:.3em .6em;text-align:left}",
+        ".ok{color:#080}.failed{color:#b00}",
+        "</style></head><body>\n",
+    ));
+    let _ = writeln!(
+        html,
+        "<h1>TSight agent {}</h1><p>Commit {}, up for {}</p>",
+        escape(&report.agent.version),
+        escape(&report.agent.git_commit),
+        format_uptime(report.uptime_secs)
+    );
+
+    html.push_str("<h2>Datasources</h2>\n<table><tr><th>Name</th><th>Type</th><th>Connectivity</th><th>Checked</th><th>Dropped rows</th><th>Masked values</th><th>Sampled rows</th></tr>\n");
+    for datasource in &report.datasources {
+        let (connectivity, checked) = match &datasource.status {
+            Some(status) if status.reachable => (
+                format!(
+                    "<span class=\"ok\">reachable</span> ({} ms)",
+                    status.latency_ms
+                ),
+                status.checked_at.to_rfc3339(),
+            ),
+            Some(status) => (
+                format!(
+                    "<span class=\"failed\">unreachable</span>: {}",
+                    escape(status.error.as_deref().unwrap_or_default())
+                ),
+                status.checked_at.to_rfc3339(),
+            ),
+            None => ("not probed".to_string(), String::new()),
+        };
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&datasource.name),
+            escape(&datasource.source_type),
+            connectivity,
+            checked,
+            datasource.filter_stats.dropped_rows,
+            datasource.filter_stats.masked_values,
+            datasource.filter_stats.sampled_rows,
+        );
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>In progress</h2>\n<table><tr><th>Queue</th><th>Tasks</th></tr>\n");
+    for (queue, count) in &report.in_progress {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            escape(queue),
+            count
+        );
+    }
+    html.push_str("</table>\n");
+
+    let _ = writeln!(
+        html,
+        "<h2>Recent tasks</h2>\n<table><tr><th>Finished</th><th>Id</th><th>Queue</th><th>Datasource</th><th>Duration</th><th>Outcome</th></tr>"
+    );
+    for task in &report.recent_tasks {
+        let outcome = match &task.error {
+            None => "<span class=\"ok\">succeeded</span>".to_string(),
+            Some(error) => format!("<span class=\"failed\">failed</span>: {}", escape(error)),
+        };
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} ms</td><td>{}</td></tr>",
+            task.finished_at.to_rfc3339(),
+            escape(&task.id),
+            escape(&task.queue),
+            escape(&task.datasource),
+            task.duration_ms,
+            outcome,
+        );
+    }
+    html.push_str("</table>\n");
+
+    let config = serde_json::to_string_pretty(&report.config).unwrap_or_default();
+    let _ = writeln!(
+        html,
+        "<h2>Configuration</h2>\n<pre>{}</pre>\n</body></html>",
+        escape(&config)
+    );
+    html
+}
+
+/// Uptime as days, hours, minutes and seconds, e.g. `1d 2h 3m 4s`
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", secs % 60),
+        (0, 0, _) => format!("{}m {}s", minutes, secs % 60),
+        (0, _, _) => format!("{}h {}m {}s", hours, minutes, secs % 60),
+        _ => format!("{}d {}h {}m {}s", days, hours, minutes, secs % 60),
+    }
+}
+
+/// Escape text for HTML element content and attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
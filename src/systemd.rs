@@ -0,0 +1,240 @@
+//! systemd service integration
+//!
+//! When run as a `Type=notify` service, the agent reports `READY=1` once its
+//! configuration is loaded and the first acquire reached the server, pings
+//! the watchdog while every agent loop keeps iterating, and reports
+//! `STOPPING=1` when it shuts down. Outside of systemd `NOTIFY_SOCKET` is
+//! unset and nothing is sent.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::errors::ErrorCategory;
+
+/// The agent finished starting up
+pub const READY: &str = "READY=1";
+/// The agent is shutting down
+pub const STOPPING: &str = "STOPPING=1";
+/// The agent is alive, resetting the watchdog timer
+pub const WATCHDOG: &str = "WATCHDOG=1";
+
+/// Sends state changes to the service manager
+pub struct Notifier {
+    /// Path of the notification socket, `@` prefixed for abstract sockets
+    socket: Option<OsString>,
+}
+
+static NOTIFIER: LazyLock<Notifier> = LazyLock::new(Notifier::from_env);
+
+/// Get the notifier of the process, configured by `NOTIFY_SOCKET`
+pub fn notifier() -> &'static Notifier {
+    &NOTIFIER
+}
+
+impl Notifier {
+    /// Notifier sending to `socket`
+    pub fn new(socket: impl Into<OsString>) -> Self {
+        Self {
+            socket: Some(socket.into()),
+        }
+    }
+
+    /// Notifier sending to `NOTIFY_SOCKET`, or nowhere when it's unset
+    pub fn from_env() -> Self {
+        Self {
+            socket: std::env::var_os("NOTIFY_SOCKET").filter(|socket| !socket.is_empty()),
+        }
+    }
+
+    /// Whether the agent runs under a service manager expecting notifications
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Send newline separated `KEY=value` assignments, e.g. `READY=1`
+    pub fn notify(&self, state: &str) -> Result<()> {
+        match &self.socket {
+            Some(socket) => send(socket, state),
+            None => Ok(()),
+        }
+    }
+
+    /// Report a state along with a human-readable status shown by
+    /// `systemctl status`, logging failures
+    pub fn notify_status(&self, state: &str, status: &str) {
+        if let Err(e) = self.notify(&format!("{}\nSTATUS={}", state, status)) {
+            log::warn!("Failed to notify service manager: {:#}", e);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &std::ffi::OsStr, state: &str) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &address)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => anyhow::bail!("Abstract notification sockets are only supported on Linux"),
+        None => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &std::ffi::OsStr, _state: &str) -> Result<()> {
+    anyhow::bail!("Service manager notifications are only supported on Unix")
+}
+
+/// Watchdog timeout requested by the service manager through `WATCHDOG_USEC`,
+/// unless `WATCHDOG_PID` names another process
+pub fn watchdog_timeout() -> Option<Duration> {
+    parse_watchdog_timeout(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+/// Parse the values of `WATCHDOG_USEC` and `WATCHDOG_PID` for process `pid`
+pub fn parse_watchdog_timeout(
+    usec: Option<&str>,
+    watchdog_pid: Option<&str>,
+    pid: u32,
+) -> Option<Duration> {
+    if let Some(watchdog_pid) = watchdog_pid {
+        if watchdog_pid.parse::<u32>().ok()? != pid {
+            return None;
+        }
+    }
+    let usec = usec?.parse::<u64>().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec))
+}
+
+/// Progress of the agent's loops, deciding readiness and watchdog pings
+pub struct Liveness {
+    /// Last iteration of each loop by name
+    beats: Mutex<HashMap<&'static str, Instant>>,
+    acquired: watch::Sender<bool>,
+}
+
+static LIVENESS: LazyLock<Liveness> = LazyLock::new(Liveness::new);
+
+/// Get the liveness of the process's agent loops
+pub fn liveness() -> &'static Liveness {
+    &LIVENESS
+}
+
+impl Liveness {
+    pub fn new() -> Self {
+        Self {
+            beats: Mutex::new(HashMap::new()),
+            acquired: watch::Sender::new(false),
+        }
+    }
+
+    /// Record an iteration of the loop named `name`
+    pub fn beat(&self, name: &'static str) {
+        self.beats.lock().unwrap().insert(name, Instant::now());
+    }
+
+    /// Whether every loop that started iterated within `max_silence`
+    pub fn is_alive(&self, max_silence: Duration) -> bool {
+        self.stalled(max_silence).is_empty()
+    }
+
+    /// Loops that didn't iterate within `max_silence`
+    pub fn stalled(&self, max_silence: Duration) -> Vec<&'static str> {
+        let beats = self.beats.lock().unwrap();
+        let mut stalled: Vec<_> = beats
+            .iter()
+            .filter(|(_, beat)| beat.elapsed() > max_silence)
+            .map(|(name, _)| *name)
+            .collect();
+        stalled.sort();
+        stalled
+    }
+
+    /// Record the outcome of an acquire, which reached the server unless it
+    /// failed for another reason than there being no work
+    pub fn record_acquire<T>(&self, acquired: &Result<T>) {
+        let reached_server = match acquired {
+            Ok(_) => true,
+            Err(e) => ErrorCategory::of(e) == ErrorCategory::NoWork,
+        };
+        if reached_server {
+            self.acquired.send_replace(true);
+        }
+    }
+
+    /// Whether an acquire reached the server
+    pub fn has_acquired(&self) -> bool {
+        *self.acquired.borrow()
+    }
+
+    /// Wait until an acquire reaches the server
+    pub async fn first_acquire(&self) {
+        let mut acquired = self.acquired.subscribe();
+        // The sender lives as long as `self`, so this only fails once it's dropped
+        let _ = acquired.wait_for(|acquired| *acquired).await;
+    }
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Report readiness once the first acquire reached the server
+pub fn spawn_ready_notification(
+    notifier: &'static Notifier,
+    liveness: &'static Liveness,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        liveness.first_acquire().await;
+        log::info!("First acquire reached the server, agent is ready");
+        notifier.notify_status(READY, "Processing tasks");
+    })
+}
+
+/// Ping the watchdog at half its `timeout` while every loop iterated within
+/// the timeout, so the service manager restarts an agent whose loops hang
+pub fn spawn_watchdog(
+    notifier: &'static Notifier,
+    liveness: &'static Liveness,
+    timeout: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(timeout / 2);
+        loop {
+            interval.tick().await;
+            let stalled = liveness.stalled(timeout);
+            if !stalled.is_empty() {
+                log::error!(
+                    "Agent loops stalled: {}, not pinging the watchdog",
+                    stalled.join(", ")
+                );
+                continue;
+            }
+            if let Err(e) = notifier.notify(WATCHDOG) {
+                log::warn!("Failed to ping watchdog: {:#}", e);
+            }
+        }
+    })
+}
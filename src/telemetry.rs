@@ -0,0 +1,74 @@
+//! OpenTelemetry tracing of task lifecycles
+//!
+//! The `tracing` spans of each task, from acquiring it through executing and
+//! filtering its queries to submitting the results, are exported over
+//! OTLP/HTTP. Requests to the server carry the W3C trace context, so its
+//! traces of a task continue the agent's.
+
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::Layer;
+
+use crate::config::OpenTelemetryConfig;
+use crate::logging::TraceLayer;
+
+/// Service name reported when the config doesn't set one
+pub const DEFAULT_SERVICE_NAME: &str = "tsight-agent";
+
+/// Path of the traces endpoint below the collector's OTLP/HTTP address
+const TRACES_PATH: &str = "/v1/traces";
+
+/// Create the tracer provider exporting spans to the configured collector
+pub fn tracer_provider(config: &OpenTelemetryConfig) -> Result<SdkTracerProvider> {
+    let mut exporter = SpanExporter::builder().with_http();
+    // Without an endpoint the exporter follows the `OTEL_EXPORTER_OTLP_*`
+    // environment variables
+    if let Some(endpoint) = &config.endpoint {
+        exporter =
+            exporter.with_endpoint(format!("{}{}", endpoint.trim_end_matches('/'), TRACES_PATH));
+    }
+    if let Some(headers) = &config.headers {
+        exporter = exporter.with_headers(headers.clone());
+    }
+    let exporter = exporter.build().context("Failed to create OTLP exporter")?;
+
+    let service_name = config
+        .service_name
+        .clone()
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build())
+}
+
+/// Export spans to the configured collector, returning the layer passing
+/// them on and the provider to flush on shutdown
+pub fn init(config: &OpenTelemetryConfig) -> Result<(TraceLayer, SdkTracerProvider)> {
+    let provider = tracer_provider(config)?;
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("tsight_agent"))
+        .boxed();
+
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    opentelemetry::global::set_tracer_provider(provider.clone());
+    Ok((layer, provider))
+}
+
+/// Headers carrying the trace context of the current span, empty unless
+/// tracing is enabled
+pub fn trace_headers() -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    let context = Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut headers)
+    });
+    headers
+}
@@ -0,0 +1,157 @@
+//! Grafana-style time macros of queries received from the server
+//!
+//! Stored queries refer to the time range they're run for with macros such
+//! as `$__timeFilter(created_at)`, which the agent expands with the time
+//! range of the task in the syntax of the datasource. One query thereby
+//! works across dashboards, and across datasources of different dialects.
+//! Macros are expanded after the query's signature was verified, so the
+//! signed query is the one with macros.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+
+use crate::client::TimeRange;
+use crate::models::DataSourceType;
+
+/// Macro name with its optional parenthesized argument
+static MACRO: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$__(\w+)(?:\(([^()]*)\))?").unwrap());
+
+/// Points a range is divided into when the task sets no interval, as
+/// Grafana's default for panels without a width
+const DEFAULT_DATA_POINTS: i64 = 1000;
+
+/// Query whose macros couldn't be expanded
+#[derive(Debug, thiserror::Error)]
+#[error("failed to expand time macros: {0}")]
+pub struct MacroError(String);
+
+/// Expand the time macros of `sql` with `range`, in the syntax of
+/// `source_type`:
+///
+/// - `$__timeFilter(column)`: condition of `column` being within the range
+/// - `$__timeFrom()` and `$__timeTo()`: start and end of the range
+/// - `$__interval`: interval between points, as `30s` or `5m`
+/// - `$__interval_ms`: interval between points in milliseconds
+///
+/// Queries without macros are returned unchanged, with or without a range
+pub fn expand_time_macros(
+    sql: &str,
+    range: Option<&TimeRange>,
+    source_type: &DataSourceType,
+) -> Result<String, MacroError> {
+    if !MACRO.is_match(sql) {
+        return Ok(sql.to_string());
+    }
+    let mut error = None;
+    let expanded = MACRO.replace_all(sql, |captures: &Captures| {
+        match expand(captures, range, source_type) {
+            Ok(expansion) => expansion,
+            Err(e) => {
+                error.get_or_insert(e);
+                String::new()
+            }
+        }
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(expanded.into_owned()),
+    }
+}
+
+fn expand(
+    captures: &Captures,
+    range: Option<&TimeRange>,
+    source_type: &DataSourceType,
+) -> Result<String, MacroError> {
+    let name = &captures[1];
+    let argument = captures.get(2).map(|argument| argument.as_str().trim());
+    let range = range.ok_or_else(|| {
+        MacroError(format!(
+            "query uses $__{} but the task has no time range",
+            name
+        ))
+    })?;
+    match name {
+        "timeFilter" => {
+            let column = argument
+                .filter(|column| !column.is_empty())
+                .ok_or_else(|| {
+                    MacroError(
+                        "$__timeFilter needs a column, as in $__timeFilter(created_at)".into(),
+                    )
+                })?;
+            time_filter(column, range, source_type)
+        }
+        "timeFrom" => Ok(timestamp(&range.from, source_type)),
+        "timeTo" => Ok(timestamp(&range.to, source_type)),
+        "interval" => Ok(format_interval(interval_ms(range))),
+        "interval_ms" => Ok(interval_ms(range).to_string()),
+        _ => Err(MacroError(format!("unknown macro $__{}", name))),
+    }
+}
+
+/// Condition of `column` being within `range`
+fn time_filter(
+    column: &str,
+    range: &TimeRange,
+    source_type: &DataSourceType,
+) -> Result<String, MacroError> {
+    if *source_type == DataSourceType::Prometheus {
+        return Err(MacroError(
+            "$__timeFilter isn't supported by Prometheus datasources".to_string(),
+        ));
+    }
+    Ok(format!(
+        "{} BETWEEN {} AND {}",
+        column,
+        timestamp(&range.from, source_type),
+        timestamp(&range.to, source_type)
+    ))
+}
+
+/// Literal of `time` in the dialect of `source_type`, to the millisecond
+/// when it has a fraction of a second
+fn timestamp(time: &DateTime<Utc>, source_type: &DataSourceType) -> String {
+    let millis = time.timestamp_subsec_millis();
+    let seconds = time.timestamp();
+    match source_type {
+        DataSourceType::Clickhouse | DataSourceType::Mock if millis == 0 => {
+            format!("toDateTime({})", seconds)
+        }
+        DataSourceType::Clickhouse | DataSourceType::Mock => {
+            format!("toDateTime64({}.{:03}, 3)", seconds, millis)
+        }
+        DataSourceType::PostgreSQL => {
+            format!("'{}'", time.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+        }
+        DataSourceType::MySQL if millis == 0 => format!("FROM_UNIXTIME({})", seconds),
+        DataSourceType::MySQL => format!("FROM_UNIXTIME({}.{:03})", seconds, millis),
+        DataSourceType::Prometheus if millis == 0 => seconds.to_string(),
+        DataSourceType::Prometheus => format!("{}.{:03}", seconds, millis),
+    }
+}
+
+/// Milliseconds between points of `range`, at least one
+fn interval_ms(range: &TimeRange) -> u64 {
+    range.interval_ms.unwrap_or_else(|| {
+        let span = (range.to - range.from).num_milliseconds();
+        (span / DEFAULT_DATA_POINTS).max(1) as u64
+    })
+}
+
+/// Interval in its largest whole unit, as Grafana formats `$__interval`
+fn format_interval(ms: u64) -> String {
+    const UNITS: [(u64, &str); 4] = [
+        (24 * 60 * 60 * 1000, "d"),
+        (60 * 60 * 1000, "h"),
+        (60 * 1000, "m"),
+        (1000, "s"),
+    ];
+    UNITS
+        .iter()
+        .find(|(unit, _)| ms >= *unit && ms % unit == 0)
+        .map(|(unit, suffix)| format!("{}{}", ms / unit, suffix))
+        .unwrap_or_else(|| format!("{}ms", ms))
+}
@@ -0,0 +1,300 @@
+//! Mapping of task query rows to time series records
+//!
+//! Task queries used to alias exactly `t` and `cnt`. A `TsMapping` names the
+//! column holding the timestamp and those holding values instead, so any
+//! numeric columns can be reported, and timestamps keep millisecond precision.
+//! Other columns label the series, so one query can report e.g. a series per
+//! status or region.
+
+use crate::downsample::Downsample;
+use crate::models::{JobType, Record};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TsMappingError {
+    #[error("Time column '{0}' is missing from the query results")]
+    MissingTimeColumn(String),
+    #[error("Invalid timestamp in column '{column}': {value}")]
+    InvalidTimestamp { column: String, value: String },
+    #[error("Value column '{0}' is missing from the query results")]
+    MissingValueColumn(String),
+    #[error("Value column '{column}' is not numeric: {value}")]
+    NotNumeric { column: String, value: String },
+    #[error("Label column '{0}' is missing from the query results")]
+    MissingLabelColumn(String),
+}
+
+/// Unit of numeric timestamps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeUnit {
+    /// Seconds since the Unix epoch, as returned by `toUnixTimestamp`
+    #[default]
+    Seconds,
+    /// Milliseconds since the Unix epoch
+    Milliseconds,
+}
+
+/// Handling of `NULL` values, e.g. from a `Nullable` column or an outer join
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NullValues {
+    /// Leave the row out
+    #[default]
+    Skip,
+    /// Report the value as zero
+    Zero,
+}
+
+/// Columns of a task query's rows making up time series records
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TsMapping {
+    /// Column holding the timestamp, either a number in `time_unit` or a
+    /// date or date-time
+    #[serde(default = "default_time_column")]
+    pub time_column: String,
+    /// Unit of numeric timestamps
+    #[serde(default)]
+    pub time_unit: TimeUnit,
+    /// Columns holding values, all other numeric columns when unset
+    #[serde(default)]
+    pub value_columns: Option<Vec<String>>,
+    /// Columns labelling series, all columns besides the time and value ones
+    /// when unset
+    #[serde(default)]
+    pub label_columns: Option<Vec<String>>,
+    /// What to do with rows having a `NULL` value; rows with a `NULL`
+    /// timestamp are always skipped
+    #[serde(default)]
+    pub null_values: NullValues,
+    /// Bucketing, gap filling and capping of the records, after filters
+    #[serde(default)]
+    pub downsample: Option<Downsample>,
+}
+
+fn default_time_column() -> String {
+    "t".to_string()
+}
+
+impl Default for TsMapping {
+    fn default() -> Self {
+        Self {
+            time_column: default_time_column(),
+            time_unit: TimeUnit::default(),
+            value_columns: None,
+            label_columns: None,
+            null_values: NullValues::default(),
+            downsample: None,
+        }
+    }
+}
+
+impl TsMapping {
+    /// Map query rows to records, skipping or zero-filling rows with `NULL`s
+    /// as set by `null_values`
+    pub fn records(&self, rows: Vec<JobType>) -> Result<(Vec<Record>, NullStats), TsMappingError> {
+        let value_columns = self.value_columns_of(&rows);
+        let mut stats = NullStats::default();
+        let mut records = Vec::with_capacity(rows.len());
+        for row in &rows {
+            if let Some(record) = self.map_row(row, &value_columns, &mut stats)? {
+                records.push(record);
+            }
+        }
+        Ok((records, stats))
+    }
+
+    /// Map a single query row to a record, `None` when it's skipped for a
+    /// `NULL` timestamp or value
+    pub fn record(&self, row: &JobType) -> Result<Option<Record>, TsMappingError> {
+        let value_columns = self.value_columns_of(std::slice::from_ref(row));
+        self.map_row(row, &value_columns, &mut NullStats::default())
+    }
+
+    /// Value columns of the rows: the listed ones, or else the columns
+    /// besides the time and label ones whose values are all numbers or
+    /// `NULL`, and not only `NULL`
+    fn value_columns_of(&self, rows: &[JobType]) -> Vec<String> {
+        if let Some(columns) = &self.value_columns {
+            return columns.clone();
+        }
+
+        let mut numeric: BTreeMap<&String, bool> = BTreeMap::new();
+        for (column, value) in rows.iter().flatten() {
+            if *column == self.time_column || self.is_label(column) {
+                continue;
+            }
+            let is_numeric = numeric.entry(column).or_insert(true);
+            *is_numeric = *is_numeric && (value.is_null() || parse_number(value).is_some());
+        }
+        numeric
+            .into_iter()
+            .filter(|(column, is_numeric)| {
+                *is_numeric
+                    && rows
+                        .iter()
+                        .any(|row| row.get(*column).is_some_and(|v| !v.is_null()))
+            })
+            .map(|(column, _)| column.clone())
+            .collect()
+    }
+
+    fn map_row(
+        &self,
+        row: &JobType,
+        value_columns: &[String],
+        stats: &mut NullStats,
+    ) -> Result<Option<Record>, TsMappingError> {
+        let time = row
+            .get(&self.time_column)
+            .ok_or_else(|| TsMappingError::MissingTimeColumn(self.time_column.clone()))?;
+        if time.is_null() {
+            // There's no point in a value without a time
+            stats.skipped_rows += 1;
+            return Ok(None);
+        }
+        let t = parse_timestamp(time, self.time_unit).ok_or_else(|| {
+            TsMappingError::InvalidTimestamp {
+                column: self.time_column.clone(),
+                value: time.to_string(),
+            }
+        })?;
+
+        let mut values = BTreeMap::new();
+        let mut has_null = false;
+        for column in value_columns {
+            let value = row
+                .get(column)
+                .ok_or_else(|| TsMappingError::MissingValueColumn(column.clone()))?;
+            if value.is_null() {
+                has_null = true;
+                match self.null_values {
+                    NullValues::Skip => {
+                        stats.skipped_rows += 1;
+                        return Ok(None);
+                    }
+                    NullValues::Zero => {
+                        values.insert(column.clone(), 0.0);
+                        continue;
+                    }
+                }
+            }
+            let number = parse_number(value).ok_or_else(|| TsMappingError::NotNumeric {
+                column: column.clone(),
+                value: value.to_string(),
+            })?;
+            values.insert(column.clone(), number);
+        }
+        if has_null {
+            stats.zero_filled_rows += 1;
+        }
+
+        let labels = match &self.label_columns {
+            Some(columns) => columns
+                .iter()
+                .map(|column| {
+                    let value = row
+                        .get(column)
+                        .ok_or_else(|| TsMappingError::MissingLabelColumn(column.clone()))?;
+                    Ok(label(value).map(|value| (column.clone(), value)))
+                })
+                .filter_map(Result::transpose)
+                .collect::<Result<BTreeMap<_, _>, _>>()?,
+            None => row
+                .iter()
+                .filter(|(column, _)| {
+                    **column != self.time_column && !value_columns.contains(column)
+                })
+                .filter_map(|(column, value)| Some((column.clone(), label(value)?)))
+                .collect(),
+        };
+
+        Ok(Some(Record { t, values, labels }))
+    }
+
+    fn is_label(&self, column: &str) -> bool {
+        self.label_columns
+            .as_ref()
+            .is_some_and(|columns| columns.iter().any(|c| c == column))
+    }
+}
+
+/// Counts of rows with `NULL`s while mapping a task's results
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NullStats {
+    /// Rows left out for a `NULL` timestamp or value
+    pub skipped_rows: u64,
+    /// Rows whose `NULL` values were reported as zero
+    pub zero_filled_rows: u64,
+}
+
+impl NullStats {
+    /// Check if no row had `NULL`s
+    pub fn is_empty(&self) -> bool {
+        self.skipped_rows == 0 && self.zero_filled_rows == 0
+    }
+
+    /// Add the counts of another result set
+    pub fn merge(&mut self, other: &NullStats) {
+        self.skipped_rows += other.skipped_rows;
+        self.zero_filled_rows += other.zero_filled_rows;
+    }
+}
+
+impl std::fmt::Display for NullStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} rows skipped, {} rows zero-filled",
+            self.skipped_rows, self.zero_filled_rows
+        )
+    }
+}
+
+/// Numeric value of a JSON number or of a string holding one, as ClickHouse
+/// returns 64-bit integers and decimals
+fn parse_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Text of a label, `None` for `NULL`
+fn label(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(text) => Some(text.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Milliseconds since the Unix epoch of a numeric timestamp, an RFC 3339
+/// date-time, a ClickHouse `YYYY-MM-DD hh:mm:ss[.fff]` date-time in UTC or a
+/// `YYYY-MM-DD` date
+fn parse_timestamp(value: &Value, unit: TimeUnit) -> Option<i64> {
+    if let Some(number) = parse_number(value) {
+        let millis = match unit {
+            TimeUnit::Seconds => number * 1000.0,
+            TimeUnit::Milliseconds => number,
+        };
+        return millis.is_finite().then_some(millis.round() as i64);
+    }
+
+    let text = value.as_str()?.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.timestamp_millis());
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f") {
+        return Some(time.and_utc().timestamp_millis());
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc().timestamp_millis())
+}
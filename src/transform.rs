@@ -0,0 +1,227 @@
+//! Post-processing hook transforming job results before they're submitted
+//!
+//! A datasource's `transform` program gets the filtered rows of each job on
+//! its stdin as JSON lines and writes the rows to submit to its stdout the
+//! same way, so it can rename columns, derive fields or scrub values beyond
+//! what regexes can express. Any language works. The program runs with the
+//! agent's permissions, not in a sandbox, so configs may only use it in
+//! builds with the `transform-programs` feature, and only on Unix, where
+//! its memory can be capped. It's killed along with the processes it started
+//! once it exceeds its time limit, and its output is capped too.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeSet;
+use std::io;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{ChildStdin, Command};
+
+use crate::config::TransformConfig;
+use crate::executors::clickhouse_source::ResultColumn;
+use crate::models::JobType;
+use crate::spill::JobRows;
+
+/// Bytes of the program's stderr kept for the error of a failed transform
+const STDERR_LIMIT: u64 = 4096;
+
+/// Run `rows` through the program of `config`, keeping the rows it writes
+/// within `budget` bytes of memory like the rows of the query. Columns of
+/// the output keep the types of `columns`, and new ones are typed after
+/// their values in the first row
+pub async fn transform_rows(
+    config: &TransformConfig,
+    rows: JobRows,
+    columns: Vec<ResultColumn>,
+    budget: Option<u64>,
+) -> Result<(JobRows, Vec<ResultColumn>)> {
+    let program = config
+        .command
+        .first()
+        .ok_or_else(|| anyhow!("Transform command is empty"))?;
+    let mut command = Command::new(program);
+    command
+        .args(&config.command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    limit_memory(&mut command, config.memory_limit_mb)?;
+    // Lead a process group, so the processes the program starts, such as
+    // those of a shell, can be killed with it
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to start transform {}", program))?;
+    let mut group = ProcessGroup(child.id());
+    let stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let run = async {
+        let (_, (output, first), stderr) = tokio::try_join!(
+            write_rows(stdin, rows),
+            read_rows(stdout, config.max_output_mb * 1024 * 1024, budget),
+            read_stderr(stderr),
+        )?;
+        let status = child.wait().await?;
+        group.release();
+        if !status.success() {
+            bail!(
+                "Transform {} failed with {}: {}",
+                program,
+                status,
+                stderr.trim()
+            );
+        }
+        Ok((output.finish()?, first))
+    };
+    // The program and its process group are killed when `child` and `group`
+    // are dropped
+    let (output, first) = tokio::time::timeout(config.timeout, run)
+        .await
+        .map_err(|_| anyhow!("Transform {} timed out after {:?}", program, config.timeout))??;
+    Ok((output, output_columns(columns, first.as_ref())))
+}
+
+/// Transform the rows of one result set of a multi-query job
+pub async fn transform_vec(
+    config: &TransformConfig,
+    rows: Vec<JobType>,
+    columns: Vec<ResultColumn>,
+) -> Result<(Vec<JobType>, Vec<ResultColumn>)> {
+    let (rows, columns) = transform_rows(config, JobRows::from(rows), columns, None).await?;
+    Ok((rows.into_vec()?, columns))
+}
+
+/// Write rows to the program as JSON lines. Programs may exit without
+/// reading all of them, e.g. to keep only the first rows
+async fn write_rows(mut stdin: ChildStdin, rows: JobRows) -> Result<()> {
+    let written = async {
+        for chunk in rows.into_chunks()? {
+            let mut lines = Vec::new();
+            for row in chunk? {
+                serde_json::to_writer(&mut lines, &row)?;
+                lines.push(b'\n');
+            }
+            stdin.write_all(&lines).await?;
+        }
+        stdin.shutdown().await
+    };
+    match written.await {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Read the rows the program writes, failing once they exceed `limit`
+/// bytes. Also returns the first row, from which new columns are typed
+async fn read_rows(
+    stdout: impl AsyncRead + Unpin,
+    limit: u64,
+    budget: Option<u64>,
+) -> Result<(JobRows, Option<JobType>)> {
+    let mut rows = JobRows::new(budget);
+    let mut first = None;
+    let mut read = 0;
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await? {
+        read += line.len() as u64 + 1;
+        if read > limit {
+            bail!("Transform wrote more than {} bytes", limit);
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row: JobType = serde_json::from_str(&line)
+            .with_context(|| format!("Transform wrote an invalid row at row {}", rows.len() + 1))?;
+        if first.is_none() {
+            first = Some(row.clone());
+        }
+        rows.push(row);
+    }
+    Ok((rows, first))
+}
+
+/// Read the start of the program's stderr, draining the rest so it never
+/// blocks writing to it
+async fn read_stderr(stderr: impl AsyncRead + Unpin) -> Result<String> {
+    let mut message = String::new();
+    let mut stderr = stderr.take(STDERR_LIMIT);
+    stderr.read_to_string(&mut message).await.ok();
+    tokio::io::copy(&mut stderr.into_inner(), &mut tokio::io::sink()).await?;
+    Ok(message)
+}
+
+/// Columns of the rows written by the program: the query's columns still in
+/// `first` in their order, then its new columns by name. The query's
+/// columns are kept as they are when the program wrote no rows
+fn output_columns(columns: Vec<ResultColumn>, first: Option<&JobType>) -> Vec<ResultColumn> {
+    let Some(first) = first else {
+        return columns;
+    };
+    let known: BTreeSet<&str> = columns.iter().map(|column| column.name.as_str()).collect();
+    let mut added: Vec<ResultColumn> = first
+        .iter()
+        .filter(|(name, _)| !known.contains(name.as_str()))
+        .map(|(name, value)| ResultColumn::from_value(name, value))
+        .collect();
+    added.sort_by(|a, b| a.name.cmp(&b.name));
+    columns
+        .into_iter()
+        .filter(|column| first.contains_key(&column.name))
+        .chain(added)
+        .collect()
+}
+
+/// Kills the process group of a program when dropped, so the processes it
+/// started don't outlive it when it times out or the job is cancelled
+struct ProcessGroup(Option<u32>);
+
+impl ProcessGroup {
+    /// Leave the group alone once the program was waited for, as its id may
+    /// be reused then
+    fn release(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(id) = self.0 {
+            // SAFETY: the program leads the group and wasn't waited for, so
+            // the group id still refers to its processes
+            unsafe {
+                libc::kill(-(id as libc::pid_t), libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// Cap the memory the program may allocate. Limits the data segment rather
+/// than the address space, since runtimes such as the JVM reserve large
+/// address ranges they never use
+#[cfg(unix)]
+fn limit_memory(command: &mut Command, limit_mb: u64) -> Result<()> {
+    let limit = (limit_mb * 1024 * 1024) as libc::rlim_t;
+    // SAFETY: setrlimit is async-signal-safe and only affects the child
+    unsafe {
+        command.pre_exec(move || {
+            let rlimit = libc::rlimit {
+                rlim_cur: limit,
+                rlim_max: limit,
+            };
+            if libc::setrlimit(libc::RLIMIT_DATA, &rlimit) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn limit_memory(_command: &mut Command, _limit_mb: u64) -> Result<()> {
+    bail!("Transform programs are only supported on Unix, where their memory can be limited")
+}
@@ -26,6 +26,7 @@ fn create_test_datasource(hosts: Vec<String>) -> DataSource {
         password: "test_password".to_string(),
         timeout: 60,
         filters: None,
+        ..Default::default()
     }
 }
 
@@ -239,7 +240,7 @@ async fn test_agent_run_with_no_tasks() {
     };
 
     // Run with timeout to ensure it completes
-    let _ = timeout(Duration::from_secs(1), run_task)
+    timeout(Duration::from_secs(1), run_task)
         .await
         .expect("Test timed out");
 
@@ -293,7 +294,7 @@ async fn test_job_agent_run_with_no_jobs() {
     };
 
     // Run with timeout to ensure it completes
-    let _ = timeout(Duration::from_secs(1), run_task)
+    timeout(Duration::from_secs(1), run_task)
         .await
         .expect("Test timed out");
 
@@ -360,7 +361,7 @@ async fn test_agent_run_with_unexpected_error() {
     };
 
     // Run with timeout to ensure it completes
-    let _ = timeout(Duration::from_secs(1), run_task)
+    timeout(Duration::from_secs(1), run_task)
         .await
         .expect("Test timed out");
 
@@ -0,0 +1,149 @@
+use mockito::{Matcher, Server};
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::audit::{self, AuditLog, AuditOutcome, AuditRecord};
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind, TaskQuery};
+use tsight_agent::config::{AuditConfig, QueryPolicy, StatementKind};
+use tsight_agent::models::DataSource;
+
+const BODY: &str = concat!("[\"id\"]\n", "[\"UInt64\"]\n", "[1]\n");
+
+fn records(path: &Path) -> Vec<AuditRecord> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_finished_and_dropped_entries() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("audit/audit.jsonl");
+    let log = AuditLog::open(&AuditConfig::new(path.clone())).unwrap();
+
+    log.start("1", "main", "SELECT 1".to_string()).finish(None);
+    log.start("2", "main", "SELECT x".to_string())
+        .finish(Some("Unknown identifier x".to_string()));
+    drop(log.start("3", "main", "SELECT sleep(3)".to_string()));
+
+    let records = records(&path);
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].task_id, "1");
+    assert_eq!(records[0].datasource, "main");
+    assert_eq!(records[0].query, "SELECT 1");
+    assert_eq!(records[0].outcome, AuditOutcome::Succeeded);
+    assert_eq!(records[0].error, None);
+    assert_eq!(records[1].outcome, AuditOutcome::Failed);
+    assert_eq!(records[1].error.as_deref(), Some("Unknown identifier x"));
+    assert_eq!(records[2].outcome, AuditOutcome::Cancelled);
+}
+
+#[test]
+fn test_reopened_log_appends() {
+    let dir = TempDir::new().unwrap();
+    let config = AuditConfig::new(dir.path().join("audit.jsonl"));
+
+    AuditLog::open(&config)
+        .unwrap()
+        .start("1", "main", "SELECT 1".to_string())
+        .finish(None);
+    AuditLog::open(&config)
+        .unwrap()
+        .start("2", "main", "SELECT 2".to_string())
+        .finish(None);
+
+    let task_ids: Vec<_> = records(&config.path)
+        .into_iter()
+        .map(|record| record.task_id)
+        .collect();
+    assert_eq!(task_ids, vec!["1", "2"]);
+}
+
+#[tokio::test]
+async fn test_agent_audits_each_query() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    audit::init(&AuditConfig::new(path.clone())).unwrap();
+
+    let mut clickhouse = Server::new_async().await;
+    clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::Regex("^SET ".to_string()))
+        .create_async()
+        .await;
+    clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::Regex("FROM users".to_string()))
+        .with_body(BODY)
+        .create_async()
+        .await;
+    clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::Regex("FROM orders".to_string()))
+        .with_status(404)
+        .create_async()
+        .await;
+
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "9".to_string(),
+        datasource_name: "main".to_string(),
+        query: String::new(),
+        queries: Some(vec![
+            TaskQuery {
+                name: None,
+                query: "SET max_threads = 1".to_string(),
+                signature: None,
+            },
+            TaskQuery {
+                name: Some("users".to_string()),
+                query: "SELECT id FROM users WHERE email = 'john@example.com'".to_string(),
+                signature: None,
+            },
+            TaskQuery {
+                name: Some("orders".to_string()),
+                query: "SELECT id FROM orders".to_string(),
+                signature: None,
+            },
+        ]),
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+
+        signature: None,
+        time_range: None,
+    });
+    let agent = create_job_agent_with_client(
+        server,
+        vec![DataSource {
+            name: "main".to_string(),
+            hosts: vec![clickhouse.url()],
+            query_policy: QueryPolicy {
+                allowed_statements: vec![StatementKind::Select, StatementKind::Set],
+                ..Default::default()
+            },
+            ..Default::default()
+        }],
+        None,
+    );
+
+    agent.process_next().await.unwrap_err();
+
+    let records = records(&path);
+    assert_eq!(records.len(), 3);
+    assert!(records.iter().all(|record| record.task_id == "9"));
+    assert_eq!(records[0].query, "SET max_threads = 1");
+    assert_eq!(records[0].outcome, AuditOutcome::Succeeded);
+    assert_eq!(records[1].query, "SELECT id FROM users WHERE email = '?'");
+    assert_eq!(records[1].outcome, AuditOutcome::Succeeded);
+    assert_eq!(records[2].outcome, AuditOutcome::Failed);
+    assert!(records[2].error.is_some());
+}
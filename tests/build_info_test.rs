@@ -0,0 +1,58 @@
+use clap::error::ErrorKind;
+use clap::Parser;
+use mockito::{Matcher, Server};
+use tsight_agent::build_info::{build_info, long_version};
+use tsight_agent::cli::Cli;
+use tsight_agent::client::{ServerApi, ServerClient};
+
+#[test]
+fn test_build_info() {
+    let info = build_info();
+
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert!(!info.git_commit.is_empty());
+    assert!(info.build_date == "unknown" || info.build_date.len() == "2025-03-01".len());
+    assert!(!info.target.is_empty());
+    assert!(info.features.iter().all(|feature| !feature.is_empty()));
+    assert!(info.to_string().starts_with(&format!(
+        "{} (commit {}, built {}",
+        info.version, info.git_commit, info.build_date
+    )));
+}
+
+#[test]
+fn test_version_flag_prints_build_metadata() {
+    let error = Cli::try_parse_from(["tsight_agent", "--version"]).unwrap_err();
+
+    assert_eq!(error.kind(), ErrorKind::DisplayVersion);
+    let output = error.to_string();
+    assert!(output.starts_with(&format!("tsight_agent {}", long_version())));
+    assert!(output.contains(&format!("commit: {}", build_info().git_commit)));
+    assert!(output.contains("build date: "));
+    assert!(output.contains("features: "));
+}
+
+#[tokio::test]
+async fn test_registration_includes_build_info() {
+    let mut server = Server::new_async().await;
+    let add_mock = server
+        .mock("POST", "/datasource/main/add")
+        .match_body(Matcher::PartialJson(serde_json::json!({
+            "datasource_type": "clickhouse",
+            "agent": {
+                "version": env!("CARGO_PKG_VERSION"),
+                "git_commit": build_info().git_commit,
+            }
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client
+        .add_datasource("main", "clickhouse", &Default::default())
+        .await
+        .unwrap();
+
+    add_mock.assert();
+}
@@ -0,0 +1,312 @@
+use clap::Parser;
+use mockito::{Matcher, Server};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use tsight_agent::cli::{
+    self, select_datasources, Cli, Command, DatasourceArgs, DiscoverArgs, FiltersCommand,
+    SecretCommand, ServiceCommand,
+};
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::config::{Config, GlobalFilters, SqlFilterRules};
+use tsight_agent::health::CHECK_OK;
+use tsight_agent::models::DataSource;
+use tsight_agent::secrets::keyring::DEFAULT_KEYRING_SERVICE;
+use tsight_agent::service::DEFAULT_SERVICE_NAME;
+
+/// URL nothing listens on
+fn closed_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+fn datasource(name: &str, host: String) -> DataSource {
+    DataSource {
+        name: name.to_string(),
+        hosts: vec![host],
+        ..Default::default()
+    }
+}
+
+fn config(datasources: Vec<DataSource>) -> Config {
+    Config {
+        datasources,
+        ..Default::default()
+    }
+}
+
+fn parse(args: &[&str]) -> Cli {
+    Cli::try_parse_from(std::iter::once("tsight_agent").chain(args.iter().copied())).unwrap()
+}
+
+#[test]
+fn test_run_is_default_command() {
+    assert_eq!(*parse(&[]).command(), Command::Run);
+    assert_eq!(*parse(&["run"]).command(), Command::Run);
+}
+
+#[test]
+fn test_config_option_is_global() {
+    assert_eq!(
+        parse(&["--config", "/etc/a.yaml"]).config,
+        Some(PathBuf::from("/etc/a.yaml"))
+    );
+    assert_eq!(
+        parse(&["validate", "--config=/etc/b.yaml"]).config,
+        Some(PathBuf::from("/etc/b.yaml"))
+    );
+    assert_eq!(parse(&["validate"]).config, None);
+    assert!(Cli::try_parse_from(["tsight_agent", "--config"]).is_err());
+}
+
+#[test]
+fn test_parse_subcommands() {
+    assert_eq!(
+        *parse(&["test-connection", "--datasource", "main"]).command(),
+        Command::TestConnection(DatasourceArgs {
+            datasource: Some("main".to_string())
+        })
+    );
+    assert_eq!(
+        *parse(&["query", "--datasource", "main", "SELECT 1"]).command(),
+        Command::Query {
+            datasource: "main".to_string(),
+            sql: "SELECT 1".to_string(),
+        }
+    );
+    assert_eq!(
+        *parse(&["secret", "set", "pw"]).command(),
+        Command::Secret(SecretCommand::Set {
+            entry: "pw".to_string(),
+            service: DEFAULT_KEYRING_SERVICE.to_string(),
+        })
+    );
+    assert_eq!(
+        *parse(&["secret", "set", "pw", "--service=custom"]).command(),
+        Command::Secret(SecretCommand::Set {
+            entry: "pw".to_string(),
+            service: "custom".to_string(),
+        })
+    );
+    assert_eq!(
+        *parse(&["secret", "encrypt", "--key-file", "agent.key"]).command(),
+        Command::Secret(SecretCommand::Encrypt {
+            key_file: Some(PathBuf::from("agent.key")),
+            keyring_entry: None,
+            service: DEFAULT_KEYRING_SERVICE.to_string(),
+        })
+    );
+    assert!(Cli::try_parse_from([
+        "tsight_agent",
+        "secret",
+        "encrypt",
+        "--key-file",
+        "agent.key",
+        "--keyring-entry",
+        "key"
+    ])
+    .is_err());
+    assert!(matches!(
+        parse(&["filters", "explain", "--value", "a@b.c"]).command(),
+        Command::Filters(FiltersCommand::Explain { value: Some(value), .. }) if value == "a@b.c"
+    ));
+    assert!(Cli::try_parse_from(["tsight_agent", "query", "SELECT 1"]).is_err());
+}
+
+#[test]
+fn test_daemon_flags() {
+    let cli = parse(&["--daemon", "--pid-file", "/run/tsight_agent.pid"]);
+    assert!(cli.daemon);
+    assert_eq!(cli.pid_file, Some(PathBuf::from("/run/tsight_agent.pid")));
+    assert_eq!(*cli.command(), Command::Run);
+
+    let cli = parse(&["run", "--daemon"]);
+    assert!(cli.daemon);
+    assert_eq!(cli.pid_file, None);
+}
+
+#[test]
+fn test_service_commands() {
+    assert_eq!(
+        *parse(&["service", "install"]).command(),
+        Command::Service(ServiceCommand::Install {
+            name: DEFAULT_SERVICE_NAME.to_string(),
+        })
+    );
+    let cli = parse(&[
+        "--config",
+        "C:\\tsight\\config.yaml",
+        "service",
+        "run",
+        "--name",
+        "agent-2",
+    ]);
+    assert_eq!(cli.config, Some(PathBuf::from("C:\\tsight\\config.yaml")));
+    assert_eq!(
+        *cli.command(),
+        Command::Service(ServiceCommand::Run {
+            name: "agent-2".to_string(),
+        })
+    );
+    assert!(Cli::try_parse_from(["tsight_agent", "service"]).is_err());
+}
+
+#[test]
+fn test_discover_prints_unless_submitting() {
+    assert_eq!(
+        *parse(&["discover"]).command(),
+        Command::Discover(DiscoverArgs {
+            datasources: DatasourceArgs { datasource: None },
+            submit: false,
+            dry_run: false,
+        })
+    );
+    assert_eq!(
+        *parse(&["discover", "--datasource", "main", "--submit"]).command(),
+        Command::Discover(DiscoverArgs {
+            datasources: DatasourceArgs {
+                datasource: Some("main".to_string())
+            },
+            submit: true,
+            dry_run: false,
+        })
+    );
+    assert!(Cli::try_parse_from(["tsight_agent", "discover", "--dry-run", "--submit"]).is_err());
+}
+
+#[test]
+fn test_select_datasources() {
+    let config = config(vec![
+        datasource("main", closed_url()),
+        datasource("replica", closed_url()),
+    ]);
+
+    assert_eq!(select_datasources(&config, None).unwrap().len(), 2);
+    assert_eq!(
+        select_datasources(&config, Some("replica")).unwrap()[0].name,
+        "replica"
+    );
+    assert_eq!(
+        select_datasources(&config, Some("missing"))
+            .unwrap_err()
+            .to_string(),
+        "Unknown datasource 'missing'"
+    );
+}
+
+#[test]
+fn test_validate() {
+    assert!(cli::validate(&config(vec![datasource("main", closed_url())])).is_ok());
+
+    let mut no_hosts = datasource("main", closed_url());
+    no_hosts.hosts.clear();
+    assert_eq!(
+        cli::validate(&config(vec![no_hosts]))
+            .unwrap_err()
+            .to_string(),
+        "Datasource main has no hosts"
+    );
+
+    let mut invalid_filters = config(vec![]);
+    invalid_filters.global_filters = Some(GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            column_value_regexes: Some(vec!["(".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    });
+    assert!(cli::validate(&invalid_filters)
+        .unwrap_err()
+        .to_string()
+        .starts_with("Invalid global filters"));
+}
+
+#[tokio::test]
+async fn test_connection_ignores_skipped_datasources() {
+    let mut disabled = datasource("disabled", closed_url());
+    disabled.enabled = false;
+    let config = config(vec![disabled, datasource("down", closed_url())]);
+    let server = FakeServer::new();
+
+    let disabled_only = select_datasources(&config, Some("disabled")).unwrap();
+    let (report, passed) = cli::test_connection(&config, &disabled_only, &server).await;
+    assert!(passed);
+    assert_eq!(report.server.status, CHECK_OK);
+
+    let all = select_datasources(&config, None).unwrap();
+    let (report, passed) = cli::test_connection(&config, &all, &server).await;
+    assert!(!passed);
+    assert!(report.datasources["down"]
+        .status
+        .starts_with("Connection error"));
+}
+
+#[tokio::test]
+async fn test_query_applies_policy_and_filters() {
+    let mut clickhouse = Server::new_async().await;
+    clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .with_body(concat!(
+            "[\"id\",\"email\"]\n",
+            "[\"UInt64\",\"String\"]\n",
+            "[1,\"john@example.com\"]\n",
+            "[2,\"ops@internal\"]\n",
+        ))
+        .create_async()
+        .await;
+    let mut config = config(vec![datasource("main", clickhouse.url())]);
+    config.global_filters = Some(GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            column_value_regexes: Some(vec!["@internal$".to_string()]),
+            action: Some(tsight_agent::config::FilterAction::DropRow),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    });
+    let datasource = &config.datasources[0];
+
+    let rows = cli::query(&config, datasource, "SELECT id, email FROM users")
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["email"], "john@example.com");
+
+    assert!(cli::query(&config, datasource, "DROP TABLE users")
+        .await
+        .unwrap_err()
+        .to_string()
+        .contains("rejected by policy"));
+}
+
+#[tokio::test]
+async fn test_discover_reports_failed_datasources() {
+    let mut disabled = datasource("disabled", closed_url());
+    disabled.enabled = false;
+    let config = config(vec![datasource("down", closed_url()), disabled]);
+    let all = select_datasources(&config, None).unwrap();
+
+    let error = cli::discover(&config, &all, &FakeServer::new())
+        .await
+        .unwrap_err();
+
+    assert_eq!(error.to_string(), "Schema discovery failed for down");
+}
+
+#[tokio::test]
+async fn test_preview_skips_unavailable_datasources() {
+    let mut disabled = datasource("disabled", closed_url());
+    disabled.enabled = false;
+    let config = config(vec![disabled, datasource("down", closed_url())]);
+
+    let disabled_only = select_datasources(&config, Some("disabled")).unwrap();
+    assert!(cli::preview(&config, &disabled_only)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let all = select_datasources(&config, None).unwrap();
+    assert_eq!(
+        cli::preview(&config, &all).await.unwrap_err().to_string(),
+        "Schema discovery failed for down"
+    );
+}
@@ -99,7 +99,7 @@ impl TestContext {
         let table = schemas
             .iter()
             .find(|schema| schema.table == table_name)
-            .expect(&format!("Table {} should be present", table_name));
+            .unwrap_or_else(|| panic!("Table {} should be present", table_name));
 
         if should_exist {
             assert!(
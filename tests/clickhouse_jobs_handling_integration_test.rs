@@ -47,6 +47,7 @@ fn create_test_datasource(hosts: Vec<String>) -> DataSource {
         password: "test_password".to_string(),
         timeout: 60,
         filters: None,
+        ..Default::default()
     }
 }
 
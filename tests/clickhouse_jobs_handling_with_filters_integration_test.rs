@@ -40,6 +40,7 @@ fn create_test_datasource(hosts: Vec<String>) -> DataSource {
         password: "test_password".to_string(),
         timeout: 60,
         filters: None,
+        ..Default::default()
     }
 }
 
@@ -79,14 +80,14 @@ fn mock_submit_results(server: &mut mockito::ServerGuard) -> Mock {
 {
 "records":
 [
-{"status":"user4@example.com","order_name":"Fourth Order","notification_recipient_email":"user4@example.com","cnt":"1"},
-{"notification_recipient_email":"user2@example.com","cnt":"1","status":"processing","order_name":"Second Order"},
-{"cnt":"1","status":"completed","order_name":"Third Order","notification_recipient_email":"user3@example.com"},
-{"notification_recipient_email":"user4@example.com","order_name":"Fourth Order","cnt":"7","status":"cancelled"},
-{"order_name":"First Order","status":"new","notification_recipient_email":"user1@example.com","cnt":"1"},
-{"notification_recipient_email":"user0@example.com","order_name":"First Order","status":"new","cnt":"1"},
-{"notification_recipient_email":"user4@example.com","status":"4222 2222 2222 2","cnt":"1","order_name":"Fourth Order"},
-{"status":"cancelled","cnt":"1","notification_recipient_email":"user3@example.com","order_name":"Third Order"}
+{"status":"user4@example.com","order_name":"Fourth Order","notification_recipient_email":"user4@example.com","cnt":1},
+{"notification_recipient_email":"user2@example.com","cnt":1,"status":"processing","order_name":"Second Order"},
+{"cnt":1,"status":"completed","order_name":"Third Order","notification_recipient_email":"user3@example.com"},
+{"notification_recipient_email":"user4@example.com","order_name":"Fourth Order","cnt":7,"status":"cancelled"},
+{"order_name":"First Order","status":"new","notification_recipient_email":"user1@example.com","cnt":1},
+{"notification_recipient_email":"user0@example.com","order_name":"First Order","status":"new","cnt":1},
+{"notification_recipient_email":"user4@example.com","status":"4222 2222 2222 2","cnt":1,"order_name":"Fourth Order"},
+{"status":"cancelled","cnt":1,"notification_recipient_email":"user3@example.com","order_name":"Third Order"}
 ]
 }
         )))
@@ -1,7 +1,8 @@
 use mockito::{Mock, Server};
-use serde_json::json;
+use serde_json::{json, Value};
 use tsight_agent::{
     agent::Agent,
+    client::manifest::Manifest,
     models::{DataSource, DataSourceType},
 };
 
@@ -47,6 +48,7 @@ fn create_test_datasource(hosts: Vec<String>) -> DataSource {
         password: "test_password".to_string(),
         timeout: 60,
         filters: None,
+        ..Default::default()
     }
 }
 
@@ -125,22 +127,34 @@ fn mock_acquire_error(server: &mut mockito::ServerGuard, status: usize) -> Mock
         .create()
 }
 
+fn expected_records() -> Value {
+    json!([
+        {"t":1738280700000i64,"values":{"cnt":0.016666666666666666}},{"t":1738281060000i64,"values":{"cnt":0.016666666666666666}},
+        {"t":1738281120000i64,"values":{"cnt":0.016666666666666666}},{"t":1738281180000i64,"values":{"cnt":0.016666666666666666}},
+        {"t":1738281240000i64,"values":{"cnt":0.05}},{"t":1738281300000i64,"values":{"cnt":0.016666666666666666}}
+    ])
+}
+
+/// Task submission of `records` along with their manifest and the row
+/// counts of its execution statistics, whose timings vary
+fn submission(records: Value, is_high_priority_queue: bool) -> Value {
+    let manifest = Manifest::of(records.as_array().unwrap()).unwrap();
+    json!({
+        "records": records,
+        "manifest": manifest,
+        "null_stats": {"skipped_rows": 0, "zero_filled_rows": 0},
+        "execution_stats": {"rows_returned": 6, "rows_filtered": 0},
+        "is_high_priority_queue": is_high_priority_queue
+    })
+}
+
 fn mock_submit_results(server: &mut mockito::ServerGuard) -> Mock {
     server
         .mock("POST", format!("/tasks/{}/submit", TEST_TASK_ID).as_str())
-        .match_body(mockito::Matcher::Json(
-            json!(
-                {
-                    "records":
-                    [
-                        {"t":1738280700,"cnt":0.016666666666666666},{"t":1738281060,"cnt":0.016666666666666666},
-                        {"cnt":0.016666666666666666,"t":1738281120},{"cnt":0.016666666666666666,"t":1738281180},
-                        {"cnt":0.05,"t":1738281240},{"cnt":0.016666666666666666,"t":1738281300}
-                    ],
-                    "is_high_priority_queue":false
-                }
-            )
-        ))
+        .match_body(mockito::Matcher::PartialJson(submission(
+            expected_records(),
+            false,
+        )))
         .match_header("Authorization", TEST_BEARER_HEADER)
         .with_status(200)
         .create()
@@ -149,19 +163,10 @@ fn mock_submit_results(server: &mut mockito::ServerGuard) -> Mock {
 fn mock_submit_high_priority_results(server: &mut mockito::ServerGuard) -> Mock {
     server
         .mock("POST", format!("/tasks/{}/submit", TEST_TASK_ID).as_str())
-        .match_body(mockito::Matcher::Json(
-            json!(
-                {
-                    "records":
-                    [
-                        {"t":1738280700,"cnt":0.016666666666666666},{"t":1738281060,"cnt":0.016666666666666666},
-                        {"cnt":0.016666666666666666,"t":1738281120},{"cnt":0.016666666666666666,"t":1738281180},
-                        {"cnt":0.05,"t":1738281240},{"cnt":0.016666666666666666,"t":1738281300}
-                    ],
-                    "is_high_priority_queue":true
-                }
-            )
-        ))
+        .match_body(mockito::Matcher::PartialJson(submission(
+            expected_records(),
+            true,
+        )))
         .match_header("Authorization", TEST_BEARER_HEADER)
         .with_status(200)
         .create()
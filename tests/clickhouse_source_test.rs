@@ -1,7 +1,7 @@
 use anyhow::Result;
 use std::path::PathBuf;
 use tsight_agent::config::Config;
-use tsight_agent::executors::base::{QueryError, QueryExecutor};
+use tsight_agent::executors::base::{DiscoveryOptions, QueryError, QueryExecutor};
 use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
 
 // Helper function to create a test executor
@@ -30,7 +30,7 @@ async fn test_execute_ts() -> Result<()> {
     // Verify the structure of the results
     for record in &result {
         assert!(record.t > 0);
-        assert!(record.cnt > 0.0);
+        assert!(record.values["cnt"] > 0.0);
     }
 
     Ok(())
@@ -216,3 +216,64 @@ async fn test_card_numbers_table() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_discover_numeric_column_stats() -> Result<()> {
+    let executor = create_test_executor().await;
+
+    let schemas = executor.discover_schemas().await?;
+    let orders = schemas
+        .iter()
+        .find(|s| s.database == "test_db" && s.table == "orders")
+        .expect("test_db.orders table not found in schema discovery");
+
+    let id = orders.columns.get("id").unwrap();
+    let (min, max, avg) = (id.min.unwrap(), id.max.unwrap(), id.avg.unwrap());
+    assert!(min <= avg && avg <= max);
+    assert_eq!(id.null_ratio, Some(0.0));
+
+    // Statistics are only collected for numeric columns
+    let status = orders.columns.get("status").unwrap();
+    assert_eq!(status.min, None);
+    assert_eq!(status.null_ratio, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_discover_time_column_freshness() -> Result<()> {
+    let executor = create_test_executor().await;
+
+    let schemas = executor.discover_schemas().await?;
+    let orders = schemas
+        .iter()
+        .find(|s| s.database == "test_db" && s.table == "orders")
+        .expect("test_db.orders table not found in schema discovery");
+
+    assert!(orders.freshness.contains_key("created_at"));
+    assert!(!orders.freshness.contains_key("id"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_discover_sample_values() -> Result<()> {
+    let executor = create_test_executor().await;
+
+    let options = DiscoveryOptions {
+        sample_values: Some(2),
+        ..Default::default()
+    };
+    let schemas = executor.discover_schemas_with(&options).await?;
+    let orders = schemas
+        .iter()
+        .find(|s| s.database == "test_db" && s.table == "orders")
+        .expect("test_db.orders table not found in schema discovery");
+
+    let status = orders.columns.get("status").unwrap();
+    assert!(!status.sample_values.is_empty());
+    assert!(status.sample_values.len() <= 2);
+    assert!(orders.columns.get("id").unwrap().sample_values.is_empty());
+
+    Ok(())
+}
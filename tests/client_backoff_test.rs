@@ -0,0 +1,71 @@
+use mockito::Server;
+use std::time::Duration;
+use tsight_agent::agent::factory::create_job_agent;
+use tsight_agent::client::{BackoffRequested, ServerApi, ServerClient};
+
+#[tokio::test]
+async fn test_too_many_requests_with_retry_after_seconds() {
+    let mut server = Server::new_async().await;
+    let acquire_mock = server
+        .mock("POST", "/tasks/acquire")
+        .with_status(429)
+        .with_header("Retry-After", "5")
+        .create();
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    let error = client.acquire_next_query(false).await.unwrap_err();
+
+    let backoff = error.downcast_ref::<BackoffRequested>().unwrap();
+    assert_eq!(backoff.retry_after, Duration::from_secs(5));
+    acquire_mock.assert();
+}
+
+#[tokio::test]
+async fn test_maintenance_with_retry_after_date() {
+    let mut server = Server::new_async().await;
+    let retry_at = chrono::Utc::now() + chrono::Duration::seconds(120);
+    let acquire_mock = server
+        .mock("POST", "/jobs/acquire")
+        .with_status(503)
+        .with_header("Retry-After", &retry_at.to_rfc2822())
+        .create();
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    let error = client.acquire_next_job().await.unwrap_err();
+
+    let backoff = error.downcast_ref::<BackoffRequested>().unwrap();
+    assert!(backoff.retry_after > Duration::from_secs(100));
+    assert!(backoff.retry_after <= Duration::from_secs(120));
+    acquire_mock.assert();
+}
+
+#[tokio::test]
+async fn test_maintenance_without_retry_after_uses_default() {
+    let mut server = Server::new_async().await;
+    let _acquire_mock = server
+        .mock("POST", "/jobs/acquire")
+        .with_status(503)
+        .create();
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    let error = client.acquire_next_job().await.unwrap_err();
+
+    let backoff = error.downcast_ref::<BackoffRequested>().unwrap();
+    assert_eq!(backoff.retry_after, Duration::from_secs(30));
+}
+
+#[tokio::test]
+async fn test_agent_preserves_backoff_error() {
+    let mut server = Server::new_async().await;
+    let _acquire_mock = server
+        .mock("POST", "/jobs/acquire")
+        .with_status(429)
+        .with_header("Retry-After", "7")
+        .create();
+
+    let agent = create_job_agent("test-api-key".to_string(), server.url(), vec![], None);
+    let error = agent.process_next().await.unwrap_err();
+
+    let backoff = error.downcast_ref::<BackoffRequested>().unwrap();
+    assert_eq!(backoff.retry_after, Duration::from_secs(7));
+}
@@ -0,0 +1,48 @@
+use mockito::Server;
+use std::collections::HashMap;
+use tsight_agent::client::{ServerApi, ServerClient};
+use tsight_agent::config::ServerConfig;
+
+fn create_gateway_config(server_url: &str, path_prefix: &str) -> ServerConfig {
+    ServerConfig {
+        api_key: "test-api-key".to_string(),
+        server_url: server_url.to_string(),
+        path_prefix: Some(path_prefix.to_string()),
+        extra_headers: Some(HashMap::from([
+            ("X-Org-Id".to_string(), "42".to_string()),
+            ("Invalid Header".to_string(), "skipped".to_string()),
+        ])),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_path_prefix_and_extra_headers() {
+    let mut server = Server::new_async().await;
+    let acquire_mock = server
+        .mock("POST", "/tsight/api/jobs/acquire")
+        .match_header("X-Org-Id", "42")
+        .match_header("Authorization", "Bearer test-api-key")
+        .with_status(404)
+        .create();
+
+    let client = ServerClient::from_config(&create_gateway_config(&server.url(), "/tsight/api"));
+    let _ = client.acquire_next_job().await;
+
+    acquire_mock.assert();
+}
+
+#[tokio::test]
+async fn test_path_prefix_slashes_are_normalized() {
+    let mut server = Server::new_async().await;
+    let submit_mock = server
+        .mock("POST", "/tsight/api/tasks/7/submit")
+        .with_status(200)
+        .create();
+
+    let server_url = format!("{}/", server.url());
+    let client = ServerClient::from_config(&create_gateway_config(&server_url, "tsight/api/"));
+    client.submit_error("7", "boom", false).await.unwrap();
+
+    submit_mock.assert();
+}
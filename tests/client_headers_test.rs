@@ -0,0 +1,62 @@
+use mockito::{Matcher, Server};
+use tsight_agent::client::{ServerApi, ServerClient, PROTOCOL_VERSION, PROTOCOL_VERSION_HEADER};
+use tsight_agent::executors::base::ExecutionStats;
+use tsight_agent::filters::FilterStats;
+
+#[tokio::test]
+async fn test_requests_carry_user_agent_and_protocol_version() {
+    let mut server = Server::new_async().await;
+    let acquire_mock = server
+        .mock("POST", "/jobs/acquire")
+        .match_header(
+            "User-Agent",
+            Matcher::Regex(r"^tsight-agent/\d+\.\d+\.\d+ \(\w+/\w+\)$".to_string()),
+        )
+        .match_header(PROTOCOL_VERSION_HEADER, PROTOCOL_VERSION)
+        .with_status(404)
+        .create();
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    let _ = client.acquire_next_job().await;
+
+    acquire_mock.assert();
+}
+
+#[tokio::test]
+async fn test_upgrade_required_response() {
+    let mut server = Server::new_async().await;
+    let acquire_mock = server
+        .mock("POST", "/tasks/acquire")
+        .with_status(426)
+        .create();
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    let error = client.acquire_next_query(false).await.unwrap_err();
+
+    assert!(error.to_string().contains("upgrade the agent"), "{}", error);
+    acquire_mock.assert();
+}
+
+#[tokio::test]
+async fn test_conflict_response_reported_as_upgrade_required() {
+    let mut server = Server::new_async().await;
+    let submit_mock = server
+        .mock("POST", "/jobs/1/submit")
+        .with_status(409)
+        .create();
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    let error = client
+        .submit_job_results(
+            "1",
+            vec![],
+            vec![],
+            FilterStats::default(),
+            ExecutionStats::default(),
+        )
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("protocol version"), "{}", error);
+    submit_mock.assert();
+}
@@ -0,0 +1,121 @@
+use mockito::{Matcher, Server};
+use serde_json::json;
+use tsight_agent::client::{ServerApi, ServerClient};
+use tsight_agent::config::{AuthConfig, ServerConfig};
+
+fn create_oauth_config(server_url: &str) -> ServerConfig {
+    ServerConfig {
+        server_url: server_url.to_string(),
+        auth: Some(AuthConfig {
+            token_url: format!("{}/oauth/token", server_url),
+            client_id: "agent".to_string(),
+            client_secret: "secret".to_string(),
+            scope: Some("agent".to_string()),
+            refresh_margin_secs: 30,
+        }),
+        ..Default::default()
+    }
+}
+
+fn mock_token(server: &mut mockito::ServerGuard, token: &str, expires_in: u64) -> mockito::Mock {
+    server
+        .mock("POST", "/oauth/token")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("grant_type".into(), "client_credentials".into()),
+            Matcher::UrlEncoded("client_id".into(), "agent".into()),
+            Matcher::UrlEncoded("client_secret".into(), "secret".into()),
+            Matcher::UrlEncoded("scope".into(), "agent".into()),
+        ]))
+        .with_status(200)
+        .with_body(
+            json!({"access_token": token, "token_type": "Bearer", "expires_in": expires_in})
+                .to_string(),
+        )
+}
+
+#[tokio::test]
+async fn test_oauth_token_is_cached_between_requests() {
+    let mut server = Server::new_async().await;
+    let token_mock = mock_token(&mut server, "oauth-token", 3600)
+        .expect(1)
+        .create();
+    let acquire_mock = server
+        .mock("POST", "/jobs/acquire")
+        .match_header("Authorization", "Bearer oauth-token")
+        .with_status(404)
+        .expect(2)
+        .create();
+
+    let client = ServerClient::from_config(&create_oauth_config(&server.url()));
+
+    for _ in 0..2 {
+        let result = client.acquire_next_job().await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("No jobs available"));
+    }
+
+    token_mock.assert();
+    acquire_mock.assert();
+}
+
+#[tokio::test]
+async fn test_oauth_token_refreshed_before_expiry() {
+    let mut server = Server::new_async().await;
+    // expires_in is within the refresh margin, so every request refreshes the token
+    let token_mock = mock_token(&mut server, "short-lived", 10)
+        .expect(2)
+        .create();
+    let acquire_mock = server
+        .mock("POST", "/jobs/acquire")
+        .match_header("Authorization", "Bearer short-lived")
+        .with_status(404)
+        .expect(2)
+        .create();
+
+    let client = ServerClient::from_config(&create_oauth_config(&server.url()));
+
+    for _ in 0..2 {
+        let _ = client.acquire_next_job().await;
+    }
+
+    token_mock.assert();
+    acquire_mock.assert();
+}
+
+#[tokio::test]
+async fn test_oauth_token_endpoint_failure() {
+    let mut server = Server::new_async().await;
+    let token_mock = server
+        .mock("POST", "/oauth/token")
+        .with_status(401)
+        .create();
+
+    let client = ServerClient::from_config(&create_oauth_config(&server.url()));
+    let result = client.acquire_next_job().await;
+
+    let error = format!("{:#}", result.unwrap_err());
+    assert!(error.contains("Failed to obtain access token"), "{}", error);
+    token_mock.assert();
+}
+
+#[tokio::test]
+async fn test_unauthorized_response_invalidates_token() {
+    let mut server = Server::new_async().await;
+    let token_mock = mock_token(&mut server, "revoked", 3600).expect(2).create();
+    let acquire_mock = server
+        .mock("POST", "/jobs/acquire")
+        .with_status(401)
+        .expect(2)
+        .create();
+
+    let client = ServerClient::from_config(&create_oauth_config(&server.url()));
+
+    for _ in 0..2 {
+        assert!(client.acquire_next_job().await.is_err());
+    }
+
+    token_mock.assert();
+    acquire_mock.assert();
+}
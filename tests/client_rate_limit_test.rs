@@ -0,0 +1,59 @@
+use mockito::Server;
+use std::time::{Duration, Instant};
+use tsight_agent::client::rate_limit::RateLimiter;
+use tsight_agent::client::{ServerApi, ServerClient};
+use tsight_agent::config::{RateLimitConfig, ServerConfig};
+
+#[tokio::test]
+async fn test_rate_limiter_allows_burst() {
+    let limiter = RateLimiter::new(1.0, 5);
+
+    let started = Instant::now();
+    for _ in 0..5 {
+        limiter.acquire().await;
+    }
+
+    assert!(started.elapsed() < Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn test_rate_limiter_delays_requests_over_limit() {
+    let limiter = RateLimiter::new(20.0, 1);
+
+    let started = Instant::now();
+    for _ in 0..5 {
+        limiter.acquire().await;
+    }
+
+    // First request uses the burst token, the remaining four wait 50ms each
+    assert!(started.elapsed() >= Duration::from_millis(190));
+}
+
+#[tokio::test]
+async fn test_rate_limit_shared_between_client_clones() {
+    let mut server = Server::new_async().await;
+    let acquire_mock = server
+        .mock("POST", "/jobs/acquire")
+        .with_status(404)
+        .expect(4)
+        .create();
+
+    let client = ServerClient::from_config(&ServerConfig {
+        api_key: "test-api-key".to_string(),
+        server_url: server.url(),
+        rate_limit: Some(RateLimitConfig {
+            requests_per_second: 10.0,
+            burst: Some(1),
+        }),
+        ..Default::default()
+    });
+    let clones = [client.clone(), client.clone(), client.clone(), client];
+
+    let started = Instant::now();
+    for client in &clones {
+        let _ = client.acquire_next_job().await;
+    }
+
+    assert!(started.elapsed() >= Duration::from_millis(290));
+    acquire_mock.assert();
+}
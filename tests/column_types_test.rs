@@ -0,0 +1,87 @@
+use tsight_agent::executors::clickhouse_source::ColumnInfo;
+
+#[test]
+fn test_enum_variants_are_reported() {
+    let info = ColumnInfo::from_clickhouse_type("Enum8('new' = 1, 'paid' = 2, 'it\\'s' = 3)");
+
+    assert_eq!(info.type_name, "enum");
+    assert_eq!(info.enum_values, ["new", "paid", "it's"]);
+    assert!(!info.low_cardinality);
+
+    let info = ColumnInfo::from_clickhouse_type("Enum16('a' = -1000, 'b, c' = 1000)");
+    assert_eq!(info.enum_values, ["a", "b, c"]);
+}
+
+#[test]
+fn test_low_cardinality_is_flagged() {
+    let info = ColumnInfo::from_clickhouse_type("LowCardinality(String)");
+
+    assert_eq!(info.type_name, "string");
+    assert!(info.low_cardinality);
+    assert!(info.enum_values.is_empty());
+}
+
+#[test]
+fn test_plain_types_are_unchanged() {
+    for (ch_type, type_name) in [
+        ("UInt64", "int"),
+        ("Float32", "float"),
+        ("Bool", "bool"),
+        ("Date", "date"),
+        ("DateTime64(3)", "datetime"),
+        ("String", "string"),
+    ] {
+        let info = ColumnInfo::from_clickhouse_type(ch_type);
+        assert_eq!(info.type_name, type_name);
+        assert!(!info.low_cardinality);
+        assert!(info.enum_values.is_empty());
+    }
+}
+
+#[test]
+fn test_nullable_is_unwrapped_and_flagged() {
+    let info = ColumnInfo::from_clickhouse_type("Nullable(Int64)");
+    assert_eq!(info.type_name, "int");
+    assert!(info.nullable);
+
+    let info = ColumnInfo::from_clickhouse_type("LowCardinality(Nullable(String))");
+    assert_eq!(info.type_name, "string");
+    assert!(info.nullable);
+    assert!(info.low_cardinality);
+
+    assert!(!ColumnInfo::from_clickhouse_type("Int64").nullable);
+}
+
+#[test]
+fn test_containers_are_described_structurally() {
+    for (ch_type, type_name) in [
+        ("Array(String)", "array<string>"),
+        ("Array(Nullable(Int64))", "array<int>"),
+        ("Array(Array(Float64))", "array<array<float>>"),
+        ("Map(String, UInt64)", "map<string,int>"),
+        (
+            "Map(LowCardinality(String), Array(DateTime))",
+            "map<string,array<datetime>>",
+        ),
+        ("Tuple(Int32, String)", "tuple<int,string>"),
+        (
+            "Tuple(id UInt64, at DateTime64(3, 'UTC'))",
+            "tuple<int,datetime>",
+        ),
+        ("Nullable(Date32)", "date"),
+    ] {
+        assert_eq!(
+            ColumnInfo::from_clickhouse_type(ch_type).type_name,
+            type_name
+        );
+    }
+}
+
+#[test]
+fn test_nullable_enum_keeps_variants() {
+    let info = ColumnInfo::from_clickhouse_type("Nullable(Enum8('a' = 1, 'b' = 2))");
+
+    assert_eq!(info.type_name, "enum");
+    assert_eq!(info.enum_values, ["a", "b"]);
+    assert!(info.nullable);
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tsight_agent::config::{Config, LogFormat, RotationPeriod};
+use tsight_agent::models::DataSourceType;
+
+fn from_vars(vars: &[(&str, &str)]) -> Result<Option<Config>, config::ConfigError> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    Config::from_env_lookup(|name| vars.get(name).cloned())
+}
+
+#[test]
+fn test_no_server_url_means_no_env_config() {
+    let config = from_vars(&[("TSIGHT_DATASOURCE_0_TYPE", "clickhouse")]).unwrap();
+    assert!(config.is_none());
+}
+
+#[test]
+fn test_server_and_datasource_from_env() {
+    let config = from_vars(&[
+        ("TSIGHT_SERVER_URL", "https://api.tsight.app"),
+        ("TSIGHT_API_KEY", "env-key"),
+        ("TSIGHT_DATASOURCE_0_TYPE", "clickhouse"),
+        ("TSIGHT_DATASOURCE_0_NAME", "main"),
+        (
+            "TSIGHT_DATASOURCE_0_HOSTS",
+            "http://ch1:8123, http://ch2:8123",
+        ),
+        ("TSIGHT_DATASOURCE_0_USERNAME", "reader"),
+        ("TSIGHT_DATASOURCE_0_PASSWORD", "vault:kv/data/ch#password"),
+        ("TSIGHT_DATASOURCE_0_TIMEOUT", "30"),
+    ])
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(config.server.server_url, "https://api.tsight.app");
+    assert_eq!(config.server.api_key, "env-key");
+    assert_eq!(config.datasources.len(), 1);
+
+    let datasource = &config.datasources[0];
+    assert_eq!(datasource.name, "main");
+    assert_eq!(datasource.source_type, DataSourceType::Clickhouse);
+    assert_eq!(datasource.hosts, ["http://ch1:8123", "http://ch2:8123"]);
+    assert_eq!(datasource.username, "reader");
+    assert_eq!(datasource.password, "vault:kv/data/ch#password");
+    assert_eq!(datasource.timeout, 30);
+}
+
+#[test]
+fn test_datasources_are_read_until_first_gap() {
+    let config = from_vars(&[
+        ("TSIGHT_SERVER_URL", "https://api.tsight.app"),
+        ("TSIGHT_DATASOURCE_0_TYPE", "clickhouse"),
+        ("TSIGHT_DATASOURCE_0_HOSTS", "http://ch1:8123"),
+        ("TSIGHT_DATASOURCE_1_TYPE", "clickhouse"),
+        ("TSIGHT_DATASOURCE_1_HOSTS", "http://ch2:8123"),
+        ("TSIGHT_DATASOURCE_3_TYPE", "clickhouse"),
+        ("TSIGHT_DATASOURCE_3_HOSTS", "http://ch3:8123"),
+    ])
+    .unwrap()
+    .unwrap();
+
+    let names: Vec<_> = config
+        .datasources
+        .iter()
+        .map(|ds| ds.name.as_str())
+        .collect();
+    assert_eq!(names, ["datasource_0", "datasource_1"]);
+    assert_eq!(config.datasources[0].timeout, 60);
+}
+
+#[test]
+fn test_invalid_datasource_variables() {
+    let base = [
+        ("TSIGHT_SERVER_URL", "https://api.tsight.app"),
+        ("TSIGHT_DATASOURCE_0_HOSTS", "http://ch1:8123"),
+    ];
+
+    let error = from_vars(&[base[0], base[1], ("TSIGHT_DATASOURCE_0_TYPE", "oracle")])
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("TSIGHT_DATASOURCE_0_TYPE"), "{}", error);
+
+    let error = from_vars(&[base[0], ("TSIGHT_DATASOURCE_0_TYPE", "clickhouse")])
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("TSIGHT_DATASOURCE_0_HOSTS"), "{}", error);
+
+    let error = from_vars(&[
+        base[0],
+        base[1],
+        ("TSIGHT_DATASOURCE_0_TYPE", "clickhouse"),
+        ("TSIGHT_DATASOURCE_0_TIMEOUT", "soon"),
+    ])
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("TSIGHT_DATASOURCE_0_TIMEOUT"), "{}", error);
+}
+
+#[test]
+fn test_listener_from_env() {
+    let config = from_vars(&[("TSIGHT_SERVER_URL", "https://api.tsight.app")])
+        .unwrap()
+        .unwrap();
+    assert!(config.listener.is_none());
+
+    let config = from_vars(&[
+        ("TSIGHT_SERVER_URL", "https://api.tsight.app"),
+        ("TSIGHT_LISTEN_ADDRESS", "0.0.0.0:9464"),
+    ])
+    .unwrap()
+    .unwrap();
+    assert_eq!(config.listener.unwrap().address, "0.0.0.0:9464");
+}
+
+#[test]
+fn test_audit_log_from_env() {
+    let config = from_vars(&[
+        ("TSIGHT_SERVER_URL", "https://api.tsight.app"),
+        ("TSIGHT_AUDIT_LOG", "/var/log/tsight/audit.jsonl"),
+    ])
+    .unwrap()
+    .unwrap();
+    let audit = config.audit.unwrap();
+    assert_eq!(audit.path, PathBuf::from("/var/log/tsight/audit.jsonl"));
+    assert_eq!(audit.max_size_mb, 100);
+    assert_eq!(audit.max_files, 10);
+}
+
+#[test]
+fn test_log_file_from_env() {
+    let config = from_vars(&[
+        ("TSIGHT_SERVER_URL", "https://api.tsight.app"),
+        ("TSIGHT_LOG_FILE", "/var/log/tsight/agent.log"),
+    ])
+    .unwrap()
+    .unwrap();
+    let logging = config.logging.unwrap();
+    assert_eq!(logging.format, LogFormat::Text);
+    let file = logging.file.unwrap();
+    assert_eq!(file.path, PathBuf::from("/var/log/tsight/agent.log"));
+    assert_eq!(file.rotation, RotationPeriod::Never);
+    assert_eq!(file.max_files, 7);
+}
+
+#[test]
+fn test_log_format_from_env() {
+    let config = from_vars(&[
+        ("TSIGHT_SERVER_URL", "https://api.tsight.app"),
+        ("TSIGHT_LOG_FORMAT", "json"),
+    ])
+    .unwrap()
+    .unwrap();
+    assert_eq!(config.logging.unwrap().format, LogFormat::Json);
+
+    let result = from_vars(&[
+        ("TSIGHT_SERVER_URL", "https://api.tsight.app"),
+        ("TSIGHT_LOG_FORMAT", "xml"),
+    ]);
+    assert!(result.is_err());
+}
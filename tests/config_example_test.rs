@@ -0,0 +1,112 @@
+use regex::Regex;
+use std::fs;
+use tempfile::TempDir;
+use tsight_agent::cli;
+use tsight_agent::config::example::example_config;
+use tsight_agent::config::{Config, FilterAction};
+use tsight_agent::models::DataSourceType;
+
+const SOURCE_TYPES: [DataSourceType; 5] = [
+    DataSourceType::Clickhouse,
+    DataSourceType::PostgreSQL,
+    DataSourceType::MySQL,
+    DataSourceType::Prometheus,
+    DataSourceType::Mock,
+];
+
+fn load(dir: &TempDir, content: &str) -> Result<Config, config::ConfigError> {
+    let config_path = dir.path().join("config.yaml");
+    fs::write(&config_path, content).unwrap();
+    Config::load(&config_path)
+}
+
+/// Uncomment the optional settings of the example, keeping comments that
+/// describe them
+fn uncomment_settings(example: &str) -> String {
+    let setting = Regex::new(r#"^(\s*)# (\s*(?:- |[A-Za-z_"][\w."-]*:).*)$"#).unwrap();
+    example
+        .lines()
+        .map(|line| setting.replace(line, "$1$2").into_owned())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Drop a setting of the example and the lines nested under it
+fn without_setting(example: &str, key: &str) -> String {
+    let mut indent = None;
+    example
+        .lines()
+        .filter(|line| {
+            let depth = line.len() - line.trim_start().len();
+            if indent.is_some_and(|indent| depth > indent) {
+                return false;
+            }
+            indent = line
+                .trim_start()
+                .starts_with(&format!("{}:", key))
+                .then_some(depth);
+            indent.is_none()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn test_example_loads_for_every_source_type() {
+    for source_type in SOURCE_TYPES {
+        let dir = TempDir::new().unwrap();
+        let config = load(&dir, &example_config(&source_type)).unwrap();
+
+        assert_eq!(config.datasources.len(), 1);
+        let datasource = &config.datasources[0];
+        assert_eq!(datasource.source_type, source_type);
+        assert!(datasource.preset_filters.is_some());
+        assert!(cli::validate(&config).is_ok());
+    }
+}
+
+#[test]
+fn test_example_shows_filter_actions() {
+    let dir = TempDir::new().unwrap();
+    let config = load(&dir, &example_config(&DataSourceType::Clickhouse)).unwrap();
+
+    let exclude = config.global_filters.unwrap().sql_filters_exclude.unwrap();
+    assert_eq!(exclude[1].action, Some(FilterAction::RedactValue));
+    assert!(config
+        .filter_presets
+        .unwrap()
+        .contains_key("internal_users"));
+}
+
+#[test]
+fn test_commented_settings_are_valid() {
+    for source_type in SOURCE_TYPES {
+        let dir = TempDir::new().unwrap();
+        let mut example = uncomment_settings(&example_config(&source_type));
+        // Transform programs only load in builds opting into them
+        if !cfg!(feature = "transform-programs") {
+            example = without_setting(&example, "transform");
+        }
+        let config = load(&dir, &example).unwrap();
+
+        assert!(config.listener.is_some());
+        assert!(config.slow_query.is_some());
+        assert!(config.server.auth.is_some());
+        // The mock datasource only shows its canned results
+        if source_type != DataSourceType::Mock {
+            assert!(config.datasources[0].maintenance_windows.is_some());
+        }
+    }
+}
+
+#[test]
+fn test_checked_in_example_is_up_to_date() {
+    let checked_in =
+        fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/config_example.yaml")).unwrap();
+
+    assert_eq!(
+        checked_in,
+        example_config(&DataSourceType::Clickhouse),
+        "regenerate it with `tsight_agent config example > config_example.yaml`"
+    );
+}
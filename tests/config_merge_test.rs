@@ -0,0 +1,106 @@
+use std::fs;
+use tempfile::TempDir;
+use tsight_agent::config::Config;
+
+const MAIN_CONFIG: &str = r#"
+server:
+  api_key: test-api-key
+  server_url: http://localhost:8080
+datasources:
+  - name: main_clickhouse
+    source_type: clickhouse
+    hosts: ["http://localhost:8123"]
+    username: default
+    password: ""
+global_filters:
+  sql_filters_exclude:
+    - database_regexes: ["^test_"]
+"#;
+
+fn write_fragment(dir: &TempDir, name: &str, content: &str) {
+    let fragments_dir = dir.path().join("config.d");
+    fs::create_dir_all(&fragments_dir).unwrap();
+    fs::write(fragments_dir.join(name), content).unwrap();
+}
+
+fn load(dir: &TempDir) -> Result<Config, config::ConfigError> {
+    let config_path = dir.path().join("config.yaml");
+    fs::write(&config_path, MAIN_CONFIG).unwrap();
+    Config::load(&config_path)
+}
+
+#[test]
+fn test_load_without_fragments() {
+    let dir = TempDir::new().unwrap();
+    let config = load(&dir).unwrap();
+
+    assert_eq!(config.datasources.len(), 1);
+}
+
+#[test]
+fn test_fragments_are_merged_in_name_order() {
+    let dir = TempDir::new().unwrap();
+    write_fragment(
+        &dir,
+        "20-payments.yaml",
+        r#"
+datasources:
+  - name: payments
+    source_type: clickhouse
+    hosts: ["http://payments:8123"]
+    username: reader
+    password: ""
+global_filters:
+  sql_filters_exclude:
+    - column_name_regexes: ["card"]
+  sql_filters_allow:
+    - database_regexes: ["^payments$"]
+"#,
+    );
+    write_fragment(
+        &dir,
+        "10-analytics.yml",
+        r#"
+datasources:
+  - name: analytics
+    source_type: clickhouse
+    hosts: ["http://analytics:8123"]
+    username: reader
+    password: ""
+"#,
+    );
+    write_fragment(&dir, "README.txt", "not a config");
+
+    let config = load(&dir).unwrap();
+
+    let names: Vec<&str> = config
+        .datasources
+        .iter()
+        .map(|ds| ds.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["main_clickhouse", "analytics", "payments"]);
+
+    let filters = config.global_filters.unwrap();
+    assert_eq!(filters.sql_filters_exclude.unwrap().len(), 2);
+    assert_eq!(filters.sql_filters_allow.unwrap().len(), 1);
+}
+
+#[test]
+fn test_duplicate_datasource_in_fragment() {
+    let dir = TempDir::new().unwrap();
+    write_fragment(
+        &dir,
+        "dup.yaml",
+        r#"
+datasources:
+  - name: main_clickhouse
+    source_type: clickhouse
+    hosts: ["http://other:8123"]
+    username: reader
+    password: ""
+"#,
+    );
+
+    let error = load(&dir).unwrap_err();
+    assert!(error.to_string().contains("duplicate datasource name"));
+}
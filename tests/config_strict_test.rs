@@ -0,0 +1,117 @@
+use std::fs;
+use tempfile::TempDir;
+use tsight_agent::config::Config;
+
+fn load(dir: &TempDir, content: &str) -> Result<Config, config::ConfigError> {
+    let config_path = dir.path().join("config.yaml");
+    fs::write(&config_path, content).unwrap();
+    Config::load(&config_path)
+}
+
+const CONFIG_WITH_TYPO: &str = r#"server:
+  api_key: test-api-key
+  server_url: http://localhost:8080
+datasources:
+  - name: main_clickhouse
+    source_type: clickhouse
+    hosts: ["http://localhost:8123"]
+    username: default
+    password: ""
+    sql_filter_exclude:
+      - column_name_regexes: ["email"]
+"#;
+
+#[test]
+fn test_unknown_key_is_rejected_with_location() {
+    let dir = TempDir::new().unwrap();
+    let error = load(&dir, CONFIG_WITH_TYPO).unwrap_err().to_string();
+
+    assert!(
+        error.contains("config.yaml:10: unknown key 'datasources.0.sql_filter_exclude'"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_nested_unknown_key_is_rejected() {
+    let dir = TempDir::new().unwrap();
+    let content = r#"server:
+  api_key: test-api-key
+  server_url: http://localhost:8080
+datasources: []
+global_filters:
+  sql_filters_exclude:
+    - database_regexes: ["^test_"]
+      tables_regexes: ["^tmp_"]
+"#;
+    let error = load(&dir, content).unwrap_err().to_string();
+
+    assert!(
+        error.contains(
+            "config.yaml:8: unknown key 'global_filters.sql_filters_exclude.0.tables_regexes'"
+        ),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_unknown_key_in_fragment_is_rejected() {
+    let dir = TempDir::new().unwrap();
+    let fragments_dir = dir.path().join("config.d");
+    fs::create_dir_all(&fragments_dir).unwrap();
+    fs::write(
+        fragments_dir.join("10-extra.yaml"),
+        "datasource:\n  - name: typo\n",
+    )
+    .unwrap();
+
+    let error = load(
+        &dir,
+        "server:\n  server_url: http://localhost:8080\ndatasources: []\n",
+    )
+    .unwrap_err()
+    .to_string();
+
+    assert!(
+        error.contains("10-extra.yaml:1: unknown key 'datasource'"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_strict_mode_can_be_disabled() {
+    let dir = TempDir::new().unwrap();
+    let config = load(&dir, &format!("strict: false\n{}", CONFIG_WITH_TYPO)).unwrap();
+
+    assert_eq!(config.datasources.len(), 1);
+    assert!(config.datasources[0].sql_filters_exclude.is_none());
+}
+
+#[test]
+fn test_known_keys_are_accepted() {
+    let dir = TempDir::new().unwrap();
+    let content = r#"server:
+  api_key: test-api-key
+  server_url: http://localhost:8080
+  extra_headers:
+    X-Org-Id: "42"
+filter_presets:
+  custom:
+    sql_filters_exclude:
+      - table_regexes: ["^tmp_"]
+datasources:
+  - name: main_clickhouse
+    source_type: clickhouse
+    hosts: ["http://localhost:8123"]
+    username: default
+    password: ""
+    filter_presets: ["custom"]
+    discovery:
+      interval: 6h
+"#;
+
+    assert!(load(&dir, content).is_ok());
+}
@@ -0,0 +1,132 @@
+use mockito::{Matcher, Server};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{DatasourceStatus, ServerApi, ServerClient};
+use tsight_agent::health::{spawn_connectivity_probe, ConnectivityProbe};
+use tsight_agent::models::DataSource;
+
+/// ClickHouse the datasource tests run against
+const CLICKHOUSE_URL: &str = "http://localhost:8123";
+
+/// URL nothing listens on
+fn closed_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+fn datasource(host: String) -> DataSource {
+    DataSource {
+        name: "main".to_string(),
+        hosts: vec![host],
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_probe_reports_only_changes() {
+    let server = Arc::new(FakeServer::new());
+    let mut probe = ConnectivityProbe::new(datasource(closed_url()), server.clone());
+
+    let status = probe.probe().await.unwrap();
+    assert!(!status.reachable);
+    probe.probe().await.unwrap();
+
+    let statuses = server.datasource_statuses();
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0].0, "main");
+    assert!(statuses[0]
+        .1
+        .error
+        .as_ref()
+        .unwrap()
+        .contains("Connection error"));
+}
+
+#[tokio::test]
+async fn test_probe_reports_reachable_datasource() {
+    let server = Arc::new(FakeServer::new());
+    let mut probe = ConnectivityProbe::new(datasource(CLICKHOUSE_URL.to_string()), server.clone());
+
+    assert!(probe.probe().await.unwrap().reachable);
+    probe.probe().await.unwrap();
+
+    let statuses = server.datasource_statuses();
+    assert_eq!(statuses.len(), 1);
+    assert!(statuses[0].1.reachable);
+    assert_eq!(statuses[0].1.error, None);
+}
+
+#[tokio::test]
+async fn test_probe_skips_disabled_datasource() {
+    let server = Arc::new(FakeServer::new());
+    let mut disabled = datasource(closed_url());
+    disabled.enabled = false;
+    let mut probe = ConnectivityProbe::new(disabled, server.clone());
+
+    assert_eq!(probe.probe().await, None);
+    assert!(server.datasource_statuses().is_empty());
+}
+
+#[tokio::test]
+async fn test_probe_retries_failed_report() {
+    let server = Arc::new(FakeServer::new());
+    server.set_unreachable(true);
+    let mut probe = ConnectivityProbe::new(datasource(closed_url()), server.clone());
+
+    probe.probe().await.unwrap();
+    server.set_unreachable(false);
+    probe.probe().await.unwrap();
+
+    assert_eq!(server.datasource_statuses().len(), 1);
+}
+
+#[tokio::test]
+async fn test_spawn_probe_requires_interval() {
+    let server = Arc::new(FakeServer::new());
+    assert!(spawn_connectivity_probe(datasource(closed_url()), server.clone()).is_none());
+
+    let mut probed = datasource(closed_url());
+    probed.probe_interval = Some(Duration::from_secs(3600));
+    let handle = spawn_connectivity_probe(probed, server.clone()).unwrap();
+    for _ in 0..50 {
+        if !server.datasource_statuses().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    handle.abort();
+
+    assert_eq!(server.datasource_statuses().len(), 1);
+}
+
+#[tokio::test]
+async fn test_server_client_reports_datasource_status() {
+    let mut server = Server::new_async().await;
+    let status_mock = server
+        .mock("POST", "/datasource/main/status")
+        .match_body(Matcher::PartialJson(serde_json::json!({
+            "reachable": false,
+            "error": "Connection error: refused",
+            "latency_ms": 12,
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+    let status = DatasourceStatus {
+        reachable: false,
+        error: Some("Connection error: refused".to_string()),
+        latency_ms: 12,
+        checked_at: chrono::Utc::now(),
+        labels: Default::default(),
+    };
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client
+        .report_datasource_status("main", &status)
+        .await
+        .unwrap();
+
+    status_mock.assert();
+}
@@ -0,0 +1,83 @@
+use mockito::{Matcher, Server};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{ServerApi, ServerClient};
+use tsight_agent::crash::{self, CrashReport};
+
+#[tokio::test]
+async fn test_supervised_loop_restarts_after_panic() {
+    crash::install_panic_hook();
+    let server = Arc::new(FakeServer::new());
+    let runs = Arc::new(AtomicUsize::new(0));
+
+    let counter = runs.clone();
+    crash::supervise("job_agent", server.clone(), move || {
+        let counter = counter.clone();
+        async move {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("boom");
+            }
+        }
+    })
+    .await;
+
+    assert_eq!(runs.load(Ordering::SeqCst), 2);
+    let reports = server.crash_reports();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].component, "job_agent");
+    assert_eq!(reports[0].message, "boom");
+    assert!(reports[0]
+        .location
+        .as_ref()
+        .unwrap()
+        .starts_with("tests/crash_test.rs"));
+    assert!(!reports[0].backtrace.is_empty());
+    assert_eq!(reports[0].version, env!("CARGO_PKG_VERSION"));
+}
+
+#[tokio::test]
+async fn test_supervised_loop_that_returns_is_not_restarted() {
+    let server = Arc::new(FakeServer::new());
+    let runs = Arc::new(AtomicUsize::new(0));
+
+    let counter = runs.clone();
+    crash::supervise("job_agent", server.clone(), move || {
+        let counter = counter.clone();
+        async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    })
+    .await;
+
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    assert!(server.crash_reports().is_empty());
+}
+
+#[tokio::test]
+async fn test_server_client_reports_crash() {
+    let mut server = Server::new_async().await;
+    let crash_mock = server
+        .mock("POST", "/agent/crash")
+        .match_body(Matcher::PartialJson(serde_json::json!({
+            "component": "high_priority_agent",
+            "message": "index out of bounds",
+            "location": "src/agent/mod.rs:42:5",
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+    let report = CrashReport {
+        component: "high_priority_agent".to_string(),
+        message: "index out of bounds".to_string(),
+        location: Some("src/agent/mod.rs:42:5".to_string()),
+        backtrace: "0: tsight_agent::agent::Agent::run".to_string(),
+        version: "0.1.0".to_string(),
+        occurred_at: chrono::Utc::now(),
+    };
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client.report_crash(&report).await.unwrap();
+
+    crash_mock.assert();
+}
@@ -0,0 +1,126 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tsight_agent::daemon::PidFile;
+
+/// Wait until `condition` holds, failing after a few seconds
+fn wait_for(description: &str, condition: impl Fn() -> bool) {
+    let started = Instant::now();
+    while !condition() {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "timed out waiting for {}",
+            description
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn read_pid(path: &Path) -> Option<i32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[test]
+fn test_pid_file_is_exclusive() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("agent.pid");
+
+    let mut pid_file = PidFile::acquire(&path).unwrap();
+    pid_file.write_pid().unwrap();
+    assert_eq!(read_pid(&path), Some(std::process::id() as i32));
+
+    let error = PidFile::acquire(&path).unwrap_err().to_string();
+    assert!(error.contains("already running"), "{}", error);
+    assert!(
+        error.contains(&format!("PID {}", std::process::id())),
+        "{}",
+        error
+    );
+
+    // Released and removed once the agent is done with it
+    drop(pid_file);
+    assert!(!path.exists());
+    PidFile::acquire(&path).unwrap();
+}
+
+#[test]
+fn test_stale_pid_file_is_taken_over() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("agent.pid");
+    fs::write(&path, "999999\n").unwrap();
+
+    let mut pid_file = PidFile::acquire(&path).unwrap();
+    pid_file.write_pid().unwrap();
+
+    assert_eq!(read_pid(&path), Some(std::process::id() as i32));
+}
+
+#[test]
+fn test_daemon_detaches_and_stops_on_sigterm() {
+    let dir = TempDir::new().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    let log_path = dir.path().join("agent.log");
+    let pid_path = dir.path().join("agent.pid");
+    fs::write(
+        &config_path,
+        format!(
+            r#"
+server:
+  api_key: test_key
+  server_url: http://127.0.0.1:1
+logging:
+  file:
+    path: {}
+datasources:
+  - name: main
+    source_type: Clickhouse
+    hosts:
+      - http://127.0.0.1:1
+    username: default
+    password: ""
+"#,
+            log_path.display()
+        ),
+    )
+    .unwrap();
+    let agent = || {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_tsight_agent"));
+        command
+            .arg("--config")
+            .arg(&config_path)
+            .arg("--daemon")
+            .arg("--pid-file")
+            .arg(&pid_path)
+            .env("RUST_LOG", "info")
+            .env_remove("NOTIFY_SOCKET");
+        command
+    };
+
+    // The command returns once the agent detached
+    let status = agent().status().unwrap();
+    assert!(status.success());
+    wait_for("the PID file", || read_pid(&pid_path).is_some());
+    let pid = read_pid(&pid_path).unwrap();
+    // SAFETY: signal 0 only checks that the process exists
+    assert_eq!(unsafe { libc::kill(pid, 0) }, 0);
+
+    let second = agent().output().unwrap();
+    assert!(!second.status.success());
+    let stderr = String::from_utf8_lossy(&second.stderr);
+    assert!(stderr.contains("already running"), "{}", stderr);
+
+    // SIGTERM is only handled gracefully once the agent started up
+    wait_for("the agent to start", || {
+        fs::read_to_string(&log_path).is_ok_and(|log| log.contains("Starting main processing loop"))
+    });
+    std::thread::sleep(Duration::from_millis(200));
+    // SAFETY: the agent was started by this test
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGTERM) }, 0);
+    wait_for("the agent to stop", || !pid_path.exists());
+    let log = fs::read_to_string(&log_path).unwrap();
+    assert!(log.contains("TSight Agent stopped"), "{}", log);
+}
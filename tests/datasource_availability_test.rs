@@ -0,0 +1,148 @@
+use chrono::{NaiveTime, TimeZone, Utc, Weekday};
+use std::sync::Arc;
+use tsight_agent::agent::factory::{
+    create_job_agent_with_client, create_observation_agent_with_client,
+};
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::MaintenanceWindow;
+use tsight_agent::models::DataSource;
+
+fn create_test_datasource(name: &str, enabled: bool) -> DataSource {
+    DataSource {
+        name: name.to_string(),
+        hosts: vec!["http://127.0.0.1:1".to_string()],
+        enabled,
+        ..Default::default()
+    }
+}
+
+fn create_task(datasource_name: &str) -> AcquireResultBody {
+    AcquireResultBody {
+        id: "123".to_string(),
+        datasource_name: datasource_name.to_string(),
+        query: "SELECT 1".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    }
+}
+
+fn window(days: Vec<Weekday>, start: &str, end: &str) -> MaintenanceWindow {
+    MaintenanceWindow {
+        days,
+        start: start.parse::<NaiveTime>().unwrap(),
+        end: end.parse::<NaiveTime>().unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn test_task_for_disabled_datasource_fails_fast() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_task(create_task("disabled"), false);
+
+    let agent = create_observation_agent_with_client(
+        server.clone(),
+        vec![
+            create_test_datasource("enabled", true),
+            create_test_datasource("disabled", false),
+        ],
+        false,
+        None,
+    );
+
+    assert!(agent.process_next().await.is_err());
+
+    let errors = server.task_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0].1.contains("disabled by operator"),
+        "{}",
+        errors[0].1
+    );
+}
+
+#[tokio::test]
+async fn test_no_work_acquired_when_all_datasources_unavailable() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(create_task("disabled"));
+
+    let agent = create_job_agent_with_client(
+        server.clone(),
+        vec![create_test_datasource("disabled", false)],
+        None,
+    );
+
+    let error = agent.process_next().await.unwrap_err().to_string();
+    assert!(error.contains("No datasources available"), "{}", error);
+    // The job stays queued for another agent
+    assert!(server.job_errors().is_empty());
+}
+
+#[test]
+fn test_maintenance_window_same_day() {
+    let window = window(vec![Weekday::Sun], "02:00", "04:00");
+
+    // 2024-06-02 is a Sunday
+    assert!(window.contains(Utc.with_ymd_and_hms(2024, 6, 2, 3, 0, 0).unwrap()));
+    assert!(!window.contains(Utc.with_ymd_and_hms(2024, 6, 2, 4, 0, 0).unwrap()));
+    assert!(!window.contains(Utc.with_ymd_and_hms(2024, 6, 3, 3, 0, 0).unwrap()));
+}
+
+#[test]
+fn test_maintenance_window_past_midnight() {
+    let window = window(vec![Weekday::Sat], "23:00", "01:00");
+
+    // Starts on Saturday 2024-06-01 and ends on Sunday
+    assert!(window.contains(Utc.with_ymd_and_hms(2024, 6, 1, 23, 30, 0).unwrap()));
+    assert!(window.contains(Utc.with_ymd_and_hms(2024, 6, 2, 0, 30, 0).unwrap()));
+    assert!(!window.contains(Utc.with_ymd_and_hms(2024, 6, 2, 23, 30, 0).unwrap()));
+}
+
+#[test]
+fn test_datasource_in_maintenance_is_unavailable() {
+    let datasource = DataSource {
+        maintenance_windows: Some(vec![window(vec![], "02:00", "04:00")]),
+        ..create_test_datasource("analytics", true)
+    };
+
+    let reason = datasource
+        .unavailable_reason(Utc.with_ymd_and_hms(2024, 6, 5, 2, 30, 0).unwrap())
+        .unwrap();
+    assert!(reason.contains("maintenance window"), "{}", reason);
+    assert!(datasource
+        .unavailable_reason(Utc.with_ymd_and_hms(2024, 6, 5, 12, 0, 0).unwrap())
+        .is_none());
+}
+
+#[test]
+fn test_availability_config_parsing() {
+    let datasource: DataSource = config::Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+name: analytics
+source_type: clickhouse
+hosts: ["http://localhost:8123"]
+username: default
+password: ""
+maintenance_windows:
+  - days: [sat, sun]
+    start: "02:00"
+    end: "04:30"
+"#,
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    assert!(datasource.enabled);
+    let windows = datasource.maintenance_windows.unwrap();
+    assert_eq!(windows[0].days, [Weekday::Sat, Weekday::Sun]);
+    assert_eq!(windows[0].end, NaiveTime::from_hms_opt(4, 30, 0).unwrap());
+}
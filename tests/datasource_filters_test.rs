@@ -0,0 +1,106 @@
+use tsight_agent::config::{GlobalFilters, SqlFilterRules};
+use tsight_agent::models::DataSource;
+
+fn rules(database_regex: &str) -> Vec<SqlFilterRules> {
+    vec![SqlFilterRules {
+        database_regexes: Some(vec![database_regex.to_string()]),
+        ..Default::default()
+    }]
+}
+
+fn database_regexes(rules: &Option<Vec<SqlFilterRules>>) -> Vec<String> {
+    rules
+        .as_ref()
+        .unwrap()
+        .iter()
+        .flat_map(|r| r.database_regexes.clone().unwrap_or_default())
+        .collect()
+}
+
+fn global_filters() -> GlobalFilters {
+    GlobalFilters {
+        sql_filters_exclude: Some(rules("^system$")),
+        sql_filters_allow: Some(rules("^production$")),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_datasource_without_rules_uses_global_filters() {
+    let datasource = DataSource::default();
+    let filters = datasource
+        .effective_filters(Some(&global_filters()))
+        .unwrap();
+
+    assert_eq!(database_regexes(&filters.sql_filters_exclude), ["^system$"]);
+    assert_eq!(
+        database_regexes(&filters.sql_filters_allow),
+        ["^production$"]
+    );
+}
+
+#[test]
+fn test_datasource_rules_override_global_filters() {
+    let datasource = DataSource {
+        sql_filters_exclude: Some(rules("^staging$")),
+        ..Default::default()
+    };
+    let filters = datasource
+        .effective_filters(Some(&global_filters()))
+        .unwrap();
+
+    assert_eq!(
+        database_regexes(&filters.sql_filters_exclude),
+        ["^staging$"]
+    );
+    // Allow rules aren't defined on the datasource, so they are inherited
+    assert_eq!(
+        database_regexes(&filters.sql_filters_allow),
+        ["^production$"]
+    );
+}
+
+#[test]
+fn test_datasource_rules_without_global_filters() {
+    let datasource = DataSource {
+        sql_filters_allow: Some(rules("^analytics$")),
+        ..Default::default()
+    };
+    let filters = datasource.effective_filters(None).unwrap();
+
+    assert!(filters.sql_filters_exclude.is_none());
+    assert_eq!(
+        database_regexes(&filters.sql_filters_allow),
+        ["^analytics$"]
+    );
+}
+
+#[test]
+fn test_no_filters_at_all() {
+    assert!(DataSource::default().effective_filters(None).is_none());
+}
+
+#[test]
+fn test_datasource_rules_parsed_from_yaml() {
+    let datasource: DataSource = config::Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+name: analytics
+source_type: clickhouse
+hosts: ["http://localhost:8123"]
+username: default
+password: ""
+sql_filters_exclude:
+  - table_regexes: ["^tmp_"]
+"#,
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    let exclude = datasource.sql_filters_exclude.unwrap();
+    assert_eq!(exclude[0].table_regexes, Some(vec!["^tmp_".to_string()]));
+    assert!(datasource.sql_filters_allow.is_none());
+}
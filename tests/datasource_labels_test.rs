@@ -0,0 +1,128 @@
+use mockito::{Matcher, Server};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tsight_agent::agent::discover_and_submit_schemas;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{DatasourceStatus, ServerApi, ServerClient};
+use tsight_agent::config::Config;
+use tsight_agent::health::ConnectivityProbe;
+use tsight_agent::models::{DataSource, DataSourceType};
+
+fn labels() -> BTreeMap<String, String> {
+    BTreeMap::from([
+        ("env".to_string(), "prod".to_string()),
+        ("team".to_string(), "payments".to_string()),
+    ])
+}
+
+fn datasource() -> DataSource {
+    DataSource {
+        name: "payments".to_string(),
+        source_type: DataSourceType::Mock,
+        labels: labels(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_labels_loaded_from_config() {
+    let dir = TempDir::new().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    fs::write(
+        &config_path,
+        r#"server:
+  api_key: test-api-key
+  server_url: http://localhost:8080
+datasources:
+  - name: payments
+    source_type: mock
+    labels:
+      env: prod
+      team: payments
+  - name: scratch
+    source_type: mock
+"#,
+    )
+    .unwrap();
+
+    let config = Config::load(&config_path).unwrap();
+
+    assert_eq!(config.datasources[0].labels, labels());
+    assert!(config.datasources[1].labels.is_empty());
+}
+
+#[tokio::test]
+async fn test_labels_sent_with_registration_and_schemas() {
+    let server = FakeServer::new();
+
+    discover_and_submit_schemas(&[datasource()], &server, None)
+        .await
+        .unwrap();
+
+    assert_eq!(server.datasource_labels("payments"), Some(labels()));
+    assert_eq!(server.schema_labels("payments"), Some(labels()));
+}
+
+#[tokio::test]
+async fn test_labels_sent_with_status() {
+    let server = Arc::new(FakeServer::new());
+    let mut probe = ConnectivityProbe::new(datasource(), server.clone());
+
+    probe.probe().await.unwrap();
+
+    let statuses = server.datasource_statuses();
+    assert_eq!(statuses[0].1.labels, labels());
+}
+
+#[tokio::test]
+async fn test_server_client_sends_labels() {
+    let mut server = Server::new_async().await;
+    let add_mock = server
+        .mock("POST", "/datasource/payments/add")
+        .match_body(Matcher::PartialJson(json!({
+            "datasource_type": "mock",
+            "labels": {"env": "prod", "team": "payments"},
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+    let schemas_mock = server
+        .mock("POST", "/datasource/payments/discovery")
+        .match_body(Matcher::PartialJson(json!({
+            "labels": {"env": "prod", "team": "payments"},
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client
+        .add_datasource("payments", "mock", &labels())
+        .await
+        .unwrap();
+    client
+        .submit_schemas("payments", vec![], &labels())
+        .await
+        .unwrap();
+
+    add_mock.assert();
+    schemas_mock.assert();
+}
+
+#[test]
+fn test_status_without_labels_leaves_them_out() {
+    let status = DatasourceStatus {
+        reachable: true,
+        error: None,
+        latency_ms: 3,
+        checked_at: chrono::Utc::now(),
+        labels: BTreeMap::new(),
+    };
+
+    let json = serde_json::to_value(&status).unwrap();
+
+    assert!(json.get("labels").is_none());
+}
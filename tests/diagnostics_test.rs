@@ -0,0 +1,140 @@
+use chrono::Utc;
+use mockito::{Matcher, Server};
+use std::collections::BTreeMap;
+use std::net::TcpListener;
+use std::sync::Arc;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind, ServerApi, ServerClient};
+use tsight_agent::diagnostics::{self, CheckResult, ConfigSummary, DiagnosticsReport};
+use tsight_agent::health::CHECK_OK;
+use tsight_agent::models::DataSource;
+
+const TEST_JOB_ID: &str = "diag-1";
+
+/// URL nothing listens on
+fn closed_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+fn datasource(name: &str, enabled: bool) -> DataSource {
+    DataSource {
+        name: name.to_string(),
+        hosts: vec![closed_url()],
+        username: "diag_user".to_string(),
+        password: "diag_secret".to_string(),
+        enabled,
+        ..Default::default()
+    }
+}
+
+fn diagnostics_job() -> AcquireResultBody {
+    AcquireResultBody {
+        id: TEST_JOB_ID.to_string(),
+        datasource_name: String::new(),
+        query: String::new(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Diagnostics,
+        signature: None,
+        time_range: None,
+    }
+}
+
+#[tokio::test]
+async fn test_diagnostics_job_submits_report() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(diagnostics_job());
+    let agent = create_job_agent_with_client(
+        server.clone(),
+        vec![datasource("down", true), datasource("disabled", false)],
+        None,
+    );
+
+    agent.process_next().await.unwrap();
+
+    let submitted = server.diagnostics();
+    assert_eq!(submitted.len(), 1);
+    let (job_id, report) = &submitted[0];
+    assert_eq!(job_id, TEST_JOB_ID);
+    assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(report.server.status, CHECK_OK);
+    assert!(report.server.latency_ms.is_some());
+
+    let down = &report.datasources["down"];
+    assert!(down.status.starts_with("Connection error"));
+    assert!(down.latency_ms.is_some());
+    let disabled = &report.datasources["disabled"];
+    assert_eq!(disabled.status, "Datasource disabled disabled by operator");
+    assert_eq!(disabled.latency_ms, None);
+
+    assert_eq!(report.config.datasources.len(), 2);
+    assert!(!report.config.datasources[1].enabled);
+    assert!(server.job_results().is_empty());
+    assert!(server.job_errors().is_empty());
+}
+
+#[tokio::test]
+async fn test_diagnostics_report_has_no_credentials() {
+    let server = FakeServer::new();
+    let datasources = vec![datasource("down", true)];
+
+    let report = diagnostics::run(&server, &datasources, None).await;
+
+    let json = serde_json::to_string(&report).unwrap();
+    assert!(!json.contains("diag_user"));
+    assert!(!json.contains("diag_secret"));
+    assert!(!json.contains(&datasources[0].hosts[0]));
+}
+
+#[tokio::test]
+async fn test_diagnostics_reports_unreachable_server() {
+    let server = FakeServer::new();
+    server.set_unreachable(true);
+
+    let report = diagnostics::run(&server, &[], None).await;
+
+    assert_eq!(report.server.status, "Server unreachable");
+    assert!(report.datasources.is_empty());
+}
+
+#[tokio::test]
+async fn test_server_client_submits_diagnostics() {
+    let mut server = Server::new_async().await;
+    let submit_mock = server
+        .mock("POST", format!("/jobs/{}/submit", TEST_JOB_ID).as_str())
+        .match_body(Matcher::PartialJson(serde_json::json!({
+            "diagnostics": {
+                "version": "1.2.3",
+                "server": {"status": "ok", "latency_ms": 4},
+            }
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+    let report = DiagnosticsReport {
+        version: "1.2.3".to_string(),
+        platform: "linux/x86_64".to_string(),
+        generated_at: Utc::now(),
+        config: ConfigSummary {
+            datasources: vec![],
+            global_filters: false,
+        },
+        server: CheckResult {
+            status: CHECK_OK.to_string(),
+            latency_ms: Some(4),
+        },
+        datasources: BTreeMap::new(),
+    };
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client
+        .submit_diagnostics(TEST_JOB_ID, report)
+        .await
+        .unwrap();
+
+    submit_mock.assert();
+}
@@ -0,0 +1,181 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tempfile::TempDir;
+use tsight_agent::agent::submit_in_batches;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::DiscoverySummary;
+use tsight_agent::config::DiscoveryConfig;
+use tsight_agent::executors::base::{DiscoveryOptions, QueryError, QueryExecutor};
+use tsight_agent::executors::clickhouse_source::{ResultColumn, TableSchema};
+use tsight_agent::filters::FilterStats;
+use tsight_agent::models::{DataSource, JobType, Record};
+use tsight_agent::schema_diff::load_cached_schemas;
+use tsight_agent::timeseries::{NullStats, TsMapping};
+
+/// Executor reporting progress for all tables but the last one
+struct ProgressExecutor {
+    tables: usize,
+}
+
+fn schema(table: usize) -> TableSchema {
+    TableSchema {
+        database: "prod".to_string(),
+        table: format!("t{}", table),
+        row_count: 0,
+        columns: HashMap::new(),
+        partial: table == 0,
+        freshness: Default::default(),
+        growth: None,
+    }
+}
+
+#[async_trait]
+impl QueryExecutor for ProgressExecutor {
+    async fn execute_ts_with(
+        &self,
+        _query: &str,
+        _mapping: &TsMapping,
+    ) -> Result<(Vec<Record>, NullStats), QueryError> {
+        Ok((Vec::new(), NullStats::default()))
+    }
+
+    async fn execute_job_with_columns(
+        &self,
+        _query: &str,
+    ) -> Result<(Vec<JobType>, FilterStats, Vec<ResultColumn>), QueryError> {
+        Ok((Vec::new(), FilterStats::default(), Vec::new()))
+    }
+
+    async fn execute_statement(&self, _query: &str) -> Result<(), QueryError> {
+        Ok(())
+    }
+
+    fn set_session(&mut self, _session_id: Option<String>) {}
+
+    async fn connect(&mut self) -> Result<(), QueryError> {
+        Ok(())
+    }
+
+    async fn discover_schemas_with(
+        &self,
+        options: &DiscoveryOptions,
+    ) -> Result<Vec<TableSchema>, QueryError> {
+        let schemas: Vec<TableSchema> = (0..self.tables).map(schema).collect();
+        if let Some(progress) = &options.progress {
+            for schema in &schemas[..self.tables - 1] {
+                progress.send(schema.clone()).unwrap();
+                tokio::task::yield_now().await;
+            }
+        }
+        Ok(schemas)
+    }
+
+    fn filter_job_results_with_stats(&self, rows: Vec<JobType>) -> (Vec<JobType>, FilterStats) {
+        (rows, FilterStats::default())
+    }
+}
+
+fn datasource() -> DataSource {
+    DataSource {
+        name: "main".to_string(),
+        discovery: DiscoveryConfig {
+            batch_size: Some(2),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_schemas_are_submitted_in_batches() {
+    let server = FakeServer::new();
+    let executor = ProgressExecutor { tables: 5 };
+
+    let summary = submit_in_batches(&datasource(), &executor, &server, None, 2)
+        .await
+        .unwrap();
+
+    let sizes: Vec<usize> = server
+        .schema_batches()
+        .iter()
+        .map(|(_, schemas)| schemas.len())
+        .collect();
+    assert_eq!(sizes, [2, 2, 1]);
+    assert_eq!(
+        summary,
+        DiscoverySummary {
+            tables: 5,
+            partial_tables: 1,
+            batches: 3,
+        }
+    );
+    assert_eq!(
+        server.discovery_summaries(),
+        [("main".to_string(), summary)]
+    );
+    assert_eq!(server.schemas("main").unwrap().len(), 5);
+}
+
+#[tokio::test]
+async fn test_completion_replaces_previous_schemas() {
+    let server = FakeServer::new();
+
+    submit_in_batches(
+        &datasource(),
+        &ProgressExecutor { tables: 5 },
+        &server,
+        None,
+        2,
+    )
+    .await
+    .unwrap();
+    submit_in_batches(
+        &datasource(),
+        &ProgressExecutor { tables: 2 },
+        &server,
+        None,
+        2,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(server.schemas("main").unwrap().len(), 2);
+    assert_eq!(server.discovery_summaries().len(), 2);
+}
+
+#[tokio::test]
+async fn test_batched_schemas_are_cached() {
+    let dir = TempDir::new().unwrap();
+    let mut datasource = datasource();
+    datasource.discovery.submit_cached = true;
+    datasource.discovery.cache_file = Some(dir.path().join("cache.json"));
+
+    submit_in_batches(
+        &datasource,
+        &ProgressExecutor { tables: 3 },
+        &FakeServer::new(),
+        None,
+        2,
+    )
+    .await
+    .unwrap();
+
+    let cached = load_cached_schemas(&dir.path().join("cache.json")).unwrap();
+    assert_eq!(cached.len(), 3);
+}
+
+#[test]
+fn test_batch_size_parsed_from_yaml() {
+    let discovery: DiscoveryConfig = config::Config::builder()
+        .add_source(config::File::from_str(
+            "batch_size: 100\n",
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    assert_eq!(discovery.batch_size, Some(100));
+    assert_eq!(DiscoveryConfig::default().batch_size, None);
+}
@@ -0,0 +1,112 @@
+use mockito::Server;
+use serde_json::json;
+use std::collections::HashMap;
+use tempfile::TempDir;
+use tsight_agent::agent::{discover_and_submit_schemas, submit_schema_cache};
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{ServerApi, ServerClient};
+use tsight_agent::config::DiscoveryConfig;
+use tsight_agent::executors::clickhouse_source::TableSchema;
+use tsight_agent::models::DataSource;
+use tsight_agent::schema_diff::save_cached_schemas;
+
+fn schema(table: &str) -> TableSchema {
+    TableSchema {
+        database: "prod".to_string(),
+        table: table.to_string(),
+        row_count: 10,
+        columns: HashMap::new(),
+        partial: false,
+        freshness: Default::default(),
+        growth: None,
+    }
+}
+
+fn datasource(dir: &TempDir) -> DataSource {
+    DataSource {
+        name: "main".to_string(),
+        discovery: DiscoveryConfig {
+            submit_cached: true,
+            cache_file: Some(dir.path().join("cache.json")),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_cached_schemas_are_submitted() {
+    let dir = TempDir::new().unwrap();
+    let datasource = datasource(&dir);
+    save_cached_schemas(&dir.path().join("cache.json"), &[schema("users")]).unwrap();
+    let server = FakeServer::new();
+
+    assert!(submit_schema_cache(&datasource, &server).await.unwrap());
+
+    assert_eq!(
+        server.cached_schemas(),
+        [("main".to_string(), vec![schema("users")])]
+    );
+    assert!(server.datasource_type("main").is_some());
+}
+
+#[tokio::test]
+async fn test_missing_cache_submits_nothing() {
+    let dir = TempDir::new().unwrap();
+    let server = FakeServer::new();
+
+    assert!(!submit_schema_cache(&datasource(&dir), &server)
+        .await
+        .unwrap());
+    assert!(server.cached_schemas().is_empty());
+}
+
+#[tokio::test]
+async fn test_cache_goes_first_at_startup() {
+    let dir = TempDir::new().unwrap();
+    save_cached_schemas(&dir.path().join("cache.json"), &[schema("users")]).unwrap();
+    let server = FakeServer::new();
+
+    // Fresh discovery can't connect to the default datasource, the cached
+    // schemas are submitted regardless
+    discover_and_submit_schemas(&[datasource(&dir)], &server, None)
+        .await
+        .unwrap();
+
+    assert_eq!(server.cached_schemas().len(), 1);
+    assert_eq!(server.schemas("main").unwrap(), [schema("users")]);
+}
+
+#[tokio::test]
+async fn test_client_marks_cached_schemas() {
+    let mut server = Server::new_async().await;
+    let schemas_mock = server
+        .mock("POST", "/datasource/main/discovery")
+        .match_body(mockito::Matcher::PartialJson(json!({"from_cache": true})))
+        .with_status(200)
+        .create();
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+
+    client
+        .submit_cached_schemas("main", vec![schema("users")], &Default::default())
+        .await
+        .unwrap();
+
+    schemas_mock.assert();
+}
+
+#[test]
+fn test_submit_cached_parsed_from_yaml() {
+    let discovery: DiscoveryConfig = config::Config::builder()
+        .add_source(config::File::from_str(
+            "submit_cached: true\n",
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    assert!(discovery.submit_cached);
+    assert!(!DiscoveryConfig::default().submit_cached);
+}
@@ -0,0 +1,78 @@
+use tsight_agent::config::{DiscoveryConfig, FilterAction, GlobalFilters, SqlFilterRules};
+use tsight_agent::executors::clickhouse_source::FilterConfig;
+
+fn filter_config() -> FilterConfig {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![
+            SqlFilterRules {
+                column_value_regexes: Some(vec![r"^\d{16}$".to_string()]),
+                ..Default::default()
+            },
+            SqlFilterRules {
+                column_value_regexes: Some(vec![r"^[^@]+@[^@]+$".to_string()]),
+                action: Some(FilterAction::Mask),
+                ..Default::default()
+            },
+            SqlFilterRules {
+                column_name_regexes: Some(vec!["^phone$".to_string()]),
+                action: Some(FilterAction::RedactValue),
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    };
+    FilterConfig::with_global_filters(Some(&filters)).unwrap()
+}
+
+#[test]
+fn test_samples_pass_value_filters() {
+    let config = filter_config();
+
+    assert_eq!(
+        config.filter_sample("status", "shipped".to_string()),
+        Some("shipped".to_string())
+    );
+    // Values dropping rows are left out entirely
+    assert_eq!(
+        config.filter_sample("note", "4111111111111111".to_string()),
+        None
+    );
+    assert_eq!(
+        config.filter_sample("email", "jo@example.com".to_string()),
+        Some("**@*******.com".to_string())
+    );
+}
+
+#[test]
+fn test_samples_pass_column_filters() {
+    assert_eq!(
+        filter_config().filter_sample("phone", "555-0100".to_string()),
+        Some("[REDACTED]".to_string())
+    );
+}
+
+#[test]
+fn test_samples_are_kept_without_filters() {
+    let config = FilterConfig::with_global_filters(None).unwrap();
+
+    assert_eq!(
+        config.filter_sample("status", "shipped".to_string()),
+        Some("shipped".to_string())
+    );
+}
+
+#[test]
+fn test_sample_values_parsed_from_yaml() {
+    let discovery: DiscoveryConfig = config::Config::builder()
+        .add_source(config::File::from_str(
+            "sample_values: 5\n",
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    assert_eq!(discovery.sample_values, Some(5));
+    assert_eq!(DiscoveryConfig::default().sample_values, None);
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tsight_agent::agent::{discover_and_submit_schemas, spawn_scheduled_discovery};
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::config::DiscoveryConfig;
+use tsight_agent::models::DataSource;
+
+fn create_test_datasource(name: &str, discovery: DiscoveryConfig) -> DataSource {
+    DataSource {
+        name: name.to_string(),
+        hosts: vec!["http://127.0.0.1:1".to_string()],
+        username: "default".to_string(),
+        discovery,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_disabled_datasource_is_not_discovered() {
+    let server = FakeServer::new();
+    let datasources = vec![
+        create_test_datasource("enabled", DiscoveryConfig::default()),
+        create_test_datasource(
+            "disabled",
+            DiscoveryConfig {
+                enabled: false,
+                interval: None,
+                ..Default::default()
+            },
+        ),
+    ];
+
+    discover_and_submit_schemas(&datasources, &server, None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        server.datasource_type("enabled"),
+        Some("clickhouse".to_string())
+    );
+    assert_eq!(server.datasource_type("disabled"), None);
+}
+
+#[tokio::test]
+async fn test_discovery_is_scheduled_only_with_interval() {
+    let server = Arc::new(FakeServer::new());
+
+    let once = create_test_datasource("once", DiscoveryConfig::default());
+    assert!(spawn_scheduled_discovery(once, server.clone(), None).is_none());
+
+    let disabled = create_test_datasource(
+        "disabled",
+        DiscoveryConfig {
+            enabled: false,
+            interval: Some(Duration::from_secs(60)),
+            ..Default::default()
+        },
+    );
+    assert!(spawn_scheduled_discovery(disabled, server.clone(), None).is_none());
+
+    let scheduled = create_test_datasource(
+        "scheduled",
+        DiscoveryConfig {
+            enabled: true,
+            interval: Some(Duration::from_secs(60)),
+            ..Default::default()
+        },
+    );
+    let handle = spawn_scheduled_discovery(scheduled, server.clone(), None).unwrap();
+    handle.abort();
+}
+
+#[tokio::test]
+async fn test_scheduled_discovery_runs_after_interval() {
+    let server = Arc::new(FakeServer::new());
+    let datasource = create_test_datasource(
+        "scheduled",
+        DiscoveryConfig {
+            enabled: true,
+            interval: Some(Duration::from_millis(200)),
+            ..Default::default()
+        },
+    );
+
+    let handle = spawn_scheduled_discovery(datasource, server.clone(), None).unwrap();
+    tokio::task::yield_now().await;
+    assert_eq!(server.datasource_type("scheduled"), None);
+
+    for _ in 0..50 {
+        if server.datasource_type("scheduled").is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(
+        server.datasource_type("scheduled"),
+        Some("clickhouse".to_string())
+    );
+    handle.abort();
+}
+
+#[test]
+fn test_discovery_config_parsing() {
+    let datasource: DataSource = config::Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+name: analytics
+source_type: clickhouse
+hosts: ["http://localhost:8123"]
+username: default
+password: ""
+discovery:
+  interval: 6h
+"#,
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    assert!(datasource.discovery.enabled);
+    assert_eq!(
+        datasource.discovery.interval,
+        Some(Duration::from_secs(6 * 3600))
+    );
+}
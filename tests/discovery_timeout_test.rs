@@ -0,0 +1,148 @@
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+use tempfile::TempDir;
+use tsight_agent::agent::discover_with_skip_list;
+use tsight_agent::config::DiscoveryConfig;
+use tsight_agent::executors::base::{DiscoveryOptions, QueryError, QueryExecutor};
+use tsight_agent::executors::clickhouse_source::{ResultColumn, TableSchema};
+use tsight_agent::filters::FilterStats;
+use tsight_agent::models::{DataSource, JobType, Record};
+use tsight_agent::timeseries::{NullStats, TsMapping};
+
+/// Executor timing out on the `prod.slow` table unless it's skipped
+#[derive(Default)]
+struct SlowTableExecutor {
+    options: Mutex<Vec<DiscoveryOptions>>,
+}
+
+fn schema(table: &str, partial: bool) -> TableSchema {
+    TableSchema {
+        database: "prod".to_string(),
+        table: table.to_string(),
+        row_count: 0,
+        columns: HashMap::new(),
+        partial,
+        freshness: Default::default(),
+        growth: None,
+    }
+}
+
+#[async_trait]
+impl QueryExecutor for SlowTableExecutor {
+    async fn execute_ts_with(
+        &self,
+        _query: &str,
+        _mapping: &TsMapping,
+    ) -> Result<(Vec<Record>, NullStats), QueryError> {
+        Ok((Vec::new(), NullStats::default()))
+    }
+
+    async fn execute_job_with_columns(
+        &self,
+        _query: &str,
+    ) -> Result<(Vec<JobType>, FilterStats, Vec<ResultColumn>), QueryError> {
+        Ok((Vec::new(), FilterStats::default(), Vec::new()))
+    }
+
+    async fn execute_statement(&self, _query: &str) -> Result<(), QueryError> {
+        Ok(())
+    }
+
+    fn set_session(&mut self, _session_id: Option<String>) {}
+
+    async fn connect(&mut self) -> Result<(), QueryError> {
+        Ok(())
+    }
+
+    async fn discover_schemas_with(
+        &self,
+        options: &DiscoveryOptions,
+    ) -> Result<Vec<TableSchema>, QueryError> {
+        self.options.lock().unwrap().push(options.clone());
+        Ok(vec![schema("fast", false), schema("slow", true)])
+    }
+
+    fn filter_job_results_with_stats(&self, rows: Vec<JobType>) -> (Vec<JobType>, FilterStats) {
+        (rows, FilterStats::default())
+    }
+}
+
+fn datasource(dir: &TempDir) -> DataSource {
+    DataSource {
+        name: "main".to_string(),
+        discovery: DiscoveryConfig {
+            table_timeout: Some(Duration::from_secs(5)),
+            skip_after_timeouts: 2,
+            skip_list_file: Some(dir.path().join("skip.json")),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn timeouts(dir: &TempDir) -> BTreeMap<String, u32> {
+    let content = std::fs::read_to_string(dir.path().join("skip.json")).unwrap();
+    serde_json::from_str(&content).unwrap()
+}
+
+#[tokio::test]
+async fn test_tables_timing_out_repeatedly_are_skipped() {
+    let dir = TempDir::new().unwrap();
+    let datasource = datasource(&dir);
+    let executor = SlowTableExecutor::default();
+    let timeout = Duration::from_secs(5);
+
+    for _ in 0..3 {
+        let schemas = discover_with_skip_list(&datasource, &executor, timeout)
+            .await
+            .unwrap();
+        assert_eq!(schemas.len(), 2);
+    }
+
+    let options = executor.options.lock().unwrap();
+    assert_eq!(options[0].table_timeout, Some(timeout));
+    assert!(options[0].skip_tables.is_empty());
+    assert!(options[1].skip_tables.is_empty());
+    assert!(options[2].skip_tables.contains("prod.slow"));
+    assert_eq!(
+        timeouts(&dir),
+        BTreeMap::from([("prod.slow".to_string(), 2)])
+    );
+}
+
+#[tokio::test]
+async fn test_completed_discovery_resets_timeouts() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("skip.json"), r#"{"prod.fast": 1}"#).unwrap();
+
+    discover_with_skip_list(
+        &datasource(&dir),
+        &SlowTableExecutor::default(),
+        Duration::from_secs(5),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        timeouts(&dir),
+        BTreeMap::from([("prod.slow".to_string(), 1)])
+    );
+}
+
+#[test]
+fn test_timeout_options_parsed_from_yaml() {
+    let discovery: DiscoveryConfig = config::Config::builder()
+        .add_source(config::File::from_str(
+            "table_timeout: 30s\n",
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    assert_eq!(discovery.table_timeout, Some(Duration::from_secs(30)));
+    assert_eq!(discovery.skip_after_timeouts, 3);
+}
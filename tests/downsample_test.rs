@@ -0,0 +1,247 @@
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tsight_agent::agent::factory::create_observation_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::manifest::Manifest;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{MockConfig, MockResult};
+use tsight_agent::downsample::{Aggregate, Downsample, DownsampleError, Fill};
+use tsight_agent::models::{DataSource, DataSourceType, JobType, Record};
+use tsight_agent::timeseries::TsMapping;
+
+fn record(t: i64, cnt: f64) -> Record {
+    Record {
+        t,
+        values: BTreeMap::from([("cnt".to_string(), cnt)]),
+        labels: BTreeMap::new(),
+    }
+}
+
+fn labelled(t: i64, cnt: f64, region: &str) -> Record {
+    Record {
+        labels: BTreeMap::from([("region".to_string(), region.to_string())]),
+        ..record(t, cnt)
+    }
+}
+
+fn points(records: &[Record]) -> Vec<(i64, f64)> {
+    records
+        .iter()
+        .map(|record| (record.t, record.values["cnt"]))
+        .collect()
+}
+
+fn downsample(bucket_ms: Option<i64>, aggregate: Aggregate, fill: Fill) -> Downsample {
+    Downsample {
+        bucket_ms,
+        aggregate,
+        fill,
+        max_points: None,
+    }
+}
+
+#[test]
+fn test_buckets_aligned_and_aggregated() {
+    let records = vec![
+        record(1_000, 1.0),
+        record(1_500, 3.0),
+        record(2_200, 5.0),
+        record(2_900, 7.0),
+    ];
+
+    let cases = [
+        (Aggregate::Avg, vec![(1_000, 2.0), (2_000, 6.0)]),
+        (Aggregate::Sum, vec![(1_000, 4.0), (2_000, 12.0)]),
+        (Aggregate::Min, vec![(1_000, 1.0), (2_000, 5.0)]),
+        (Aggregate::Max, vec![(1_000, 3.0), (2_000, 7.0)]),
+        (Aggregate::First, vec![(1_000, 1.0), (2_000, 5.0)]),
+        (Aggregate::Last, vec![(1_000, 3.0), (2_000, 7.0)]),
+    ];
+    for (aggregate, expected) in cases {
+        let downsampled = downsample(Some(1_000), aggregate, Fill::None)
+            .apply(records.clone())
+            .unwrap();
+        assert_eq!(points(&downsampled), expected, "{:?}", aggregate);
+    }
+}
+
+#[test]
+fn test_gaps_filled() {
+    let records = vec![record(0, 1.0), record(3_000, 4.0)];
+
+    let filled = |fill| {
+        points(
+            &downsample(Some(1_000), Aggregate::Avg, fill)
+                .apply(records.clone())
+                .unwrap(),
+        )
+    };
+
+    assert_eq!(filled(Fill::None), vec![(0, 1.0), (3_000, 4.0)]);
+    assert_eq!(
+        filled(Fill::Zero),
+        vec![(0, 1.0), (1_000, 0.0), (2_000, 0.0), (3_000, 4.0)]
+    );
+    assert_eq!(
+        filled(Fill::Previous),
+        vec![(0, 1.0), (1_000, 1.0), (2_000, 1.0), (3_000, 4.0)]
+    );
+    let nulls = filled(Fill::Null);
+    assert_eq!(nulls.len(), 4);
+    assert!(nulls[1].1.is_nan());
+}
+
+#[test]
+fn test_null_fill_serialized_as_null() {
+    let records = downsample(Some(1_000), Aggregate::Avg, Fill::Null)
+        .apply(vec![record(0, 1.0), record(2_000, 2.0)])
+        .unwrap();
+
+    let json = serde_json::to_value(&records).unwrap();
+
+    assert_eq!(json[1]["values"]["cnt"], json!(null));
+    assert!(Manifest::of(&records).is_ok());
+}
+
+#[test]
+fn test_series_downsampled_separately_on_shared_buckets() {
+    let records = vec![
+        labelled(0, 1.0, "eu"),
+        labelled(500, 3.0, "eu"),
+        labelled(2_000, 10.0, "us"),
+    ];
+
+    let downsampled = downsample(Some(1_000), Aggregate::Sum, Fill::Zero)
+        .apply(records)
+        .unwrap();
+
+    let series = |region: &str| {
+        points(
+            &downsampled
+                .iter()
+                .filter(|record| record.labels["region"] == region)
+                .cloned()
+                .collect::<Vec<_>>(),
+        )
+    };
+    assert_eq!(series("eu"), vec![(0, 4.0), (1_000, 0.0), (2_000, 0.0)]);
+    assert_eq!(series("us"), vec![(0, 0.0), (1_000, 0.0), (2_000, 10.0)]);
+}
+
+#[test]
+fn test_max_points_widens_buckets() {
+    let records: Vec<Record> = (0..100).map(|i| record(i * 1_000, 1.0)).collect();
+    let downsample = Downsample {
+        bucket_ms: Some(1_000),
+        aggregate: Aggregate::Sum,
+        fill: Fill::None,
+        max_points: Some(10),
+    };
+
+    let downsampled = downsample.apply(records).unwrap();
+
+    assert_eq!(downsampled.len(), 10);
+    assert!(downsampled.iter().all(|record| record.t % 1_000 == 0));
+    assert_eq!(
+        downsampled.iter().map(|r| r.values["cnt"]).sum::<f64>(),
+        100.0
+    );
+}
+
+#[test]
+fn test_max_points_without_bucket() {
+    let records: Vec<Record> = (0..1_000).map(|i| record(i * 7 + 3, 1.0)).collect();
+    for max_points in [1, 2, 7, 999, 1_000, 5_000] {
+        let downsample = Downsample {
+            max_points: Some(max_points),
+            ..Default::default()
+        };
+        let downsampled = downsample.apply(records.clone()).unwrap();
+        assert!(downsampled.len() as i64 <= max_points, "{}", max_points);
+    }
+}
+
+#[test]
+fn test_invalid_downsampling_rejected() {
+    let records = vec![record(0, 1.0), record(i64::from(i32::MAX), 1.0)];
+
+    assert_eq!(
+        Downsample::default().apply(records.clone()),
+        Err(DownsampleError::NoResolution)
+    );
+    assert_eq!(
+        downsample(Some(0), Aggregate::Avg, Fill::None).apply(records.clone()),
+        Err(DownsampleError::NotPositive)
+    );
+    assert!(matches!(
+        downsample(Some(1), Aggregate::Avg, Fill::Zero).apply(records),
+        Err(DownsampleError::TooManyPoints(_))
+    ));
+}
+
+#[test]
+fn test_mapping_parses_downsample() {
+    let mapping: TsMapping = serde_json::from_value(json!({
+        "downsample": {"bucket_ms": 60000, "aggregate": "max", "fill": "previous", "max_points": 500}
+    }))
+    .unwrap();
+
+    assert_eq!(
+        mapping.downsample,
+        Some(Downsample {
+            bucket_ms: Some(60_000),
+            aggregate: Aggregate::Max,
+            fill: Fill::Previous,
+            max_points: Some(500),
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_task_records_downsampled() {
+    let rows: Vec<JobType> = [(0, 1), (30, 2), (120, 5)]
+        .into_iter()
+        .map(|(t, cnt)| serde_json::from_value(json!({"t": t, "cnt": cnt})).unwrap())
+        .collect();
+    let datasource = DataSource {
+        name: "demo".to_string(),
+        source_type: DataSourceType::Mock,
+        mock: MockConfig {
+            results: vec![MockResult {
+                rows,
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_task(
+        AcquireResultBody {
+            id: "task-1".to_string(),
+            datasource_name: "demo".to_string(),
+            query: "SELECT t, cnt FROM events".to_string(),
+            queries: None,
+            ts_mapping: Some(TsMapping {
+                downsample: Some(downsample(Some(60_000), Aggregate::Sum, Fill::Zero)),
+                ..Default::default()
+            }),
+            timeout: None,
+            enqueued_at: None,
+            kind: JobKind::Query,
+            signature: None,
+            time_range: None,
+        },
+        false,
+    );
+
+    let agent = create_observation_agent_with_client(server.clone(), vec![datasource], false, None);
+    agent.process_next().await.unwrap();
+
+    let results = server.task_results();
+    assert_eq!(
+        points(&results[0].1),
+        vec![(0, 3.0), (60_000, 0.0), (120_000, 5.0)]
+    );
+}
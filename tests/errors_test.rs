@@ -0,0 +1,182 @@
+use anyhow::anyhow;
+use mockito::{Matcher, Server};
+use reqwest::StatusCode;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+use tsight_agent::agent::factory::{
+    create_job_agent_with_client, create_observation_agent_with_client,
+};
+use tsight_agent::agent::QueryTimedOut;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, BackoffRequested, JobKind, ServerApi, ServerClient};
+use tsight_agent::errors::{CategorizedError, ErrorCategory, ErrorTracker};
+use tsight_agent::executors::base::QueryError;
+use tsight_agent::models::DataSource;
+
+/// URL nothing listens on
+fn closed_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+fn datasource(name: &str) -> DataSource {
+    DataSource {
+        name: name.to_string(),
+        hosts: vec![closed_url()],
+        ..Default::default()
+    }
+}
+
+fn task(datasource_name: &str, query: &str) -> AcquireResultBody {
+    AcquireResultBody {
+        id: "1".to_string(),
+        datasource_name: datasource_name.to_string(),
+        query: query.to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    }
+}
+
+/// Category of the error of processing `task` with a job agent
+async fn job_error_category(
+    task: AcquireResultBody,
+    datasources: Vec<DataSource>,
+) -> ErrorCategory {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(task);
+    let agent = create_job_agent_with_client(server, datasources, None);
+    let error = agent.process_next().await.unwrap_err();
+    ErrorCategory::of(&error)
+}
+
+#[tokio::test]
+async fn test_no_work_keeps_message() {
+    let agent = create_observation_agent_with_client(
+        Arc::new(FakeServer::new()),
+        vec![datasource("main")],
+        false,
+        None,
+    );
+
+    let error = agent.process_next().await.unwrap_err();
+
+    assert_eq!(ErrorCategory::of(&error), ErrorCategory::NoWork);
+    assert!(ErrorCategory::of(&error).is_idle());
+    assert_eq!(
+        error.to_string(),
+        "Failed to acquire next query from server: No tasks available"
+    );
+}
+
+#[tokio::test]
+async fn test_no_datasources() {
+    let mut disabled = datasource("main");
+    disabled.enabled = false;
+    let agent = create_job_agent_with_client(Arc::new(FakeServer::new()), vec![disabled], None);
+
+    let error = agent.process_next().await.unwrap_err();
+
+    assert_eq!(ErrorCategory::of(&error), ErrorCategory::NoDatasources);
+}
+
+#[tokio::test]
+async fn test_task_errors_keep_category_after_redaction() {
+    let mut disabled = datasource("disabled");
+    disabled.enabled = false;
+    let datasources = vec![datasource("main"), disabled];
+
+    assert_eq!(
+        job_error_category(task("unknown", "SELECT 1"), datasources.clone()).await,
+        ErrorCategory::UnknownDatasource
+    );
+    assert_eq!(
+        job_error_category(task("disabled", "SELECT 1"), datasources.clone()).await,
+        ErrorCategory::DatasourceUnavailable
+    );
+    assert_eq!(
+        job_error_category(task("main", "DROP TABLE users"), datasources.clone()).await,
+        ErrorCategory::Policy
+    );
+    assert_eq!(
+        job_error_category(task("main", "SELECT 1"), datasources).await,
+        ErrorCategory::Connection
+    );
+}
+
+#[test]
+fn test_classify_by_type() {
+    let backoff = anyhow!(BackoffRequested {
+        status: StatusCode::TOO_MANY_REQUESTS,
+        retry_after: Duration::from_secs(5),
+    });
+    assert_eq!(ErrorCategory::of(&backoff), ErrorCategory::Backoff);
+
+    let timed_out = anyhow!(QueryTimedOut(Duration::from_secs(60)));
+    assert_eq!(ErrorCategory::of(&timed_out), ErrorCategory::Timeout);
+
+    let query = anyhow!(QueryError::ExecutionError("Code: 60".to_string()));
+    assert_eq!(ErrorCategory::of(&query), ErrorCategory::Query);
+
+    let wrapped =
+        anyhow!(QueryError::ConnectionError("refused".to_string())).context("Failed to run query");
+    assert_eq!(ErrorCategory::of(&wrapped), ErrorCategory::Connection);
+
+    let categorized = anyhow!(CategorizedError::new(ErrorCategory::Server, "Bad gateway"));
+    assert_eq!(ErrorCategory::of(&categorized), ErrorCategory::Server);
+
+    assert_eq!(
+        ErrorCategory::of(&anyhow!("No tasks available")),
+        ErrorCategory::Other
+    );
+}
+
+#[tokio::test]
+async fn test_tracker_reports_summary_once() {
+    let tracker = ErrorTracker::new();
+    let server = FakeServer::new();
+    tracker.record(ErrorCategory::Connection, "Connection error: refused");
+    tracker.record(ErrorCategory::Connection, "Connection error: timed out");
+    tracker.record(ErrorCategory::Query, "Query execution error: Code: 60");
+
+    tracker.report(&server).await.unwrap();
+    tracker.report(&server).await.unwrap();
+
+    let summaries = server.error_summaries();
+    assert_eq!(summaries.len(), 1);
+    let connection = &summaries[0].errors[&ErrorCategory::Connection];
+    assert_eq!(connection.count, 2);
+    assert_eq!(connection.last_error, "Connection error: timed out");
+    assert_eq!(summaries[0].errors[&ErrorCategory::Query].count, 1);
+    assert!(summaries[0].since <= summaries[0].until);
+}
+
+#[tokio::test]
+async fn test_server_client_reports_errors() {
+    let mut server = Server::new_async().await;
+    let report_mock = server
+        .mock("POST", "/agent/errors")
+        .match_body(Matcher::PartialJson(serde_json::json!({
+            "errors": {"timeout": {"count": 3, "last_error": "Query timed out after 60s"}}
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+    let tracker = ErrorTracker::new();
+    for _ in 0..3 {
+        tracker.record(ErrorCategory::Timeout, "Query timed out after 60s");
+    }
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client
+        .report_errors(&tracker.take_summary().unwrap())
+        .await
+        .unwrap();
+
+    report_mock.assert();
+}
@@ -0,0 +1,154 @@
+use chrono::{Duration, Utc};
+use mockito::{Matcher, Server};
+use std::sync::Arc;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{FilterAction, GlobalFilters, SqlFilterRules};
+use tsight_agent::executors::base::{ExecutionStats, QueryExecutor};
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+use tsight_agent::models::DataSource;
+
+const BODY: &str = concat!(
+    "[\"id\",\"email\"]\n",
+    "[\"UInt64\",\"String\"]\n",
+    "[1,\"john@example.com\"]\n",
+    "[2,\"jane@example.com\"]\n",
+    "[3,\"ops@internal\"]\n",
+);
+
+const SUMMARY: &str = r#"{"read_rows":"1500","read_bytes":"48000","written_rows":"0","written_bytes":"0","total_rows_to_read":"1500","result_rows":"3","result_bytes":"96","elapsed_ns":"1200000"}"#;
+
+async fn clickhouse() -> (mockito::ServerGuard, mockito::Mock) {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .with_header("X-ClickHouse-Summary", SUMMARY)
+        .with_body(BODY)
+        .create_async()
+        .await;
+    (server, mock)
+}
+
+/// Filters dropping rows with internal email addresses
+fn filters() -> GlobalFilters {
+    GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            column_value_regexes: Some(vec!["@internal$".to_string()]),
+            action: Some(FilterAction::DropRow),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_executor_counts_rows_and_bytes() {
+    let (server, _mock) = clickhouse().await;
+    let executor =
+        ClickhouseExecutor::with_global_filters(&server.url(), "default", "", Some(filters()))
+            .unwrap();
+
+    executor.execute_job("SELECT 1").await.unwrap();
+    executor.execute_job("SELECT 2").await.unwrap();
+
+    assert_eq!(
+        executor.execution_stats(),
+        ExecutionStats {
+            rows_returned: 6,
+            rows_filtered: 2,
+            rows_read: 3000,
+            bytes_read: 96000,
+            ..Default::default()
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_missing_summary_counts_no_bytes() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .with_body(BODY)
+        .create_async()
+        .await;
+    let executor = ClickhouseExecutor::new(&server.url(), "default", "").unwrap();
+
+    executor.execute_job("SELECT 1").await.unwrap();
+
+    let stats = executor.execution_stats();
+    assert_eq!(stats.rows_returned, 3);
+    assert_eq!(stats.bytes_read, 0);
+}
+
+#[tokio::test]
+async fn test_stats_submitted_with_job_results() {
+    let (clickhouse, _mock) = clickhouse().await;
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "7".to_string(),
+        datasource_name: "main".to_string(),
+        query: "SELECT id, email FROM users".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: Some(Utc::now() - Duration::seconds(3)),
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+    let agent = create_job_agent_with_client(
+        server.clone(),
+        vec![DataSource {
+            name: "main".to_string(),
+            hosts: vec![clickhouse.url()],
+            ..Default::default()
+        }],
+        Some(filters()),
+    );
+
+    agent.process_next().await.unwrap();
+
+    let (job_id, stats) = &server.execution_stats()[0];
+    assert_eq!(job_id, "7");
+    assert!(stats.queue_wait_ms.unwrap() >= 3000);
+    assert!(stats.execution_ms < 60_000);
+    assert_eq!(stats.rows_returned, 3);
+    assert_eq!(stats.rows_filtered, 1);
+    assert_eq!(stats.bytes_read, 48000);
+}
+
+#[tokio::test]
+async fn test_no_queue_wait_without_enqueue_time() {
+    let (clickhouse, _mock) = clickhouse().await;
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "8".to_string(),
+        datasource_name: "main".to_string(),
+        query: "SELECT id, email FROM users".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+    let agent = create_job_agent_with_client(
+        server.clone(),
+        vec![DataSource {
+            name: "main".to_string(),
+            hosts: vec![clickhouse.url()],
+            ..Default::default()
+        }],
+        None,
+    );
+
+    agent.process_next().await.unwrap();
+
+    let (_, stats) = &server.execution_stats()[0];
+    assert_eq!(stats.queue_wait_ms, None);
+    assert_eq!(stats.rows_filtered, 0);
+}
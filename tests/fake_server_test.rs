@@ -0,0 +1,105 @@
+use std::sync::Arc;
+use tsight_agent::agent::factory::{
+    create_job_agent_with_client, create_observation_agent_with_client,
+};
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind, ServerApi};
+use tsight_agent::models::{DataSource, DataSourceType};
+
+const TEST_TASK_ID: &str = "123";
+
+fn create_test_datasource() -> DataSource {
+    DataSource {
+        name: "test_clickhouse".to_string(),
+        source_type: DataSourceType::Clickhouse,
+        hosts: vec!["http://invalid-host:8123".to_string()],
+        username: "test_user".to_string(),
+        password: "test_password".to_string(),
+        timeout: 60,
+        filters: None,
+        ..Default::default()
+    }
+}
+
+fn create_task(datasource_name: &str) -> AcquireResultBody {
+    AcquireResultBody {
+        id: TEST_TASK_ID.to_string(),
+        datasource_name: datasource_name.to_string(),
+        query: "SELECT 1".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    }
+}
+
+#[tokio::test]
+async fn test_fake_server_no_tasks() {
+    let server = Arc::new(FakeServer::new());
+    let agent = create_observation_agent_with_client(
+        server.clone(),
+        vec![create_test_datasource()],
+        false,
+        None,
+    );
+
+    let result = agent.process_next().await;
+
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("No tasks available"));
+    assert!(server.task_errors().is_empty());
+}
+
+#[tokio::test]
+async fn test_fake_server_records_task_error() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_task(create_task("unknown_datasource"), true);
+
+    let agent = create_observation_agent_with_client(
+        server.clone(),
+        vec![create_test_datasource()],
+        true,
+        None,
+    );
+
+    assert!(agent.process_next().await.is_err());
+    assert_eq!(
+        server.task_errors(),
+        vec![(
+            TEST_TASK_ID.to_string(),
+            "No matching datasource found for query unknown_datasource".to_string()
+        )]
+    );
+}
+
+#[tokio::test]
+async fn test_fake_server_separates_priority_queues() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_task(create_task("test_clickhouse"), true);
+
+    assert!(server.acquire_next_query(false).await.is_err());
+    assert_eq!(
+        server.acquire_next_query(true).await.unwrap().id,
+        TEST_TASK_ID
+    );
+}
+
+#[tokio::test]
+async fn test_fake_server_records_job_error() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(create_task("test_clickhouse"));
+
+    let agent = create_job_agent_with_client(server.clone(), vec![create_test_datasource()], None);
+
+    assert!(agent.process_next().await.is_err());
+
+    let errors = server.job_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, TEST_TASK_ID);
+    assert!(server.job_results().is_empty());
+}
@@ -0,0 +1,266 @@
+use serde_json::{json, Value};
+use tsight_agent::config::{FilterAction, GlobalFilters, SqlFilterRules};
+use tsight_agent::executors::base::QueryExecutor;
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+use tsight_agent::filters::{SqlFilters, REDACTED_VALUE};
+use tsight_agent::models::JobType;
+
+fn executor(rules: Vec<SqlFilterRules>) -> ClickhouseExecutor {
+    executor_with_hash_key(rules, None)
+}
+
+fn executor_with_hash_key(
+    rules: Vec<SqlFilterRules>,
+    hash_key: Option<&str>,
+) -> ClickhouseExecutor {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(rules),
+        hash_key: hash_key.map(str::to_string),
+        ..Default::default()
+    };
+    ClickhouseExecutor::with_global_filters("http://localhost:8123", "default", "", Some(filters))
+        .unwrap()
+}
+
+fn row(value: Value) -> JobType {
+    serde_json::from_value(value).unwrap()
+}
+
+fn rows() -> Vec<JobType> {
+    vec![
+        row(json!({"email": "john@example.com", "note": "ok", "orders": 3})),
+        row(json!({"email": "jane@example.com", "note": "card 4111 1111 1111 1111", "orders": 5})),
+    ]
+}
+
+fn card_rule(action: Option<FilterAction>) -> SqlFilterRules {
+    SqlFilterRules {
+        column_value_regexes: Some(vec![r"^card\d{16}$".to_string()]),
+        action,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_drop_row_is_the_default_action() {
+    let filtered = executor(vec![card_rule(None)]).filter_job_results(rows());
+
+    assert_eq!(filtered.len(), 1);
+    assert_eq!(filtered[0]["note"], "ok");
+}
+
+#[test]
+fn test_redact_value_keeps_row() {
+    let filtered =
+        executor(vec![card_rule(Some(FilterAction::RedactValue))]).filter_job_results(rows());
+
+    assert_eq!(filtered.len(), 2);
+    assert_eq!(filtered[1]["note"], REDACTED_VALUE);
+    assert_eq!(filtered[1]["orders"], 5);
+}
+
+#[test]
+fn test_mask_column_values() {
+    let rule = SqlFilterRules {
+        column_name_regexes: Some(vec!["^email$".to_string()]),
+        action: Some(FilterAction::Mask),
+        ..Default::default()
+    };
+    let filtered = executor(vec![rule]).filter_job_results(rows());
+
+    assert_eq!(filtered.len(), 2);
+    assert_eq!(filtered[0]["email"], "****@*******.com");
+    assert_eq!(filtered[1]["email"], "****@*******.com");
+    assert_eq!(filtered[0]["note"], "ok");
+}
+
+#[test]
+fn test_drop_row_wins_over_other_actions() {
+    let redact_email = SqlFilterRules {
+        column_name_regexes: Some(vec!["^email$".to_string()]),
+        action: Some(FilterAction::RedactValue),
+        ..Default::default()
+    };
+    let filtered = executor(vec![redact_email, card_rule(Some(FilterAction::DropRow))])
+        .filter_job_results(rows());
+
+    assert_eq!(filtered.len(), 1);
+    assert_eq!(filtered[0]["email"], REDACTED_VALUE);
+}
+
+#[test]
+fn test_apply_action() {
+    let filters = SqlFilters::new(None).unwrap();
+
+    assert_eq!(
+        filters.apply_action(FilterAction::Mask, &json!("4111-1111-1111-1234")),
+        json!("****-****-****-1234")
+    );
+    assert_eq!(
+        filters.apply_action(FilterAction::Mask, &json!(123456789)),
+        json!("*****6789")
+    );
+    assert_eq!(
+        filters.apply_action(FilterAction::Mask, &json!("abc")),
+        json!("***")
+    );
+    assert_eq!(
+        filters.apply_action(FilterAction::RedactValue, &json!(42)),
+        json!(REDACTED_VALUE)
+    );
+    assert_eq!(
+        filters.apply_action(FilterAction::Mask, &Value::Null),
+        Value::Null
+    );
+}
+
+#[test]
+fn test_mask_hides_short_values_entirely() {
+    let filters = SqlFilters::new(None).unwrap();
+
+    for (value, masked) in [
+        (json!("7"), "*"),
+        (json!("42"), "**"),
+        (json!("123"), "***"),
+        (json!("1234"), "****"),
+        (json!(987), "***"),
+        (json!("12-34"), "**-**"),
+        (json!("12345678"), "********"),
+    ] {
+        assert_eq!(
+            filters.apply_action(FilterAction::Mask, &value),
+            json!(masked),
+            "{}",
+            value
+        );
+    }
+}
+
+fn user_id_hash_rule() -> SqlFilterRules {
+    SqlFilterRules {
+        column_name_regexes: Some(vec!["^user_id$".to_string()]),
+        action: Some(FilterAction::Hash),
+        ..Default::default()
+    }
+}
+
+fn user_rows() -> Vec<JobType> {
+    vec![
+        row(json!({"user_id": "u-1", "orders": 3})),
+        row(json!({"user_id": "u-2", "orders": 1})),
+        row(json!({"user_id": "u-1", "orders": 5})),
+    ]
+}
+
+#[test]
+fn test_hash_is_deterministic_and_keyed() {
+    let filtered = executor_with_hash_key(vec![user_id_hash_rule()], Some("local-secret"))
+        .filter_job_results(user_rows());
+
+    assert_eq!(filtered.len(), 3);
+    // Equal values hash the same, so they can still be grouped server-side
+    assert_eq!(filtered[0]["user_id"], filtered[2]["user_id"]);
+    assert_ne!(filtered[0]["user_id"], filtered[1]["user_id"]);
+    // HMAC-SHA256("local-secret", "u-1")
+    assert_eq!(
+        filtered[0]["user_id"],
+        "5774ac6edeb72c0563851c5f513aae90737dd6e453f86a1e29b4c2d767ef8053"
+    );
+
+    let other_key = executor_with_hash_key(vec![user_id_hash_rule()], Some("other-secret"))
+        .filter_job_results(user_rows());
+    assert_ne!(filtered[0]["user_id"], other_key[0]["user_id"]);
+}
+
+#[test]
+fn test_hash_requires_key() {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![user_id_hash_rule()]),
+        ..Default::default()
+    };
+
+    let error = SqlFilters::new(Some(&filters)).unwrap_err().to_string();
+    assert!(error.contains("hash_key"), "{}", error);
+}
+
+fn email_value_rule(action: Option<FilterAction>) -> SqlFilterRules {
+    SqlFilterRules {
+        column_value_regexes: Some(vec![
+            r"^[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}$".to_string()
+        ]),
+        action,
+        ..Default::default()
+    }
+}
+
+fn nested_rows() -> Vec<JobType> {
+    vec![
+        row(
+            json!({"id": 1, "attributes": {"plan": "enterprise", "contacts": ["ops@example.com"]}}),
+        ),
+        row(json!({"id": 2, "attributes": {"plan": "free", "contacts": []}})),
+    ]
+}
+
+#[test]
+fn test_nested_values_are_filtered() {
+    let filtered = executor(vec![email_value_rule(None)]).filter_job_results(nested_rows());
+
+    assert_eq!(filtered.len(), 1);
+    assert_eq!(filtered[0]["id"], 2);
+}
+
+#[test]
+fn test_nested_values_are_redacted() {
+    let filtered = executor(vec![email_value_rule(Some(FilterAction::RedactValue))])
+        .filter_job_results(nested_rows());
+
+    assert_eq!(filtered.len(), 2);
+    assert_eq!(
+        filtered[0]["attributes"],
+        json!({"plan": "enterprise", "contacts": [REDACTED_VALUE]})
+    );
+}
+
+#[test]
+fn test_nested_keys_match_column_rules() {
+    let rule = SqlFilterRules {
+        column_name_regexes: Some(vec!["^plan$".to_string()]),
+        action: Some(FilterAction::Mask),
+        ..Default::default()
+    };
+    let filtered = executor(vec![rule]).filter_job_results(nested_rows());
+
+    assert_eq!(filtered[0]["attributes"]["plan"], "******rise");
+    assert_eq!(filtered[1]["attributes"]["plan"], "****");
+
+    let rule = SqlFilterRules {
+        column_name_regexes: Some(vec!["^contacts$".to_string()]),
+        action: Some(FilterAction::RedactValue),
+        ..Default::default()
+    };
+    let filtered = executor(vec![rule]).filter_job_results(nested_rows());
+
+    assert_eq!(filtered[0]["attributes"]["contacts"], REDACTED_VALUE);
+    assert_eq!(filtered[0]["attributes"]["plan"], "enterprise");
+}
+
+#[test]
+fn test_column_allow_list_applies_to_top_level_only() {
+    let filters = GlobalFilters {
+        sql_filters_allow: Some(vec![SqlFilterRules {
+            column_name_regexes: Some(vec!["^(id|attributes)$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let executor = ClickhouseExecutor::with_global_filters(
+        "http://localhost:8123",
+        "default",
+        "",
+        Some(filters),
+    )
+    .unwrap();
+
+    assert_eq!(executor.filter_job_results(nested_rows()).len(), 2);
+}
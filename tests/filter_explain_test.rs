@@ -0,0 +1,78 @@
+use tsight_agent::config::{FilterAction, GlobalFilters, SqlFilterRules};
+use tsight_agent::filters::{Decision, RuleMatch, SqlFilters};
+
+fn filters() -> SqlFilters {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![
+            SqlFilterRules {
+                name: Some("pii_emails".to_string()),
+                column_value_regexes: Some(vec!["^[^@]+@[^@]+$".to_string()]),
+                ..Default::default()
+            },
+            SqlFilterRules {
+                column_name_regexes: Some(vec!["(?i)email".to_string()]),
+                action: Some(FilterAction::Mask),
+                ..Default::default()
+            },
+            SqlFilterRules {
+                table_regexes: Some(vec!["^orders$".to_string()]),
+                ..Default::default()
+            },
+        ]),
+        sql_filters_allow: Some(vec![SqlFilterRules {
+            database_regexes: Some(vec!["^prod$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    SqlFilters::new(Some(&filters)).unwrap()
+}
+
+#[test]
+fn test_explain_names_matching_exclude_rule() {
+    let filters = filters();
+
+    assert_eq!(
+        filters.explain_value("foo@bar.com"),
+        Decision::Excluded(RuleMatch {
+            action: FilterAction::DropRow,
+            rule: "pii_emails",
+            pattern: Some("^[^@]+@[^@]+$"),
+        })
+    );
+    assert_eq!(
+        filters.explain_table("orders").to_string(),
+        "excluded by rule 'sql_filters_exclude[2]' (pattern '^orders$')"
+    );
+    assert_eq!(
+        filters.explain_column("user_email").to_string(),
+        "masked by rule 'sql_filters_exclude[1]' (pattern '(?i)email')"
+    );
+}
+
+#[test]
+fn test_explain_allow_rules() {
+    let filters = filters();
+
+    assert_eq!(
+        filters.explain_database("prod").to_string(),
+        "kept, allowed by rule 'sql_filters_allow[0]' (pattern '^prod$')"
+    );
+    assert_eq!(filters.explain_database("dev"), Decision::NotAllowed);
+    assert_eq!(
+        filters.explain_database("dev").to_string(),
+        "excluded, no sql_filters_allow rule matched"
+    );
+}
+
+#[test]
+fn test_explain_without_matching_rule() {
+    let filters = filters();
+
+    assert_eq!(filters.explain_column("id"), Decision::Kept);
+    assert_eq!(
+        filters.explain_value("42").to_string(),
+        "kept, no rule matched"
+    );
+    assert_eq!(filters.explain_column("id").rule_match(), None);
+}
@@ -0,0 +1,82 @@
+use tsight_agent::config::{GlobalFilters, MatchMode, SqlFilterRules};
+use tsight_agent::filters::SqlFilters;
+
+fn allow_tables(
+    tables: &[&str],
+    match_mode: Option<MatchMode>,
+    case_insensitive: bool,
+) -> SqlFilters {
+    let filters = GlobalFilters {
+        sql_filters_allow: Some(vec![SqlFilterRules {
+            table_regexes: Some(tables.iter().map(|t| t.to_string()).collect()),
+            match_mode,
+            case_insensitive,
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    SqlFilters::new(Some(&filters)).unwrap()
+}
+
+#[test]
+fn test_regex_is_the_default_match_mode() {
+    let filters = allow_tables(&["orders"], None, false);
+
+    assert!(!filters.should_exclude_table("orders"));
+    assert!(!filters.should_exclude_table("orders_archive"));
+    assert!(filters.should_exclude_table("Orders"));
+}
+
+#[test]
+fn test_exact_match_requires_whole_name() {
+    let filters = allow_tables(&["orders", "user.events"], Some(MatchMode::Exact), false);
+
+    assert!(!filters.should_exclude_table("orders"));
+    assert!(filters.should_exclude_table("orders_archive"));
+    assert!(!filters.should_exclude_table("user.events"));
+    // Regex metacharacters are matched literally
+    assert!(filters.should_exclude_table("user_events"));
+}
+
+#[test]
+fn test_substring_match_is_literal() {
+    let filters = allow_tables(&["log."], Some(MatchMode::Substring), false);
+
+    assert!(!filters.should_exclude_table("app_log.2024"));
+    assert!(filters.should_exclude_table("app_logs"));
+}
+
+#[test]
+fn test_case_insensitive_match() {
+    let filters = allow_tables(&["orders"], Some(MatchMode::Exact), true);
+
+    assert!(!filters.should_exclude_table("ORDERS"));
+    assert!(!filters.should_exclude_table("Orders"));
+    assert!(filters.should_exclude_table("Orders_Archive"));
+}
+
+#[test]
+fn test_match_options_parsed_from_yaml() {
+    let filters: GlobalFilters = config::Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+sql_filters_exclude:
+  - column_name_regexes: ["Email"]
+    match: substring
+    case_insensitive: true
+"#,
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    let rule = &filters.sql_filters_exclude.as_ref().unwrap()[0];
+    assert_eq!(rule.match_mode, Some(MatchMode::Substring));
+    assert!(rule.case_insensitive);
+
+    let filters = SqlFilters::new(Some(&filters)).unwrap();
+    assert!(filters.should_exclude_column("user_email"));
+    assert!(!filters.should_exclude_column("user_id"));
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use tempfile::TempDir;
+use tsight_agent::config::Config;
+use tsight_agent::filters::{builtin_preset, SqlFilters};
+
+fn load(content: &str) -> Result<Config, config::ConfigError> {
+    let dir = TempDir::new().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    fs::write(&config_path, content).unwrap();
+    Config::load(&config_path)
+}
+
+const CONFIG_WITH_PRESETS: &str = r#"
+server:
+  api_key: test-api-key
+  server_url: http://localhost:8080
+filter_presets:
+  pii_strict:
+    sql_filters_exclude:
+      - column_name_regexes: ["(?i)customer"]
+global_filters:
+  sql_filters_exclude:
+    - database_regexes: ["^test_"]
+datasources:
+  - name: analytics
+    source_type: clickhouse
+    hosts: ["http://localhost:8123"]
+    username: default
+    password: ""
+    filter_presets: ["pii_strict", "builtin:finance"]
+  - name: logs
+    source_type: clickhouse
+    hosts: ["http://localhost:8123"]
+    username: default
+    password: ""
+"#;
+
+#[test]
+fn test_presets_are_added_to_datasource_filters() {
+    let config = load(CONFIG_WITH_PRESETS).unwrap();
+
+    let filters = config.datasources[0]
+        .effective_filters(config.global_filters.as_ref())
+        .unwrap();
+    let sql_filters = SqlFilters::new(Some(&filters)).unwrap();
+
+    // Global rules still apply alongside the presets
+    assert!(sql_filters.should_exclude_database("test_db"));
+    assert!(sql_filters.should_exclude_column("CustomerName"));
+    assert!(sql_filters.should_exclude_column("iban"));
+    assert!(!sql_filters.should_exclude_column("revenue"));
+}
+
+#[test]
+fn test_datasource_without_presets_uses_global_filters_only() {
+    let config = load(CONFIG_WITH_PRESETS).unwrap();
+
+    let filters = config.datasources[1]
+        .effective_filters(config.global_filters.as_ref())
+        .unwrap();
+    let sql_filters = SqlFilters::new(Some(&filters)).unwrap();
+
+    assert!(sql_filters.should_exclude_database("test_db"));
+    assert!(!sql_filters.should_exclude_column("CustomerName"));
+}
+
+#[test]
+fn test_unknown_preset_is_rejected() {
+    let config = CONFIG_WITH_PRESETS.replace("pii_strict\", ", "pii_lenient\", ");
+    let error = load(&config).unwrap_err().to_string();
+    assert!(
+        error.contains("unknown filter preset 'pii_lenient'"),
+        "{}",
+        error
+    );
+
+    let config = CONFIG_WITH_PRESETS.replace("builtin:finance", "builtin:health");
+    let error = load(&config).unwrap_err().to_string();
+    assert!(error.contains("builtin:health"), "{}", error);
+}
+
+#[test]
+fn test_builtin_prefix_is_reserved() {
+    let config = CONFIG_WITH_PRESETS.replace("  pii_strict:", "  \"builtin:pii\":");
+    let error = load(&config).unwrap_err().to_string();
+    assert!(error.contains("reserved"), "{}", error);
+}
+
+#[test]
+fn test_builtin_pii_preset() {
+    let filters = SqlFilters::new(builtin_preset("builtin:pii").as_ref()).unwrap();
+
+    assert!(filters.should_exclude_column("user_email"));
+    assert!(filters.should_exclude_column("PhoneNumber"));
+    assert!(filters.should_exclude_column("last_name"));
+    assert!(!filters.should_exclude_column("event_count"));
+
+    assert!(filters.should_exclude_value("john.doe@example.com"));
+    assert!(filters.should_exclude_value("123-45-6789"));
+    assert!(filters.should_exclude_value("192.168.1.10"));
+    assert!(!filters.should_exclude_value("1712345678"));
+}
+
+#[test]
+fn test_builtin_finance_preset() {
+    let filters = SqlFilters::new(builtin_preset("builtin:finance").as_ref()).unwrap();
+
+    assert!(filters.should_exclude_column("card_number"));
+    assert!(filters.should_exclude_column("IBAN"));
+    assert!(filters.should_exclude_column("card_cvv"));
+    assert!(filters.should_exclude_column("CVC"));
+    assert!(!filters.should_exclude_column("amount"));
+    // Security codes are matched as whole words of the column name
+    assert!(!filters.should_exclude_column("xcvvx"));
+    assert!(!filters.should_exclude_column("dcvvx"));
+    assert!(!filters.should_exclude_column("abcvc"));
+
+    assert!(filters.should_exclude_value("4111111111111111"));
+    assert!(filters.should_exclude_value("DE89370400440532013000"));
+    // Millisecond timestamps aren't mistaken for card numbers
+    assert!(!filters.should_exclude_value("1712345678901"));
+}
+
+#[test]
+fn test_unknown_builtin_preset() {
+    assert!(builtin_preset("builtin:unknown").is_none());
+    assert!(builtin_preset("pii").is_none());
+}
@@ -0,0 +1,133 @@
+use tsight_agent::config::{FilterRule, GlobalFilters, QueryPolicy, RuleAction, SqlFilterRules};
+use tsight_agent::filters::{Decision, SqlFilters};
+use tsight_agent::policy::{check_query, DEFAULT_DATABASE};
+
+fn qualified(patterns: &[&str]) -> SqlFilterRules {
+    SqlFilterRules {
+        qualified_table_regexes: Some(patterns.iter().map(|p| p.to_string()).collect()),
+        ..Default::default()
+    }
+}
+
+fn exclude_filters() -> SqlFilters {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![qualified(&[r"^analytics\.events.*"])]),
+        ..Default::default()
+    };
+    SqlFilters::new(Some(&filters)).unwrap()
+}
+
+#[test]
+fn test_qualified_patterns_tell_databases_apart() {
+    let filters = exclude_filters();
+
+    assert!(filters.should_exclude_qualified_table("analytics", "events"));
+    assert!(filters.should_exclude_qualified_table("analytics", "events_daily"));
+    assert!(!filters.should_exclude_qualified_table("staging", "events"));
+    // Table names alone never match qualified patterns
+    assert!(!filters.should_exclude_table("events"));
+}
+
+#[test]
+fn test_table_patterns_still_apply_to_qualified_tables() {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            table_regexes: Some(vec!["^tmp_".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let filters = SqlFilters::new(Some(&filters)).unwrap();
+
+    assert!(filters.should_exclude_qualified_table("prod", "tmp_orders"));
+    assert!(!filters.should_exclude_qualified_table("prod", "orders"));
+}
+
+#[test]
+fn test_allow_rules_accept_either_kind_of_pattern() {
+    let filters = GlobalFilters {
+        sql_filters_allow: Some(vec![
+            qualified(&[r"^prod\.orders$"]),
+            SqlFilterRules {
+                table_regexes: Some(vec!["^users$".to_string()]),
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    };
+    let filters = SqlFilters::new(Some(&filters)).unwrap();
+
+    assert!(!filters.should_exclude_qualified_table("prod", "orders"));
+    assert!(!filters.should_exclude_qualified_table("staging", "users"));
+    assert_eq!(
+        filters.explain_qualified_table("staging", "orders"),
+        Decision::NotAllowed
+    );
+}
+
+#[test]
+fn test_ordered_rules_match_qualified_names() {
+    let filters = GlobalFilters {
+        rules: Some(vec![
+            FilterRule {
+                name: Some("staging_events".to_string()),
+                matcher: qualified(&[r"^staging\.events$"]),
+                action: RuleAction::Allow,
+            },
+            FilterRule {
+                name: None,
+                matcher: SqlFilterRules {
+                    table_regexes: Some(vec!["^events$".to_string()]),
+                    ..Default::default()
+                },
+                action: RuleAction::Deny,
+            },
+        ]),
+        ..Default::default()
+    };
+    let filters = SqlFilters::new(Some(&filters)).unwrap();
+
+    assert!(!filters.should_exclude_qualified_table("staging", "events"));
+    assert!(filters.should_exclude_qualified_table("analytics", "events"));
+    assert_eq!(
+        filters
+            .explain_qualified_table("staging", "events")
+            .to_string(),
+        r"kept, allowed by rule 'staging_events' (pattern '^staging\.events$')"
+    );
+}
+
+#[test]
+fn test_query_policy_checks_qualified_names() {
+    let policy = QueryPolicy::default();
+    let filters = exclude_filters();
+
+    let error = check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "SELECT * FROM analytics.events",
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("table 'analytics.events' is excluded by filters"));
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "SELECT * FROM staging.events"
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_unqualified_tables_are_checked_in_datasource_database() {
+    let policy = QueryPolicy::default();
+    let filters = exclude_filters();
+
+    let error = check_query(&policy, Some(&filters), "analytics", "SELECT * FROM events")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("table 'analytics.events' is excluded by filters"));
+    assert!(check_query(&policy, Some(&filters), "staging", "SELECT * FROM events").is_ok());
+}
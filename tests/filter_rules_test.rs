@@ -0,0 +1,180 @@
+use tsight_agent::config::{FilterAction, FilterRule, GlobalFilters, RuleAction, SqlFilterRules};
+use tsight_agent::filters::{Decision, FilterError, SqlFilters};
+
+fn rule(action: RuleAction, matcher: SqlFilterRules) -> FilterRule {
+    FilterRule {
+        name: None,
+        matcher,
+        action,
+    }
+}
+
+fn columns(patterns: &[&str]) -> SqlFilterRules {
+    SqlFilterRules {
+        column_name_regexes: Some(patterns.iter().map(|p| p.to_string()).collect()),
+        ..Default::default()
+    }
+}
+
+fn filters(rules: Vec<FilterRule>) -> SqlFilters {
+    let filters = GlobalFilters {
+        rules: Some(rules),
+        ..Default::default()
+    };
+    SqlFilters::new(Some(&filters)).unwrap()
+}
+
+#[test]
+fn test_first_matching_rule_wins() {
+    let filters = filters(vec![
+        rule(RuleAction::Allow, columns(&["^email_domain$"])),
+        rule(RuleAction::Mask, columns(&["email"])),
+        rule(RuleAction::Deny, columns(&[".*"])),
+    ]);
+
+    assert!(matches!(
+        filters.explain_column("email_domain"),
+        Decision::Allowed(_)
+    ));
+    assert_eq!(
+        filters.column_action("user_email"),
+        Some(FilterAction::Mask)
+    );
+    assert!(filters.should_exclude_column("user_id"));
+}
+
+#[test]
+fn test_order_matters() {
+    let deny_first = filters(vec![
+        rule(RuleAction::Deny, columns(&["email"])),
+        rule(RuleAction::Allow, columns(&["email"])),
+    ]);
+    let allow_first = filters(vec![
+        rule(RuleAction::Allow, columns(&["email"])),
+        rule(RuleAction::Deny, columns(&["email"])),
+    ]);
+
+    assert!(deny_first.should_exclude_column("email"));
+    assert!(!allow_first.should_exclude_column("email"));
+}
+
+#[test]
+fn test_unmatched_names_fall_back_to_legacy_lists() {
+    let filters = GlobalFilters {
+        rules: Some(vec![rule(RuleAction::Allow, columns(&["^ssn_last4$"]))]),
+        sql_filters_exclude: Some(vec![columns(&["ssn"])]),
+        ..Default::default()
+    };
+    let filters = SqlFilters::new(Some(&filters)).unwrap();
+
+    assert!(!filters.should_exclude_column("ssn_last4"));
+    assert!(filters.should_exclude_column("ssn"));
+    assert!(!filters.should_exclude_column("user_id"));
+}
+
+#[test]
+fn test_databases_and_tables_rules() {
+    let filters = filters(vec![
+        rule(
+            RuleAction::Allow,
+            SqlFilterRules {
+                table_regexes: Some(vec!["^public_".to_string()]),
+                ..Default::default()
+            },
+        ),
+        rule(
+            RuleAction::Mask,
+            SqlFilterRules {
+                database_regexes: Some(vec!["^secret$".to_string()]),
+                table_regexes: Some(vec![".*".to_string()]),
+                ..Default::default()
+            },
+        ),
+    ]);
+
+    // Rules rewriting values deny databases and tables
+    assert!(filters.should_exclude_database("secret"));
+    assert!(!filters.should_exclude_database("prod"));
+    assert!(!filters.should_exclude_table("public_events"));
+    assert!(filters.should_exclude_table("orders"));
+}
+
+#[test]
+fn test_value_rules_are_ordered_with_checks() {
+    let filters = filters(vec![
+        FilterRule {
+            name: Some("keep_ids".to_string()),
+            matcher: SqlFilterRules {
+                column_value_regexes: Some(vec!["^id-".to_string()]),
+                ..Default::default()
+            },
+            action: RuleAction::Allow,
+        },
+        FilterRule {
+            name: Some("long_values".to_string()),
+            matcher: SqlFilterRules {
+                max_length: Some(8),
+                ..Default::default()
+            },
+            action: RuleAction::RedactValue,
+        },
+    ]);
+
+    assert_eq!(filters.value_match("id-1234567890"), None);
+    let decision = filters.explain_value("abcdefghij");
+    assert_eq!(
+        decision.to_string(),
+        "redacted by rule 'long_values' (pattern 'max_length 8')"
+    );
+    assert!(!filters.should_exclude_value("short"));
+}
+
+#[test]
+fn test_hash_rules_require_key() {
+    let filters = GlobalFilters {
+        rules: Some(vec![rule(RuleAction::Hash, columns(&["user_id"]))]),
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        SqlFilters::new(Some(&filters)),
+        Err(FilterError::MissingHashKey)
+    ));
+}
+
+#[test]
+fn test_rules_parsed_from_yaml() {
+    let filters: GlobalFilters = config::Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+rules:
+  - match:
+      column_name_regexes: ["email"]
+      match: substring
+    action: mask
+  - name: everything_else
+    match:
+      column_name_regexes: [".*"]
+    action: deny
+"#,
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    let filters = SqlFilters::new(Some(&filters)).unwrap();
+    assert_eq!(
+        filters.column_action("Email.address"),
+        Some(FilterAction::DropRow)
+    );
+    assert_eq!(
+        filters.column_action("user_email"),
+        Some(FilterAction::Mask)
+    );
+    assert_eq!(
+        filters.explain_column("id").to_string(),
+        "excluded by rule 'everything_else' (pattern '.*')"
+    );
+}
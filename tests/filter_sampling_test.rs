@@ -0,0 +1,118 @@
+use mockito::{Matcher, Server};
+use serde_json::json;
+use tsight_agent::client::{ServerApi, ServerClient};
+use tsight_agent::config::GlobalFilters;
+use tsight_agent::executors::base::{ExecutionStats, QueryExecutor};
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+use tsight_agent::filters::{FilterError, FilterStats, SqlFilters};
+use tsight_agent::models::JobType;
+
+fn filters(max_rows: Option<usize>, sample_rate: Option<f64>) -> SqlFilters {
+    let filters = GlobalFilters {
+        max_rows,
+        sample_rate,
+        ..Default::default()
+    };
+    SqlFilters::new(Some(&filters)).unwrap()
+}
+
+#[test]
+fn test_max_rows_keeps_first_rows() {
+    let mut stats = FilterStats::default();
+    let rows = filters(Some(3), None).limit_rows((0..10).collect(), &mut stats);
+
+    assert_eq!(rows, vec![0, 1, 2]);
+    assert_eq!(stats.sampled_rows, 7);
+    assert!(stats.sampled());
+}
+
+#[test]
+fn test_max_rows_with_sampling_draws_ordered_subset() {
+    let mut stats = FilterStats::default();
+    let rows = filters(Some(5), Some(1.0)).limit_rows((0..1000).collect(), &mut stats);
+
+    assert_eq!(rows.len(), 5);
+    assert!(rows.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(stats.sampled_rows, 995);
+}
+
+#[test]
+fn test_sample_rate_keeps_fraction_of_rows() {
+    let mut stats = FilterStats::default();
+    let rows = filters(None, Some(0.5)).limit_rows((0..10_000).collect(), &mut stats);
+
+    assert!((4_000..6_000).contains(&rows.len()), "{}", rows.len());
+    assert_eq!(stats.sampled_rows as usize, 10_000 - rows.len());
+}
+
+#[test]
+fn test_rows_within_bounds_are_not_sampled() {
+    let mut stats = FilterStats::default();
+    let rows = filters(Some(10), Some(1.0)).limit_rows((0..10).collect(), &mut stats);
+
+    assert_eq!(rows.len(), 10);
+    assert!(!stats.sampled());
+}
+
+#[test]
+fn test_invalid_sample_rate_is_rejected() {
+    for rate in [0.0, -0.5, 1.5, f64::NAN] {
+        let filters = GlobalFilters {
+            sample_rate: Some(rate),
+            ..Default::default()
+        };
+        assert!(matches!(
+            SqlFilters::new(Some(&filters)),
+            Err(FilterError::InvalidSampleRate(_))
+        ));
+    }
+}
+
+#[test]
+fn test_job_results_are_capped() {
+    let filters = GlobalFilters {
+        max_rows: Some(2),
+        ..Default::default()
+    };
+    let executor = ClickhouseExecutor::with_global_filters(
+        "http://localhost:8123",
+        "default",
+        "",
+        Some(filters),
+    )
+    .unwrap();
+    let rows: Vec<JobType> = (0..4)
+        .map(|id| serde_json::from_value(json!({ "id": id })).unwrap())
+        .collect();
+
+    let (rows, stats) = executor.filter_job_results_with_stats(rows);
+
+    assert_eq!(rows.len(), 2);
+    assert_eq!(stats.sampled_rows, 2);
+}
+
+#[tokio::test]
+async fn test_sampled_marker_submitted_with_job_results() {
+    let stats = FilterStats {
+        sampled_rows: 5,
+        ..Default::default()
+    };
+
+    let mut server = Server::new_async().await;
+    let submit_mock = server
+        .mock("POST", "/jobs/1/submit")
+        .match_body(Matcher::PartialJson(json!({
+            "sampled": true,
+            "filter_stats": {"sampled_rows": 5}
+        })))
+        .with_status(200)
+        .create();
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client
+        .submit_job_results("1", vec![], vec![], stats, ExecutionStats::default())
+        .await
+        .unwrap();
+
+    submit_mock.assert();
+}
@@ -0,0 +1,139 @@
+use mockito::{Matcher, Server};
+use serde_json::{json, Value};
+use tsight_agent::client::{ServerApi, ServerClient};
+use tsight_agent::config::{FilterAction, GlobalFilters, SqlFilterRules};
+use tsight_agent::executors::base::{ExecutionStats, QueryExecutor};
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+use tsight_agent::filters::{FilterStats, ALLOW_LIST_RULE};
+use tsight_agent::models::JobType;
+
+fn executor(filters: GlobalFilters) -> ClickhouseExecutor {
+    ClickhouseExecutor::with_global_filters("http://localhost:8123", "default", "", Some(filters))
+        .unwrap()
+}
+
+fn row(value: Value) -> JobType {
+    serde_json::from_value(value).unwrap()
+}
+
+fn rows() -> Vec<JobType> {
+    vec![
+        row(json!({"email": "john@example.com", "note": "ok"})),
+        row(json!({"email": "jane@example.com", "note": "card 4111 1111 1111 1111"})),
+        row(json!({"email": "joe@example.com", "note": "card 5500 0000 0000 0004"})),
+    ]
+}
+
+fn rules() -> Vec<SqlFilterRules> {
+    vec![
+        SqlFilterRules {
+            column_value_regexes: Some(vec![r"^card\d{16}$".to_string()]),
+            name: Some("card_numbers".to_string()),
+            ..Default::default()
+        },
+        SqlFilterRules {
+            column_name_regexes: Some(vec!["^email$".to_string()]),
+            action: Some(FilterAction::RedactValue),
+            ..Default::default()
+        },
+    ]
+}
+
+#[test]
+fn test_stats_count_dropped_rows_and_masked_values() {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(rules()),
+        ..Default::default()
+    };
+    let (filtered, stats) = executor(filters).filter_job_results_with_stats(rows());
+
+    assert_eq!(filtered.len(), 1);
+    assert_eq!(stats.dropped_rows, 2);
+    assert_eq!(stats.rules["card_numbers"], 2);
+    // Only the email of the kept row is submitted redacted
+    assert_eq!(stats.masked_values, 1);
+    assert!(stats.rules["sql_filters_exclude[1]"] >= 1);
+}
+
+#[test]
+fn test_stats_skip_masked_values_of_dropped_rows() {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(rules()),
+        ..Default::default()
+    };
+    let rows = vec![row(json!({
+        "email": "jane@example.com",
+        "note": "card 4111 1111 1111 1111",
+    }))];
+    let (filtered, stats) = executor(filters).filter_job_results_with_stats(rows);
+
+    assert!(filtered.is_empty());
+    assert_eq!(stats.dropped_rows, 1);
+    // The email may be redacted before the note drops the row, but it's
+    // never submitted
+    assert_eq!(stats.masked_values, 0);
+}
+
+#[test]
+fn test_stats_name_allow_list_rejections() {
+    let filters = GlobalFilters {
+        sql_filters_allow: Some(vec![SqlFilterRules {
+            column_name_regexes: Some(vec!["^note$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let (filtered, stats) = executor(filters).filter_job_results_with_stats(rows());
+
+    assert!(filtered.is_empty());
+    assert_eq!(stats.dropped_rows, 3);
+    assert_eq!(stats.rules[ALLOW_LIST_RULE], 3);
+}
+
+#[test]
+fn test_stats_empty_without_matches() {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            column_name_regexes: Some(vec!["^password$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let (filtered, stats) = executor(filters).filter_job_results_with_stats(rows());
+
+    assert_eq!(filtered.len(), 3);
+    assert!(stats.is_empty());
+    assert_eq!(stats.to_string(), "0 rows dropped, 0 values masked");
+}
+
+#[tokio::test]
+async fn test_filter_stats_submitted_with_job_results() {
+    let mut stats = FilterStats {
+        dropped_rows: 2,
+        masked_values: 1,
+        ..Default::default()
+    };
+    stats.rules.insert("card_numbers".to_string(), 3);
+
+    let mut server = Server::new_async().await;
+    let submit_mock = server
+        .mock("POST", "/jobs/1/submit")
+        .match_body(Matcher::PartialJson(json!({
+            "records": [],
+            "filter_stats": {
+                "dropped_rows": 2,
+                "masked_values": 1,
+                "rules": {"card_numbers": 3}
+            }
+        })))
+        .with_status(200)
+        .create();
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client
+        .submit_job_results("1", vec![], vec![], stats, ExecutionStats::default())
+        .await
+        .unwrap();
+
+    submit_mock.assert();
+}
@@ -5,15 +5,21 @@ use tsight_agent::filters::SqlFilters;
 #[test]
 fn test_sql_filters() {
     // Create test filter rules
-    let mut exclude_rules = SqlFilterRules::default();
-    exclude_rules.database_regexes = Some(vec!["^test_.*".to_string(), "^_.*".to_string()]);
+    let exclude_rules = SqlFilterRules {
+        database_regexes: Some(vec!["^test_.*".to_string(), "^_.*".to_string()]),
+        ..Default::default()
+    };
 
-    let mut allow_rules = SqlFilterRules::default();
-    allow_rules.database_regexes = Some(vec!["^prod_.*".to_string()]);
+    let allow_rules = SqlFilterRules {
+        database_regexes: Some(vec!["^prod_.*".to_string()]),
+        ..Default::default()
+    };
 
-    let mut global_filters = GlobalFilters::default();
-    global_filters.sql_filters_exclude = Some(vec![exclude_rules]);
-    global_filters.sql_filters_allow = Some(vec![allow_rules]);
+    let global_filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![exclude_rules]),
+        sql_filters_allow: Some(vec![allow_rules]),
+        ..Default::default()
+    };
 
     // Create SQL filters
     let sql_filters = SqlFilters::new(Some(&global_filters)).unwrap();
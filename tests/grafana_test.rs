@@ -0,0 +1,331 @@
+use base64::Engine;
+use serde_json::{json, Value};
+use std::fs;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tsight_agent::agent::BaseAgent;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::config::{Config, GrafanaConfig};
+use tsight_agent::grafana::{GrafanaApi, GrafanaEndpoint, QueryRequest, QueryResult};
+
+const CONFIG: &str = r#"server:
+  api_key: "test-api-key"
+  server_url: "http://localhost:8080"
+
+datasources:
+  - name: "demo"
+    source_type: "mock"
+    mock:
+      results:
+        - query_regex: "(?i)from users"
+          columns:
+            - {name: "id", type: "UInt64"}
+            - {name: "email", type: "String"}
+          rows:
+            - {id: 1, email: "alice@example.com"}
+            - {id: 2, email: "bob@internal.example.com"}
+        - query_regex: "(?i)from events"
+          columns:
+            - {name: "t", type: "UInt64"}
+            - {name: "cnt", type: "UInt64"}
+            - {name: "status", type: "String"}
+          rows:
+            - {t: 1709251260, cnt: 5, status: "ok"}
+            - {t: 1709251200, cnt: 3, status: "ok"}
+            - {t: 1709251200, cnt: 1, status: "error"}
+        - query_regex: "missing"
+          error: "Unknown table"
+  - name: "hidden"
+    source_type: "mock"
+    mock:
+      results: []
+
+global_filters:
+  sql_filters_exclude:
+    - column_value_regexes:
+        - "@internal\\.example\\.com$"
+      action: "drop_row"
+"#;
+
+fn load_config() -> Config {
+    let dir = TempDir::new().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    fs::write(&config_path, CONFIG).unwrap();
+    Config::load(&config_path).unwrap()
+}
+
+fn grafana_config(api_key: Option<&str>, datasources: Option<&[&str]>) -> GrafanaConfig {
+    serde_json::from_value(json!({
+        "address": "127.0.0.1:0",
+        "api_key": api_key,
+        "datasources": datasources,
+    }))
+    .unwrap()
+}
+
+fn agents(config: &Config) -> Vec<BaseAgent> {
+    vec![BaseAgent::with_filters(
+        Arc::new(FakeServer::new()),
+        config.datasources.clone(),
+        config.global_filters.clone(),
+    )]
+}
+
+fn api(datasources: Option<&[&str]>) -> GrafanaApi {
+    let config = load_config();
+    GrafanaApi::new(&grafana_config(None, datasources), agents(&config))
+}
+
+fn query(body: Value) -> QueryRequest {
+    serde_json::from_value(body).unwrap()
+}
+
+#[tokio::test]
+async fn test_table_target_is_filtered() {
+    let results = api(None)
+        .query(&query(json!({
+            "targets": [{
+                "refId": "A",
+                "type": "table",
+                "target": "SELECT id, email FROM users",
+                "payload": {"datasource": "demo"},
+            }],
+        })))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        serde_json::to_value(&results).unwrap(),
+        json!([{
+            "type": "table",
+            "columns": [
+                {"text": "id", "type": "number"},
+                {"text": "email", "type": "string"},
+            ],
+            "rows": [[1, "alice@example.com"]],
+        }])
+    );
+}
+
+#[tokio::test]
+async fn test_timeserie_target_has_a_series_per_value_and_labels() {
+    let results = api(Some(&["demo"]))
+        .query(&query(json!({
+            "range": {"from": "2024-03-01T00:00:00Z", "to": "2024-03-01T06:00:00Z"},
+            "intervalMs": 60000,
+            "targets": [
+                {"refId": "A", "target": "SELECT t, cnt, status FROM events"},
+                {"refId": "B", "target": "SELECT * FROM users", "hide": true},
+            ],
+        })))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        results,
+        vec![
+            QueryResult::Series {
+                target: "cnt {status=\"error\"}".to_string(),
+                datapoints: vec![(1.0, 1709251200000)],
+            },
+            QueryResult::Series {
+                target: "cnt {status=\"ok\"}".to_string(),
+                datapoints: vec![(3.0, 1709251200000), (5.0, 1709251260000)],
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_target_payload_as_text_maps_columns() {
+    let results = api(None)
+        .query(&query(json!({
+            "targets": [{
+                "refId": "A",
+                "target": "SELECT t, cnt, status FROM events",
+                "data": "{\"datasource\": \"demo\", \"label_columns\": []}",
+            }],
+        })))
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 1);
+    let QueryResult::Series { target, datapoints } = &results[0] else {
+        panic!("{:?}", results);
+    };
+    assert_eq!(target, "cnt");
+    assert_eq!(datapoints.len(), 3);
+}
+
+#[tokio::test]
+async fn test_target_errors() {
+    let unrestricted = api(None);
+    let error = unrestricted
+        .query(&query(
+            json!({"targets": [{"refId": "A", "target": "SELECT 1"}]}),
+        ))
+        .await
+        .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("names no datasource, one of: demo, hidden"),
+        "{}",
+        error
+    );
+
+    let error = unrestricted
+        .query(&query(json!({
+            "targets": [{"refId": "A", "target": "SELECT * FROM missing", "payload": {"datasource": "demo"}}],
+        })))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("Target A failed"), "{}", error);
+    assert!(error.to_string().contains("Unknown table"), "{}", error);
+
+    let restricted = api(Some(&["demo"]));
+    let error = restricted
+        .query(&query(json!({
+            "targets": [{"refId": "B", "target": "SELECT 1", "payload": {"datasource": "hidden"}}],
+        })))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Datasource hidden of target B can't be queried from Grafana"
+    );
+}
+
+#[test]
+fn test_authorization() {
+    let config = load_config();
+    let api = GrafanaApi::new(&grafana_config(Some("grafana-key"), None), agents(&config));
+    let basic = |credentials: &str| {
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        )
+    };
+
+    assert!(api.is_authorized(Some("Bearer grafana-key")));
+    assert!(api.is_authorized(Some(&basic("grafana:grafana-key"))));
+    assert!(!api.is_authorized(None));
+    assert!(!api.is_authorized(Some("Bearer other-key")));
+    assert!(!api.is_authorized(Some(&basic("grafana-key:other"))));
+    assert!(GrafanaApi::new(&grafana_config(None, None), agents(&config)).is_authorized(None));
+}
+
+#[tokio::test]
+async fn test_endpoint_serves_grafana_routes() {
+    let config = load_config();
+    let endpoint = GrafanaEndpoint::bind(
+        &grafana_config(Some("grafana-key"), Some(&["demo"])),
+        agents(&config),
+    )
+    .await
+    .unwrap();
+    let address = endpoint.local_addr().unwrap();
+    endpoint.spawn();
+    let client = reqwest::Client::new();
+    let url = |path: &str| format!("http://{}{}", address, path);
+
+    let response = client.get(url("/")).send().await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    let response = client
+        .get(url("/"))
+        .bearer_auth("grafana-key")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let response = client
+        .post(url("/search"))
+        .bearer_auth("grafana-key")
+        .json(&json!({"target": ""}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.json::<Value>().await.unwrap(), json!(["demo"]));
+
+    let response = client
+        .post(url("/query"))
+        .basic_auth("grafana", Some("grafana-key"))
+        .json(&json!({
+            "targets": [{"refId": "A", "type": "table", "target": "SELECT * FROM users"}],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let results: Value = response.json().await.unwrap();
+    assert_eq!(results[0]["rows"], json!([[1, "alice@example.com"]]));
+
+    let response = client
+        .post(url("/query"))
+        .bearer_auth("grafana-key")
+        .json(&json!({"targets": [{"refId": "A", "target": "SELECT * FROM missing"}]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert!(error["message"].as_str().unwrap().contains("Unknown table"));
+
+    let response = client
+        .post(url("/annotations"))
+        .bearer_auth("grafana-key")
+        .json(&json!({}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.json::<Value>().await.unwrap(), json!([]));
+}
+
+#[test]
+fn test_exposed_datasources_must_be_configured() {
+    let dir = TempDir::new().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    let content = format!(
+        "{}\ngrafana:\n  address: \"127.0.0.1:3100\"\n  datasources: [\"nope\"]\n",
+        CONFIG
+    );
+    fs::write(&config_path, content).unwrap();
+    let error = Config::load(&config_path).unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("Datasource 'nope' exposed to Grafana isn't configured"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_api_key_is_required_off_loopback() {
+    let load = |grafana: &str| {
+        let dir = TempDir::new().unwrap();
+        let config_path = dir.path().join("config.yaml");
+        fs::write(&config_path, format!("{}\ngrafana:\n{}", CONFIG, grafana)).unwrap();
+        Config::load(&config_path).map_err(|e| e.to_string())
+    };
+
+    for address in [
+        "0.0.0.0:3100",
+        "10.1.2.3:3100",
+        "[::]:3100",
+        "agent.internal:3100",
+    ] {
+        let error = load(&format!("  address: \"{}\"\n", address)).unwrap_err();
+        assert!(
+            error.contains("grafana.api_key must be set to listen on"),
+            "{}",
+            error
+        );
+    }
+    for address in ["127.0.0.1:3100", "[::1]:3100", "localhost:3100"] {
+        assert!(load(&format!("  address: \"{}\"\n", address)).is_ok());
+    }
+    assert!(load("  address: \"0.0.0.0:3100\"\n  api_key: \"grafana-key\"\n").is_ok());
+}
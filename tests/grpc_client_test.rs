@@ -0,0 +1,424 @@
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::HeaderMap;
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use prost::Message;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::Infallible;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tsight_agent::agent::factory::create_observation_agent_with_client;
+use tsight_agent::client::grpc::proto::{self, Queue};
+use tsight_agent::client::grpc::GrpcServerClient;
+use tsight_agent::client::manifest::Manifest;
+use tsight_agent::client::{AcquireResultBody, BackoffRequested, JobKind, ServerApi, TimeRange};
+use tsight_agent::config::{Config, GrpcConfig, MockConfig, MockResult, ServerConfig};
+use tsight_agent::errors::NoWorkAvailable;
+use tsight_agent::executors::base::ExecutionStats;
+use tsight_agent::filters::FilterStats;
+use tsight_agent::models::{DataSource, DataSourceType, JobType, Record};
+use tsight_agent::timeseries::NullStats;
+
+/// Call received by the test server
+struct Call {
+    method: String,
+    metadata: HeaderMap,
+    message: Vec<u8>,
+}
+
+/// gRPC server handing out queued tasks and recording the calls it gets
+#[derive(Default)]
+struct TestServer {
+    tasks: Mutex<HashMap<i32, VecDeque<proto::Task>>>,
+    calls: Mutex<Vec<Call>>,
+    /// HTTP/2 requests, one per call or stream
+    requests: Mutex<usize>,
+    /// Status code and `retry-after` every call fails with
+    failure: Mutex<Option<(i32, &'static str)>>,
+}
+
+impl TestServer {
+    fn enqueue(&self, queue: Queue, task: AcquireResultBody) {
+        self.tasks
+            .lock()
+            .unwrap()
+            .entry(queue as i32)
+            .or_default()
+            .push_back(task.try_into().unwrap());
+    }
+
+    fn next_task(&self, queue: i32) -> Option<proto::Task> {
+        self.tasks.lock().unwrap().get_mut(&queue)?.pop_front()
+    }
+
+    fn calls(&self, method: &str) -> Vec<Vec<u8>> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| call.method == method)
+            .map(|call| call.message.clone())
+            .collect()
+    }
+
+    /// Answer one message of a call, or fail it with a status code
+    fn answer(&self, method: &str, message: &[u8]) -> Result<Vec<u8>, i32> {
+        match method {
+            "Acquire" => {
+                let request = proto::AcquireRequest::decode(message).unwrap();
+                let task = self.next_task(request.queue).ok_or(5)?;
+                Ok(task.encode_to_vec())
+            }
+            "Session" => {
+                let proto::AgentMessage {
+                    message: Some(proto::agent_message::Message::Ready(ready)),
+                } = proto::AgentMessage::decode(message).unwrap()
+                else {
+                    return Err(3);
+                };
+                let message = match self.next_task(ready.queue) {
+                    Some(task) => proto::server_message::Message::Task(Box::new(task)),
+                    None => proto::server_message::Message::NoWork(proto::NoWork {}),
+                };
+                Ok(proto::ServerMessage {
+                    queue: ready.queue,
+                    message: Some(message),
+                }
+                .encode_to_vec())
+            }
+            _ => Ok(proto::Ack {}.encode_to_vec()),
+        }
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        request: Request<Incoming>,
+    ) -> Result<Response<StreamBody<ReceiverStream<Result<Frame<Bytes>, Infallible>>>>, Infallible>
+    {
+        *self.requests.lock().unwrap() += 1;
+        let method = request.uri().path().rsplit('/').next().unwrap().to_string();
+        let metadata = request.headers().clone();
+        let (frames, body) = mpsc::channel(16);
+        let mut request_body = request.into_body();
+        tokio::spawn(async move {
+            let mut buffer = Vec::new();
+            let mut status = 0;
+            'read: while let Some(Ok(frame)) = request_body.frame().await {
+                let Ok(data) = frame.into_data() else {
+                    continue;
+                };
+                buffer.extend_from_slice(&data);
+                while buffer.len() >= 5 {
+                    let length = u32::from_be_bytes(buffer[1..5].try_into().unwrap()) as usize;
+                    if buffer.len() < 5 + length {
+                        break;
+                    }
+                    let message: Vec<u8> = buffer.drain(..5 + length).skip(5).collect();
+                    self.calls.lock().unwrap().push(Call {
+                        method: method.clone(),
+                        metadata: metadata.clone(),
+                        message: message.clone(),
+                    });
+                    let failure = *self.failure.lock().unwrap();
+                    let answer = match failure {
+                        Some((code, _)) => Err(code),
+                        None => self.answer(&method, &message),
+                    };
+                    match answer {
+                        Ok(answer) => {
+                            let mut frame = vec![0];
+                            frame.extend_from_slice(&(answer.len() as u32).to_be_bytes());
+                            frame.extend_from_slice(&answer);
+                            let _ = frames.send(Ok(Frame::data(Bytes::from(frame)))).await;
+                        }
+                        Err(code) => {
+                            status = code;
+                            break 'read;
+                        }
+                    }
+                }
+            }
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", status.into());
+            if let Some((_, retry_after)) = *self.failure.lock().unwrap() {
+                trailers.insert("retry-after", retry_after.parse().unwrap());
+            }
+            let _ = frames.send(Ok(Frame::trailers(trailers))).await;
+        });
+        Ok(Response::builder()
+            .header("content-type", "application/grpc")
+            .body(StreamBody::new(ReceiverStream::new(body)))
+            .unwrap())
+    }
+
+    /// Serve gRPC over plaintext HTTP/2, returning its URL
+    async fn start(self: &Arc<Self>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = self.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let service =
+                        hyper::service::service_fn(move |request| server.clone().handle(request));
+                    let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        format!("http://{}", address)
+    }
+}
+
+fn task(id: &str) -> AcquireResultBody {
+    AcquireResultBody {
+        id: id.to_string(),
+        datasource_name: "demo".to_string(),
+        query: "SELECT t, cnt FROM events".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: Some(30),
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    }
+}
+
+async fn client(server: &Arc<TestServer>, server_url: &str, push: bool) -> GrpcServerClient {
+    let config = ServerConfig {
+        api_key: "test-api-key".to_string(),
+        server_url: server_url.to_string(),
+        extra_headers: Some(HashMap::from([(
+            "X-Org-Id".to_string(),
+            "acme".to_string(),
+        )])),
+        ..Default::default()
+    };
+    let grpc = GrpcConfig {
+        url: server.start().await,
+        push,
+    };
+    GrpcServerClient::from_config(&config, &grpc).unwrap()
+}
+
+fn no_work(error: &anyhow::Error) -> &'static str {
+    error.downcast_ref::<NoWorkAvailable>().unwrap().0
+}
+
+#[tokio::test]
+async fn test_tasks_acquired_with_calls() {
+    let server = Arc::new(TestServer::default());
+    server.enqueue(Queue::HighPriority, task("task-1"));
+    let client = client(&server, "http://localhost:1", false).await;
+
+    let acquired = client.acquire_next_query(true).await.unwrap();
+    let error = client.acquire_next_query(true).await.unwrap_err();
+
+    assert_eq!(acquired.id, "task-1");
+    assert_eq!(acquired.timeout, Some(30));
+    assert_eq!(no_work(&error), "tasks");
+    let calls = server.calls.lock().unwrap();
+    assert_eq!(calls[0].metadata["authorization"], "Bearer test-api-key");
+    assert_eq!(calls[0].metadata["x-org-id"], "acme");
+    assert_eq!(calls[0].metadata["x-tsight-protocol-version"], "2");
+}
+
+#[tokio::test]
+async fn test_tasks_pushed_over_one_stream() {
+    let server = Arc::new(TestServer::default());
+    server.enqueue(Queue::Observations, task("task-1"));
+    server.enqueue(Queue::Observations, task("task-2"));
+    server.enqueue(Queue::Jobs, task("job-1"));
+    let client = client(&server, "http://localhost:1", true).await;
+
+    let first = client.acquire_next_query(false).await.unwrap();
+    let job = client.acquire_next_job().await.unwrap();
+    let second = client.acquire_next_query(false).await.unwrap();
+    let error = client.acquire_next_job().await.unwrap_err();
+
+    assert_eq!(first.id, "task-1");
+    assert_eq!(job.id, "job-1");
+    assert_eq!(second.id, "task-2");
+    assert_eq!(no_work(&error), "jobs");
+    assert_eq!(server.calls("Session").len(), 4);
+    assert_eq!(*server.requests.lock().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_results_submitted_as_typed_records() {
+    let server = Arc::new(TestServer::default());
+    let client = client(&server, "http://localhost:1", false).await;
+    let records = vec![Record {
+        t: 60_000,
+        values: BTreeMap::from([("cnt".to_string(), 3.0)]),
+        labels: BTreeMap::from([("region".to_string(), "eu".to_string())]),
+    }];
+
+    client
+        .submit_results(
+            "task-1",
+            records.clone(),
+            NullStats::default(),
+            ExecutionStats::default(),
+            true,
+        )
+        .await
+        .unwrap();
+
+    let submitted = proto::TaskResults::decode(&server.calls("SubmitTaskResults")[0][..]).unwrap();
+    assert_eq!(submitted.task_id, "task-1");
+    assert_eq!(submitted.queue(), Queue::HighPriority);
+    let received: Vec<Record> = submitted.records.into_iter().map(Into::into).collect();
+    assert_eq!(received, records);
+    assert_eq!(
+        submitted.manifest,
+        Some(Manifest::of(&records).unwrap().into())
+    );
+}
+
+#[tokio::test]
+async fn test_job_rows_and_errors_submitted() {
+    let server = Arc::new(TestServer::default());
+    let client = client(&server, "http://localhost:1", false).await;
+    let row: JobType = serde_json::from_value(json!({"one": 1})).unwrap();
+
+    client
+        .submit_job_results(
+            "job-1",
+            vec![row],
+            vec![],
+            FilterStats::default(),
+            ExecutionStats::default(),
+        )
+        .await
+        .unwrap();
+    client.submit_job_error("job-2", "boom").await.unwrap();
+
+    let results = proto::JobResults::decode(&server.calls("SubmitJobResults")[0][..]).unwrap();
+    assert_eq!(results.rows_json, vec![r#"{"one":1}"#]);
+    let error = proto::TaskError::decode(&server.calls("SubmitError")[0][..]).unwrap();
+    assert_eq!((error.id.as_str(), error.queue()), ("job-2", Queue::Jobs));
+}
+
+#[tokio::test]
+async fn test_unavailable_server_requests_backoff() {
+    let server = Arc::new(TestServer::default());
+    *server.failure.lock().unwrap() = Some((14, "7"));
+    let client = client(&server, "http://localhost:1", false).await;
+
+    let error = client.acquire_next_job().await.unwrap_err();
+
+    let backoff = error.downcast_ref::<BackoffRequested>().unwrap();
+    assert_eq!(backoff.retry_after, Duration::from_secs(7));
+}
+
+#[tokio::test]
+async fn test_other_calls_use_http_api() {
+    let mut http = mockito::Server::new_async().await;
+    let add_mock = http
+        .mock("POST", "/datasource/demo/add")
+        .with_status(200)
+        .create_async()
+        .await;
+    let server = Arc::new(TestServer::default());
+    let client = client(&server, &http.url(), true).await;
+
+    client
+        .add_datasource("demo", "mock", &BTreeMap::new())
+        .await
+        .unwrap();
+
+    add_mock.assert();
+    assert!(server.calls.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_agent_runs_pushed_task() {
+    let rows: Vec<JobType> = [(0, 1), (60, 2)]
+        .into_iter()
+        .map(|(t, cnt)| serde_json::from_value(json!({"t": t, "cnt": cnt})).unwrap())
+        .collect();
+    let datasource = DataSource {
+        name: "demo".to_string(),
+        source_type: DataSourceType::Mock,
+        mock: MockConfig {
+            results: vec![MockResult {
+                rows,
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let server = Arc::new(TestServer::default());
+    server.enqueue(Queue::Observations, task("task-1"));
+    let client = client(&server, "http://localhost:1", true).await;
+
+    let agent =
+        create_observation_agent_with_client(Arc::new(client), vec![datasource], false, None);
+    agent.process_next().await.unwrap();
+
+    let submitted = proto::TaskResults::decode(&server.calls("SubmitTaskResults")[0][..]).unwrap();
+    let points: Vec<(i64, f64)> = submitted
+        .records
+        .iter()
+        .map(|record| (record.t, record.values["cnt"]))
+        .collect();
+    assert_eq!(points, vec![(0, 1.0), (60_000, 2.0)]);
+}
+
+#[test]
+fn test_task_conversion_keeps_fields() {
+    let mut body = task("task-1");
+    body.ts_mapping = Some(serde_json::from_value(json!({"time_column": "ts"})).unwrap());
+    body.enqueued_at = chrono::DateTime::from_timestamp_millis(1_700_000_000_123);
+    body.kind = JobKind::Diagnostics;
+    body.time_range = Some(TimeRange {
+        from: chrono::DateTime::from_timestamp_millis(1_000).unwrap(),
+        to: chrono::DateTime::from_timestamp_millis(2_000).unwrap(),
+        interval_ms: Some(10),
+    });
+
+    let task: proto::Task = body.clone().try_into().unwrap();
+    let converted: AcquireResultBody = task.try_into().unwrap();
+
+    assert_eq!(
+        serde_json::to_value(&converted).unwrap(),
+        serde_json::to_value(&body).unwrap()
+    );
+}
+
+#[test]
+fn test_invalid_grpc_url_rejected() {
+    let dir = TempDir::new().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    fs::write(
+        &config_path,
+        r#"server:
+  api_key: test-api-key
+  server_url: http://localhost:8080
+  grpc:
+    url: grpc.example.com:443
+datasources: []
+"#,
+    )
+    .unwrap();
+
+    let error = Config::load(&config_path).unwrap_err().to_string();
+
+    assert!(
+        error.contains("isn't an http:// or https:// URL"),
+        "{}",
+        error
+    );
+}
@@ -0,0 +1,323 @@
+//! Checks the hand-written messages of `src/client/grpc/proto.rs` against
+//! `proto/agent.proto`, so their field numbers and types can't drift apart
+
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Field of a message as it goes on the wire
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Field {
+    number: u32,
+    name: String,
+    /// `repeated`, `optional` for scalars with presence, `oneof` or empty
+    label: String,
+    /// Scalar, message or enum name, or `map<key, value>`
+    ty: String,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Schema {
+    messages: BTreeMap<String, BTreeSet<Field>>,
+    enums: BTreeMap<String, BTreeSet<(String, i32)>>,
+}
+
+impl Schema {
+    /// Messages are always optional, so `optional` only matters for scalars
+    fn normalized(mut self) -> Self {
+        let names: BTreeSet<String> = self.messages.keys().cloned().collect();
+        for fields in self.messages.values_mut() {
+            *fields = std::mem::take(fields)
+                .into_iter()
+                .map(|mut field| {
+                    if field.label == "optional" && names.contains(&field.ty) {
+                        field.label.clear();
+                    }
+                    field
+                })
+                .collect();
+        }
+        self
+    }
+}
+
+fn read(path: &str) -> String {
+    std::fs::read_to_string(format!("{}/{}", env!("CARGO_MANIFEST_DIR"), path)).unwrap()
+}
+
+fn proto_schema(source: &str) -> Schema {
+    let field = Regex::new(r"^(?:(repeated|optional) )?(map<[^>]+>|\w+) (\w+) = (\d+);$").unwrap();
+    let value = Regex::new(r"^(\w+) = (-?\d+);$").unwrap();
+    let mut schema = Schema::default();
+    // Enclosing blocks, innermost last
+    let mut blocks: Vec<(&str, String)> = Vec::new();
+
+    for line in source.lines() {
+        let line = line.split("//").next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "}" {
+            blocks.pop();
+            continue;
+        }
+        if let Some(header) = line.strip_suffix('{').or_else(|| line.strip_suffix("{}")) {
+            let mut words = header.split_whitespace();
+            let kind = words.next().unwrap();
+            let name = words.next().unwrap().to_string();
+            match kind {
+                "message" => {
+                    schema.messages.entry(name.clone()).or_default();
+                }
+                "enum" => {
+                    schema.enums.entry(name.clone()).or_default();
+                }
+                _ => (),
+            }
+            if line.ends_with('{') {
+                blocks.push((kind, name));
+            }
+            continue;
+        }
+
+        match blocks.as_slice() {
+            [.., ("message", message)] | [.., ("message", message), ("oneof", _)] => {
+                let captures = field
+                    .captures(line)
+                    .unwrap_or_else(|| panic!("unexpected line in {}: {}", message, line));
+                let in_oneof = blocks.last().unwrap().0 == "oneof";
+                let ty = &captures[2];
+                schema.messages.get_mut(message).unwrap().insert(Field {
+                    number: captures[4].parse().unwrap(),
+                    name: captures[3].to_string(),
+                    label: match captures.get(1) {
+                        _ if in_oneof => "oneof".to_string(),
+                        Some(label) => label.as_str().to_string(),
+                        None => String::new(),
+                    },
+                    ty: ty.to_string(),
+                });
+            }
+            [.., ("enum", name)] => {
+                let captures = value.captures(line).unwrap();
+                schema
+                    .enums
+                    .get_mut(name)
+                    .unwrap()
+                    .insert((captures[1].to_string(), captures[2].parse().unwrap()));
+            }
+            _ => (),
+        }
+    }
+    schema.normalized()
+}
+
+/// `JobKind` as `JOB_KIND`, the prefix of its values in the proto file
+fn screaming_snake_case(name: &str) -> String {
+    let mut screaming = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            screaming.push('_');
+        }
+        screaming.push(c.to_ascii_uppercase());
+    }
+    screaming
+}
+
+/// Message named by the Rust type of a field, e.g. `Option<Box<super::Task>>`
+fn message_name(ty: &str) -> String {
+    let mut ty = ty.trim();
+    for wrapper in ["Option<", "Vec<", "Box<"] {
+        if let Some(inner) = ty.strip_prefix(wrapper) {
+            ty = inner.strip_suffix('>').unwrap();
+        }
+    }
+    ty.rsplit("::").next().unwrap().to_string()
+}
+
+/// Field of a `#[prost(...)]` attribute, and the oneof it refers to
+fn rust_field(attribute: &str, name: &str, ty: &str) -> (Field, Option<String>) {
+    let quoted = Regex::new(r#"^(\w+) = "([^"]*)"$"#).unwrap();
+    let mut kind = String::new();
+    let mut value = String::new();
+    let mut flags = BTreeSet::new();
+    let mut number = 0;
+    // Split on commas outside quotes, as in `btree_map = "string, double"`
+    let parts = Regex::new(r#"(\w+(?: = "[^"]*")?)"#).unwrap();
+    for (i, part) in parts.find_iter(attribute).enumerate() {
+        let part = part.as_str();
+        match quoted.captures(part) {
+            Some(captures) if &captures[1] == "tag" => number = captures[2].parse().unwrap(),
+            Some(captures) if &captures[1] == "tags" => (),
+            Some(captures) if i == 0 => {
+                kind = captures[1].to_string();
+                value = captures[2].to_string();
+            }
+            None if i == 0 => kind = part.to_string(),
+            _ => {
+                flags.insert(part.to_string());
+            }
+        }
+    }
+
+    let oneof = (kind == "oneof").then(|| value.clone());
+    let ty = match kind.as_str() {
+        "enumeration" => value,
+        "map" | "btree_map" | "hash_map" => format!("map<{}>", value),
+        "message" => message_name(ty),
+        scalar => scalar.to_string(),
+    };
+    let label = if flags.contains("repeated") {
+        "repeated"
+    } else if flags.contains("optional") {
+        "optional"
+    } else {
+        ""
+    };
+    let field = Field {
+        number,
+        name: name.trim_start_matches("r#").to_string(),
+        label: label.to_string(),
+        ty,
+    };
+    (field, oneof)
+}
+
+fn rust_schema(source: &str) -> Schema {
+    let item = Regex::new(r"^pub (struct|enum|mod) (\w+)").unwrap();
+    let field = Regex::new(r"^pub ((?:r#)?\w+): (.+),$").unwrap();
+    let variant = Regex::new(r"^(\w+)\((.+)\),$").unwrap();
+    let value = Regex::new(r"^(\w+) = (-?\d+),$").unwrap();
+    let mut schema = Schema::default();
+    // Variants of oneofs by their path, e.g. `agent_message::Message`
+    let mut oneofs: BTreeMap<String, BTreeSet<Field>> = BTreeMap::new();
+    // Oneof fields of messages, expanded into their variants at the end
+    let mut oneof_fields: Vec<(String, String)> = Vec::new();
+    let mut derive = String::new();
+    let mut attribute: Option<String> = None;
+    let mut module: Option<String> = None;
+    // Message, oneof or enum whose body is being read
+    let mut current: Option<(String, String)> = None;
+
+    for line in source.lines() {
+        let line = line.trim();
+        if line.starts_with("#[derive(") {
+            derive = line.to_string();
+            continue;
+        }
+        if let Some(prost) = line
+            .strip_prefix("#[prost(")
+            .and_then(|line| line.strip_suffix(")]"))
+        {
+            attribute = Some(prost.to_string());
+            continue;
+        }
+        if let Some(captures) = item.captures(line) {
+            let name = captures[2].to_string();
+            match &captures[1] {
+                "mod" => module = Some(name),
+                "struct" if derive.contains("prost::Message") => {
+                    schema.messages.entry(name.clone()).or_default();
+                    current = Some(("message".to_string(), name));
+                }
+                "enum" if derive.contains("prost::Oneof") => {
+                    let path = format!("{}::{}", module.clone().unwrap(), name);
+                    oneofs.entry(path.clone()).or_default();
+                    current = Some(("oneof".to_string(), path));
+                }
+                "enum" if derive.contains("prost::Enumeration") => {
+                    schema.enums.entry(name.clone()).or_default();
+                    current = Some(("enum".to_string(), name));
+                }
+                _ => current = None,
+            }
+            derive.clear();
+            if line.ends_with("{}") {
+                current = None;
+            }
+            continue;
+        }
+        if line == "}" {
+            if current.take().is_none() {
+                module = None;
+            }
+            continue;
+        }
+
+        let Some((kind, name)) = &current else {
+            continue;
+        };
+        match kind.as_str() {
+            "message" => {
+                if let (Some(prost), Some(captures)) = (attribute.take(), field.captures(line)) {
+                    let (field, oneof) = rust_field(&prost, &captures[1], &captures[2]);
+                    match oneof {
+                        Some(path) => oneof_fields.push((name.clone(), path)),
+                        None => {
+                            schema.messages.get_mut(name).unwrap().insert(field);
+                        }
+                    }
+                }
+            }
+            "oneof" => {
+                if let (Some(prost), Some(captures)) = (attribute.take(), variant.captures(line)) {
+                    let snake = screaming_snake_case(&captures[1]).to_lowercase();
+                    let (mut field, _) = rust_field(&prost, &snake, &captures[2]);
+                    field.label = "oneof".to_string();
+                    oneofs.get_mut(name).unwrap().insert(field);
+                }
+            }
+            _ => {
+                if let Some(captures) = value.captures(line) {
+                    let prefix = screaming_snake_case(name);
+                    let value = format!("{}_{}", prefix, screaming_snake_case(&captures[1]));
+                    schema
+                        .enums
+                        .get_mut(name)
+                        .unwrap()
+                        .insert((value, captures[2].parse().unwrap()));
+                }
+            }
+        }
+    }
+
+    for (message, path) in oneof_fields {
+        let variants = oneofs[&path].clone();
+        schema.messages.get_mut(&message).unwrap().extend(variants);
+    }
+    schema.normalized()
+}
+
+#[test]
+fn test_messages_match_proto_file() {
+    let proto = proto_schema(&read("proto/agent.proto"));
+    let rust = rust_schema(&read("src/client/grpc/proto.rs"));
+
+    assert!(proto.messages.contains_key("DatasourceHeartbeat"));
+    assert!(proto.enums.contains_key("JobKind"));
+    for (name, fields) in &proto.messages {
+        assert_eq!(
+            rust.messages.get(name),
+            Some(fields),
+            "fields of message {} differ from proto/agent.proto",
+            name
+        );
+    }
+    for (name, values) in &proto.enums {
+        assert_eq!(
+            rust.enums.get(name),
+            Some(values),
+            "values of enum {} differ from proto/agent.proto",
+            name
+        );
+    }
+    assert_eq!(
+        rust.messages.keys().collect::<Vec<_>>(),
+        proto.messages.keys().collect::<Vec<_>>(),
+        "messages missing from proto/agent.proto"
+    );
+    assert_eq!(
+        rust.enums.keys().collect::<Vec<_>>(),
+        proto.enums.keys().collect::<Vec<_>>(),
+        "enums missing from proto/agent.proto"
+    );
+}
@@ -0,0 +1,123 @@
+use mockito::Server;
+use std::net::TcpListener;
+use std::sync::Arc;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{ServerApi, ServerClient};
+use tsight_agent::config::ListenerConfig;
+use tsight_agent::health::{Readiness, CHECK_OK};
+use tsight_agent::listener::Listener;
+use tsight_agent::models::DataSource;
+
+/// ClickHouse the datasource tests run against
+const CLICKHOUSE_URL: &str = "http://localhost:8123";
+
+/// URL nothing listens on
+fn closed_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+fn datasource(name: &str, host: String) -> DataSource {
+    DataSource {
+        name: name.to_string(),
+        hosts: vec![host],
+        ..Default::default()
+    }
+}
+
+async fn serve(readiness: Readiness) -> String {
+    let listener = Listener::bind(&ListenerConfig {
+        address: "127.0.0.1:0".to_string(),
+    })
+    .await
+    .unwrap()
+    .with_readiness(readiness);
+    let address = listener.local_addr().unwrap();
+    listener.spawn();
+    format!("http://{}", address)
+}
+
+#[tokio::test]
+async fn test_healthz() {
+    let url = serve(Readiness::new(Arc::new(FakeServer::new()), vec![])).await;
+
+    let response = reqwest::get(format!("{}/healthz", url)).await.unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn test_ready_with_one_connectable_datasource() {
+    let mut disabled = datasource("disabled", CLICKHOUSE_URL.to_string());
+    disabled.enabled = false;
+    let readiness = Readiness::new(
+        Arc::new(FakeServer::new()),
+        vec![
+            datasource("main", CLICKHOUSE_URL.to_string()),
+            datasource("down", closed_url()),
+            disabled,
+        ],
+    );
+
+    let report = readiness.check().await;
+
+    assert!(report.ready);
+    assert_eq!(report.server, CHECK_OK);
+    assert_eq!(report.datasources["main"], CHECK_OK);
+    assert!(report.datasources["down"].starts_with("Connection error"));
+    assert_eq!(
+        report.datasources["disabled"],
+        "Datasource disabled disabled by operator"
+    );
+}
+
+#[tokio::test]
+async fn test_not_ready_without_connectable_datasource() {
+    let readiness = Readiness::new(
+        Arc::new(FakeServer::new()),
+        vec![datasource("down", closed_url())],
+    );
+    let url = serve(readiness).await;
+
+    let response = reqwest::get(format!("{}/readyz", url)).await.unwrap();
+    assert_eq!(response.status(), 503);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["ready"], false);
+    assert_eq!(report["server"], CHECK_OK);
+}
+
+#[tokio::test]
+async fn test_not_ready_without_reachable_server() {
+    let server = Arc::new(FakeServer::new());
+    server.set_unreachable(true);
+    let readiness = Readiness::new(server, vec![]);
+    let url = serve(readiness).await;
+
+    let response = reqwest::get(format!("{}/readyz", url)).await.unwrap();
+    assert_eq!(response.status(), 503);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["server"], "Server unreachable");
+}
+
+#[tokio::test]
+async fn test_server_client_reachability() {
+    let mut server = Server::new_async().await;
+    let client = ServerClient::new("key".to_string(), server.url());
+
+    let not_found = server
+        .mock("GET", "/")
+        .with_status(404)
+        .create_async()
+        .await;
+    assert!(client.check_reachable().await.is_ok());
+    not_found.remove_async().await;
+
+    server
+        .mock("GET", "/")
+        .with_status(502)
+        .create_async()
+        .await;
+    assert!(client.check_reachable().await.is_err());
+
+    let client = ServerClient::new("key".to_string(), closed_url());
+    assert!(client.check_reachable().await.is_err());
+}
@@ -0,0 +1,224 @@
+use mockito::{Matcher, Server};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tsight_agent::host_resolver::{is_dynamic, HostResolver};
+use tsight_agent::models::{DataSource, DataSourceType};
+
+/// SRV record of a fake DNS zone: priority, weight, port, target and TTL
+type Record = (u16, u16, u16, &'static str, u32);
+
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+/// Answer to `query` with `records`, NXDOMAIN when there are none
+fn answer(query: &[u8], records: &[Record]) -> Vec<u8> {
+    // The question ends after its name, type and class
+    let mut end = 12;
+    while query[end] != 0 {
+        end += query[end] as usize + 1;
+    }
+    end += 5;
+
+    let mut response = query[..2].to_vec();
+    let rcode = if records.is_empty() { 3 } else { 0 };
+    response.extend_from_slice(&[0x81, 0x80 | rcode, 0, 1]);
+    response.extend_from_slice(&(records.len() as u16).to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]);
+    response.extend_from_slice(&query[12..end]);
+    for (priority, weight, port, target, ttl) in records {
+        // Name compressed to a pointer at the question
+        response.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1]);
+        response.extend_from_slice(&ttl.to_be_bytes());
+        let target = encode_name(target);
+        response.extend_from_slice(&(6 + target.len() as u16).to_be_bytes());
+        response.extend_from_slice(&priority.to_be_bytes());
+        response.extend_from_slice(&weight.to_be_bytes());
+        response.extend_from_slice(&port.to_be_bytes());
+        response.extend_from_slice(&target);
+    }
+    response
+}
+
+/// DNS server answering every query with `records`, counting the queries
+async fn dns_server(records: Vec<Record>) -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counter = queries.clone();
+    tokio::spawn(async move {
+        let mut buffer = [0; 512];
+        while let Ok((length, peer)) = socket.recv_from(&mut buffer).await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let response = answer(&buffer[..length], &records);
+            socket.send_to(&response, peer).await.unwrap();
+        }
+    });
+    (address, queries)
+}
+
+fn datasource(source_type: DataSourceType, hosts: &[&str]) -> DataSource {
+    DataSource {
+        name: "analytics".to_string(),
+        source_type,
+        hosts: hosts.iter().map(|host| host.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_dynamic_hosts() {
+    assert!(is_dynamic("srv+_clickhouse._tcp.example.com"));
+    assert!(is_dynamic("consul+clickhouse"));
+    assert!(!is_dynamic("http://ch.example.com:8123"));
+}
+
+#[tokio::test]
+async fn test_srv_records_ordered_by_priority_and_weight() {
+    let (nameserver, _) = dns_server(vec![
+        (20, 0, 8123, "ch3.example.com", 60),
+        (10, 10, 8123, "ch1.example.com.", 60),
+        (10, 50, 9123, "ch2.example.com", 60),
+    ])
+    .await;
+    let resolver = HostResolver::new(nameserver, "127.0.0.1:8500", None);
+
+    let hosts = resolver
+        .resolve_hosts(&datasource(
+            DataSourceType::Clickhouse,
+            &["srv+_clickhouse._tcp.example.com", "http://static:8123"],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        hosts,
+        [
+            "http://ch2.example.com:9123",
+            "http://ch1.example.com:8123",
+            "http://ch3.example.com:8123",
+            "http://static:8123",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_srv_hosts_with_scheme_and_for_postgres() {
+    let (nameserver, _) = dns_server(vec![(0, 0, 8443, "ch.example.com", 60)]).await;
+    let resolver = HostResolver::new(nameserver, "127.0.0.1:8500", None);
+
+    let hosts = resolver
+        .resolve(
+            "srv+https://_clickhouse._tcp.example.com",
+            &DataSourceType::Clickhouse,
+        )
+        .await
+        .unwrap();
+    assert_eq!(hosts, ["https://ch.example.com:8443"]);
+
+    let hosts = resolver
+        .resolve(
+            "srv+_clickhouse._tcp.example.com",
+            &DataSourceType::PostgreSQL,
+        )
+        .await
+        .unwrap();
+    assert_eq!(hosts, ["ch.example.com:8443"]);
+}
+
+#[tokio::test]
+async fn test_srv_records_cached_for_their_ttl() {
+    let (nameserver, queries) = dns_server(vec![(0, 0, 8123, "ch.example.com", 60)]).await;
+    let resolver = HostResolver::new(nameserver, "127.0.0.1:8500", None);
+
+    for _ in 0..3 {
+        resolver
+            .resolve(
+                "srv+_clickhouse._tcp.example.com",
+                &DataSourceType::Clickhouse,
+            )
+            .await
+            .unwrap();
+    }
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_unknown_service_is_an_error() {
+    let (nameserver, _) = dns_server(Vec::new()).await;
+    let resolver = HostResolver::new(nameserver, "127.0.0.1:8500", None);
+
+    let error = resolver
+        .resolve(
+            "srv+_clickhouse._tcp.example.com",
+            &DataSourceType::Clickhouse,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        format!("{:#}", error),
+        "Failed to resolve srv+_clickhouse._tcp.example.com: no such service"
+    );
+}
+
+#[tokio::test]
+async fn test_consul_passing_instances() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("GET", "/v1/health/service/clickhouse")
+        .match_query(Matcher::UrlEncoded(
+            "passing".to_string(),
+            "true".to_string(),
+        ))
+        .match_header("X-Consul-Token", "consul-token")
+        .with_body(
+            r#"[
+                {"Node": {"Address": "10.0.0.1"}, "Service": {"Address": "", "Port": 8123}},
+                {"Node": {"Address": "10.0.0.2"}, "Service": {"Address": "10.0.1.2", "Port": 8124}}
+            ]"#,
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let nameserver = SocketAddr::from(([127, 0, 0, 1], 1));
+    let resolver = HostResolver::new(nameserver, &server.url(), Some("consul-token".to_string()));
+
+    for _ in 0..2 {
+        let hosts = resolver
+            .resolve("consul+clickhouse", &DataSourceType::Clickhouse)
+            .await
+            .unwrap();
+        assert_eq!(hosts, ["http://10.0.0.1:8123", "http://10.0.1.2:8124"]);
+    }
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_consul_without_instances_is_an_error() {
+    let mut server = Server::new_async().await;
+    let _mock = server
+        .mock("GET", "/v1/health/service/clickhouse")
+        .match_query(Matcher::Any)
+        .with_body("[]")
+        .create_async()
+        .await;
+    let nameserver = SocketAddr::from(([127, 0, 0, 1], 1));
+    let resolver = HostResolver::new(nameserver, &server.url(), None);
+
+    let error = resolver
+        .resolve("consul+clickhouse", &DataSourceType::Clickhouse)
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("no replicas are registered"),
+        "{:#}",
+        error
+    );
+}
@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tsight_agent::executors::create_executor;
+use tsight_agent::http::datasource_client;
+use tsight_agent::models::DataSource;
+
+/// Answer every HTTP request on `stream` with an empty 200, keeping the
+/// connection open
+async fn serve(mut stream: TcpStream) {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        // Requests are complete once their headers and body arrived
+        while let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            let headers = String::from_utf8_lossy(&buffer[..end]).to_lowercase();
+            let length: usize = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |length| length.trim().parse().unwrap());
+            if buffer.len() < end + 4 + length {
+                break;
+            }
+            buffer.drain(..end + 4 + length);
+            let response = "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+            if stream.write_all(response.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(length) => buffer.extend_from_slice(&chunk[..length]),
+        }
+    }
+}
+
+/// HTTP server counting the connections it accepted
+async fn server() -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(serve(stream));
+        }
+    });
+    (url, connections)
+}
+
+#[tokio::test]
+async fn test_executors_of_a_datasource_reuse_connections() {
+    let (url, connections) = server().await;
+    let datasource = DataSource {
+        name: "pooled".to_string(),
+        hosts: vec![url],
+        ..Default::default()
+    };
+
+    for _ in 0..3 {
+        let executor = create_executor(&datasource, None).await.unwrap();
+        executor
+            .execute_statement("SET max_threads = 1")
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_datasources_have_separate_clients() {
+    let (url, connections) = server().await;
+
+    for name in ["first", "second"] {
+        datasource_client(name).post(&url).send().await.unwrap();
+        datasource_client(name).post(&url).send().await.unwrap();
+    }
+
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,228 @@
+use chrono::{TimeZone, Utc};
+use mockito::{Matcher, Server};
+use tsight_agent::aws::AwsCredentials;
+use tsight_agent::config::{DatasourceAuth, IamAuthConfig};
+use tsight_agent::executors::create_executor;
+use tsight_agent::iam_auth::{generate_token, IamAuth};
+use tsight_agent::models::{DataSource, DataSourceType};
+
+fn credentials() -> AwsCredentials {
+    AwsCredentials {
+        access_key_id: "AKIDEXAMPLE".to_string(),
+        secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        session_token: None,
+    }
+}
+
+fn postgres(host: &str, auth: IamAuthConfig) -> DataSource {
+    DataSource {
+        name: "billing".to_string(),
+        source_type: DataSourceType::PostgreSQL,
+        hosts: vec![host.to_string()],
+        username: "tsight_reader".to_string(),
+        auth: DatasourceAuth::Iam(auth),
+        ..Default::default()
+    }
+}
+
+const ASSUME_ROLE_RESPONSE: &str = r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <Credentials>
+      <AccessKeyId>ASIAROLE</AccessKeyId>
+      <SecretAccessKey>role-secret</SecretAccessKey>
+      <SessionToken>role-session-token</SessionToken>
+      <Expiration>2099-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleResult>
+</AssumeRoleResponse>"#;
+
+#[test]
+fn test_iam_auth_parsed_from_yaml() {
+    let datasource: DataSource = config::Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+name: billing
+source_type: postgresql
+hosts: ["billing.cluster-abc.eu-west-1.rds.amazonaws.com:5432"]
+username: tsight_reader
+auth:
+  mode: iam
+  region: eu-west-1
+  role_arn: arn:aws:iam::123456789012:role/tsight-reader
+"#,
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    assert_eq!(
+        datasource.auth,
+        DatasourceAuth::Iam(IamAuthConfig {
+            region: Some("eu-west-1".to_string()),
+            role_arn: Some("arn:aws:iam::123456789012:role/tsight-reader".to_string()),
+            sts_endpoint_url: None,
+        })
+    );
+}
+
+#[test]
+fn test_token_is_presigned_connect_request() {
+    let time = Utc.with_ymd_and_hms(2026, 10, 15, 12, 0, 0).unwrap();
+    let token = generate_token(
+        "db.example.com",
+        5432,
+        "tsight_reader",
+        "eu-west-1",
+        &credentials(),
+        time,
+    )
+    .unwrap();
+
+    assert!(
+        token.starts_with("db.example.com:5432/?Action=connect&DBUser=tsight_reader&"),
+        "{}",
+        token
+    );
+    assert!(token
+        .contains("X-Amz-Credential=AKIDEXAMPLE%2F20261015%2Feu-west-1%2Frds-db%2Faws4_request"));
+    assert!(token.contains("X-Amz-Date=20261015T120000Z"));
+    assert!(token.contains("X-Amz-Expires=900"));
+    assert!(token.contains("X-Amz-SignedHeaders=host"));
+    let signature = token.split("X-Amz-Signature=").nth(1).unwrap();
+    assert_eq!(signature.len(), 64);
+
+    let other_user = generate_token(
+        "db.example.com",
+        5432,
+        "admin",
+        "eu-west-1",
+        &credentials(),
+        time,
+    )
+    .unwrap();
+    assert_ne!(
+        other_user.split("X-Amz-Signature=").nth(1).unwrap(),
+        signature
+    );
+}
+
+#[test]
+fn test_iam_auth_requires_postgres_or_mysql() {
+    let datasource = DataSource {
+        source_type: DataSourceType::Clickhouse,
+        ..postgres("http://ch.example.com:8123", IamAuthConfig::default())
+    };
+
+    let error = IamAuth::new(&IamAuthConfig::default(), &datasource)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("requires a PostgreSQL or MySQL datasource"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_clickhouse_executor_rejects_iam_auth() {
+    let datasource = DataSource {
+        source_type: DataSourceType::Clickhouse,
+        ..postgres("http://ch.example.com:8123", IamAuthConfig::default())
+    };
+
+    let error = create_executor(&datasource, None)
+        .await
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(
+        error.contains("only supported by PostgreSQL and MySQL"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_role_is_assumed_once_and_token_reused() {
+    std::env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    std::env::set_var(
+        "AWS_SECRET_ACCESS_KEY",
+        "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+    );
+    let mut server = Server::new_async().await;
+    let sts = server
+        .mock("POST", "/")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::Regex("Action=AssumeRole".to_string()),
+            Matcher::Regex(
+                "RoleArn=arn%3Aaws%3Aiam%3A%3A123456789012%3Arole%2Ftsight-reader".to_string(),
+            ),
+            Matcher::Regex("RoleSessionName=tsight-agent".to_string()),
+        ]))
+        .match_header(
+            "authorization",
+            Matcher::Regex("Credential=AKIDEXAMPLE/.*/eu-west-1/sts/aws4_request".to_string()),
+        )
+        .with_body(ASSUME_ROLE_RESPONSE)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let config = IamAuthConfig {
+        region: Some("eu-west-1".to_string()),
+        role_arn: Some("arn:aws:iam::123456789012:role/tsight-reader".to_string()),
+        sts_endpoint_url: Some(server.url()),
+    };
+    let auth = IamAuth::new(
+        &config,
+        &postgres("postgres://billing.example.com", config.clone()),
+    )
+    .unwrap();
+
+    let token = auth.password().await.unwrap();
+    assert!(
+        token.starts_with("billing.example.com:5432/?Action=connect&DBUser=tsight_reader&"),
+        "{}",
+        token
+    );
+    assert!(token.contains("X-Amz-Credential=ASIAROLE%2F"));
+    assert!(token.contains("X-Amz-Security-Token=role-session-token"));
+    assert_eq!(auth.password().await.unwrap(), token);
+    sts.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_failed_assume_role_is_reported() {
+    std::env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    std::env::set_var(
+        "AWS_SECRET_ACCESS_KEY",
+        "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+    );
+    let mut server = Server::new_async().await;
+    let _sts = server
+        .mock("POST", "/")
+        .with_status(403)
+        .with_body(
+            "<ErrorResponse><Error><Code>AccessDenied</Code><Message>not authorized to assume role</Message></Error></ErrorResponse>",
+        )
+        .create_async()
+        .await;
+
+    let config = IamAuthConfig {
+        region: Some("eu-west-1".to_string()),
+        role_arn: Some("arn:aws:iam::123456789012:role/tsight-reader".to_string()),
+        sts_endpoint_url: Some(server.url()),
+    };
+    let mut datasource = postgres("billing.example.com:3307", config.clone());
+    datasource.source_type = DataSourceType::MySQL;
+    let auth = IamAuth::new(&config, &datasource).unwrap();
+
+    let error = auth.password().await.unwrap_err().to_string();
+    assert!(
+        error.contains("Failed to assume role arn:aws:iam::123456789012:role/tsight-reader: not authorized to assume role"),
+        "{}",
+        error
+    );
+}
@@ -0,0 +1,116 @@
+use mockito::{Matcher, Server};
+use serde_json::json;
+use std::sync::Arc;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{FilterAction, GlobalFilters, SqlFilterRules};
+use tsight_agent::executors::base::QueryExecutor;
+use tsight_agent::executors::clickhouse_source::{ClickhouseExecutor, ResultColumn};
+use tsight_agent::models::DataSource;
+
+const BODY: &str = concat!(
+    "[\"id\",\"email\",\"amount\",\"tags\"]\n",
+    "[\"UInt64\",\"LowCardinality(Nullable(String))\",\"Nullable(Decimal(10, 2))\",\"Array(String)\"]\n",
+    "[1,\"john@example.com\",9.5,[\"a\"]]\n",
+    "[2,null,null,[]]\n",
+);
+
+async fn clickhouse() -> (mockito::ServerGuard, mockito::Mock) {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::Regex(
+            "FORMAT JSONCompactEachRowWithNamesAndTypes$".to_string(),
+        ))
+        .with_body(BODY)
+        .create_async()
+        .await;
+    (server, mock)
+}
+
+fn column(name: &str, type_name: &str, nullable: bool) -> ResultColumn {
+    ResultColumn {
+        name: name.to_string(),
+        type_name: type_name.to_string(),
+        nullable,
+    }
+}
+
+#[tokio::test]
+async fn test_columns_from_response_types() {
+    let (server, _mock) = clickhouse().await;
+    let executor = ClickhouseExecutor::new(&server.url(), "default", "").unwrap();
+
+    let (rows, _, columns) = executor.execute_job_with_columns("SELECT 1").await.unwrap();
+
+    assert_eq!(
+        columns,
+        vec![
+            column("id", "int", false),
+            column("email", "string", true),
+            column("amount", "string", true),
+            column("tags", "array<string>", false),
+        ]
+    );
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["email"], json!("john@example.com"));
+    assert_eq!(rows[1]["amount"], json!(null));
+}
+
+#[tokio::test]
+async fn test_rewritten_columns_reported_as_strings() {
+    let (server, _mock) = clickhouse().await;
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            column_name_regexes: Some(vec!["^id$".to_string()]),
+            action: Some(FilterAction::Hash),
+            ..Default::default()
+        }]),
+        hash_key: Some("key".to_string()),
+        ..Default::default()
+    };
+    let executor =
+        ClickhouseExecutor::with_global_filters(&server.url(), "default", "", Some(filters))
+            .unwrap();
+
+    let (rows, _, columns) = executor.execute_job_with_columns("SELECT 1").await.unwrap();
+
+    assert!(rows[0]["id"].is_string());
+    assert_eq!(columns[0], column("id", "string", false));
+}
+
+#[tokio::test]
+async fn test_columns_submitted_with_job_results() {
+    let (clickhouse, _mock) = clickhouse().await;
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "7".to_string(),
+        datasource_name: "main".to_string(),
+        query: "SELECT id, email, amount, tags FROM users".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+    let agent = create_job_agent_with_client(
+        server.clone(),
+        vec![DataSource {
+            name: "main".to_string(),
+            hosts: vec![clickhouse.url()],
+            ..Default::default()
+        }],
+        None,
+    );
+
+    agent.process_next().await.unwrap();
+
+    let (job_id, columns) = &server.job_columns()[0];
+    assert_eq!(job_id, "7");
+    let names: Vec<_> = columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["id", "email", "amount", "tags"]);
+}
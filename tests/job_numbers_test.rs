@@ -0,0 +1,61 @@
+use mockito::{Matcher, Server};
+use serde_json::json;
+use tsight_agent::executors::base::QueryExecutor;
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+
+fn quote_settings(quote: &str) -> Matcher {
+    Matcher::AllOf(vec![
+        Matcher::UrlEncoded(
+            "output_format_json_quote_64bit_integers".into(),
+            quote.into(),
+        ),
+        Matcher::UrlEncoded("output_format_json_quote_decimals".into(), quote.into()),
+    ])
+}
+
+#[tokio::test]
+async fn test_job_results_keep_numbers() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/")
+        .match_query(quote_settings("0"))
+        .with_body(concat!(
+            "[\"status\",\"cnt\",\"total\"]\n",
+            "[\"String\",\"UInt64\",\"Float64\"]\n",
+            "[\"new\",7,12.5]\n",
+        ))
+        .create_async()
+        .await;
+
+    let executor = ClickhouseExecutor::new(&server.url(), "default", "").unwrap();
+    let rows = executor.execute_job("SELECT 1").await.unwrap();
+
+    mock.assert_async().await;
+    assert_eq!(rows[0]["cnt"], json!(7));
+    assert!(rows[0]["cnt"].is_u64());
+    assert_eq!(rows[0]["total"], json!(12.5));
+}
+
+#[tokio::test]
+async fn test_exact_numbers_keep_strings() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("POST", "/")
+        .match_query(quote_settings("1"))
+        .with_body(concat!(
+            "[\"cnt\",\"amount\"]\n",
+            "[\"UInt64\",\"Decimal(38, 19)\"]\n",
+            "[\"18446744073709551615\",\"0.1000000000000000001\"]\n",
+        ))
+        .create_async()
+        .await;
+
+    let executor = ClickhouseExecutor::new(&server.url(), "default", "")
+        .unwrap()
+        .with_exact_numbers(true);
+    let rows = executor.execute_job("SELECT 1").await.unwrap();
+
+    mock.assert_async().await;
+    assert_eq!(rows[0]["cnt"], json!("18446744073709551615"));
+    assert_eq!(rows[0]["amount"], json!("0.1000000000000000001"));
+}
@@ -0,0 +1,156 @@
+use mockito::{Matcher, Server};
+use serde_json::json;
+use tsight_agent::client::{ServerApi, ServerClient};
+use tsight_agent::config::GlobalFilters;
+use tsight_agent::executors::base::{ExecutionStats, QueryExecutor};
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+use tsight_agent::filters::FilterStats;
+use tsight_agent::models::JobType;
+use tsight_agent::spill::{row_size, JobRows};
+
+fn row(id: u64) -> JobType {
+    JobType::from([
+        ("id".to_string(), json!(id)),
+        ("name".to_string(), json!(format!("user {}", id))),
+    ])
+}
+
+fn ids(rows: &[JobType]) -> Vec<u64> {
+    rows.iter().map(|row| row["id"].as_u64().unwrap()).collect()
+}
+
+/// Rows of `count` rows spilled beyond the size of ten rows
+fn spilled_rows(count: u64) -> JobRows {
+    let mut rows = JobRows::new(Some(row_size(&row(0)) * 10));
+    (0..count).for_each(|id| rows.push(row(id)));
+    rows.finish().unwrap()
+}
+
+#[test]
+fn test_rows_within_budget_stay_in_memory() {
+    let mut rows = JobRows::new(Some(1024 * 1024));
+    (0..100).for_each(|id| rows.push(row(id)));
+    let rows = rows.finish().unwrap();
+
+    assert!(!rows.is_spilled());
+    assert_eq!(rows.len(), 100);
+    assert_eq!(rows.into_chunks().unwrap().count(), 1);
+}
+
+#[test]
+fn test_rows_over_budget_spilled_and_read_back_in_order() {
+    let rows = spilled_rows(95);
+
+    assert!(rows.is_spilled());
+    assert_eq!(rows.len(), 95);
+    assert_eq!(ids(&rows.into_vec().unwrap()), (0..95).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_spilled_rows_chunked_by_budget() {
+    let chunks: Vec<Vec<JobType>> = spilled_rows(95)
+        .into_chunks()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    assert_eq!(chunks.len(), 10);
+    assert!(chunks.iter().all(|chunk| chunk.len() <= 10));
+    let all: Vec<JobType> = chunks.into_iter().flatten().collect();
+    assert_eq!(ids(&all), (0..95).collect::<Vec<_>>());
+}
+
+async fn clickhouse(rows: u64) -> mockito::ServerGuard {
+    let mut body = String::from("[\"id\",\"name\"]\n[\"UInt64\",\"String\"]\n");
+    for id in 0..rows {
+        body.push_str(&format!("[{},\"user {}\"]\n", id, id));
+    }
+    let mut server = Server::new_async().await;
+    server
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .with_body(body)
+        .create_async()
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn test_job_rows_spilled_beyond_memory_budget() {
+    let server = clickhouse(20_000).await;
+    let executor = ClickhouseExecutor::new(&server.url(), "default", "")
+        .unwrap()
+        .with_memory_budget(Some(1));
+
+    let (rows, _, columns) = executor.execute_job_rows("SELECT 1").await.unwrap();
+
+    assert!(rows.is_spilled());
+    assert_eq!(columns.len(), 2);
+    assert_eq!(
+        ids(&rows.into_vec().unwrap()),
+        (0..20_000).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_rows_not_spilled_with_result_set_rules() {
+    let server = clickhouse(20_000).await;
+    let filters = GlobalFilters {
+        max_rows: Some(15_000),
+        ..Default::default()
+    };
+    let executor =
+        ClickhouseExecutor::with_global_filters(&server.url(), "default", "", Some(filters))
+            .unwrap()
+            .with_memory_budget(Some(1));
+
+    let (rows, stats, _) = executor.execute_job_rows("SELECT 1").await.unwrap();
+
+    assert!(!rows.is_spilled());
+    assert_eq!(rows.len(), 15_000);
+    assert_eq!(stats.sampled_rows, 5_000);
+}
+
+#[tokio::test]
+async fn test_spilled_rows_submitted_in_chunks() {
+    let mut server = Server::new_async().await;
+    let first_chunk = server
+        .mock("POST", "/jobs/1/submit/chunk")
+        .match_body(Matcher::PartialJson(json!({
+            "index": 0,
+            "manifest": {"row_count": 10}
+        })))
+        .with_status(200)
+        .expect(1)
+        .create_async()
+        .await;
+    let chunks = server
+        .mock("POST", "/jobs/1/submit/chunk")
+        .with_status(200)
+        .expect(2)
+        .create_async()
+        .await;
+    let complete = server
+        .mock("POST", "/jobs/1/submit")
+        .match_body(Matcher::PartialJson(json!({"records": [], "chunks": 3})))
+        .with_status(200)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client
+        .submit_job_chunks(
+            "1",
+            spilled_rows(25),
+            Vec::new(),
+            FilterStats::default(),
+            ExecutionStats::default(),
+        )
+        .await
+        .unwrap();
+
+    first_chunk.assert_async().await;
+    chunks.assert_async().await;
+    complete.assert_async().await;
+}
@@ -0,0 +1,118 @@
+use mockito::{Matcher, Server};
+use serde_json::json;
+use tsight_agent::config::{FilterAction, GlobalFilters, SqlFilterRules};
+use tsight_agent::executors::base::QueryExecutor;
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+use tsight_agent::executors::json_rows::JsonRowsParser;
+use tsight_agent::models::JobType;
+
+const BODY: &str = concat!(
+    "[\"id\",\"note\"]\n",
+    "[\"UInt64\",\"String\"]\n",
+    "[1,\"first\\nline\"]\n",
+    "\n",
+    "[2,\"second\"]\n",
+);
+
+fn parse_chunks(chunks: &[&[u8]]) -> (JsonRowsParser, Vec<JobType>) {
+    let mut parser = JsonRowsParser::default();
+    let mut rows = Vec::new();
+    for chunk in chunks {
+        parser.feed(chunk, &mut |row| rows.push(row)).unwrap();
+    }
+    parser.finish(&mut |row| rows.push(row)).unwrap();
+    (parser, rows)
+}
+
+fn row(id: u64, note: &str) -> JobType {
+    JobType::from([
+        ("id".to_string(), json!(id)),
+        ("note".to_string(), json!(note)),
+    ])
+}
+
+#[test]
+fn test_rows_parsed_across_every_chunk_boundary() {
+    let body = BODY.as_bytes();
+    for split in 0..=body.len() {
+        let (parser, rows) = parse_chunks(&[&body[..split], &body[split..]]);
+
+        assert_eq!(parser.names(), ["id", "note"]);
+        assert_eq!(parser.types(), ["UInt64", "String"]);
+        assert_eq!(rows, [row(1, "first\nline"), row(2, "second")], "{}", split);
+    }
+}
+
+#[test]
+fn test_rows_parsed_byte_by_byte() {
+    let chunks: Vec<&[u8]> = BODY.as_bytes().chunks(1).collect();
+    let (_, rows) = parse_chunks(&chunks);
+
+    assert_eq!(rows, [row(1, "first\nline"), row(2, "second")]);
+}
+
+#[test]
+fn test_last_line_without_line_break() {
+    let (_, rows) = parse_chunks(&[BODY.trim_end().as_bytes()]);
+
+    assert_eq!(rows.len(), 2);
+}
+
+#[test]
+fn test_empty_body_has_no_columns() {
+    let (parser, rows) = parse_chunks(&[b""]);
+
+    assert!(parser.names().is_empty());
+    assert!(rows.is_empty());
+}
+
+#[test]
+fn test_invalid_line_reported() {
+    let mut parser = JsonRowsParser::default();
+    let body = "[\"id\"]\n[\"UInt64\"]\nCode: 241. DB::Exception: Memory limit exceeded\n";
+
+    let error = parser.feed(body.as_bytes(), &mut |_| {}).unwrap_err();
+    assert_eq!(
+        error.line,
+        "Code: 241. DB::Exception: Memory limit exceeded"
+    );
+}
+
+#[tokio::test]
+async fn test_job_rows_streamed_and_filtered() {
+    let mut server = Server::new_async().await;
+    let _mock = server
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .with_chunked_body(|writer| {
+            writer.write_all(b"[\"id\",\"email\"]\n[\"UInt64\",\"String\"]\n[1,\"john@exa")?;
+            writer.flush()?;
+            writer.write_all(b"mple.com\"]\n[2,\"ops@internal\"]\n[3,\"jane@example.com\"]")
+        })
+        .create_async()
+        .await;
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            column_value_regexes: Some(vec!["@internal$".to_string()]),
+            action: Some(FilterAction::DropRow),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let executor =
+        ClickhouseExecutor::with_global_filters(&server.url(), "default", "", Some(filters))
+            .unwrap();
+
+    let (rows, stats, columns) = executor
+        .execute_job_with_columns("SELECT id, email FROM users")
+        .await
+        .unwrap();
+
+    let ids: Vec<&serde_json::Value> = rows.iter().map(|row| &row["id"]).collect();
+    assert_eq!(ids, [&json!(1), &json!(3)]);
+    assert_eq!(stats.dropped_rows, 1);
+    assert_eq!(columns.len(), 2);
+    let execution = executor.execution_stats();
+    assert_eq!(execution.rows_returned, 3);
+    assert_eq!(execution.rows_filtered, 1);
+}
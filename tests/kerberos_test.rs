@@ -0,0 +1,108 @@
+use mockito::{Matcher, Server};
+use std::path::PathBuf;
+use tsight_agent::config::{DatasourceAuth, KerberosConfig};
+use tsight_agent::executors::create_executor;
+use tsight_agent::kerberos::KerberosAuth;
+use tsight_agent::models::DataSource;
+
+fn kerberos_config(keytab: &str) -> KerberosConfig {
+    KerberosConfig {
+        keytab: PathBuf::from(keytab),
+        principal: None,
+        service: "HTTP".to_string(),
+    }
+}
+
+#[test]
+fn test_kerberos_auth_parsed_from_yaml() {
+    let datasource: DataSource = config::Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+name: main
+source_type: clickhouse
+hosts: ["http://ch.example.com:8123"]
+auth:
+  mode: kerberos
+  keytab: /etc/tsight/agent.keytab
+  principal: tsight@EXAMPLE.COM
+"#,
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    assert_eq!(
+        datasource.auth,
+        DatasourceAuth::Kerberos(KerberosConfig {
+            keytab: PathBuf::from("/etc/tsight/agent.keytab"),
+            principal: Some("tsight@EXAMPLE.COM".to_string()),
+            service: "HTTP".to_string(),
+        })
+    );
+    assert!(datasource.password.is_empty());
+}
+
+#[test]
+fn test_password_auth_by_default() {
+    assert_eq!(DataSource::default().auth, DatasourceAuth::Password);
+}
+
+#[test]
+fn test_target_is_service_at_datasource_host() {
+    let auth = KerberosAuth::new(
+        &kerberos_config("/etc/tsight/agent.keytab"),
+        "http://ch.example.com:8123",
+    )
+    .unwrap();
+    assert_eq!(auth.target(), "HTTP@ch.example.com");
+
+    let config = KerberosConfig {
+        service: "clickhouse".to_string(),
+        ..kerberos_config("/etc/tsight/agent.keytab")
+    };
+    let auth = KerberosAuth::new(&config, "https://ch.example.com").unwrap();
+    assert_eq!(auth.target(), "clickhouse@ch.example.com");
+}
+
+#[test]
+fn test_target_requires_host_url() {
+    let error = KerberosAuth::new(&kerberos_config("/etc/tsight/agent.keytab"), "ch:8123")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("requires a host URL"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_failed_kerberos_authentication_fails_before_querying() {
+    let dir = tempfile::tempdir().unwrap();
+    let keytab = dir.path().join("missing.keytab");
+    let mut server = Server::new_async().await;
+    let clickhouse = server
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .expect(0)
+        .create_async()
+        .await;
+
+    let datasource = DataSource {
+        name: "main".to_string(),
+        hosts: vec![server.url()],
+        auth: DatasourceAuth::Kerberos(kerberos_config(keytab.to_str().unwrap())),
+        ..Default::default()
+    };
+    let executor = create_executor(&datasource, None).await.unwrap();
+    let error = executor
+        .execute_job("SELECT 1")
+        .await
+        .unwrap_err()
+        .to_string();
+
+    assert!(
+        error.contains("Kerberos authentication to HTTP@127.0.0.1 failed"),
+        "{}",
+        error
+    );
+    clickhouse.assert_async().await;
+}
@@ -0,0 +1,218 @@
+use base64::Engine;
+use mockito::{Matcher, Server};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+use tsight_agent::client::{DatasourceStatus, ServerApi, ServerClient};
+use tsight_agent::config::Config;
+use tsight_agent::executors::create_executor;
+use tsight_agent::kubernetes::{pod_metadata, PodMetadata};
+use tsight_agent::models::DataSource;
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+fn basic_auth(username: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password))
+    )
+}
+
+#[test]
+fn test_pod_metadata_from_downward_api() {
+    let dir = TempDir::new().unwrap();
+    let namespace_file = dir.path().join("namespace");
+    fs::write(&namespace_file, "from-service-account\n").unwrap();
+
+    let metadata = PodMetadata::detect(
+        env(&[
+            ("KUBERNETES_SERVICE_HOST", "10.0.0.1"),
+            ("POD_NAME", "tsight-agent-7d9f8-x2k4q"),
+            ("POD_NAMESPACE", "monitoring"),
+            ("NODE_NAME", "node-3"),
+            ("HOSTNAME", "ignored"),
+        ]),
+        &namespace_file,
+    )
+    .unwrap();
+    assert_eq!(
+        metadata,
+        PodMetadata {
+            pod_name: "tsight-agent-7d9f8-x2k4q".to_string(),
+            namespace: Some("monitoring".to_string()),
+            node_name: Some("node-3".to_string()),
+        }
+    );
+    assert_eq!(
+        metadata.to_string(),
+        "pod monitoring/tsight-agent-7d9f8-x2k4q on node node-3"
+    );
+
+    // Without the downward API, the hostname and service account are used
+    let metadata = PodMetadata::detect(
+        env(&[
+            ("KUBERNETES_SERVICE_HOST", "10.0.0.1"),
+            ("HOSTNAME", "tsight-agent-0"),
+        ]),
+        &namespace_file,
+    )
+    .unwrap();
+    assert_eq!(metadata.pod_name, "tsight-agent-0");
+    assert_eq!(metadata.namespace.as_deref(), Some("from-service-account"));
+    assert_eq!(metadata.node_name, None);
+    let metadata = PodMetadata::detect(
+        env(&[("KUBERNETES_SERVICE_HOST", "10.0.0.1")]),
+        &dir.path().join("missing"),
+    )
+    .unwrap();
+    assert_eq!(metadata.namespace, None);
+
+    assert_eq!(
+        PodMetadata::detect(env(&[("POD_NAME", "agent")]), &namespace_file),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_registration_and_status_carry_pod_metadata() {
+    // Read once by the first registration, which no other test makes
+    std::env::set_var("KUBERNETES_SERVICE_HOST", "10.0.0.1");
+    std::env::set_var("POD_NAME", "tsight-agent-0");
+    std::env::set_var("POD_NAMESPACE", "monitoring");
+    std::env::set_var("NODE_NAME", "node-3");
+    let pod = json!({
+        "pod_name": "tsight-agent-0",
+        "namespace": "monitoring",
+        "node_name": "node-3",
+    });
+    let mut server = Server::new_async().await;
+    let add = server
+        .mock("POST", "/datasource/main/add")
+        .match_body(Matcher::PartialJson(json!({
+            "datasource_type": "clickhouse",
+            "kubernetes": pod,
+        })))
+        .create_async()
+        .await;
+    let status = server
+        .mock("POST", "/datasource/main/status")
+        .match_body(Matcher::PartialJson(json!({
+            "reachable": true,
+            "latency_ms": 3,
+            "kubernetes": pod,
+        })))
+        .create_async()
+        .await;
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client
+        .add_datasource("main", "clickhouse", &BTreeMap::new())
+        .await
+        .unwrap();
+    client
+        .report_datasource_status(
+            "main",
+            &DatasourceStatus {
+                reachable: true,
+                error: None,
+                latency_ms: 3,
+                checked_at: chrono::Utc::now(),
+                labels: BTreeMap::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+    add.assert_async().await;
+    status.assert_async().await;
+    assert_eq!(pod_metadata().unwrap().pod_name, "tsight-agent-0");
+}
+
+#[tokio::test]
+async fn test_rotated_credential_files_are_reloaded() {
+    let dir = TempDir::new().unwrap();
+    let username_file = dir.path().join("username");
+    let password_file = dir.path().join("password");
+    fs::write(&username_file, "reader\n").unwrap();
+    fs::write(&password_file, "first-secret\n").unwrap();
+    let mut clickhouse = Server::new_async().await;
+    let first = clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .match_header(
+            "authorization",
+            basic_auth("reader", "first-secret").as_str(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let rotated = clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .match_header(
+            "authorization",
+            basic_auth("reader", "second-secret").as_str(),
+        )
+        .expect(2)
+        .create_async()
+        .await;
+    let datasource = DataSource {
+        name: "warehouse".to_string(),
+        hosts: vec![clickhouse.url()],
+        username_file: Some(username_file),
+        password_file: Some(password_file.clone()),
+        ..Default::default()
+    };
+
+    let executor = create_executor(&datasource, None).await.unwrap();
+    executor.execute_statement("SELECT 1").await.unwrap();
+    fs::write(&password_file, "second-secret\n").unwrap();
+    let executor = create_executor(&datasource, None).await.unwrap();
+    executor.execute_statement("SELECT 1").await.unwrap();
+    // A file missing mid-rotation leaves the last credential in use
+    fs::remove_file(&password_file).unwrap();
+    let executor = create_executor(&datasource, None).await.unwrap();
+    executor.execute_statement("SELECT 1").await.unwrap();
+
+    first.assert_async().await;
+    rotated.assert_async().await;
+}
+
+#[test]
+fn test_credential_files_are_checked_on_load() {
+    let load = |datasource: &str| {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("password"), "secret").unwrap();
+        let config_path = dir.path().join("config.yaml");
+        let content = format!(
+            "server:\n  api_key: \"test-api-key\"\n  server_url: \"http://localhost:8080\"\ndatasources:\n  - name: \"warehouse\"\n    source_type: \"clickhouse\"\n    hosts: [\"http://localhost:8123\"]\n{}",
+            datasource.replace("{dir}", &dir.path().display().to_string())
+        );
+        fs::write(&config_path, content).unwrap();
+        Config::load(Path::new(&config_path)).map_err(|e| e.to_string())
+    };
+
+    let config = load("    password_file: \"{dir}/password\"\n").unwrap();
+    assert_eq!(config.datasources[0].credentials().unwrap().1, "secret");
+    let error =
+        load("    password: \"plain\"\n    password_file: \"{dir}/password\"\n").unwrap_err();
+    assert!(
+        error.contains("'password' and 'password_file' can't both be set"),
+        "{}",
+        error
+    );
+    let error = load("    username_file: \"{dir}/missing\"\n").unwrap_err();
+    assert!(
+        error.contains("Failed to read credential from"),
+        "{}",
+        error
+    );
+}
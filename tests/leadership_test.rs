@@ -0,0 +1,221 @@
+use mockito::{Matcher, Server};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{
+    AcquireResultBody, JobKind, LeaseGrant, LeaseRequest, ServerApi, ServerClient,
+};
+use tsight_agent::config::{LeadershipConfig, MockConfig, MockResult};
+use tsight_agent::leadership::LeaseKeeper;
+use tsight_agent::models::{DataSource, DataSourceType, JobType};
+
+const TTL: Duration = Duration::from_millis(200);
+
+fn keeper(holder: &str, server: &Arc<FakeServer>) -> LeaseKeeper {
+    let config = LeadershipConfig {
+        holder: Some(holder.to_string()),
+        lease_ttl: TTL,
+        renew_interval: None,
+    };
+    LeaseKeeper::new(&config, server.clone())
+}
+
+#[tokio::test]
+async fn test_one_agent_of_a_pair_leads() {
+    let server = Arc::new(FakeServer::new());
+    let mut active = keeper("agent-a", &server);
+    let mut standby = keeper("agent-b", &server);
+
+    assert!(active.renew().await);
+    assert!(!standby.renew().await);
+    assert!(active.renew().await);
+
+    assert_eq!(server.lease_holder().as_deref(), Some("agent-a"));
+}
+
+#[tokio::test]
+async fn test_standby_takes_over_expired_lease() {
+    let server = Arc::new(FakeServer::new());
+    let mut active = keeper("agent-a", &server);
+    let mut standby = keeper("agent-b", &server);
+    active.renew().await;
+
+    tokio::time::sleep(TTL + Duration::from_millis(50)).await;
+
+    assert!(standby.renew().await);
+    assert!(!active.renew().await);
+    assert!(!active.is_leader());
+}
+
+#[tokio::test]
+async fn test_released_lease_taken_over_at_once() {
+    let server = Arc::new(FakeServer::new());
+    let mut active = keeper("agent-a", &server);
+    let mut standby = keeper("agent-b", &server);
+    active.renew().await;
+
+    active.release().await;
+
+    assert!(!active.is_leader());
+    assert!(standby.renew().await);
+}
+
+#[tokio::test]
+async fn test_leader_steps_down_when_lease_expires_unrenewed() {
+    let server = Arc::new(FakeServer::new());
+    let mut active = keeper("agent-a", &server);
+    active.renew().await;
+    server.set_unreachable(true);
+
+    // Still within the lease it holds
+    assert!(active.renew().await);
+
+    tokio::time::sleep(TTL).await;
+    assert!(!active.renew().await);
+}
+
+#[tokio::test]
+async fn test_leader_steps_down_when_renewal_hangs() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Grants the first request, then never answers
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        let mut first = true;
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            if first {
+                first = false;
+                let mut buffer = [0u8; 4096];
+                let _ = stream.read(&mut buffer).await.unwrap();
+                let body = r#"{"granted": true, "holder": "agent-a"}"#;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            } else {
+                connections.push(stream);
+            }
+        }
+    });
+    let config = LeadershipConfig {
+        holder: Some("agent-a".to_string()),
+        lease_ttl: TTL,
+        renew_interval: None,
+    };
+    let client = ServerClient::new("test-api-key".to_string(), url);
+    let mut active = LeaseKeeper::new(&config, Arc::new(client));
+    assert!(active.renew().await);
+
+    let renewed = tokio::time::timeout(TTL * 5, active.renew()).await;
+
+    assert_eq!(renewed.ok(), Some(false));
+    assert!(!active.is_leader());
+}
+
+fn datasource() -> DataSource {
+    let row: JobType = serde_json::from_value(json!({"one": 1})).unwrap();
+    DataSource {
+        name: "main".to_string(),
+        source_type: DataSourceType::Mock,
+        mock: MockConfig {
+            results: vec![MockResult {
+                rows: vec![row],
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_standby_agent_acquires_nothing_until_it_leads() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "job-1".to_string(),
+        datasource_name: "main".to_string(),
+        query: "SELECT 1 AS one".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+    let mut other = keeper("agent-a", &server);
+    let mut lease = keeper("agent-b", &server);
+    other.renew().await;
+    lease.renew().await;
+    let agent = create_job_agent_with_client(server.clone(), vec![datasource()], None)
+        .with_leadership(lease.leadership());
+    assert!(!agent.is_leader());
+
+    let running = tokio::spawn({
+        let agent = agent.clone();
+        async move { agent.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(server.job_results().is_empty());
+
+    other.release().await;
+    assert!(lease.renew().await);
+    assert!(agent.is_leader());
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    running.abort();
+
+    assert_eq!(server.job_results().len(), 1);
+}
+
+#[tokio::test]
+async fn test_server_client_requests_lease() {
+    let mut server = Server::new_async().await;
+    let lease_mock = server
+        .mock("POST", "/agent/lease")
+        .match_body(Matcher::Json(json!({"holder": "agent-a", "ttl_ms": 10000})))
+        .with_status(200)
+        .with_body(r#"{"granted": false, "holder": "agent-b"}"#)
+        .create_async()
+        .await;
+    let release_mock = server
+        .mock("POST", "/agent/lease/release")
+        .match_body(Matcher::Json(json!({"holder": "agent-a"})))
+        .with_status(200)
+        .create_async()
+        .await;
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    let grant = client
+        .acquire_lease(&LeaseRequest {
+            holder: "agent-a".to_string(),
+            ttl_ms: 10_000,
+        })
+        .await
+        .unwrap();
+    client.release_lease("agent-a").await.unwrap();
+
+    assert_eq!(
+        grant,
+        LeaseGrant {
+            granted: false,
+            holder: Some("agent-b".to_string()),
+        }
+    );
+    lease_mock.assert();
+    release_mock.assert();
+}
+
+#[test]
+fn test_config_defaults() {
+    let config: LeadershipConfig = serde_json::from_value(json!({"holder": "agent-a"})).unwrap();
+
+    assert_eq!(config.lease_ttl, Duration::from_secs(10));
+    assert_eq!(config.renew_interval, None);
+}
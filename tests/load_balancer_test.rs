@@ -0,0 +1,156 @@
+use std::net::TcpListener;
+use std::time::Duration;
+use tsight_agent::config::HostStrategy;
+use tsight_agent::executors::create_executor;
+use tsight_agent::health::probe_hosts;
+use tsight_agent::load_balancer::{load_balancer, HostHealth, LoadBalancer};
+use tsight_agent::metrics::metrics;
+use tsight_agent::models::DataSource;
+
+fn datasource(name: &str, strategy: HostStrategy, hosts: &[&str]) -> DataSource {
+    DataSource {
+        name: name.to_string(),
+        hosts: hosts.iter().map(|host| host.to_string()).collect(),
+        host_strategy: strategy,
+        ..Default::default()
+    }
+}
+
+fn hosts(datasource: &DataSource) -> Vec<String> {
+    datasource.hosts.clone()
+}
+
+fn health(healthy: bool, latency_ms: u64) -> HostHealth {
+    HostHealth {
+        healthy,
+        latency: Duration::from_millis(latency_ms),
+    }
+}
+
+/// ClickHouse the datasource tests run against
+const CLICKHOUSE_URL: &str = "http://localhost:8123";
+
+/// URL nothing listens on
+fn closed_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+#[test]
+fn test_first_healthy_skips_unhealthy_hosts() {
+    let balancer = LoadBalancer::default();
+    let datasource = datasource("main", HostStrategy::FirstHealthy, &["a", "b", "c"]);
+
+    assert_eq!(
+        balancer.select(&datasource, &hosts(&datasource)).unwrap(),
+        "a"
+    );
+    balancer.record_probe("main", "a", health(false, 5));
+    assert_eq!(
+        balancer.select(&datasource, &hosts(&datasource)).unwrap(),
+        "b"
+    );
+    balancer.record_probe("main", "a", health(true, 5));
+    assert_eq!(
+        balancer.select(&datasource, &hosts(&datasource)).unwrap(),
+        "a"
+    );
+}
+
+#[test]
+fn test_round_robin_cycles_through_healthy_hosts() {
+    let balancer = LoadBalancer::default();
+    let datasource = datasource("main", HostStrategy::RoundRobin, &["a", "b", "c"]);
+    balancer.record_probe("main", "b", health(false, 5));
+
+    let picked: Vec<String> = (0..4)
+        .map(|_| balancer.select(&datasource, &hosts(&datasource)).unwrap())
+        .collect();
+    assert_eq!(picked, ["a", "c", "a", "c"]);
+}
+
+#[test]
+fn test_nearest_picks_lowest_latency() {
+    let balancer = LoadBalancer::default();
+    let datasource = datasource("main", HostStrategy::Nearest, &["a", "b", "c"]);
+
+    // Nothing probed yet
+    assert_eq!(
+        balancer.select(&datasource, &hosts(&datasource)).unwrap(),
+        "a"
+    );
+
+    balancer.record_probe("main", "a", health(true, 40));
+    balancer.record_probe("main", "b", health(true, 12));
+    balancer.record_probe("main", "c", health(false, 1));
+    assert_eq!(
+        balancer.select(&datasource, &hosts(&datasource)).unwrap(),
+        "b"
+    );
+}
+
+#[test]
+fn test_all_hosts_tried_when_none_is_healthy() {
+    let balancer = LoadBalancer::default();
+    let datasource = datasource("main", HostStrategy::FirstHealthy, &["a", "b"]);
+    balancer.record_probe("main", "a", health(false, 5));
+    balancer.record_probe("main", "b", health(false, 5));
+
+    assert_eq!(
+        balancer.select(&datasource, &hosts(&datasource)).unwrap(),
+        "a"
+    );
+    assert_eq!(balancer.select(&datasource, &[]), None);
+}
+
+#[test]
+fn test_datasources_are_balanced_separately() {
+    let balancer = LoadBalancer::default();
+    let main = datasource("main", HostStrategy::FirstHealthy, &["a", "b"]);
+    let other = datasource("other", HostStrategy::FirstHealthy, &["a", "b"]);
+    balancer.record_probe("main", "a", health(false, 5));
+
+    assert_eq!(balancer.select(&main, &hosts(&main)).unwrap(), "b");
+    assert_eq!(balancer.select(&other, &hosts(&other)).unwrap(), "a");
+}
+
+#[tokio::test]
+async fn test_probes_record_unreachable_hosts() {
+    let (first, second) = (closed_url(), closed_url());
+    let datasource = datasource("unreachable", HostStrategy::RoundRobin, &[&first, &second]);
+
+    probe_hosts(&datasource).await;
+
+    for host in [&first, &second] {
+        assert!(!load_balancer().health("unreachable", host).unwrap().healthy);
+        assert!(metrics().render().contains(&format!(
+            "tsight_host_healthy{{datasource=\"unreachable\",host=\"{}\"}} 0",
+            host
+        )));
+    }
+    // With no healthy host, all of them are still tried
+    let executor = create_executor(&datasource, None).await.unwrap();
+    assert!(executor.host().is_some());
+}
+
+#[tokio::test]
+async fn test_probes_steer_executors_to_healthy_hosts() {
+    let closed = closed_url();
+    let datasource = datasource(
+        "probed",
+        HostStrategy::FirstHealthy,
+        &[&closed, CLICKHOUSE_URL],
+    );
+
+    probe_hosts(&datasource).await;
+
+    assert!(!load_balancer().health("probed", &closed).unwrap().healthy);
+    assert!(
+        load_balancer()
+            .health("probed", CLICKHOUSE_URL)
+            .unwrap()
+            .healthy
+    );
+    let executor = create_executor(&datasource, None).await.unwrap();
+    assert_eq!(executor.host(), Some(CLICKHOUSE_URL));
+}
@@ -0,0 +1,95 @@
+use serde_json::Value;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{LogFileConfig, LogFormat, LoggingConfig};
+use tsight_agent::logging::{self, format_layer};
+
+/// Log output collected in memory
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn lines(&self) -> Vec<Value> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn test_json_lines_carry_task_fields() {
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::registry()
+        .with(format_layer(LogFormat::Json, move || writer.clone()))
+        .with(LevelFilter::DEBUG);
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "42".to_string(),
+        datasource_name: "missing".to_string(),
+        query: "SELECT 1".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+    let agent = create_job_agent_with_client(server, vec![], None);
+    agent.process_next().await.unwrap_err();
+
+    let lines = buffer.lines();
+    let finished = lines
+        .iter()
+        .find(|line| line["message"] == "Task finished")
+        .expect("task outcome is logged");
+    assert_eq!(finished["level"], "DEBUG");
+    assert_eq!(finished["succeeded"], false);
+    assert!(finished["duration_ms"].is_u64());
+    assert_eq!(finished["span"]["name"], "task");
+    assert_eq!(finished["span"]["task_id"], "42");
+    assert_eq!(finished["span"]["queue"], "job");
+    assert_eq!(finished["span"]["datasource"], "missing");
+}
+
+#[test]
+fn test_logs_written_to_file() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("logs/agent.log");
+    let handle = logging::init();
+
+    handle
+        .configure(&LoggingConfig {
+            format: LogFormat::Json,
+            file: Some(LogFileConfig::new(PathBuf::from(&path))),
+        })
+        .unwrap();
+    log::error!("Written to the log file");
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let line: Value = serde_json::from_str(contents.lines().last().unwrap()).unwrap();
+    assert_eq!(line["message"], "Written to the log file");
+    assert_eq!(line["level"], "ERROR");
+}
@@ -16,6 +16,7 @@ fn create_test_config(server_url: &str) -> Config {
         server: ServerConfig {
             api_key: "test_api_key".to_string(),
             server_url: server_url.to_string(),
+            ..Default::default()
         },
         datasources: vec![DataSource {
             name: "test_source".to_string(),
@@ -25,8 +26,10 @@ fn create_test_config(server_url: &str) -> Config {
             password: "".to_string(),
             filters: None,
             timeout: 60,
+            ..Default::default()
         }],
         global_filters: None,
+        ..Default::default()
     }
 }
 
@@ -41,7 +44,7 @@ async fn test_initialize_agents() {
     let config = create_test_config(&server_url);
 
     // Initialize agents
-    let (hp_agent, job_agent, main_agent) = initialize_agents(&config);
+    let (hp_agent, job_agent, main_agent) = initialize_agents(&config).unwrap();
 
     // Verify agents were created with correct types
     match hp_agent {
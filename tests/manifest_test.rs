@@ -0,0 +1,101 @@
+use mockito::{Matcher, Server};
+use serde_json::{json, Value};
+use tsight_agent::client::manifest::Manifest;
+use tsight_agent::client::{JobResultSet, ServerApi, ServerClient};
+use tsight_agent::executors::base::ExecutionStats;
+use tsight_agent::filters::FilterStats;
+use tsight_agent::models::JobType;
+
+fn row(value: Value) -> JobType {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn test_manifest_ignores_key_order() {
+    let mut first = JobType::new();
+    first.insert("b".to_string(), json!({"y": 1, "x": 2}));
+    first.insert("a".to_string(), json!("1"));
+    let mut second = JobType::new();
+    second.insert("a".to_string(), json!("1"));
+    second.insert("b".to_string(), json!({"x": 2, "y": 1}));
+
+    let manifest = Manifest::of(&[first]).unwrap();
+
+    assert_eq!(manifest, Manifest::of(&[second]).unwrap());
+    assert_eq!(manifest.row_count, 1);
+    assert_eq!(
+        manifest.byte_size,
+        r#"[{"a":"1","b":{"x":2,"y":1}}]"#.len() as u64
+    );
+    assert_eq!(manifest.checksum.len(), 16);
+}
+
+#[test]
+fn test_manifest_detects_changed_records() {
+    let manifest = Manifest::of(&[row(json!({"cnt": 1})), row(json!({"cnt": 2}))]).unwrap();
+    let changed = Manifest::of(&[row(json!({"cnt": 1})), row(json!({"cnt": 3}))]).unwrap();
+    let truncated = Manifest::of(&[row(json!({"cnt": 1}))]).unwrap();
+
+    assert_ne!(manifest.checksum, changed.checksum);
+    assert_eq!(manifest.byte_size, changed.byte_size);
+    assert_eq!(truncated.row_count, 1);
+    assert_ne!(manifest.checksum, truncated.checksum);
+}
+
+#[tokio::test]
+async fn test_job_submission_carries_manifests() {
+    let records = vec![row(json!({"cnt": 1}))];
+    let manifest = Manifest::of(&records).unwrap();
+
+    let mut server = Server::new_async().await;
+    let single = server
+        .mock("POST", "/jobs/1/submit")
+        .match_body(Matcher::PartialJson(json!({
+            "manifest": {
+                "row_count": 1,
+                "byte_size": manifest.byte_size,
+                "checksum": manifest.checksum
+            }
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+    let sets = server
+        .mock("POST", "/jobs/2/submit")
+        .match_body(Matcher::PartialJson(json!({
+            "manifest": {"row_count": 0},
+            "result_sets": [{"name": "orders", "manifest": {"checksum": manifest.checksum}}]
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client
+        .submit_job_results(
+            "1",
+            records.clone(),
+            vec![],
+            FilterStats::default(),
+            ExecutionStats::default(),
+        )
+        .await
+        .unwrap();
+    client
+        .submit_job_result_sets(
+            "2",
+            vec![JobResultSet {
+                name: "orders".to_string(),
+                columns: vec![],
+                records,
+                manifest: None,
+            }],
+            FilterStats::default(),
+            ExecutionStats::default(),
+        )
+        .await
+        .unwrap();
+
+    single.assert_async().await;
+    sets.assert_async().await;
+}
@@ -0,0 +1,131 @@
+use mockito::{Matcher, Server};
+use std::sync::Arc;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{FilterAction, GlobalFilters, ListenerConfig, SqlFilterRules};
+use tsight_agent::listener::Listener;
+use tsight_agent::metrics::metrics;
+use tsight_agent::models::DataSource;
+
+const BODY: &str = concat!(
+    "[\"id\",\"email\"]\n",
+    "[\"UInt64\",\"String\"]\n",
+    "[1,\"john@example.com\"]\n",
+    "[2,\"jane@example.com\"]\n",
+    "[3,\"nobody\"]\n",
+);
+
+/// Value of the sample of `name` with exactly `labels` in rendered metrics
+fn sample(rendered: &str, name: &str, labels: &str) -> Option<f64> {
+    let prefix = format!("{}{{{}}} ", name, labels);
+    rendered
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .map(|value| value.parse().unwrap())
+}
+
+fn job(id: &str, datasource_name: &str) -> AcquireResultBody {
+    AcquireResultBody {
+        id: id.to_string(),
+        datasource_name: datasource_name.to_string(),
+        query: "SELECT id, email FROM users".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    }
+}
+
+#[tokio::test]
+async fn test_listener_serves_metrics() {
+    let listener = Listener::bind(&ListenerConfig {
+        address: "127.0.0.1:0".to_string(),
+    })
+    .await
+    .unwrap();
+    let address = listener.local_addr().unwrap();
+    listener.spawn();
+
+    let response = reqwest::get(format!("http://{}/metrics", address))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let body = response.text().await.unwrap();
+    for queue in ["high_priority", "observation", "job"] {
+        let labels = format!("queue=\"{}\"", queue);
+        assert!(sample(&body, "tsight_tasks_processed_total", &labels).is_some());
+        assert!(sample(&body, "tsight_tasks_failed_total", &labels).is_some());
+    }
+
+    let response = reqwest::get(format!("http://{}/other", address))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_job_metrics() {
+    let mut clickhouse = Server::new_async().await;
+    let _mock = clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .with_body(BODY)
+        .create_async()
+        .await;
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(job("1", "metrics_main"));
+    server.enqueue_job(job("2", "metrics_missing"));
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            column_value_regexes: Some(vec!["@example\\.com$".to_string()]),
+            action: Some(FilterAction::DropRow),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let agent = create_job_agent_with_client(
+        server.clone(),
+        vec![DataSource {
+            name: "metrics_main".to_string(),
+            hosts: vec![clickhouse.url()],
+            ..Default::default()
+        }],
+        Some(filters),
+    );
+
+    let before = metrics().render();
+    agent.process_next().await.unwrap();
+    agent.process_next().await.unwrap_err();
+    let after = metrics().render();
+
+    let delta = |name: &str, labels: &str| {
+        sample(&after, name, labels).unwrap_or_default()
+            - sample(&before, name, labels).unwrap_or_default()
+    };
+    assert!(delta("tsight_tasks_processed_total", "queue=\"job\"") >= 1.0);
+    assert!(delta("tsight_tasks_failed_total", "queue=\"job\"") >= 1.0);
+    assert!(delta("tsight_acquire_duration_seconds_count", "queue=\"job\"") >= 2.0);
+    assert!(delta("tsight_task_duration_seconds_count", "queue=\"job\"") >= 2.0);
+    assert_eq!(
+        delta(
+            "tsight_query_duration_seconds_count",
+            "datasource=\"metrics_main\""
+        ),
+        1.0
+    );
+    assert_eq!(
+        delta(
+            "tsight_filtered_rows_total",
+            "datasource=\"metrics_main\",reason=\"filter\""
+        ),
+        2.0
+    );
+}
@@ -0,0 +1,174 @@
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{Config, MockConfig, MockResult};
+use tsight_agent::executors::base::QueryExecutor;
+use tsight_agent::executors::clickhouse_source::ResultColumn;
+use tsight_agent::executors::create_executor;
+use tsight_agent::executors::mock::MockExecutor;
+use tsight_agent::models::{DataSourceType, JobType};
+
+fn load_config() -> Config {
+    Config::load(Path::new("tests/test_configs/mock_config.yaml")).unwrap()
+}
+
+fn job(query: &str) -> AcquireResultBody {
+    AcquireResultBody {
+        id: "job-1".to_string(),
+        datasource_name: "demo".to_string(),
+        query: query.to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    }
+}
+
+fn row(values: serde_json::Value) -> JobType {
+    serde_json::from_value(values).unwrap()
+}
+
+#[test]
+fn test_mock_datasource_config_loads() {
+    let config = load_config();
+    let datasource = &config.datasources[0];
+
+    assert_eq!(datasource.source_type, DataSourceType::Mock);
+    assert!(datasource.hosts.is_empty());
+    assert_eq!(datasource.mock.results.len(), 2);
+    assert_eq!(
+        datasource.mock.results[1].error.as_deref(),
+        Some("Unknown table")
+    );
+    assert_eq!(datasource.mock.tables[0].database, "default");
+}
+
+#[tokio::test]
+async fn test_job_on_mock_datasource_submits_filtered_rows() {
+    let config = load_config();
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(job("SELECT id, email FROM users"));
+
+    let agent =
+        create_job_agent_with_client(server.clone(), config.datasources, config.global_filters);
+    agent.process_next().await.unwrap();
+
+    let results = server.job_results();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, "job-1");
+    assert_eq!(
+        results[0].1,
+        vec![row(json!({"id": 1, "email": "alice@example.com"}))]
+    );
+    assert_eq!(
+        server.job_columns()[0].1,
+        vec![
+            ResultColumn::from_clickhouse_type("id", "UInt64"),
+            ResultColumn::from_clickhouse_type("email", "String"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_mock_error_result_fails_job() {
+    let config = load_config();
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(job("SELECT * FROM missing"));
+
+    let agent =
+        create_job_agent_with_client(server.clone(), config.datasources, config.global_filters);
+    assert!(agent.process_next().await.is_err());
+
+    let errors = server.job_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].1.contains("Unknown table"));
+}
+
+#[tokio::test]
+async fn test_query_without_matching_result_fails() {
+    let config = load_config();
+    let executor = create_executor(&config.datasources[0], None).await.unwrap();
+
+    let error = executor
+        .execute_job_with_columns("SELECT 1")
+        .await
+        .unwrap_err();
+
+    assert!(error
+        .to_string()
+        .contains("No mock result matches the query"));
+}
+
+#[tokio::test]
+async fn test_result_without_regex_matches_every_query() {
+    let config = MockConfig {
+        results: vec![MockResult {
+            rows: vec![row(json!({"n": 1}))],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let executor = MockExecutor::new(config, None).unwrap();
+
+    let (rows, _, _) = executor.execute_job_with_columns("SELECT 1").await.unwrap();
+
+    assert_eq!(rows, vec![row(json!({"n": 1}))]);
+    assert_eq!(executor.execution_stats().rows_returned, 1);
+}
+
+#[tokio::test]
+async fn test_columns_inferred_from_first_row() {
+    let config = MockConfig {
+        results: vec![MockResult {
+            rows: vec![row(json!({"name": "a", "count": 3, "ratio": 0.5}))],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let executor = MockExecutor::new(config, None).unwrap();
+
+    let (_, _, columns) = executor.execute_job_with_columns("SELECT 1").await.unwrap();
+
+    assert_eq!(
+        columns,
+        vec![
+            ResultColumn::from_clickhouse_type("count", "Int64"),
+            ResultColumn::from_clickhouse_type("name", "String"),
+            ResultColumn::from_clickhouse_type("ratio", "Float64"),
+        ]
+    );
+}
+
+#[test]
+fn test_invalid_query_regex_rejected() {
+    let config = MockConfig {
+        results: vec![MockResult {
+            query_regex: Some("(".to_string()),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    assert!(MockExecutor::new(config, None).is_err());
+}
+
+#[tokio::test]
+async fn test_discovery_applies_filters() {
+    let config = load_config();
+    let executor = create_executor(&config.datasources[0], config.global_filters)
+        .await
+        .unwrap();
+
+    let schemas = executor.discover_schemas().await.unwrap();
+
+    assert_eq!(schemas.len(), 1);
+    assert_eq!(schemas[0].table, "users");
+    assert_eq!(schemas[0].row_count, 2);
+    assert_eq!(schemas[0].columns.len(), 2);
+}
@@ -0,0 +1,175 @@
+use mockito::{Matcher, Server};
+use serde_json::json;
+use std::sync::Arc;
+use tsight_agent::agent::factory::{
+    create_job_agent_with_client, create_observation_agent_with_client,
+};
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind, TaskQuery};
+use tsight_agent::config::{QueryPolicy, StatementKind};
+use tsight_agent::models::DataSource;
+
+fn datasource(url: &str) -> DataSource {
+    DataSource {
+        name: "main".to_string(),
+        hosts: vec![url.to_string()],
+        query_policy: QueryPolicy {
+            allowed_statements: vec![StatementKind::Select, StatementKind::Set],
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn task_query(name: Option<&str>, query: &str) -> TaskQuery {
+    TaskQuery {
+        name: name.map(str::to_string),
+        query: query.to_string(),
+        signature: None,
+    }
+}
+
+fn multi_query_task(queries: Vec<TaskQuery>) -> AcquireResultBody {
+    AcquireResultBody {
+        id: "42".to_string(),
+        datasource_name: "main".to_string(),
+        query: String::new(),
+        queries: Some(queries),
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    }
+}
+
+#[tokio::test]
+async fn test_job_queries_run_on_one_session() {
+    let mut clickhouse = Server::new_async().await;
+    let session = Matcher::Regex("session_id=tsight-42-".to_string());
+    let set = clickhouse
+        .mock("POST", "/")
+        .match_query(session.clone())
+        .match_body("SET max_threads = 2")
+        .create_async()
+        .await;
+    let orders = clickhouse
+        .mock("POST", "/")
+        .match_query(session.clone())
+        .match_body(Matcher::Regex(
+            "^SELECT count\\(\\) AS cnt FROM orders".to_string(),
+        ))
+        .with_body("[\"cnt\"]\n[\"UInt64\"]\n[3]\n")
+        .create_async()
+        .await;
+    let users = clickhouse
+        .mock("POST", "/")
+        .match_query(session)
+        .match_body(Matcher::Regex("^SELECT name FROM users".to_string()))
+        .with_body("[\"name\"]\n[\"String\"]\n[\"a\"]\n[\"b\"]\n")
+        .create_async()
+        .await;
+
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(multi_query_task(vec![
+        task_query(None, "SET max_threads = 2"),
+        task_query(Some("orders"), "SELECT count() AS cnt FROM orders"),
+        task_query(Some("users"), "SELECT name FROM users"),
+    ]));
+    let agent =
+        create_job_agent_with_client(server.clone(), vec![datasource(&clickhouse.url())], None);
+
+    agent.process_next().await.unwrap();
+
+    set.assert_async().await;
+    orders.assert_async().await;
+    users.assert_async().await;
+    let result_sets = &server.job_result_sets()[0].1;
+    assert_eq!(result_sets.len(), 2);
+    assert_eq!(result_sets[0].name, "orders");
+    assert_eq!(result_sets[0].records[0]["cnt"], json!(3));
+    assert_eq!(result_sets[1].name, "users");
+    assert_eq!(result_sets[1].records.len(), 2);
+    assert_eq!(result_sets[1].columns[0].type_name, "string");
+    assert!(server.job_results().is_empty());
+}
+
+#[tokio::test]
+async fn test_task_queries_return_named_series() {
+    let mut clickhouse = Server::new_async().await;
+    let _mock = clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::Regex("^SELECT".to_string()))
+        .with_body("[\"t\",\"cnt\"]\n[\"UInt8\",\"UInt8\"]\n[1,2]\n")
+        .create_async()
+        .await;
+
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_task(
+        multi_query_task(vec![
+            task_query(Some("today"), "SELECT 1 AS t, 2 AS cnt"),
+            task_query(Some("yesterday"), "SELECT 1 AS t, 2 AS cnt"),
+        ]),
+        false,
+    );
+    let agent = create_observation_agent_with_client(
+        server.clone(),
+        vec![datasource(&clickhouse.url())],
+        false,
+        None,
+    );
+
+    agent.process_next().await.unwrap();
+
+    let result_sets = &server.task_result_sets()[0].1;
+    let names: Vec<_> = result_sets.iter().map(|set| set.name.as_str()).collect();
+    assert_eq!(names, vec!["today", "yesterday"]);
+    assert_eq!(result_sets[1].records[0].t, 1000);
+    assert_eq!(result_sets[1].records[0].values["cnt"], 2.0);
+}
+
+#[tokio::test]
+async fn test_every_query_is_checked_by_policy() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(multi_query_task(vec![
+        task_query(Some("orders"), "SELECT 1"),
+        task_query(None, "DROP TABLE orders"),
+    ]));
+    let agent =
+        create_job_agent_with_client(server.clone(), vec![datasource("http://127.0.0.1:1")], None);
+
+    assert!(agent.process_next().await.is_err());
+    let errors = server.job_errors();
+    assert!(
+        errors[0].1.contains("rejected by policy"),
+        "{}",
+        errors[0].1
+    );
+}
+
+#[tokio::test]
+async fn test_set_statements_need_to_be_allowed() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(multi_query_task(vec![
+        task_query(None, "SET max_threads = 2"),
+        task_query(Some("orders"), "SELECT 1"),
+    ]));
+    let agent = create_job_agent_with_client(
+        server.clone(),
+        vec![DataSource {
+            query_policy: QueryPolicy::default(),
+            ..datasource("http://127.0.0.1:1")
+        }],
+        None,
+    );
+
+    assert!(agent.process_next().await.is_err());
+    let errors = server.job_errors();
+    assert!(
+        errors[0].1.contains("SET statements are not allowed"),
+        "{}",
+        errors[0].1
+    );
+}
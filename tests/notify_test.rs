@@ -0,0 +1,179 @@
+use chrono::{Duration, Utc};
+use mockito::{Matcher, Server};
+use serde_json::json;
+use std::net::TcpListener;
+use tsight_agent::client::{DatasourceStatus, ServerApi, ServerClient};
+use tsight_agent::config::NotificationsConfig;
+use tsight_agent::notify::{conditions, Condition, Conditions, NotificationState, Notifier};
+
+fn notifications_config(webhooks: serde_json::Value) -> NotificationsConfig {
+    serde_json::from_value(json!({
+        "webhooks": webhooks,
+        "datasource_down_after": "5m",
+        "server_down_after": "0s",
+        "auth_failures": 3,
+        "auth_failure_window": "10m",
+    }))
+    .unwrap()
+}
+
+fn status(reachable: bool, checked_at: chrono::DateTime<Utc>) -> DatasourceStatus {
+    DatasourceStatus {
+        reachable,
+        error: (!reachable).then(|| "Connection refused".to_string()),
+        latency_ms: 5,
+        checked_at,
+        labels: Default::default(),
+    }
+}
+
+fn unused_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+#[test]
+fn test_datasource_down_fires_once_and_resolves() {
+    let conditions = Box::leak(Box::new(Conditions::new()));
+    let mut notifier =
+        Notifier::with_conditions(&notifications_config(json!([])), conditions).unwrap();
+    let now = Utc::now();
+
+    conditions.record_datasource_status("main", &status(false, now - Duration::minutes(2)));
+    assert!(notifier.evaluate(now).is_empty());
+
+    // Later probes keep the start of the outage
+    conditions.record_datasource_status("main", &status(false, now));
+    let notifications = notifier.evaluate(now + Duration::minutes(3));
+    assert_eq!(notifications.len(), 1);
+    let firing = &notifications[0];
+    assert_eq!(firing.condition, Condition::DatasourceDown);
+    assert_eq!(firing.state, NotificationState::Firing);
+    assert_eq!(firing.subject, "main");
+    assert_eq!(firing.since, now - Duration::minutes(2));
+    assert!(
+        firing.message.contains("Connection refused"),
+        "{}",
+        firing.message
+    );
+    assert!(notifier.evaluate(now + Duration::minutes(4)).is_empty());
+
+    conditions.record_datasource_status("main", &status(true, now + Duration::minutes(5)));
+    let notifications = notifier.evaluate(now + Duration::minutes(5));
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].state, NotificationState::Resolved);
+    assert_eq!(
+        notifications[0].message,
+        "Datasource main is reachable again"
+    );
+}
+
+#[test]
+fn test_auth_failures_within_window() {
+    let conditions = Box::leak(Box::new(Conditions::new()));
+    let mut notifier =
+        Notifier::with_conditions(&notifications_config(json!([])), conditions).unwrap();
+    let now = Utc::now();
+    let server = "https://tsight.example.com";
+
+    conditions.record_auth_failure(server, now - Duration::minutes(20));
+    conditions.record_auth_failure(server, now - Duration::minutes(1));
+    conditions.record_auth_failure(server, now);
+    assert!(notifier.evaluate(now).is_empty());
+
+    conditions.record_auth_failure(server, now);
+    let notifications = notifier.evaluate(now);
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].condition, Condition::AuthFailures);
+    assert_eq!(notifications[0].subject, server);
+    assert_eq!(notifications[0].since, now - Duration::minutes(1));
+
+    let notifications = notifier.evaluate(now + Duration::minutes(10));
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].state, NotificationState::Resolved);
+}
+
+#[tokio::test]
+async fn test_server_client_records_conditions() {
+    let url = unused_url();
+    let client = ServerClient::new("test-api-key".to_string(), url.clone());
+    assert!(client.acquire_next_query(false).await.is_err());
+
+    let mut server = Server::new_async().await;
+    server
+        .mock("POST", "/tasks/acquire")
+        .with_status(401)
+        .expect(3)
+        .create_async()
+        .await;
+    let rejecting = ServerClient::new("wrong-api-key".to_string(), server.url());
+    for _ in 0..3 {
+        assert!(rejecting.acquire_next_query(false).await.is_err());
+    }
+
+    let mut notifier = Notifier::new(&notifications_config(json!([]))).unwrap();
+    let notifications = notifier.evaluate(Utc::now());
+    let subjects: Vec<_> = notifications
+        .iter()
+        .map(|notification| (notification.condition, notification.subject.as_str()))
+        .collect();
+    assert!(subjects.contains(&(Condition::ServerUnreachable, url.as_str())));
+    assert!(subjects.contains(&(Condition::AuthFailures, server.url().as_str())));
+    assert!(!subjects.contains(&(Condition::ServerUnreachable, server.url().as_str())));
+}
+
+#[tokio::test]
+async fn test_webhooks_are_posted() {
+    let mut server = Server::new_async().await;
+    let generic = server
+        .mock("POST", "/generic")
+        .match_header("authorization", "Bearer webhook-token")
+        .match_body(Matcher::PartialJson(json!({
+            "condition": "datasource_down",
+            "state": "firing",
+            "subject": "webhook_test",
+        })))
+        .expect(1)
+        .create_async()
+        .await;
+    let slack = server
+        .mock("POST", "/slack")
+        .match_body(Matcher::Regex(
+            r#"^\{"text":":rotating_light: \*tsight-agent on .+\*: Datasource webhook_test has been unreachable"#
+                .to_string(),
+        ))
+        .expect(1)
+        .create_async()
+        .await;
+
+    let config = notifications_config(json!([
+        {
+            "url": format!("{}/generic", server.url()),
+            "headers": {"Authorization": "Bearer webhook-token"},
+        },
+        {"url": format!("{}/slack", server.url()), "format": "slack"},
+    ]));
+    let conditions = Box::leak(Box::new(Conditions::new()));
+    conditions.record_datasource_status(
+        "webhook_test",
+        &status(false, Utc::now() - Duration::minutes(10)),
+    );
+    let mut notifier = Notifier::with_conditions(&config, conditions).unwrap();
+    notifier.check().await;
+    // Nothing changed, so nothing is posted again
+    notifier.check().await;
+
+    generic.assert_async().await;
+    slack.assert_async().await;
+}
+
+#[test]
+fn test_process_conditions_are_shared() {
+    let now = Utc::now();
+    conditions().record_datasource_status("shared", &status(false, now - Duration::hours(1)));
+    let mut notifier = Notifier::new(&notifications_config(json!([]))).unwrap();
+    assert!(notifier
+        .evaluate(now)
+        .iter()
+        .any(|notification| notification.subject == "shared"));
+}
@@ -0,0 +1,227 @@
+use mockito::{Mock, Server};
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::{metric, number_data_point, Metric};
+use prost::Message;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tsight_agent::agent::factory::create_observation_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{MockConfig, MockResult, OtlpMetricsConfig};
+use tsight_agent::models::{DataSource, DataSourceType, JobType, Record};
+use tsight_agent::otlp_metrics::{self, gauges, MetricExporter};
+use tsight_agent::timeseries::TsMapping;
+
+fn otlp_config(endpoint: &str, datasources: Option<&[&str]>) -> OtlpMetricsConfig {
+    serde_json::from_value(json!({
+        "endpoint": endpoint,
+        "headers": {"x-api-key": "collector-key"},
+        "datasources": datasources,
+    }))
+    .unwrap()
+}
+
+fn record(t: i64, values: &[(&str, f64)], labels: &[(&str, &str)]) -> Record {
+    Record {
+        t,
+        values: values
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect(),
+        labels: labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+fn attributes(attributes: &[KeyValue]) -> BTreeMap<String, String> {
+    attributes
+        .iter()
+        .map(|attribute| {
+            let value = match attribute
+                .value
+                .as_ref()
+                .and_then(|value| value.value.as_ref())
+            {
+                Some(any_value::Value::StringValue(value)) => value.clone(),
+                other => panic!("{:?}", other),
+            };
+            (attribute.key.clone(), value)
+        })
+        .collect()
+}
+
+/// Points of a gauge as time in milliseconds, value and attributes
+fn points(metric: &Metric) -> Vec<(u64, f64, BTreeMap<String, String>)> {
+    let Some(metric::Data::Gauge(gauge)) = &metric.data else {
+        panic!("{:?}", metric);
+    };
+    gauge
+        .data_points
+        .iter()
+        .map(|point| {
+            let Some(number_data_point::Value::AsDouble(value)) = point.value else {
+                panic!("{:?}", point);
+            };
+            (
+                point.time_unix_nano / 1_000_000,
+                value,
+                attributes(&point.attributes),
+            )
+        })
+        .collect()
+}
+
+fn decode(body: &[u8]) -> ExportMetricsServiceRequest {
+    ExportMetricsServiceRequest::decode(body).unwrap()
+}
+
+/// Metrics of a request body, which must come from a single resource
+fn metrics(request: &ExportMetricsServiceRequest) -> Vec<Metric> {
+    assert_eq!(request.resource_metrics.len(), 1);
+    request.resource_metrics[0]
+        .scope_metrics
+        .iter()
+        .flat_map(|scope| scope.metrics.clone())
+        .collect()
+}
+
+async fn wait_for(mock: &Mock) {
+    for _ in 0..100 {
+        if mock.matched_async().await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    mock.assert_async().await;
+}
+
+#[test]
+fn test_gauge_per_value_column() {
+    let records = [
+        record(1_000, &[("cnt", 3.0), ("errors", 1.0)], &[("status", "ok")]),
+        record(2_000, &[("cnt", 5.0)], &[]),
+    ];
+
+    let metrics = gauges("tsight_", "demo", Some("orders"), &records);
+
+    let names: Vec<_> = metrics.iter().map(|metric| metric.name.as_str()).collect();
+    assert_eq!(names, vec!["tsight_cnt", "tsight_errors"]);
+    let attributes = |labels: &[(&str, &str)]| -> BTreeMap<String, String> {
+        labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .chain([
+                ("datasource".to_string(), "demo".to_string()),
+                ("result_set".to_string(), "orders".to_string()),
+            ])
+            .collect()
+    };
+    assert_eq!(
+        points(&metrics[0]),
+        vec![
+            (1_000, 3.0, attributes(&[("status", "ok")])),
+            (2_000, 5.0, attributes(&[])),
+        ]
+    );
+    assert_eq!(
+        points(&metrics[1]),
+        vec![(1_000, 1.0, attributes(&[("status", "ok")]))]
+    );
+}
+
+#[tokio::test]
+async fn test_exporter_posts_exported_datasources() {
+    let mut server = Server::new_async().await;
+    let collector = server
+        .mock("POST", "/v1/metrics")
+        .match_header("content-type", "application/x-protobuf")
+        .match_header("x-api-key", "collector-key")
+        .match_request(|request| {
+            let request = decode(request.body().unwrap());
+            let resource = request.resource_metrics[0].resource.as_ref().unwrap();
+            let metrics = metrics(&request);
+            attributes(&resource.attributes)["service.name"] == "tsight-agent"
+                && metrics.len() == 1
+                && points(&metrics[0])
+                    .iter()
+                    .all(|(_, _, attributes)| attributes["datasource"] == "demo")
+        })
+        .expect(1)
+        .create_async()
+        .await;
+
+    let exporter = MetricExporter::spawn(&otlp_config(&server.url(), Some(&["demo"]))).unwrap();
+    exporter.export("other", None, &[record(1_000, &[("cnt", 1.0)], &[])]);
+    exporter.export("demo", None, &[]);
+    exporter.export("demo", None, &[record(1_000, &[("cnt", 2.0)], &[])]);
+
+    wait_for(&collector).await;
+    collector.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_task_records_are_exported() {
+    let mut server = Server::new_async().await;
+    let collector = server
+        .mock("POST", "/v1/metrics")
+        .match_request(|request| {
+            let metrics = metrics(&decode(request.body().unwrap()));
+            metrics.len() == 1
+                && metrics[0].name == "tsight_cnt"
+                && points(&metrics[0])
+                    .iter()
+                    .map(|(t, value, _)| (*t, *value))
+                    .eq([(0, 1.0), (60_000, 2.0)])
+        })
+        .expect(1)
+        .create_async()
+        .await;
+    otlp_metrics::init(&otlp_config(&server.url(), None)).unwrap();
+
+    let rows: Vec<JobType> = [(0, 1), (60, 2)]
+        .into_iter()
+        .map(|(t, cnt)| serde_json::from_value(json!({"t": t, "cnt": cnt})).unwrap())
+        .collect();
+    let datasource = DataSource {
+        name: "demo".to_string(),
+        source_type: DataSourceType::Mock,
+        mock: MockConfig {
+            results: vec![MockResult {
+                rows,
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let fake_server = Arc::new(FakeServer::new());
+    fake_server.enqueue_task(
+        AcquireResultBody {
+            id: "task-1".to_string(),
+            datasource_name: "demo".to_string(),
+            query: "SELECT t, cnt FROM events".to_string(),
+            queries: None,
+            ts_mapping: Some(TsMapping::default()),
+            timeout: None,
+            enqueued_at: None,
+            kind: JobKind::Query,
+            signature: None,
+            time_range: None,
+        },
+        false,
+    );
+
+    let agent =
+        create_observation_agent_with_client(fake_server.clone(), vec![datasource], false, None);
+    agent.process_next().await.unwrap();
+
+    // The server still gets the records
+    assert_eq!(fake_server.task_results()[0].1.len(), 2);
+    wait_for(&collector).await;
+    collector.assert_async().await;
+}
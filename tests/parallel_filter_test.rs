@@ -0,0 +1,96 @@
+use mockito::{Matcher, Server};
+use serde_json::json;
+use tsight_agent::config::{FilterAction, GlobalFilters, SqlFilterRules};
+use tsight_agent::executors::base::QueryExecutor;
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+use tsight_agent::models::JobType;
+
+/// Enough rows to be split across several filter threads
+const ROWS: u64 = 50_000;
+
+fn executor(url: &str) -> ClickhouseExecutor {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![
+            SqlFilterRules {
+                column_value_regexes: Some(vec!["@internal$".to_string()]),
+                action: Some(FilterAction::DropRow),
+                name: Some("internal".to_string()),
+                ..Default::default()
+            },
+            SqlFilterRules {
+                column_name_regexes: Some(vec!["^note$".to_string()]),
+                action: Some(FilterAction::RedactValue),
+                name: Some("notes".to_string()),
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    };
+    ClickhouseExecutor::with_global_filters(url, "default", "", Some(filters)).unwrap()
+}
+
+/// Email of the row `id`, every seventh one internal
+fn email(id: u64) -> String {
+    if id % 7 == 0 {
+        format!("user{}@internal", id)
+    } else {
+        format!("user{}@example.com", id)
+    }
+}
+
+fn kept_ids() -> Vec<u64> {
+    (0..ROWS).filter(|id| id % 7 != 0).collect()
+}
+
+fn ids(rows: &[JobType]) -> Vec<u64> {
+    rows.iter().map(|row| row["id"].as_u64().unwrap()).collect()
+}
+
+#[test]
+fn test_parallel_filtering_keeps_row_order() {
+    let rows = (0..ROWS)
+        .map(|id| {
+            JobType::from([
+                ("id".to_string(), json!(id)),
+                ("email".to_string(), json!(email(id))),
+                ("note".to_string(), json!("secret")),
+            ])
+        })
+        .collect();
+
+    let (filtered, stats) = executor("http://localhost:8123").filter_job_results_with_stats(rows);
+
+    assert_eq!(ids(&filtered), kept_ids());
+    assert!(filtered.iter().all(|row| row["note"] != json!("secret")));
+    assert_eq!(stats.dropped_rows, ROWS.div_ceil(7));
+    assert_eq!(stats.rules["internal"], ROWS.div_ceil(7));
+    assert!(stats.masked_values >= filtered.len() as u64);
+}
+
+#[tokio::test]
+async fn test_streamed_rows_filtered_in_order() {
+    let mut body =
+        String::from("[\"id\",\"email\",\"note\"]\n[\"UInt64\",\"String\",\"String\"]\n");
+    for id in 0..ROWS {
+        body.push_str(&format!("[{},\"{}\",\"secret\"]\n", id, email(id)));
+    }
+    let mut server = Server::new_async().await;
+    let _mock = server
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .with_body(body)
+        .create_async()
+        .await;
+    let executor = executor(&server.url());
+
+    let (rows, stats, _) = executor
+        .execute_job_with_columns("SELECT id, email, note FROM users")
+        .await
+        .unwrap();
+
+    assert_eq!(ids(&rows), kept_ids());
+    assert_eq!(stats.dropped_rows, ROWS.div_ceil(7));
+    let execution = executor.execution_stats();
+    assert_eq!(execution.rows_returned, ROWS);
+    assert_eq!(execution.rows_filtered, ROWS.div_ceil(7));
+}
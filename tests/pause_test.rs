@@ -0,0 +1,63 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::models::DataSource;
+use tsight_agent::shutdown;
+use tsight_agent::systemd::liveness;
+
+// Pausing is process-wide, so this is the only test of this file
+
+#[tokio::test]
+async fn test_paused_agent_acquires_nothing_until_resumed() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "123".to_string(),
+        datasource_name: "main".to_string(),
+        query: "SELECT 1".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+    let agent = create_job_agent_with_client(
+        server.clone(),
+        vec![DataSource {
+            name: "main".to_string(),
+            hosts: vec!["http://127.0.0.1:1".to_string()],
+            ..Default::default()
+        }],
+        None,
+    );
+
+    shutdown::pause();
+    let running = tokio::spawn(async move { agent.run().await });
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    // The job stays queued, while the loop still counts as alive
+    assert!(server.job_errors().is_empty());
+    assert!(server.job_results().is_empty());
+    assert!(liveness().is_alive(Duration::from_millis(1200)));
+
+    shutdown::resume();
+    assert!(!shutdown::is_paused());
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while server.job_errors().is_empty() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // Stopping isn't held up by a pause either
+    shutdown::pause();
+    shutdown::request();
+    tokio::time::timeout(Duration::from_secs(2), running)
+        .await
+        .unwrap()
+        .unwrap();
+}
@@ -0,0 +1,182 @@
+use serde_json::{json, Value};
+use tsight_agent::config::{GlobalFilters, PrivacyConfig};
+use tsight_agent::executors::base::QueryExecutor;
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+use tsight_agent::filters::{FilterError, FilterStats, SqlFilters};
+use tsight_agent::models::JobType;
+
+fn privacy() -> PrivacyConfig {
+    PrivacyConfig {
+        epsilon: None,
+        sensitivity: 1.0,
+        noise_columns: Vec::new(),
+        count_column: None,
+        min_group_size: None,
+    }
+}
+
+fn filters(privacy: PrivacyConfig) -> Result<SqlFilters, FilterError> {
+    let filters = GlobalFilters {
+        privacy: Some(privacy),
+        ..Default::default()
+    };
+    SqlFilters::new(Some(&filters))
+}
+
+fn rows(values: &[Value]) -> Vec<JobType> {
+    values
+        .iter()
+        .map(|row| serde_json::from_value(row.clone()).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_small_groups_are_suppressed() {
+    let filters = filters(PrivacyConfig {
+        count_column: Some("users".to_string()),
+        min_group_size: Some(5),
+        ..privacy()
+    })
+    .unwrap();
+    let mut stats = FilterStats::default();
+
+    let rows = filters.protect_rows(
+        rows(&[
+            json!({"country": "DE", "users": 120}),
+            json!({"country": "LI", "users": 2}),
+            json!({"country": "FR", "users": "5"}),
+            json!({"country": "MC"}),
+        ]),
+        &mut stats,
+    );
+
+    let countries: Vec<&Value> = rows.iter().map(|row| &row["country"]).collect();
+    assert_eq!(countries, [&json!("DE"), &json!("FR")]);
+    assert_eq!(stats.suppressed_rows, 2);
+    assert!(!stats.is_empty());
+}
+
+#[test]
+fn test_noise_is_added_to_aggregate_columns_only() {
+    let filters = filters(PrivacyConfig {
+        epsilon: Some(0.01),
+        noise_columns: vec!["total".to_string(), "avg".to_string(), "big".to_string()],
+        ..privacy()
+    })
+    .unwrap();
+    let mut stats = FilterStats::default();
+
+    let rows = filters.protect_rows(
+        rows(&[json!({"hour": 10, "total": 1000, "avg": 2.5, "big": "18446744"})]),
+        &mut stats,
+    );
+
+    let row = &rows[0];
+    assert_eq!(row["hour"], json!(10));
+    // Integers stay integers, quoted numbers stay quoted
+    assert!(row["total"].is_i64());
+    assert!(row["avg"].is_f64());
+    assert!(row["big"].as_str().unwrap().parse::<i64>().is_ok());
+    assert_eq!(stats.noised_values, 3);
+}
+
+#[test]
+fn test_noise_is_centered_on_true_value() {
+    let filters = filters(PrivacyConfig {
+        epsilon: Some(1.0),
+        noise_columns: vec!["total".to_string()],
+        ..privacy()
+    })
+    .unwrap();
+    let mut stats = FilterStats::default();
+
+    let rows = filters.protect_rows(rows(&vec![json!({"total": 100.0}); 10_000]), &mut stats);
+
+    let values: Vec<f64> = rows
+        .iter()
+        .map(|row| row["total"].as_f64().unwrap())
+        .collect();
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    assert!((mean - 100.0).abs() < 0.2, "{}", mean);
+    assert!(values.iter().any(|value| *value != 100.0));
+}
+
+#[test]
+fn test_invalid_privacy_settings_are_rejected() {
+    for config in [
+        PrivacyConfig {
+            epsilon: Some(0.0),
+            ..privacy()
+        },
+        PrivacyConfig {
+            sensitivity: -1.0,
+            ..privacy()
+        },
+        PrivacyConfig {
+            noise_columns: vec!["total".to_string()],
+            ..privacy()
+        },
+        PrivacyConfig {
+            min_group_size: Some(5),
+            ..privacy()
+        },
+    ] {
+        assert!(matches!(
+            filters(config),
+            Err(FilterError::InvalidPrivacy(_))
+        ));
+    }
+}
+
+#[test]
+fn test_job_results_are_protected() {
+    let filters = GlobalFilters {
+        privacy: Some(PrivacyConfig {
+            count_column: Some("n".to_string()),
+            min_group_size: Some(10),
+            ..privacy()
+        }),
+        ..Default::default()
+    };
+    let executor = ClickhouseExecutor::with_global_filters(
+        "http://localhost:8123",
+        "default",
+        "",
+        Some(filters),
+    )
+    .unwrap();
+
+    let (rows, stats) =
+        executor.filter_job_results_with_stats(rows(&[json!({"n": 3}), json!({"n": 30})]));
+
+    assert_eq!(rows.len(), 1);
+    assert_eq!(
+        stats.to_string(),
+        "0 rows dropped, 0 values masked, 1 groups suppressed"
+    );
+}
+
+#[test]
+fn test_privacy_parsed_from_yaml() {
+    let filters: GlobalFilters = config::Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+privacy:
+  epsilon: 0.5
+  noise_columns: ["total"]
+  count_column: users
+  min_group_size: 10
+"#,
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    let privacy = filters.privacy.unwrap();
+    assert_eq!(privacy.epsilon, Some(0.5));
+    assert_eq!(privacy.sensitivity, 1.0);
+    assert_eq!(privacy.noise_columns, ["total"]);
+    assert_eq!(privacy.min_group_size, Some(10));
+}
@@ -0,0 +1,350 @@
+use std::sync::Arc;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{GlobalFilters, QueryPolicy, SqlFilterRules, StatementKind};
+use tsight_agent::filters::SqlFilters;
+use tsight_agent::models::DataSource;
+use tsight_agent::policy::{check_query, DEFAULT_DATABASE};
+
+fn policy(allowed_statements: Vec<StatementKind>) -> QueryPolicy {
+    QueryPolicy {
+        allowed_statements,
+        ..Default::default()
+    }
+}
+
+fn rejection(policy: &QueryPolicy, sql: &str) -> String {
+    check_query(policy, None, DEFAULT_DATABASE, sql)
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn test_select_queries_are_accepted() {
+    let policy = QueryPolicy::default();
+
+    for sql in [
+        "SELECT 1",
+        "SELECT count() FROM events WHERE ts > now() - INTERVAL 1 DAY",
+        "WITH recent AS (SELECT * FROM events) SELECT * FROM recent",
+        "SELECT a FROM t1 UNION ALL SELECT a FROM t2",
+        "SELECT id FROM users WHERE id IN (SELECT user_id FROM orders)",
+        "SELECT toStartOfInterval(ts, INTERVAL 1 minute) AS t, count() FROM events GROUP BY t",
+        "SELECT name FROM system.tables LIMIT 1 BY database SETTINGS max_threads = 1",
+        "SELECT arrayJoin([1, 2, 3]) AS x, map('a', 1)['a']",
+    ] {
+        assert!(
+            check_query(&policy, None, DEFAULT_DATABASE, sql).is_ok(),
+            "{}",
+            sql
+        );
+    }
+}
+
+#[test]
+fn test_modifying_statements_are_rejected() {
+    let policy = QueryPolicy::default();
+
+    for sql in [
+        "INSERT INTO events VALUES (1)",
+        "DROP TABLE events",
+        "ALTER TABLE events DELETE WHERE 1",
+        "TRUNCATE TABLE events",
+        "CREATE TABLE t (a Int32) ENGINE = Memory",
+    ] {
+        let error = rejection(&policy, sql);
+        assert!(error.starts_with("query rejected by policy"), "{}", error);
+    }
+}
+
+#[test]
+fn test_multiple_statements_are_rejected() {
+    let error = rejection(&QueryPolicy::default(), "SELECT 1; DROP TABLE events");
+    assert!(error.contains("single statement"), "{}", error);
+}
+
+#[test]
+fn test_unparsable_queries_are_rejected() {
+    let error = rejection(&QueryPolicy::default(), "SELEC 1");
+    assert!(error.contains("failed to parse"), "{}", error);
+}
+
+#[test]
+fn test_additional_statement_kinds() {
+    let default_policy = QueryPolicy::default();
+    let error = rejection(&default_policy, "SHOW TABLES");
+    assert!(
+        error.contains("SHOW statements are not allowed"),
+        "{}",
+        error
+    );
+
+    let policy = policy(vec![
+        StatementKind::Select,
+        StatementKind::Show,
+        StatementKind::Explain,
+    ]);
+    assert!(check_query(&policy, None, DEFAULT_DATABASE, "SHOW TABLES").is_ok());
+    assert!(check_query(&policy, None, DEFAULT_DATABASE, "EXPLAIN SELECT 1").is_ok());
+    assert!(check_query(
+        &policy,
+        None,
+        DEFAULT_DATABASE,
+        "EXPLAIN INSERT INTO events VALUES (1)"
+    )
+    .is_err());
+}
+
+#[test]
+fn test_disabled_policy_accepts_anything() {
+    let policy = QueryPolicy {
+        enabled: false,
+        ..Default::default()
+    };
+    assert!(check_query(&policy, None, DEFAULT_DATABASE, "DROP TABLE events").is_ok());
+}
+
+#[tokio::test]
+async fn test_rejected_job_fails_without_querying_datasource() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "42".to_string(),
+        datasource_name: "main".to_string(),
+        query: "DROP TABLE events".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+
+    let datasource = DataSource {
+        name: "main".to_string(),
+        hosts: vec!["http://127.0.0.1:1".to_string()],
+        ..Default::default()
+    };
+    let agent = create_job_agent_with_client(server.clone(), vec![datasource], None);
+
+    assert!(agent.process_next().await.is_err());
+
+    let errors = server.job_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0].1.starts_with("query rejected by policy"),
+        "{}",
+        errors[0].1
+    );
+}
+
+fn table_filters() -> SqlFilters {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            database_regexes: Some(vec!["^some_secret_db$".to_string()]),
+            table_regexes: Some(vec!["^salaries$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    SqlFilters::new(Some(&filters)).unwrap()
+}
+
+#[test]
+fn test_excluded_tables_cannot_be_queried() {
+    let policy = QueryPolicy::default();
+    let filters = table_filters();
+
+    for sql in [
+        "SELECT * FROM some_secret_db.users",
+        "SELECT * FROM salaries",
+        "SELECT * FROM prod.salaries",
+        "SELECT * FROM events e JOIN some_secret_db.users u ON e.user_id = u.id",
+        "SELECT * FROM events WHERE user_id IN (SELECT id FROM some_secret_db.users)",
+        "WITH s AS (SELECT * FROM salaries) SELECT * FROM s",
+        // A common table expression only hides tables within its query
+        "SELECT * FROM (WITH salaries AS (SELECT 1 AS x) SELECT * FROM salaries) AS a, salaries",
+        // nor in its own body or the bodies of the ones defined before it
+        "WITH salaries AS (SELECT * FROM salaries) SELECT * FROM salaries",
+        "WITH a AS (SELECT * FROM salaries), salaries AS (SELECT 1) SELECT * FROM a",
+    ] {
+        let error = check_query(&policy, Some(&filters), DEFAULT_DATABASE, sql)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("excluded by filters"), "{}: {}", sql, error);
+    }
+
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "SELECT * FROM prod.events"
+    )
+    .is_ok());
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "WITH recent AS (SELECT * FROM events) SELECT * FROM recent"
+    )
+    .is_ok());
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "WITH salaries AS (SELECT 1 AS x) SELECT * FROM (SELECT * FROM salaries) AS a"
+    )
+    .is_ok());
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "WITH a AS (SELECT 1 AS x), salaries AS (SELECT * FROM a) SELECT * FROM salaries"
+    )
+    .is_ok());
+    // A recursive expression refers to itself
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "WITH RECURSIVE salaries AS (SELECT 1 AS x UNION ALL SELECT x + 1 FROM salaries) \
+         SELECT * FROM salaries"
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_tables_outside_allow_rules_cannot_be_queried() {
+    let filters = GlobalFilters {
+        sql_filters_allow: Some(vec![SqlFilterRules {
+            database_regexes: Some(vec!["^prod$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let filters = SqlFilters::new(Some(&filters)).unwrap();
+    let policy = QueryPolicy::default();
+
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "SELECT * FROM prod.events"
+    )
+    .is_ok());
+    // Tables without a database are read from the default one
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "SELECT * FROM events"
+    )
+    .is_err());
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "SELECT * FROM system.tables"
+    )
+    .is_err());
+    // or the datasource's
+    assert!(check_query(&policy, Some(&filters), "prod", "SELECT * FROM events").is_ok());
+    assert!(check_query(&policy, Some(&filters), "staging", "SELECT * FROM events").is_err());
+}
+
+#[test]
+fn test_table_functions_reading_other_sources_are_rejected() {
+    let policy = QueryPolicy::default();
+    let filters = table_filters();
+
+    let error = check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "SELECT * FROM remote('127.0.0.1', 'some_secret_db', 'users')",
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("table function 'remote'"), "{}", error);
+
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "SELECT number FROM numbers(10)"
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_dictionary_functions_reading_excluded_tables_are_rejected() {
+    let policy = QueryPolicy::default();
+    let filters = table_filters();
+    let check = |sql| check_query(&policy, Some(&filters), DEFAULT_DATABASE, sql);
+
+    for sql in [
+        "SELECT dictGet('salaries', 'amount', 1)",
+        "SELECT dictGetOrDefault('default.salaries', 'amount', 1, 0)",
+        "SELECT dictHas('some_secret_db.users', 1)",
+        "SELECT joinGet(salaries, 'amount', 1)",
+        "SELECT id FROM events WHERE dictGetString('some_secret_db.users', 'name', id) = ''",
+    ] {
+        let error = check(sql).unwrap_err().to_string();
+        assert!(error.contains("excluded by filters"), "{}: {}", sql, error);
+    }
+    // Names only known when the query runs can't be checked
+    let error = check("SELECT dictGet(concat('sala', 'ries'), 'amount', 1)")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("must name its dictionary"), "{}", error);
+
+    assert!(check("SELECT dictGet('prod.rates', 'rate', 1)").is_ok());
+    assert!(check_query(
+        &policy,
+        Some(&filters),
+        "prod",
+        "SELECT dictGet('rates', 'rate', 1)"
+    )
+    .is_ok());
+}
+
+#[tokio::test]
+async fn test_job_reading_excluded_table_is_rejected() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "42".to_string(),
+        datasource_name: "main".to_string(),
+        query: "SELECT * FROM some_secret_db.users".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+
+    let datasource = DataSource {
+        name: "main".to_string(),
+        hosts: vec!["http://127.0.0.1:1".to_string()],
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            database_regexes: Some(vec!["^some_secret_db$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let agent = create_job_agent_with_client(server.clone(), vec![datasource], None);
+
+    assert!(agent.process_next().await.is_err());
+
+    let errors = server.job_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0]
+            .1
+            .contains("database 'some_secret_db' is excluded"),
+        "{}",
+        errors[0].1
+    );
+}
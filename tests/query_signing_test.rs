@@ -0,0 +1,185 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::sync::Arc;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind, TaskQuery};
+use tsight_agent::config::QuerySigningConfig;
+use tsight_agent::models::DataSource;
+use tsight_agent::signing::{self, signed_message, QueryVerifier};
+
+fn key_pair() -> Ed25519KeyPair {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+}
+
+fn signing_config(keys: &[&Ed25519KeyPair]) -> QuerySigningConfig {
+    QuerySigningConfig {
+        public_keys: keys
+            .iter()
+            .map(|key| STANDARD.encode(key.public_key().as_ref()))
+            .collect(),
+    }
+}
+
+fn sign(key: &Ed25519KeyPair, datasource: &str, query: &str) -> String {
+    STANDARD.encode(key.sign(&signed_message(datasource, query)).as_ref())
+}
+
+#[test]
+fn test_signed_queries_are_accepted() {
+    let key = key_pair();
+    let verifier = QueryVerifier::new(&signing_config(&[&key])).unwrap();
+
+    let signature = sign(&key, "main", "SELECT 1");
+    assert!(verifier
+        .verify("main", "SELECT 1", Some(&signature))
+        .is_ok());
+}
+
+#[test]
+fn test_any_pinned_key_is_accepted() {
+    let old_key = key_pair();
+    let new_key = key_pair();
+    let verifier = QueryVerifier::new(&signing_config(&[&old_key, &new_key])).unwrap();
+
+    for key in [&old_key, &new_key] {
+        let signature = sign(key, "main", "SELECT 1");
+        assert!(verifier
+            .verify("main", "SELECT 1", Some(&signature))
+            .is_ok());
+    }
+}
+
+#[test]
+fn test_invalid_signatures_are_rejected() {
+    let key = key_pair();
+    let other_key = key_pair();
+    let verifier = QueryVerifier::new(&signing_config(&[&key])).unwrap();
+    let signature = sign(&key, "main", "SELECT 1");
+
+    let error = verifier.verify("main", "SELECT 1", None).unwrap_err();
+    assert_eq!(error.to_string(), "query rejected: query is not signed");
+
+    let error = verifier
+        .verify("main", "SELECT 2", Some(&signature))
+        .unwrap_err();
+    assert!(error.to_string().contains("doesn't match"), "{}", error);
+
+    let error = verifier
+        .verify("replica", "SELECT 1", Some(&signature))
+        .unwrap_err();
+    assert!(error.to_string().contains("doesn't match"), "{}", error);
+
+    let other_signature = sign(&other_key, "main", "SELECT 1");
+    let error = verifier
+        .verify("main", "SELECT 1", Some(&other_signature))
+        .unwrap_err();
+    assert!(error.to_string().contains("doesn't match"), "{}", error);
+
+    let error = verifier
+        .verify("main", "SELECT 1", Some("not base64!"))
+        .unwrap_err();
+    assert!(error.to_string().contains("base64"), "{}", error);
+}
+
+#[test]
+fn test_invalid_public_keys_are_rejected() {
+    let error = QueryVerifier::new(&QuerySigningConfig {
+        public_keys: vec![],
+    })
+    .err()
+    .unwrap();
+    assert!(error.to_string().contains("at least one key"), "{}", error);
+
+    let error = QueryVerifier::new(&QuerySigningConfig {
+        public_keys: vec!["not base64!".to_string()],
+    })
+    .err()
+    .unwrap();
+    assert!(error.to_string().contains("public_keys[0]"), "{}", error);
+
+    let error = QueryVerifier::new(&QuerySigningConfig {
+        public_keys: vec![STANDARD.encode([0u8; 16])],
+    })
+    .err()
+    .unwrap();
+    assert!(error.to_string().contains("32-byte"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_agent_only_runs_signed_queries() {
+    let key = key_pair();
+    signing::init(&signing_config(&[&key])).unwrap();
+
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "1".to_string(),
+        datasource_name: "main".to_string(),
+        query: "SELECT 1".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+    server.enqueue_job(AcquireResultBody {
+        id: "2".to_string(),
+        datasource_name: "main".to_string(),
+        query: String::new(),
+        queries: Some(vec![
+            TaskQuery {
+                name: Some("events".to_string()),
+                query: "SELECT count() FROM events".to_string(),
+                signature: Some(sign(&key, "main", "SELECT count() FROM events")),
+            },
+            TaskQuery {
+                name: Some("users".to_string()),
+                query: "SELECT id FROM users".to_string(),
+                signature: None,
+            },
+        ]),
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+    server.enqueue_job(AcquireResultBody {
+        id: "3".to_string(),
+        datasource_name: "main".to_string(),
+        query: "SELECT 1".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: Some(sign(&key, "main", "SELECT 1")),
+        time_range: None,
+    });
+
+    let datasource = DataSource {
+        name: "main".to_string(),
+        hosts: vec!["http://127.0.0.1:1".to_string()],
+        ..Default::default()
+    };
+    let agent = create_job_agent_with_client(server.clone(), vec![datasource], None);
+    for _ in 0..3 {
+        // The signed query fails too, on connecting to the datasource
+        assert!(agent.process_next().await.is_err());
+    }
+
+    let errors = server.job_errors();
+    assert_eq!(errors.len(), 3);
+    assert_eq!(errors[0].0, "1");
+    assert_eq!(errors[0].1, "query rejected: query is not signed");
+    assert_eq!(errors[1].0, "2");
+    assert_eq!(errors[1].1, "query rejected: query is not signed");
+    assert_eq!(errors[2].0, "3");
+    assert!(!errors[2].1.contains("query rejected"), "{}", errors[2].1);
+}
@@ -0,0 +1,169 @@
+use mockito::{Matcher, Mock, Server, ServerGuard};
+use std::net::TcpListener;
+use tsight_agent::config::ReadOnlyCheck;
+use tsight_agent::executors::base::UserAccess;
+use tsight_agent::models::DataSource;
+use tsight_agent::read_only::{check_read_only, write_privileges};
+
+fn grants(grants: &[&str]) -> UserAccess {
+    UserAccess {
+        readonly: false,
+        grants: grants.iter().map(|grant| grant.to_string()).collect(),
+    }
+}
+
+fn datasource(host: String, read_only_check: ReadOnlyCheck) -> DataSource {
+    DataSource {
+        name: "main".to_string(),
+        hosts: vec![host],
+        username: "agent".to_string(),
+        read_only_check,
+        ..Default::default()
+    }
+}
+
+/// ClickHouse answering a query matching `pattern` with one string column
+async fn mock_query(server: &mut ServerGuard, pattern: &str, column: &str, rows: &[&str]) -> Mock {
+    let mut body = format!("[\"{}\"]\n[\"String\"]\n", column);
+    for row in rows {
+        body.push_str(&serde_json::to_string(&[row]).unwrap());
+        body.push('\n');
+    }
+    server
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::Regex(pattern.to_string()))
+        .with_body(body)
+        .create_async()
+        .await
+}
+
+/// ClickHouse whose user isn't read-only and has `user_grants`
+async fn clickhouse_with_grants(user_grants: &[&str]) -> (ServerGuard, Vec<Mock>) {
+    let mut server = Server::new_async().await;
+    let mocks = vec![
+        mock_query(&mut server, "getSetting", "readonly", &["0"]).await,
+        mock_query(&mut server, "SHOW GRANTS FINAL", "GRANTS", user_grants).await,
+    ];
+    (server, mocks)
+}
+
+#[test]
+fn test_read_privileges_are_accepted() {
+    let access = grants(&[
+        "GRANT SELECT, SHOW TABLES, SHOW COLUMNS ON analytics.* TO agent",
+        "GRANT SELECT(id, name) ON prod.users TO agent",
+        "GRANT dictGet ON *.* TO agent",
+        "REVOKE INSERT ON analytics.* FROM agent",
+    ]);
+
+    assert!(write_privileges(&access).is_empty());
+}
+
+#[test]
+fn test_write_privileges_are_reported() {
+    let access = grants(&[
+        "GRANT SELECT, INSERT, ALTER DELETE ON analytics.* TO agent",
+        "GRANT ALL ON *.* TO agent WITH GRANT OPTION",
+        "GRANT admin TO agent",
+    ]);
+
+    assert_eq!(
+        write_privileges(&access),
+        vec![
+            "INSERT ON analytics.*",
+            "ALTER DELETE ON analytics.*",
+            "ALL ON *.*",
+            "role admin",
+        ]
+    );
+}
+
+#[test]
+fn test_readonly_setting_overrides_grants() {
+    let access = UserAccess {
+        readonly: true,
+        ..grants(&["GRANT ALL ON *.* TO agent"])
+    };
+
+    assert!(write_privileges(&access).is_empty());
+}
+
+#[tokio::test]
+async fn test_readonly_user_passes() {
+    let mut server = Server::new_async().await;
+    let readonly = mock_query(&mut server, "getSetting", "readonly", &["1"]).await;
+    let show_grants = server
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::Regex("SHOW GRANTS".to_string()))
+        .expect(0)
+        .create_async()
+        .await;
+
+    check_read_only(&[datasource(server.url(), ReadOnlyCheck::Enforce)])
+        .await
+        .unwrap();
+
+    readonly.assert_async().await;
+    show_grants.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_enforced_check_refuses_writable_user() {
+    let (server, _mocks) =
+        clickhouse_with_grants(&["GRANT SELECT, INSERT ON analytics.* TO agent"]).await;
+
+    let error = check_read_only(&[datasource(server.url(), ReadOnlyCheck::Enforce)])
+        .await
+        .unwrap_err()
+        .to_string();
+
+    assert!(
+        error.contains("Datasource 'main' user 'agent' can write: INSERT ON analytics.*"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_enforced_check_accepts_select_grants() {
+    let (server, _mocks) = clickhouse_with_grants(&["GRANT SELECT ON analytics.* TO agent"]).await;
+
+    check_read_only(&[datasource(server.url(), ReadOnlyCheck::Enforce)])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_warning_check_starts_with_writable_user() {
+    let (server, _mocks) = clickhouse_with_grants(&["GRANT ALL ON *.* TO agent"]).await;
+
+    check_read_only(&[datasource(server.url(), ReadOnlyCheck::Warn)])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_unreachable_and_disabled_checks_are_skipped() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let mut server = Server::new_async().await;
+    let untouched = server
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .expect(0)
+        .create_async()
+        .await;
+
+    check_read_only(&[
+        datasource(closed, ReadOnlyCheck::Enforce),
+        datasource(server.url(), ReadOnlyCheck::Off),
+    ])
+    .await
+    .unwrap();
+
+    untouched.assert_async().await;
+}
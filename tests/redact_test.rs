@@ -0,0 +1,146 @@
+use std::sync::Arc;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{GlobalFilters, SqlFilterRules};
+use tsight_agent::filters::SqlFilters;
+use tsight_agent::models::DataSource;
+use tsight_agent::redact::{redact_literals, redact_message, redact_values};
+
+fn email_filters() -> GlobalFilters {
+    GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            column_value_regexes: Some(vec!["^[^@]+@[^@]+$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_literals_are_replaced() {
+    assert_eq!(
+        redact_literals("SELECT * FROM users WHERE email = 'bob@example.com' AND id = 1"),
+        "SELECT * FROM users WHERE email = '?' AND id = 1"
+    );
+    assert_eq!(
+        redact_literals("WHERE name IN ('O''Brien', 'it\\'s')"),
+        "WHERE name IN ('?', '?')"
+    );
+    assert_eq!(
+        redact_literals("WHERE token = 'unterminated"),
+        "WHERE token = '?'"
+    );
+}
+
+#[test]
+fn test_apostrophes_are_kept() {
+    let message = "Table default.events doesn't exist";
+    assert_eq!(redact_literals(message), message);
+}
+
+#[test]
+fn test_filtered_values_are_replaced() {
+    let filters = SqlFilters::new(Some(&email_filters())).unwrap();
+
+    assert_eq!(
+        redact_values(
+            "Cannot parse input: (bob@example.com), expected Int32",
+            Some(&filters)
+        ),
+        "Cannot parse input: ([REDACTED]), expected Int32"
+    );
+    assert_eq!(
+        redact_message(
+            "Syntax error in `SELECT 'x' FROM t WHERE a = alice@example.com`",
+            Some(&filters)
+        ),
+        "Syntax error in `SELECT '?' FROM t WHERE a = [REDACTED]`"
+    );
+}
+
+#[test]
+fn test_quoted_and_assigned_values_are_replaced() {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            column_value_regexes: Some(vec!["^hunter\\d$".to_string(), "^tok_\\w+$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let filters = SqlFilters::new(Some(&filters)).unwrap();
+
+    assert_eq!(
+        redact_values("Login failed for password='hunter2'", Some(&filters)),
+        "Login failed for password='[REDACTED]'"
+    );
+    assert_eq!(
+        redact_values(
+            r#"Bad request {"token":"tok_abc","user":"bob"}"#,
+            Some(&filters)
+        ),
+        r#"Bad request {"token":"[REDACTED]","user":"bob"}"#
+    );
+    assert_eq!(
+        redact_values("Auth failed: password=hunter2, user=bob", Some(&filters)),
+        "Auth failed: password=[REDACTED], user=bob"
+    );
+    assert_eq!(
+        redact_values("Headers:\ntoken: tok_abc\n", Some(&filters)),
+        "Headers:\ntoken: [REDACTED]\n"
+    );
+}
+
+#[test]
+fn test_allow_lists_do_not_redact_messages() {
+    let filters = GlobalFilters {
+        sql_filters_allow: Some(vec![SqlFilterRules {
+            column_value_regexes: Some(vec!["^\\d+$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let filters = SqlFilters::new(Some(&filters)).unwrap();
+    let message = "Query execution error: timeout";
+
+    assert_eq!(redact_values(message, Some(&filters)), message);
+}
+
+#[tokio::test]
+async fn test_submitted_errors_are_redacted() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "42".to_string(),
+        datasource_name: "main".to_string(),
+        query: "SELECT * FROM events".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+
+    let datasource = DataSource {
+        name: "main".to_string(),
+        hosts: vec!["http://127.0.0.1:1".to_string()],
+        sql_filters_exclude: Some(vec![SqlFilterRules {
+            database_regexes: Some(vec!["^default$".to_string()]),
+            column_value_regexes: Some(vec!["^default$".to_string()]),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let agent = create_job_agent_with_client(server.clone(), vec![datasource], None);
+
+    let error = agent.process_next().await.unwrap_err().to_string();
+
+    let errors = server.job_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].1,
+        "query rejected by policy: database '[REDACTED]' is excluded by filters"
+    );
+    assert_eq!(error, errors[0].1);
+}
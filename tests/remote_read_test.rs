@@ -0,0 +1,327 @@
+use prost::Message;
+use serde_json::json;
+use std::fs;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tsight_agent::agent::BaseAgent;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::config::{Config, RemoteReadConfig};
+use tsight_agent::remote_read::proto::{
+    LabelMatcher, MatchType, Query, ReadHints, ReadRequest, ReadResponse,
+};
+use tsight_agent::remote_read::{matches, snappy, RemoteReadApi, RemoteReadEndpoint};
+
+const CONFIG: &str = r#"server:
+  api_key: "test-api-key"
+  server_url: "http://localhost:8080"
+
+datasources:
+  - name: "demo"
+    source_type: "mock"
+    mock:
+      results:
+        - query_regex: "(?i)from events"
+          columns:
+            - {name: "t", type: "UInt64"}
+            - {name: "value", type: "UInt64"}
+            - {name: "status", type: "String"}
+            - {name: "host", type: "String"}
+          rows:
+            - {t: 1709251260, value: 5, status: "ok", host: "a"}
+            - {t: 1709251200, value: 3, status: "ok", host: "a"}
+            - {t: 1709251200, value: 1, status: "error", host: "internal-b"}
+            - {t: 1709240000, value: 9, status: "ok", host: "a"}
+        - query_regex: "(?i)from users"
+          columns:
+            - {name: "t", type: "UInt64"}
+            - {name: "value", type: "UInt64"}
+          rows:
+            - {t: 1709251200, value: 42}
+        - query_regex: "missing"
+          error: "Unknown table"
+
+global_filters:
+  sql_filters_exclude:
+    - column_value_regexes:
+        - "^internal-"
+      action: "drop_row"
+"#;
+
+fn load_config() -> Config {
+    let dir = TempDir::new().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    fs::write(&config_path, CONFIG).unwrap();
+    Config::load(&config_path).unwrap()
+}
+
+fn remote_read_config(api_key: Option<&str>) -> RemoteReadConfig {
+    serde_json::from_value(json!({
+        "address": "127.0.0.1:0",
+        "api_key": api_key,
+        "metrics": [
+            {
+                "name": "events_total",
+                "datasource": "demo",
+                "query": "SELECT t, value, status, host FROM events",
+                "label_columns": ["status", "host"],
+            },
+            {"name": "users", "datasource": "demo", "query": "SELECT t, value FROM users"},
+            {"name": "broken", "datasource": "demo", "query": "SELECT * FROM missing"},
+        ],
+    }))
+    .unwrap()
+}
+
+fn agents(config: &Config) -> Vec<BaseAgent> {
+    vec![BaseAgent::with_filters(
+        Arc::new(FakeServer::new()),
+        config.datasources.clone(),
+        config.global_filters.clone(),
+    )]
+}
+
+fn matcher(match_type: MatchType, name: &str, value: &str) -> LabelMatcher {
+    LabelMatcher {
+        r#type: match_type as i32,
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn query(matchers: Vec<LabelMatcher>) -> Query {
+    Query {
+        start_timestamp_ms: 1709251000000,
+        end_timestamp_ms: 1709252000000,
+        matchers,
+        hints: Some(ReadHints {
+            step_ms: 60000,
+            ..Default::default()
+        }),
+    }
+}
+
+fn labels(response: &ReadResponse) -> Vec<Vec<(String, String)>> {
+    response.results[0]
+        .timeseries
+        .iter()
+        .map(|series| {
+            series
+                .labels
+                .iter()
+                .map(|label| (label.name.clone(), label.value.clone()))
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_snappy_round_trip() {
+    let message: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    for input in [&b""[..], b"a", &message[..70], &message] {
+        assert_eq!(snappy::decompress(&snappy::compress(input)).unwrap(), input);
+    }
+}
+
+#[test]
+fn test_snappy_decompresses_copies() {
+    // "abcd" as a literal, then an overlapping copy of 8 bytes at offset 4
+    // and a 2-byte offset copy of 3 bytes at offset 12
+    let compressed = [
+        15,
+        0b0000_1100,
+        b'a',
+        b'b',
+        b'c',
+        b'd',
+        0b0001_0001,
+        4,
+        0b0000_1010,
+        12,
+        0,
+    ];
+    assert_eq!(snappy::decompress(&compressed).unwrap(), b"abcdabcdabcdabc");
+
+    assert!(snappy::decompress(&[4, 0b0000_0001, 1]).is_err());
+    assert!(snappy::decompress(&[5, 0b0000_1100, b'a', b'b', b'c', b'd']).is_err());
+}
+
+#[test]
+fn test_label_matchers() {
+    assert!(matches(&matcher(MatchType::Eq, "status", "ok"), "ok").unwrap());
+    assert!(matches(&matcher(MatchType::Neq, "status", "ok"), "error").unwrap());
+    assert!(matches(&matcher(MatchType::Re, "status", "o|e.*"), "error").unwrap());
+    // Regular expressions match whole values
+    assert!(!matches(&matcher(MatchType::Re, "status", "o"), "ok").unwrap());
+    assert!(matches(&matcher(MatchType::Nre, "status", "o"), "ok").unwrap());
+    assert!(matches(&matcher(MatchType::Re, "status", "("), "ok").is_err());
+}
+
+#[tokio::test]
+async fn test_read_returns_filtered_series() {
+    let config = load_config();
+    let api = RemoteReadApi::new(&remote_read_config(None), agents(&config));
+
+    let response = api
+        .read(&ReadRequest {
+            queries: vec![query(vec![matcher(
+                MatchType::Eq,
+                "__name__",
+                "events_total",
+            )])],
+            accepted_response_types: vec![],
+        })
+        .await
+        .unwrap();
+
+    // The row of an internal host is dropped, and the sample before the
+    // range clipped
+    assert_eq!(response.results.len(), 1);
+    let timeseries = &response.results[0].timeseries;
+    assert_eq!(timeseries.len(), 1);
+    assert_eq!(
+        labels(&response)[0],
+        vec![
+            ("__name__".to_string(), "events_total".to_string()),
+            ("host".to_string(), "a".to_string()),
+            ("status".to_string(), "ok".to_string()),
+        ]
+    );
+    let samples: Vec<_> = timeseries[0]
+        .samples
+        .iter()
+        .map(|sample| (sample.timestamp, sample.value))
+        .collect();
+    assert_eq!(samples, vec![(1709251200000, 3.0), (1709251260000, 5.0)]);
+}
+
+#[tokio::test]
+async fn test_read_applies_label_matchers() {
+    let config = load_config();
+    let api = RemoteReadApi::new(&remote_read_config(None), agents(&config));
+
+    let response = api
+        .read(&ReadRequest {
+            queries: vec![query(vec![
+                matcher(MatchType::Re, "__name__", "events_total|users"),
+                matcher(MatchType::Neq, "status", "ok"),
+            ])],
+            accepted_response_types: vec![],
+        })
+        .await
+        .unwrap();
+    // Series of users have no status label, which counts as empty
+    assert_eq!(
+        labels(&response),
+        vec![vec![("__name__".to_string(), "users".to_string())]]
+    );
+
+    let error = api
+        .read(&ReadRequest {
+            queries: vec![query(vec![matcher(MatchType::Eq, "__name__", "broken")])],
+            accepted_response_types: vec![],
+        })
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("Metric broken failed"),
+        "{}",
+        error
+    );
+    assert!(error.to_string().contains("Unknown table"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_endpoint_answers_snappy_protobuf() {
+    let config = load_config();
+    let endpoint = RemoteReadEndpoint::bind(&remote_read_config(Some("prom-key")), agents(&config))
+        .await
+        .unwrap();
+    let address = endpoint.local_addr().unwrap();
+    endpoint.spawn();
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/api/v1/read", address);
+    let body = snappy::compress(
+        &ReadRequest {
+            queries: vec![query(vec![matcher(MatchType::Eq, "__name__", "users")])],
+            accepted_response_types: vec![],
+        }
+        .encode_to_vec(),
+    );
+
+    let response = client.post(&url).body(body.clone()).send().await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    let response = client
+        .post(&url)
+        .bearer_auth("prom-key")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-encoding"], "snappy");
+    assert_eq!(response.headers()["content-type"], "application/x-protobuf");
+    let bytes = response.bytes().await.unwrap();
+    let read_response =
+        ReadResponse::decode(snappy::decompress(&bytes).unwrap().as_slice()).unwrap();
+    assert_eq!(
+        read_response.results[0].timeseries[0].samples[0].value,
+        42.0
+    );
+
+    let response = client
+        .post(&url)
+        .bearer_auth("prom-key")
+        .body("not snappy")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .starts_with("Invalid read request"));
+}
+
+#[test]
+fn test_metric_datasources_must_be_configured() {
+    let dir = TempDir::new().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    let content = format!(
+        "{}\nremote_read:\n  address: \"127.0.0.1:9201\"\n  metrics:\n    - {{name: \"up\", datasource: \"nope\", query: \"SELECT 1\"}}\n",
+        CONFIG
+    );
+    fs::write(&config_path, content).unwrap();
+    let error = Config::load(&config_path).unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("Datasource 'nope' of remote read metric 'up' isn't configured"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_api_key_is_required_off_loopback() {
+    let load = |remote_read: &str| {
+        let dir = TempDir::new().unwrap();
+        let config_path = dir.path().join("config.yaml");
+        let content = format!(
+            "{}\nremote_read:\n{}  metrics:\n    - {{name: \"up\", datasource: \"demo\", query: \"SELECT 1\"}}\n",
+            CONFIG, remote_read
+        );
+        fs::write(&config_path, content).unwrap();
+        Config::load(&config_path).map_err(|e| e.to_string())
+    };
+
+    let error = load("  address: \"0.0.0.0:9201\"\n").unwrap_err();
+    assert!(
+        error.contains("remote_read.api_key must be set to listen on 0.0.0.0:9201"),
+        "{}",
+        error
+    );
+    assert!(load("  address: \"127.0.0.1:9201\"\n").is_ok());
+    assert!(load("  address: \"0.0.0.0:9201\"\n  api_key: \"prometheus-key\"\n").is_ok());
+}
@@ -0,0 +1,236 @@
+use flate2::read::GzDecoder;
+use mockito::{Matcher, Server};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{Config, ResultExportConfig};
+use tsight_agent::models::JobType;
+use tsight_agent::result_export::{self, ResultExporter};
+use tsight_agent::spill::JobRows;
+
+const CONFIG: &str = r#"server:
+  api_key: "test-api-key"
+  server_url: "http://localhost:8080"
+
+datasources:
+  - name: "demo"
+    source_type: "mock"
+    mock:
+      results:
+        - query_regex: "(?i)from events"
+          rows:
+            - {id: 1, status: "ok"}
+            - {id: 2, status: "ok"}
+            - {id: 3, status: "error"}
+        - query_regex: "(?i)from users"
+          rows:
+            - {id: 1, name: "alice"}
+"#;
+
+fn export_config(endpoint: &str, prefix: &str, min_rows: Option<usize>) -> ResultExportConfig {
+    serde_json::from_value(json!({
+        "bucket": "exports",
+        "prefix": prefix,
+        "region": "us-east-1",
+        "endpoint_url": endpoint,
+        "access_key_id": "AKIDEXAMPLE",
+        "secret_access_key": "export-secret",
+        "min_rows": min_rows,
+    }))
+    .unwrap()
+}
+
+fn rows(count: usize) -> Vec<JobType> {
+    (0..count)
+        .map(|i| {
+            serde_json::from_value(json!({
+                "id": i,
+                "event": format!("event-{:08x}", (i as u64).wrapping_mul(0x9e3779b97f4a7c15)),
+            }))
+            .unwrap()
+        })
+        .collect()
+}
+
+fn decompress(object: &[u8]) -> Vec<JobType> {
+    let mut lines = String::new();
+    GzDecoder::new(object).read_to_string(&mut lines).unwrap();
+    lines
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn job(id: &str, query: &str) -> AcquireResultBody {
+    AcquireResultBody {
+        id: id.to_string(),
+        datasource_name: "demo".to_string(),
+        query: query.to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    }
+}
+
+#[tokio::test]
+async fn test_export_uploads_compressed_rows() {
+    let mut server = Server::new_async().await;
+    let object = Arc::new(Mutex::new(Vec::new()));
+    let uploaded = object.clone();
+    let put = server
+        .mock("PUT", "/exports/tsight/job-1.jsonl.gz")
+        .match_header(
+            "authorization",
+            Matcher::Regex("^AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/.*/us-east-1/s3/".into()),
+        )
+        .match_header(
+            "x-amz-content-sha256",
+            Matcher::Regex("^[0-9a-f]{64}$".into()),
+        )
+        .match_request(move |request| {
+            *uploaded.lock().unwrap() = request.body().unwrap().clone();
+            true
+        })
+        .expect(1)
+        .create_async()
+        .await;
+
+    let exporter =
+        ResultExporter::from_config(&export_config(&server.url(), "tsight/", None)).unwrap();
+    let export = exporter
+        .export("job-1", JobRows::from(rows(3)))
+        .await
+        .unwrap();
+
+    put.assert_async().await;
+    let object = object.lock().unwrap().clone();
+    assert_eq!(decompress(&object), rows(3));
+    assert_eq!(
+        export.location,
+        format!("{}/exports/tsight/job-1.jsonl.gz", server.url())
+    );
+    assert_eq!(export.row_count, 3);
+    assert_eq!(export.byte_size, object.len() as u64);
+    assert_eq!(export.sha256, hex::encode(Sha256::digest(&object)));
+}
+
+#[tokio::test]
+async fn test_large_objects_are_uploaded_in_parts() {
+    let mut server = Server::new_async().await;
+    let start = server
+        .mock("POST", "/exports/job-2.jsonl.gz")
+        .match_query(Matcher::Regex("^uploads$".into()))
+        .with_body("<InitiateMultipartUploadResult><UploadId>upload-1</UploadId></InitiateMultipartUploadResult>")
+        .expect(1)
+        .create_async()
+        .await;
+    let parts = Arc::new(Mutex::new(BTreeMap::new()));
+    let uploaded = parts.clone();
+    let put_part = server
+        .mock("PUT", "/exports/job-2.jsonl.gz")
+        .match_query(Matcher::UrlEncoded("uploadId".into(), "upload-1".into()))
+        .match_request(move |request| {
+            let query = request.path_and_query().split_once('?').unwrap().1;
+            let number: u32 = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("partNumber="))
+                .unwrap()
+                .parse()
+                .unwrap();
+            uploaded
+                .lock()
+                .unwrap()
+                .insert(number, request.body().unwrap().clone());
+            true
+        })
+        .with_header("etag", "\"part-etag\"")
+        .expect_at_least(2)
+        .create_async()
+        .await;
+    let complete = server
+        .mock("POST", "/exports/job-2.jsonl.gz")
+        .match_query(Matcher::UrlEncoded("uploadId".into(), "upload-1".into()))
+        .match_body(Matcher::Regex(
+            "^<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>\"part-etag\"</ETag></Part><Part><PartNumber>2</PartNumber>".into(),
+        ))
+        .with_body("<CompleteMultipartUploadResult></CompleteMultipartUploadResult>")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let exporter = ResultExporter::from_config(&export_config(&server.url(), "", None))
+        .unwrap()
+        .with_part_size(256);
+    let export = exporter
+        .export("job-2", JobRows::from(rows(200)))
+        .await
+        .unwrap();
+
+    start.assert_async().await;
+    put_part.assert_async().await;
+    complete.assert_async().await;
+    let object: Vec<u8> = parts.lock().unwrap().values().flatten().copied().collect();
+    assert!(parts.lock().unwrap().values().all(|part| part.len() <= 256));
+    assert_eq!(decompress(&object), rows(200));
+    assert_eq!(export.byte_size, object.len() as u64);
+    assert_eq!(export.sha256, hex::encode(Sha256::digest(&object)));
+}
+
+#[tokio::test]
+async fn test_jobs_submit_manifests_of_exported_results() {
+    let mut server = Server::new_async().await;
+    let put = server
+        .mock("PUT", "/exports/jobs/large.jsonl.gz")
+        .expect(1)
+        .create_async()
+        .await;
+    let denied = server
+        .mock("PUT", "/exports/jobs/denied.jsonl.gz")
+        .with_status(403)
+        .with_body("<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>")
+        .create_async()
+        .await;
+    result_export::init(&export_config(&server.url(), "jobs/", Some(2))).unwrap();
+
+    let dir = TempDir::new().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    fs::write(&config_path, CONFIG).unwrap();
+    let config = Config::load(&config_path).unwrap();
+    let fake_server = Arc::new(FakeServer::new());
+    fake_server.enqueue_job(job("large", "SELECT * FROM events"));
+    fake_server.enqueue_job(job("small", "SELECT * FROM users"));
+    fake_server.enqueue_job(job("denied", "SELECT * FROM events"));
+    let agent = create_job_agent_with_client(fake_server.clone(), config.datasources, None);
+    agent.process_next().await.unwrap();
+    agent.process_next().await.unwrap();
+    assert!(agent.process_next().await.is_err());
+
+    put.assert_async().await;
+    denied.assert_async().await;
+    let exports = fake_server.job_exports();
+    assert_eq!(exports.len(), 1);
+    assert_eq!(exports[0].0, "large");
+    assert_eq!(exports[0].1.row_count, 3);
+    // Results below `min_rows` are submitted as usual
+    let results = fake_server.job_results();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, "small");
+    let errors = fake_server.job_errors();
+    assert_eq!(errors[0].0, "denied");
+    assert!(
+        errors[0].1.contains("Failed to export results") && errors[0].1.contains("Access Denied"),
+        "{}",
+        errors[0].1
+    );
+}
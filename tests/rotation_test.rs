@@ -0,0 +1,79 @@
+use chrono::Utc;
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+use tsight_agent::config::RotationPeriod;
+use tsight_agent::rotation::RotatingFile;
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn test_rotation_keeps_max_files() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+
+    for line in ["first", "second", "third", "fourth"] {
+        file.write_line(line.as_bytes()).unwrap();
+    }
+
+    assert_eq!(read(&path), "fourth\n");
+    assert_eq!(read(&RotatingFile::rotated_path(&path, 1)), "third\n");
+    assert_eq!(read(&RotatingFile::rotated_path(&path, 2)), "second\n");
+    assert!(!RotatingFile::rotated_path(&path, 3).exists());
+}
+
+#[test]
+fn test_no_rotated_files_kept() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("agent.log");
+    let mut file = RotatingFile::open(&path, 10, 0).unwrap();
+
+    file.write_line(b"first").unwrap();
+    file.write_line(b"second").unwrap();
+
+    assert_eq!(read(&path), "second\n");
+    assert!(!RotatingFile::rotated_path(&path, 1).exists());
+}
+
+#[test]
+fn test_file_of_previous_day_rotated() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("agent.log");
+    fs::write(&path, "yesterday\n").unwrap();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(2 * 86400))
+        .unwrap();
+
+    let mut file = RotatingFile::open(&path, 1024, 3)
+        .unwrap()
+        .with_period(RotationPeriod::Daily)
+        .unwrap();
+    file.write_line(b"today").unwrap();
+    file.write_line(b"later today").unwrap();
+
+    assert_eq!(read(&path), "today\nlater today\n");
+    assert_eq!(read(&RotatingFile::rotated_path(&path, 1)), "yesterday\n");
+}
+
+#[test]
+fn test_file_of_current_period_appended() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("agent.log");
+    fs::write(&path, format!("{}\n", Utc::now())).unwrap();
+
+    let mut file = RotatingFile::open(&path, 1024, 3)
+        .unwrap()
+        .with_period(RotationPeriod::Daily)
+        .unwrap();
+    file.write_line(b"next").unwrap();
+
+    assert_eq!(read(&path).lines().count(), 2);
+    assert!(!RotatingFile::rotated_path(&path, 1).exists());
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use tsight_agent::models::DataSource;
+use tsight_agent::policy::{apply_row_filters, DEFAULT_DATABASE};
+
+fn row_filters(entries: &[(&str, &str)]) -> HashMap<String, String> {
+    entries
+        .iter()
+        .map(|(table, condition)| (table.to_string(), condition.to_string()))
+        .collect()
+}
+
+fn rewrite(entries: &[(&str, &str)], sql: &str) -> String {
+    apply_row_filters(Some(&row_filters(entries)), DEFAULT_DATABASE, sql).unwrap()
+}
+
+#[test]
+fn test_filtered_table_is_replaced_with_subquery() {
+    let sql = rewrite(
+        &[("prod.orders", "tenant_id = 42")],
+        "SELECT count() FROM prod.orders WHERE status = 'paid'",
+    );
+
+    assert_eq!(
+        sql,
+        "SELECT count() FROM (SELECT * FROM prod.orders WHERE tenant_id = 42) AS orders \
+         WHERE status = 'paid'"
+    );
+}
+
+#[test]
+fn test_alias_is_kept() {
+    let sql = rewrite(
+        &[("prod.orders", "tenant_id = 42")],
+        "SELECT o.id FROM prod.orders AS o JOIN prod.users AS u ON o.user_id = u.id",
+    );
+
+    assert_eq!(
+        sql,
+        "SELECT o.id FROM (SELECT * FROM prod.orders WHERE tenant_id = 42) AS o \
+         JOIN prod.users AS u ON o.user_id = u.id"
+    );
+}
+
+#[test]
+fn test_tables_without_database_use_default() {
+    let filters = [("orders", "tenant_id = 42")];
+
+    assert!(rewrite(&filters, "SELECT * FROM orders").contains("WHERE tenant_id = 42"));
+    assert!(rewrite(&filters, "SELECT * FROM default.orders").contains("WHERE tenant_id = 42"));
+    assert_eq!(
+        rewrite(&filters, "SELECT * FROM prod.orders"),
+        "SELECT * FROM prod.orders"
+    );
+}
+
+#[test]
+fn test_tables_without_database_use_datasource_database() {
+    let filters = row_filters(&[
+        ("prod.orders", "tenant_id = 42"),
+        ("users", "tenant_id = 7"),
+    ]);
+    let rewrite = |sql| apply_row_filters(Some(&filters), "prod", sql).unwrap();
+
+    assert_eq!(
+        rewrite("SELECT * FROM orders"),
+        "SELECT * FROM (SELECT * FROM orders WHERE tenant_id = 42) AS orders"
+    );
+    assert!(rewrite("SELECT * FROM prod.users").contains("WHERE tenant_id = 7"));
+    assert_eq!(
+        rewrite("SELECT * FROM default.orders"),
+        "SELECT * FROM default.orders"
+    );
+}
+
+#[test]
+fn test_nested_references_are_filtered() {
+    let sql = rewrite(
+        &[("prod.orders", "tenant_id = 42")],
+        "WITH recent AS (SELECT * FROM prod.orders) SELECT * FROM recent \
+         WHERE id IN (SELECT id FROM prod.orders)",
+    );
+
+    assert_eq!(sql.matches("WHERE tenant_id = 42").count(), 2, "{}", sql);
+}
+
+#[test]
+fn test_common_table_expressions_only_shadow_tables_in_their_query() {
+    let sql = rewrite(
+        &[("orders", "tenant_id = 42")],
+        "SELECT * FROM (WITH orders AS (SELECT 1 AS x) SELECT * FROM orders) AS a, orders",
+    );
+
+    assert_eq!(
+        sql,
+        "SELECT * FROM (WITH orders AS (SELECT 1 AS x) SELECT * FROM orders) AS a, \
+         (SELECT * FROM orders WHERE tenant_id = 42) AS orders"
+    );
+}
+
+#[test]
+fn test_common_table_expressions_dont_shadow_tables_in_their_own_or_earlier_bodies() {
+    let filters = [("default.orders", "tenant_id = 42")];
+
+    assert_eq!(
+        rewrite(
+            &filters,
+            "WITH orders AS (SELECT * FROM orders) SELECT * FROM orders"
+        ),
+        "WITH orders AS (SELECT * FROM (SELECT * FROM orders WHERE tenant_id = 42) AS orders) \
+         SELECT * FROM orders"
+    );
+    assert_eq!(
+        rewrite(
+            &filters,
+            "WITH o AS (SELECT * FROM orders), orders AS (SELECT 1) SELECT * FROM o"
+        ),
+        "WITH o AS (SELECT * FROM (SELECT * FROM orders WHERE tenant_id = 42) AS orders), \
+         orders AS (SELECT 1) SELECT * FROM o"
+    );
+}
+
+#[test]
+fn test_queries_without_filtered_tables_are_unchanged() {
+    let sql = "SELECT   toStartOfHour(ts) AS h, count() FROM events GROUP BY h";
+
+    assert_eq!(rewrite(&[("prod.orders", "tenant_id = 42")], sql), sql);
+    assert_eq!(apply_row_filters(None, DEFAULT_DATABASE, sql).unwrap(), sql);
+}
+
+#[test]
+fn test_invalid_row_filter_is_rejected() {
+    let error = apply_row_filters(
+        Some(&row_filters(&[("prod.orders", "tenant_id = = 42")])),
+        DEFAULT_DATABASE,
+        "SELECT * FROM prod.orders",
+    )
+    .unwrap_err()
+    .to_string();
+
+    assert!(error.contains("invalid row filter"), "{}", error);
+}
+
+#[test]
+fn test_sample_is_kept_on_filtered_table() {
+    let sql = rewrite(
+        &[("orders", "tenant_id = 42")],
+        "SELECT * FROM orders AS o SAMPLE 1 / 10",
+    );
+
+    assert_eq!(
+        sql,
+        "SELECT * FROM (SELECT * FROM orders SAMPLE 1 / 10 WHERE tenant_id = 42) AS o"
+    );
+}
+
+#[test]
+fn test_final_on_filtered_table_is_rejected() {
+    let filters = row_filters(&[("orders", "tenant_id = 42")]);
+    let error = apply_row_filters(
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "SELECT * FROM orders FINAL",
+    )
+    .unwrap_err()
+    .to_string();
+
+    assert!(error.contains("FINAL can't be used"), "{}", error);
+    assert!(apply_row_filters(
+        Some(&filters),
+        DEFAULT_DATABASE,
+        "SELECT * FROM events FINAL"
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_prewhere_on_filtered_table_is_rejected() {
+    let filters = row_filters(&[("orders", "tenant_id = 42")]);
+    let rewrite = |sql| apply_row_filters(Some(&filters), DEFAULT_DATABASE, sql);
+
+    for sql in [
+        "SELECT * FROM orders PREWHERE status = 'paid'",
+        "SELECT * FROM events JOIN orders USING (id) PREWHERE status = 'paid'",
+        "SELECT 1 UNION ALL SELECT id FROM orders PREWHERE status = 'paid'",
+    ] {
+        let error = rewrite(sql).unwrap_err().to_string();
+        assert!(
+            error.contains("PREWHERE can't be used"),
+            "{}: {}",
+            sql,
+            error
+        );
+    }
+    assert!(rewrite("SELECT * FROM events PREWHERE status = 'paid'").is_ok());
+    assert!(rewrite(
+        "WITH orders AS (SELECT 1 AS status) SELECT * FROM orders PREWHERE status = 1"
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_row_filters_parsed_from_yaml() {
+    let datasource: DataSource = config::Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+name: main
+source_type: clickhouse
+hosts: ["http://localhost:8123"]
+username: default
+password: ""
+row_filters:
+  prod.orders: "tenant_id = 42"
+"#,
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    let row_filters = datasource.row_filters.unwrap();
+    assert_eq!(row_filters["prod.orders"], "tenant_id = 42");
+}
@@ -0,0 +1,125 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use tempfile::TempDir;
+use tsight_agent::config::DiscoveryConfig;
+use tsight_agent::executors::clickhouse_source::{RowGrowth, TableSchema};
+use tsight_agent::growth::GrowthTracker;
+use tsight_agent::schema_diff::SchemaDiff;
+
+fn schema(table: &str, row_count: u64, partial: bool) -> TableSchema {
+    TableSchema {
+        database: "prod".to_string(),
+        table: table.to_string(),
+        row_count,
+        columns: HashMap::new(),
+        partial,
+        freshness: Default::default(),
+        growth: None,
+    }
+}
+
+fn start() -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+}
+
+/// Track `schemas` in a discovery at `at`, saving the row counts
+fn discover(dir: &TempDir, at: DateTime<Utc>, mut schemas: Vec<TableSchema>) -> Vec<TableSchema> {
+    let mut tracker = GrowthTracker::load(&dir.path().join("row-counts.json"), at);
+    tracker.track(&mut schemas);
+    tracker.save().unwrap();
+    schemas
+}
+
+#[test]
+fn test_first_discovery_has_no_growth() {
+    let dir = TempDir::new().unwrap();
+
+    let schemas = discover(&dir, start(), vec![schema("events", 100, false)]);
+
+    assert_eq!(schemas[0].growth, None);
+}
+
+#[test]
+fn test_growth_since_previous_discovery() {
+    let dir = TempDir::new().unwrap();
+    discover(
+        &dir,
+        start(),
+        vec![schema("events", 100, false), schema("users", 50, false)],
+    );
+
+    let schemas = discover(
+        &dir,
+        start() + Duration::hours(2),
+        vec![schema("events", 300, false), schema("users", 40, false)],
+    );
+
+    assert_eq!(
+        schemas[0].growth,
+        Some(RowGrowth {
+            row_delta: 200,
+            rows_per_hour: Some(100.0),
+        })
+    );
+    assert_eq!(schemas[1].growth.unwrap().row_delta, -10);
+}
+
+#[test]
+fn test_partial_tables_keep_previous_row_count() {
+    let dir = TempDir::new().unwrap();
+    discover(&dir, start(), vec![schema("events", 100, false)]);
+    let schemas = discover(
+        &dir,
+        start() + Duration::hours(1),
+        vec![schema("events", 0, true)],
+    );
+    assert_eq!(schemas[0].growth, None);
+
+    let schemas = discover(
+        &dir,
+        start() + Duration::hours(2),
+        vec![schema("events", 160, false)],
+    );
+    assert_eq!(
+        schemas[0].growth,
+        Some(RowGrowth {
+            row_delta: 60,
+            rows_per_hour: Some(30.0),
+        })
+    );
+}
+
+#[test]
+fn test_growth_changes_are_diffed() {
+    let mut previous = schema("events", 100, false);
+    previous.growth = Some(RowGrowth {
+        row_delta: 10,
+        rows_per_hour: Some(10.0),
+    });
+    let mut current = previous.clone();
+    current.growth = Some(RowGrowth {
+        row_delta: 0,
+        rows_per_hour: Some(0.0),
+    });
+
+    let diff = SchemaDiff::between(&[previous.clone()], &[current.clone()]);
+    assert_eq!(diff.changed.len(), 1);
+    assert_eq!(diff.changed[0].row_count, None);
+    assert_eq!(diff.apply(vec![previous]), [current]);
+}
+
+#[test]
+fn test_track_growth_parsed_from_yaml() {
+    let discovery: DiscoveryConfig = config::Config::builder()
+        .add_source(config::File::from_str(
+            "track_growth: true\n",
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    assert!(discovery.track_growth);
+    assert!(!DiscoveryConfig::default().track_growth);
+}
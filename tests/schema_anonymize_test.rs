@@ -0,0 +1,131 @@
+use tempfile::TempDir;
+use tsight_agent::anonymize::{AnonymizeError, SchemaAnonymizer, SchemaMapping};
+use tsight_agent::config::DiscoveryConfig;
+use tsight_agent::executors::clickhouse_source::{ColumnInfo, TableSchema};
+
+fn schema(database: &str, table: &str, columns: &[&str]) -> TableSchema {
+    TableSchema {
+        database: database.to_string(),
+        table: table.to_string(),
+        row_count: 100,
+        columns: columns
+            .iter()
+            .map(|column| {
+                (
+                    column.to_string(),
+                    ColumnInfo {
+                        type_name: "string".to_string(),
+                        cardinality: Some(10),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect(),
+        partial: false,
+        freshness: Default::default(),
+        growth: None,
+    }
+}
+
+#[test]
+fn test_names_are_replaced_keeping_shapes() {
+    let anonymizer = SchemaAnonymizer::new(Some("secret")).unwrap();
+    let (schemas, mapping) = anonymizer.anonymize(vec![schema("prod", "salaries", &["ssn"])]);
+
+    let schema = &schemas[0];
+    assert!(schema.database.starts_with("db_"));
+    assert!(schema.table.starts_with("tbl_"));
+    assert_eq!(schema.row_count, 100);
+    let (column, info) = schema.columns.iter().next().unwrap();
+    assert!(column.starts_with("col_"));
+    assert_eq!(info.cardinality, Some(10));
+
+    assert_eq!(mapping.databases[&schema.database], "prod");
+    assert_eq!(mapping.tables[&schema.table], "salaries");
+    assert_eq!(mapping.columns[column], "ssn");
+}
+
+#[test]
+fn test_freshness_uses_anonymized_column_names() {
+    let anonymizer = SchemaAnonymizer::new(Some("secret")).unwrap();
+    let mut events = schema("prod", "events", &["created_at"]);
+    let latest = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    events.freshness.insert("created_at".to_string(), latest);
+
+    let (schemas, _) = anonymizer.anonymize(vec![events]);
+    let column = schemas[0].columns.keys().next().unwrap();
+    assert_eq!(schemas[0].freshness[column], latest);
+}
+
+#[test]
+fn test_equal_names_get_equal_hashes() {
+    let anonymizer = SchemaAnonymizer::new(Some("secret")).unwrap();
+    let (schemas, mapping) = anonymizer.anonymize(vec![
+        schema("prod", "users", &["id"]),
+        schema("prod", "orders", &["id"]),
+    ]);
+
+    assert_eq!(schemas[0].database, schemas[1].database);
+    assert_ne!(schemas[0].table, schemas[1].table);
+    assert_eq!(mapping.columns.len(), 1);
+
+    // Names depend on the key and on what is named
+    let other = SchemaAnonymizer::new(Some("other")).unwrap();
+    assert_ne!(
+        anonymizer.anonymize_name("tbl", "users"),
+        other.anonymize_name("tbl", "users")
+    );
+    assert_ne!(
+        anonymizer.anonymize_name("tbl", "users"),
+        anonymizer.anonymize_name("col", "users")
+    );
+}
+
+#[test]
+fn test_hash_key_is_required() {
+    assert!(matches!(
+        SchemaAnonymizer::new(None),
+        Err(AnonymizeError::MissingHashKey)
+    ));
+}
+
+#[test]
+fn test_mapping_file_accumulates_names() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("mapping.json");
+    let anonymizer = SchemaAnonymizer::new(Some("secret")).unwrap();
+
+    let (_, first) = anonymizer.anonymize(vec![schema("prod", "users", &["id"])]);
+    first.save(&path).unwrap();
+    let (_, second) = anonymizer.anonymize(vec![schema("prod", "orders", &["total"])]);
+    second.save(&path).unwrap();
+
+    let mapping = SchemaMapping::load(&path).unwrap();
+    let mut tables: Vec<_> = mapping.tables.values().collect();
+    tables.sort();
+    assert_eq!(tables, ["orders", "users"]);
+    assert_eq!(mapping.columns.len(), 2);
+}
+
+#[test]
+fn test_anonymize_options_parsed_from_yaml() {
+    let discovery: DiscoveryConfig = config::Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+anonymize: true
+mapping_file: /var/lib/tsight/mapping.json
+"#,
+            config::FileFormat::Yaml,
+        ))
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap();
+
+    assert!(discovery.enabled);
+    assert!(discovery.anonymize);
+    assert_eq!(
+        discovery.mapping_file.unwrap().to_str(),
+        Some("/var/lib/tsight/mapping.json")
+    );
+}
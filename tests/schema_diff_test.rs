@@ -0,0 +1,208 @@
+use mockito::Server;
+use serde_json::json;
+use tempfile::TempDir;
+use tsight_agent::agent::submit_schema_changes;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{FullSyncRequested, ServerApi, ServerClient};
+use tsight_agent::config::DiscoveryConfig;
+use tsight_agent::executors::clickhouse_source::{ColumnInfo, TableSchema};
+use tsight_agent::models::DataSource;
+use tsight_agent::schema_diff::{load_cached_schemas, SchemaDiff, TableRef};
+
+fn column(type_name: &str, cardinality: u64) -> ColumnInfo {
+    ColumnInfo {
+        type_name: type_name.to_string(),
+        cardinality: Some(cardinality),
+        ..Default::default()
+    }
+}
+
+fn schema(table: &str, row_count: u64, columns: &[(&str, ColumnInfo)]) -> TableSchema {
+    TableSchema {
+        database: "prod".to_string(),
+        table: table.to_string(),
+        row_count,
+        columns: columns
+            .iter()
+            .map(|(name, info)| (name.to_string(), info.clone()))
+            .collect(),
+        partial: false,
+        freshness: Default::default(),
+        growth: None,
+    }
+}
+
+fn previous() -> Vec<TableSchema> {
+    vec![
+        schema("users", 10, &[("id", column("int", 10))]),
+        schema(
+            "orders",
+            100,
+            &[("id", column("int", 100)), ("note", column("string", 5))],
+        ),
+        schema("legacy", 1, &[]),
+    ]
+}
+
+fn current() -> Vec<TableSchema> {
+    vec![
+        schema("users", 10, &[("id", column("int", 10))]),
+        schema(
+            "orders",
+            120,
+            &[
+                ("id", column("string", 120)),
+                ("total", column("float", 50)),
+            ],
+        ),
+        schema("events", 1000, &[("ts", column("datetime", 1000))]),
+    ]
+}
+
+#[test]
+fn test_diff_lists_added_removed_and_changed_tables() {
+    let diff = SchemaDiff::between(&previous(), &current());
+
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.added[0].table, "events");
+    assert_eq!(
+        diff.removed,
+        [TableRef {
+            database: "prod".to_string(),
+            table: "legacy".to_string()
+        }]
+    );
+
+    assert_eq!(diff.changed.len(), 1);
+    let change = &diff.changed[0];
+    assert_eq!(change.table, "orders");
+    assert_eq!(change.row_count, Some(120));
+    assert!(change.added_columns.contains_key("total"));
+    assert_eq!(change.removed_columns, ["note"]);
+    assert_eq!(change.changed_columns["id"].type_name, "string");
+    assert_eq!(diff.to_string(), "1 tables added, 1 removed, 1 changed");
+}
+
+#[test]
+fn test_applying_diff_gives_current_schemas() {
+    let diff = SchemaDiff::between(&previous(), &current());
+    let mut applied = diff.apply(previous());
+    let mut expected = current();
+    applied.sort_by(|a, b| a.table.cmp(&b.table));
+    expected.sort_by(|a, b| a.table.cmp(&b.table));
+
+    assert_eq!(applied, expected);
+    assert!(SchemaDiff::between(&current(), &current()).is_empty());
+}
+
+#[test]
+fn test_diff_carries_new_freshness() {
+    let latest = |seconds| chrono::DateTime::from_timestamp(seconds, 0).unwrap();
+    let mut old = schema("events", 10, &[("ts", column("datetime", 10))]);
+    old.freshness
+        .insert("ts".to_string(), latest(1_700_000_000));
+    let mut new = old.clone();
+    new.freshness
+        .insert("ts".to_string(), latest(1_700_003_600));
+
+    let diff = SchemaDiff::between(&[old.clone()], &[new.clone()]);
+    assert_eq!(diff.changed.len(), 1);
+    assert_eq!(diff.changed[0].freshness["ts"], latest(1_700_003_600));
+    assert_eq!(diff.apply(vec![old]), [new]);
+}
+
+fn datasource(dir: &TempDir) -> DataSource {
+    DataSource {
+        name: "main".to_string(),
+        discovery: DiscoveryConfig {
+            incremental: true,
+            cache_file: Some(dir.path().join("cache.json")),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_changes_are_submitted_after_first_full_sync() {
+    let dir = TempDir::new().unwrap();
+    let datasource = datasource(&dir);
+    let server = FakeServer::new();
+
+    submit_schema_changes(&datasource, &server, previous())
+        .await
+        .unwrap();
+    assert_eq!(server.schemas("main").unwrap().len(), 3);
+    assert!(server.schema_diffs().is_empty());
+
+    submit_schema_changes(&datasource, &server, current())
+        .await
+        .unwrap();
+    let diffs = server.schema_diffs();
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].1.added[0].table, "events");
+
+    let cached = load_cached_schemas(&dir.path().join("cache.json")).unwrap();
+    assert_eq!(cached, current());
+
+    // Unchanged schemas submit nothing
+    submit_schema_changes(&datasource, &server, current())
+        .await
+        .unwrap();
+    assert_eq!(server.schema_diffs().len(), 1);
+}
+
+#[tokio::test]
+async fn test_full_sync_on_server_request() {
+    let dir = TempDir::new().unwrap();
+    let datasource = datasource(&dir);
+    let server = FakeServer::new();
+
+    submit_schema_changes(&datasource, &server, previous())
+        .await
+        .unwrap();
+    server.request_full_sync();
+    submit_schema_changes(&datasource, &server, current())
+        .await
+        .unwrap();
+
+    assert!(server.schema_diffs().is_empty());
+    assert_eq!(server.schemas("main").unwrap(), current());
+}
+
+#[tokio::test]
+async fn test_client_reports_full_sync_requests() {
+    let mut server = Server::new_async().await;
+    let diff_mock = server
+        .mock("POST", "/datasource/main/discovery/diff")
+        .with_status(200)
+        .with_body(json!({"full_sync": true}).to_string())
+        .create();
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+
+    let error = client
+        .submit_schema_diff("main", SchemaDiff::default())
+        .await
+        .unwrap_err();
+
+    diff_mock.assert();
+    assert!(error.is::<FullSyncRequested>());
+}
+
+#[tokio::test]
+async fn test_client_accepts_applied_diff() {
+    let mut server = Server::new_async().await;
+    let diff_mock = server
+        .mock("POST", "/datasource/main/discovery/diff")
+        .match_body(mockito::Matcher::PartialJson(json!({"removed": [
+            {"database": "prod", "table": "legacy"}
+        ]})))
+        .with_status(200)
+        .create();
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+
+    let diff = SchemaDiff::between(&previous(), &current());
+    client.submit_schema_diff("main", diff).await.unwrap();
+
+    diff_mock.assert();
+}
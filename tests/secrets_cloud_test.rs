@@ -0,0 +1,132 @@
+use base64::Engine;
+use chrono::TimeZone;
+use mockito::{Matcher, Server};
+use serde_json::json;
+use std::collections::BTreeMap;
+use tsight_agent::aws::{sign_request, AwsCredentials, SigningScope};
+use tsight_agent::config::{AwsSecretsConfig, GcpSecretsConfig, SecretsConfig};
+use tsight_agent::secrets::SecretResolver;
+
+#[test]
+fn test_sigv4_get_vanilla() {
+    // "get-vanilla" case from the AWS Signature Version 4 test suite
+    let credentials = AwsCredentials {
+        access_key_id: "AKIDEXAMPLE".to_string(),
+        secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        session_token: None,
+    };
+    let url = reqwest::Url::parse("https://example.amazonaws.com/").unwrap();
+    let time = chrono::Utc
+        .with_ymd_and_hms(2015, 8, 30, 12, 36, 0)
+        .unwrap();
+
+    let headers = sign_request(
+        "GET",
+        &url,
+        &BTreeMap::new(),
+        b"",
+        &credentials,
+        &SigningScope {
+            region: "us-east-1",
+            service: "service",
+        },
+        time,
+    );
+
+    assert_eq!(headers["x-amz-date"], "20150830T123600Z");
+    assert_eq!(
+        headers["authorization"],
+        "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+         SignedHeaders=host;x-amz-date, \
+         Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+    );
+}
+
+fn create_aws_config(endpoint: &str) -> SecretsConfig {
+    SecretsConfig {
+        aws: Some(AwsSecretsConfig {
+            region: Some("us-east-1".to_string()),
+            access_key_id: Some("AKIDEXAMPLE".to_string()),
+            secret_access_key: Some("secret".to_string()),
+            session_token: None,
+            endpoint_url: Some(endpoint.to_string()),
+        }),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_resolve_aws_secret_key() {
+    let mut server = Server::new_async().await;
+    let secrets_mock = server
+        .mock("POST", "/")
+        .match_header("x-amz-target", "secretsmanager.GetSecretValue")
+        .match_header(
+            "authorization",
+            Matcher::Regex("^AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/".to_string()),
+        )
+        .match_body(Matcher::Json(json!({"SecretId": "tsight/prod"})))
+        .with_status(200)
+        .with_body(
+            json!({"Name": "tsight/prod", "SecretString": "{\"password\":\"s3cret\"}"}).to_string(),
+        )
+        .create();
+
+    let resolver = SecretResolver::from_config(Some(&create_aws_config(&server.url()))).unwrap();
+    let value = resolver
+        .resolve("aws-sm:tsight/prod#password")
+        .await
+        .unwrap();
+
+    assert_eq!(value, "s3cret");
+    secrets_mock.assert();
+}
+
+#[tokio::test]
+async fn test_resolve_aws_plain_secret() {
+    let mut server = Server::new_async().await;
+    let _secrets_mock = server
+        .mock("POST", "/")
+        .with_status(200)
+        .with_body(json!({"SecretString": "plain-value"}).to_string())
+        .create();
+
+    let resolver = SecretResolver::from_config(Some(&create_aws_config(&server.url()))).unwrap();
+    let value = resolver
+        .resolve("aws-sm:arn:aws:secretsmanager:us-east-1:123456789012:secret:tsight")
+        .await
+        .unwrap();
+
+    assert_eq!(value, "plain-value");
+}
+
+#[tokio::test]
+async fn test_resolve_gcp_secret() {
+    let mut server = Server::new_async().await;
+    let data = base64::engine::general_purpose::STANDARD.encode("{\"api_key\":\"from-gcp\"}");
+    let secrets_mock = server
+        .mock(
+            "GET",
+            "/v1/projects/p/secrets/tsight/versions/latest:access",
+        )
+        .match_header("authorization", "Bearer gcp-token")
+        .with_status(200)
+        .with_body(json!({"payload": {"data": data}}).to_string())
+        .create();
+
+    let config = SecretsConfig {
+        gcp: Some(GcpSecretsConfig {
+            access_token: Some("gcp-token".to_string()),
+            endpoint_url: Some(server.url()),
+        }),
+        ..Default::default()
+    };
+    let resolver = SecretResolver::from_config(Some(&config)).unwrap();
+    let value = resolver
+        .resolve("gcp-sm:projects/p/secrets/tsight/versions/latest#api_key")
+        .await
+        .unwrap();
+
+    assert_eq!(value, "from-gcp");
+    secrets_mock.assert();
+}
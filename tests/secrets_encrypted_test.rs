@@ -0,0 +1,212 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+use tsight_agent::config::{Config, EncryptionConfig, KeyringConfig, SecretsConfig};
+use tsight_agent::secrets::encrypted::{is_encrypted, EncryptionKey, KeySource, DEFAULT_KEY_FILE};
+
+fn write_config(dir: &Path, password: &str, secrets: &str) -> std::path::PathBuf {
+    let config_path = dir.join("config.yaml");
+    fs::write(
+        &config_path,
+        format!(
+            r#"
+server:
+  api_key: test_key
+  server_url: http://test-server.com
+datasources:
+  - name: main
+    source_type: Clickhouse
+    hosts:
+      - http://localhost:8123
+    username: default
+    password: "{}"
+{}
+"#,
+            password, secrets
+        ),
+    )
+    .unwrap();
+    config_path
+}
+
+#[test]
+fn test_encrypt_round_trip() {
+    let key = EncryptionKey::generate();
+
+    let first = key.encrypt("s3cret: with #special chars");
+    let second = key.encrypt("s3cret: with #special chars");
+
+    assert!(is_encrypted(&first));
+    assert!(first.starts_with("enc:v1:"));
+    // A fresh IV per value hides that both hold the same secret
+    assert_ne!(first, second);
+    assert_eq!(key.decrypt(&first).unwrap(), "s3cret: with #special chars");
+    assert_eq!(
+        EncryptionKey::decode(&key.encode())
+            .unwrap()
+            .decrypt(&second)
+            .unwrap(),
+        "s3cret: with #special chars"
+    );
+}
+
+#[test]
+fn test_decrypt_rejects_other_keys_and_modified_values() {
+    let key = EncryptionKey::generate();
+    let value = key.encrypt("password");
+
+    let error = EncryptionKey::generate().decrypt(&value).unwrap_err();
+    assert!(
+        error.to_string().contains("doesn't match the key"),
+        "{}",
+        error
+    );
+
+    let mut modified = value.clone().into_bytes();
+    let last = modified.len() - 5;
+    modified[last] = if modified[last] == b'A' { b'B' } else { b'A' };
+    assert!(key.decrypt(&String::from_utf8(modified).unwrap()).is_err());
+
+    assert!(key.decrypt("enc:v1:c2hvcnQ=").is_err());
+    assert!(key.decrypt("enc:v2:abc").is_err());
+    assert!(EncryptionKey::decode("c2hvcnQ=").is_err());
+}
+
+#[test]
+fn test_config_load_decrypts_with_key_next_to_config() {
+    let dir = TempDir::new().unwrap();
+    let source = KeySource::File(dir.path().join(DEFAULT_KEY_FILE));
+    let (key, created) = source.load_or_create().unwrap();
+    assert!(created);
+    let config_path = write_config(dir.path(), &key.encrypt("ch_password"), "");
+
+    let config = Config::load(&config_path).unwrap();
+
+    assert_eq!(config.datasources[0].password, "ch_password");
+    assert_eq!(config.datasources[0].username, "default");
+    assert_eq!(config.server.api_key, "test_key");
+
+    // The existing key is reused
+    let (_, created) = source.load_or_create().unwrap();
+    assert!(!created);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_key_file_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("keys").join(DEFAULT_KEY_FILE);
+    KeySource::File(path.clone()).load_or_create().unwrap();
+
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn test_configured_key_file_is_relative_to_config() {
+    let dir = TempDir::new().unwrap();
+    let (key, _) = KeySource::File(dir.path().join("keys/agent.key"))
+        .load_or_create()
+        .unwrap();
+    let config_path = write_config(
+        dir.path(),
+        &key.encrypt("ch_password"),
+        "secrets:\n  encryption:\n    key_file: keys/agent.key",
+    );
+
+    let config = Config::load(&config_path).unwrap();
+
+    assert_eq!(config.datasources[0].password, "ch_password");
+}
+
+#[test]
+fn test_missing_key_fails_loading() {
+    let dir = TempDir::new().unwrap();
+    let value = EncryptionKey::generate().encrypt("ch_password");
+    let config_path = write_config(dir.path(), &value, "");
+
+    let error = Config::load(&config_path).unwrap_err().to_string();
+
+    assert!(error.contains("secret.key"), "{}", error);
+    assert!(error.contains("secret encrypt"), "{}", error);
+}
+
+#[test]
+fn test_key_is_only_read_for_encrypted_credentials() {
+    let dir = TempDir::new().unwrap();
+    let config_path = write_config(
+        dir.path(),
+        "plain",
+        "secrets:\n  encryption:\n    key_file: missing.key",
+    );
+
+    let config = Config::load(&config_path).unwrap();
+
+    assert_eq!(config.datasources[0].password, "plain");
+}
+
+#[test]
+fn test_keyring_key_source() {
+    keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+    let config = SecretsConfig {
+        keyring: Some(KeyringConfig {
+            service: Some("custom-service".to_string()),
+        }),
+        encryption: Some(EncryptionConfig {
+            keyring_entry: Some("encryption-key".to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let source = KeySource::from_config(Some(&config), Path::new("/etc/tsight_agent"));
+
+    assert_eq!(
+        source,
+        KeySource::Keyring {
+            service: "custom-service".to_string(),
+            entry: "encryption-key".to_string(),
+        }
+    );
+    let error = format!("{:#}", source.load().unwrap_err());
+    assert!(
+        error.contains("No keyring entry 'encryption-key' for service 'custom-service'"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_secret_encrypt_command() {
+    let dir = TempDir::new().unwrap();
+    let config_path = write_config(dir.path(), "placeholder", "");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tsight_agent"))
+        .arg("--config")
+        .arg(&config_path)
+        .args(["secret", "encrypt"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"ch_password\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    // The key is created next to the config and the value works in it
+    assert!(dir.path().join(DEFAULT_KEY_FILE).exists());
+    let value = String::from_utf8(output.stdout).unwrap();
+    let config_path = write_config(dir.path(), value.trim(), "");
+    let config = Config::load(&config_path).unwrap();
+    assert_eq!(config.datasources[0].password, "ch_password");
+}
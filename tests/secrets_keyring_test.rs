@@ -0,0 +1,59 @@
+use std::sync::Arc;
+use tsight_agent::config::{KeyringConfig, SecretsConfig};
+use tsight_agent::secrets::keyring::KeyringProvider;
+use tsight_agent::secrets::{SecretProvider, SecretResolver};
+
+fn use_mock_keyring() {
+    keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+}
+
+#[tokio::test]
+async fn test_missing_keyring_entry() {
+    use_mock_keyring();
+    let provider = KeyringProvider::new("tsight-agent-test");
+
+    let error = format!("{:#}", provider.fetch("ch_password").await.unwrap_err());
+    assert!(
+        error.contains("No keyring entry 'ch_password' for service 'tsight-agent-test'"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_empty_keyring_entry_name() {
+    let provider = KeyringProvider::new("tsight-agent-test");
+    assert!(provider.fetch("").await.is_err());
+}
+
+#[tokio::test]
+async fn test_keyring_references_are_resolved_without_config() {
+    use_mock_keyring();
+    let resolver = SecretResolver::from_config(None).unwrap();
+
+    let error = format!(
+        "{:#}",
+        resolver.resolve("keyring:api_key").await.unwrap_err()
+    );
+    assert!(
+        error.contains("Failed to resolve keyring secret 'api_key'"),
+        "{}",
+        error
+    );
+    assert_eq!(resolver.resolve("plain").await.unwrap(), "plain");
+}
+
+#[tokio::test]
+async fn test_keyring_service_from_config() {
+    use_mock_keyring();
+    let config = SecretsConfig {
+        keyring: Some(KeyringConfig {
+            service: Some("custom-service".to_string()),
+        }),
+        ..Default::default()
+    };
+    let provider = Arc::new(KeyringProvider::from_config(config.keyring.as_ref()));
+
+    let error = format!("{:#}", provider.fetch("api_key").await.unwrap_err());
+    assert!(error.contains("custom-service"), "{}", error);
+}
@@ -0,0 +1,100 @@
+use mockito::Server;
+use serde_json::json;
+use tsight_agent::config::{Config, SecretsConfig, ServerConfig, VaultConfig};
+use tsight_agent::models::{DataSource, DataSourceType};
+use tsight_agent::secrets::SecretResolver;
+
+fn create_vault_config(address: &str) -> SecretsConfig {
+    SecretsConfig {
+        vault: Some(VaultConfig {
+            address: address.to_string(),
+            token: Some("vault-token".to_string()),
+            token_file: None,
+            namespace: None,
+            renew_interval_secs: 300,
+        }),
+        ..Default::default()
+    }
+}
+
+fn create_test_datasource(password: &str) -> DataSource {
+    DataSource {
+        name: "test_clickhouse".to_string(),
+        source_type: DataSourceType::Clickhouse,
+        hosts: vec!["http://localhost:8123".to_string()],
+        username: "test_user".to_string(),
+        password: password.to_string(),
+        timeout: 60,
+        filters: None,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_resolve_config_from_vault_kv2() {
+    let mut server = Server::new_async().await;
+    let vault_mock = server
+        .mock("GET", "/v1/kv/data/tsight")
+        .match_header("X-Vault-Token", "vault-token")
+        .with_status(200)
+        .with_body(
+            json!({
+                "lease_id": "",
+                "renewable": false,
+                "lease_duration": 0,
+                "data": {"data": {"ch_password": "s3cret", "api_key": "key-from-vault"}}
+            })
+            .to_string(),
+        )
+        .expect(2)
+        .create();
+
+    let mut config = Config {
+        server: ServerConfig {
+            api_key: "vault:kv/data/tsight#api_key".to_string(),
+            server_url: "http://localhost:8080".to_string(),
+            ..Default::default()
+        },
+        datasources: vec![create_test_datasource("vault:kv/data/tsight#ch_password")],
+        secrets: Some(create_vault_config(&server.url())),
+        ..Default::default()
+    };
+
+    let resolver = SecretResolver::from_config(config.secrets.as_ref()).unwrap();
+    resolver.resolve_config(&mut config).await.unwrap();
+
+    assert_eq!(config.server.api_key, "key-from-vault");
+    assert_eq!(config.datasources[0].password, "s3cret");
+    assert_eq!(config.datasources[0].username, "test_user");
+    vault_mock.assert();
+}
+
+#[tokio::test]
+async fn test_resolve_missing_key() {
+    let mut server = Server::new_async().await;
+    let _vault_mock = server
+        .mock("GET", "/v1/secret/tsight")
+        .with_status(200)
+        .with_body(json!({"data": {"other": "value"}}).to_string())
+        .create();
+
+    let resolver = SecretResolver::from_config(Some(&create_vault_config(&server.url()))).unwrap();
+    let error = resolver
+        .resolve("vault:secret/tsight#ch_password")
+        .await
+        .unwrap_err();
+
+    assert!(format!("{:#}", error).contains("Key 'ch_password' not found"));
+}
+
+#[tokio::test]
+async fn test_reference_without_configured_provider() {
+    let resolver = SecretResolver::from_config(None).unwrap();
+
+    assert!(resolver.resolve("vault:kv/data/tsight#key").await.is_err());
+    assert_eq!(
+        resolver.resolve("plain:password").await.unwrap(),
+        "plain:password"
+    );
+    assert_eq!(resolver.resolve("password").await.unwrap(), "password");
+}
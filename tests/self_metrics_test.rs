@@ -0,0 +1,172 @@
+use base64::Engine;
+use chrono::{TimeZone, Utc};
+use mockito::{Matcher, Server};
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry};
+use serde_json::json;
+use std::fs;
+use std::time::Duration;
+use tempfile::TempDir;
+use tsight_agent::config::{Config, SelfMetricsConfig};
+use tsight_agent::metrics::{metrics, JOB_QUEUE};
+use tsight_agent::models::{DataSource, DataSourceType};
+use tsight_agent::self_metrics::{rows, SelfMetricsWriter};
+
+fn self_metrics_config(table: &str) -> SelfMetricsConfig {
+    serde_json::from_value(json!({
+        "datasource": "warehouse",
+        "table": table,
+        "username": "metrics_writer",
+        "password": "writer-secret",
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_rows_of_metric_families() {
+    let registry = Registry::new();
+    let tasks = IntCounterVec::new(Opts::new("tasks_total", "Tasks"), &["queue"]).unwrap();
+    let running = IntGauge::new("running", "Running").unwrap();
+    let duration = HistogramVec::new(
+        HistogramOpts::new("duration_seconds", "Duration").buckets(vec![0.5, 1.0]),
+        &["queue"],
+    )
+    .unwrap();
+    registry.register(Box::new(tasks.clone())).unwrap();
+    registry.register(Box::new(running.clone())).unwrap();
+    registry.register(Box::new(duration.clone())).unwrap();
+    tasks.with_label_values(&["job"]).inc_by(3);
+    running.set(2);
+    duration.with_label_values(&["job"]).observe(0.25);
+    duration.with_label_values(&["job"]).observe(0.75);
+
+    let at = Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap();
+    let rows = rows(&registry.gather(), "agent-1", at);
+
+    let row = |metric: &str, labels: serde_json::Value, value: f64| {
+        json!({
+            "timestamp": "2025-03-01 12:00:00.000",
+            "agent": "agent-1",
+            "metric": metric,
+            "labels": labels,
+            "value": value,
+        })
+    };
+    assert_eq!(
+        rows,
+        vec![
+            row("duration_seconds_count", json!({"queue": "job"}), 2.0),
+            row("duration_seconds_sum", json!({"queue": "job"}), 1.0),
+            row(
+                "duration_seconds_bucket",
+                json!({"queue": "job", "le": "0.5"}),
+                1.0
+            ),
+            row(
+                "duration_seconds_bucket",
+                json!({"queue": "job", "le": "1"}),
+                2.0
+            ),
+            row(
+                "duration_seconds_bucket",
+                json!({"queue": "job", "le": "+Inf"}),
+                2.0
+            ),
+            row("running", json!({}), 2.0),
+            row("tasks_total", json!({"queue": "job"}), 3.0),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_writer_creates_table_and_inserts_asynchronously() {
+    let mut clickhouse = Server::new_async().await;
+    let authorization = format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode("metrics_writer:writer-secret")
+    );
+    let create = clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .match_header("authorization", authorization.as_str())
+        .match_body(Matcher::Regex(
+            r"^CREATE TABLE IF NOT EXISTS monitoring\.agent_metrics \(timestamp DateTime64"
+                .to_string(),
+        ))
+        .expect(1)
+        .create_async()
+        .await;
+    let insert = clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("async_insert".into(), "1".into()),
+            Matcher::UrlEncoded("wait_for_async_insert".into(), "1".into()),
+        ]))
+        .match_header("authorization", authorization.as_str())
+        .match_body(Matcher::AllOf(vec![
+            Matcher::Regex(
+                r"^INSERT INTO monitoring\.agent_metrics FORMAT JSONEachRow\n\{".to_string(),
+            ),
+            Matcher::Regex(r#""metric":"tsight_task_duration_seconds_count""#.to_string()),
+        ]))
+        .expect(1)
+        .create_async()
+        .await;
+    metrics().record_task(JOB_QUEUE, true, Duration::from_millis(20));
+
+    let datasource = DataSource {
+        name: "warehouse".to_string(),
+        source_type: DataSourceType::Clickhouse,
+        hosts: vec![clickhouse.url()],
+        username: "reader".to_string(),
+        password: "reader-secret".to_string(),
+        ..Default::default()
+    };
+    let writer = SelfMetricsWriter::new(
+        &self_metrics_config("monitoring.agent_metrics"),
+        &datasource,
+    )
+    .await
+    .unwrap();
+    writer.create_table().await.unwrap();
+    assert!(writer.write(Utc::now()).await.unwrap() > 0);
+
+    create.assert_async().await;
+    insert.assert_async().await;
+}
+
+#[test]
+fn test_self_metrics_need_a_clickhouse_table() {
+    let load = |source_type: &str, table: &str| {
+        let dir = TempDir::new().unwrap();
+        let config_path = dir.path().join("config.yaml");
+        let content = format!(
+            r#"server:
+  api_key: "test-api-key"
+  server_url: "http://localhost:8080"
+datasources:
+  - name: "warehouse"
+    source_type: "{}"
+    hosts: ["http://localhost:8123"]
+self_metrics:
+  datasource: "warehouse"
+  table: "{}"
+"#,
+            source_type, table
+        );
+        fs::write(&config_path, content).unwrap();
+        Config::load(&config_path).map_err(|e| e.to_string())
+    };
+
+    let config = load("clickhouse", "monitoring.agent_metrics").unwrap();
+    let self_metrics = config.self_metrics.unwrap();
+    assert_eq!(self_metrics.interval, Duration::from_secs(60));
+    assert!(load("postgresql", "agent_metrics")
+        .unwrap_err()
+        .contains("can only be written to ClickHouse"));
+    assert!(load("clickhouse", "agent_metrics; DROP TABLE users")
+        .unwrap_err()
+        .contains("Invalid self metrics table"));
+    assert!(load("clickhouse", "a.b.c")
+        .unwrap_err()
+        .contains("Invalid self metrics table"));
+}
@@ -0,0 +1,52 @@
+use std::path::Path;
+use tsight_agent::service::{command_line, launch_arguments, DEFAULT_SERVICE_NAME};
+
+#[test]
+fn test_launch_arguments() {
+    assert_eq!(
+        launch_arguments(DEFAULT_SERVICE_NAME, None).unwrap(),
+        ["service", "run", "--name", "tsight-agent"]
+    );
+    assert_eq!(
+        launch_arguments(
+            "agent-2",
+            Some(Path::new(r"C:\ProgramData\tsight\config.yaml"))
+        )
+        .unwrap(),
+        [
+            "--config",
+            r"C:\ProgramData\tsight\config.yaml",
+            "service",
+            "run",
+            "--name",
+            "agent-2"
+        ]
+    );
+}
+
+#[test]
+fn test_command_line_quotes_arguments() {
+    let arguments = [
+        r"C:\Program Files\TSight\tsight_agent.exe",
+        "--config",
+        r"C:\configs dir\",
+        r#"say "hi""#,
+        "",
+        r"C:\plain\path",
+    ]
+    .map(String::from);
+
+    assert_eq!(
+        command_line(&arguments),
+        r#""C:\Program Files\TSight\tsight_agent.exe" --config "C:\configs dir\\" "say \"hi\"" "" C:\plain\path"#
+    );
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_service_commands_fail_outside_windows() {
+    let error = tsight_agent::service::install(DEFAULT_SERVICE_NAME, None).unwrap_err();
+    assert!(error.to_string().contains("only supported on Windows"));
+    assert!(tsight_agent::service::uninstall(DEFAULT_SERVICE_NAME).is_err());
+    assert!(tsight_agent::service::run(DEFAULT_SERVICE_NAME, || ()).is_err());
+}
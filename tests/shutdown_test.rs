@@ -0,0 +1,34 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::models::DataSource;
+use tsight_agent::shutdown;
+
+// Shutdown is process-wide, so this is the only test of this file
+
+#[tokio::test]
+async fn test_agent_loop_stops_on_shutdown() {
+    let agent = create_job_agent_with_client(
+        Arc::new(FakeServer::new()),
+        vec![DataSource {
+            name: "main".to_string(),
+            hosts: vec!["http://localhost:8123".to_string()],
+            ..Default::default()
+        }],
+        None,
+    );
+    let running = tokio::spawn(async move { agent.run().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!running.is_finished());
+    assert!(!shutdown::is_requested());
+
+    shutdown::request();
+
+    // The loop leaves its idle sleep instead of waiting it out
+    tokio::time::timeout(Duration::from_millis(500), running)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(shutdown::is_requested());
+}
@@ -0,0 +1,107 @@
+use mockito::{Matcher, Server};
+use std::sync::Arc;
+use std::time::Duration;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind, ServerApi, ServerClient};
+use tsight_agent::config::SlowQueryConfig;
+use tsight_agent::models::DataSource;
+use tsight_agent::slow_query::{self, SlowQuery};
+
+const BODY: &str = concat!(
+    "[\"id\",\"email\"]\n",
+    "[\"UInt64\",\"String\"]\n",
+    "[1,\"john@example.com\"]\n",
+    "[2,\"jane@example.com\"]\n",
+);
+
+#[test]
+fn test_threshold_is_inclusive() {
+    let config = SlowQueryConfig {
+        threshold: Duration::from_secs(10),
+        report: false,
+    };
+
+    assert!(!config.is_slow(Duration::from_millis(9999)));
+    assert!(config.is_slow(Duration::from_secs(10)));
+}
+
+#[tokio::test]
+async fn test_slow_job_query_reported() {
+    // Every query counts as slow with a zero threshold
+    slow_query::init(&SlowQueryConfig {
+        threshold: Duration::ZERO,
+        report: true,
+    })
+    .unwrap();
+    let mut clickhouse = Server::new_async().await;
+    clickhouse
+        .mock("POST", "/")
+        .match_query(Matcher::Any)
+        .with_body(BODY)
+        .create_async()
+        .await;
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "7".to_string(),
+        datasource_name: "main".to_string(),
+        query: "SELECT id, email FROM users WHERE email LIKE '%@example.com'".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+    let agent = create_job_agent_with_client(
+        server.clone(),
+        vec![DataSource {
+            name: "main".to_string(),
+            hosts: vec![clickhouse.url()],
+            ..Default::default()
+        }],
+        None,
+    );
+
+    agent.process_next().await.unwrap();
+
+    let slow_queries = server.slow_queries();
+    assert_eq!(slow_queries.len(), 1);
+    assert_eq!(slow_queries[0].task_id, "7");
+    assert_eq!(slow_queries[0].datasource, "main");
+    assert_eq!(
+        slow_queries[0].query,
+        "SELECT id, email FROM users WHERE email LIKE '?'"
+    );
+    assert_eq!(slow_queries[0].rows, Some(2));
+}
+
+#[tokio::test]
+async fn test_server_client_reports_slow_query() {
+    let mut server = Server::new_async().await;
+    let report_mock = server
+        .mock("POST", "/agent/slow_query")
+        .match_body(Matcher::PartialJson(serde_json::json!({
+            "task_id": "7",
+            "datasource": "main",
+            "duration_ms": 12500,
+            "rows": 40,
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+    let slow_query = SlowQuery {
+        task_id: "7".to_string(),
+        datasource: "main".to_string(),
+        query: "SELECT count() FROM events".to_string(),
+        duration_ms: 12500,
+        rows: Some(40),
+        started_at: chrono::Utc::now(),
+    };
+
+    let client = ServerClient::new("test-api-key".to_string(), server.url());
+    client.report_slow_query(&slow_query).await.unwrap();
+
+    report_mock.assert();
+}
@@ -0,0 +1,166 @@
+#![cfg(unix)]
+
+use anyhow::anyhow;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::errors::NoWorkAvailable;
+use tsight_agent::models::DataSource;
+use tsight_agent::systemd::{
+    self, parse_watchdog_timeout, spawn_ready_notification, spawn_watchdog, Liveness, Notifier,
+    READY, WATCHDOG,
+};
+
+/// Socket standing in for systemd's notification socket
+fn notify_socket(dir: &TempDir) -> (UnixDatagram, Notifier) {
+    let path = dir.path().join("notify.sock");
+    let socket = UnixDatagram::bind(&path).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (socket, Notifier::new(path))
+}
+
+fn receive(socket: &UnixDatagram) -> String {
+    let mut buffer = [0; 256];
+    let len = socket.recv(&mut buffer).unwrap();
+    String::from_utf8(buffer[..len].to_vec()).unwrap()
+}
+
+#[test]
+fn test_notify_sends_state() {
+    let dir = TempDir::new().unwrap();
+    let (socket, notifier) = notify_socket(&dir);
+
+    assert!(notifier.is_enabled());
+    notifier.notify_status(READY, "Processing tasks");
+
+    assert_eq!(receive(&socket), "READY=1\nSTATUS=Processing tasks");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_notify_abstract_socket() {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let name = format!("tsight-agent-test-{}", std::process::id());
+    let address = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+    let socket = UnixDatagram::bind_addr(&address).unwrap();
+
+    Notifier::new(format!("@{}", name))
+        .notify(WATCHDOG)
+        .unwrap();
+
+    let mut buffer = [0; 64];
+    let len = socket.recv(&mut buffer).unwrap();
+    assert_eq!(&buffer[..len], WATCHDOG.as_bytes());
+}
+
+#[test]
+fn test_notify_without_socket_is_noop() {
+    let notifier = Notifier::from_env();
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        assert!(!notifier.is_enabled());
+        notifier.notify(READY).unwrap();
+    }
+}
+
+#[test]
+fn test_parse_watchdog_timeout() {
+    assert_eq!(
+        parse_watchdog_timeout(Some("30000000"), None, 42),
+        Some(Duration::from_secs(30))
+    );
+    assert_eq!(
+        parse_watchdog_timeout(Some("30000000"), Some("42"), 42),
+        Some(Duration::from_secs(30))
+    );
+    assert_eq!(
+        parse_watchdog_timeout(Some("30000000"), Some("7"), 42),
+        None
+    );
+    assert_eq!(parse_watchdog_timeout(Some("0"), None, 42), None);
+    assert_eq!(parse_watchdog_timeout(Some("soon"), None, 42), None);
+    assert_eq!(parse_watchdog_timeout(None, None, 42), None);
+}
+
+#[test]
+fn test_acquire_reaches_server_unless_it_fails() {
+    let liveness = Liveness::new();
+
+    liveness.record_acquire::<()>(&Err(anyhow!("Failed to connect")));
+    assert!(!liveness.has_acquired());
+
+    liveness.record_acquire::<()>(&Err(anyhow!(NoWorkAvailable::TASKS)));
+    assert!(liveness.has_acquired());
+}
+
+#[tokio::test]
+async fn test_ready_after_first_acquire() {
+    let dir = TempDir::new().unwrap();
+    let (socket, notifier) = notify_socket(&dir);
+    let notifier: &'static Notifier = Box::leak(Box::new(notifier));
+    let liveness: &'static Liveness = Box::leak(Box::new(Liveness::new()));
+
+    let ready = spawn_ready_notification(notifier, liveness);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!ready.is_finished());
+
+    liveness.record_acquire(&Ok(()));
+    tokio::time::timeout(Duration::from_secs(5), ready)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(receive(&socket), "READY=1\nSTATUS=Processing tasks");
+}
+
+#[tokio::test]
+async fn test_agent_acquire_marks_liveness() {
+    let agent = create_job_agent_with_client(
+        Arc::new(FakeServer::new()),
+        vec![DataSource {
+            name: "main".to_string(),
+            hosts: vec!["http://localhost:8123".to_string()],
+            ..Default::default()
+        }],
+        None,
+    );
+
+    // The fake server has no jobs, which still means it was reached
+    agent.process_next().await.unwrap_err();
+
+    assert!(systemd::liveness().has_acquired());
+}
+
+// Receiving blocks, so the watchdog needs a thread of its own
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_watchdog_pings_only_while_loops_are_alive() {
+    let dir = TempDir::new().unwrap();
+    let (socket, notifier) = notify_socket(&dir);
+    let notifier: &'static Notifier = Box::leak(Box::new(notifier));
+    let liveness: &'static Liveness = Box::leak(Box::new(Liveness::new()));
+    let timeout = Duration::from_millis(200);
+
+    liveness.beat("job");
+    let watchdog = spawn_watchdog(notifier, liveness, timeout);
+    assert_eq!(receive(&socket), WATCHDOG);
+    assert!(liveness.is_alive(timeout));
+
+    // The loop stops beating, so pings stop once the timeout passed
+    tokio::time::sleep(timeout * 2).await;
+    assert_eq!(liveness.stalled(timeout), vec!["job"]);
+    socket.set_nonblocking(true).unwrap();
+    while socket.recv(&mut [0; 64]).is_ok() {}
+    tokio::time::sleep(timeout).await;
+    assert!(socket.recv(&mut [0; 64]).is_err());
+
+    liveness.beat("job");
+    socket.set_nonblocking(false).unwrap();
+    assert_eq!(receive(&socket), WATCHDOG);
+    watchdog.abort();
+}
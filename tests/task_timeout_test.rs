@@ -0,0 +1,97 @@
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tsight_agent::agent::factory::{
+    create_job_agent_with_client, create_observation_agent_with_client,
+};
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::models::DataSource;
+
+/// Datasource whose connections are accepted by the OS but never answered
+fn unresponsive_datasource(listener: &TcpListener, timeout: u64) -> DataSource {
+    DataSource {
+        name: "slow".to_string(),
+        hosts: vec![format!("http://{}", listener.local_addr().unwrap())],
+        timeout,
+        ..Default::default()
+    }
+}
+
+fn create_task(timeout: Option<u64>) -> AcquireResultBody {
+    AcquireResultBody {
+        id: "123".to_string(),
+        datasource_name: "slow".to_string(),
+        query: "SELECT 1".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    }
+}
+
+#[tokio::test]
+async fn test_task_timeout_overrides_datasource_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_task(create_task(Some(1)), false);
+
+    let agent = create_observation_agent_with_client(
+        server.clone(),
+        vec![unresponsive_datasource(&listener, 600)],
+        false,
+        None,
+    );
+
+    let started = Instant::now();
+    assert!(agent.process_next().await.is_err());
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let errors = server.task_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0].1.contains("timed out after 1s"),
+        "{}",
+        errors[0].1
+    );
+}
+
+#[tokio::test]
+async fn test_job_falls_back_to_datasource_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(create_task(None));
+
+    let agent = create_job_agent_with_client(
+        server.clone(),
+        vec![unresponsive_datasource(&listener, 1)],
+        None,
+    );
+
+    assert!(agent.process_next().await.is_err());
+
+    let errors = server.job_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0].1.contains("timed out after 1s"),
+        "{}",
+        errors[0].1
+    );
+}
+
+#[test]
+fn test_timeout_is_optional_in_acquire_payload() {
+    let task: AcquireResultBody =
+        serde_json::from_str(r#"{"id": "1", "datasource_name": "main", "query": "SELECT 1"}"#)
+            .unwrap();
+    assert_eq!(task.timeout, None);
+
+    let task: AcquireResultBody = serde_json::from_str(
+        r#"{"id": "1", "datasource_name": "main", "query": "SELECT 1", "timeout": 5}"#,
+    )
+    .unwrap();
+    assert_eq!(task.timeout, Some(5));
+}
@@ -0,0 +1,107 @@
+use mockito::{Matcher, Server};
+use opentelemetry::trace::{Status, TracerProvider as _};
+use opentelemetry::Value;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+use std::sync::Arc;
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind, ServerApi, ServerClient};
+use tsight_agent::telemetry::trace_headers;
+
+/// Subscriber exporting spans to memory
+fn subscriber(
+    exporter: &InMemorySpanExporter,
+) -> (impl tracing::Subscriber + Send + Sync, SdkTracerProvider) {
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("test"));
+    (tracing_subscriber::registry().with(layer), provider)
+}
+
+fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+    span.attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == key)
+        .map(|attribute| attribute.value.clone())
+}
+
+#[tokio::test]
+async fn test_task_trace() {
+    let exporter = InMemorySpanExporter::default();
+    let (subscriber, provider) = subscriber(&exporter);
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "42".to_string(),
+        datasource_name: "missing".to_string(),
+        query: "SELECT 1".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+    let agent = create_job_agent_with_client(server, vec![], None);
+    agent.process_next().await.unwrap_err();
+    provider.force_flush().unwrap();
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let task = spans
+        .iter()
+        .find(|span| span.name == "task")
+        .expect("task span is exported");
+    assert_eq!(attribute(task, "task_id"), Some(Value::from("42")));
+    assert_eq!(attribute(task, "queue"), Some(Value::from("job")));
+    assert_eq!(attribute(task, "datasource"), Some(Value::from("missing")));
+    assert!(matches!(task.status, Status::Error { .. }));
+
+    for phase in ["acquire", "submit"] {
+        let span = spans
+            .iter()
+            .find(|span| span.name == phase)
+            .unwrap_or_else(|| panic!("{} span is exported", phase));
+        assert_eq!(span.parent_span_id, task.span_context.span_id());
+        assert_eq!(span.span_context.trace_id(), task.span_context.trace_id());
+    }
+}
+
+#[tokio::test]
+async fn test_requests_carry_trace_context() {
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    let exporter = InMemorySpanExporter::default();
+    let (subscriber, _provider) = subscriber(&exporter);
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let mut server = Server::new_async().await;
+    let submit = server
+        .mock("POST", "/jobs/42/submit")
+        .match_header(
+            "traceparent",
+            Matcher::Regex("^00-[0-9a-f]{32}-".to_string()),
+        )
+        .with_status(200)
+        .create_async()
+        .await;
+    let client = ServerClient::new("key".to_string(), server.url());
+
+    let span = tracing::info_span!("task");
+    assert!(span.in_scope(trace_headers).contains_key("traceparent"));
+    client
+        .submit_job_error("42", "failed")
+        .instrument(span)
+        .await
+        .unwrap();
+    submit.assert_async().await;
+}
+
+#[test]
+fn test_no_trace_context_without_span() {
+    assert!(trace_headers().is_empty());
+}
@@ -0,0 +1,220 @@
+use serde_json::json;
+use std::fs;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tsight_agent::agent::initialize_tenant_agents;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{Config, MockConfig, MockResult, DEFAULT_TENANT};
+use tsight_agent::health::{Readiness, CHECK_OK};
+use tsight_agent::models::{DataSource, DataSourceType, JobType};
+
+fn load(dir: &TempDir, content: &str) -> Result<Config, config::ConfigError> {
+    let config_path = dir.path().join("config.yaml");
+    fs::write(&config_path, content).unwrap();
+    Config::load(&config_path)
+}
+
+const DATASOURCES: &str = r#"datasources:
+  - name: shared_db
+    source_type: mock
+  - name: acme_db
+    source_type: mock
+  - name: globex_db
+    source_type: mock
+"#;
+
+fn config(server: &str, tenants: &str) -> String {
+    format!("{}{}tenants:\n{}", server, DATASOURCES, tenants)
+}
+
+const SERVER: &str = r#"server:
+  api_key: main-key
+  server_url: http://localhost:8080
+"#;
+
+const TENANTS: &str = r#"  - name: acme
+    server:
+      api_key: acme-key
+      server_url: http://acme.example.com
+    datasources: [acme_db]
+  - name: globex
+    server:
+      api_key: globex-key
+      server_url: http://globex.example.com
+    datasources: [globex_db]
+"#;
+
+fn names(datasources: &[DataSource]) -> Vec<&str> {
+    datasources.iter().map(|ds| ds.name.as_str()).collect()
+}
+
+#[test]
+fn test_datasources_grouped_by_tenant() {
+    let dir = TempDir::new().unwrap();
+    let config = load(&dir, &config(SERVER, TENANTS)).unwrap();
+
+    let tenants = config.tenants();
+
+    assert_eq!(tenants.len(), 3);
+    assert_eq!(tenants[0].name, DEFAULT_TENANT);
+    assert_eq!(tenants[0].server.api_key, "main-key");
+    assert_eq!(names(&tenants[0].datasources), vec!["shared_db"]);
+    assert_eq!(tenants[1].name, "acme");
+    assert_eq!(tenants[1].server.server_url, "http://acme.example.com");
+    assert_eq!(names(&tenants[1].datasources), vec!["acme_db"]);
+    assert_eq!(tenants[2].name, "globex");
+    assert_eq!(names(&tenants[2].datasources), vec!["globex_db"]);
+}
+
+#[test]
+fn test_single_server_is_default_tenant() {
+    let dir = TempDir::new().unwrap();
+    let config = load(&dir, &format!("{}{}", SERVER, DATASOURCES)).unwrap();
+
+    let tenants = config.tenants();
+
+    assert_eq!(tenants.len(), 1);
+    assert_eq!(tenants[0].name, DEFAULT_TENANT);
+    assert_eq!(tenants[0].datasources.len(), 3);
+}
+
+#[test]
+fn test_top_level_server_optional_with_tenants() {
+    let dir = TempDir::new().unwrap();
+    let tenants = TENANTS.replace("[globex_db]", "[globex_db, shared_db]");
+    let config = load(&dir, &config("", &tenants)).unwrap();
+
+    let tenants = config.tenants();
+
+    assert_eq!(tenants.len(), 2);
+    assert_eq!(tenants[0].name, "acme");
+    assert_eq!(
+        names(&tenants[1].datasources),
+        vec!["shared_db", "globex_db"]
+    );
+}
+
+#[test]
+fn test_invalid_tenants_rejected() {
+    let cases = [
+        (
+            config("", TENANTS),
+            "datasource 'shared_db' belongs to no tenant",
+        ),
+        (
+            config(SERVER, &TENANTS.replace("globex_db]", "acme_db]")),
+            "datasource 'acme_db' belongs to both tenants 'acme' and 'globex'",
+        ),
+        (
+            config(SERVER, &TENANTS.replace("[globex_db]", "[missing_db]")),
+            "tenant 'globex' lists unknown datasource 'missing_db'",
+        ),
+        (
+            config(SERVER, &TENANTS.replace("name: globex", "name: acme")),
+            "duplicate tenant name 'acme'",
+        ),
+        (
+            config(SERVER, &TENANTS.replace("name: globex", "name: default")),
+            "duplicate tenant name 'default'",
+        ),
+        (
+            config(
+                SERVER,
+                &TENANTS.replace("http://globex.example.com", "\"\""),
+            ),
+            "tenant 'globex' has no server_url",
+        ),
+        (DATASOURCES.to_string(), "server.server_url is required"),
+    ];
+    for (content, expected) in cases {
+        let dir = TempDir::new().unwrap();
+        let error = load(&dir, &content).unwrap_err().to_string();
+        assert!(error.contains(expected), "{}", error);
+    }
+}
+
+#[test]
+fn test_tenant_credentials_resolved() {
+    let dir = TempDir::new().unwrap();
+    let mut config = load(&dir, &config(SERVER, TENANTS)).unwrap();
+
+    let credentials: Vec<String> = config
+        .credentials_mut()
+        .into_iter()
+        .map(|credential| credential.clone())
+        .collect();
+
+    for key in ["main-key", "acme-key", "globex-key"] {
+        assert!(
+            credentials.iter().any(|credential| credential == key),
+            "{}",
+            key
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_readiness_checks_every_tenant_server() {
+    let acme = Arc::new(FakeServer::new());
+    acme.set_unreachable(true);
+    let datasource = DataSource {
+        name: "shared_db".to_string(),
+        source_type: DataSourceType::Mock,
+        ..Default::default()
+    };
+    let readiness = Readiness::new(Arc::new(FakeServer::new()), vec![datasource]).with_tenant(
+        "acme",
+        acme,
+        vec![],
+    );
+
+    let report = readiness.check().await;
+
+    assert!(!report.ready);
+    assert_eq!(report.server, CHECK_OK);
+    assert_eq!(report.tenants["acme"], "Server unreachable");
+    assert_eq!(report.datasources["shared_db"], CHECK_OK);
+}
+
+fn job(datasource_name: &str) -> AcquireResultBody {
+    AcquireResultBody {
+        id: "job-1".to_string(),
+        datasource_name: datasource_name.to_string(),
+        query: "SELECT 1 AS one".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    }
+}
+
+#[tokio::test]
+async fn test_tenant_agents_only_run_their_datasources() {
+    let row: JobType = serde_json::from_value(json!({"one": 1})).unwrap();
+    let acme_db = DataSource {
+        name: "acme_db".to_string(),
+        source_type: DataSourceType::Mock,
+        mock: MockConfig {
+            results: vec![MockResult {
+                rows: vec![row],
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(job("acme_db"));
+    server.enqueue_job(job("globex_db"));
+
+    let (_, job_agent, _) = initialize_tenant_agents(&[acme_db], None, server.clone());
+    job_agent.process_next().await.unwrap();
+    assert!(job_agent.process_next().await.is_err());
+
+    assert_eq!(server.job_results()[0].1[0]["one"], json!(1));
+    assert!(server.job_errors()[0].1.contains("globex_db"));
+}
@@ -0,0 +1,192 @@
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::sync::Arc;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind, TimeRange};
+use tsight_agent::config::{MockConfig, MockResult};
+use tsight_agent::models::{DataSource, DataSourceType, JobType};
+use tsight_agent::time_macros::expand_time_macros;
+
+fn time(rfc3339: &str) -> DateTime<Utc> {
+    rfc3339.parse().unwrap()
+}
+
+fn range(interval_ms: Option<u64>) -> TimeRange {
+    TimeRange {
+        from: time("2024-03-01T00:00:00Z"),
+        to: time("2024-03-01T06:00:00Z"),
+        interval_ms,
+    }
+}
+
+fn expand(sql: &str, source_type: DataSourceType) -> String {
+    expand_time_macros(sql, Some(&range(Some(60_000))), &source_type).unwrap()
+}
+
+#[test]
+fn test_clickhouse_macros() {
+    assert_eq!(
+        expand(
+            "SELECT count() FROM events WHERE $__timeFilter(created_at) AND t < $__timeTo()",
+            DataSourceType::Clickhouse
+        ),
+        "SELECT count() FROM events WHERE created_at BETWEEN toDateTime(1709251200) AND toDateTime(1709272800) AND t < toDateTime(1709272800)"
+    );
+    assert_eq!(
+        expand("$__timeFrom()", DataSourceType::Clickhouse),
+        "toDateTime(1709251200)"
+    );
+}
+
+#[test]
+fn test_clickhouse_milliseconds() {
+    let range = TimeRange {
+        from: time("2024-03-01T00:00:00.250Z"),
+        ..range(None)
+    };
+
+    let sql = expand_time_macros("$__timeFrom()", Some(&range), &DataSourceType::Clickhouse);
+
+    assert_eq!(sql.unwrap(), "toDateTime64(1709251200.250, 3)");
+}
+
+#[test]
+fn test_dialects() {
+    assert_eq!(
+        expand("$__timeFilter(created_at)", DataSourceType::PostgreSQL),
+        "created_at BETWEEN '2024-03-01T00:00:00Z' AND '2024-03-01T06:00:00Z'"
+    );
+    assert_eq!(
+        expand("$__timeFilter(created_at)", DataSourceType::MySQL),
+        "created_at BETWEEN FROM_UNIXTIME(1709251200) AND FROM_UNIXTIME(1709272800)"
+    );
+    assert_eq!(
+        expand(
+            "rate(http_requests_total[$__interval]) @ $__timeTo()",
+            DataSourceType::Prometheus
+        ),
+        "rate(http_requests_total[1m]) @ 1709272800"
+    );
+}
+
+#[test]
+fn test_interval_macros() {
+    assert_eq!(
+        expand("$__interval $__interval_ms", DataSourceType::Clickhouse),
+        "1m 60000"
+    );
+    let sql = expand_time_macros(
+        "$__interval",
+        Some(&range(Some(1500))),
+        &DataSourceType::Clickhouse,
+    );
+    assert_eq!(sql.unwrap(), "1500ms");
+}
+
+#[test]
+fn test_default_interval_divides_range() {
+    let sql = expand_time_macros(
+        "$__interval $__interval_ms",
+        Some(&range(None)),
+        &DataSourceType::Clickhouse,
+    );
+
+    // Six hours in a thousand points
+    assert_eq!(sql.unwrap(), "21600ms 21600");
+}
+
+#[test]
+fn test_query_without_macros_needs_no_range() {
+    let sql = "SELECT count() FROM events";
+
+    assert_eq!(
+        expand_time_macros(sql, None, &DataSourceType::Clickhouse).unwrap(),
+        sql
+    );
+}
+
+#[test]
+fn test_invalid_macros_rejected() {
+    let errors = [
+        ("WHERE $__timeFilter(created_at)", None),
+        ("WHERE $__timeFilter()", Some(range(None))),
+        ("WHERE $__unixEpochFilter(created_at)", Some(range(None))),
+    ];
+    for (sql, range) in errors {
+        assert!(
+            expand_time_macros(sql, range.as_ref(), &DataSourceType::Clickhouse).is_err(),
+            "{}",
+            sql
+        );
+    }
+    assert!(expand_time_macros(
+        "$__timeFilter(created_at)",
+        Some(&range(None)),
+        &DataSourceType::Prometheus
+    )
+    .is_err());
+}
+
+fn job(query: &str, time_range: Option<TimeRange>) -> AcquireResultBody {
+    AcquireResultBody {
+        id: "job-1".to_string(),
+        datasource_name: "demo".to_string(),
+        query: query.to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range,
+    }
+}
+
+fn datasource() -> DataSource {
+    let row: JobType = serde_json::from_value(json!({"cnt": 3})).unwrap();
+    DataSource {
+        name: "demo".to_string(),
+        source_type: DataSourceType::Mock,
+        mock: MockConfig {
+            results: vec![MockResult {
+                query_regex: Some(regex::escape(
+                    "WHERE created_at BETWEEN toDateTime(1709251200) AND toDateTime(1709272800)",
+                )),
+                rows: vec![row],
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_job_query_expanded_with_time_range() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(job(
+        "SELECT toStartOfInterval(created_at, INTERVAL $__interval_ms millisecond) AS t, count() AS cnt FROM events WHERE $__timeFilter(created_at) GROUP BY t",
+        Some(range(Some(60_000))),
+    ));
+
+    let agent = create_job_agent_with_client(server.clone(), vec![datasource()], None);
+    agent.process_next().await.unwrap();
+
+    assert_eq!(server.job_results()[0].1[0]["cnt"], json!(3));
+}
+
+#[tokio::test]
+async fn test_job_with_macros_and_no_time_range_fails() {
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(job(
+        "SELECT count() AS cnt FROM events WHERE $__timeFilter(created_at)",
+        None,
+    ));
+
+    let agent = create_job_agent_with_client(server.clone(), vec![datasource()], None);
+    assert!(agent.process_next().await.is_err());
+
+    let errors = server.job_errors();
+    assert!(errors[0].1.contains("no time range"));
+}
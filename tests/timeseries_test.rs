@@ -0,0 +1,239 @@
+use serde_json::json;
+use tsight_agent::config::{FilterAction, GlobalFilters, SqlFilterRules};
+use tsight_agent::executors::clickhouse_source::ClickhouseExecutor;
+use tsight_agent::models::JobType;
+use tsight_agent::timeseries::{NullStats, NullValues, TimeUnit, TsMapping, TsMappingError};
+
+fn row(value: serde_json::Value) -> JobType {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn test_default_mapping_uses_t_and_numeric_columns() {
+    let mapping = TsMapping::default();
+    let record = mapping
+        .record(&row(json!({
+            "t": 1700000000,
+            "cnt": 3,
+            "total": "12.5",
+            "status": "new"
+        })))
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(record.t, 1_700_000_000_000);
+    assert_eq!(record.values.len(), 2);
+    assert_eq!(record.values["cnt"], 3.0);
+    assert_eq!(record.values["total"], 12.5);
+}
+
+#[test]
+fn test_timestamps_after_2106_and_in_milliseconds() {
+    let mapping = TsMapping {
+        time_unit: TimeUnit::Milliseconds,
+        ..TsMapping::default()
+    };
+    let record = mapping
+        .record(&row(json!({"t": "4300000000123", "cnt": 1})))
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.t, 4_300_000_000_123);
+
+    let record = TsMapping::default()
+        .record(&row(json!({"t": 4300000000u64, "cnt": 1})))
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.t, 4_300_000_000_000);
+}
+
+#[test]
+fn test_date_time_columns() {
+    let mapping = TsMapping {
+        time_column: "ts".to_string(),
+        ..TsMapping::default()
+    };
+    let cases = [
+        ("2023-11-14T22:13:20.250Z", 1_700_000_000_250),
+        ("2023-11-14 22:13:20.250", 1_700_000_000_250),
+        ("2023-11-14 22:13:20", 1_700_000_000_000),
+        ("2023-11-14", 1_699_920_000_000),
+    ];
+    for (ts, expected) in cases {
+        let record = mapping
+            .record(&row(json!({"ts": ts, "cnt": 1})))
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.t, expected, "{}", ts);
+    }
+}
+
+#[test]
+fn test_explicit_value_columns() {
+    let mapping = TsMapping {
+        value_columns: Some(vec!["p99".to_string()]),
+        ..TsMapping::default()
+    };
+    let record = mapping
+        .record(&row(json!({"t": 1, "p50": 2.0, "p99": 9.5})))
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.values.keys().collect::<Vec<_>>(), vec!["p99"]);
+
+    let err = mapping
+        .record(&row(json!({"t": 1, "p99": "n/a"})))
+        .unwrap_err();
+    assert!(matches!(err, TsMappingError::NotNumeric { .. }));
+
+    let err = mapping.record(&row(json!({"t": 1}))).unwrap_err();
+    assert_eq!(err, TsMappingError::MissingValueColumn("p99".to_string()));
+}
+
+#[test]
+fn test_missing_or_invalid_time_column() {
+    let mapping = TsMapping::default();
+    let err = mapping.record(&row(json!({"cnt": 1}))).unwrap_err();
+    assert_eq!(err, TsMappingError::MissingTimeColumn("t".to_string()));
+
+    let err = mapping
+        .record(&row(json!({"t": "yesterday", "cnt": 1})))
+        .unwrap_err();
+    assert!(matches!(err, TsMappingError::InvalidTimestamp { .. }));
+}
+
+#[test]
+fn test_mapping_deserializes_with_defaults() {
+    let mapping: TsMapping = serde_json::from_str(r#"{"time_unit": "milliseconds"}"#).unwrap();
+    assert_eq!(mapping.time_column, "t");
+    assert_eq!(mapping.time_unit, TimeUnit::Milliseconds);
+    assert_eq!(mapping.value_columns, None);
+}
+
+#[test]
+fn test_non_numeric_columns_label_series() {
+    let mapping = TsMapping::default();
+    let (records, _) = mapping
+        .records(vec![
+            row(json!({"t": 1, "status": "new", "region": "eu", "cnt": 3})),
+            row(json!({"t": 1, "status": "paid", "region": null, "cnt": 5})),
+        ])
+        .unwrap();
+
+    assert_eq!(records[0].labels.len(), 2);
+    assert_eq!(records[0].labels["status"], "new");
+    assert_eq!(records[0].labels["region"], "eu");
+    assert_eq!(records[1].labels.keys().collect::<Vec<_>>(), vec!["status"]);
+    assert_eq!(records[1].values["cnt"], 5.0);
+}
+
+#[test]
+fn test_explicit_label_columns() {
+    let mapping = TsMapping {
+        label_columns: Some(vec!["shard".to_string()]),
+        ..TsMapping::default()
+    };
+    let record = mapping
+        .record(&row(json!({"t": 1, "shard": 2, "cnt": 3, "note": "x"})))
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.labels.len(), 1);
+    assert_eq!(record.labels["shard"], "2");
+    assert_eq!(record.values.keys().collect::<Vec<_>>(), vec!["cnt"]);
+
+    let err = mapping.record(&row(json!({"t": 1, "cnt": 3}))).unwrap_err();
+    assert_eq!(err, TsMappingError::MissingLabelColumn("shard".to_string()));
+}
+
+#[test]
+fn test_filters_apply_to_labels() {
+    let filters = GlobalFilters {
+        sql_filters_exclude: Some(vec![
+            SqlFilterRules {
+                column_value_regexes: Some(vec!["^internal$".to_string()]),
+                ..Default::default()
+            },
+            SqlFilterRules {
+                column_name_regexes: Some(vec!["^email$".to_string()]),
+                action: Some(FilterAction::RedactValue),
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    };
+    let executor = ClickhouseExecutor::with_global_filters(
+        "http://localhost:8123",
+        "default",
+        "",
+        Some(filters),
+    )
+    .unwrap();
+    let (records, _) = TsMapping::default()
+        .records(vec![
+            row(json!({"t": 1, "source": "internal", "cnt": 1})),
+            row(json!({"t": 1, "source": "web", "email": "john@example.com", "cnt": 2})),
+        ])
+        .unwrap();
+
+    let records = executor.filter_labels(records);
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].labels["source"], "web");
+    assert_ne!(records[0].labels["email"], "john@example.com");
+    assert_eq!(records[0].values["cnt"], 2.0);
+}
+
+#[test]
+fn test_null_timestamps_and_values_skip_rows() {
+    let (records, stats) = TsMapping::default()
+        .records(vec![
+            row(json!({"t": 1, "status": "new", "cnt": 3})),
+            row(json!({"t": null, "status": "new", "cnt": 4})),
+            row(json!({"t": 2, "status": "new", "cnt": null})),
+        ])
+        .unwrap();
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].values["cnt"], 3.0);
+    assert_eq!(
+        stats,
+        NullStats {
+            skipped_rows: 2,
+            zero_filled_rows: 0
+        }
+    );
+}
+
+#[test]
+fn test_null_values_zero_filled() {
+    let mapping = TsMapping {
+        null_values: NullValues::Zero,
+        ..TsMapping::default()
+    };
+    let (records, stats) = mapping
+        .records(vec![
+            row(json!({"t": 1, "cnt": "3", "total": null})),
+            row(json!({"t": 2, "cnt": null, "total": null})),
+            row(json!({"t": null, "cnt": 1, "total": 1})),
+        ])
+        .unwrap();
+
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].values["total"], 0.0);
+    assert_eq!(records[1].values["cnt"], 0.0);
+    // A column that's NULL in every row can't be told apart from a label
+    assert!(records[0].labels.is_empty());
+    assert_eq!(stats.skipped_rows, 1);
+    assert_eq!(stats.zero_filled_rows, 2);
+}
+
+#[test]
+fn test_only_numeric_columns_are_values() {
+    let (records, _) = TsMapping::default()
+        .records(vec![
+            row(json!({"t": 1, "zone": "42", "cnt": 1})),
+            row(json!({"t": 2, "zone": "eu", "cnt": 2})),
+        ])
+        .unwrap();
+
+    assert_eq!(records[0].labels["zone"], "42");
+    assert_eq!(records[0].values.keys().collect::<Vec<_>>(), vec!["cnt"]);
+}
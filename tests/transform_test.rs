@@ -0,0 +1,246 @@
+#![cfg(unix)]
+
+use serde_json::json;
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tsight_agent::agent::factory::create_job_agent_with_client;
+use tsight_agent::client::fake::FakeServer;
+use tsight_agent::client::{AcquireResultBody, JobKind};
+use tsight_agent::config::{Config, MockConfig, MockResult, TransformConfig};
+use tsight_agent::executors::clickhouse_source::ResultColumn;
+use tsight_agent::models::{DataSource, DataSourceType, JobType};
+use tsight_agent::spill::{row_size, JobRows};
+use tsight_agent::transform::{transform_rows, transform_vec};
+
+fn transform(script: &str) -> TransformConfig {
+    TransformConfig {
+        command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+        timeout: Duration::from_secs(10),
+        memory_limit_mb: 256,
+        max_output_mb: 1024,
+    }
+}
+
+fn row(id: u64) -> JobType {
+    JobType::from([
+        ("id".to_string(), json!(id)),
+        (
+            "email".to_string(),
+            json!(format!("user{}@example.com", id)),
+        ),
+    ])
+}
+
+fn columns() -> Vec<ResultColumn> {
+    vec![
+        ResultColumn::from_clickhouse_type("id", "UInt64"),
+        ResultColumn::from_clickhouse_type("email", "String"),
+    ]
+}
+
+#[tokio::test]
+async fn test_transform_renames_column() {
+    let (rows, columns) = transform_vec(
+        &transform(r#"sed 's/"email"/"contact"/'"#),
+        vec![row(1), row(2)],
+        columns(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["contact"], json!("user1@example.com"));
+    assert!(!rows[0].contains_key("email"));
+    assert_eq!(
+        columns,
+        vec![
+            ResultColumn::from_clickhouse_type("id", "UInt64"),
+            ResultColumn::from_clickhouse_type("contact", "String"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_transform_may_drop_rows() {
+    let rows = (0..1000).map(row).collect();
+
+    let (rows, _) = transform_vec(&transform("head -n 1"), rows, columns())
+        .await
+        .unwrap();
+
+    assert_eq!(rows, vec![row(0)]);
+}
+
+#[tokio::test]
+async fn test_transform_without_rows_keeps_columns() {
+    let (rows, columns) = transform_vec(&transform("cat > /dev/null"), vec![row(1)], columns())
+        .await
+        .unwrap();
+
+    assert!(rows.is_empty());
+    assert_eq!(columns, self::columns());
+}
+
+#[tokio::test]
+async fn test_failed_transform_reports_stderr() {
+    let error = transform_vec(&transform("echo boom >&2; exit 3"), vec![row(1)], columns())
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("boom"));
+}
+
+#[tokio::test]
+async fn test_invalid_output_fails() {
+    let error = transform_vec(&transform("echo not json"), vec![row(1)], columns())
+        .await
+        .unwrap_err();
+
+    assert!(format!("{:#}", error).contains("invalid row"));
+}
+
+#[tokio::test]
+async fn test_slow_transform_killed() {
+    let config = TransformConfig {
+        timeout: Duration::from_millis(200),
+        ..transform("sleep 30")
+    };
+    let started = Instant::now();
+
+    let error = transform_vec(&config, vec![row(1)], columns())
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("timed out"));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_processes_started_by_slow_transform_killed() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let pid_file = dir.path().join("pid");
+    let config = TransformConfig {
+        timeout: Duration::from_millis(500),
+        ..transform(&format!(
+            "sleep 30 & echo $! > {}; wait",
+            pid_file.display()
+        ))
+    };
+
+    let error = transform_vec(&config, vec![row(1)], columns())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("timed out"));
+
+    // The shell's background process is killed along with it, leaving at
+    // most a zombie until it's reaped
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    let stat = format!("/proc/{}/stat", pid.trim());
+    let started = Instant::now();
+    let alive = || {
+        std::fs::read_to_string(&stat).is_ok_and(|stat| {
+            !stat
+                .rsplit(')')
+                .next()
+                .unwrap()
+                .trim_start()
+                .starts_with('Z')
+        })
+    };
+    while alive() && started.elapsed() < Duration::from_secs(5) {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(!alive(), "background process {} still runs", pid.trim());
+}
+
+#[tokio::test]
+async fn test_output_over_limit_fails() {
+    let config = TransformConfig {
+        max_output_mb: 1,
+        ..transform("yes '{\"id\": 1}'")
+    };
+
+    let error = transform_vec(&config, vec![row(1)], columns())
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("more than 1048576 bytes"));
+}
+
+#[tokio::test]
+async fn test_transform_output_spilled_beyond_budget() {
+    let budget = row_size(&row(0)) * 10;
+    let rows = JobRows::from((0..100).map(row).collect::<Vec<_>>());
+
+    let (rows, _) = transform_rows(&transform("cat"), rows, columns(), Some(budget))
+        .await
+        .unwrap();
+
+    assert!(rows.is_spilled());
+    assert_eq!(
+        rows.into_vec().unwrap(),
+        (0..100).map(row).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_job_results_transformed_before_submission() {
+    let datasource = DataSource {
+        name: "demo".to_string(),
+        source_type: DataSourceType::Mock,
+        mock: MockConfig {
+            results: vec![MockResult {
+                rows: vec![row(1)],
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        transform: Some(transform(r#"sed 's/"email":"[^"]*"/"email":"hidden"/'"#)),
+        ..Default::default()
+    };
+    let server = Arc::new(FakeServer::new());
+    server.enqueue_job(AcquireResultBody {
+        id: "job-1".to_string(),
+        datasource_name: "demo".to_string(),
+        query: "SELECT id, email FROM users".to_string(),
+        queries: None,
+        ts_mapping: None,
+        timeout: None,
+        enqueued_at: None,
+        kind: JobKind::Query,
+        signature: None,
+        time_range: None,
+    });
+
+    let agent = create_job_agent_with_client(server.clone(), vec![datasource], None);
+    agent.process_next().await.unwrap();
+
+    let results = server.job_results();
+    assert_eq!(results[0].1[0]["email"], json!("hidden"));
+    assert_eq!(results[0].1[0]["id"], json!(1));
+}
+
+#[test]
+fn test_transforms_only_load_in_builds_opting_into_them() {
+    let dir = TempDir::new().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    fs::write(
+        &config_path,
+        "server:\n  api_key: \"test-api-key\"\n  server_url: \"http://localhost:8080\"\ndatasources:\n  - name: \"warehouse\"\n    source_type: \"clickhouse\"\n    hosts: [\"http://localhost:8123\"]\n    transform:\n      command: [\"cat\"]\n",
+    )
+    .unwrap();
+
+    let loaded = Config::load(&config_path).map_err(|e| e.to_string());
+
+    if cfg!(feature = "transform-programs") {
+        let transform = loaded.unwrap().datasources[0].transform.clone().unwrap();
+        assert_eq!(transform.command, vec!["cat".to_string()]);
+        assert_eq!(transform.memory_limit_mb, 256);
+    } else {
+        let error = loaded.unwrap_err();
+        assert!(error.contains("'transform-programs' feature"), "{}", error);
+    }
+}